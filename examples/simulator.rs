@@ -0,0 +1,209 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Protocol simulator
+// Runs the full protocol between three parties in memory and prints an annotated transcript with the size of every
+// ciphertext and how the PFS keys evolve, as an executable reference for client implementers:
+// cargo run --example simulator --features simulator
+
+use dawn_stdlib::*;
+use dawn_stdlib::content_type;
+use dawn_stdlib::event;
+
+// one side of a conversation
+struct Side {
+	name: String,
+	identity: Identity,
+	pubkey_kyber: Vec<u8>, // own kyber key of the conversation
+	seckey_kyber: Vec<u8>,
+	send_pfs_key: Vec<u8>,
+	recv_pfs_key: Vec<u8>,
+	remote_pubkey_kyber: Vec<u8>,
+	remote_pubkey_sig: Vec<u8>,
+}
+
+struct Conversation {
+	id: String,
+	pfs_salt: Vec<u8>,
+	mdc_seed: String,
+}
+
+// short fingerprint of a key, so the transcript shows when a key changes without printing it
+fn fingerprint(key: &[u8]) -> String {
+	hash(key).iter().take(4).map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn content_type_name(msg_type: ContentType) -> &'static str {
+	match msg_type {
+		content_type::INTERNAL => "INTERNAL",
+		content_type::TEXT => "TEXT",
+		content_type::VOICE => "VOICE",
+		content_type::PICTURE => "PICTURE",
+		content_type::REACTION => "REACTION",
+		content_type::RETRACTION => "RETRACTION",
+		content_type::LINKED_MEDIA => "LINKED_MEDIA",
+		content_type::HISTORY_SYNC => "HISTORY_SYNC",
+		content_type::DELTA_SYNC => "DELTA_SYNC",
+		content_type::GATEWAY => "GATEWAY",
+		_ => "UNKNOWN",
+	}
+}
+
+fn section(title: &str) {
+	println!();
+	println!("== {} ==", title);
+}
+
+// run the handshake between two identities
+fn handshake(initiator_name: &str, initiator: &Identity, responder_name: &str, responder: &Identity) -> Result<(Side, Side, Conversation), DawnError> {
+	let handle = responder.gen_handle(responder_name, &mdc_gen());
+	println!("[{}] publishes a handle of {} bytes", responder_name, handle.len());
+	
+	let InitRequestResult { own_pubkey_kyber: initiator_pk_kyber, own_seckey_kyber: initiator_sk_kyber, own_pfs_key: initiator_pfs_key, remote_pfs_key: responder_pfs_key, pfs_salt, id, mdc_seed, ciphertext: request, .. } = initiator.init_request_builder().handle(handle)?.name(initiator_name).comment("simulated").build()?;
+	println!("[{} -> {}] init request, {} bytes, conversation {}", initiator_name, responder_name, request.len(), &id[..16]);
+	
+	let ParsedInitRequest { id: recv_id, peer: initiator_peer, own_pfs_key: recv_responder_pfs_key, remote_pfs_key: recv_initiator_pfs_key, pfs_salt: recv_pfs_salt, comment, mdc_seed: recv_mdc_seed, .. } = responder.parse_init_request(&request)?;
+	println!("[{}] parsed the init request of {} (comment: {:?}, capabilities: {:?})", responder_name, initiator_peer.name, comment, initiator_peer.capabilities);
+	
+	let (responder_send_pfs_key, (responder_pk_kyber, responder_sk_kyber), _, accept) = responder.accept_init_request(initiator_peer.pubkey_kyber.as_bytes(), &recv_responder_pfs_key, &recv_pfs_salt, &recv_id, &recv_mdc_seed)?;
+	println!("[{} -> {}] init accept, {} bytes", responder_name, initiator_name, accept.len());
+	
+	let ParsedInitResponse { peer: responder_peer, new_pfs_key: initiator_recv_pfs_key, warning, .. } = parse_init_response(&accept, &initiator_sk_kyber, Some(responder.pubkey_sig.as_bytes()), &responder_pfs_key, &pfs_salt, responder_name)?;
+	println!("[{}] parsed the init accept (warning: {:?}), security number {}", initiator_name, warning, &responder_peer.security_number(&initiator_pk_kyber)?[..16]);
+	
+	let initiator_side = Side {
+		name: initiator_name.to_string(),
+		identity: initiator.clone(),
+		pubkey_kyber: initiator_pk_kyber,
+		seckey_kyber: initiator_sk_kyber,
+		send_pfs_key: initiator_pfs_key,
+		recv_pfs_key: initiator_recv_pfs_key,
+		remote_pubkey_kyber: responder_pk_kyber.clone(),
+		remote_pubkey_sig: responder.pubkey_sig.to_vec(),
+	};
+	let responder_side = Side {
+		name: responder_name.to_string(),
+		identity: responder.clone(),
+		pubkey_kyber: responder_pk_kyber,
+		seckey_kyber: responder_sk_kyber,
+		send_pfs_key: responder_send_pfs_key,
+		recv_pfs_key: recv_initiator_pfs_key,
+		remote_pubkey_kyber: initiator_side.pubkey_kyber.clone(),
+		remote_pubkey_sig: initiator.pubkey_sig.to_vec(),
+	};
+	println!("[{}] send key {}, receive key {}", initiator_name, fingerprint(&initiator_side.send_pfs_key), fingerprint(&initiator_side.recv_pfs_key));
+	println!("[{}] send key {}, receive key {}", responder_name, fingerprint(&responder_side.send_pfs_key), fingerprint(&responder_side.recv_pfs_key));
+	Ok((initiator_side, responder_side, Conversation { id, pfs_salt, mdc_seed }))
+}
+
+// send a message from one side to the other and print what happened
+// returns the ciphertext and the received content
+fn deliver(conversation: &Conversation, sender: &mut Side, receiver: &mut Side, content: (ContentType, Option<&str>, Option<&[u8]>)) -> Result<(Vec<u8>, ReceivedMessage), DawnError> {
+	let old_key = fingerprint(&sender.send_pfs_key);
+	let estimate = estimate_ciphertext_len(content, true)?;
+	let (new_pfs_key, mdc, ciphertext) = send_msg(content, &sender.remote_pubkey_kyber, Some(sender.identity.seckey_sig.as_bytes()), &sender.send_pfs_key, &conversation.pfs_salt, &conversation.id, &conversation.mdc_seed)?;
+	sender.send_pfs_key = new_pfs_key;
+	println!("[{} -> {}] {}, {} bytes (estimated {}), mdc {}, send key {} -> {}", sender.name, receiver.name, content_type_name(content.0), ciphertext.len(), estimate, mdc, old_key, fingerprint(&sender.send_pfs_key));
+	
+	let (received, new_pfs_key, recv_mdc, warning) = parse_msg(&ciphertext, &receiver.seckey_kyber, Some(&receiver.remote_pubkey_sig), &receiver.recv_pfs_key, &conversation.pfs_salt)?;
+	receiver.recv_pfs_key = new_pfs_key;
+	println!("[{}] received {} with mdc {} (warning: {:?}), receive key now {}", receiver.name, content_type_name(received.content_type()), recv_mdc, warning, fingerprint(&receiver.recv_pfs_key));
+	Ok((ciphertext, received))
+}
+
+fn run() -> Result<(), DawnError> {
+	init_crypto();
+	// a fixed start time keeps the transcript reproducible
+	let clock = ManualClock::new(1_700_000_000);
+	let alice = Identity::generate()?;
+	let bob = Identity::generate()?;
+	let carol = Identity::generate()?;
+	section("identities");
+	for (name, identity) in [("alice", &alice), ("bob", &bob), ("carol", &carol)] {
+		println!("[{}] signature key {}, init kyber key {} bytes", name, fingerprint(identity.pubkey_sig.as_bytes()), identity.init_pubkey_kyber.as_bytes().len());
+	}
+	
+	section("handshake alice -> bob");
+	let (mut alice_bob, mut bob_alice, conversation) = handshake("alice", &alice, "bob", &bob)?;
+	
+	section("messages");
+	let (root, _) = deliver(&conversation, &mut bob_alice, &mut alice_bob, (content_type::TEXT, Some("Hi Alice"), None))?;
+	deliver(&conversation, &mut alice_bob, &mut bob_alice, (content_type::TEXT, Some("Hi Bob, how are you?"), None))?;
+	deliver(&conversation, &mut alice_bob, &mut bob_alice, (content_type::VOICE, None, Some(&[7; 2000])))?;
+	deliver(&conversation, &mut bob_alice, &mut alice_bob, (content_type::PICTURE, Some("the view from here"), Some(&[42; 5000])))?;
+	
+	section("presence");
+	let typing = gen_presence_update(PresenceUpdate::Typing(true));
+	deliver(&conversation, &mut alice_bob, &mut bob_alice, (content_type::INTERNAL, Some(&event::PRESENCE.to_string()), Some(&typing)))?;
+	
+	section("linked media");
+	let (encrypted_file, media_key) = encrypt_file(&[1; 100_000])?;
+	let link = "https://media.example.org/f/1";
+	let wrapped_key = wrap_media_key(&media_key, &conversation.pfs_salt, &conversation.id, link)?;
+	let delete_token = gen_media_delete_token(link, bob.seckey_sig.as_bytes())?;
+	println!("[bob] uploaded {} encrypted bytes to {}", encrypted_file.len(), link);
+	let linked_media = OutgoingMessage::linked_media(link, &wrapped_key, "a large picture", content_type::PICTURE, Some(clock.now() + 86400), Some(&delete_token))?;
+	deliver(&conversation, &mut bob_alice, &mut alice_bob, linked_media.content())?;
+	
+	section("threads");
+	let thread_id = thread_id_for(&root);
+	let (new_pfs_key, mdc, ciphertext) = send_msg_with_options(&OutgoingMessage::text("replying to your first message"), &SendOptions::new().thread(&thread_id), &alice_bob.remote_pubkey_kyber, Some(alice.seckey_sig.as_bytes()), &alice_bob.send_pfs_key, &conversation.pfs_salt, &conversation.id, &conversation.mdc_seed)?;
+	alice_bob.send_pfs_key = new_pfs_key;
+	println!("[alice -> bob] TEXT in thread {}, {} bytes, mdc {}", thread_id, ciphertext.len(), mdc);
+	let parsed = parse_msg_with_config(&ciphertext, &bob_alice.seckey_kyber, Some(&bob_alice.remote_pubkey_sig), &bob_alice.recv_pfs_key, &conversation.pfs_salt, &ProtocolConfig::default())?;
+	println!("[bob] received {:?} in thread {:?}", parsed.message, parsed.thread_id);
+	bob_alice.recv_pfs_key = parsed.new_pfs_key;
+	
+	section("gateway");
+	let origin = GatewayOrigin { network: "matrix".to_string(), remote_id: "@dave:example.org".to_string(), timestamp: clock.now() };
+	let envelope = gen_gateway_envelope(&origin, (content_type::TEXT, Some("hello from matrix"), None))?;
+	let (origin, relayed) = match deliver(&conversation, &mut bob_alice, &mut alice_bob, (content_type::GATEWAY, Some(&envelope), None))? {
+		(_, ReceivedMessage::Gateway { envelope, data }) => parse_gateway_envelope(&envelope, data)?,
+		_ => return Err(DawnError::InvalidInput("expected a gateway message".to_string()))
+	};
+	println!("[alice] relayed from {} ({}): {:?}", origin.network, origin.remote_id, relayed.1);
+	
+	section("handshake alice -> carol");
+	let (mut alice_carol, mut carol_alice, conversation) = handshake("alice", &alice, "carol", &carol)?;
+	
+	section("own devices");
+	let entries = vec![HistoryEntry { id: conversation.id.clone(), sent: true, timestamp: clock.now(), content_type: content_type::TEXT, text: Some("Hi Carol".to_string()), data: None }];
+	for (header, chunk) in gen_history_chunks(&entries, 256)? {
+		deliver(&conversation, &mut alice_carol, &mut carol_alice, (content_type::HISTORY_SYNC, Some(&header), Some(&chunk)))?;
+	}
+	let delta = gen_delta_sync(&DeltaSync { known: 0, since: 0, until: 1, entries, receipts: Vec::new(), settings: vec![SettingChange { key: "theme".to_string(), value: "dark".to_string() }] })?;
+	deliver(&conversation, &mut alice_carol, &mut carol_alice, (content_type::DELTA_SYNC, None, Some(&delta)))?;
+	
+	section("armored");
+	let (new_pfs_key, mdc, ciphertext) = send_msg((content_type::TEXT, Some("sent through an email gateway"), None), &carol_alice.remote_pubkey_kyber, Some(carol.seckey_sig.as_bytes()), &carol_alice.send_pfs_key, &conversation.pfs_salt, &conversation.id, &conversation.mdc_seed)?;
+	carol_alice.send_pfs_key = new_pfs_key;
+	let armored = armor_msg(&ciphertext, &mdc);
+	println!("{}", armored);
+	let (ciphertext, _) = dearmor_msg(&armored.replace('\n', " "))?;
+	let (received, new_pfs_key, _, _) = parse_msg(&ciphertext, &alice_carol.seckey_kyber, Some(&alice_carol.remote_pubkey_sig), &alice_carol.recv_pfs_key, &conversation.pfs_salt)?;
+	alice_carol.recv_pfs_key = new_pfs_key;
+	println!("[alice] received {:?} after the transport collapsed all line breaks", received);
+	Ok(())
+}
+
+fn main() {
+	if let Err(err) = run() {
+		eprintln!("simulation failed: {}", err);
+		std::process::exit(1);
+	}
+}
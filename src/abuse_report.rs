@@ -0,0 +1,145 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Abuse reports
+// A user reporting abusive messages hands the moderators a report with the received ciphertexts and the key material
+// needed to decrypt them: the kyber secret key of the conversation, its pfs salt and the pfs key every message was parsed
+// with. Only signed messages can be reported. The moderators decrypt every message again and check the signature of its
+// sender, so a reporter can't make up messages, and the whole report is signed by the reporter, so it can't be put
+// together by anyone else. Moderators still have to check with the server that the signature keys belong to the accounts
+// in question.
+// The key material also decrypts the messages that followed the reported ones in the conversation, clients should start
+// a new conversation (with new keys) after sending a report.
+
+use serde::{Serialize, Deserialize};
+use crate::canonical::canonical_json;
+use crate::codec::{encode, decode, encode_base64, decode_base64};
+use crate::keys::{SignPublicKey, SignSecretKey};
+use crate::signature::{sign_detached, verify_detached};
+use crate::parse_msg;
+use crate::received::ReceivedMessage;
+use crate::domain::ABUSE_REPORT_DOMAIN;
+use crate::DawnError;
+
+const ABUSE_REPORT_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct ReportContent {
+	version: u8,
+	conversation_id: String,
+	reporter: String,
+	reported: String,
+	seckey_kyber: String,
+	pfs_salt: String,
+	messages: Vec<(String, String)>, // ciphertext and pfs key, base64
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedReport {
+	report: ReportContent,
+	signature: String,
+}
+
+// report as verified by the moderators
+#[derive(Debug, PartialEq)]
+pub struct AbuseReport {
+	pub conversation_id: String,
+	pub reporter: SignPublicKey,
+	pub reported: SignPublicKey,
+	pub messages: Vec<(ReceivedMessage, String)>, // reported messages and their mdc
+}
+
+// decrypt all messages of a report, which have to be signed by the reported key
+fn open_messages(report: &ReportContent, reported: &SignPublicKey) -> Result<Vec<(ReceivedMessage, String)>, DawnError> {
+	let (seckey_kyber, pfs_salt) = match (decode_base64(&report.seckey_kyber), decode_base64(&report.pfs_salt)) {
+		(Ok(seckey_kyber), Ok(pfs_salt)) => (seckey_kyber, pfs_salt),
+		_ => error!("abuse report key material invalid")
+	};
+	let mut messages = Vec::new();
+	for (ciphertext, pfs_key) in &report.messages {
+		let (ciphertext, pfs_key) = match (decode_base64(ciphertext), decode_base64(pfs_key)) {
+			(Ok(ciphertext), Ok(pfs_key)) => (ciphertext, pfs_key),
+			_ => error!("abuse report message invalid")
+		};
+		// passing the signature key rejects unsigned messages
+		match parse_msg(&ciphertext, &seckey_kyber, Some(reported.as_bytes()), &pfs_key, &pfs_salt) {
+			Ok((content, _, mdc, _)) => messages.push((content, mdc)),
+			Err(err) => return Err(err)
+		}
+	}
+	Ok(messages)
+}
+
+// package received messages into a report
+// messages are the ciphertexts together with the pfs key they were parsed with, own_seckey_kyber and pfs_salt are the keys of the conversation
+// returns the report, which can be sent to the moderators as is
+pub fn gen_abuse_report(conversation_id: &str, messages: &[(&[u8], &[u8])], own_seckey_kyber: &[u8], pfs_salt: &[u8], reported_pubkey_sig: &SignPublicKey, own_pubkey_sig: &SignPublicKey, own_seckey_sig: &SignSecretKey) -> Result<Vec<u8>, DawnError> {
+	if messages.is_empty() { error!("no messages to report"); }
+	let report = ReportContent {
+		version: ABUSE_REPORT_VERSION,
+		conversation_id: conversation_id.to_string(),
+		reporter: encode(own_pubkey_sig),
+		reported: encode(reported_pubkey_sig),
+		seckey_kyber: encode_base64(own_seckey_kyber),
+		pfs_salt: encode_base64(pfs_salt),
+		messages: messages.iter().map(|(ciphertext, pfs_key)| (encode_base64(ciphertext), encode_base64(pfs_key))).collect(),
+	};
+	// fail now instead of sending a report the moderators can't verify
+	if let Err(err) = open_messages(&report, reported_pubkey_sig) { return Err(err); }
+	let signature = match canonical_json(&report).map(|content| sign_detached(ABUSE_REPORT_DOMAIN, &content, own_seckey_sig.as_bytes())) {
+		Ok(Ok(res)) => res,
+		Ok(Err(err)) | Err(err) => return Err(err)
+	};
+	match serde_json::to_vec(&SignedReport { report, signature: encode(signature) }) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// verify a report as moderator and decrypt the reported messages
+pub fn verify_abuse_report(report: &[u8]) -> Result<AbuseReport, DawnError> {
+	let signed_report = match serde_json::from_slice::<SignedReport>(report) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "abuse report json parsing failed")
+	};
+	let report = signed_report.report;
+	if report.version != ABUSE_REPORT_VERSION { error!("abuse report version not supported"); }
+	let (reporter, reported) = match (decode(&report.reporter).map(SignPublicKey::try_from), decode(&report.reported).map(SignPublicKey::try_from)) {
+		(Ok(Ok(reporter)), Ok(Ok(reported))) => (reporter, reported),
+		_ => error!("abuse report contains an invalid key")
+	};
+	let signature = match decode(&signed_report.signature) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "abuse report signature invalid")
+	};
+	let content = match canonical_json(&report) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if let Err(err) = verify_detached(ABUSE_REPORT_DOMAIN, &content, &signature, reporter.as_bytes()) { return Err(err); }
+	let messages = match open_messages(&report, &reported) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	Ok(AbuseReport {
+		conversation_id: report.conversation_id,
+		reporter,
+		reported,
+		messages,
+	})
+}
@@ -0,0 +1,182 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Application identities
+// Integrations like CI notification bots get their own signature keys instead of the keys of their owner. The owner signs
+// a certificate for the keys of the application that declares its policy as capabilities: the content types it may send
+// and the conversations it may send them into. The application hands its certificate to the conversation partners (e.g.
+// as comment of its init requests), which verify it against the key of the owner and check every received message
+// against the policy, so a leaked application key can't be used to impersonate the owner or to send anything else.
+
+use serde::{Serialize, Deserialize};
+use crate::capability;
+use crate::canonical::canonical_json;
+use crate::content_type::ContentType;
+use crate::codec::{encode, decode};
+use crate::keys::{SignPublicKey, SignSecretKey, gen_sign_keypair};
+use crate::signature::{sign_detached, verify_detached};
+use crate::domain::APPLICATION_CERTIFICATE_DOMAIN;
+use crate::DawnError;
+
+const CONTENT_TYPE_PREFIX: &str = "application:content_type:";
+const CONVERSATION_PREFIX: &str = "application:conversation:";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ApplicationPolicy {
+	pub content_types: Vec<ContentType>,
+	pub conversations: Vec<String>, // ids of the conversations the application may send into
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApplicationIdentity {
+	pub pubkey_sig: SignPublicKey,
+	pub seckey_sig: SignSecretKey,
+	pub policy: ApplicationPolicy,
+	pub certificate: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ApplicationCertificate {
+	name: String,
+	sign: String,
+	capabilities: Vec<String>,
+	signature: String,
+}
+
+impl ApplicationPolicy {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	pub fn content_type(mut self, content_type: ContentType) -> Self {
+		if !self.content_types.contains(&content_type) { self.content_types.push(content_type); }
+		self
+	}
+	
+	pub fn conversation(mut self, id: &str) -> Self {
+		if !self.conversations.iter().any(|conversation| conversation == id) { self.conversations.push(id.to_string()); }
+		self
+	}
+	
+	// check whether the application may send a message of the content type into the conversation
+	pub fn permits(&self, content_type: ContentType, id: &str) -> bool {
+		self.content_types.contains(&content_type) && self.conversations.iter().any(|conversation| conversation == id)
+	}
+	
+	// declare the policy as capabilities
+	pub fn to_capabilities(&self) -> Vec<String> {
+		let mut capabilities = vec![capability::APPLICATION.to_string()];
+		capabilities.extend(self.content_types.iter().map(|content_type| format!("{}{}", CONTENT_TYPE_PREFIX, u8::from(*content_type))));
+		capabilities.extend(self.conversations.iter().map(|id| format!("{}{}", CONVERSATION_PREFIX, id)));
+		capabilities
+	}
+	
+	// read the policy from declared capabilities
+	// returns None if the capabilities don't belong to an application identity
+	pub fn from_capabilities(capabilities: &[String]) -> Result<Option<Self>, DawnError> {
+		if !capabilities.iter().any(|capability| capability == capability::APPLICATION) { return Ok(None); }
+		let mut policy = ApplicationPolicy::new();
+		for capability in capabilities {
+			if let Some(content_type) = capability.strip_prefix(CONTENT_TYPE_PREFIX) {
+				match content_type.parse::<u8>().map(ContentType::try_from) {
+					Ok(Ok(res)) => policy = policy.content_type(res),
+					_ => error!("application policy contains an invalid content type")
+				}
+			}
+			else if let Some(id) = capability.strip_prefix(CONVERSATION_PREFIX) {
+				if id.is_empty() { error!("application policy contains an empty conversation id"); }
+				policy = policy.conversation(id);
+			}
+		}
+		Ok(Some(policy))
+	}
+}
+
+// the data covered by the signature of a certificate
+fn certificate_content(name: &str, pubkey_sig: &[u8], capabilities: &[String]) -> Result<Vec<u8>, DawnError> {
+	canonical_json(&(name, encode(pubkey_sig), capabilities))
+}
+
+impl ApplicationIdentity {
+	// generate keys for an application and certify them with the signature key of the owner
+	pub fn mint(name: &str, policy: ApplicationPolicy, owner_seckey_sig: &SignSecretKey) -> Result<Self, DawnError> {
+		if policy.content_types.is_empty() || policy.conversations.is_empty() { error!("application policy permits nothing"); }
+		let (pubkey_sig, seckey_sig) = match gen_sign_keypair() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let capabilities = policy.to_capabilities();
+		let content = match certificate_content(name, pubkey_sig.as_bytes(), &capabilities) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let signature = match sign_detached(APPLICATION_CERTIFICATE_DOMAIN, &content, owner_seckey_sig.as_bytes()) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let certificate = ApplicationCertificate {
+			name: name.to_string(),
+			sign: encode(pubkey_sig.as_bytes()),
+			capabilities,
+			signature: encode(signature),
+		};
+		let certificate = match serde_json::to_string(&certificate) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "json serialization failed")
+		};
+		Ok(ApplicationIdentity {
+			pubkey_sig,
+			seckey_sig,
+			policy,
+			certificate,
+		})
+	}
+	
+	// check a message before sending it, so the application fails early instead of being rejected by the receiver
+	pub fn check_send(&self, content_type: ContentType, id: &str) -> Result<(), DawnError> {
+		if !self.policy.permits(content_type, id) { error!("application policy does not permit this message"); }
+		Ok(())
+	}
+}
+
+// verify the certificate of an application against the signature key of its owner
+// returns the name, signature key and policy of the application
+pub fn verify_application_identity(certificate: &str, owner_pubkey_sig: &SignPublicKey) -> Result<(String, SignPublicKey, ApplicationPolicy), DawnError> {
+	let certificate = match serde_json::from_str::<ApplicationCertificate>(certificate) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "application certificate json parsing failed")
+	};
+	let pubkey_sig = match decode(&certificate.sign).map(SignPublicKey::try_from) {
+		Ok(Ok(res)) => res,
+		_ => error!("application certificate contains an invalid key")
+	};
+	let signature = match decode(&certificate.signature) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "application certificate signature invalid")
+	};
+	let content = match certificate_content(&certificate.name, pubkey_sig.as_bytes(), &certificate.capabilities) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if let Err(err) = verify_detached(APPLICATION_CERTIFICATE_DOMAIN, &content, &signature, owner_pubkey_sig.as_bytes()) { return Err(err); }
+	match ApplicationPolicy::from_capabilities(&certificate.capabilities) {
+		Ok(Some(policy)) => Ok((certificate.name, pubkey_sig, policy)),
+		Ok(None) => error!("certificate does not declare an application identity"),
+		Err(err) => Err(err)
+	}
+}
@@ -0,0 +1,100 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Armored messages
+// For delivery through email or SMS gateways, a ciphertext is turned into a self-describing block of text with a version
+// header, the message detail code and a checksum, followed by the base64 encoded ciphertext in short lines. Such transports
+// like to rewrap lines, indent, quote or collapse whitespace, so the parser ignores all whitespace and "> " quote
+// prefixes, and the checksum tells a mangled transfer apart from a message that fails to decrypt.
+
+use dawn_crypto::hash;
+use crate::codec::{encode, encode_base64, decode_base64};
+use crate::DawnError;
+
+const ARMOR_VERSION: &str = "1";
+const ARMOR_BEGIN: &str = "-----BEGIN DAWN MESSAGE-----";
+const ARMOR_END: &str = "-----END DAWN MESSAGE-----";
+const ARMOR_LINE_LEN: usize = 64;
+
+fn checksum(msg_ciphertext: &[u8]) -> String {
+	encode(hash(msg_ciphertext).get(..4).unwrap_or_default())
+}
+
+// armor a ciphertext as returned by send_msg together with its message detail code
+pub fn armor_msg(msg_ciphertext: &[u8], mdc: &str) -> String {
+	let mut armored = format!("{}\nVersion: {}\nMDC: {}\nChecksum: {}\n\n", ARMOR_BEGIN, ARMOR_VERSION, mdc, checksum(msg_ciphertext));
+	let body = encode_base64(msg_ciphertext);
+	// base64 is pure ASCII, so splitting it into chunks of bytes keeps every chunk valid UTF-8
+	for line in body.as_bytes().chunks(ARMOR_LINE_LEN) {
+		armored += &String::from_utf8_lossy(line);
+		armored += "\n";
+	}
+	armored + ARMOR_END + "\n"
+}
+
+// parse an armored message, which may be surrounded by other text (e.g. the rest of an email)
+// returns ciphertext and message detail code
+pub fn dearmor_msg(armored: &str) -> Result<(Vec<u8>, String), DawnError> {
+	let begin = match armored.find(ARMOR_BEGIN) {
+		Some(res) => res + ARMOR_BEGIN.len(),
+		None => error!("armored message has no begin marker")
+	};
+	let armored = match armored.get(begin..) {
+		Some(res) => res,
+		None => error!("armored message invalid")
+	};
+	let armored = match armored.find(ARMOR_END).and_then(|end| armored.get(..end)) {
+		Some(res) => res,
+		None => error!("armored message has no end marker, it may have been truncated")
+	};
+	
+	// headers are "key: value", with whitespace around the value possibly lost or replaced by a line break
+	// base64 never contains a colon, so all other tokens belong to the body
+	let (mut version, mut mdc, mut expected_checksum) = (None, None, None);
+	let mut body = String::new();
+	let mut tokens = armored.split_whitespace().filter(|token| *token != ">");
+	while let Some(token) = tokens.next() {
+		let token = token.trim_start_matches('>');
+		let (key, value) = match token.split_once(':') {
+			Some((key, "")) => (key, tokens.next().unwrap_or_default()),
+			Some(res) => res,
+			None => {
+				body += token;
+				continue;
+			}
+		};
+		match key.to_ascii_lowercase().as_str() {
+			"version" => version = Some(value),
+			"mdc" => mdc = Some(value.to_string()),
+			"checksum" => expected_checksum = Some(value.to_ascii_lowercase()),
+			_ => {} // headers of later versions
+		}
+	}
+	
+	if version != Some(ARMOR_VERSION) { error!("armored message version not supported"); }
+	let mdc = match mdc {
+		Some(res) if !res.is_empty() => res,
+		_ => error!("armored message has no message detail code")
+	};
+	let msg_ciphertext = match decode_base64(&body) {
+		Ok(res) => res,
+		Err(_) => error!("armored message body invalid")
+	};
+	if expected_checksum != Some(checksum(&msg_ciphertext)) { error!(Crypto, "armored message checksum mismatch, it was altered in transit"); }
+	Ok((msg_ciphertext, mdc))
+}
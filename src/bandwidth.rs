@@ -0,0 +1,91 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Bandwidth accounting
+// Counts the bytes of ciphertext sent and received in a conversation by content type, so clients can show how much data
+// a chat used without parsing its history again. A Session counts its messages itself and keeps the counters in its
+// state, clients of the free functions store them next to the other state of the conversation and can serialize them.
+
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use crate::content_type::ContentType;
+use crate::DawnError;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BandwidthCounters {
+	sent: BTreeMap<ContentType, u64>,
+	received: BTreeMap<ContentType, u64>,
+}
+
+impl BandwidthCounters {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// count a sent message (ciphertext as returned by send_msg)
+	pub fn record_sent(&mut self, content_type: ContentType, ciphertext_len: usize) {
+		let counter = self.sent.entry(content_type).or_insert(0);
+		*counter = counter.saturating_add(ciphertext_len as u64);
+	}
+	
+	// count a received message (ciphertext as passed to parse_msg)
+	pub fn record_received(&mut self, content_type: ContentType, ciphertext_len: usize) {
+		let counter = self.received.entry(content_type).or_insert(0);
+		*counter = counter.saturating_add(ciphertext_len as u64);
+	}
+	
+	pub fn sent(&self, content_type: ContentType) -> u64 {
+		self.sent.get(&content_type).copied().unwrap_or(0)
+	}
+	
+	pub fn received(&self, content_type: ContentType) -> u64 {
+		self.received.get(&content_type).copied().unwrap_or(0)
+	}
+	
+	pub fn total_sent(&self) -> u64 {
+		self.sent.values().fold(0, |total, bytes| total.saturating_add(*bytes))
+	}
+	
+	pub fn total_received(&self) -> u64 {
+		self.received.values().fold(0, |total, bytes| total.saturating_add(*bytes))
+	}
+	
+	// returns all bytes sent and received
+	pub fn total(&self) -> u64 {
+		self.total_sent().saturating_add(self.total_received())
+	}
+	
+	pub fn reset(&mut self) {
+		self.sent.clear();
+		self.received.clear();
+	}
+	
+	pub fn to_bytes(&self) -> Result<Vec<u8>, DawnError> {
+		match serde_json::to_vec(self) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "json serialization failed")
+		}
+	}
+	
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, DawnError> {
+		match serde_json::from_slice::<BandwidthCounters>(bytes) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "bandwidth counters json parsing failed")
+		}
+	}
+}
@@ -0,0 +1,119 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Canonical serialization
+// Signatures over JSON only hold as long as every client serializes a structure to exactly the same bytes, which breaks as
+// soon as a client version orders fields differently, adds whitespace or escapes characters in another way. Everything
+// that gets signed is therefore serialized canonically: no whitespace, the keys of every object sorted by their UTF-8
+// bytes, integers only and strings escaped like serde_json does. Documents signed by clients (e.g. profiles) are verified
+// over the canonical form of the received JSON, so they stay valid when another client parses and re-serializes them.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::keys::{SignPublicKey, SignSecretKey};
+use crate::signature::{sign_detached, verify_detached};
+use crate::domain::DOCUMENT_DOMAIN_PREFIX;
+use crate::DawnError;
+
+// prefix of the domains of documents signed by clients, keeping them apart from the signatures made by the library itself
+
+fn write_canonical(value: &Value, output: &mut Vec<u8>) -> Result<(), DawnError> {
+	match value {
+		Value::Null | Value::Bool(_) | Value::String(_) => match serde_json::to_writer(&mut *output, value) {
+			Ok(_) => Ok(()),
+			Err(_) => error!(Serialization, "json serialization failed")
+		},
+		Value::Number(number) => {
+			if !number.is_i64() && !number.is_u64() { error!("canonical json only supports integers"); }
+			output.extend_from_slice(number.to_string().as_bytes());
+			Ok(())
+		},
+		Value::Array(values) => {
+			output.push(b'[');
+			for (index, value) in values.iter().enumerate() {
+				if index > 0 { output.push(b','); }
+				if let Err(err) = write_canonical(value, output) { return Err(err); }
+			}
+			output.push(b']');
+			Ok(())
+		},
+		Value::Object(map) => {
+			// sort explicitly, the order of the map depends on the features serde_json was built with
+			let mut entries = map.iter().collect::<Vec<(&String, &Value)>>();
+			entries.sort_by(|(key_a, _), (key_b, _)| key_a.as_bytes().cmp(key_b.as_bytes()));
+			output.push(b'{');
+			for (index, (key, value)) in entries.into_iter().enumerate() {
+				if index > 0 { output.push(b','); }
+				if serde_json::to_writer(&mut *output, key).is_err() { error!(Serialization, "json serialization failed"); }
+				output.push(b':');
+				if let Err(err) = write_canonical(value, output) { return Err(err); }
+			}
+			output.push(b'}');
+			Ok(())
+		}
+	}
+}
+
+// serialize a structure canonically
+pub fn canonical_json<T: Serialize>(value: &T) -> Result<Vec<u8>, DawnError> {
+	let value = match serde_json::to_value(value) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "json serialization failed")
+	};
+	let mut output = Vec::new();
+	match write_canonical(&value, &mut output) {
+		Ok(_) => Ok(output),
+		Err(err) => Err(err)
+	}
+}
+
+// bring received JSON into its canonical form
+pub fn canonicalize_json(json: &[u8]) -> Result<Vec<u8>, DawnError> {
+	match serde_json::from_slice::<Value>(json) {
+		Ok(value) => canonical_json(&value),
+		Err(_) => error!(Serialization, "json parsing failed")
+	}
+}
+
+// sign a document of the client (e.g. a profile) under a domain chosen by the client
+// returns the canonical document and the signature
+pub fn sign_document<T: Serialize>(domain: &str, document: &T, own_seckey_sig: &SignSecretKey) -> Result<(Vec<u8>, Vec<u8>), DawnError> {
+	let document = match canonical_json(document) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	match sign_detached(&(DOCUMENT_DOMAIN_PREFIX.to_string() + domain), &document, own_seckey_sig.as_bytes()) {
+		Ok(signature) => Ok((document, signature)),
+		Err(err) => Err(err)
+	}
+}
+
+// verify a signed document, which doesn't have to be in canonical form anymore
+// returns the parsed document
+pub fn verify_document<T: DeserializeOwned>(domain: &str, document: &[u8], signature: &[u8], remote_pubkey_sig: &SignPublicKey) -> Result<T, DawnError> {
+	let canonical_document = match canonicalize_json(document) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if let Err(err) = verify_detached(&(DOCUMENT_DOMAIN_PREFIX.to_string() + domain), &canonical_document, signature, remote_pubkey_sig.as_bytes()) { return Err(err); }
+	match serde_json::from_slice::<T>(&canonical_document) {
+		Ok(res) => Ok(res),
+		Err(_) => error!("document invalid")
+	}
+}
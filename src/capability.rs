@@ -0,0 +1,49 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Capabilities
+// Announced in init requests and init accepts, so both sides know which optional features the other client supports.
+// Clients that predate capabilities announce none.
+
+use crate::version;
+
+pub const LINKED_MEDIA: &str = "linked_media";
+pub const HISTORY_SYNC: &str = "history_sync";
+pub const DELTA_SYNC: &str = "delta_sync";
+pub const BINARY_FORMAT: &str = "binary_format"; // binary messages can be parsed (see WireFormat)
+pub const REACTION: &str = "reaction";
+pub const RETRACTION: &str = "retraction";
+pub const COMPRESSION: &str = "compression"; // deflated binary messages can be parsed if compression is enabled (see ProtocolConfig)
+
+// followed by a protocol version the client can parse (see version.rs), one capability per version
+pub const PROTOCOL_VERSION_PREFIX: &str = "protocol_version:";
+
+// followed by a media codec the client can decode (see media_codec.rs), announced by the client, one capability per codec
+pub const MEDIA_CODEC_PREFIX: &str = "media_codec:";
+
+// declared by application identities (not a feature, so it is not part of SUPPORTED)
+pub const APPLICATION: &str = "application";
+
+// capabilities of this version of the library
+pub const SUPPORTED: [&str; 7] = [LINKED_MEDIA, HISTORY_SYNC, DELTA_SYNC, BINARY_FORMAT, REACTION, RETRACTION, COMPRESSION];
+
+pub(crate) fn supported() -> Vec<String> {
+	let mut capabilities: Vec<String> = SUPPORTED.iter().map(|capability| capability.to_string()).collect();
+	capabilities.extend(version::capabilities());
+	capabilities
+}
@@ -0,0 +1,307 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Key ceremonies
+// Organizations that don't want a single person to hold a key (e.g. the signature key of an announcement channel) split
+// it into shares for several holders during a ceremony. The dealer signs a transcript listing the purpose, the threshold,
+// a fingerprint of the key and a commitment to every share together with the signature key of its holder. Every holder
+// checks the share received against the transcript and acknowledges it with a signature, so anyone can verify later
+// who holds which share, and reassembly rejects shares that don't match their commitment.
+
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use dawn_crypto::{hash, id_gen};
+use crate::canonical::canonical_json;
+use crate::codec::{encode, decode};
+use crate::keys::{SignPublicKey, SignSecretKey};
+use crate::secret::SecretBytes;
+use crate::sharing::{split_secret, combine_shares};
+use crate::signature::{sign_detached, verify_detached};
+use crate::domain::{CEREMONY_DOMAIN, CEREMONY_ACK_DOMAIN};
+use crate::DawnError;
+
+// a share as it is handed to its holder
+#[derive(Clone, PartialEq, Eq)]
+pub struct CeremonyShare {
+	pub ceremony_id: String,
+	pub index: u8,
+	data: SecretBytes,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct ShareCommitment {
+	holder: String,
+	commitment: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	acknowledgment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CeremonyTranscript {
+	ceremony_id: String,
+	purpose: String,
+	threshold: u8,
+	fingerprint: String,
+	dealer: String,
+	shares: Vec<ShareCommitment>, // share i + 1 is at position i
+	signature: String,
+}
+
+#[derive(Serialize)]
+struct SignedTranscript<'a> {
+	ceremony_id: &'a str,
+	purpose: &'a str,
+	threshold: u8,
+	fingerprint: &'a str,
+	dealer: &'a str,
+	shares: Vec<(&'a str, &'a str)>,
+}
+
+// hide the share data behind a hash that also covers its position in the ceremony
+fn share_commitment(ceremony_id: &str, index: u8, data: &[u8]) -> String {
+	let mut input = ceremony_id.as_bytes().to_vec();
+	input.push(0);
+	input.push(index);
+	input.extend_from_slice(data);
+	encode(hash(&input))
+}
+
+fn fingerprint(ceremony_id: &str, secret: &[u8]) -> String {
+	let mut input = ceremony_id.as_bytes().to_vec();
+	input.push(0);
+	input.extend_from_slice(secret);
+	encode(hash(&input))
+}
+
+impl fmt::Debug for CeremonyShare {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "CeremonyShare({}, {}, <redacted>)", self.ceremony_id, self.index)
+	}
+}
+
+impl CeremonyShare {
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = vec![self.index];
+		bytes.extend_from_slice(self.ceremony_id.as_bytes());
+		bytes.push(b'\n');
+		bytes.extend_from_slice(self.data.as_bytes());
+		bytes
+	}
+	
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, DawnError> {
+		let (index, rest) = match bytes.split_first() {
+			Some((index, rest)) if *index != 0 => (*index, rest),
+			_ => error!("ceremony share invalid")
+		};
+		let separator = match rest.iter().position(|byte| *byte == b'\n') {
+			Some(res) => res,
+			None => error!("ceremony share invalid")
+		};
+		let (ceremony_id, data) = match (rest.get(..separator), rest.get(separator + 1..)) {
+			(Some(ceremony_id), Some(data)) if !data.is_empty() => (ceremony_id, data),
+			_ => error!("ceremony share invalid")
+		};
+		let ceremony_id = match std::str::from_utf8(ceremony_id) {
+			Ok(res) => res.to_string(),
+			Err(_) => error!("ceremony share invalid")
+		};
+		Ok(CeremonyShare { ceremony_id, index, data: SecretBytes::new(data) })
+	}
+}
+
+// split a key among holders, threshold of them are needed to reassemble it
+// returns the transcript signed by the dealer and the shares in the order of the holders
+pub fn run_key_ceremony(purpose: &str, secret: &[u8], threshold: u8, holders: &[SignPublicKey], dealer_pubkey_sig: &SignPublicKey, dealer_seckey_sig: &SignSecretKey) -> Result<(CeremonyTranscript, Vec<CeremonyShare>), DawnError> {
+	let count = match u8::try_from(holders.len()) {
+		Ok(res) => res,
+		Err(_) => error!("a ceremony can have at most 255 holders")
+	};
+	let split = match split_secret(secret, threshold, count) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let ceremony_id = id_gen();
+	let mut transcript = CeremonyTranscript {
+		ceremony_id: ceremony_id.clone(),
+		purpose: purpose.to_string(),
+		threshold,
+		fingerprint: fingerprint(&ceremony_id, secret),
+		dealer: encode(dealer_pubkey_sig),
+		shares: holders.iter().zip(&split).map(|(holder, (index, data))| ShareCommitment {
+			holder: encode(holder),
+			commitment: share_commitment(&ceremony_id, *index, data),
+			acknowledgment: None,
+		}).collect(),
+		signature: String::new(),
+	};
+	let signature = match transcript.signed_content().map(|content| sign_detached(CEREMONY_DOMAIN, &content, dealer_seckey_sig.as_bytes())) {
+		Ok(Ok(res)) => res,
+		Ok(Err(err)) | Err(err) => return Err(err)
+	};
+	transcript.signature = encode(signature);
+	let shares = split.into_iter().map(|(index, data)| CeremonyShare { ceremony_id: ceremony_id.clone(), index, data: SecretBytes::new(&data) }).collect();
+	Ok((transcript, shares))
+}
+
+impl CeremonyTranscript {
+	pub fn ceremony_id(&self) -> &str {
+		&self.ceremony_id
+	}
+	
+	pub fn purpose(&self) -> &str {
+		&self.purpose
+	}
+	
+	pub fn threshold(&self) -> u8 {
+		self.threshold
+	}
+	
+	// returns the number of shares that were acknowledged by their holders
+	pub fn acknowledged(&self) -> usize {
+		self.shares.iter().filter(|share| share.acknowledgment.is_some()).count()
+	}
+	
+	// the part of the transcript signed by the dealer (everything but the acknowledgments)
+	fn signed_content(&self) -> Result<Vec<u8>, DawnError> {
+		let signed = SignedTranscript {
+			ceremony_id: &self.ceremony_id,
+			purpose: &self.purpose,
+			threshold: self.threshold,
+			fingerprint: &self.fingerprint,
+			dealer: &self.dealer,
+			shares: self.shares.iter().map(|share| (share.holder.as_str(), share.commitment.as_str())).collect(),
+		};
+		canonical_json(&signed)
+	}
+	
+	fn commitment(&self, index: u8) -> Result<&ShareCommitment, DawnError> {
+		match self.shares.get((index as usize).wrapping_sub(1)) {
+			Some(res) => Ok(res),
+			None => error!("share index out of range")
+		}
+	}
+	
+	// check that a share belongs to this ceremony and matches its commitment
+	pub fn verify_share(&self, share: &CeremonyShare) -> Result<(), DawnError> {
+		if share.ceremony_id != self.ceremony_id { error!("share belongs to another ceremony"); }
+		let commitment = match self.commitment(share.index) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		if commitment.commitment != share_commitment(&self.ceremony_id, share.index, share.data.as_bytes()) { error!("share does not match the transcript"); }
+		Ok(())
+	}
+	
+	// verify the own share and acknowledge its receipt as its holder
+	pub fn acknowledge(&mut self, share: &CeremonyShare, holder_seckey_sig: &SignSecretKey) -> Result<(), DawnError> {
+		if let Err(err) = self.verify_share(share) { return Err(err); }
+		let content = match self.acknowledgment_content(share.index) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let signature = match sign_detached(CEREMONY_ACK_DOMAIN, &content, holder_seckey_sig.as_bytes()) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let acknowledgment = encode(signature);
+		match self.shares.get_mut((share.index as usize).wrapping_sub(1)) {
+			Some(commitment) => commitment.acknowledgment = Some(acknowledgment),
+			None => error!("share index out of range")
+		}
+		// reject acknowledgments made with a key that isn't the one of the holder
+		self.verify_acknowledgment(share.index)
+	}
+	
+	// an acknowledgment covers the signature of the dealer and the commitment of the acknowledged share
+	fn acknowledgment_content(&self, index: u8) -> Result<Vec<u8>, DawnError> {
+		let commitment = match self.commitment(index) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let mut content = self.signature.as_bytes().to_vec();
+		content.push(index);
+		content.extend_from_slice(commitment.commitment.as_bytes());
+		Ok(content)
+	}
+	
+	fn verify_acknowledgment(&self, index: u8) -> Result<(), DawnError> {
+		let commitment = match self.commitment(index) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let (holder, signature) = match (decode(&commitment.holder), commitment.acknowledgment.as_ref().map(decode)) {
+			(Ok(holder), Some(Ok(signature))) => (holder, signature),
+			(_, None) => return Ok(()),
+			_ => error!("ceremony transcript contains an invalid acknowledgment")
+		};
+		let content = match self.acknowledgment_content(index) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		verify_detached(CEREMONY_ACK_DOMAIN, &content, &signature, &holder)
+	}
+	
+	// verify the signature of the dealer and all acknowledgments
+	pub fn verify(&self, dealer_pubkey_sig: &SignPublicKey) -> Result<(), DawnError> {
+		if self.dealer != encode(dealer_pubkey_sig) { error!("ceremony transcript was signed by another dealer"); }
+		let signature = match decode(&self.signature) {
+			Ok(res) => res,
+			Err(_) => error!(Crypto, "ceremony transcript signature invalid")
+		};
+		let content = match self.signed_content() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		if let Err(err) = verify_detached(CEREMONY_DOMAIN, &content, &signature, dealer_pubkey_sig.as_bytes()) { return Err(err); }
+		for index in 1..=self.shares.len() {
+			if let Err(err) = self.verify_acknowledgment(index as u8) { return Err(err); }
+		}
+		Ok(())
+	}
+	
+	// reassemble the key from at least threshold shares
+	pub fn reassemble(&self, shares: &[CeremonyShare]) -> Result<Vec<u8>, DawnError> {
+		for share in shares {
+			if let Err(err) = self.verify_share(share) { return Err(err); }
+		}
+		if shares.len() < self.threshold as usize { error!(&format!("{} shares are needed to reassemble the key", self.threshold)); }
+		let points: Vec<(u8, &[u8])> = shares.iter().map(|share| (share.index, share.data.as_bytes())).collect();
+		let secret = match combine_shares(&points) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		if fingerprint(&self.ceremony_id, &secret) != self.fingerprint { error!("reassembled key does not match the ceremony"); }
+		Ok(secret)
+	}
+	
+	pub fn to_bytes(&self) -> Result<Vec<u8>, DawnError> {
+		match serde_json::to_vec(self) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "json serialization failed")
+		}
+	}
+	
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, DawnError> {
+		let transcript = match serde_json::from_slice::<CeremonyTranscript>(bytes) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "ceremony transcript json parsing failed")
+		};
+		if transcript.shares.len() > u8::MAX as usize { error!("ceremony transcript has too many shares"); }
+		Ok(transcript)
+	}
+}
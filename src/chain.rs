@@ -0,0 +1,202 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Message chains
+// The pfs key of the next message only exists once the current message was decrypted, so parse_msg can't decrypt a
+// message before all earlier ones arrived, which happens with the Dawn server. Chained messages carry a counter in the
+// routing header and keep the pfs key ratchet of dawn-crypto: the sending chain holds the pfs key of the next message and
+// the counter it gets, the receiving chain the pfs key and counter of the next message in order. A receiver that gets
+// message 5 while expecting message 3 holds the ciphertext of message 5 until messages 3 and 4 were decrypted and the
+// ratchet reached the pfs key of message 5. A held message is only decrypted once, since the counter moves past it. The
+// number of held messages is bounded, so forged counters can't make the receiver buffer an unbounded amount of data, and
+// several candidates are kept per counter, so a forged message can't keep the real one out.
+// The receiving chain also keeps the pfs key of the last message it decrypted in order. Only a clone of the sending
+// session (see device_counter.rs) sends a second, different message with that counter, so a Session can still decrypt it
+// and check its device counter. The last decrypted message stays readable with the kept key until the next one arrives.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use dawn_crypto::hash;
+use crate::secret::SecretBytes;
+use crate::DawnError;
+
+// default number of messages a receiving chain holds
+pub const DEFAULT_MAX_SKIPPED: usize = 1000;
+
+#[derive(Clone)]
+pub struct SendChain {
+	pfs_key: SecretBytes, // pfs key of the next message
+	counter: u64, // counter of the next message
+}
+
+#[derive(Clone)]
+pub struct ReceiveChain {
+	pfs_key: SecretBytes, // pfs key of the next message in order
+	counter: u64, // counter of the next message in order
+	held: BTreeMap<u64, Vec<Vec<u8>>>, // ciphertexts of messages that arrived early, by counter
+	previous: Option<(SecretBytes, Vec<u8>)>, // pfs key and ciphertext hash of the last message decrypted in order
+	max_skipped: usize,
+}
+
+impl SendChain {
+	// start a chain with the pfs key of the sending direction of a conversation
+	pub fn new(pfs_key: &[u8]) -> Self {
+		SendChain::restore(pfs_key, 0)
+	}
+	
+	pub(crate) fn restore(pfs_key: &[u8], counter: u64) -> Self {
+		SendChain {
+			pfs_key: SecretBytes::new(pfs_key),
+			counter,
+		}
+	}
+	
+	// counter of the next message
+	pub fn counter(&self) -> u64 {
+		self.counter
+	}
+	
+	pub(crate) fn pfs_key(&self) -> &[u8] {
+		self.pfs_key.as_bytes()
+	}
+	
+	// move on to the next message once the current one was sent
+	pub(crate) fn advance(&mut self, new_pfs_key: &[u8]) -> Result<(), DawnError> {
+		self.counter = match self.counter.checked_add(1) {
+			Some(res) => res,
+			None => error!("message chain is exhausted")
+		};
+		self.pfs_key = SecretBytes::new(new_pfs_key);
+		Ok(())
+	}
+}
+
+impl ReceiveChain {
+	// start a chain with the pfs key of the receiving direction of a conversation
+	pub fn new(pfs_key: &[u8]) -> Self {
+		ReceiveChain {
+			pfs_key: SecretBytes::new(pfs_key),
+			counter: 0,
+			held: BTreeMap::new(),
+			previous: None,
+			max_skipped: DEFAULT_MAX_SKIPPED,
+		}
+	}
+	
+	pub(crate) fn restore(pfs_key: &[u8], counter: u64, held: Vec<(u64, Vec<u8>)>, previous: Option<(Vec<u8>, Vec<u8>)>) -> Self {
+		let mut chain = ReceiveChain::new(pfs_key);
+		chain.counter = counter;
+		chain.previous = previous.map(|(pfs_key, ciphertext_hash)| (SecretBytes::new(&pfs_key), ciphertext_hash));
+		for (held_counter, ciphertext) in held {
+			chain.held.entry(held_counter).or_default().push(ciphertext);
+		}
+		chain
+	}
+	
+	// hold at most this many messages, dropping the ones furthest ahead
+	pub fn max_skipped(mut self, max_skipped: usize) -> Self {
+		self.max_skipped = max_skipped;
+		while self.held_count() > self.max_skipped {
+			let mut last = match self.held.last_entry() {
+				Some(res) => res,
+				None => break
+			};
+			last.get_mut().pop();
+			if last.get().is_empty() { last.remove(); }
+		}
+		self
+	}
+	
+	// counter of the next message in order
+	pub fn counter(&self) -> u64 {
+		self.counter
+	}
+	
+	// returns the counters of the messages that are still missing before the held ones
+	pub fn skipped(&self) -> Vec<u64> {
+		match self.held.keys().next_back() {
+			Some(last) => (self.counter..*last).filter(|counter| !self.held.contains_key(counter)).collect(),
+			None => Vec::new()
+		}
+	}
+	
+	// returns the counters of the messages that arrived early and wait for the missing ones
+	pub fn held(&self) -> Vec<u64> {
+		self.held.keys().copied().collect()
+	}
+	
+	pub(crate) fn pfs_key(&self) -> &[u8] {
+		self.pfs_key.as_bytes()
+	}
+	
+	// pfs key and ciphertext hash of the last message decrypted in order
+	pub(crate) fn previous(&self) -> Option<(&[u8], &[u8])> {
+		self.previous.as_ref().map(|(pfs_key, ciphertext_hash)| (pfs_key.as_bytes(), ciphertext_hash.as_slice()))
+	}
+	
+	// the pfs key of a message with the counter of the last decrypted one, unless it is that message again
+	pub(crate) fn previous_pfs_key(&self, counter: u64, ciphertext: &[u8]) -> Option<&[u8]> {
+		match &self.previous {
+			Some((pfs_key, ciphertext_hash)) if counter.checked_add(1) == Some(self.counter) && *ciphertext_hash != hash(ciphertext) => Some(pfs_key.as_bytes()),
+			_ => None
+		}
+	}
+	
+	pub(crate) fn held_messages(&self) -> Vec<(u64, &[u8])> {
+		self.held.iter().flat_map(|(counter, ciphertexts)| ciphertexts.iter().map(|ciphertext| (*counter, ciphertext.as_slice()))).collect()
+	}
+	
+	fn held_count(&self) -> usize {
+		self.held.values().map(|ciphertexts| ciphertexts.len()).sum()
+	}
+	
+	// hold the ciphertext of a message that arrived before the next one in order
+	pub(crate) fn hold(&mut self, counter: u64, ciphertext: &[u8]) -> Result<(), DawnError> {
+		if counter < self.counter { error!("message was already received"); }
+		if counter - self.counter > self.max_skipped as u64 { error!("message skips too many messages"); }
+		if self.held_count() >= self.max_skipped { error!("too many messages are held"); }
+		let ciphertexts = self.held.entry(counter).or_default();
+		if ciphertexts.iter().any(|held| held.as_slice() == ciphertext) { error!("message is already held"); }
+		ciphertexts.push(ciphertext.to_vec());
+		Ok(())
+	}
+	
+	// take a held candidate for the next message in order
+	pub(crate) fn take_next(&mut self) -> Option<Vec<u8>> {
+		let mut entry = match self.held.entry(self.counter) {
+			Entry::Occupied(entry) => entry,
+			Entry::Vacant(_) => return None
+		};
+		let ciphertext = entry.get_mut().pop();
+		if entry.get().is_empty() { entry.remove(); }
+		ciphertext
+	}
+	
+	// move on to the next message once the current one (ciphertext) was decrypted
+	pub(crate) fn advance(&mut self, new_pfs_key: &[u8], ciphertext: &[u8]) -> Result<(), DawnError> {
+		self.counter = match self.counter.checked_add(1) {
+			Some(res) => res,
+			None => error!("message chain is exhausted")
+		};
+		let pfs_key = std::mem::replace(&mut self.pfs_key, SecretBytes::new(new_pfs_key));
+		self.previous = Some((pfs_key, hash(ciphertext)));
+		// other candidates for the decrypted message are forgeries or replays
+		self.held = self.held.split_off(&self.counter);
+		Ok(())
+	}
+}
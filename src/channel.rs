@@ -0,0 +1,141 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Administrative actions in channels
+// Pinning announcements and changing settings of a channel is reserved to its publishers. Every action is signed by a
+// publisher and carries a sequence number, so subscribers can verify it against the publisher key set of the channel
+// and apply actions in order, ignoring replayed or outdated ones. The action is signed in its serialized form, so
+// verification does not depend on how a client serializes it.
+
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use crate::codec::{encode, decode};
+use crate::keys::SignPublicKey;
+use crate::signature::{sign_detached, verify_detached};
+use crate::domain::CHANNEL_ACTION_DOMAIN;
+use crate::DawnError;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelAction {
+	Pin(String), // reference of the pinned message
+	Unpin(String),
+	Setting(String, String), // key and value
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SignedChannelAction {
+	channel_id: String,
+	sequence: u64,
+	action: String, // serialized ChannelAction
+	signer: String,
+	signature: String,
+}
+
+// state of a channel as established by verified actions
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChannelAdminState {
+	pub sequence: u64,
+	pub pinned: Vec<String>,
+	pub settings: BTreeMap<String, String>,
+}
+
+// the data covered by the signature of a channel action
+fn channel_action_content(channel_id: &str, sequence: u64, action: &str) -> Vec<u8> {
+	let mut content = (channel_id.len() as u64).to_be_bytes().to_vec();
+	content.extend_from_slice(channel_id.as_bytes());
+	content.extend_from_slice(&sequence.to_be_bytes());
+	content.extend_from_slice(action.as_bytes());
+	content
+}
+
+// sign an administrative action as a publisher of the channel
+// sequence has to be larger than the sequence of every earlier action in the channel
+pub fn gen_channel_action(channel_id: &str, sequence: u64, action: &ChannelAction, own_pubkey_sig: &[u8], own_seckey_sig: &[u8]) -> Result<Vec<u8>, DawnError> {
+	let action = match serde_json::to_string(action) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "json serialization failed")
+	};
+	let signature = match sign_detached(CHANNEL_ACTION_DOMAIN, &channel_action_content(channel_id, sequence, &action), own_seckey_sig) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let signed_action = SignedChannelAction {
+		channel_id: channel_id.to_string(),
+		sequence,
+		action,
+		signer: encode(own_pubkey_sig),
+		signature: encode(signature),
+	};
+	match serde_json::to_vec(&signed_action) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// verify an administrative action against the publisher keys of the channel
+// returns the sequence number and the action
+pub fn verify_channel_action(signed_action: &[u8], channel_id: &str, publishers: &[SignPublicKey]) -> Result<(u64, ChannelAction), DawnError> {
+	let signed_action = match serde_json::from_slice::<SignedChannelAction>(signed_action) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "channel action json parsing failed")
+	};
+	if signed_action.channel_id != channel_id { error!("channel action belongs to another channel"); }
+	let signer = match decode(&signed_action.signer) {
+		Ok(res) => res,
+		Err(_) => error!("channel action signer invalid")
+	};
+	if !publishers.iter().any(|publisher| publisher.as_bytes() == signer.as_slice()) { error!("channel action was not signed by a publisher"); }
+	let signature = match decode(&signed_action.signature) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "channel action signature invalid")
+	};
+	if let Err(err) = verify_detached(CHANNEL_ACTION_DOMAIN, &channel_action_content(channel_id, signed_action.sequence, &signed_action.action), &signature, &signer) {
+		return Err(err);
+	}
+	match serde_json::from_str::<ChannelAction>(&signed_action.action) {
+		Ok(action) => Ok((signed_action.sequence, action)),
+		Err(_) => error!("channel action invalid")
+	}
+}
+
+impl ChannelAdminState {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// verify an action and apply it if it is newer than the current state
+	// returns false if the action was outdated
+	pub fn apply(&mut self, signed_action: &[u8], channel_id: &str, publishers: &[SignPublicKey]) -> Result<bool, DawnError> {
+		let (sequence, action) = match verify_channel_action(signed_action, channel_id, publishers) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		if sequence <= self.sequence { return Ok(false); }
+		match action {
+			ChannelAction::Pin(message) => {
+				if !self.pinned.contains(&message) { self.pinned.push(message); }
+			},
+			ChannelAction::Unpin(message) => self.pinned.retain(|pinned| *pinned != message),
+			ChannelAction::Setting(key, value) => {
+				self.settings.insert(key, value);
+			}
+		}
+		self.sequence = sequence;
+		Ok(true)
+	}
+}
@@ -0,0 +1,54 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Keys for local client data
+// Clients encrypt local data that belongs to a conversation (search index, drafts, thumbnail cache) and must not reuse the
+// PFS key for that: it changes with every message and is a message key, not a storage key. derive_client_key derives a
+// separate key for every purpose from the secrets of the conversation with HKDF-SHA256, so the keys are stable for the
+// lifetime of the conversation, independent of each other and useless for decrypting messages.
+
+use crate::kdf::hkdf_sha256;
+use crate::domain::{CLIENT_KEY_SEARCH_INDEX_DOMAIN, CLIENT_KEY_DRAFTS_DOMAIN, CLIENT_KEY_THUMBNAIL_CACHE_DOMAIN};
+use crate::DawnError;
+
+pub const CLIENT_KEY_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ClientKeyPurpose {
+	SearchIndex,
+	Drafts,
+	ThumbnailCache,
+}
+
+impl ClientKeyPurpose {
+	fn label(self) -> &'static str {
+		match self {
+			ClientKeyPurpose::SearchIndex => CLIENT_KEY_SEARCH_INDEX_DOMAIN,
+			ClientKeyPurpose::Drafts => CLIENT_KEY_DRAFTS_DOMAIN,
+			ClientKeyPurpose::ThumbnailCache => CLIENT_KEY_THUMBNAIL_CACHE_DOMAIN,
+		}
+	}
+}
+
+// derive the key for a local purpose from the secret pfs salt and the id of a conversation
+// returns a key of CLIENT_KEY_LEN bytes that stays the same for the conversation
+pub fn derive_client_key(pfs_salt: &[u8], id: &str, purpose: ClientKeyPurpose) -> Result<Vec<u8>, DawnError> {
+	if pfs_salt.is_empty() { error!("pfs salt must not be empty"); }
+	if id.is_empty() { error!("conversation id must not be empty"); }
+	Ok(hkdf_sha256(id.as_bytes(), pfs_salt, purpose.label().as_bytes()).to_vec())
+}
@@ -0,0 +1,68 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Clocks
+// Everything that depends on the time (expiry, receipts, timeouts, temp id scheduling) gets the current time from a Clock
+// instead of reading the system time itself, so tests and deterministic replays can control it. Clients normally use the
+// SystemClock; a ManualClock only moves when it is told to and all its clones share the same time.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use dawn_crypto::get_current_timestamp;
+
+pub trait Clock {
+	// returns the current unix timestamp in seconds
+	fn now(&self) -> u64;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> u64 {
+		get_current_timestamp()
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+	now: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+	pub fn new(now: u64) -> Self {
+		ManualClock {
+			now: Arc::new(AtomicU64::new(now)),
+		}
+	}
+	
+	pub fn set(&self, now: u64) {
+		self.now.store(now, Ordering::SeqCst);
+	}
+	
+	// move the clock forward by the given number of seconds
+	pub fn advance(&self, seconds: u64) {
+		let _ = self.now.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |now| Some(now.saturating_add(seconds)));
+	}
+}
+
+impl Clock for ManualClock {
+	fn now(&self) -> u64 {
+		self.now.load(Ordering::SeqCst)
+	}
+}
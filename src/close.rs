@@ -0,0 +1,94 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Closing conversations
+// "Delete chat for both sides" needs both clients to agree that a conversation is over. One side sends a close request,
+// the other side answers it with an acknowledgement, and from then on neither side sends into the conversation. Both
+// are INTERNAL messages with event::CONVERSATION_CLOSE, signed over the conversation id, so a close can't be forged by
+// the server or replayed into another conversation. Session tracks the handshake in its SessionStatus.
+
+use serde::{Serialize, Deserialize};
+use crate::codec::{encode, decode};
+use crate::signature::{sign_detached, verify_detached};
+use crate::domain::CONVERSATION_CLOSE_DOMAIN;
+use crate::DawnError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConversationClose {
+	Request(String), // id of the close handshake
+	Ack(String), // id of the acknowledged close request
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SignedConversationClose {
+	ack: bool,
+	close_id: String,
+	signature: String,
+}
+
+// the data covered by the signature of a close message
+fn close_content(id: &str, ack: bool, close_id: &str) -> Vec<u8> {
+	let mut content = (id.len() as u64).to_be_bytes().to_vec();
+	content.extend_from_slice(id.as_bytes());
+	content.push(ack as u8);
+	content.extend_from_slice(close_id.as_bytes());
+	content
+}
+
+// sign a close request or acknowledgement for the conversation with the given id
+// returns the event data for an INTERNAL message with event::CONVERSATION_CLOSE
+pub fn gen_conversation_close(id: &str, close: &ConversationClose, own_seckey_sig: &[u8]) -> Result<Vec<u8>, DawnError> {
+	let (ack, close_id) = match close {
+		ConversationClose::Request(close_id) => (false, close_id),
+		ConversationClose::Ack(close_id) => (true, close_id)
+	};
+	if close_id.is_empty() { error!("close id must not be empty"); }
+	let signature = match sign_detached(CONVERSATION_CLOSE_DOMAIN, &close_content(id, ack, close_id), own_seckey_sig) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let signed_close = SignedConversationClose {
+		ack,
+		close_id: close_id.clone(),
+		signature: encode(signature),
+	};
+	match serde_json::to_vec(&signed_close) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// verify the event data of a close message against the signature key of the peer
+pub fn parse_conversation_close(data: &[u8], id: &str, remote_pubkey_sig: &[u8]) -> Result<ConversationClose, DawnError> {
+	let signed_close = match serde_json::from_slice::<SignedConversationClose>(data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "conversation close json parsing failed")
+	};
+	if signed_close.close_id.is_empty() { error!("close id must not be empty"); }
+	let signature = match decode(&signed_close.signature) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "conversation close signature invalid")
+	};
+	if let Err(err) = verify_detached(CONVERSATION_CLOSE_DOMAIN, &close_content(id, signed_close.ack, &signed_close.close_id), &signature, remote_pubkey_sig) {
+		return Err(err);
+	}
+	match signed_close.ack {
+		false => Ok(ConversationClose::Request(signed_close.close_id)),
+		true => Ok(ConversationClose::Ack(signed_close.close_id))
+	}
+}
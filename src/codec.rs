@@ -0,0 +1,89 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Hex and base64 encoding used for keys and binary message content
+// With the "simd" feature, vectorized implementations are used instead of the hex and base64 crates. Both variants
+// produce and accept exactly the same encodings.
+
+#[cfg(not(feature = "simd"))]
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use crate::DawnError;
+
+#[cfg(not(feature = "simd"))]
+pub(crate) fn encode<T: AsRef<[u8]>>(data: T) -> String {
+	hex::encode(data)
+}
+
+#[cfg(feature = "simd")]
+pub(crate) fn encode<T: AsRef<[u8]>>(data: T) -> String {
+	faster_hex::hex_string(data.as_ref())
+}
+
+#[cfg(not(feature = "simd"))]
+pub(crate) fn decode<T: AsRef<[u8]>>(data: T) -> Result<Vec<u8>, DawnError> {
+	match hex::decode(data) {
+		Ok(res) => Ok(res),
+		Err(err) => Err(DawnError::Serialization(err.to_string()))
+	}
+}
+
+#[cfg(feature = "simd")]
+pub(crate) fn decode<T: AsRef<[u8]>>(data: T) -> Result<Vec<u8>, DawnError> {
+	let data = data.as_ref();
+	if data.len() % 2 != 0 { return Err(DawnError::Serialization(String::from("odd number of hex digits"))); }
+	let mut bytes = vec![0u8; data.len() / 2];
+	match faster_hex::hex_decode(data, &mut bytes) {
+		Ok(_) => Ok(bytes),
+		Err(err) => Err(DawnError::Serialization(err.to_string()))
+	}
+}
+
+#[cfg(not(feature = "simd"))]
+pub(crate) fn encode_base64<T: AsRef<[u8]>>(data: T) -> String {
+	BASE64.encode(data)
+}
+
+#[cfg(feature = "simd")]
+pub(crate) fn encode_base64<T: AsRef<[u8]>>(data: T) -> String {
+	base64_simd::STANDARD_NO_PAD.encode_to_string(data)
+}
+
+#[cfg(not(feature = "simd"))]
+pub(crate) fn decode_base64<T: AsRef<[u8]>>(data: T) -> Result<Vec<u8>, DawnError> {
+	match BASE64.decode(data) {
+		Ok(res) => Ok(res),
+		Err(err) => Err(DawnError::Serialization(err.to_string()))
+	}
+}
+
+#[cfg(feature = "simd")]
+pub(crate) fn decode_base64<T: AsRef<[u8]>>(data: T) -> Result<Vec<u8>, DawnError> {
+	match base64_simd::STANDARD_NO_PAD.decode_to_vec(data) {
+		Ok(res) => Ok(res),
+		Err(err) => Err(DawnError::Serialization(err.to_string()))
+	}
+}
+
+// split bytes at the given position without panicking
+// returns None if there are less than mid bytes
+pub(crate) fn split_bytes(bytes: &[u8], mid: usize) -> Option<(&[u8], &[u8])> {
+	match (bytes.get(..mid), bytes.get(mid..)) {
+		(Some(left), Some(right)) => Some((left, right)),
+		_ => None
+	}
+}
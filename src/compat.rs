@@ -0,0 +1,317 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Compatibility with earlier wire formats
+// Messages can wait on the content server for a long time, so an upgraded client still has to decrypt the messages
+// that were queued for it by clients of the previous version. The structs below are a frozen copy of the JSON layout of
+// protocol version 1, with or without routing header, and are not changed when the current layout changes. parse_compat_msg
+// parses JSON messages of version 1 with them and hands everything else to parse_msg_with_config, so it keeps working for queued
+// messages after a new format (see wire_format.rs and version.rs) became the default.
+
+use serde::Deserialize;
+use dawn_crypto::decrypt_msg;
+use crate::codec::{decode, decode_base64};
+use crate::effect::Effect;
+use crate::transcription::Transcription;
+use crate::reply::Reply;
+use crate::media_codec::check_codec;
+use crate::limits::{self, ParseLimits};
+use crate::received::{ReceivedMessage, ParsedMessage};
+use crate::warning::{Warning, check_warning};
+use crate::{routing, escrow, wire_format, version};
+use crate::config::ProtocolConfig;
+use crate::parse_msg_with_config;
+use crate::DawnError;
+
+// format of a received message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireGeneration {
+	JsonV1, // JSON message of protocol version 1 (or older, without a version), parsed with the frozen layout
+	Current, // any other message, parsed by parse_msg
+}
+
+// fields that were added to version 1 over time are optional, unknown fields are ignored
+#[derive(Deserialize)]
+enum LegacyMessage {
+	Text(LegacyText),
+	Internal(LegacyInternal),
+	Voice(LegacyVoice),
+	Picture(LegacyPicture),
+	LinkedMedia(LegacyLinkedMedia),
+	HistorySync(LegacyHistorySync),
+	DeltaSync(LegacyDeltaSync),
+	Gateway(LegacyGateway),
+	Reaction(LegacyReaction),
+	Retraction(LegacyRetraction),
+}
+
+#[derive(Deserialize)]
+struct LegacyText {
+	text: String,
+	mdc: String,
+	#[serde(default)]
+	thread_id: Option<String>,
+	#[serde(default)]
+	effect: Option<Effect>,
+	#[serde(default)]
+	in_reply_to: Option<LegacyReply>,
+}
+
+#[derive(Deserialize)]
+struct LegacyInternal {
+	event: u8,
+	event_data: String,
+	mdc: String,
+}
+
+#[derive(Deserialize)]
+struct LegacyVoice {
+	voice: String,
+	mdc: String,
+	#[serde(default)]
+	thread_id: Option<String>,
+	#[serde(default)]
+	transcription: Option<LegacyTranscription>,
+	#[serde(default)]
+	in_reply_to: Option<LegacyReply>,
+	#[serde(default)]
+	codec: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LegacyTranscription {
+	text: String,
+	language: String,
+}
+
+#[derive(Deserialize)]
+struct LegacyReply {
+	mdc: String,
+	#[serde(default)]
+	excerpt: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LegacyPicture {
+	picture: String,
+	description: String,
+	#[serde(default)]
+	alt_text: Option<String>,
+	mdc: String,
+	#[serde(default)]
+	thread_id: Option<String>,
+	#[serde(default)]
+	effect: Option<Effect>,
+	#[serde(default)]
+	in_reply_to: Option<LegacyReply>,
+	#[serde(default)]
+	codec: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LegacyLinkedMedia {
+	media_type: u8,
+	media_link: String,
+	media_key: String,
+	description: String,
+	mdc: String,
+	#[serde(default)]
+	expires_at: Option<u64>,
+	#[serde(default)]
+	delete_token: Option<String>,
+	#[serde(default)]
+	thread_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LegacyHistorySync {
+	transfer_id: String,
+	chunk_index: u32,
+	chunk_count: u32,
+	chunk: String,
+	mdc: String,
+}
+
+#[derive(Deserialize)]
+struct LegacyDeltaSync {
+	delta: String,
+	mdc: String,
+}
+
+#[derive(Deserialize)]
+struct LegacyGateway {
+	envelope: String,
+	#[serde(default)]
+	gateway_data: Option<String>,
+	mdc: String,
+}
+
+#[derive(Deserialize)]
+struct LegacyReaction {
+	target: String,
+	reaction: String,
+	mdc: String,
+}
+
+#[derive(Deserialize)]
+struct LegacyRetraction {
+	target: String,
+	mdc: String,
+}
+
+impl LegacyReply {
+	fn into_reply(self) -> Result<Reply, DawnError> {
+		let reply = Reply { mdc: self.mdc, excerpt: self.excerpt };
+		match reply.check() {
+			Ok(()) => Ok(reply),
+			Err(err) => Err(err)
+		}
+	}
+}
+
+fn legacy_codec(codec: Option<String>) -> Result<Option<String>, DawnError> {
+	match codec.as_deref().map(check_codec) {
+		Some(Err(err)) => Err(err),
+		_ => Ok(codec)
+	}
+}
+
+fn legacy_data(data: &str, field: &str) -> Result<Vec<u8>, DawnError> {
+	match decode_base64(data) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(&format!("{} invalid", field))
+	}
+}
+
+impl LegacyMessage {
+	// returns the message, message detail code and thread id
+	fn into_received(self) -> Result<(ReceivedMessage, String, Option<String>), DawnError> {
+		let parsed = match self {
+			LegacyMessage::Text(msg) => match msg.in_reply_to.map(LegacyReply::into_reply).transpose() {
+				Ok(in_reply_to) => (ReceivedMessage::Text { text: msg.text, effect: msg.effect, in_reply_to, expires_after: None }, msg.mdc, msg.thread_id),
+				Err(err) => return Err(err)
+			},
+			LegacyMessage::Internal(msg) => match legacy_data(&msg.event_data, "event data") {
+				Ok(data) => (ReceivedMessage::Internal { event: msg.event, data }, msg.mdc, None),
+				Err(err) => return Err(err)
+			},
+			LegacyMessage::Voice(msg) => match (legacy_data(&msg.voice, "voice message data"), msg.in_reply_to.map(LegacyReply::into_reply).transpose(), legacy_codec(msg.codec)) {
+				(Ok(data), Ok(in_reply_to), Ok(codec)) => {
+					let transcription = msg.transcription.map(|transcription| Transcription { text: transcription.text, language: transcription.language });
+					(ReceivedMessage::Voice { data, transcription, in_reply_to, codec, expires_after: None }, msg.mdc, msg.thread_id)
+				},
+				(Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => return Err(err)
+			},
+			LegacyMessage::Picture(msg) => match (legacy_data(&msg.picture, "picture data"), msg.in_reply_to.map(LegacyReply::into_reply).transpose(), legacy_codec(msg.codec)) {
+				(Ok(data), Ok(in_reply_to), Ok(codec)) => (ReceivedMessage::Picture { data, description: msg.description, alt_text: msg.alt_text, effect: msg.effect, in_reply_to, codec, expires_after: None }, msg.mdc, msg.thread_id),
+				(Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => return Err(err)
+			},
+			LegacyMessage::LinkedMedia(msg) => {
+				let delete_token = match msg.delete_token.as_ref().map(decode) {
+					Some(Ok(res)) => Some(res),
+					Some(Err(_)) => error!("linked media delete token invalid"),
+					None => None
+				};
+				let message = ReceivedMessage::LinkedMedia {
+					link: msg.media_link,
+					key: msg.media_key,
+					description: msg.description,
+					media_type: msg.media_type,
+					expires_at: msg.expires_at,
+					delete_token,
+				};
+				(message, msg.mdc, msg.thread_id)
+			},
+			LegacyMessage::HistorySync(msg) => match legacy_data(&msg.chunk, "history chunk data") {
+				Ok(chunk) => (ReceivedMessage::HistorySync { transfer_id: msg.transfer_id, chunk_index: msg.chunk_index, chunk_count: msg.chunk_count, chunk }, msg.mdc, None),
+				Err(err) => return Err(err)
+			},
+			LegacyMessage::DeltaSync(msg) => match legacy_data(&msg.delta, "delta sync data") {
+				Ok(delta) => (ReceivedMessage::DeltaSync { delta }, msg.mdc, None),
+				Err(err) => return Err(err)
+			},
+			LegacyMessage::Gateway(msg) => {
+				let data = match msg.gateway_data.as_deref().map(|data| legacy_data(data, "gateway message data")) {
+					Some(Ok(res)) => Some(res),
+					Some(Err(err)) => return Err(err),
+					None => None
+				};
+				(ReceivedMessage::Gateway { envelope: msg.envelope, data }, msg.mdc, None)
+			},
+			LegacyMessage::Reaction(msg) => (ReceivedMessage::Reaction { target: msg.target, reaction: msg.reaction }, msg.mdc, None),
+			LegacyMessage::Retraction(msg) => (ReceivedMessage::Retraction { target: msg.target }, msg.mdc, None)
+		};
+		Ok(parsed)
+	}
+}
+
+// parse a decrypted JSON message of protocol version 1
+// returns the message, message detail code and thread id
+pub(crate) fn parse_legacy_content(msg_content: &str) -> Result<(ReceivedMessage, String, Option<String>), DawnError> {
+	if let Err(err) = limits::check_fields(msg_content, &ParseLimits::default()) { return Err(err); }
+	match serde_json::from_str::<LegacyMessage>(msg_content) {
+		Ok(message) => message.into_received(),
+		Err(_) => error!(Serialization, "json parsing failed")
+	}
+}
+
+// parse a message that may have been built by an earlier version of the library
+// returns the same as parse_msg_with_config and the format the message was built with
+pub fn parse_compat_msg(msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8]) -> Result<(ParsedMessage, WireGeneration), DawnError> {
+	if let Err(err) = limits::check_ciphertext(msg_ciphertext, &ParseLimits::default()) { return Err(err); }
+	
+	// messages of version 1 may carry a routing header and an escrow copy, but never a binary payload
+	let (header, ciphertext) = match routing::split_routing_header(msg_ciphertext) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let (escrow_parts, ciphertext) = match escrow::split_escrow(ciphertext) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let binary = match wire_format::split_binary(ciphertext) {
+		Ok(res) => res.is_some(),
+		Err(err) => return Err(err)
+	};
+	let current = || match parse_msg_with_config(msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, &ProtocolConfig::default()) {
+		Ok(res) => Ok((res, WireGeneration::Current)),
+		Err(err) => Err(err)
+	};
+	if binary { return current(); }
+	
+	let (msg_content, new_pfs_key, warning) = match decrypt_msg(own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, ciphertext) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "decryption failed")
+	};
+	let warning = Warning::from_code(warning);
+	if let Err(err) = check_warning(warning, remote_pubkey_sig) { return Err(err); }
+	match version::message_version(&msg_content) {
+		Ok(1) => (),
+		Ok(_) => return current(),
+		Err(err) => return Err(err)
+	}
+	
+	let (message, mdc, thread_id) = match parse_legacy_content(&msg_content) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if let Some(header) = header {
+		if let Err(err) = routing::check_routing_header(&header, message.content_type(), &mdc, thread_id.is_some(), escrow_parts.is_some(), warning, None) { return Err(err); }
+	}
+	Ok((ParsedMessage { message, new_pfs_key, mdc, warning, thread_id, seq: None, device: None }, WireGeneration::JsonV1))
+}
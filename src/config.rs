@@ -0,0 +1,101 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Protocol configuration
+// The knobs that decide how messages are sent and parsed were passed to the functions that needed them one by one. A
+// ProtocolConfig bundles them, so a deployment sets its configuration up once, hands it to Session or the *_with_config
+// functions and can review all of it in one place. The default matches send_msg and parse_msg: no limits, lenient
+// parsing, signatures required whenever the signature key of the sender is passed, no padding and no compression.
+// Padding fills the serialized message up to a multiple of the bucket size before it is encrypted, so the length of the
+// ciphertext only reveals the bucket. Compression deflates binary messages (see wire_format.rs) for peers that announced
+// capability::COMPRESSION, JSON messages are never compressed. Received deflated messages are only inflated if
+// compression is enabled, up to ParseLimits::max_decompressed_len, otherwise they are rejected.
+
+use crate::limits::{ParseLimits, ParseMode};
+use crate::DawnError;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignaturePolicy {
+	#[default]
+	Required, // messages have to be signed if the signature key of the sender is passed
+	AllowUnsigned, // unsigned messages are accepted and returned with Warning::Unsigned
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Padding {
+	#[default]
+	None,
+	Bucket(usize), // pad to a multiple of this many bytes
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProtocolConfig {
+	pub limits: ParseLimits,
+	pub signature_policy: SignaturePolicy,
+	pub padding: Padding,
+	pub compression: bool,
+}
+
+impl ProtocolConfig {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// reject received messages exceeding the limits (see ParseLimits::low_memory)
+	pub fn limits(mut self, limits: ParseLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+	
+	// reject received messages with fields this version of the library doesn't know (see ParseMode)
+	pub fn strict(mut self, strict: bool) -> Self {
+		self.limits.mode = if strict { ParseMode::Strict } else { ParseMode::Lenient };
+		self
+	}
+	
+	pub fn signature_policy(mut self, signature_policy: SignaturePolicy) -> Self {
+		self.signature_policy = signature_policy;
+		self
+	}
+	
+	pub fn padding(mut self, padding: Padding) -> Self {
+		self.padding = padding;
+		self
+	}
+	
+	pub fn compression(mut self, compression: bool) -> Self {
+		self.compression = compression;
+		self
+	}
+}
+
+// pad a serialized message with the filler byte up to the next multiple of the bucket size
+// the parsers of both wire formats stop at the end of the message, so the padding is never read
+pub(crate) fn pad(message: &mut Vec<u8>, padding: Padding, filler: u8) -> Result<(), DawnError> {
+	let bucket = match padding {
+		Padding::None => return Ok(()),
+		Padding::Bucket(0) => error!("padding bucket size must not be zero"),
+		Padding::Bucket(bucket) => bucket
+	};
+	let padded_len = match message.len().div_ceil(bucket).checked_mul(bucket) {
+		Some(res) => res,
+		None => error!("padded message too large")
+	};
+	message.resize(padded_len, filler);
+	Ok(())
+}
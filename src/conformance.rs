@@ -0,0 +1,284 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Conformance test runner
+// Other implementations of Dawn certify that they are compatible with this library by running test vectors against it.
+// A test vector directory holds JSON Lines files (*.jsonl), one vector per line, and every vector is one of:
+// - message: a ciphertext with the keys to decrypt it, expecting the content and mdc or an error code
+// - handshake: an init request with the secret keys of the handle it was sent to, expecting id, peer name, comment and
+//   capabilities or an error code
+// - plaintext: a decrypted message that is only parsed, for malformed inputs that don't need any keys
+// Binary fields are hex encoded. Expected errors are given as DawnError::code. The report lists the result of every
+// vector and is serialized as JSON, so it can be checked by scripts or published along with the implementation.
+
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::codec::{encode, decode};
+use crate::content_type::ContentType;
+use crate::received::ReceivedMessage;
+use crate::limits::ParseLimits;
+use crate::version::PROTOCOL_VERSION;
+use crate::{parse_msg, parse_init_request, parse_message_content};
+use crate::DawnError;
+
+const VECTOR_EXTENSION: &str = "jsonl";
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Vector {
+	Message {
+		name: String,
+		ciphertext: String,
+		own_seckey_kyber: String,
+		remote_pubkey_sig: Option<String>,
+		pfs_key: String,
+		pfs_salt: String,
+		expect: Option<ExpectedMessage>,
+		expect_error: Option<String>,
+	},
+	Handshake {
+		name: String,
+		request: String,
+		own_seckey_kyber: String,
+		own_seckey_curve: String,
+		own_seckey_curve_pfs_2: String,
+		own_seckey_kyber_for_salt: String,
+		own_seckey_curve_for_salt: String,
+		expect: Option<ExpectedHandshake>,
+		expect_error: Option<String>,
+	},
+	Plaintext {
+		name: String,
+		content: String,
+		expect: Option<ExpectedMessage>,
+		expect_error: Option<String>,
+	},
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+struct ExpectedMessage {
+	content_type: ContentType,
+	text: Option<String>,
+	data: Option<String>, // hex encoded
+	mdc: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+struct ExpectedHandshake {
+	id: String,
+	name: String,
+	comment: String,
+	capabilities: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConformanceResult {
+	pub file: String,
+	pub name: String,
+	pub passed: bool,
+	pub detail: Option<String>, // why the vector failed
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConformanceReport {
+	pub protocol_version: u16,
+	pub passed: usize,
+	pub failed: usize,
+	pub results: Vec<ConformanceResult>,
+}
+
+impl ConformanceReport {
+	pub fn all_passed(&self) -> bool {
+		self.failed == 0
+	}
+	
+	// the machine-readable form of the report
+	pub fn to_json(&self) -> Result<String, DawnError> {
+		match serde_json::to_string_pretty(self) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "json serialization failed")
+		}
+	}
+}
+
+// decode a hex field of a vector
+fn decode_field(name: &str, value: &str) -> Result<Vec<u8>, String> {
+	decode(value).map_err(|_| format!("vector field {} is not valid hex", name))
+}
+
+// compare the outcome of a vector with its expectation
+// returns why they differ
+fn compare<T: PartialEq + Debug>(outcome: Result<T, DawnError>, expect: Option<T>, expect_error: Option<String>) -> Result<(), String> {
+	match (outcome, expect, expect_error) {
+		(_, Some(_), Some(_)) | (_, None, None) => Err("vector has to give either expect or expect_error".to_string()),
+		(Ok(outcome), Some(expect), None) if outcome == expect => Ok(()),
+		(Ok(outcome), Some(expect), _) => Err(format!("expected {:?}, got {:?}", expect, outcome)),
+		(Ok(outcome), None, Some(code)) => Err(format!("expected error {}, got {:?}", code, outcome)),
+		(Err(err), None, Some(code)) if err.code() == code => Ok(()),
+		(Err(err), _, _) => Err(format!("unexpected error {}: {}", err.code(), err.message()))
+	}
+}
+
+// the received message in the form of a message expectation
+fn message_outcome(outcome: Result<(ReceivedMessage, String), DawnError>) -> Result<ExpectedMessage, DawnError> {
+	let (message, mdc) = match outcome {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let (content_type, text, data) = message.into_content();
+	Ok(ExpectedMessage {
+		content_type,
+		text,
+		data: data.map(encode),
+		mdc,
+	})
+}
+
+// decrypt a message vector
+fn run_message(ciphertext: &str, own_seckey_kyber: &str, remote_pubkey_sig: Option<&str>, pfs_key: &str, pfs_salt: &str) -> Result<Result<ExpectedMessage, DawnError>, String> {
+	let ciphertext = match decode_field("ciphertext", ciphertext) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let own_seckey_kyber = match decode_field("own_seckey_kyber", own_seckey_kyber) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let remote_pubkey_sig = match remote_pubkey_sig.map(|key| decode_field("remote_pubkey_sig", key)) {
+		Some(Ok(res)) => Some(res),
+		Some(Err(err)) => return Err(err),
+		None => None
+	};
+	let (pfs_key, pfs_salt) = match (decode_field("pfs_key", pfs_key), decode_field("pfs_salt", pfs_salt)) {
+		(Ok(pfs_key), Ok(pfs_salt)) => (pfs_key, pfs_salt),
+		(Err(err), _) | (_, Err(err)) => return Err(err)
+	};
+	let outcome = parse_msg(&ciphertext, &own_seckey_kyber, remote_pubkey_sig.as_deref(), &pfs_key, &pfs_salt).map(|(message, _, mdc, _)| (message, mdc));
+	Ok(message_outcome(outcome))
+}
+
+// parse a handshake vector with the secret keys of the handle (in the order of parse_init_request)
+fn run_handshake(request: &str, keys: [(&str, &str); 5]) -> Result<Result<ExpectedHandshake, DawnError>, String> {
+	let request = match decode_field("request", request) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let mut decoded = Vec::with_capacity(keys.len());
+	for (field, key) in keys {
+		match decode_field(field, key) {
+			Ok(res) => decoded.push(res),
+			Err(err) => return Err(err)
+		}
+	}
+	let [kyber, curve, curve_pfs_2, kyber_for_salt, curve_for_salt] = decoded.as_slice() else {
+		return Err("vector is missing keys".to_string());
+	};
+	Ok(parse_init_request(&request, kyber, curve, curve_pfs_2, kyber_for_salt, curve_for_salt).map(|request| ExpectedHandshake {
+		id: request.id,
+		name: request.peer.name,
+		comment: request.comment,
+		capabilities: request.peer.capabilities,
+	}))
+}
+
+// run a single vector
+// returns its name and why it failed
+fn run_vector(vector: Vector) -> (String, Result<(), String>) {
+	match vector {
+		Vector::Message { name, ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, expect, expect_error } => {
+			let expect = expect.map(|expect| ExpectedMessage { data: expect.data.map(|data| data.to_lowercase()), ..expect });
+			match run_message(&ciphertext, &own_seckey_kyber, remote_pubkey_sig.as_deref(), &pfs_key, &pfs_salt) {
+				Ok(outcome) => (name, compare(outcome, expect, expect_error)),
+				Err(err) => (name, Err(err))
+			}
+		},
+		Vector::Handshake { name, request, own_seckey_kyber, own_seckey_curve, own_seckey_curve_pfs_2, own_seckey_kyber_for_salt, own_seckey_curve_for_salt, expect, expect_error } => {
+			let keys = [
+				("own_seckey_kyber", own_seckey_kyber.as_str()),
+				("own_seckey_curve", own_seckey_curve.as_str()),
+				("own_seckey_curve_pfs_2", own_seckey_curve_pfs_2.as_str()),
+				("own_seckey_kyber_for_salt", own_seckey_kyber_for_salt.as_str()),
+				("own_seckey_curve_for_salt", own_seckey_curve_for_salt.as_str()),
+			];
+			match run_handshake(&request, keys) {
+				Ok(outcome) => (name, compare(outcome, expect, expect_error)),
+				Err(err) => (name, Err(err))
+			}
+		},
+		Vector::Plaintext { name, content, expect, expect_error } => {
+			let outcome = parse_message_content(&content, &ParseLimits::default()).map(|parsed| (parsed.message, parsed.mdc));
+			(name, compare(message_outcome(outcome), expect, expect_error))
+		}
+	}
+}
+
+// run the vectors of in-memory files, given as (file name, JSON Lines content)
+pub fn run_conformance_vectors(files: &[(&str, &str)]) -> ConformanceReport {
+	let mut results = Vec::new();
+	for (file, content) in files {
+		for (index, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+			let (name, outcome) = match serde_json::from_str::<Vector>(line) {
+				Ok(vector) => run_vector(vector),
+				Err(err) => (format!("line {}", index + 1), Err(format!("vector could not be parsed: {}", err)))
+			};
+			results.push(ConformanceResult {
+				file: file.to_string(),
+				name,
+				passed: outcome.is_ok(),
+				detail: outcome.err(),
+			});
+		}
+	}
+	let passed = results.iter().filter(|result| result.passed).count();
+	ConformanceReport {
+		protocol_version: PROTOCOL_VERSION,
+		passed,
+		failed: results.len() - passed,
+		results,
+	}
+}
+
+// run all vector files (*.jsonl) of a directory, in the order of their names
+pub fn run_conformance(dir: &Path) -> Result<ConformanceReport, DawnError> {
+	let entries = match fs::read_dir(dir) {
+		Ok(res) => res,
+		Err(_) => error!(Storage, "test vector directory could not be read")
+	};
+	let mut paths = Vec::new();
+	for entry in entries {
+		match entry {
+			Ok(entry) if entry.path().extension().is_some_and(|extension| extension == VECTOR_EXTENSION) => paths.push(entry.path()),
+			Ok(_) => (),
+			Err(_) => error!(Storage, "test vector directory could not be read")
+		}
+	}
+	paths.sort();
+	
+	let mut files = Vec::with_capacity(paths.len());
+	for path in paths {
+		let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+		match fs::read_to_string(&path) {
+			Ok(content) => files.push((name, content)),
+			Err(_) => error!(Storage, "test vector file could not be read")
+		}
+	}
+	let files: Vec<(&str, &str)> = files.iter().map(|(name, content)| (name.as_str(), content.as_str())).collect();
+	Ok(run_conformance_vectors(&files))
+}
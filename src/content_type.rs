@@ -16,8 +16,68 @@
 	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-pub const INTERNAL: u8 = 0;
-pub const TEXT: u8 = 1;
-pub const VOICE: u8 = 2;
-pub const PICTURE: u8 = 3;
-pub const LINKED_MEDIA: u8 = 200;
+// Content types
+// Messages carry their content type on the wire as a single byte. Clients only handle ContentType values, so an invalid
+// byte can't be passed to send_msg; values received from elsewhere are checked with ContentType::try_from.
+
+use serde::{Serialize, Deserialize};
+use crate::DawnError;
+
+// serialized as the byte used on the wire
+#[non_exhaustive]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(into = "u8", try_from = "u8")]
+pub enum ContentType {
+	Internal,
+	Text,
+	Voice,
+	Picture,
+	Reaction,
+	Retraction,
+	LinkedMedia,
+	HistorySync,
+	DeltaSync,
+	Gateway,
+}
+
+pub const INTERNAL: ContentType = ContentType::Internal;
+pub const TEXT: ContentType = ContentType::Text;
+pub const VOICE: ContentType = ContentType::Voice;
+pub const PICTURE: ContentType = ContentType::Picture;
+pub const REACTION: ContentType = ContentType::Reaction;
+pub const RETRACTION: ContentType = ContentType::Retraction;
+pub const LINKED_MEDIA: ContentType = ContentType::LinkedMedia;
+pub const HISTORY_SYNC: ContentType = ContentType::HistorySync;
+pub const DELTA_SYNC: ContentType = ContentType::DeltaSync;
+pub const GATEWAY: ContentType = ContentType::Gateway;
+
+// content types this version of the library can send and parse
+pub const SUPPORTED: [ContentType; 10] = [INTERNAL, TEXT, VOICE, PICTURE, REACTION, RETRACTION, LINKED_MEDIA, HISTORY_SYNC, DELTA_SYNC, GATEWAY];
+
+impl From<ContentType> for u8 {
+	fn from(content_type: ContentType) -> Self {
+		match content_type {
+			ContentType::Internal => 0,
+			ContentType::Text => 1,
+			ContentType::Voice => 2,
+			ContentType::Picture => 3,
+			ContentType::Reaction => 4,
+			ContentType::Retraction => 5,
+			ContentType::LinkedMedia => 200,
+			ContentType::HistorySync => 201,
+			ContentType::DeltaSync => 202,
+			ContentType::Gateway => 203,
+		}
+	}
+}
+
+impl TryFrom<u8> for ContentType {
+	type Error = DawnError;
+	
+	fn try_from(value: u8) -> Result<Self, DawnError> {
+		match SUPPORTED.iter().find(|content_type| u8::from(**content_type) == value) {
+			Some(content_type) => Ok(*content_type),
+			None => error!(&format!("unknown content type {}", value))
+		}
+	}
+}
@@ -0,0 +1,57 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Reusable buffers for the hot paths
+// Clients that process many messages in a row (e.g. bulk decryption after being offline) can keep a Context around
+// and use send_msg_with_context/parse_msg_with_context. The buffer for serializing messages then keeps its allocation
+// between calls instead of being allocated for every single message, and the binary content of the last parsed message
+// is copied into the data buffer of the context, which only grows if a message doesn't fit.
+
+use crate::content_type::ContentType;
+
+// content type, text and data as returned by parse_msg_with_context, the data is borrowed from the context
+pub type BorrowedContent<'a> = (ContentType, Option<String>, Option<&'a [u8]>);
+
+// content type, text and data as returned by ReceivedMessage::into_content
+pub type OwnedContent = (ContentType, Option<String>, Option<Vec<u8>>);
+
+#[derive(Default)]
+pub struct Context {
+	pub(crate) serialization_buffer: Vec<u8>,
+	pub(crate) data_buffer: Vec<u8>,
+}
+
+impl Context {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// create a context with buffers that can hold messages of the given size without growing
+	pub fn with_capacity(capacity: usize) -> Self {
+		Context {
+			serialization_buffer: Vec::with_capacity(capacity),
+			data_buffer: Vec::with_capacity(capacity),
+		}
+	}
+	
+	// free the memory held by the buffers, e.g. after a large media message was processed
+	pub fn shrink(&mut self) {
+		self.serialization_buffer = Vec::new();
+		self.data_buffer = Vec::new();
+	}
+}
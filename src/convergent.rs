@@ -0,0 +1,101 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Deduplicated linked media
+// Normally every linked media file is encrypted with a random key, so sending the same file into several conversations
+// uploads it once per conversation. With convergent keys, which have to be requested explicitly for every file, the key is
+// derived from the hash of the file and a secret of the account instead. Encrypting the same file again results in the
+// same key and dedup id, and the client can send the link of the earlier upload (wrapping the key for the new conversation
+// with wrap_media_key as usual) instead of uploading the file again.
+// Privacy tradeoff: the key of a file no longer depends on the conversation it was sent into. Everyone who received the
+// file from this account in one conversation can decrypt its uploads in all other conversations, and the content server
+// sees the same file being downloaded by members of different conversations and can link them. Deleting or expiring the
+// upload removes the file from all conversations at once. Other accounts can't derive the keys or dedup ids, so the
+// content server can't confirm guesses about the content. Only use convergent keys for files where linking the
+// conversations they were sent into is acceptable.
+
+use std::collections::HashMap;
+use dawn_crypto::{hash, encrypt_data};
+use crate::codec::encode;
+use crate::domain::{CONVERGENT_KEY_DOMAIN, DEDUP_ID_DOMAIN};
+use crate::DawnError;
+
+const CONVERGENT_KEY_LEN: usize = 32;
+const MIN_ACCOUNT_SECRET_LEN: usize = 32;
+
+fn derive(domain: &str, account_secret: &[u8], file_hash: &[u8]) -> Vec<u8> {
+	let mut input = domain.as_bytes().to_vec();
+	for part in [account_secret, file_hash] {
+		input.extend_from_slice(&(part.len() as u64).to_be_bytes());
+		input.extend_from_slice(part);
+	}
+	hash(&input)
+}
+
+// derive the convergent key and dedup id of a file
+// the account secret has to be random, at least 32 bytes long and the same on all devices of the account
+// returns the key and the dedup id
+pub fn derive_convergent_key(file: &[u8], account_secret: &[u8]) -> Result<(Vec<u8>, String), DawnError> {
+	if account_secret.len() < MIN_ACCOUNT_SECRET_LEN { error!("account secret too short"); }
+	let file_hash = hash(file);
+	let mut key = derive(CONVERGENT_KEY_DOMAIN, account_secret, &file_hash);
+	if key.len() < CONVERGENT_KEY_LEN { error!(Crypto, "hash output too short to derive a convergent key"); }
+	key.truncate(CONVERGENT_KEY_LEN);
+	let dedup_id = derive(DEDUP_ID_DOMAIN, account_secret, &file_hash);
+	Ok((key, encode(dedup_id.get(..16).unwrap_or(&dedup_id))))
+}
+
+// encrypt a file with its convergent key instead of a random one (opt-in replacement of encrypt_file)
+// returns the ciphertext, the key and the dedup id
+pub fn encrypt_file_convergent(file: &[u8], account_secret: &[u8]) -> Result<(Vec<u8>, Vec<u8>, String), DawnError> {
+	let (key, dedup_id) = match derive_convergent_key(file, account_secret) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	match encrypt_data(file, &key) {
+		Ok(ciphertext) => Ok((ciphertext, key, dedup_id)),
+		Err(err) => { error!(Crypto, &format!("file encryption failed: {}", err)); }
+	}
+}
+
+// links of the files uploaded with convergent keys, by dedup id
+// the client has to persist the uploads and forget them when they expire on the content server
+#[derive(Default)]
+pub struct ConvergentUploads {
+	uploads: HashMap<String, String>,
+}
+
+impl ConvergentUploads {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// remember the link of an upload
+	pub fn add(&mut self, dedup_id: &str, media_link: &str) {
+		self.uploads.insert(dedup_id.to_string(), media_link.to_string());
+	}
+	
+	// returns the link of an earlier upload of the same file
+	pub fn get(&self, dedup_id: &str) -> Option<&str> {
+		self.uploads.get(dedup_id).map(|media_link| media_link.as_str())
+	}
+	
+	pub fn remove(&mut self, dedup_id: &str) {
+		self.uploads.remove(dedup_id);
+	}
+}
@@ -0,0 +1,134 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Data-saver mode for media messages
+// The policy decides whether a picture or voice message is sent inline or has to go through a content server as linked
+// media. Pictures that are too large can be downscaled by a hook of the client first. Clients pass whether the current
+// connection is metered, everything else is configured once.
+
+use crate::content_type::{self, ContentType};
+use crate::estimate::estimate_ciphertext_len;
+use crate::{send_msg, encrypt_file};
+use crate::DawnError;
+
+// downscales a picture, see DataSaverPolicy::downscale_image
+type DownscaleHook = Box<dyn Fn(&[u8]) -> Result<Vec<u8>, DawnError>>;
+
+pub struct DataSaverPolicy {
+	max_inline_size: usize,
+	metered: bool,
+	prefer_linked_on_metered: bool,
+	downscale_image: Option<DownscaleHook>,
+}
+
+// result of sending media according to a policy
+pub enum MediaSend {
+	Inline((Vec<u8>, String, Vec<u8>)), // the message was sent inline, same as the result of send_msg
+	Upload((Vec<u8>, Vec<u8>)), // the media has to be uploaded and sent as LINKED_MEDIA, same as the result of encrypt_file
+}
+
+impl Default for DataSaverPolicy {
+	fn default() -> Self {
+		DataSaverPolicy {
+			max_inline_size: 1024 * 1024,
+			metered: false,
+			prefer_linked_on_metered: true,
+			downscale_image: None,
+		}
+	}
+}
+
+impl DataSaverPolicy {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// largest ciphertext that is sent inline
+	pub fn max_inline_size(mut self, max_inline_size: usize) -> Self {
+		self.max_inline_size = max_inline_size;
+		self
+	}
+	
+	// whether the current connection is metered (set by the client whenever the connection changes)
+	pub fn metered(mut self, metered: bool) -> Self {
+		self.metered = metered;
+		self
+	}
+	
+	// send all media as linked media on metered connections, so recipients only download it when they want to
+	pub fn prefer_linked_on_metered(mut self, prefer_linked_on_metered: bool) -> Self {
+		self.prefer_linked_on_metered = prefer_linked_on_metered;
+		self
+	}
+	
+	// hook that returns a smaller version of a picture that is too large to be sent inline
+	pub fn downscale_image<F: Fn(&[u8]) -> Result<Vec<u8>, DawnError> + 'static>(mut self, hook: F) -> Self {
+		self.downscale_image = Some(Box::new(hook));
+		self
+	}
+	
+	// returns the data that should be sent inline or None if the media should be sent as linked media
+	fn inline_data(&self, content: (ContentType, Option<&str>, &[u8]), signed: bool) -> Result<Option<Vec<u8>>, DawnError> {
+		let (content_type, text, data) = content;
+		if self.metered && self.prefer_linked_on_metered { return Ok(None); }
+		let fits = |data: &[u8]| match estimate_ciphertext_len((content_type, text, Some(data)), signed) {
+			Ok(len) => Ok(len <= self.max_inline_size),
+			Err(err) => Err(err)
+		};
+		match fits(data) {
+			Ok(true) => return Ok(Some(data.to_vec())),
+			Ok(false) => (),
+			Err(err) => return Err(err)
+		}
+		
+		// try to make pictures small enough
+		let hook = match &self.downscale_image {
+			Some(hook) if content_type == content_type::PICTURE => hook,
+			_ => return Ok(None)
+		};
+		let downscaled = match hook(data) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		match fits(&downscaled) {
+			Ok(true) => Ok(Some(downscaled)),
+			Ok(false) => Ok(None),
+			Err(err) => Err(err)
+		}
+	}
+}
+
+// send a picture or voice message according to the policy
+// returns the sent message or the encrypted file that has to be uploaded to a content server
+pub fn send_file(policy: &DataSaverPolicy, (content_type, text, data): (ContentType, Option<&str>, &[u8]), remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<MediaSend, DawnError> {
+	if content_type != content_type::PICTURE && content_type != content_type::VOICE { error!("only pictures and voice messages can be sent inline"); }
+	let inline_data = match policy.inline_data((content_type, text, data), own_seckey_sig.is_some()) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	match inline_data {
+		Some(inline_data) => match send_msg((content_type, text, Some(&inline_data)), remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt, id, mdc_seed) {
+			Ok(res) => Ok(MediaSend::Inline(res)),
+			Err(err) => Err(err)
+		},
+		None => match encrypt_file(data) {
+			Ok(res) => Ok(MediaSend::Upload(res)),
+			Err(err) => Err(err)
+		}
+	}
+}
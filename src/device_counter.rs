@@ -0,0 +1,117 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Device counters
+// If the state of a session is restored from a backup onto two devices at once, both clones continue the same ratchet and
+// the peer can't tell them apart. To detect this, every device puts a counter into its messages that increases with
+// every message and is covered by the message signature. A clone reuses counter values its twin already sent, so the
+// CounterTracker of the peer sees a counter that doesn't increase and reports a SecurityEvent instead of trusting it.
+// A Session keeps both and persists them in its state: it stamps every sent message and checks every received one. The
+// clones of a session send their messages with the same chain counters, so the session decrypts a clone's message with
+// the pfs key of the message it decrypted before (see chain.rs) and rejects it with DawnError::Security.
+
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::DawnError;
+
+// counter as it is put into a message
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DeviceStamp {
+	pub(crate) device: String,
+	pub(crate) counter: u64,
+}
+
+// the counter of the own device, it has to be persisted together with the session state
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeviceCounter {
+	device_id: String,
+	counter: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SecurityEvent {
+	// a message of the device carried a counter that was not larger than an earlier one
+	ClonedSession { device_id: String, counter: u64, last_counter: u64 },
+	// the server published init keys in the own handle that this client never generated (see KeyAudit)
+	UnknownPublishedKeys { keys: Vec<String> },
+}
+
+// last counter seen per device of the peer, it has to be persisted together with the session state
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CounterTracker {
+	last_counters: HashMap<String, u64>,
+}
+
+impl DeviceCounter {
+	pub fn new(device_id: &str) -> Self {
+		Self::resume(device_id, 0)
+	}
+	
+	// continue with a persisted counter
+	pub fn resume(device_id: &str, counter: u64) -> Self {
+		DeviceCounter { device_id: device_id.to_string(), counter }
+	}
+	
+	pub fn device_id(&self) -> &str {
+		&self.device_id
+	}
+	
+	// returns the counter of the last message sent
+	pub fn counter(&self) -> u64 {
+		self.counter
+	}
+	
+	pub(crate) fn next(&mut self) -> Result<DeviceStamp, DawnError> {
+		self.counter = match self.counter.checked_add(1) {
+			Some(res) => res,
+			None => error!("device counter exhausted")
+		};
+		Ok(DeviceStamp { device: self.device_id.clone(), counter: self.counter })
+	}
+}
+
+impl CounterTracker {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// continue with a persisted counter of a device
+	pub fn restore(&mut self, device_id: &str, last_counter: u64) {
+		self.last_counters.insert(device_id.to_string(), last_counter);
+	}
+	
+	pub fn last_counter(&self, device_id: &str) -> Option<u64> {
+		self.last_counters.get(device_id).copied()
+	}
+	
+	// check the counter of a received message
+	// returns a security event if the counter did not increase, the last counter is kept in that case
+	pub(crate) fn check(&mut self, stamp: &DeviceStamp) -> Option<SecurityEvent> {
+		match self.last_counters.get(&stamp.device) {
+			Some(last_counter) if stamp.counter <= *last_counter => Some(SecurityEvent::ClonedSession {
+				device_id: stamp.device.clone(),
+				counter: stamp.counter,
+				last_counter: *last_counter,
+			}),
+			_ => {
+				self.last_counters.insert(stamp.device.clone(), stamp.counter);
+				None
+			}
+		}
+	}
+}
@@ -0,0 +1,63 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Disappearing messages
+// Either side of a conversation can set a timer after which text, voice and picture messages are deleted. The timer is
+// sent as an INTERNAL message with event::DISAPPEARING_TIMER and applies to both sides from then on, the last timer that
+// was sent or received wins. Every message sent while the timer is set carries it as expires_after, so the recipient
+// deletes the message even if it missed the timer event (or a newer client sets one per message). Recipients count
+// expires_after from the time they received the message.
+
+use serde::{Serialize, Deserialize};
+use crate::outgoing::OutgoingMessage;
+use crate::event;
+use crate::DawnError;
+
+// longest timer that can be set (one year, in seconds)
+pub const MAX_EXPIRES_AFTER: u64 = 365 * 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisappearingTimer {
+	pub expires_after: Option<u64>, // in seconds, None turns disappearing messages off
+}
+
+pub(crate) fn check_expires_after(expires_after: u64) -> Result<(), DawnError> {
+	if expires_after == 0 || expires_after > MAX_EXPIRES_AFTER { error!("expiry of disappearing messages out of range"); }
+	Ok(())
+}
+
+// set the disappearing message timer of the conversation, None turns it off
+pub fn gen_disappearing_timer(expires_after: Option<u64>) -> Result<OutgoingMessage, DawnError> {
+	if let Some(Err(err)) = expires_after.map(check_expires_after) { return Err(err); }
+	match serde_json::to_vec(&DisappearingTimer { expires_after }) {
+		Ok(res) => Ok(OutgoingMessage::internal(event::DISAPPEARING_TIMER, &res)),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// parse the event data of a disappearing message timer
+pub fn parse_disappearing_timer(data: &[u8]) -> Result<DisappearingTimer, DawnError> {
+	let timer = match serde_json::from_slice::<DisappearingTimer>(data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "disappearing timer json parsing failed")
+	};
+	match timer.expires_after.map(check_expires_after) {
+		Some(Err(err)) => Err(err),
+		_ => Ok(timer)
+	}
+}
@@ -0,0 +1,128 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Domain separation labels
+// Every key, identifier and signature the library derives covers a label that names its purpose, so an output made for
+// one purpose can never be used for another one. All labels are defined here and listed by domain_labels(), so auditors
+// can check the domain separation without reading every call site. check_domain_separation() verifies that no two
+// derivations share a label; clients can append the labels of their own documents (see sign_document) before checking.
+// Derivation labels are directly followed by the input, so none of them may be a prefix of another one. Signature labels
+// are terminated by a zero byte (see the signature module) and only have to be distinct.
+
+use crate::DawnError;
+
+// key and identifier derivations
+pub(crate) const ESCROW_FINGERPRINT_DOMAIN: &str = "dawn-escrow-fingerprint";
+pub(crate) const ESCROW_KEY_DOMAIN: &str = "dawn-escrow-key";
+pub(crate) const MEDIA_KEY_DOMAIN: &str = "dawn-media-key";
+pub(crate) const CONVERGENT_KEY_DOMAIN: &str = "dawn-convergent-key";
+pub(crate) const DEDUP_ID_DOMAIN: &str = "dawn-convergent-id";
+pub(crate) const PAIRING_GENERATOR_DOMAIN: &str = "dawn-pairing-generator";
+pub(crate) const PAIRING_KEY_DOMAIN: &str = "dawn-pairing-key";
+pub(crate) const SECURITY_ENCODING_DOMAIN: &str = "dawn-security-encoding";
+pub(crate) const CLIENT_KEY_SEARCH_INDEX_DOMAIN: &str = "dawn-client-key:search-index";
+pub(crate) const CLIENT_KEY_DRAFTS_DOMAIN: &str = "dawn-client-key:drafts";
+pub(crate) const CLIENT_KEY_THUMBNAIL_CACHE_DOMAIN: &str = "dawn-client-key:thumbnail-cache";
+pub(crate) const SESSION_EXPORT_DOMAIN: &str = "dawn-session-export";
+pub(crate) const IDENTITY_FINGERPRINT_DOMAIN: &str = "dawn-identity-fingerprint";
+pub(crate) const GROUP_IDENTITY_DOMAIN: &str = "dawn-group-identity";
+pub(crate) const CHAIN_KEY_DOMAIN: &str = "dawn-chain-key";
+pub(crate) const CHAIN_MESSAGE_KEY_DOMAIN: &str = "dawn-chain-message-key";
+pub(crate) const BINARY_PAYLOAD_DOMAIN: &str = "dawn-binary-payload";
+
+// signatures
+pub(crate) const ABUSE_REPORT_DOMAIN: &str = "dawn-abuse-report";
+pub(crate) const APPLICATION_CERTIFICATE_DOMAIN: &str = "dawn-application-identity";
+pub(crate) const CEREMONY_DOMAIN: &str = "dawn-key-ceremony";
+pub(crate) const CEREMONY_ACK_DOMAIN: &str = "dawn-key-ceremony-ack";
+pub(crate) const CHANNEL_ACTION_DOMAIN: &str = "dawn-channel-action";
+pub(crate) const CONVERSATION_CLOSE_DOMAIN: &str = "dawn-conversation-close";
+pub(crate) const DELETE_TOKEN_DOMAIN: &str = "dawn-media-delete";
+pub(crate) const UPLOAD_TICKET_DOMAIN: &str = "dawn-upload-ticket";
+pub(crate) const GROUP_MESSAGE_DOMAIN: &str = "dawn-group-message";
+pub(crate) const GROUP_MEMBERSHIP_DOMAIN: &str = "dawn-group-membership";
+pub(crate) const REGISTRATION_DOMAIN: &str = "dawn-account-registration";
+pub(crate) const SERVER_CHALLENGE_DOMAIN: &str = "dawn-server-challenge";
+pub(crate) const DOCUMENT_DOMAIN_PREFIX: &str = "dawn-document:"; // followed by the domain passed to sign_document
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelKind {
+	Derivation, // hashed together with the input to derive a key or identifier
+	Signature, // signed together with the data (see sign_detached)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DomainLabel {
+	pub label: &'static str,
+	pub kind: LabelKind,
+	pub purpose: &'static str,
+}
+
+const DOMAIN_LABELS: [DomainLabel; 30] = [
+	DomainLabel { label: ESCROW_FINGERPRINT_DOMAIN, kind: LabelKind::Derivation, purpose: "fingerprint of an escrow key" },
+	DomainLabel { label: ESCROW_KEY_DOMAIN, kind: LabelKind::Derivation, purpose: "key sealing the escrow copy of a message" },
+	DomainLabel { label: MEDIA_KEY_DOMAIN, kind: LabelKind::Derivation, purpose: "key wrapping the key of a linked media file" },
+	DomainLabel { label: CONVERGENT_KEY_DOMAIN, kind: LabelKind::Derivation, purpose: "convergent file key" },
+	DomainLabel { label: DEDUP_ID_DOMAIN, kind: LabelKind::Derivation, purpose: "dedup id of a convergently encrypted file" },
+	DomainLabel { label: PAIRING_GENERATOR_DOMAIN, kind: LabelKind::Derivation, purpose: "generator derived from a pairing code" },
+	DomainLabel { label: PAIRING_KEY_DOMAIN, kind: LabelKind::Derivation, purpose: "key shared after a pairing" },
+	DomainLabel { label: SECURITY_ENCODING_DOMAIN, kind: LabelKind::Derivation, purpose: "emoji and spoken encodings of a security number" },
+	DomainLabel { label: CLIENT_KEY_SEARCH_INDEX_DOMAIN, kind: LabelKind::Derivation, purpose: "client key for the search index of a conversation" },
+	DomainLabel { label: CLIENT_KEY_DRAFTS_DOMAIN, kind: LabelKind::Derivation, purpose: "client key for the drafts of a conversation" },
+	DomainLabel { label: CLIENT_KEY_THUMBNAIL_CACHE_DOMAIN, kind: LabelKind::Derivation, purpose: "client key for the thumbnail cache of a conversation" },
+	DomainLabel { label: SESSION_EXPORT_DOMAIN, kind: LabelKind::Derivation, purpose: "passphrase key of an exported session" },
+	DomainLabel { label: IDENTITY_FINGERPRINT_DOMAIN, kind: LabelKind::Derivation, purpose: "fingerprint of the signature key of a peer" },
+	DomainLabel { label: GROUP_IDENTITY_DOMAIN, kind: LabelKind::Derivation, purpose: "fingerprint of the signature key of a group member" },
+	DomainLabel { label: CHAIN_KEY_DOMAIN, kind: LabelKind::Derivation, purpose: "next chain key of a group sender key" },
+	DomainLabel { label: CHAIN_MESSAGE_KEY_DOMAIN, kind: LabelKind::Derivation, purpose: "key of a group message" },
+	DomainLabel { label: BINARY_PAYLOAD_DOMAIN, kind: LabelKind::Derivation, purpose: "hash binding the payload of a binary message to its signed key" },
+	DomainLabel { label: ABUSE_REPORT_DOMAIN, kind: LabelKind::Signature, purpose: "abuse report" },
+	DomainLabel { label: APPLICATION_CERTIFICATE_DOMAIN, kind: LabelKind::Signature, purpose: "certificate of an application identity" },
+	DomainLabel { label: CEREMONY_DOMAIN, kind: LabelKind::Signature, purpose: "transcript of a key ceremony" },
+	DomainLabel { label: CEREMONY_ACK_DOMAIN, kind: LabelKind::Signature, purpose: "acknowledgement of a key ceremony share" },
+	DomainLabel { label: CHANNEL_ACTION_DOMAIN, kind: LabelKind::Signature, purpose: "channel admin action" },
+	DomainLabel { label: CONVERSATION_CLOSE_DOMAIN, kind: LabelKind::Signature, purpose: "close request or acknowledgement of a conversation" },
+	DomainLabel { label: DELETE_TOKEN_DOMAIN, kind: LabelKind::Signature, purpose: "delete token of a media file" },
+	DomainLabel { label: UPLOAD_TICKET_DOMAIN, kind: LabelKind::Signature, purpose: "upload ticket of a media file" },
+	DomainLabel { label: GROUP_MESSAGE_DOMAIN, kind: LabelKind::Signature, purpose: "message fanned out to a group" },
+	DomainLabel { label: GROUP_MEMBERSHIP_DOMAIN, kind: LabelKind::Signature, purpose: "membership change of a group" },
+	DomainLabel { label: REGISTRATION_DOMAIN, kind: LabelKind::Signature, purpose: "registration request of a new account" },
+	DomainLabel { label: SERVER_CHALLENGE_DOMAIN, kind: LabelKind::Signature, purpose: "answer to an authentication challenge of a server" },
+	DomainLabel { label: DOCUMENT_DOMAIN_PREFIX, kind: LabelKind::Signature, purpose: "prefix of the domains of signed documents" },
+];
+
+// returns all labels used by the library
+pub fn domain_labels() -> &'static [DomainLabel] {
+	&DOMAIN_LABELS
+}
+
+// check that the labels separate all derivations
+// fails with the first two labels that could be confused
+pub fn check_domain_separation(labels: &[DomainLabel]) -> Result<(), DawnError> {
+	for (index, first) in labels.iter().enumerate() {
+		if first.label.is_empty() { error!(&format!("empty label for {}", first.purpose)); }
+		for second in labels.iter().skip(index + 1) {
+			let overlapping = first.kind == LabelKind::Derivation && second.kind == LabelKind::Derivation
+				&& (first.label.starts_with(second.label) || second.label.starts_with(first.label));
+			if first.label == second.label || overlapping {
+				error!(&format!("labels \"{}\" ({}) and \"{}\" ({}) are not separated", first.label, first.purpose, second.label, second.purpose));
+			}
+		}
+	}
+	Ok(())
+}
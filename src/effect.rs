@@ -0,0 +1,91 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Message effects
+// Text and picture messages can ask the receiving client to play an effect (e.g. confetti or an invisible ink reveal)
+// when the message is shown. Effects are registered here and travel by name, so an effect added in a later version
+// arrives as Effect::Unknown at older clients, which then show the message without it.
+
+use serde::{Serialize, Deserialize};
+use crate::DawnError;
+
+pub const MAX_EFFECT_NAME_LEN: usize = 32;
+
+// serialized as the name of the effect
+#[non_exhaustive]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(into = "String", from = "String")]
+pub enum Effect {
+	Confetti,
+	InvisibleInk, // content is hidden until the recipient reveals it
+	Balloons,
+	Fireworks,
+	Shake,
+	Unknown(String), // an effect this version of the library does not know, carrying its name
+}
+
+// effects this version of the library knows
+pub const KNOWN_EFFECTS: [Effect; 5] = [Effect::Confetti, Effect::InvisibleInk, Effect::Balloons, Effect::Fireworks, Effect::Shake];
+
+impl Effect {
+	pub fn name(&self) -> &str {
+		match self {
+			Effect::Confetti => "confetti",
+			Effect::InvisibleInk => "invisible_ink",
+			Effect::Balloons => "balloons",
+			Effect::Fireworks => "fireworks",
+			Effect::Shake => "shake",
+			Effect::Unknown(name) => name,
+		}
+	}
+	
+	// whether the receiving client can play the effect
+	pub fn is_known(&self) -> bool {
+		!matches!(self, Effect::Unknown(_))
+	}
+}
+
+impl From<Effect> for String {
+	fn from(effect: Effect) -> Self {
+		effect.name().to_string()
+	}
+}
+
+impl From<String> for Effect {
+	fn from(name: String) -> Self {
+		match KNOWN_EFFECTS.iter().find(|effect| effect.name() == name) {
+			Some(effect) => effect.clone(),
+			None => Effect::Unknown(name)
+		}
+	}
+}
+
+impl From<&str> for Effect {
+	fn from(name: &str) -> Self {
+		Effect::from(name.to_string())
+	}
+}
+
+// check an effect before it is sent
+// unknown effects can be sent as long as their name is valid, so clients can use effects that are not registered yet
+pub(crate) fn check_effect(effect: &Effect) -> Result<(), DawnError> {
+	let name = effect.name();
+	if name.is_empty() || name.len() > MAX_EFFECT_NAME_LEN { error!("effect name must be between 1 and 32 characters"); }
+	if !name.bytes().all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'_') { error!("effect name may only contain lowercase letters, digits and underscores"); }
+	Ok(())
+}
@@ -0,0 +1,82 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Errors
+// All fallible functions return a DawnError. The variant tells clients what kind of failure happened (e.g. to tell a
+// tampered message apart from a bug in the client), the message describes it. The Display output is the same as the
+// error strings of earlier versions, so clients that log or compare those keep working.
+
+use std::error::Error;
+use std::fmt;
+use crate::warning::Warning;
+use crate::oversized::OversizedMedia;
+use crate::device_counter::SecurityEvent;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DawnError {
+	Crypto(String), // a cryptographic operation failed (e.g. decryption, key agreement or an invalid signature)
+	Serialization(String), // data could not be encoded or decoded (JSON, base64, hex)
+	InvalidInput(String), // the arguments or the received data are malformed or not allowed
+	Storage(String), // a storage backend failed to read or write (see Storage)
+	SignatureWarning(Warning), // signature verification was requested, but the message did not carry a signature
+	MdcMismatch { expected: String, received: String }, // the message carries another message detail code than it was received with
+	UnsupportedVersion(u16), // the message or the peer uses a protocol version this library does not support (see SUPPORTED_VERSIONS)
+	Corrupted, // the message was damaged in transport (see routing.rs), fetching or sending it again can help
+	TooLarge(Box<OversizedMedia>), // the inline media of the message exceeds the local limit (see oversized.rs)
+	Security(SecurityEvent), // the message revealed a security problem, e.g. a cloned session of the peer (see device_counter.rs)
+}
+
+impl DawnError {
+	// the description of the error, without the prefix of the Display output
+	pub fn message(&self) -> &str {
+		match self {
+			DawnError::Crypto(message) | DawnError::Serialization(message) | DawnError::InvalidInput(message) | DawnError::Storage(message) => message,
+			DawnError::SignatureWarning(_) => "CRITICAL: signature verification was requested, but the remote side did not provide a signature",
+			DawnError::MdcMismatch { .. } => "CRITICAL: the message detail code of the message does not match the one it was received with",
+			DawnError::UnsupportedVersion(_) => "the protocol version is not supported",
+			DawnError::Corrupted => "the message was damaged in transport",
+			DawnError::TooLarge(_) => "the inline media of the message exceeds the local limit",
+			DawnError::Security(_) => "CRITICAL: the message revealed a security problem, e.g. a cloned session of the peer"
+		}
+	}
+	
+	// stable name of the variant, e.g. for test vectors that expect an error (see conformance.rs)
+	pub fn code(&self) -> &'static str {
+		match self {
+			DawnError::Crypto(_) => "crypto",
+			DawnError::Serialization(_) => "serialization",
+			DawnError::InvalidInput(_) => "invalid_input",
+			DawnError::Storage(_) => "storage",
+			DawnError::SignatureWarning(_) => "signature_warning",
+			DawnError::MdcMismatch { .. } => "mdc_mismatch",
+			DawnError::UnsupportedVersion(_) => "unsupported_version",
+			DawnError::Corrupted => "corrupted",
+			DawnError::TooLarge(_) => "too_large",
+			DawnError::Security(_) => "security"
+		}
+	}
+}
+
+impl fmt::Display for DawnError {
+	fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		write!(formatter, "@dawn-stdlib: {}", self.message())
+	}
+}
+
+impl Error for DawnError {}
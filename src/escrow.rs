@@ -0,0 +1,154 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Conversation key escrow
+// Some organizations are required to be able to read the messages of their members. Instead of handing out conversation
+// keys, SendOptions::escrow encrypts a second copy of every message to the escrow key of the organization: a fresh key is
+// encapsulated to the escrow kyber key and encrypts the serialized message. The copy is put in front of the normal
+// ciphertext together with the fingerprint of the escrow key, so the server stores it with the message and the escrow
+// holder can decrypt it with open_escrow without any conversation keys.
+// Escrow is never enabled implicitly, and it can't be hidden from the recipients: the parse functions accept escrowed
+// messages, and escrow_status tells the client which escrow key a received message was escrowed to, so it can show that
+// escrow is active in the conversation.
+
+use dawn_crypto::{hash, get_kyber_secret, decrypt_kyber_secret, encrypt_data, decrypt_data};
+use crate::codec::{encode, split_bytes};
+use crate::keys::{KyberPublicKey, KyberSecretKey, KYBER_CIPHERTEXT_LEN};
+use crate::limits::ParseLimits;
+use crate::parse_message_content;
+use crate::routing::split_routing_header;
+use crate::received::ReceivedMessage;
+use crate::domain::{ESCROW_FINGERPRINT_DOMAIN, ESCROW_KEY_DOMAIN};
+use crate::DawnError;
+
+const ESCROW_MAGIC: &[u8] = b"DAWNESC1";
+const FINGERPRINT_LEN: usize = 16;
+const ESCROW_KEY_LEN: usize = 32;
+
+// fingerprint of an escrow key as shown to users
+pub fn escrow_key_fingerprint(escrow_pubkey_kyber: &KyberPublicKey) -> String {
+	encode(fingerprint(escrow_pubkey_kyber))
+}
+
+fn fingerprint(escrow_pubkey_kyber: &KyberPublicKey) -> Vec<u8> {
+	let mut input = ESCROW_FINGERPRINT_DOMAIN.as_bytes().to_vec();
+	input.extend_from_slice(escrow_pubkey_kyber.as_bytes());
+	let mut fingerprint = hash(&input);
+	fingerprint.truncate(FINGERPRINT_LEN);
+	fingerprint
+}
+
+fn escrow_key(shared_secret: &[u8]) -> Result<Vec<u8>, DawnError> {
+	let mut input = ESCROW_KEY_DOMAIN.as_bytes().to_vec();
+	input.extend_from_slice(shared_secret);
+	let mut key = hash(&input);
+	if key.len() < ESCROW_KEY_LEN { error!(Crypto, "hash output too short to derive an escrow key"); }
+	key.truncate(ESCROW_KEY_LEN);
+	Ok(key)
+}
+
+// put the escrow copy of a serialized message in front of its ciphertext
+// format: magic, fingerprint, kyber ciphertext, length of the sealed message (u32 BE), sealed message, ciphertext
+pub(crate) fn attach_escrow(escrow_pubkey_kyber: &KyberPublicKey, message: &[u8], msg_ciphertext: &[u8]) -> Result<Vec<u8>, DawnError> {
+	let (shared_secret, kyber_ciphertext) = match get_kyber_secret(escrow_pubkey_kyber.as_bytes()) {
+		Ok(res) => res,
+		Err(err) => { error!(Crypto, &format!("escrow key encapsulation failed: {}", err)); }
+	};
+	if kyber_ciphertext.len() != KYBER_CIPHERTEXT_LEN { error!(Crypto, "escrow key encapsulation returned a ciphertext of unexpected size"); }
+	let sealed = match escrow_key(&shared_secret).map(|key| encrypt_data(message, &key)) {
+		Ok(Ok(res)) => res,
+		Ok(Err(err)) => { error!(Crypto, &format!("escrow encryption failed: {}", err)); },
+		Err(err) => return Err(err)
+	};
+	let sealed_len = match u32::try_from(sealed.len()) {
+		Ok(res) => res,
+		Err(_) => error!("message too large for escrow")
+	};
+	let mut envelope = ESCROW_MAGIC.to_vec();
+	envelope.extend_from_slice(&fingerprint(escrow_pubkey_kyber));
+	envelope.extend_from_slice(&kyber_ciphertext);
+	envelope.extend_from_slice(&sealed_len.to_be_bytes());
+	envelope.extend_from_slice(&sealed);
+	envelope.extend_from_slice(msg_ciphertext);
+	Ok(envelope)
+}
+
+// parts of the escrow copy: fingerprint, kyber ciphertext and sealed message
+type EscrowParts<'a> = (&'a [u8], &'a [u8], &'a [u8]);
+
+// split a received message into the escrow copy (if any) and the ciphertext of the message
+pub(crate) fn split_escrow(envelope: &[u8]) -> Result<(Option<EscrowParts<'_>>, &[u8]), DawnError> {
+	let rest = match envelope.strip_prefix(ESCROW_MAGIC) {
+		Some(res) => res,
+		None => return Ok((None, envelope))
+	};
+	let (fingerprint, rest) = match split_bytes(rest, FINGERPRINT_LEN) {
+		Some(res) => res,
+		None => error!("escrowed message truncated")
+	};
+	let (kyber_ciphertext, rest) = match split_bytes(rest, KYBER_CIPHERTEXT_LEN) {
+		Some(res) => res,
+		None => error!("escrowed message truncated")
+	};
+	let (sealed_len, rest) = match split_bytes(rest, 4).map(|(len, rest)| (<[u8; 4]>::try_from(len), rest)) {
+		Some((Ok(len), rest)) => (u32::from_be_bytes(len) as usize, rest),
+		_ => error!("escrowed message truncated")
+	};
+	match split_bytes(rest, sealed_len) {
+		Some((sealed, msg_ciphertext)) => Ok((Some((fingerprint, kyber_ciphertext, sealed)), msg_ciphertext)),
+		None => error!("escrowed message truncated")
+	}
+}
+
+// check whether a received message was escrowed
+// returns the fingerprint of the escrow key or None for messages without escrow
+pub fn escrow_status(msg_ciphertext: &[u8]) -> Result<Option<String>, DawnError> {
+	match split_routing_header(msg_ciphertext).and_then(|(_, rest)| split_escrow(rest)) {
+		Ok((Some((fingerprint, _, _)), _)) => Ok(Some(encode(fingerprint))),
+		Ok((None, _)) => Ok(None),
+		Err(err) => Err(err)
+	}
+}
+
+// decrypt the escrow copy of a message as holder of the escrow key
+// returns the message and its message detail code
+pub fn open_escrow(msg_ciphertext: &[u8], escrow_pubkey_kyber: &KyberPublicKey, escrow_seckey_kyber: &KyberSecretKey) -> Result<(ReceivedMessage, String), DawnError> {
+	let (fingerprint_of_message, kyber_ciphertext, sealed) = match split_routing_header(msg_ciphertext).and_then(|(_, rest)| split_escrow(rest)) {
+		Ok((Some(res), _)) => res,
+		Ok((None, _)) => error!("message was not escrowed"),
+		Err(err) => return Err(err)
+	};
+	if fingerprint_of_message != fingerprint(escrow_pubkey_kyber).as_slice() { error!("message was escrowed to another key"); }
+	let shared_secret = match decrypt_kyber_secret(kyber_ciphertext, escrow_seckey_kyber.as_bytes()) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "escrow decryption failed")
+	};
+	let message = match escrow_key(&shared_secret).map(|key| decrypt_data(sealed, &key)) {
+		Ok(Ok(res)) => res,
+		Ok(Err(_)) => error!(Crypto, "escrow decryption failed"),
+		Err(err) => return Err(err)
+	};
+	let message = match String::from_utf8(message) {
+		Ok(res) => res,
+		Err(_) => error!("escrowed message invalid")
+	};
+	match parse_message_content(&message, &ParseLimits::default()) {
+		Ok(parsed) => Ok((parsed.message, parsed.mdc)),
+		Err(err) => Err(err)
+	}
+}
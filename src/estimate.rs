@@ -0,0 +1,92 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Ciphertext size estimation
+// The length of a ciphertext is the length of the serialized message plus the routing header and an overhead added by the
+// encryption (kyber ciphertext, nonce, tag and the optional signature), neither of which depends on the message. The
+// overhead is measured once by encrypting an empty message, so the message itself only has to be serialized to know the
+// size of the ciphertext.
+
+use std::io;
+use std::sync::OnceLock;
+use dawn_crypto::{encrypt_msg, kyber_keygen, sign_keygen, sym_key_gen, mdc_gen};
+use crate::build_message;
+use crate::outgoing::{MessageParts, SendOptions};
+use crate::routing::ROUTING_HEADER_LEN;
+use crate::codec::decode;
+use crate::content_type::ContentType;
+use crate::DawnError;
+
+// overhead of unsigned and signed ciphertexts
+static OVERHEAD: OnceLock<(usize, usize)> = OnceLock::new();
+
+// writer that only counts the bytes written to it
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0 += buf.len();
+		Ok(buf.len())
+	}
+	
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+fn measure_overhead() -> Result<(usize, usize), DawnError> {
+	let (pubkey_kyber, _) = kyber_keygen();
+	let (_, seckey_sig) = sign_keygen();
+	let pfs_key = sym_key_gen();
+	let pfs_salt = sym_key_gen();
+	let unsigned = match encrypt_msg(&pubkey_kyber, None, &pfs_key, &pfs_salt, "") {
+		Ok((ciphertext, _)) => ciphertext.len(),
+		Err(err) => return Err(DawnError::Crypto(err))
+	};
+	let signed = match encrypt_msg(&pubkey_kyber, Some(&seckey_sig), &pfs_key, &pfs_salt, "") {
+		Ok((ciphertext, _)) => ciphertext.len(),
+		Err(err) => return Err(DawnError::Crypto(err))
+	};
+	Ok((unsigned, signed))
+}
+
+// estimate the length of the ciphertext send_msg would produce for the content (signed: whether own_seckey_sig is passed)
+// returns the length in bytes or an error if the content can't be sent
+pub fn estimate_ciphertext_len(content: (ContentType, Option<&str>, Option<&[u8]>), signed: bool) -> Result<usize, DawnError> {
+	// the mdc only has to have the right length
+	let mdc = mdc_gen();
+	let message_data = match build_message(MessageParts::from_content(content), &mdc, &SendOptions::default()) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let mut counter = ByteCounter(0);
+	if serde_json::to_writer(&mut counter, &message_data).is_err() { error!(Serialization, "json serialization failed"); }
+	
+	let (unsigned_overhead, signed_overhead) = match OVERHEAD.get() {
+		Some(res) => *res,
+		None => match measure_overhead() {
+			Ok(res) => *OVERHEAD.get_or_init(|| res),
+			Err(err) => return Err(err)
+		}
+	};
+	let mdc_len = match decode(&mdc) {
+		Ok(res) => res.len(),
+		Err(err) => return Err(err)
+	};
+	Ok(ROUTING_HEADER_LEN + mdc_len + counter.0 + if signed { signed_overhead } else { unsigned_overhead })
+}
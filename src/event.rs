@@ -17,3 +17,15 @@
 */
 
 pub const PROFILE_UPDATE: u8 = 0;
+pub const PRESENCE: u8 = 1;
+pub const CONVERSATION_CLOSE: u8 = 2;
+pub const NICKNAME: u8 = 3;
+pub const SENDER_KEY: u8 = 4;
+pub const MEDIA_RESEND: u8 = 5;
+pub const READ_RECEIPT: u8 = 6;
+pub const DELIVERY_RECEIPT: u8 = 7;
+pub const DISAPPEARING_TIMER: u8 = 8;
+pub const GROUP_MEMBERS: u8 = 9;
+pub const GROUP_APPEARANCE: u8 = 10;
+pub const GROUP_JOIN_REQUEST: u8 = 11;
+pub const GROUP_JOIN_DENIAL: u8 = 12;
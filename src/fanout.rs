@@ -0,0 +1,135 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Init request fan-out
+// If several handles are believed to belong to the same person (e.g. one from a printed card and one from an online
+// directory), an init request is sent to each of them. All requests share one conversation id, which the other side can
+// use as dedup key to only accept one of them. Accepts are fed into the InitFanout, which completes the handshake with
+// whichever accept arrives first and ignores the accepts for the other requests afterwards.
+
+use dawn_crypto::id_gen;
+use crate::init_request::InitRequestBuilder;
+use crate::peer::Peer;
+use crate::parse_init_response;
+use crate::DawnError;
+
+// an init request of the fan-out that has not been accepted yet
+struct FanoutRequest {
+	mdc: String,
+	ciphertext: Vec<u8>,
+	own_keypair_kyber: (Vec<u8>, Vec<u8>),
+	own_pfs_key: Vec<u8>,
+	remote_pfs_key: Vec<u8>,
+	pfs_salt: Vec<u8>,
+	id_salt: Vec<u8>,
+	mdc_seed: String,
+}
+
+pub struct InitFanout {
+	id: String,
+	requests: Vec<FanoutRequest>,
+	accepted: Option<usize>,
+}
+
+// everything needed to continue the conversation after the first accept arrived
+#[derive(Clone, Debug, PartialEq)]
+pub struct FanoutAccept {
+	pub index: usize, // index of the accepted request (order of the builders passed to InitFanout::new)
+	pub peer: Peer,
+	pub own_pubkey_kyber: Vec<u8>,
+	pub own_seckey_kyber: Vec<u8>,
+	pub own_pfs_key: Vec<u8>,
+	pub remote_pfs_key: Vec<u8>,
+	pub pfs_salt: Vec<u8>,
+	pub id: String,
+	pub id_salt: Vec<u8>,
+	pub mdc: String,
+	pub mdc_seed: String,
+}
+
+impl InitFanout {
+	// generate one init request per builder, all using the same conversation id
+	pub fn new(builders: Vec<InitRequestBuilder>) -> Result<Self, DawnError> {
+		if builders.is_empty() { error!("fan-out needs at least one init request"); }
+		let id = id_gen();
+		let mut requests = Vec::with_capacity(builders.len());
+		for builder in builders {
+			let request = match builder.conversation_id(&id).build() {
+				Ok(res) => res,
+				Err(err) => return Err(err)
+			};
+			requests.push(FanoutRequest {
+				mdc: request.mdc,
+				ciphertext: request.ciphertext,
+				own_keypair_kyber: (request.own_pubkey_kyber, request.own_seckey_kyber),
+				own_pfs_key: request.own_pfs_key,
+				remote_pfs_key: request.remote_pfs_key,
+				pfs_salt: request.pfs_salt,
+				id_salt: request.id_salt,
+				mdc_seed: request.mdc_seed,
+			});
+		}
+		Ok(InitFanout {
+			id,
+			requests,
+			accepted: None,
+		})
+	}
+	
+	// returns the conversation id shared by all requests
+	pub fn id(&self) -> &str {
+		&self.id
+	}
+	
+	// returns the message detail code and ciphertext of every request, in the order of the builders
+	pub fn requests(&self) -> Vec<(&str, &[u8])> {
+		self.requests.iter().map(|request| (request.mdc.as_str(), request.ciphertext.as_slice())).collect()
+	}
+	
+	// returns true once one of the requests was accepted
+	pub fn is_accepted(&self) -> bool {
+		self.accepted.is_some()
+	}
+	
+	// process an init accept received for the conversation id
+	// returns the accepted conversation for the first accept and None for accepts of the other requests arriving later
+	pub fn reconcile(&mut self, accept_ciphertext: &[u8], remote_pubkey_sig: Option<&[u8]>, remote_name: &str) -> Result<Option<FanoutAccept>, DawnError> {
+		for (index, request) in self.requests.iter().enumerate() {
+			let response = match parse_init_response(accept_ciphertext, &request.own_keypair_kyber.1, remote_pubkey_sig, &request.remote_pfs_key, &request.pfs_salt, remote_name) {
+				Ok(res) => res,
+				Err(_) => continue
+			};
+			if self.accepted.is_some() { return Ok(None); }
+			self.accepted = Some(index);
+			return Ok(Some(FanoutAccept {
+				index,
+				peer: response.peer,
+				own_pubkey_kyber: request.own_keypair_kyber.0.clone(),
+				own_seckey_kyber: request.own_keypair_kyber.1.clone(),
+				own_pfs_key: request.own_pfs_key.clone(),
+				remote_pfs_key: response.new_pfs_key,
+				pfs_salt: request.pfs_salt.clone(),
+				id: self.id.clone(),
+				id_salt: request.id_salt.clone(),
+				mdc: response.mdc,
+				mdc_seed: request.mdc_seed.clone(),
+			}));
+		}
+		error!("init accept does not belong to any request of the fan-out")
+	}
+}
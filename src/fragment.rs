@@ -0,0 +1,220 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Transport fragmentation
+// Some transports (SMS-like gateways, QR relays) only carry payloads that are smaller than even a minimal message
+// ciphertext. Such transports split the ciphertext returned by send_msg into fragments and the receiving side puts them
+// back together before calling parse_msg. This is independent of the chunking of history transfers, which happens on the
+// content level and needs complete messages. Every fragment starts with a fixed binary header:
+// magic "DWF" (3) ‖ message id (8) ‖ fragment index (2, big endian) ‖ fragment count (2, big endian)
+// The message id is derived from the hash of the ciphertext, so a transport retrying a message produces the same fragments
+// and the reassembled ciphertext can be checked against it. Messages whose fragments don't all arrive in time are dropped.
+// As the timeout alone doesn't stop a peer from sending fragments of ever new messages, the number of incomplete messages
+// and the bytes buffered for each of them are limited as well, fragments beyond these limits are rejected.
+
+use std::collections::HashMap;
+use dawn_crypto::hash;
+use crate::codec::split_bytes;
+use crate::DawnError;
+
+const FRAGMENT_MAGIC: &[u8] = b"DWF";
+const MESSAGE_ID_LEN: usize = 8;
+pub const FRAGMENT_HEADER_LEN: usize = 3 + MESSAGE_ID_LEN + 2 + 2;
+
+// incomplete messages are dropped if their fragments don't all arrive within this time (in seconds)
+pub const FRAGMENT_TIMEOUT: u64 = 600;
+
+// default limits for incomplete messages and the bytes buffered per message
+pub const FRAGMENT_MAX_PENDING: usize = 64;
+pub const FRAGMENT_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+// fragments of a message that is not complete yet
+struct PendingMessage {
+	fragments: HashMap<u16, Vec<u8>>,
+	fragment_count: u16,
+	first_seen: u64,
+	bytes: usize, // bytes of all fragments received so far
+}
+
+// collects fragments on the receiving side
+pub struct FragmentReassembler {
+	messages: HashMap<[u8; MESSAGE_ID_LEN], PendingMessage>,
+	timeout: u64,
+	max_pending: usize,
+	max_bytes: usize,
+}
+
+fn derive_message_id(ciphertext: &[u8]) -> Result<[u8; MESSAGE_ID_LEN], DawnError> {
+	match hash(ciphertext).get(..MESSAGE_ID_LEN).map(<[u8; MESSAGE_ID_LEN]>::try_from) {
+		Some(Ok(res)) => Ok(res),
+		_ => error!(Crypto, "hash too short for a message id")
+	}
+}
+
+// split a message ciphertext (as returned by send_msg) into fragments of at most max_fragment_len bytes including header
+// returns the fragments in order, all of them have to be sent
+pub fn fragment_msg(ciphertext: &[u8], max_fragment_len: usize) -> Result<Vec<Vec<u8>>, DawnError> {
+	if max_fragment_len <= FRAGMENT_HEADER_LEN { error!(&format!("fragments have to be larger than the {} byte header", FRAGMENT_HEADER_LEN)); }
+	if ciphertext.is_empty() { error!("no ciphertext was provided"); }
+	
+	let fragment_count = match u16::try_from(ciphertext.chunks(max_fragment_len - FRAGMENT_HEADER_LEN).len()) {
+		Ok(res) => res,
+		Err(_) => error!("message needs too many fragments for this fragment size")
+	};
+	let message_id = match derive_message_id(ciphertext) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	
+	let mut fragments = Vec::with_capacity(fragment_count as usize);
+	for (index, body) in ciphertext.chunks(max_fragment_len - FRAGMENT_HEADER_LEN).enumerate() {
+		let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_LEN + body.len());
+		fragment.extend_from_slice(FRAGMENT_MAGIC);
+		fragment.extend_from_slice(&message_id);
+		fragment.extend_from_slice(&(index as u16).to_be_bytes());
+		fragment.extend_from_slice(&fragment_count.to_be_bytes());
+		fragment.extend_from_slice(body);
+		fragments.push(fragment);
+	}
+	Ok(fragments)
+}
+
+// check whether a received payload is a fragment instead of a complete message
+pub fn is_fragment(payload: &[u8]) -> bool {
+	payload.len() > FRAGMENT_HEADER_LEN && payload.starts_with(FRAGMENT_MAGIC)
+}
+
+// message id, fragment index, fragment count and body of a fragment
+type FragmentParts<'a> = ([u8; MESSAGE_ID_LEN], u16, u16, &'a [u8]);
+
+// parse the header of a fragment
+// returns message id, fragment index, fragment count and the body of the fragment
+fn parse_fragment(fragment: &[u8]) -> Result<FragmentParts<'_>, DawnError> {
+	if !is_fragment(fragment) { error!("payload is not a message fragment"); }
+	let (header, body) = match split_bytes(fragment, FRAGMENT_HEADER_LEN) {
+		Some(res) => res,
+		None => error!("fragment is shorter than its header")
+	};
+	let message_id = match header.get(3..3 + MESSAGE_ID_LEN).map(<[u8; MESSAGE_ID_LEN]>::try_from) {
+		Some(Ok(res)) => res,
+		_ => error!("fragment header is missing the message id")
+	};
+	let (fragment_index, fragment_count) = match (header.get(11..13).map(<[u8; 2]>::try_from), header.get(13..15).map(<[u8; 2]>::try_from)) {
+		(Some(Ok(index)), Some(Ok(count))) => (u16::from_be_bytes(index), u16::from_be_bytes(count)),
+		_ => error!("fragment header is incomplete")
+	};
+	if fragment_index >= fragment_count { error!("fragment index out of range"); }
+	Ok((message_id, fragment_index, fragment_count, body))
+}
+
+impl Default for FragmentReassembler {
+	fn default() -> Self {
+		FragmentReassembler {
+			messages: HashMap::new(),
+			timeout: FRAGMENT_TIMEOUT,
+			max_pending: FRAGMENT_MAX_PENDING,
+			max_bytes: FRAGMENT_MAX_BYTES,
+		}
+	}
+}
+
+impl FragmentReassembler {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// time after which incomplete messages are dropped (in seconds)
+	pub fn timeout(mut self, timeout: u64) -> Self {
+		self.timeout = timeout;
+		self
+	}
+	
+	// maximum number of messages that are waiting for fragments at the same time
+	pub fn max_pending(mut self, max_pending: usize) -> Self {
+		self.max_pending = max_pending;
+		self
+	}
+	
+	// maximum number of bytes buffered for a single message
+	pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+		self.max_bytes = max_bytes;
+		self
+	}
+	
+	// add a received fragment at the given time
+	// returns the message ciphertext for parse_msg once the last missing fragment of a message was added
+	pub fn add_fragment(&mut self, fragment: &[u8], now: u64) -> Result<Option<Vec<u8>>, DawnError> {
+		let (message_id, fragment_index, fragment_count, body) = match parse_fragment(fragment) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.expire(now);
+		
+		// fragments are only stored as they arrive, so a bogus fragment count can't make us allocate memory for it
+		if !self.messages.contains_key(&message_id) && self.messages.len() >= self.max_pending { error!("too many incomplete messages"); }
+		let message = self.messages.entry(message_id).or_insert_with(|| PendingMessage {
+			fragments: HashMap::new(),
+			fragment_count,
+			first_seen: now,
+			bytes: 0,
+		});
+		if message.fragment_count != fragment_count { error!("fragment count does not match the message"); }
+		if !message.fragments.contains_key(&fragment_index) {
+			// a message exceeding the limit can never be completed, so it is dropped
+			if message.bytes.saturating_add(body.len()) > self.max_bytes {
+				self.messages.remove(&message_id);
+				error!("message exceeds the size limit for fragmented messages");
+			}
+			message.bytes += body.len();
+			message.fragments.insert(fragment_index, body.to_vec());
+		}
+		if message.fragments.len() < fragment_count as usize { return Ok(None); }
+		
+		// all fragments are there, reassemble the message
+		let mut message = match self.messages.remove(&message_id) {
+			Some(res) => res,
+			None => error!("message vanished during reassembly")
+		};
+		let mut ciphertext = Vec::new();
+		for index in 0..fragment_count {
+			match message.fragments.remove(&index) {
+				Some(body) => ciphertext.extend(body),
+				None => error!("fragment missing during reassembly")
+			}
+		}
+		match derive_message_id(&ciphertext) {
+			Ok(res) if res == message_id => Ok(Some(ciphertext)),
+			Ok(_) => error!("reassembled message does not match its fragments"),
+			Err(err) => Err(err)
+		}
+	}
+	
+	// drop messages whose fragments didn't all arrive within the timeout
+	// returns the number of dropped messages
+	pub fn expire(&mut self, now: u64) -> usize {
+		let timeout = self.timeout;
+		let pending = self.messages.len();
+		self.messages.retain(|_, message| now < message.first_seen.saturating_add(timeout));
+		pending - self.messages.len()
+	}
+	
+	// returns the number of messages that are waiting for fragments
+	pub fn pending(&self) -> usize {
+		self.messages.len()
+	}
+}
@@ -0,0 +1,93 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Envelopes for messages relayed from other networks
+// Bridge bots relaying messages from Matrix, XMPP and other networks wrap the relayed content into a GATEWAY message, whose
+// text is an envelope with the origin of the message (network, id of the remote sender or room, original timestamp) and
+// the content type and text of the relayed message. Binary content is sent as the data of the GATEWAY message unchanged,
+// so send_msg((content_type::GATEWAY, Some(&envelope), data)) relays any text, voice, picture or linked media message.
+
+use serde::{Serialize, Deserialize};
+use crate::content_type::{self, ContentType};
+use crate::context::OwnedContent;
+use crate::DawnError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GatewayOrigin {
+	pub network: String, // e.g. "matrix" or "xmpp"
+	pub remote_id: String, // id of the sender on the origin network
+	pub timestamp: u64, // time the message was sent on the origin network
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GatewayEnvelope {
+	network: String,
+	remote_id: String,
+	timestamp: u64,
+	content_type: ContentType,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	text: Option<String>,
+}
+
+// returns true for content types that can be relayed through a gateway
+fn is_relayable(content_type: ContentType) -> bool {
+	matches!(content_type, content_type::TEXT | content_type::VOICE | content_type::PICTURE | content_type::LINKED_MEDIA)
+}
+
+// wrap the text part of a relayed message into an envelope
+// returns the envelope, which is sent as text of a GATEWAY message together with the data of the relayed message
+pub fn gen_gateway_envelope(origin: &GatewayOrigin, (msg_type, msg_text, _): (ContentType, Option<&str>, Option<&[u8]>)) -> Result<String, DawnError> {
+	if origin.network.is_empty() { error!("origin network is missing"); }
+	if !is_relayable(msg_type) { error!("content type can't be relayed through a gateway"); }
+	let envelope = GatewayEnvelope {
+		network: origin.network.clone(),
+		remote_id: origin.remote_id.clone(),
+		timestamp: origin.timestamp,
+		content_type: msg_type,
+		text: msg_text.map(|text| text.to_string()),
+	};
+	match serde_json::to_string(&envelope) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// parse an envelope into the origin, content type and text of the relayed message
+pub(crate) fn parse_envelope(envelope: &str) -> Result<(GatewayOrigin, ContentType, Option<String>), DawnError> {
+	let envelope = match serde_json::from_str::<GatewayEnvelope>(envelope) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "gateway envelope json parsing failed")
+	};
+	if envelope.network.is_empty() { error!("origin network is missing"); }
+	if !is_relayable(envelope.content_type) { error!("content type can't be relayed through a gateway"); }
+	let origin = GatewayOrigin {
+		network: envelope.network,
+		remote_id: envelope.remote_id,
+		timestamp: envelope.timestamp,
+	};
+	Ok((origin, envelope.content_type, envelope.text))
+}
+
+// unwrap a received GATEWAY message (text and data as returned by parse_msg)
+// returns the origin and the relayed content
+pub fn parse_gateway_envelope(envelope: &str, data: Option<Vec<u8>>) -> Result<(GatewayOrigin, OwnedContent), DawnError> {
+	match parse_envelope(envelope) {
+		Ok((origin, msg_type, msg_text)) => Ok((origin, (msg_type, msg_text, data))),
+		Err(err) => Err(err)
+	}
+}
@@ -0,0 +1,891 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Group conversations with sender keys
+// Every member of a group has a sender key: a symmetric key chain (see sender_chain.rs) and a signature key pair that
+// only it holds. Members hand the current state of their sender key to every other member as a SENDER_KEY event over
+// their 1:1 conversations, which already authenticate the sender. A group message is then encrypted once with the next
+// key of the sender's chain, signed with the signature key of the sender key and fanned out to all members as the same
+// ciphertext, instead of being encrypted for every member on its own. Each chain advances with every message, so keys of
+// earlier messages can't be derived from the current state (forward secrecy per member). The signature keeps members,
+// who all know the chain keys of each other, from sending messages in the name of another member.
+// The sender key message of the creator of a group doubles as invitation: it carries the id, members and admins of the
+// group. Group::join trusts the admins named in the invitation on first use, so a member that is no admin can still
+// invite someone by naming itself admin in its own invitation. Group::join_with_handle checks the inviter against the
+// admins of a group handle (see group_join.rs) the joiner got before and trusts instead.
+// A removed member still knows the chain keys of everyone else, so all remaining members have to rotate their sender keys
+// (see rotate_group_keys) before they send again. The new keys are distributed over the 1:1 conversations like the first
+// ones, which never reach the removed member.
+// Members are added and removed with a GROUP_MEMBERS event sent to the group (see send_membership_change). The change is
+// signed with the signature key of the sender key of the member that made it and numbered with the membership epoch, so
+// all members apply the changes in the same order. A change that arrives before an earlier one fails without consuming the
+// message and can be parsed again once the earlier one arrived. Two admins can make a change with the same epoch at the
+// same time: every member keeps the one with the lowest hash, no matter which one it got first, and rolls the other one
+// back, including the admin that made it, which has to make its change again. Only concurrent changes of the latest epoch
+// can be resolved, a change that arrives after the group moved on to a later epoch is rejected.
+// Every group message gets its own message detail code, derived from the sender, its sender key and the counter of the
+// message, so replies and receipts name a single message. Members check the code of every message they decrypt.
+// Group messages carry the group identity of their sender: the fingerprint of the signature key of its sender key, which
+// clients can compare out of band (see member_identity). Members only accept a message if its sender is part of the
+// membership state they got with their invitation and the signed membership changes since, if the identity is
+// the one of the sender key the sender handed out over its 1:1 conversation and if the signature matches it, so a member
+// can't send messages in the name of another one. No member can hand out the signature key of another one as its own.
+// The member that creates a group is its first admin. Only admins can change the members of a group and make other
+// members admins, and they approve or deny the requests of people who want to join (see group_join.rs).
+// The description and avatar of a group are kept with the group as well (see group_appearance.rs).
+
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use dawn_crypto::{encrypt_data, decrypt_data, sym_key_gen, id_gen, predictable_mdc_gen, hash};
+use crate::codec::{encode, decode, encode_base64, decode_base64};
+use crate::canonical::canonical_json;
+use crate::config::ProtocolConfig;
+use crate::sender_chain::{SenderChain, MemberChain};
+use crate::group_appearance::{AppearanceRecord, GroupAppearance, GroupAvatar};
+use crate::group_join::{GroupHandle, GroupJoinRequest, gen_group_handle, parse_join_request, gen_join_denial};
+use crate::keys::{SignPublicKey, SignSecretKey, gen_sign_keypair};
+use crate::signature::{sign_detached, verify_detached};
+use crate::domain::{GROUP_MESSAGE_DOMAIN, GROUP_MEMBERSHIP_DOMAIN, GROUP_IDENTITY_DOMAIN};
+use crate::limits;
+use crate::outgoing::{OutgoingMessage, SendOptions};
+use crate::received::ReceivedMessage;
+use crate::secret::SecretBytes;
+use crate::event;
+use crate::{build_message, parse_message_content, ParsedContent};
+use crate::DawnError;
+
+// number of join requests an admin keeps until it answered them
+const MAX_JOIN_REQUESTS: usize = 100;
+
+const IDENTITY_LEN: usize = 16;
+
+// the own sender key of a group
+struct OwnSenderKey {
+	key_id: String,
+	chain: SenderChain,
+	salt: SecretBytes,
+	pubkey_sig: SignPublicKey,
+	seckey_sig: SignSecretKey,
+}
+
+// the sender key of another member
+#[derive(Clone)]
+struct MemberSenderKey {
+	key_id: String,
+	chain: MemberChain,
+	salt: SecretBytes,
+	pubkey_sig: SignPublicKey,
+}
+
+pub struct Group {
+	id: String,
+	mdc_seed: String,
+	own_member: String,
+	members: Vec<String>, // ids of all members (chosen by the clients, e.g. the ids of the 1:1 conversations), including the own one
+	epoch: u64, // number of membership changes applied to the group
+	admins: Vec<String>,
+	join_secret: String, // proves that a join request was made with a handle of the group
+	join_requests: Vec<GroupJoinRequest>, // requests waiting for an answer of the own member, if it is an admin
+	group_key: SecretBytes, // shared by all members, wraps the avatar key (see group_appearance.rs)
+	appearance: Option<AppearanceRecord>,
+	sender_key: OwnSenderKey,
+	member_keys: HashMap<String, MemberSenderKey>,
+	rotation_pending: bool, // a member was removed since the own sender key was generated
+	epoch_base: Option<EpochBase>, // state before the membership change of the current epoch
+}
+
+// the state of a group before the membership change of the current epoch was applied, so the change can be rolled back
+// if a concurrent change with the same epoch wins against it
+struct EpochBase {
+	rank: Vec<u8>, // see change_rank
+	members: Vec<String>,
+	admins: Vec<String>,
+	join_requests: Vec<GroupJoinRequest>,
+	member_keys: HashMap<String, MemberSenderKey>, // sender keys of the members the change removed
+	rotation_pending: bool,
+}
+
+// content of a SENDER_KEY event
+#[derive(Serialize, Deserialize, Debug)]
+struct SenderKeyRecord {
+	group: String,
+	mdc_seed: String,
+	members: Vec<String>,
+	#[serde(default)]
+	epoch: u64,
+	#[serde(default)]
+	admins: Vec<String>,
+	#[serde(default)]
+	join_secret: String,
+	#[serde(default)]
+	group_key: String,
+	#[serde(default)]
+	appearance: Option<AppearanceRecord>,
+	sender: String,
+	key_id: String,
+	chain_key: String,
+	counter: u64,
+	salt: String,
+	sign: String,
+}
+
+// a group message as it is fanned out
+#[derive(Serialize, Deserialize, Debug)]
+struct GroupEnvelope {
+	group: String,
+	sender: String,
+	identity: String, // group identity of the sender
+	key_id: String,
+	counter: u64,
+	ciphertext: String,
+	signature: String,
+}
+
+// content of a GROUP_MEMBERS event
+#[derive(Serialize, Deserialize, Debug)]
+struct MembershipRecord {
+	group: String,
+	sender: String,
+	epoch: u64,
+	added: Vec<String>,
+	removed: Vec<String>,
+	#[serde(default)]
+	admins: Vec<String>, // members that were made admins
+	signature: String,
+}
+
+// a change of the members of a group
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MembershipChange {
+	pub sender: String,
+	pub epoch: u64,
+	pub added: Vec<String>,
+	pub removed: Vec<String>,
+	pub admins: Vec<String>, // members that were made admins
+}
+
+// the data covered by the signature of a membership change
+fn membership_content(group: &str, sender: &str, epoch: u64, added: &[String], removed: &[String], admins: &[String]) -> Result<Vec<u8>, DawnError> {
+	canonical_json(&(group, sender, epoch, added, removed, admins))
+}
+
+// of two membership changes with the same epoch, the one with the lower rank wins
+fn change_rank(content: &[u8]) -> Vec<u8> {
+	hash(content)
+}
+
+// the message detail code of a group message, unique for every message of every member
+fn group_mdc(mdc_seed: &str, group: &str, sender: &str, key_id: &str, counter: u64) -> Result<String, DawnError> {
+	match canonical_json(&(group, sender, key_id, counter)) {
+		Ok(res) => Ok(predictable_mdc_gen(mdc_seed, &encode(res))),
+		Err(err) => Err(err)
+	}
+}
+
+// the data covered by the signature of a group message
+fn envelope_content(group: &str, sender: &str, identity: &str, key_id: &str, counter: u64, ciphertext: &str) -> Result<Vec<u8>, DawnError> {
+	canonical_json(&(group, sender, identity, key_id, counter, ciphertext))
+}
+
+// fingerprint of the signature key of a sender key
+fn group_identity(pubkey_sig: &SignPublicKey) -> String {
+	let mut input = GROUP_IDENTITY_DOMAIN.as_bytes().to_vec();
+	input.extend_from_slice(pubkey_sig.as_bytes());
+	let mut identity = hash(&input);
+	identity.truncate(IDENTITY_LEN);
+	encode(identity)
+}
+
+fn check_member_id(member: &str) -> Result<(), DawnError> {
+	if member.is_empty() { error!("member id must not be empty"); }
+	Ok(())
+}
+
+impl OwnSenderKey {
+	fn generate() -> Result<Self, DawnError> {
+		let (pubkey_sig, seckey_sig) = match gen_sign_keypair() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		Ok(OwnSenderKey {
+			key_id: id_gen(),
+			chain: SenderChain::new(&sym_key_gen()),
+			salt: SecretBytes::new(&sym_key_gen()),
+			pubkey_sig,
+			seckey_sig,
+		})
+	}
+}
+
+impl Group {
+	// create a group with the given other members
+	// the sender key message has to be sent to every member afterwards (see sender_key_message)
+	pub fn create(own_member: &str, members: &[&str]) -> Result<Self, DawnError> {
+		if let Err(err) = check_member_id(own_member) { return Err(err); }
+		let sender_key = match OwnSenderKey::generate() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let mut group = Group {
+			id: id_gen(),
+			mdc_seed: encode(sym_key_gen()),
+			own_member: own_member.to_string(),
+			members: vec![own_member.to_string()],
+			epoch: 0,
+			admins: vec![own_member.to_string()],
+			join_secret: encode(sym_key_gen()),
+			join_requests: Vec::new(),
+			group_key: SecretBytes::new(&sym_key_gen()),
+			appearance: None,
+			sender_key,
+			member_keys: HashMap::new(),
+			rotation_pending: false,
+			epoch_base: None,
+		};
+		for member in members {
+			if let Err(err) = group.add_member(member) { return Err(err); }
+		}
+		Ok(group)
+	}
+	
+	// join a group with the sender key message of one of its members, received in the 1:1 conversation with that member
+	// the admins are taken from the invitation itself (trust on first use), use join_with_handle to check them
+	// the own sender key message has to be sent to every member afterwards
+	pub fn join(own_member: &str, sender: &str, sender_key: &[u8]) -> Result<Self, DawnError> {
+		let record = match parse_sender_key(sender_key) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		if !record.members.iter().any(|member| member == own_member) { error!("sender key message does not invite this member"); }
+		// this only keeps members from handing out their sender key as invitation by mistake, the sender names the admins
+		if !record.admins.iter().any(|admin| admin == sender) { error!("invitation was not sent by an admin of the group"); }
+		let group_key = match decode(&record.group_key) {
+			Ok(res) => res,
+			Err(_) => error!("sender key message contains an invalid group key")
+		};
+		let own_sender_key = match OwnSenderKey::generate() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let mut group = Group {
+			id: record.group.clone(),
+			mdc_seed: record.mdc_seed.clone(),
+			own_member: own_member.to_string(),
+			members: Vec::new(),
+			epoch: record.epoch,
+			admins: record.admins.clone(),
+			join_secret: record.join_secret.clone(),
+			join_requests: Vec::new(),
+			group_key: SecretBytes::new(&group_key),
+			appearance: None,
+			sender_key: own_sender_key,
+			member_keys: HashMap::new(),
+			rotation_pending: false,
+			epoch_base: None,
+		};
+		for member in &record.members {
+			if let Err(err) = group.add_member(member) { return Err(err); }
+		}
+		match group.add_sender_key(sender, record) {
+			Ok(()) => Ok(group),
+			Err(err) => Err(err)
+		}
+	}
+	
+	// join a group with the invitation of one of the admins of a group handle the own member already trusts
+	// the own sender key message has to be sent to every member afterwards
+	pub fn join_with_handle(own_member: &str, handle: &GroupHandle, sender: &str, sender_key: &[u8]) -> Result<Self, DawnError> {
+		if !handle.admins.iter().any(|admin| admin == sender) { error!("invitation was not sent by an admin of the group handle"); }
+		let group = match Group::join(own_member, sender, sender_key) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		if group.id != handle.group { error!("invitation belongs to another group than the handle"); }
+		Ok(group)
+	}
+	
+	pub fn id(&self) -> &str {
+		&self.id
+	}
+	
+	pub fn own_member(&self) -> &str {
+		&self.own_member
+	}
+	
+	pub fn members(&self) -> &[String] {
+		&self.members
+	}
+	
+	// number of membership changes applied to the group
+	pub fn epoch(&self) -> u64 {
+		self.epoch
+	}
+	
+	// the own group identity, the fingerprint of the signature key of the own sender key
+	pub fn identity(&self) -> String {
+		group_identity(&self.sender_key.pubkey_sig)
+	}
+	
+	// the group identity of another member, once its sender key arrived
+	pub fn member_identity(&self, member: &str) -> Option<String> {
+		self.member_keys.get(member).map(|member_key| group_identity(&member_key.pubkey_sig))
+	}
+	
+	pub fn admins(&self) -> &[String] {
+		&self.admins
+	}
+	
+	pub fn is_admin(&self, member: &str) -> bool {
+		self.admins.iter().any(|admin| admin == member)
+	}
+	
+	// returns a handle people can use to ask to join the group (see group_join.rs)
+	pub fn gen_handle(&self) -> Result<Vec<u8>, DawnError> {
+		if !self.is_admin(&self.own_member) { error!("only admins can hand out the handle of a group"); }
+		gen_group_handle(&self.id, &self.admins, &self.join_secret)
+	}
+	
+	// join requests waiting for an answer
+	pub fn join_requests(&self) -> &[GroupJoinRequest] {
+		&self.join_requests
+	}
+	
+	// process a GROUP_JOIN_REQUEST event received in the 1:1 conversation with the requester
+	// the request is kept until it is approved or denied, a newer request of the same requester replaces it
+	pub fn process_join_request(&mut self, sender: &str, data: &[u8]) -> Result<GroupJoinRequest, DawnError> {
+		if !self.is_admin(&self.own_member) { error!("only admins can answer join requests"); }
+		let (request, join_secret) = match parse_join_request(data) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		if request.group != self.id { error!("join request belongs to another group"); }
+		// the requester is taken from the 1:1 conversation, nobody can ask to join in the name of someone else
+		if request.requester != sender { error!("join request was sent by someone else"); }
+		if join_secret != self.join_secret { error!("join request was not made with a handle of the group"); }
+		if self.members.contains(&request.requester) { error!("requester is already a member of the group"); }
+		self.join_requests.retain(|pending| pending.requester != request.requester);
+		if self.join_requests.len() >= MAX_JOIN_REQUESTS { error!("too many join requests are waiting for an answer"); }
+		self.join_requests.push(request.clone());
+		Ok(request)
+	}
+	
+	// the description and avatar of the group, if a member set them
+	pub fn appearance(&self) -> Result<Option<GroupAppearance>, DawnError> {
+		match &self.appearance {
+			Some(appearance) => appearance.open(self.group_key.as_bytes()).map(Some),
+			None => Ok(None)
+		}
+	}
+	
+	// whether the own member is still part of the group, it is not anymore once another member removed it
+	pub fn is_member(&self) -> bool {
+		self.members.contains(&self.own_member)
+	}
+	
+	// whether the sender key of a member arrived, so its messages can be decrypted
+	pub fn has_sender_key(&self, member: &str) -> bool {
+		self.member_keys.contains_key(member)
+	}
+	
+	// add a member locally, which needs the own sender key message afterwards
+	// send_membership_change adds members for all members of the group
+	pub fn add_member(&mut self, member: &str) -> Result<(), DawnError> {
+		if let Err(err) = check_member_id(member) { return Err(err); }
+		if !self.members.iter().any(|known| known == member) { self.members.push(member.to_string()); }
+		Ok(())
+	}
+	
+	// remove a member locally and forget its sender key
+	// no messages can be sent to the group until the own sender key was rotated
+	// send_membership_change removes members for all members of the group
+	pub fn remove_member(&mut self, member: &str) -> Result<(), DawnError> {
+		if member == self.own_member { error!("the own member can't be removed from a group"); }
+		if !self.members.iter().any(|known| known == member) { return Ok(()); }
+		self.members.retain(|known| known != member);
+		self.member_keys.remove(member);
+		self.rotation_pending = true;
+		Ok(())
+	}
+	
+	// whether a member was removed and the own sender key has to be rotated before the next message
+	pub fn rotation_pending(&self) -> bool {
+		self.rotation_pending
+	}
+	
+	// returns the message that hands the current state of the own sender key to another member
+	// it has to be sent over the 1:1 conversation with the member, never to the group
+	pub fn sender_key_message(&self) -> Result<OutgoingMessage, DawnError> {
+		let record = SenderKeyRecord {
+			group: self.id.clone(),
+			mdc_seed: self.mdc_seed.clone(),
+			members: self.members.clone(),
+			epoch: self.epoch,
+			admins: self.admins.clone(),
+			join_secret: self.join_secret.clone(),
+			group_key: encode(self.group_key.as_bytes()),
+			appearance: self.appearance.clone(),
+			sender: self.own_member.clone(),
+			key_id: self.sender_key.key_id.clone(),
+			chain_key: encode(self.sender_key.chain.chain_key()),
+			counter: self.sender_key.chain.counter(),
+			salt: encode(self.sender_key.salt.as_bytes()),
+			sign: encode(self.sender_key.pubkey_sig.as_bytes()),
+		};
+		match serde_json::to_vec(&record) {
+			Ok(res) => Ok(OutgoingMessage::internal(event::SENDER_KEY, &res)),
+			Err(_) => error!(Serialization, "json serialization failed")
+		}
+	}
+	
+	// process the sender key message of a member, received in the 1:1 conversation with that member
+	pub fn process_sender_key(&mut self, sender: &str, sender_key: &[u8]) -> Result<(), DawnError> {
+		let record = match parse_sender_key(sender_key) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		if record.group != self.id { error!("sender key message belongs to another group"); }
+		self.add_sender_key(sender, record)
+	}
+	
+	fn add_sender_key(&mut self, sender: &str, record: SenderKeyRecord) -> Result<(), DawnError> {
+		// the sender is taken from the 1:1 conversation, a member can't hand out a sender key in the name of another one
+		if record.sender != sender { error!("sender key message was sent by another member"); }
+		if sender == self.own_member { error!("sender key message of the own member"); }
+		if !self.members.iter().any(|member| member == sender) { error!("sender is not a member of the group"); }
+		let (chain_key, salt, pubkey_sig) = match (decode(&record.chain_key), decode(&record.salt), decode(&record.sign).map(SignPublicKey::try_from)) {
+			(Ok(chain_key), Ok(salt), Ok(Ok(pubkey_sig))) => (chain_key, salt, pubkey_sig),
+			_ => error!("sender key message contains an invalid key")
+		};
+		if pubkey_sig == self.sender_key.pubkey_sig || self.member_keys.iter().any(|(member, known)| member != sender && known.pubkey_sig == pubkey_sig) {
+			error!("sender key message contains the signature key of another member");
+		}
+		if let Some(appearance) = record.appearance {
+			if appearance.group == self.id && appearance.supersedes(self.appearance.as_ref()) { self.appearance = Some(appearance); }
+		}
+		// a key that was handed out before keeps its chain, so a replayed sender key message can't reset it
+		if let Some(known) = self.member_keys.get(sender) {
+			if known.key_id == record.key_id { return Ok(()); }
+		}
+		self.member_keys.insert(sender.to_string(), MemberSenderKey {
+			key_id: record.key_id,
+			chain: MemberChain::new(&chain_key, record.counter),
+			salt: SecretBytes::new(&salt),
+			pubkey_sig,
+		});
+		Ok(())
+	}
+	
+	// apply a verified membership change, a change with the current epoch replaces the one applied before
+	fn apply_membership_change(&mut self, change: &MembershipChange, rank: Vec<u8>) -> Result<(), DawnError> {
+		if change.epoch == self.epoch {
+			match self.epoch_base.take() {
+				Some(base) => self.roll_back(base),
+				None => error!("membership change is outdated")
+			}
+		}
+		let removed_keys = self.member_keys.iter()
+			.filter(|(member, _)| change.removed.contains(member) || change.removed.contains(&self.own_member))
+			.map(|(member, member_key)| (member.clone(), member_key.clone()))
+			.collect();
+		self.epoch_base = Some(EpochBase {
+			rank,
+			members: self.members.clone(),
+			admins: self.admins.clone(),
+			join_requests: self.join_requests.clone(),
+			member_keys: removed_keys,
+			rotation_pending: self.rotation_pending,
+		});
+		
+		for member in &change.added {
+			if let Err(err) = self.add_member(member) { return Err(err); }
+			self.join_requests.retain(|pending| pending.requester != *member);
+		}
+		for member in &change.removed {
+			// the own member can only learn that it was removed, its own sender key is dropped with the group
+			if *member == self.own_member {
+				self.members.retain(|known| *known != self.own_member);
+				self.member_keys.clear();
+			}
+			else if let Err(err) = self.remove_member(member) { return Err(err); }
+			self.admins.retain(|admin| admin != member);
+		}
+		for admin in &change.admins {
+			if self.members.contains(admin) && !self.admins.contains(admin) { self.admins.push(admin.clone()); }
+		}
+		self.epoch = change.epoch;
+		Ok(())
+	}
+	
+	// undo the membership change of the current epoch
+	fn roll_back(&mut self, base: EpochBase) {
+		self.members = base.members;
+		self.admins = base.admins;
+		self.join_requests = base.join_requests;
+		self.rotation_pending = base.rotation_pending;
+		for (member, member_key) in base.member_keys {
+			self.member_keys.entry(member).or_insert(member_key);
+		}
+		let members = &self.members;
+		self.member_keys.retain(|member, _| members.contains(member));
+		self.epoch = self.epoch.saturating_sub(1);
+	}
+}
+
+fn parse_sender_key(sender_key: &[u8]) -> Result<SenderKeyRecord, DawnError> {
+	let record = match serde_json::from_slice::<SenderKeyRecord>(sender_key) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "sender key json parsing failed")
+	};
+	if record.group.is_empty() || record.key_id.is_empty() { error!("sender key message is missing its group or key id"); }
+	if let Err(err) = check_member_id(&record.sender) { return Err(err); }
+	Ok(record)
+}
+
+// parse the content of a GROUP_MEMBERS event, which parse_group_msg already verified and applied
+pub fn parse_membership_change(data: &[u8]) -> Result<MembershipChange, DawnError> {
+	let record = match serde_json::from_slice::<MembershipRecord>(data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "membership change json parsing failed")
+	};
+	Ok(MembershipChange {
+		sender: record.sender,
+		epoch: record.epoch,
+		added: record.added,
+		removed: record.removed,
+		admins: record.admins,
+	})
+}
+
+// verify a membership change sent by a member against its sender key and the current epoch of the group
+// a change with the current epoch is only accepted if it wins against the change that was applied for it
+// returns the change and its rank
+fn verify_membership_change(group: &Group, sender: &str, data: &[u8]) -> Result<(MembershipChange, Vec<u8>), DawnError> {
+	let record = match serde_json::from_slice::<MembershipRecord>(data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "membership change json parsing failed")
+	};
+	if record.group != group.id { error!("membership change belongs to another group"); }
+	if record.sender != sender { error!("membership change was made by another member"); }
+	let content = match membership_content(&record.group, &record.sender, record.epoch, &record.added, &record.removed, &record.admins) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let rank = change_rank(&content);
+	// a concurrent change is checked against the admins before the change it competes with
+	let admins = if record.epoch == group.epoch {
+		match &group.epoch_base {
+			Some(base) if rank < base.rank => &base.admins,
+			Some(base) if rank == base.rank => error!("membership change was already applied"),
+			Some(_) => error!("membership change lost against a concurrent one"),
+			None => error!("membership change is outdated")
+		}
+	}
+	else { &group.admins };
+	if !admins.iter().any(|admin| admin == sender) { error!("membership change was not made by an admin"); }
+	if record.epoch < group.epoch { error!("membership change is outdated"); }
+	if record.epoch - group.epoch > 1 { error!("membership change arrived before an earlier one"); }
+	let member_key = match group.member_keys.get(sender) {
+		Some(res) => res,
+		None => error!("no sender key for the sender of the membership change")
+	};
+	let signature = match decode(&record.signature) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "membership change signature invalid")
+	};
+	if let Err(err) = verify_detached(GROUP_MEMBERSHIP_DOMAIN, &content, &signature, member_key.pubkey_sig.as_bytes()) { return Err(err); }
+	Ok((MembershipChange {
+		sender: record.sender,
+		epoch: record.epoch,
+		added: record.added,
+		removed: record.removed,
+		admins: record.admins,
+	}, rank))
+}
+
+// add and remove members for all members of the group: the signed change is sent to the group and applied locally
+// added members need the sender key messages of all members afterwards, after a removal the own sender key has to be
+// rotated (see rotate_group_keys) before the next message
+// a concurrent change of another admin can win against it and roll it back (see above)
+// returns message detail code and ciphertext
+pub fn send_membership_change(group: &mut Group, added: &[&str], removed: &[&str]) -> Result<(String, Vec<u8>), DawnError> {
+	send_signed_membership_change(group, added, removed, &[])
+}
+
+// make members admins of the group for all members
+// returns message detail code and ciphertext
+pub fn send_admin_change(group: &mut Group, admins: &[&str]) -> Result<(String, Vec<u8>), DawnError> {
+	if let Some(admin) = admins.iter().find(|admin| !group.members.iter().any(|member| member == *admin)) {
+		error!(&format!("{} is not a member of the group", admin));
+	}
+	send_signed_membership_change(group, &[], &[], admins)
+}
+
+fn send_signed_membership_change(group: &mut Group, added: &[&str], removed: &[&str], admins: &[&str]) -> Result<(String, Vec<u8>), DawnError> {
+	if !group.is_admin(&group.own_member) { error!("only admins can change the members of a group"); }
+	if removed.contains(&group.own_member.as_str()) { error!("the own member can't be removed from a group"); }
+	for member in added.iter().chain(removed) {
+		if let Err(err) = check_member_id(member) { return Err(err); }
+	}
+	let epoch = match group.epoch.checked_add(1) {
+		Some(res) => res,
+		None => error!("membership epoch is exhausted")
+	};
+	let added: Vec<String> = added.iter().map(|member| member.to_string()).collect();
+	let removed: Vec<String> = removed.iter().map(|member| member.to_string()).collect();
+	let admins: Vec<String> = admins.iter().map(|member| member.to_string()).collect();
+	let content = match membership_content(&group.id, &group.own_member, epoch, &added, &removed, &admins) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let signature = match sign_detached(GROUP_MEMBERSHIP_DOMAIN, &content, group.sender_key.seckey_sig.as_bytes()) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let rank = change_rank(&content);
+	let record = MembershipRecord {
+		group: group.id.clone(),
+		sender: group.own_member.clone(),
+		epoch,
+		added,
+		removed,
+		admins,
+		signature: encode(signature),
+	};
+	let data = match serde_json::to_vec(&record) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "json serialization failed")
+	};
+	let sent = match send_group_msg(group, &OutgoingMessage::internal(event::GROUP_MEMBERS, &data)) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let change = MembershipChange {
+		sender: record.sender,
+		epoch,
+		added: record.added,
+		removed: record.removed,
+		admins: record.admins,
+	};
+	if let Err(err) = group.apply_membership_change(&change, rank) { return Err(err); }
+	Ok(sent)
+}
+
+// approve a join request: the requester is added to the group for all members
+// returns message detail code and ciphertext of the membership change for the group and the sender key message that
+// invites the requester, which has to be sent over the 1:1 conversation with it
+pub fn approve_join_request(group: &mut Group, request_id: &str) -> Result<((String, Vec<u8>), OutgoingMessage), DawnError> {
+	let requester = match group.join_requests.iter().find(|request| request.id == request_id) {
+		Some(request) => request.requester.clone(),
+		None => error!("no join request with this id")
+	};
+	let sent = match send_membership_change(group, &[&requester], &[]) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	match group.sender_key_message() {
+		Ok(invitation) => Ok((sent, invitation)),
+		Err(err) => Err(err)
+	}
+}
+
+// deny a join request
+// returns the GROUP_JOIN_DENIAL event, which has to be sent over the 1:1 conversation with the requester
+pub fn deny_join_request(group: &mut Group, request_id: &str) -> Result<OutgoingMessage, DawnError> {
+	let position = match group.join_requests.iter().position(|request| request.id == request_id) {
+		Some(res) => res,
+		None => error!("no join request with this id")
+	};
+	let request = group.join_requests.remove(position);
+	gen_join_denial(&request)
+}
+
+// set the description and avatar of the group for all members: the appearance is sent to the group and kept locally
+// returns message detail code and ciphertext
+pub fn send_group_appearance(group: &mut Group, description: Option<&str>, avatar: Option<&GroupAvatar>) -> Result<(String, Vec<u8>), DawnError> {
+	let epoch = match group.appearance.as_ref().map_or(Some(1), |appearance| appearance.epoch.checked_add(1)) {
+		Some(res) => res,
+		None => error!("appearance epoch is exhausted")
+	};
+	let record = match AppearanceRecord::new(group.group_key.as_bytes(), &group.id, epoch, &group.own_member, description, avatar) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let data = match record.to_vec() {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let sent = match send_group_msg(group, &OutgoingMessage::internal(event::GROUP_APPEARANCE, &data)) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	group.appearance = Some(record);
+	Ok(sent)
+}
+
+// replace the own sender key with a new one, so members that were removed can't read future messages
+// returns the new sender key message for every other member, each one has to be sent over the 1:1 conversation with that member
+pub fn rotate_group_keys(group: &mut Group) -> Result<Vec<(String, OutgoingMessage)>, DawnError> {
+	group.sender_key = match OwnSenderKey::generate() {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	group.rotation_pending = false;
+	let message = match group.sender_key_message() {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	Ok(group.members.iter()
+		.filter(|member| **member != group.own_member)
+		.map(|member| (member.clone(), message.clone()))
+		.collect())
+}
+
+// send a message to a group: it is encrypted once and the ciphertext is delivered to every member
+// returns message detail code and ciphertext
+pub fn send_group_msg(group: &mut Group, message: &OutgoingMessage) -> Result<(String, Vec<u8>), DawnError> {
+	if !group.is_member() { error!("the own member was removed from the group"); }
+	if group.rotation_pending { error!("the sender key has to be rotated after a member was removed"); }
+	let (counter, message_key) = group.sender_key.chain.next_key(group.sender_key.salt.as_bytes());
+	let mdc = match group_mdc(&group.mdc_seed, &group.id, &group.own_member, &group.sender_key.key_id, counter) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let message = match build_message(message.parts(), &mdc, &SendOptions::default()) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let message = match serde_json::to_vec(&message) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "json serialization failed")
+	};
+	
+	let sender_key = &mut group.sender_key;
+	let ciphertext = match encrypt_data(&message, message_key.as_bytes()) {
+		Ok(res) => encode_base64(res),
+		Err(err) => return Err(DawnError::Crypto(err))
+	};
+	let identity = group_identity(&sender_key.pubkey_sig);
+	let content = match envelope_content(&group.id, &group.own_member, &identity, &sender_key.key_id, counter, &ciphertext) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let signature = match sign_detached(GROUP_MESSAGE_DOMAIN, &content, sender_key.seckey_sig.as_bytes()) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let envelope = GroupEnvelope {
+		group: group.id.clone(),
+		sender: group.own_member.clone(),
+		identity,
+		key_id: sender_key.key_id.clone(),
+		counter,
+		ciphertext,
+		signature: encode(signature),
+	};
+	let envelope = match serde_json::to_vec(&envelope) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "json serialization failed")
+	};
+	if let Err(err) = sender_key.chain.advance(sender_key.salt.as_bytes()) { return Err(err); }
+	Ok((mdc, envelope))
+}
+
+// parse a group message
+// messages can arrive in any order, but each one is only decrypted once
+// membership changes (GROUP_MEMBERS events) are verified and applied to the group before they are returned
+// returns the member that sent the message, the message and its message detail code
+pub fn parse_group_msg(group: &mut Group, msg_ciphertext: &[u8], config: &ProtocolConfig) -> Result<(String, ReceivedMessage, String), DawnError> {
+	if let Err(err) = limits::check_ciphertext(msg_ciphertext, &config.limits) { return Err(err); }
+	let envelope = match serde_json::from_slice::<GroupEnvelope>(msg_ciphertext) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "group message json parsing failed")
+	};
+	if envelope.group != group.id { error!("message belongs to another group"); }
+	if envelope.sender == group.own_member { error!("message was sent by the own member"); }
+	if !group.members.contains(&envelope.sender) { error!("sender is not a member of the group"); }
+	let member_key = match group.member_keys.get_mut(&envelope.sender) {
+		Some(res) => res,
+		None => error!("no sender key for the sender of the message")
+	};
+	if member_key.key_id != envelope.key_id { error!("message was sent with an unknown sender key"); }
+	if envelope.identity != group_identity(&member_key.pubkey_sig) { error!(Crypto, "message was not sent with the group identity of its sender"); }
+	
+	// check the signature before any key is derived
+	let content = match envelope_content(&envelope.group, &envelope.sender, &envelope.identity, &envelope.key_id, envelope.counter, &envelope.ciphertext) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let signature = match decode(&envelope.signature) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "group message signature invalid")
+	};
+	if let Err(err) = verify_detached(GROUP_MESSAGE_DOMAIN, &content, &signature, member_key.pubkey_sig.as_bytes()) { return Err(err); }
+	
+	// the chain only changes once the message was decrypted
+	let (message_key, update) = match member_key.chain.key_for(envelope.counter, member_key.salt.as_bytes()) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let ciphertext = match decode_base64(&envelope.ciphertext) {
+		Ok(res) => res,
+		Err(_) => error!("group message ciphertext invalid")
+	};
+	let message = match decrypt_data(&ciphertext, message_key.as_bytes()) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "decryption failed")
+	};
+	let message = match String::from_utf8(message) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "group message is not valid utf-8")
+	};
+	let ParsedContent { message, mdc, .. } = match parse_message_content(&message, &config.limits) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let expected_mdc = match group_mdc(&group.mdc_seed, &group.id, &envelope.sender, &envelope.key_id, envelope.counter) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if mdc != expected_mdc { error!(Crypto, "message detail code does not match the group message"); }
+	let change = match &message {
+		ReceivedMessage::Internal { event: event::GROUP_MEMBERS, data } => match verify_membership_change(group, &envelope.sender, data) {
+			Ok(res) => Some(res),
+			Err(err) => return Err(err)
+		},
+		_ => None
+	};
+	let appearance = match &message {
+		ReceivedMessage::Internal { event: event::GROUP_APPEARANCE, data } => match AppearanceRecord::parse(data) {
+			Ok(res) if res.group != group.id || res.author != envelope.sender => error!("group appearance was set in another group or by another member"),
+			Ok(res) => Some(res),
+			Err(err) => return Err(err)
+		},
+		_ => None
+	};
+	
+	// the member key is looked up again, the group was borrowed to verify the membership change
+	if let Some(member_key) = group.member_keys.get_mut(&envelope.sender) { member_key.chain.apply(update); }
+	if let Some((change, rank)) = change {
+		if let Err(err) = group.apply_membership_change(&change, rank) { return Err(err); }
+	}
+	// an appearance that lost against the current one is still returned, but not kept
+	if let Some(appearance) = appearance {
+		if appearance.supersedes(group.appearance.as_ref()) { group.appearance = Some(appearance); }
+	}
+	Ok((envelope.sender, message, mdc))
+}
@@ -0,0 +1,128 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Group appearance
+// The description and avatar of a group are set by its members with a GROUP_APPEARANCE event sent to the group (see
+// send_group_appearance). The avatar is a linked media file: the event carries its link and its media key, wrapped with
+// the group key (see wrap_media_key), which all members get with the sender key messages, so the key is bound to the group
+// and the link. Every appearance carries an epoch one higher than the one its author knew. Members keep the appearance
+// with the highest epoch and, between appearances of the same epoch set concurrently, the one of the member with the
+// greater id, so all members converge on the same appearance whatever order the events arrive in. The current appearance
+// is handed to new members with the sender key messages, so they see it without the events sent before they joined.
+
+use serde::{Serialize, Deserialize};
+use crate::media::{wrap_media_key, unwrap_media_key};
+use crate::DawnError;
+
+// longest description of a group (in bytes)
+pub const MAX_GROUP_DESCRIPTION_LEN: usize = 2048;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupAvatar {
+	pub media_link: String,
+	pub media_key: Vec<u8>, // key of the linked media file (see decrypt_file)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupAppearance {
+	pub epoch: u64,
+	pub author: String, // member that set the appearance
+	pub description: Option<String>,
+	pub avatar: Option<GroupAvatar>,
+}
+
+// content of a GROUP_APPEARANCE event and the form the appearance is kept in
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct AppearanceRecord {
+	pub(crate) group: String,
+	pub(crate) epoch: u64,
+	pub(crate) author: String,
+	description: Option<String>,
+	avatar_link: Option<String>,
+	avatar_key: Option<String>, // media key wrapped with the group key
+}
+
+impl AppearanceRecord {
+	pub(crate) fn new(group_key: &[u8], group: &str, epoch: u64, author: &str, description: Option<&str>, avatar: Option<&GroupAvatar>) -> Result<Self, DawnError> {
+		if description.is_some_and(|description| description.len() > MAX_GROUP_DESCRIPTION_LEN) {
+			error!(&format!("group description exceeds the limit of {} bytes", MAX_GROUP_DESCRIPTION_LEN));
+		}
+		let (avatar_link, avatar_key) = match avatar {
+			Some(avatar) => {
+				if avatar.media_link.is_empty() { error!("avatar link must not be empty"); }
+				match wrap_media_key(&avatar.media_key, group_key, group, &avatar.media_link) {
+					Ok(res) => (Some(avatar.media_link.clone()), Some(res)),
+					Err(err) => return Err(err)
+				}
+			},
+			None => (None, None)
+		};
+		Ok(AppearanceRecord {
+			group: group.to_string(),
+			epoch,
+			author: author.to_string(),
+			description: description.map(str::to_string),
+			avatar_link,
+			avatar_key,
+		})
+	}
+	
+	pub(crate) fn parse(data: &[u8]) -> Result<Self, DawnError> {
+		let record = match serde_json::from_slice::<AppearanceRecord>(data) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "group appearance json parsing failed")
+		};
+		if record.description.as_ref().is_some_and(|description| description.len() > MAX_GROUP_DESCRIPTION_LEN) {
+			error!(&format!("group description exceeds the limit of {} bytes", MAX_GROUP_DESCRIPTION_LEN));
+		}
+		if record.avatar_link.is_some() != record.avatar_key.is_some() { error!("group avatar is missing its link or key"); }
+		Ok(record)
+	}
+	
+	pub(crate) fn to_vec(&self) -> Result<Vec<u8>, DawnError> {
+		match serde_json::to_vec(self) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "json serialization failed")
+		}
+	}
+	
+	// whether all members replace the other appearance with this one
+	pub(crate) fn supersedes(&self, other: Option<&AppearanceRecord>) -> bool {
+		match other {
+			Some(other) => (self.epoch, &self.author) > (other.epoch, &other.author),
+			None => true
+		}
+	}
+	
+	// unwrap the avatar key with the group key
+	pub(crate) fn open(&self, group_key: &[u8]) -> Result<GroupAppearance, DawnError> {
+		let avatar = match (&self.avatar_link, &self.avatar_key) {
+			(Some(media_link), Some(avatar_key)) => match unwrap_media_key(avatar_key, group_key, &self.group, media_link) {
+				Ok(media_key) => Some(GroupAvatar { media_link: media_link.clone(), media_key }),
+				Err(err) => return Err(err)
+			},
+			_ => None
+		};
+		Ok(GroupAppearance {
+			epoch: self.epoch,
+			author: self.author.clone(),
+			description: self.description.clone(),
+			avatar,
+		})
+	}
+}
@@ -0,0 +1,164 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Joining groups
+// A group handle lets people who are not members yet ask to join a group. An admin creates it with Group::gen_handle: it
+// carries the id of the group, its admins and the join secret of the group, which all members get with the sender key
+// messages, so only people who got the handle can ask to join. The prospective member sends the GROUP_JOIN_REQUEST event
+// returned by gen_group_join_request over a 1:1 conversation with one of the admins, which authenticates the requester
+// like the init request of a 1:1 conversation does. The admin keeps the request with the group (see
+// Group::process_join_request) until it approves or denies it. Approving sends the membership change that adds the
+// requester to the group and returns the sender key message that invites it, which the requester joins with
+// Group::join_with_handle, so only an admin of the handle can invite it. The other members hand their sender keys to the
+// new member once they got the membership change. Denying returns a GROUP_JOIN_DENIAL event for the requester.
+
+use serde::{Serialize, Deserialize};
+use dawn_crypto::id_gen;
+use crate::outgoing::OutgoingMessage;
+use crate::event;
+use crate::DawnError;
+
+// longest note a requester can add to a join request (in bytes)
+pub const MAX_JOIN_NOTE_LEN: usize = 512;
+
+// the part of a group handle a prospective member needs to know
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupHandle {
+	pub group: String,
+	pub admins: Vec<String>, // the request has to be sent to one of them
+	join_secret: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupJoinRequest {
+	pub id: String,
+	pub group: String,
+	pub requester: String,
+	pub note: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct HandleRecord {
+	group: String,
+	admins: Vec<String>,
+	join_secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JoinRequestRecord {
+	id: String,
+	group: String,
+	requester: String,
+	note: Option<String>,
+	join_secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JoinDenialRecord {
+	group: String,
+	request: String,
+}
+
+pub(crate) fn gen_group_handle(group: &str, admins: &[String], join_secret: &str) -> Result<Vec<u8>, DawnError> {
+	let record = HandleRecord {
+		group: group.to_string(),
+		admins: admins.to_vec(),
+		join_secret: join_secret.to_string(),
+	};
+	match serde_json::to_vec(&record) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+pub fn parse_group_handle(handle: &[u8]) -> Result<GroupHandle, DawnError> {
+	let record = match serde_json::from_slice::<HandleRecord>(handle) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "group handle json parsing failed")
+	};
+	if record.group.is_empty() || record.join_secret.is_empty() { error!("group handle is missing its group or join secret"); }
+	if record.admins.is_empty() { error!("group handle does not name an admin"); }
+	Ok(GroupHandle {
+		group: record.group,
+		admins: record.admins,
+		join_secret: record.join_secret,
+	})
+}
+
+// ask to join the group of a handle
+// the message has to be sent over a 1:1 conversation with one of the admins of the handle
+// returns the request, so the requester can match the answer, and the message
+pub fn gen_group_join_request(handle: &GroupHandle, requester: &str, note: Option<&str>) -> Result<(GroupJoinRequest, OutgoingMessage), DawnError> {
+	if requester.is_empty() { error!("member id must not be empty"); }
+	if note.is_some_and(|note| note.len() > MAX_JOIN_NOTE_LEN) { error!(&format!("join request note exceeds the limit of {} bytes", MAX_JOIN_NOTE_LEN)); }
+	let record = JoinRequestRecord {
+		id: id_gen(),
+		group: handle.group.clone(),
+		requester: requester.to_string(),
+		note: note.map(str::to_string),
+		join_secret: handle.join_secret.clone(),
+	};
+	let data = match serde_json::to_vec(&record) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "json serialization failed")
+	};
+	let request = GroupJoinRequest {
+		id: record.id,
+		group: record.group,
+		requester: record.requester,
+		note: record.note,
+	};
+	Ok((request, OutgoingMessage::internal(event::GROUP_JOIN_REQUEST, &data)))
+}
+
+// returns the request and the join secret it was made with
+pub(crate) fn parse_join_request(data: &[u8]) -> Result<(GroupJoinRequest, String), DawnError> {
+	let record = match serde_json::from_slice::<JoinRequestRecord>(data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "join request json parsing failed")
+	};
+	if record.id.is_empty() || record.requester.is_empty() { error!("join request is missing its id or requester"); }
+	if record.note.as_ref().is_some_and(|note| note.len() > MAX_JOIN_NOTE_LEN) { error!(&format!("join request note exceeds the limit of {} bytes", MAX_JOIN_NOTE_LEN)); }
+	let request = GroupJoinRequest {
+		id: record.id,
+		group: record.group,
+		requester: record.requester,
+		note: record.note,
+	};
+	Ok((request, record.join_secret))
+}
+
+pub(crate) fn gen_join_denial(request: &GroupJoinRequest) -> Result<OutgoingMessage, DawnError> {
+	let record = JoinDenialRecord {
+		group: request.group.clone(),
+		request: request.id.clone(),
+	};
+	match serde_json::to_vec(&record) {
+		Ok(res) => Ok(OutgoingMessage::internal(event::GROUP_JOIN_DENIAL, &res)),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// parse the answer of an admin that denied a join request
+// returns the group and the id of the request
+pub fn parse_group_join_denial(data: &[u8]) -> Result<(String, String), DawnError> {
+	match serde_json::from_slice::<JoinDenialRecord>(data) {
+		Ok(record) => Ok((record.group, record.request)),
+		Err(_) => error!(Serialization, "join denial json parsing failed")
+	}
+}
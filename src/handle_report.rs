@@ -0,0 +1,127 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Handle sanity reports
+// A handle that was scanned from a damaged QR code or copied incompletely is only rejected with a generic error by
+// parse_handle. analyze_handle checks every part of a handle on its own and returns what it found, without creating a
+// session or failing on the first problem, so client UIs can tell users what exactly is wrong with a handle.
+// The current handle format (version 1) has no signature and no expiry, reports of these handles say so.
+
+use crate::codec::decode;
+use crate::keys::{KYBER_PUBLIC_KEY_LEN, CURVE_PUBLIC_KEY_LEN};
+use crate::key_audit::HANDLE_KEYS;
+
+// number of lines of a version 1 handle: five init keys, name and message detail code
+const HANDLE_V1_LINES: usize = 7;
+
+// bytes a QR code of version 1 to 40 can hold in byte mode with error correction level L
+const QR_BYTE_CAPACITY: [usize; 40] = [
+	17, 32, 53, 78, 106, 134, 154, 192, 230, 271, 321, 367, 425, 458, 520, 586, 644, 718, 792, 858,
+	929, 1003, 1091, 1171, 1273, 1367, 1465, 1528, 1628, 1732, 1840, 1952, 2068, 2188, 2303, 2431, 2563, 2699, 2809, 2953,
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HandleKeyCheck {
+	pub name: &'static str,
+	pub expected_len: usize,
+	pub len: Option<usize>, // None if the key is missing or not valid hex
+	pub valid: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HandleReport {
+	pub version: Option<u8>, // None if the layout of the handle is not recognized
+	pub keys: Vec<HandleKeyCheck>,
+	pub name: Option<String>,
+	pub mdc: Option<String>,
+	pub signature_present: bool,
+	pub signature_valid: Option<bool>, // None if there is no signature to check
+	pub expires_at: Option<u64>,
+	pub len: usize, // in bytes
+	pub qr_version: Option<u8>, // smallest QR code that can hold the handle, None if it doesn't fit into one
+	pub problems: Vec<String>,
+}
+
+impl HandleReport {
+	// whether the handle can be used to send an init request
+	pub fn is_valid(&self) -> bool {
+		self.problems.is_empty()
+	}
+}
+
+// smallest QR code version (1 to 40) that holds data of this length in byte mode
+pub fn estimate_qr_version(len: usize) -> Option<u8> {
+	QR_BYTE_CAPACITY.iter().position(|capacity| len <= *capacity).map(|index| index as u8 + 1)
+}
+
+// check a handle without parsing it into keys
+pub fn analyze_handle(handle_content: &[u8]) -> HandleReport {
+	let mut report = HandleReport {
+		version: None,
+		keys: Vec::with_capacity(HANDLE_KEYS.len()),
+		name: None,
+		mdc: None,
+		signature_present: false,
+		signature_valid: None,
+		expires_at: None,
+		len: handle_content.len(),
+		qr_version: estimate_qr_version(handle_content.len()),
+		problems: Vec::new(),
+	};
+	
+	let handle_string = match std::str::from_utf8(handle_content) {
+		Ok(res) => res,
+		Err(_) => {
+			report.problems.push("handle content is not valid UTF-8".to_string());
+			return report;
+		}
+	};
+	let lines: Vec<&str> = handle_string.split('\n').collect();
+	if lines.len() == HANDLE_V1_LINES { report.version = Some(1); }
+	else { report.problems.push(format!("handle has {} lines instead of {}, it may be truncated or of an unknown version", lines.len(), HANDLE_V1_LINES)); }
+	
+	for (index, name) in HANDLE_KEYS.iter().enumerate() {
+		let expected_len = if name.contains("kyber") { KYBER_PUBLIC_KEY_LEN } else { CURVE_PUBLIC_KEY_LEN };
+		let len = match lines.get(index).map(decode) {
+			Some(Ok(res)) => Some(res.len()),
+			Some(Err(_)) => {
+				report.problems.push(format!("key {} is not valid hex", name));
+				None
+			},
+			None => {
+				report.problems.push(format!("key {} is missing", name));
+				None
+			}
+		};
+		if let Some(len) = len {
+			if len != expected_len { report.problems.push(format!("key {} has {} bytes instead of {}", name, len, expected_len)); }
+		}
+		report.keys.push(HandleKeyCheck { name, expected_len, len, valid: len == Some(expected_len) });
+	}
+	
+	report.name = lines.get(HANDLE_KEYS.len()).map(|name| name.to_string());
+	if report.name.is_none() { report.problems.push("name is missing".to_string()); }
+	report.mdc = match lines.get(HANDLE_KEYS.len() + 1) {
+		Some(mdc) if !mdc.is_empty() => Some(mdc.to_string()),
+		_ => {
+			report.problems.push("message detail code is missing".to_string());
+			None
+		}
+	};
+	report
+}
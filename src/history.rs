@@ -0,0 +1,236 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// History transfer to a newly linked device
+// The selected past messages are serialized, split into chunks and each chunk is sent as a HISTORY_SYNC message over the
+// regular message pipeline (send_msg/parse_msg) of the conversation with the own new device. The receiving device feeds
+// every chunk it gets into a HistoryReassembler, which returns the transferred messages once all chunks have arrived.
+// Like for transport fragments (see fragment.rs), the number of incomplete transfers and the bytes buffered for each of
+// them are limited, so chunks of ever new or never ending transfers can't exhaust the memory of the receiving device.
+
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::codec::{encode_base64, decode_base64};
+use crate::content_type::ContentType;
+use dawn_crypto::id_gen;
+use crate::DawnError;
+
+// default limits for incomplete transfers and the bytes buffered per transfer
+pub const HISTORY_MAX_PENDING: usize = 8;
+pub const HISTORY_MAX_BYTES: usize = 256 * 1024 * 1024;
+
+// a past message as it is handed over by the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+	pub id: String, // id of the conversation the message belongs to
+	pub sent: bool, // true if the message was sent by the own account
+	pub timestamp: u64,
+	pub content_type: ContentType,
+	pub text: Option<String>,
+	pub data: Option<Vec<u8>>,
+}
+
+// serialized form of a HistoryEntry
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct HistoryRecord {
+	id: String,
+	sent: bool,
+	timestamp: u64,
+	content_type: ContentType,
+	text: Option<String>,
+	data: Option<String>,
+}
+
+// chunks of a transfer that is not complete yet
+struct PendingTransfer {
+	chunks: HashMap<u32, Vec<u8>>,
+	chunk_count: u32,
+	bytes: usize, // bytes of all chunks received so far
+}
+
+// collects HISTORY_SYNC chunks on the receiving device
+pub struct HistoryReassembler {
+	transfers: HashMap<String, PendingTransfer>,
+	max_pending: usize,
+	max_bytes: usize,
+}
+
+impl HistoryRecord {
+	pub(crate) fn from_entry(entry: &HistoryEntry) -> Self {
+		HistoryRecord {
+			id: entry.id.clone(),
+			sent: entry.sent,
+			timestamp: entry.timestamp,
+			content_type: entry.content_type,
+			text: entry.text.clone(),
+			data: entry.data.as_ref().map(encode_base64),
+		}
+	}
+	
+	pub(crate) fn into_entry(self) -> Result<HistoryEntry, DawnError> {
+		let data = match self.data {
+			Some(data) => match decode_base64(data) {
+				Ok(res) => Some(res),
+				Err(_) => error!("history entry data invalid")
+			},
+			None => None
+		};
+		Ok(HistoryEntry {
+			id: self.id,
+			sent: self.sent,
+			timestamp: self.timestamp,
+			content_type: self.content_type,
+			text: self.text,
+			data,
+		})
+	}
+}
+
+// package past messages for a history transfer
+// returns a list of (chunk header, chunk data) that have to be sent in order using send_msg with content_type::HISTORY_SYNC
+pub fn gen_history_chunks(entries: &[HistoryEntry], max_chunk_size: usize) -> Result<Vec<(String, Vec<u8>)>, DawnError> {
+	if max_chunk_size == 0 { error!("chunk size must not be zero"); }
+	
+	let records: Vec<HistoryRecord> = entries.iter().map(HistoryRecord::from_entry).collect();
+	let payload = match serde_json::to_vec(&records) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "json serialization failed")
+	};
+	
+	let transfer_id = id_gen();
+	let chunk_count = payload.chunks(max_chunk_size).len();
+	if chunk_count > u32::MAX as usize { error!("history transfer has too many chunks"); }
+	
+	let mut chunks = Vec::with_capacity(chunk_count);
+	for (index, chunk) in payload.chunks(max_chunk_size).enumerate() {
+		chunks.push((format!("{}\n{}\n{}", transfer_id, index, chunk_count), chunk.to_vec()));
+	}
+	Ok(chunks)
+}
+
+// parse the header of a history transfer chunk
+// returns transfer id, chunk index and chunk count
+pub(crate) fn parse_chunk_header(header: &str) -> Result<(String, u32, u32), DawnError> {
+	let mut lines = header.lines();
+	let transfer_id = match lines.next() {
+		Some(res) if !res.is_empty() => res.to_string(),
+		_ => error!("history chunk header is missing the transfer id")
+	};
+	let chunk_index = match lines.next().map(|line| line.parse::<u32>()) {
+		Some(Ok(res)) => res,
+		_ => error!("history chunk header contains an invalid chunk index")
+	};
+	let chunk_count = match lines.next().map(|line| line.parse::<u32>()) {
+		Some(Ok(res)) => res,
+		_ => error!("history chunk header contains an invalid chunk count")
+	};
+	if chunk_index >= chunk_count { error!("history chunk index out of range"); }
+	Ok((transfer_id, chunk_index, chunk_count))
+}
+
+impl Default for HistoryReassembler {
+	fn default() -> Self {
+		HistoryReassembler {
+			transfers: HashMap::new(),
+			max_pending: HISTORY_MAX_PENDING,
+			max_bytes: HISTORY_MAX_BYTES,
+		}
+	}
+}
+
+impl HistoryReassembler {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// maximum number of transfers that are waiting for chunks at the same time
+	pub fn max_pending(mut self, max_pending: usize) -> Self {
+		self.max_pending = max_pending;
+		self
+	}
+	
+	// maximum number of bytes buffered for a single transfer
+	pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+		self.max_bytes = max_bytes;
+		self
+	}
+	
+	// add a received chunk (text and data returned by parse_msg for a HISTORY_SYNC message)
+	// returns the transferred messages once the last missing chunk of a transfer was added
+	pub fn add_chunk(&mut self, header: &str, chunk: &[u8]) -> Result<Option<Vec<HistoryEntry>>, DawnError> {
+		let (transfer_id, chunk_index, chunk_count) = match parse_chunk_header(header) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		
+		// chunks are only stored as they arrive, so a bogus chunk count can't make us allocate memory for it
+		if !self.transfers.contains_key(&transfer_id) && self.transfers.len() >= self.max_pending { error!("too many incomplete history transfers"); }
+		let transfer = self.transfers.entry(transfer_id.clone()).or_insert_with(|| PendingTransfer {
+			chunks: HashMap::new(),
+			chunk_count,
+			bytes: 0,
+		});
+		if transfer.chunk_count != chunk_count { error!("history chunk count does not match the transfer"); }
+		if !transfer.chunks.contains_key(&chunk_index) {
+			// a transfer exceeding the limit can never be completed, so it is dropped
+			if transfer.bytes.saturating_add(chunk.len()) > self.max_bytes {
+				self.transfers.remove(&transfer_id);
+				error!("history transfer exceeds the size limit");
+			}
+			transfer.bytes += chunk.len();
+			transfer.chunks.insert(chunk_index, chunk.to_vec());
+		}
+		if (transfer.chunks.len() as u32) < chunk_count { return Ok(None); }
+		
+		// all chunks are there, reassemble the transfer
+		let mut transfer = match self.transfers.remove(&transfer_id) {
+			Some(res) => res,
+			None => error!("history transfer vanished during reassembly")
+		};
+		let mut payload = Vec::new();
+		for index in 0..chunk_count {
+			match transfer.chunks.remove(&index) {
+				Some(chunk) => payload.extend(chunk),
+				None => error!("history chunk missing during reassembly")
+			}
+		}
+		let records = match serde_json::from_slice::<Vec<HistoryRecord>>(&payload) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "history transfer json parsing failed")
+		};
+		
+		let mut entries = Vec::with_capacity(records.len());
+		for record in records {
+			match record.into_entry() {
+				Ok(entry) => entries.push(entry),
+				Err(err) => return Err(err)
+			}
+		}
+		Ok(Some(entries))
+	}
+	
+	// drop a transfer that will not be completed (e.g. because the sending device went away)
+	pub fn discard(&mut self, transfer_id: &str) {
+		self.transfers.remove(transfer_id);
+	}
+	
+	// returns the number of transfers that are waiting for chunks
+	pub fn pending(&self) -> usize {
+		self.transfers.len()
+	}
+}
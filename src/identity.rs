@@ -0,0 +1,215 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Own identity
+// Groups the long-term keys of the user: the signature keypair and the init keys that are published in handles and used
+// to receive init requests. The methods wrap the handshake functions, so clients don't have to pass every key by hand.
+
+use serde::{Serialize, Deserialize};
+use dawn_crypto::{encrypt_data, decrypt_data};
+use crate::codec::{encode, decode};
+use crate::keys::*;
+use crate::init_request::{InitRequestBuilder, ParsedInitRequest, InitRequestPreview, peek_init_request};
+use crate::{gen_handle, parse_init_request, accept_init_request, AcceptedInit};
+use crate::DawnError;
+
+const BACKUP_VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+	pub pubkey_sig: SignPublicKey,
+	pub seckey_sig: SignSecretKey,
+	pub init_pubkey_kyber: KyberPublicKey,
+	pub init_seckey_kyber: KyberSecretKey,
+	pub init_pubkey_curve: CurvePublicKey,
+	pub init_seckey_curve: CurveSecretKey,
+	pub init_pubkey_curve_pfs_2: CurvePublicKey,
+	pub init_seckey_curve_pfs_2: CurveSecretKey,
+	pub init_pubkey_kyber_for_salt: KyberPublicKey,
+	pub init_seckey_kyber_for_salt: KyberSecretKey,
+	pub init_pubkey_curve_for_salt: CurvePublicKey,
+	pub init_seckey_curve_for_salt: CurveSecretKey,
+}
+
+// serialized form of an identity inside an encrypted backup
+#[derive(Serialize, Deserialize, Debug)]
+struct IdentityBackup {
+	version: u8,
+	sign: (String, String),
+	init_kyber: (String, String),
+	init_curve: (String, String),
+	init_curve_pfs_2: (String, String),
+	init_kyber_for_salt: (String, String),
+	init_curve_for_salt: (String, String),
+}
+
+// decode a hex encoded keypair from a backup
+fn decode_keypair<P, S>((pubkey, seckey): &(String, String)) -> Result<(P, S), DawnError>
+where P: TryFrom<Vec<u8>, Error = DawnError>, S: TryFrom<Vec<u8>, Error = DawnError> {
+	let pubkey = match decode(pubkey) {
+		Ok(res) => res,
+		Err(_) => error!("identity backup contains an invalid key")
+	};
+	let seckey = match decode(seckey) {
+		Ok(res) => res,
+		Err(_) => error!("identity backup contains an invalid key")
+	};
+	match (P::try_from(pubkey), S::try_from(seckey)) {
+		(Ok(pubkey), Ok(seckey)) => Ok((pubkey, seckey)),
+		_ => error!("identity backup contains a key of invalid size")
+	}
+}
+
+// kyber, curve, curve pfs 2, kyber for salt and curve for salt keypairs
+type InitKeys = ((KyberPublicKey, KyberSecretKey), (CurvePublicKey, CurveSecretKey), (CurvePublicKey, CurveSecretKey), (KyberPublicKey, KyberSecretKey), (CurvePublicKey, CurveSecretKey));
+
+// generate a fresh set of init keys
+fn gen_init_keys() -> Result<InitKeys, DawnError> {
+	let kyber = match gen_kyber_keypair() { Ok(res) => res, Err(err) => return Err(err) };
+	let curve = match gen_curve_keypair() { Ok(res) => res, Err(err) => return Err(err) };
+	let curve_pfs_2 = match gen_curve_keypair() { Ok(res) => res, Err(err) => return Err(err) };
+	let kyber_for_salt = match gen_kyber_keypair() { Ok(res) => res, Err(err) => return Err(err) };
+	let curve_for_salt = match gen_curve_keypair() { Ok(res) => res, Err(err) => return Err(err) };
+	Ok((kyber, curve, curve_pfs_2, kyber_for_salt, curve_for_salt))
+}
+
+impl Identity {
+	// generate a new identity with fresh keys
+	pub fn generate() -> Result<Self, DawnError> {
+		let (pubkey_sig, seckey_sig) = match gen_sign_keypair() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let (kyber, curve, curve_pfs_2, kyber_for_salt, curve_for_salt) = match gen_init_keys() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		Ok(Identity {
+			pubkey_sig,
+			seckey_sig,
+			init_pubkey_kyber: kyber.0,
+			init_seckey_kyber: kyber.1,
+			init_pubkey_curve: curve.0,
+			init_seckey_curve: curve.1,
+			init_pubkey_curve_pfs_2: curve_pfs_2.0,
+			init_seckey_curve_pfs_2: curve_pfs_2.1,
+			init_pubkey_kyber_for_salt: kyber_for_salt.0,
+			init_seckey_kyber_for_salt: kyber_for_salt.1,
+			init_pubkey_curve_for_salt: curve_for_salt.0,
+			init_seckey_curve_for_salt: curve_for_salt.1,
+		})
+	}
+	
+	// replace the init keys (invalidating all handles generated so far) while keeping the signature keys
+	pub fn rotate_init_keys(&mut self) -> Result<(), DawnError> {
+		let (kyber, curve, curve_pfs_2, kyber_for_salt, curve_for_salt) = match gen_init_keys() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		(self.init_pubkey_kyber, self.init_seckey_kyber) = kyber;
+		(self.init_pubkey_curve, self.init_seckey_curve) = curve;
+		(self.init_pubkey_curve_pfs_2, self.init_seckey_curve_pfs_2) = curve_pfs_2;
+		(self.init_pubkey_kyber_for_salt, self.init_seckey_kyber_for_salt) = kyber_for_salt;
+		(self.init_pubkey_curve_for_salt, self.init_seckey_curve_for_salt) = curve_for_salt;
+		Ok(())
+	}
+	
+	// restore an identity from a backup created by Identity::backup
+	pub fn from_backup(backup: &[u8], backup_key: &[u8]) -> Result<Self, DawnError> {
+		let backup = match decrypt_data(backup, backup_key) {
+			Ok(res) => res,
+			Err(err) => { error!(Crypto, &format!("identity backup decryption failed: {}", err)); }
+		};
+		let backup = match serde_json::from_slice::<IdentityBackup>(&backup) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "identity backup json parsing failed")
+		};
+		if backup.version != BACKUP_VERSION { error!(&format!("identity backup version {} is not supported", backup.version)); }
+		
+		let (pubkey_sig, seckey_sig) = match decode_keypair(&backup.sign) { Ok(res) => res, Err(err) => return Err(err) };
+		let (init_pubkey_kyber, init_seckey_kyber) = match decode_keypair(&backup.init_kyber) { Ok(res) => res, Err(err) => return Err(err) };
+		let (init_pubkey_curve, init_seckey_curve) = match decode_keypair(&backup.init_curve) { Ok(res) => res, Err(err) => return Err(err) };
+		let (init_pubkey_curve_pfs_2, init_seckey_curve_pfs_2) = match decode_keypair(&backup.init_curve_pfs_2) { Ok(res) => res, Err(err) => return Err(err) };
+		let (init_pubkey_kyber_for_salt, init_seckey_kyber_for_salt) = match decode_keypair(&backup.init_kyber_for_salt) { Ok(res) => res, Err(err) => return Err(err) };
+		let (init_pubkey_curve_for_salt, init_seckey_curve_for_salt) = match decode_keypair(&backup.init_curve_for_salt) { Ok(res) => res, Err(err) => return Err(err) };
+		
+		Ok(Identity {
+			pubkey_sig,
+			seckey_sig,
+			init_pubkey_kyber,
+			init_seckey_kyber,
+			init_pubkey_curve,
+			init_seckey_curve,
+			init_pubkey_curve_pfs_2,
+			init_seckey_curve_pfs_2,
+			init_pubkey_kyber_for_salt,
+			init_seckey_kyber_for_salt,
+			init_pubkey_curve_for_salt,
+			init_seckey_curve_for_salt,
+		})
+	}
+	
+	// create an encrypted backup of the identity using a symmetric key (see sym_key_gen)
+	pub fn backup(&self, backup_key: &[u8]) -> Result<Vec<u8>, DawnError> {
+		let backup = IdentityBackup {
+			version: BACKUP_VERSION,
+			sign: (encode(&self.pubkey_sig), encode(&self.seckey_sig)),
+			init_kyber: (encode(&self.init_pubkey_kyber), encode(&self.init_seckey_kyber)),
+			init_curve: (encode(&self.init_pubkey_curve), encode(&self.init_seckey_curve)),
+			init_curve_pfs_2: (encode(&self.init_pubkey_curve_pfs_2), encode(&self.init_seckey_curve_pfs_2)),
+			init_kyber_for_salt: (encode(&self.init_pubkey_kyber_for_salt), encode(&self.init_seckey_kyber_for_salt)),
+			init_curve_for_salt: (encode(&self.init_pubkey_curve_for_salt), encode(&self.init_seckey_curve_for_salt)),
+		};
+		let backup = match serde_json::to_vec(&backup) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "json serialization failed")
+		};
+		match encrypt_data(&backup, backup_key) {
+			Ok(res) => Ok(res),
+			Err(err) => { error!(Crypto, &format!("identity backup encryption failed: {}", err)); }
+		}
+	}
+	
+	// generate a handle others can use to send init requests to this identity
+	pub fn gen_handle(&self, name: &str, mdc: &str) -> Vec<u8> {
+		gen_handle(self.init_pubkey_kyber.as_bytes(), self.init_pubkey_curve.as_bytes(), self.init_pubkey_curve_pfs_2.as_bytes(), self.init_pubkey_kyber_for_salt.as_bytes(), self.init_pubkey_curve_for_salt.as_bytes(), name, mdc)
+	}
+	
+	// start an init request signed by this identity
+	pub fn init_request_builder(&self) -> InitRequestBuilder {
+		InitRequestBuilder::new().own_signature_keys(self.pubkey_sig.clone(), self.seckey_sig.clone())
+	}
+	
+	// parse an init request sent to one of the handles of this identity
+	// returns the same as parse_init_request
+	pub fn parse_init_request(&self, request_body: &[u8]) -> Result<ParsedInitRequest, DawnError> {
+		parse_init_request(request_body, self.init_seckey_kyber.as_bytes(), self.init_seckey_curve.as_bytes(), self.init_seckey_curve_pfs_2.as_bytes(), self.init_seckey_kyber_for_salt.as_bytes(), self.init_seckey_curve_for_salt.as_bytes())
+	}
+	
+	// preview an init request sent to one of the handles of this identity
+	// returns the same as peek_init_request
+	pub fn peek_init_request(&self, request_body: &[u8]) -> Result<InitRequestPreview, DawnError> {
+		peek_init_request(request_body, self.init_seckey_kyber.as_bytes(), self.init_seckey_curve.as_bytes(), self.init_seckey_curve_pfs_2.as_bytes(), self.init_seckey_kyber_for_salt.as_bytes(), self.init_seckey_curve_for_salt.as_bytes())
+	}
+	
+	// accept an init request as this identity
+	// returns the same as accept_init_request
+	pub fn accept_init_request(&self, remote_pubkey_kyber: &[u8], pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<AcceptedInit, DawnError> {
+		accept_init_request(self.pubkey_sig.as_bytes(), self.seckey_sig.as_bytes(), remote_pubkey_kyber, pfs_key, pfs_salt, id, mdc_seed)
+	}
+}
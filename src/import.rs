@@ -0,0 +1,145 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Import of message history from other messengers
+// Export tools for other messengers (e.g. for Signal, Matrix or WhatsApp backups) convert their backups into a neutral JSON
+// format, which is mapped to HistoryEntry objects here, so users migrating to Dawn keep their history:
+// {
+// 	"version": 1,
+// 	"source": "signal",
+// 	"conversations": [{
+// 		"name": "Bob",
+// 		"messages": [
+// 			{"timestamp": 1672531200, "from_self": true, "kind": "text", "text": "Hi Bob"},
+// 			{"timestamp": 1672531260, "from_self": false, "sender": "Bob", "kind": "picture", "text": "description", "data": "<unpadded base64>"}
+// 		]
+// 	}]
+// }
+// Supported kinds are text, voice and picture; messages of other kinds (calls, stickers, ...) are skipped and counted.
+// Imported conversations either all go into the own self-conversation or each into a local archive with a new id.
+
+use serde::Deserialize;
+use crate::codec::decode_base64;
+use crate::content_type;
+use crate::history::HistoryEntry;
+use dawn_crypto::id_gen;
+use crate::DawnError;
+
+pub const IMPORT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Deserialize, Debug)]
+struct ImportArchive {
+	version: u32,
+	source: String,
+	conversations: Vec<ImportConversation>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportConversation {
+	name: String,
+	messages: Vec<ImportMessage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportMessage {
+	timestamp: u64,
+	from_self: bool,
+	#[serde(default)]
+	sender: Option<String>,
+	kind: String,
+	#[serde(default)]
+	text: Option<String>,
+	#[serde(default)]
+	data: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportTarget<'a> {
+	SelfConversation(&'a str), // id of the own self-conversation
+	Archive,
+}
+
+// an imported conversation, every message comes with the name of its sender in the source messenger (if known)
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedConversation {
+	pub source: String,
+	pub name: String,
+	pub messages: Vec<(HistoryEntry, Option<String>)>,
+}
+
+// map a message of the neutral format to a HistoryEntry
+// returns None for kinds that have no Dawn equivalent
+fn map_message(message: ImportMessage, id: &str) -> Result<Option<(HistoryEntry, Option<String>)>, DawnError> {
+	let data = match message.data {
+		Some(data) => match decode_base64(data) {
+			Ok(res) => Some(res),
+			Err(_) => error!("imported message data invalid")
+		},
+		None => None
+	};
+	let content_type = match message.kind.as_str() {
+		"text" if message.text.is_some() && data.is_none() => content_type::TEXT,
+		"voice" if data.is_some() => content_type::VOICE,
+		"picture" if data.is_some() => content_type::PICTURE,
+		"text" | "voice" | "picture" => error!("imported message is missing its content"),
+		_ => return Ok(None)
+	};
+	let entry = HistoryEntry {
+		id: id.to_string(),
+		sent: message.from_self,
+		timestamp: message.timestamp,
+		content_type,
+		text: if content_type == content_type::VOICE { None } else { message.text },
+		data,
+	};
+	Ok(Some((entry, message.sender)))
+}
+
+// import an export in the neutral format
+// returns the imported conversations with their messages in chronological order and the number of skipped messages
+pub fn import_history(export: &[u8], target: ImportTarget) -> Result<(Vec<ImportedConversation>, usize), DawnError> {
+	let archive = match serde_json::from_slice::<ImportArchive>(export) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "import json parsing failed")
+	};
+	if archive.version != IMPORT_FORMAT_VERSION { error!("import format version not supported"); }
+	
+	let mut conversations = Vec::with_capacity(archive.conversations.len());
+	let mut skipped = 0;
+	for conversation in archive.conversations {
+		let id = match target {
+			ImportTarget::SelfConversation(id) => id.to_string(),
+			ImportTarget::Archive => id_gen(),
+		};
+		let mut messages = Vec::with_capacity(conversation.messages.len());
+		for message in conversation.messages {
+			match map_message(message, &id) {
+				Ok(Some(res)) => messages.push(res),
+				Ok(None) => skipped += 1,
+				Err(err) => return Err(err)
+			}
+		}
+		messages.sort_by_key(|(entry, _)| entry.timestamp);
+		conversations.push(ImportedConversation {
+			source: archive.source.clone(),
+			name: conversation.name,
+			messages,
+		});
+	}
+	Ok((conversations, skipped))
+}
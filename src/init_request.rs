@@ -0,0 +1,260 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Builder for init requests
+// gen_init_request takes ten positional key and string parameters, where swapping two keys still compiles. The builder
+// names every parameter and only accepts typed keys, so the keys of a handle can't end up in the wrong place.
+// The results of generating and parsing init requests and responses are returned as structs with named fields for the
+// same reason.
+
+use crate::keys::{KyberPublicKey, CurvePublicKey, SignPublicKey, SignSecretKey};
+use crate::{gen_init_request_with_params, parse_handle, parse_init_request};
+use crate::peer::Peer;
+use crate::warning::Warning;
+use crate::DawnError;
+
+// result of gen_init_request, everything the requesting side has to keep until the request is accepted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InitRequestResult {
+	pub own_pubkey_kyber: Vec<u8>,
+	pub own_seckey_kyber: Vec<u8>,
+	pub own_pubkey_curve: Vec<u8>,
+	pub own_seckey_curve: Vec<u8>,
+	pub own_pfs_key: Vec<u8>, // pfs key for the next message sent
+	pub remote_pfs_key: Vec<u8>, // pfs key for the first message received
+	pub pfs_salt: Vec<u8>,
+	pub id: String,
+	pub id_salt: Vec<u8>,
+	pub mdc: String, // message detail code the request is sent with
+	pub mdc_seed: String,
+	pub ciphertext: Vec<u8>,
+}
+
+// result of parse_init_request
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedInitRequest {
+	pub id: String,
+	pub id_salt: Vec<u8>,
+	pub mdc: String,
+	pub peer: Peer, // the requesting side
+	pub own_pfs_key: Vec<u8>, // pfs key for the first message sent (the accept)
+	pub remote_pfs_key: Vec<u8>, // pfs key for the next message received
+	pub pfs_salt: Vec<u8>,
+	pub comment: String,
+	pub mdc_seed: String,
+}
+
+// what a client shows about an init request before the user decided to accept it (see peek_init_request)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InitRequestPreview {
+	pub name: String,
+	pub comment: String,
+	pub fingerprint: String, // see Peer::fingerprint
+	pub capabilities: Vec<String>,
+}
+
+// result of parse_init_response
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedInitResponse {
+	pub peer: Peer, // the accepting side
+	pub new_pfs_key: Vec<u8>, // pfs key for the next message received
+	pub mdc: String,
+	pub warning: Warning,
+}
+
+// the parameters of an init request as gen_init_request_with_params takes them
+pub(crate) struct InitRequestParams<'a> {
+	pub(crate) remote_pubkey_kyber: &'a [u8],
+	pub(crate) remote_pubkey_kyber_for_salt: &'a [u8],
+	pub(crate) remote_pubkey_curve: &'a [u8],
+	pub(crate) remote_pubkey_curve_pfs_2: &'a [u8],
+	pub(crate) remote_pubkey_curve_for_salt: &'a [u8],
+	pub(crate) own_pubkey_sig: &'a [u8],
+	pub(crate) own_seckey_sig: &'a [u8],
+	pub(crate) name: &'a str,
+	pub(crate) comment: &'a str,
+	pub(crate) mdc: &'a str,
+	pub(crate) id: Option<&'a str>, // reuse an existing conversation id instead of a new one
+	pub(crate) media_codecs: &'a [&'a str],
+}
+
+#[derive(Default)]
+pub struct InitRequestBuilder {
+	remote_pubkey_kyber: Option<KyberPublicKey>,
+	remote_pubkey_kyber_for_salt: Option<KyberPublicKey>,
+	remote_pubkey_curve: Option<CurvePublicKey>,
+	remote_pubkey_curve_pfs_2: Option<CurvePublicKey>,
+	remote_pubkey_curve_for_salt: Option<CurvePublicKey>,
+	own_pubkey_sig: Option<SignPublicKey>,
+	own_seckey_sig: Option<SignSecretKey>,
+	name: Option<String>,
+	comment: String,
+	mdc: Option<String>,
+	conversation_id: Option<String>,
+	media_codecs: Vec<String>,
+}
+
+impl InitRequestBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// take all remote init keys and the message detail code from a handle
+	pub fn handle(self, handle_content: Vec<u8>) -> Result<Self, DawnError> {
+		let (init_pubkey_kyber, init_pubkey_curve, init_pubkey_curve_pfs_2, init_pubkey_kyber_for_salt, init_pubkey_curve_for_salt, _, mdc) = match parse_handle(handle_content) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let builder = match (KyberPublicKey::try_from(init_pubkey_kyber), KyberPublicKey::try_from(init_pubkey_kyber_for_salt)) {
+			(Ok(kyber), Ok(kyber_for_salt)) => self.remote_kyber(kyber).remote_kyber_for_salt(kyber_for_salt),
+			_ => error!("handle contains invalid kyber keys")
+		};
+		let builder = match (CurvePublicKey::try_from(init_pubkey_curve), CurvePublicKey::try_from(init_pubkey_curve_pfs_2), CurvePublicKey::try_from(init_pubkey_curve_for_salt)) {
+			(Ok(curve), Ok(curve_pfs_2), Ok(curve_for_salt)) => builder.remote_curve(curve).remote_curve_pfs_2(curve_pfs_2).remote_curve_for_salt(curve_for_salt),
+			_ => error!("handle contains invalid curve keys")
+		};
+		Ok(builder.mdc(&mdc))
+	}
+	
+	pub fn remote_kyber(mut self, pubkey: KyberPublicKey) -> Self {
+		self.remote_pubkey_kyber = Some(pubkey);
+		self
+	}
+	
+	pub fn remote_kyber_for_salt(mut self, pubkey: KyberPublicKey) -> Self {
+		self.remote_pubkey_kyber_for_salt = Some(pubkey);
+		self
+	}
+	
+	pub fn remote_curve(mut self, pubkey: CurvePublicKey) -> Self {
+		self.remote_pubkey_curve = Some(pubkey);
+		self
+	}
+	
+	pub fn remote_curve_pfs_2(mut self, pubkey: CurvePublicKey) -> Self {
+		self.remote_pubkey_curve_pfs_2 = Some(pubkey);
+		self
+	}
+	
+	pub fn remote_curve_for_salt(mut self, pubkey: CurvePublicKey) -> Self {
+		self.remote_pubkey_curve_for_salt = Some(pubkey);
+		self
+	}
+	
+	pub fn own_signature_keys(mut self, pubkey: SignPublicKey, seckey: SignSecretKey) -> Self {
+		self.own_pubkey_sig = Some(pubkey);
+		self.own_seckey_sig = Some(seckey);
+		self
+	}
+	
+	pub fn name(mut self, name: &str) -> Self {
+		self.name = Some(name.to_string());
+		self
+	}
+	
+	pub fn comment(mut self, comment: &str) -> Self {
+		self.comment = comment.to_string();
+		self
+	}
+	
+	pub fn mdc(mut self, mdc: &str) -> Self {
+		self.mdc = Some(mdc.to_string());
+		self
+	}
+	
+	// reuse an existing conversation id instead of generating a new one (see InitFanout)
+	pub fn conversation_id(mut self, id: &str) -> Self {
+		self.conversation_id = Some(id.to_string());
+		self
+	}
+	
+	// announce the media codecs the client can decode (see media_codec.rs)
+	pub fn media_codecs(mut self, codecs: &[&str]) -> Self {
+		self.media_codecs = codecs.iter().map(|codec| codec.to_string()).collect();
+		self
+	}
+	
+	// generate the init request
+	// returns the same as gen_init_request
+	pub fn build(&self) -> Result<InitRequestResult, DawnError> {
+		let remote_pubkey_kyber = match &self.remote_pubkey_kyber {
+			Some(res) => res,
+			None => error!("remote kyber key is missing")
+		};
+		let remote_pubkey_kyber_for_salt = match &self.remote_pubkey_kyber_for_salt {
+			Some(res) => res,
+			None => error!("remote kyber key for salt derivation is missing")
+		};
+		let remote_pubkey_curve = match &self.remote_pubkey_curve {
+			Some(res) => res,
+			None => error!("remote curve key is missing")
+		};
+		let remote_pubkey_curve_pfs_2 = match &self.remote_pubkey_curve_pfs_2 {
+			Some(res) => res,
+			None => error!("remote curve key for pfs is missing")
+		};
+		let remote_pubkey_curve_for_salt = match &self.remote_pubkey_curve_for_salt {
+			Some(res) => res,
+			None => error!("remote curve key for salt derivation is missing")
+		};
+		let (own_pubkey_sig, own_seckey_sig) = match (&self.own_pubkey_sig, &self.own_seckey_sig) {
+			(Some(pubkey), Some(seckey)) => (pubkey, seckey),
+			_ => error!("own signature keys are missing")
+		};
+		let name = match &self.name {
+			Some(res) => res,
+			None => error!("name is missing")
+		};
+		let mdc = match &self.mdc {
+			Some(res) => res,
+			None => error!("message detail code is missing")
+		};
+		let media_codecs: Vec<&str> = self.media_codecs.iter().map(String::as_str).collect();
+		
+		gen_init_request_with_params(&InitRequestParams {
+			remote_pubkey_kyber: remote_pubkey_kyber.as_bytes(),
+			remote_pubkey_kyber_for_salt: remote_pubkey_kyber_for_salt.as_bytes(),
+			remote_pubkey_curve: remote_pubkey_curve.as_bytes(),
+			remote_pubkey_curve_pfs_2: remote_pubkey_curve_pfs_2.as_bytes(),
+			remote_pubkey_curve_for_salt: remote_pubkey_curve_for_salt.as_bytes(),
+			own_pubkey_sig: own_pubkey_sig.as_bytes(),
+			own_seckey_sig: own_seckey_sig.as_bytes(),
+			name,
+			comment: &self.comment,
+			mdc,
+			id: self.conversation_id.as_deref(),
+			media_codecs: &media_codecs
+		})
+	}
+}
+
+// decrypt an init request to show it to the user, without returning any key material
+// init keys are not used up by parsing a request, so the same request body can be parsed with parse_init_request once
+// the user accepts; until then the client holds nothing it could accidentally start a conversation with
+pub fn peek_init_request(request_body: &[u8], own_seckey_kyber: &[u8], own_seckey_curve: &[u8], own_seckey_curve_pfs_2: &[u8], own_seckey_kyber_for_salt: &[u8], own_seckey_curve_for_salt: &[u8]) -> Result<InitRequestPreview, DawnError> {
+	let request = match parse_init_request(request_body, own_seckey_kyber, own_seckey_curve, own_seckey_curve_pfs_2, own_seckey_kyber_for_salt, own_seckey_curve_for_salt) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	Ok(InitRequestPreview {
+		fingerprint: request.peer.fingerprint(),
+		name: request.peer.name,
+		comment: request.comment,
+		capabilities: request.peer.capabilities,
+	})
+}
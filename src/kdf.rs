@@ -0,0 +1,40 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Key derivation functions
+// HKDF and PBKDF2 over SHA-256 for keys the library derives itself (client keys, group sender keys, exported sessions),
+// built on the hkdf and pbkdf2 crates so they don't depend on what dawn-crypto exposes.
+
+use sha2::Sha256;
+use hkdf::Hkdf;
+use pbkdf2::pbkdf2_hmac;
+
+// HKDF-SHA256 (RFC 5869) for a single output block
+pub(crate) fn hkdf_sha256(salt: &[u8], input_key: &[u8], info: &[u8]) -> [u8; 32] {
+	let mut key = [0u8; 32];
+	// a single block is always a valid output length, so expanding can't fail
+	let _ = Hkdf::<Sha256>::new(Some(salt), input_key).expand(info, &mut key);
+	key
+}
+
+// PBKDF2-HMAC-SHA256 (RFC 8018) for a single output block
+pub(crate) fn pbkdf2_sha256(passphrase: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+	let mut key = [0u8; 32];
+	pbkdf2_hmac::<Sha256>(passphrase, salt, iterations, &mut key);
+	key
+}
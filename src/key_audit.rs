@@ -0,0 +1,110 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Key transparency self-audit
+// A malicious or compromised server could hand out handles with its own init keys instead of the ones of the user and
+// read the conversations started with them. Clients can catch this by fetching the own handle from the server from time
+// to time, like everyone else would, and checking it against the keys they generated. KeyAudit remembers every init key
+// the identity ever had (rotated keys can still be published for a while) and tells the client when an audit is due; the
+// client fetches the handle and passes it to audit, which returns a security event if it contains keys the client never
+// generated. The known keys are public and have to be persisted together with the identity.
+
+use std::collections::BTreeSet;
+use serde::{Serialize, Deserialize};
+use crate::codec::encode;
+use crate::device_counter::SecurityEvent;
+use crate::identity::Identity;
+use crate::parse_handle;
+use crate::DawnError;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct KeyAudit {
+	known_keys: BTreeSet<String>,
+	interval: u64, // seconds between audits
+	last_audit: Option<u64>,
+}
+
+// names of the init keys in a handle, in the order of parse_handle
+pub(crate) const HANDLE_KEYS: [&str; 5] = ["init_kyber", "init_curve", "init_curve_pfs_2", "init_kyber_for_salt", "init_curve_for_salt"];
+
+fn identity_keys(identity: &Identity) -> [String; 5] {
+	[
+		encode(&identity.init_pubkey_kyber),
+		encode(&identity.init_pubkey_curve),
+		encode(&identity.init_pubkey_curve_pfs_2),
+		encode(&identity.init_pubkey_kyber_for_salt),
+		encode(&identity.init_pubkey_curve_for_salt),
+	]
+}
+
+impl KeyAudit {
+	// start auditing the published keys of an identity every interval seconds
+	pub fn new(identity: &Identity, interval: u64) -> Self {
+		let mut audit = KeyAudit {
+			known_keys: BTreeSet::new(),
+			interval,
+			last_audit: None,
+		};
+		audit.remember(identity);
+		audit
+	}
+	
+	// remember the current init keys of the identity, has to be called after rotating them
+	pub fn remember(&mut self, identity: &Identity) {
+		self.known_keys.extend(identity_keys(identity));
+	}
+	
+	// returns true if the own handle should be fetched and audited
+	pub fn is_due(&self, now: u64) -> bool {
+		match self.last_audit {
+			Some(last_audit) => now.saturating_sub(last_audit) >= self.interval,
+			None => true
+		}
+	}
+	
+	// check the own handle as published by the server
+	// returns a security event if the handle contains init keys the identity never had
+	pub fn audit(&mut self, now: u64, identity: &Identity, published_handle: Vec<u8>) -> Result<Option<SecurityEvent>, DawnError> {
+		let (kyber, curve, curve_pfs_2, kyber_for_salt, curve_for_salt, _, _) = match parse_handle(published_handle) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.remember(identity);
+		self.last_audit = Some(now);
+		let unknown_keys = HANDLE_KEYS.iter().zip([kyber, curve, curve_pfs_2, kyber_for_salt, curve_for_salt])
+			.filter(|(_, key)| !self.known_keys.contains(&encode(key)))
+			.map(|(name, _)| name.to_string())
+			.collect::<Vec<String>>();
+		if unknown_keys.is_empty() { return Ok(None); }
+		Ok(Some(SecurityEvent::UnknownPublishedKeys { keys: unknown_keys }))
+	}
+	
+	pub fn to_bytes(&self) -> Result<Vec<u8>, DawnError> {
+		match serde_json::to_vec(self) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "json serialization failed")
+		}
+	}
+	
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, DawnError> {
+		match serde_json::from_slice::<KeyAudit>(bytes) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "key audit json parsing failed")
+		}
+	}
+}
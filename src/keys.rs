@@ -0,0 +1,243 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Typed keys
+// The low-level functions take all keys as byte slices, which makes it easy to pass a curve key where a kyber key
+// belongs. These wrappers give every kind of key its own type (with a fixed size where the algorithm defines one), so
+// the typed APIs built on top of them turn such mix-ups into compile errors. Secret keys are kept in SecretBytes.
+
+use std::fmt;
+use dawn_crypto::{kyber_keygen, curve_keygen, sign_keygen};
+use crate::secret::SecretBytes;
+use crate::DawnError;
+
+pub const KYBER_PUBLIC_KEY_LEN: usize = 1568;
+pub const KYBER_SECRET_KEY_LEN: usize = 3168;
+pub const KYBER_CIPHERTEXT_LEN: usize = 1568;
+pub const CURVE_PUBLIC_KEY_LEN: usize = 32;
+pub const CURVE_SECRET_KEY_LEN: usize = 32;
+
+// key with a size fixed by the algorithm
+macro_rules! fixed_size_key {
+	($name:ident, $len:expr) => {
+		#[derive(Clone, PartialEq, Eq)]
+		pub struct $name([u8; $len]);
+		
+		impl $name {
+			pub const LEN: usize = $len;
+			
+			pub fn from_bytes(bytes: &[u8]) -> Result<Self, DawnError> {
+				match <[u8; $len]>::try_from(bytes) {
+					Ok(res) => Ok($name(res)),
+					Err(_) => error!(&format!("{} must be {} bytes long, got {} bytes", stringify!($name), $len, bytes.len()))
+				}
+			}
+			
+			pub fn as_bytes(&self) -> &[u8] {
+				&self.0
+			}
+			
+			pub fn to_vec(&self) -> Vec<u8> {
+				self.0.to_vec()
+			}
+		}
+		
+		impl AsRef<[u8]> for $name {
+			fn as_ref(&self) -> &[u8] {
+				&self.0
+			}
+		}
+		
+		impl TryFrom<&[u8]> for $name {
+			type Error = DawnError;
+			
+			fn try_from(bytes: &[u8]) -> Result<Self, DawnError> {
+				$name::from_bytes(bytes)
+			}
+		}
+		
+		impl TryFrom<Vec<u8>> for $name {
+			type Error = DawnError;
+			
+			fn try_from(bytes: Vec<u8>) -> Result<Self, DawnError> {
+				$name::from_bytes(&bytes)
+			}
+		}
+		
+		impl fmt::Debug for $name {
+			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				write!(f, "{}({})", stringify!($name), crate::codec::encode(self.0))
+			}
+		}
+	}
+}
+
+// key whose size depends on the signature scheme used by dawn_crypto
+macro_rules! variable_size_key {
+	($name:ident) => {
+		#[derive(Clone, PartialEq, Eq)]
+		pub struct $name(Vec<u8>);
+		
+		impl $name {
+			pub fn from_bytes(bytes: &[u8]) -> Result<Self, DawnError> {
+				if bytes.is_empty() { error!(&format!("{} must not be empty", stringify!($name))); }
+				Ok($name(bytes.to_vec()))
+			}
+			
+			pub fn as_bytes(&self) -> &[u8] {
+				&self.0
+			}
+			
+			pub fn to_vec(&self) -> Vec<u8> {
+				self.0.clone()
+			}
+		}
+		
+		impl AsRef<[u8]> for $name {
+			fn as_ref(&self) -> &[u8] {
+				&self.0
+			}
+		}
+		
+		impl TryFrom<&[u8]> for $name {
+			type Error = DawnError;
+			
+			fn try_from(bytes: &[u8]) -> Result<Self, DawnError> {
+				$name::from_bytes(bytes)
+			}
+		}
+		
+		impl TryFrom<Vec<u8>> for $name {
+			type Error = DawnError;
+			
+			fn try_from(bytes: Vec<u8>) -> Result<Self, DawnError> {
+				if bytes.is_empty() { error!(&format!("{} must not be empty", stringify!($name))); }
+				Ok($name(bytes))
+			}
+		}
+		
+		impl fmt::Debug for $name {
+			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				write!(f, "{}({})", stringify!($name), crate::codec::encode(&self.0))
+			}
+		}
+	}
+}
+
+// secret key, optionally with a size fixed by the algorithm
+macro_rules! secret_key {
+	($name:ident, $len:expr) => {
+		secret_key!(@impl $name, Some($len));
+		
+		impl $name {
+			pub const LEN: usize = $len;
+		}
+	};
+	($name:ident) => {
+		secret_key!(@impl $name, None);
+	};
+	(@impl $name:ident, $len:expr) => {
+		#[derive(Clone, PartialEq, Eq)]
+		pub struct $name(SecretBytes);
+		
+		impl $name {
+			pub fn from_bytes(bytes: &[u8]) -> Result<Self, DawnError> {
+				if bytes.is_empty() { error!(&format!("{} must not be empty", stringify!($name))); }
+				let expected_len: Option<usize> = $len;
+				if let Some(len) = expected_len {
+					if bytes.len() != len { error!(&format!("{} must be {} bytes long, got {} bytes", stringify!($name), len, bytes.len())); }
+				}
+				Ok($name(SecretBytes::new(bytes)))
+			}
+			
+			pub fn as_bytes(&self) -> &[u8] {
+				self.0.as_bytes()
+			}
+			
+			pub fn to_vec(&self) -> Vec<u8> {
+				self.0.as_bytes().to_vec()
+			}
+			
+			// returns true if the key is locked into memory (only possible with the mlock feature)
+			pub fn is_locked(&self) -> bool {
+				self.0.is_locked()
+			}
+		}
+		
+		impl AsRef<[u8]> for $name {
+			fn as_ref(&self) -> &[u8] {
+				self.0.as_bytes()
+			}
+		}
+		
+		impl TryFrom<&[u8]> for $name {
+			type Error = DawnError;
+			
+			fn try_from(bytes: &[u8]) -> Result<Self, DawnError> {
+				$name::from_bytes(bytes)
+			}
+		}
+		
+		impl TryFrom<Vec<u8>> for $name {
+			type Error = DawnError;
+			
+			fn try_from(bytes: Vec<u8>) -> Result<Self, DawnError> {
+				$name::from_bytes(&bytes)
+			}
+		}
+		
+		impl fmt::Debug for $name {
+			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				write!(f, "{}(<redacted>)", stringify!($name))
+			}
+		}
+	};
+}
+
+fixed_size_key!(KyberPublicKey, KYBER_PUBLIC_KEY_LEN);
+fixed_size_key!(CurvePublicKey, CURVE_PUBLIC_KEY_LEN);
+variable_size_key!(SignPublicKey);
+secret_key!(KyberSecretKey, KYBER_SECRET_KEY_LEN);
+secret_key!(CurveSecretKey, CURVE_SECRET_KEY_LEN);
+secret_key!(SignSecretKey);
+
+// generate typed keypairs
+// these only fail if dawn_crypto returns keys of unexpected size
+pub fn gen_kyber_keypair() -> Result<(KyberPublicKey, KyberSecretKey), DawnError> {
+	let (pubkey, seckey) = kyber_keygen();
+	match (KyberPublicKey::try_from(pubkey), KyberSecretKey::try_from(seckey)) {
+		(Ok(pubkey), Ok(seckey)) => Ok((pubkey, seckey)),
+		_ => error!(Crypto, "kyber key generation returned keys of unexpected size")
+	}
+}
+
+pub fn gen_curve_keypair() -> Result<(CurvePublicKey, CurveSecretKey), DawnError> {
+	let (pubkey, seckey) = curve_keygen();
+	match (CurvePublicKey::try_from(pubkey), CurveSecretKey::try_from(seckey)) {
+		(Ok(pubkey), Ok(seckey)) => Ok((pubkey, seckey)),
+		_ => error!(Crypto, "curve key generation returned keys of unexpected size")
+	}
+}
+
+pub fn gen_sign_keypair() -> Result<(SignPublicKey, SignSecretKey), DawnError> {
+	let (pubkey, seckey) = sign_keygen();
+	match (SignPublicKey::try_from(pubkey), SignSecretKey::try_from(seckey)) {
+		(Ok(pubkey), Ok(seckey)) => Ok((pubkey, seckey)),
+		_ => error!(Crypto, "signature key generation returned empty keys")
+	}
+}
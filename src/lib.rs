@@ -16,28 +16,215 @@
 	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+// library code must not panic on any input
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::indexing_slicing))]
+// errors are propagated with explicit matches throughout the library
+#![allow(clippy::question_mark)]
+
 use dawn_crypto::*;
 use serde::{Serialize, Deserialize};
-use hex::{encode, decode};
-use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use crate::codec::{encode, decode, split_bytes};
 use crate::Message::*;
+use crate::device_counter::DeviceStamp;
+use crate::outgoing::MessageParts;
+use crate::init_request::InitRequestParams;
 
 // re-exports that can be directly used by the Dawn client
 pub use dawn_crypto::{init as init_crypto, kyber_keygen, curve_keygen, sign_keygen, id_gen, mdc_gen, predictable_mdc_gen, get_temp_id, get_custom_temp_id, get_next_id, derive_security_number, sym_key_gen, hash, get_current_timestamp, get_all_timestamps_since};
 
-mod content_type;
-mod event;
-
-#[cfg(test)]
-mod tests;
-
 // Error return macro
+// returns an InvalidInput error unless another variant of DawnError is given, e.g. error!(Crypto, "decryption failed")
 macro_rules! error{
 	($a:expr) => {
-		return Err(String::from("@dawn-stdlib: ") + &$a)
+		return Err($crate::DawnError::InvalidInput(String::from($a)))
+	};
+	($kind:ident, $a:expr) => {
+		return Err($crate::DawnError::$kind(String::from($a)))
 	}
 }
 
+mod error;
+mod codec;
+mod history;
+mod sync;
+mod limits;
+mod context;
+mod keys;
+mod init_request;
+mod identity;
+mod peer;
+mod media;
+mod signature;
+mod namespace;
+mod retention;
+mod fanout;
+mod warning;
+mod secret;
+mod estimate;
+mod bandwidth;
+mod data_saver;
+mod presence;
+mod thread;
+mod channel;
+mod import;
+mod gateway;
+mod application;
+mod armor;
+mod pairing;
+mod sharing;
+mod ceremony;
+mod device_counter;
+mod passive;
+mod profile;
+mod canonical;
+mod stream_hash;
+mod convergent;
+mod redact;
+mod stats;
+mod storage;
+mod ratchet;
+mod escrow;
+mod abuse_report;
+mod security_encoding;
+mod key_audit;
+mod validate;
+mod routing;
+mod fragment;
+mod clock;
+mod received;
+mod outgoing;
+mod kdf;
+mod client_key;
+mod domain;
+mod session;
+mod handle_report;
+mod session_store;
+mod close;
+mod chain;
+mod sender_chain;
+mod sequence;
+mod profile_update;
+mod wire_format;
+mod nickname;
+mod effect;
+mod version;
+mod compat;
+mod transcription;
+mod group;
+mod group_appearance;
+mod group_join;
+mod reaction;
+mod retraction;
+mod oversized;
+mod receipt;
+mod disappearing;
+mod reply;
+mod maintenance;
+mod prekey;
+mod registration;
+mod server_auth;
+mod pacing;
+mod config;
+mod conformance;
+pub mod capability;
+pub mod content_type;
+pub mod event;
+pub mod media_codec;
+
+pub use error::DawnError;
+pub use content_type::ContentType;
+pub use history::{HistoryEntry, HistoryReassembler, gen_history_chunks, HISTORY_MAX_PENDING, HISTORY_MAX_BYTES};
+pub use sync::{DeltaSync, SettingChange, gen_delta_sync, parse_delta_sync};
+pub use limits::{ParseLimits, ParseMode, DEFAULT_MAX_DECOMPRESSED_LEN};
+pub use config::{ProtocolConfig, SignaturePolicy, Padding};
+pub use conformance::{ConformanceReport, ConformanceResult, run_conformance, run_conformance_vectors};
+pub use context::{Context, BorrowedContent, OwnedContent};
+pub use keys::{KyberPublicKey, KyberSecretKey, CurvePublicKey, CurveSecretKey, SignPublicKey, SignSecretKey, gen_kyber_keypair, gen_curve_keypair, gen_sign_keypair};
+pub use init_request::{InitRequestBuilder, InitRequestResult, ParsedInitRequest, ParsedInitResponse, InitRequestPreview, peek_init_request};
+pub use identity::Identity;
+pub use peer::{Peer, Verification};
+pub use media::{wrap_media_key, unwrap_media_key, gen_media_delete_token, verify_media_delete_token, gen_linked_media_data, parse_linked_media_data, UploadTicket, gen_upload_ticket, parse_upload_ticket};
+pub use namespace::{Namespace, gen_namespaced_id, parse_namespaced_id, get_next_namespaced_id, get_namespaced_temp_id};
+pub use retention::{Retention, ExpiryNotice, gen_expiry_notice, parse_expiry_notice};
+pub use fanout::{InitFanout, FanoutAccept};
+pub use warning::{Warning, check_warning};
+pub use estimate::estimate_ciphertext_len;
+pub use bandwidth::BandwidthCounters;
+pub use data_saver::{DataSaverPolicy, MediaSend, send_file};
+pub use presence::{PresenceUpdate, PresenceAggregator, TypingEvent, gen_presence_update, parse_presence_update, gen_typing_started, gen_typing_stopped, parse_typing_event, TYPING_TIMEOUT, TYPING_REFRESH_INTERVAL};
+pub use thread::{ThreadIndex, thread_id_for};
+pub use channel::{ChannelAction, ChannelAdminState, gen_channel_action, verify_channel_action};
+pub use import::{ImportTarget, ImportedConversation, import_history, IMPORT_FORMAT_VERSION};
+pub use gateway::{GatewayOrigin, gen_gateway_envelope, parse_gateway_envelope};
+pub use application::{ApplicationPolicy, ApplicationIdentity, verify_application_identity};
+pub use armor::{armor_msg, dearmor_msg};
+pub use pairing::{Pairing, PairingRole, PairingKey, gen_pairing_code};
+pub use ceremony::{CeremonyShare, CeremonyTranscript, run_key_ceremony};
+pub use sharing::{split_backup_key, recover_backup_key};
+pub use device_counter::{DeviceCounter, CounterTracker, SecurityEvent};
+pub use passive::PassiveSession;
+pub use profile::ProtocolProfile;
+pub use canonical::{canonical_json, canonicalize_json, sign_document, verify_document};
+pub use stream_hash::{StreamHasher, HashVerifier};
+pub use convergent::{derive_convergent_key, encrypt_file_convergent, ConvergentUploads};
+pub use redact::{Redactor, RedactedMessage};
+pub use stats::{ConversationStats, StatsReport, MediaStat};
+pub use storage::{Storage, MemoryStorage, with_transaction, NAMESPACE_SESSIONS, NAMESPACE_PREKEYS, NAMESPACE_PEERS};
+pub use ratchet::{PendingSend, init_send_ratchet, send_msg_staged, confirm_sent, pending_sends, NAMESPACE_RATCHETS, NAMESPACE_OUTBOX};
+pub use escrow::{escrow_status, open_escrow, escrow_key_fingerprint};
+pub use abuse_report::{AbuseReport, gen_abuse_report, verify_abuse_report};
+pub use security_encoding::{SecurityEncodings, encode_security_number};
+pub use key_audit::KeyAudit;
+pub use validate::{Violation, OutgoingRules, validate_outgoing};
+pub use routing::{RoutingHeader, RoutingClass, read_routing_header};
+pub use fragment::{FragmentReassembler, fragment_msg, is_fragment, FRAGMENT_HEADER_LEN, FRAGMENT_TIMEOUT, FRAGMENT_MAX_PENDING, FRAGMENT_MAX_BYTES};
+pub use clock::{Clock, SystemClock, ManualClock};
+pub use received::{ReceivedMessage, ParsedMessage};
+pub use outgoing::{OutgoingMessage, SendOptions};
+pub use client_key::{ClientKeyPurpose, derive_client_key, CLIENT_KEY_LEN};
+pub use domain::{DomainLabel, LabelKind, domain_labels, check_domain_separation};
+pub use session::{Session, SessionStatus};
+pub use handle_report::{HandleReport, HandleKeyCheck, analyze_handle, estimate_qr_version};
+pub use session_store::{SessionStore, MemorySessionStore, StorageSessionStore};
+pub use close::{ConversationClose, gen_conversation_close, parse_conversation_close};
+pub use chain::{SendChain, ReceiveChain, DEFAULT_MAX_SKIPPED};
+pub use sequence::{SequenceCounter, SequenceTracker, SequenceGap};
+pub use wire_format::{WireFormat, BINARY_FORMAT_VERSION};
+pub use profile_update::{ProfileUpdate, ProfilePolicy, gen_profile_update, parse_profile_update, send_profile_update};
+pub use nickname::{NicknameProposal, NicknameEvent, gen_nickname_event, parse_nickname_event, resolve_display_name, MAX_NICKNAME_LEN};
+pub use effect::{Effect, KNOWN_EFFECTS, MAX_EFFECT_NAME_LEN};
+pub use version::{PROTOCOL_VERSION, SUPPORTED_VERSIONS, peer_versions, negotiate_version};
+pub use compat::{WireGeneration, parse_compat_msg};
+pub use transcription::{Transcription, MAX_LANGUAGE_TAG_LEN};
+pub use group::{Group, MembershipChange, rotate_group_keys, send_group_msg, parse_group_msg, send_membership_change, send_admin_change, parse_membership_change, send_group_appearance, approve_join_request, deny_join_request};
+pub use group_appearance::{GroupAppearance, GroupAvatar, MAX_GROUP_DESCRIPTION_LEN};
+pub use group_join::{GroupHandle, GroupJoinRequest, parse_group_handle, gen_group_join_request, parse_group_join_denial, MAX_JOIN_NOTE_LEN};
+pub use reaction::MAX_REACTION_LEN;
+pub use reply::{Reply, MAX_EXCERPT_LEN};
+pub use oversized::{OversizedMedia, MediaResendRequest, parse_media_resend_request};
+pub use disappearing::{DisappearingTimer, MAX_EXPIRES_AFTER, gen_disappearing_timer, parse_disappearing_timer};
+pub use receipt::{ReadReceipt, DeliveryReceipt, MAX_RECEIPT_MDCS, gen_read_receipt, parse_read_receipt, gen_delivery_receipt, parse_delivery_receipt};
+pub use maintenance::{MaintenancePolicy, MaintenanceState, MaintenanceTask, MaintenanceEvent, maintenance_events};
+pub use prekey::{PrekeyServer, PrekeyReconciliation, gen_prekeys, reconcile_prekeys};
+pub use registration::{RegistrationRequest, Registration, RegistrationConfirmation, gen_registration, verify_registration, gen_registration_confirmation, parse_registration_confirmation};
+pub use server_auth::{ServerChallenge, sign_server_challenge, verify_server_challenge};
+pub use pacing::{RateLimits, PacedEnvelope, SendPacer};
+#[cfg(feature = "file-storage")]
+pub use storage::FileStorage;
+#[cfg(feature = "sqlite")]
+pub use storage::SqliteStorage;
+
+#[cfg(test)]
+mod tests;
+#[cfg(test)]
+mod test_server;
+
+// decrypted content of a message in one of the wire formats
+enum MessageContent {
+	Json(String),
+	Binary(Vec<u8>),
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 enum Message {
 	InitRequest(InitRequest),
@@ -46,7 +233,12 @@ enum Message {
 	Internal(InternalMessage),
 	Voice(VoiceMessage),
 	Picture(PictureMessage),
-	LinkedMedia(LinkedMediaMessage)
+	LinkedMedia(LinkedMediaMessage),
+	HistorySync(HistorySyncMessage),
+	DeltaSync(DeltaSyncMessage),
+	Gateway(GatewayMessage),
+	Reaction(ReactionMessage),
+	Retraction(RetractionMessage)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -59,6 +251,10 @@ struct InitRequest {
 	name: String,
 	comment: String,
 	mdc_seed: String,
+	#[serde(default)]
+	capabilities: Vec<String>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -66,32 +262,93 @@ struct InitAccept {
 	kyber: String,
 	sign: String,
 	mdc: String,
+	#[serde(default)]
+	capabilities: Vec<String>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct TextMessage {
 	text: String,
 	mdc: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	thread_id: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	effect: Option<Effect>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	in_reply_to: Option<Reply>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	expires_after: Option<u64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceStamp>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	seq: Option<u64>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct InternalMessage {
 	event: u8,
-	event_data: String,
+	#[serde(with = "wire_format::binary_field")]
+	event_data: Vec<u8>,
 	mdc: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceStamp>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	seq: Option<u64>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct VoiceMessage {
-	voice: String,
+	#[serde(with = "wire_format::binary_field")]
+	voice: Vec<u8>,
 	mdc: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	thread_id: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	transcription: Option<Transcription>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	in_reply_to: Option<Reply>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	codec: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	expires_after: Option<u64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceStamp>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	seq: Option<u64>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct PictureMessage {
-	picture: String,
+	#[serde(with = "wire_format::binary_field")]
+	picture: Vec<u8>,
 	description: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	alt_text: Option<String>,
 	mdc: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	thread_id: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	effect: Option<Effect>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	in_reply_to: Option<Reply>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	codec: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	expires_after: Option<u64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceStamp>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	seq: Option<u64>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -100,11 +357,92 @@ struct LinkedMediaMessage {
 	media_link: String,
 	media_key: String,
 	description: String,
-	mdc: String
+	mdc: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	expires_at: Option<u64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	delete_token: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	thread_id: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceStamp>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	seq: Option<u64>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct HistorySyncMessage {
+	transfer_id: String,
+	chunk_index: u32,
+	chunk_count: u32,
+	#[serde(with = "wire_format::binary_field")]
+	chunk: Vec<u8>,
+	mdc: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceStamp>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	seq: Option<u64>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DeltaSyncMessage {
+	#[serde(with = "wire_format::binary_field")]
+	delta: Vec<u8>,
+	mdc: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceStamp>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	seq: Option<u64>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GatewayMessage {
+	envelope: String,
+	#[serde(default, skip_serializing_if = "Option::is_none", with = "wire_format::optional_binary_field")]
+	gateway_data: Option<Vec<u8>>,
+	mdc: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceStamp>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	seq: Option<u64>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ReactionMessage {
+	target: String,
+	reaction: String,
+	mdc: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceStamp>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	seq: Option<u64>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RetractionMessage {
+	target: String,
+	mdc: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceStamp>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	seq: Option<u64>,
+	#[serde(default = "version::legacy_version")]
+	protocol_version: u16,
 }
 
 // generate an init request using init id, init keys and own signature key
-// returns: (own kyber public key, own kyber secret key), (own curve public key, own curve secret key), pfs key, pfs salt, id, id salt, message detail code, encrypted message
+// kept for existing clients, InitRequestBuilder names every parameter
+#[allow(clippy::too_many_arguments)]
 pub fn gen_init_request(
 	remote_pubkey_kyber: &[u8],
 	remote_pubkey_kyber_for_salt: &[u8],
@@ -116,50 +454,54 @@ pub fn gen_init_request(
 	name: &str,
 	comment: &str,
 	mdc: &str
-) -> Result<
-	(
-		(Vec<u8>, Vec<u8>), // own kyber keypair
-		(Vec<u8>, Vec<u8>), // own curve keypair
-		Vec<u8>, // own pfs key
-		Vec<u8>, // remote pfs key
-		Vec<u8>, // pfs salt
-		String, // id
-		Vec<u8>, // id salt
-		String, // message detail code
-		String, // message detail code seed
-		Vec<u8> // encrypted message
-	), String> {
+) -> Result<InitRequestResult, DawnError> {
+	gen_init_request_with_params(&InitRequestParams { remote_pubkey_kyber, remote_pubkey_kyber_for_salt, remote_pubkey_curve, remote_pubkey_curve_pfs_2, remote_pubkey_curve_for_salt, own_pubkey_sig, own_seckey_sig, name, comment, mdc, id: None, media_codecs: &[] })
+}
+
+// generate an init request, optionally reusing an existing conversation id instead of a new one and announcing the media
+// codecs the client can decode (see media_codec.rs)
+// returns the same as gen_init_request
+pub(crate) fn gen_init_request_with_params(params: &InitRequestParams) -> Result<InitRequestResult, DawnError> {
+	let InitRequestParams { remote_pubkey_kyber, remote_pubkey_kyber_for_salt, remote_pubkey_curve, remote_pubkey_curve_pfs_2, remote_pubkey_curve_for_salt, own_pubkey_sig, own_seckey_sig, name, comment, mdc, id, media_codecs } = *params;
 	// check input
 	if name.is_empty() { error!("name must not be empty"); }
+	let capabilities = match media_codec::announced_capabilities(media_codecs) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
 	
 	let (
 		(own_pubkey_kyber, own_seckey_kyber),
 		(own_pubkey_curve, own_seckey_curve),
 		_,
 		(mut own_pubkey_curve_for_salt, own_seckey_curve_for_salt),
-		id
+		new_id
 	) = init();
+	let id = match id {
+		Some(id) => id.to_string(),
+		None => new_id
+	};
 	let (own_pubkey_curve_pfs_2, own_seckey_curve_pfs_2) = curve_keygen();
 	
 	let own_pfs_key = match get_curve_secret(&own_seckey_curve, remote_pubkey_curve) {
 		Ok(res) => res,
-		Err(err) => return Err(err)
+		Err(err) => return Err(DawnError::Crypto(err))
 	};
 	let remote_pfs_key = match get_curve_secret(&own_seckey_curve_pfs_2, remote_pubkey_curve_pfs_2) {
 		Ok(res) => res,
-		Err(err) => return Err(err)
+		Err(err) => return Err(DawnError::Crypto(err))
 	};
 	let derive_salt_curve = match get_curve_secret(&own_seckey_curve_for_salt, remote_pubkey_curve_for_salt) {
 		Ok(res) => res,
-		Err(err) => return Err(err)
+		Err(err) => return Err(DawnError::Crypto(err))
 	};
 	let (derive_salt_kyber, mut derive_salt_kyber_ciphertext) = match get_kyber_secret(remote_pubkey_kyber_for_salt) {
 		Ok(res) => res,
-		Err(_) => { error!("failed to get kyber secret for salt derivation"); }
+		Err(_) => { error!(Crypto, "failed to get kyber secret for salt derivation"); }
 	};
 	let (pfs_salt, id_salt) = match derive_salts(&derive_salt_curve, &derive_salt_kyber) {
 		Ok(res) => res,
-		Err(_) => { error!("failed to derive salts"); }
+		Err(_) => { error!(Crypto, "failed to derive salts"); }
 	};
 	
 	// generate an mdc seed for predictable message detail codes (necessary for subscription-based message transport)
@@ -174,17 +516,19 @@ pub fn gen_init_request(
 		sign: encode(own_pubkey_sig),
 		name: name.to_string(),
 		comment: comment.to_string(),
-		mdc_seed: mdc_seed.to_string()
+		mdc_seed: mdc_seed.to_string(),
+		capabilities,
+		protocol_version: PROTOCOL_VERSION
 	} );
 	let message = match serde_json::to_string(&message_data) {
 		Ok(res) => res,
-		Err(_) => error!("json serialization failed")
+		Err(_) => error!(Serialization, "json serialization failed")
 	};
 	
 	// encrypt using derived pfs key
 	let (mut msg_ciphertext, new_pfs_key) = match encrypt_msg(remote_pubkey_kyber, Some(own_seckey_sig), &own_pfs_key, &pfs_salt, &message) {
 		Ok(res) => res,
-		Err(err) => return Err(err)
+		Err(err) => return Err(DawnError::Crypto(err))
 	};
 	
 	// put the curve public keys and the kyber ciphertext for salts in front as it is needed to derive the pfs key
@@ -193,46 +537,72 @@ pub fn gen_init_request(
 	ciphertext.append(&mut derive_salt_kyber_ciphertext);
 	ciphertext.append(&mut msg_ciphertext);
 	
-	Ok(((own_pubkey_kyber, own_seckey_kyber), (own_pubkey_curve, own_seckey_curve), new_pfs_key, remote_pfs_key, pfs_salt, id, id_salt, mdc.to_string(), mdc_seed, ciphertext))
+	Ok(InitRequestResult {
+		own_pubkey_kyber,
+		own_seckey_kyber,
+		own_pubkey_curve,
+		own_seckey_curve,
+		own_pfs_key: new_pfs_key,
+		remote_pfs_key,
+		pfs_salt,
+		id,
+		id_salt,
+		mdc: mdc.to_string(),
+		mdc_seed,
+		ciphertext,
+	})
 }
 
 // parse an init request
-// returns id, id salt, mdc, keys, pfs salt, name and comment
-pub fn parse_init_request(request_body: &[u8], own_seckey_kyber: &[u8], own_seckey_curve: &[u8], own_seckey_curve_pfs_2: &[u8], own_seckey_kyber_for_salt: &[u8], own_seckey_curve_for_salt: &[u8]) -> Result<(String, Vec<u8>, String, Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, String, String, String), String> {
+pub fn parse_init_request(request_body: &[u8], own_seckey_kyber: &[u8], own_seckey_curve: &[u8], own_seckey_curve_pfs_2: &[u8], own_seckey_kyber_for_salt: &[u8], own_seckey_curve_for_salt: &[u8]) -> Result<ParsedInitRequest, DawnError> {
 	// check length
-	if request_body.len() <= 32*2 + 1568 { error!("request was too short!"); }
+	if request_body.len() <= keys::CURVE_PUBLIC_KEY_LEN*2 + keys::KYBER_CIPHERTEXT_LEN { error!("request was too short!"); }
 	
-	let (remote_pubkey_curve, request_rest) = request_body.split_at(32);
-	let (remote_pubkey_curve_for_salt, request_rest) = request_rest.split_at(32);
-	let (remote_kyber_ciphertext_for_salt, ciphertext) = request_rest.split_at(1568);
+	let (remote_pubkey_curve, request_rest) = match split_bytes(request_body, keys::CURVE_PUBLIC_KEY_LEN) {
+		Some(res) => res,
+		None => error!("request was too short!")
+	};
+	let (remote_pubkey_curve_for_salt, request_rest) = match split_bytes(request_rest, keys::CURVE_PUBLIC_KEY_LEN) {
+		Some(res) => res,
+		None => error!("request was too short!")
+	};
+	let (remote_kyber_ciphertext_for_salt, ciphertext) = match split_bytes(request_rest, keys::KYBER_CIPHERTEXT_LEN) {
+		Some(res) => res,
+		None => error!("request was too short!")
+	};
 	
 	let remote_pfs_key = match get_curve_secret(own_seckey_curve, remote_pubkey_curve) {
 		Ok(res) => res,
-		Err(err) => return Err(err)
+		Err(err) => return Err(DawnError::Crypto(err))
 	};
 	let derive_salt_curve = match get_curve_secret(own_seckey_curve_for_salt, remote_pubkey_curve_for_salt) {
 		Ok(res) => res,
-		Err(err) => return Err(err)
+		Err(err) => return Err(DawnError::Crypto(err))
 	};
 	let derive_salt_kyber = match decrypt_kyber_secret(remote_kyber_ciphertext_for_salt, own_seckey_kyber_for_salt) {
 		Ok(res) => res,
-		Err(_) => { error!("failed to decrypt kyber secret for salt derivation"); }
+		Err(_) => { error!(Crypto, "failed to decrypt kyber secret for salt derivation"); }
 	};
 	let (pfs_salt, id_salt) = match derive_salts(&derive_salt_curve, &derive_salt_kyber) {
 		Ok(res) => res,
-		Err(_) => { error!("failed to derive salts"); }
+		Err(_) => { error!(Crypto, "failed to derive salts"); }
 	};
 	
 	// decrypt
 	let (msg_content, new_remote_pfs_key, _) = match decrypt_msg(own_seckey_kyber, None, &remote_pfs_key, &pfs_salt, ciphertext) {
 		Ok(res) => res,
-		Err(err) => return Err(err)
+		Err(err) => return Err(DawnError::Crypto(err))
 	};
 	
-	// parse
+	// parse, dispatching on the protocol version first (see version.rs)
+	match version::message_version(&msg_content) {
+		Ok(1) => (),
+		Ok(version) => return Err(DawnError::UnsupportedVersion(version)),
+		Err(err) => return Err(err)
+	}
 	let message = match serde_json::from_str::<Message>(&msg_content) {
 		Ok(res) => res,
-		Err(_) => error!("json parsing failed")
+		Err(_) => error!(Serialization, "json parsing failed")
 	};
 	
 	let init_request = match message {
@@ -240,32 +610,50 @@ pub fn parse_init_request(request_body: &[u8], own_seckey_kyber: &[u8], own_seck
 		_ => error!("content did not match init request type")
 	};
 	
-	let remote_pubkey_kyber = match decode(&init_request.kyber) {
+	let peer = match Peer::from_encoded(&init_request.kyber, &init_request.sign, &init_request.name, init_request.capabilities) {
 		Ok(res) => res,
-		Err(_) => error!("remote kyber pubkey invalid")
+		Err(err) => return Err(err)
 	};
 	let remote_pubkey_curve_pfs_2 = match decode(&init_request.curve_for_pfs) {
 		Ok(res) => res,
 		Err(_) => error!("remote curve pubkey invalid")
 	};
-	let remote_pubkey_sig = match decode(&init_request.sign) {
-		Ok(res) => res,
-		Err(_) => error!("remote signature pubkey invalid")
-	};
 	
 	// derive own pfs key
 	let own_pfs_key = match get_curve_secret(own_seckey_curve_pfs_2, &remote_pubkey_curve_pfs_2) {
 		Ok(res) => res,
-		Err(err) => return Err(err)
+		Err(err) => return Err(DawnError::Crypto(err))
 	};
 	
-	Ok((init_request.id, id_salt, init_request.mdc, remote_pubkey_kyber, remote_pubkey_sig, own_pfs_key, new_remote_pfs_key, pfs_salt, init_request.name, init_request.comment, init_request.mdc_seed))
+	Ok(ParsedInitRequest {
+		id: init_request.id,
+		id_salt,
+		mdc: init_request.mdc,
+		peer,
+		own_pfs_key,
+		remote_pfs_key: new_remote_pfs_key,
+		pfs_salt,
+		comment: init_request.comment,
+		mdc_seed: init_request.mdc_seed,
+	})
 }
 
+// new pfs key, own kyber keypair, message detail code and ciphertext of an accepted init request
+pub(crate) type AcceptedInit = (Vec<u8>, (Vec<u8>, Vec<u8>), String, Vec<u8>);
+
 // accept init request
 // returns the new PFS key, own kyber keypair, message detail code and ciphertext
-pub fn accept_init_request(own_pubkey_sig: &[u8], own_seckey_sig: &[u8], remote_pubkey_kyber: &[u8], pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<(Vec<u8>, (Vec<u8>, Vec<u8>), String, Vec<u8>), String> {
-	
+pub fn accept_init_request(own_pubkey_sig: &[u8], own_seckey_sig: &[u8], remote_pubkey_kyber: &[u8], pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<AcceptedInit, DawnError> {
+	accept_init_request_with_codecs(&[], own_pubkey_sig, own_seckey_sig, remote_pubkey_kyber, pfs_key, pfs_salt, id, mdc_seed)
+}
+
+// accept init request, announcing the media codecs the client can decode (see media_codec.rs)
+// returns the same as accept_init_request
+pub fn accept_init_request_with_codecs(media_codecs: &[&str], own_pubkey_sig: &[u8], own_seckey_sig: &[u8], remote_pubkey_kyber: &[u8], pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<AcceptedInit, DawnError> {
+	let capabilities = match media_codec::announced_capabilities(media_codecs) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
 	let mdc = predictable_mdc_gen(mdc_seed, id);
 	let (own_pubkey_kyber, own_seckey_kyber) = kyber_keygen();
 	
@@ -273,16 +661,18 @@ pub fn accept_init_request(own_pubkey_sig: &[u8], own_seckey_sig: &[u8], remote_
 		kyber: encode(&own_pubkey_kyber),
 		sign: encode(own_pubkey_sig),
 		mdc: mdc.clone(),
+		capabilities,
+		protocol_version: PROTOCOL_VERSION,
 	} );
 	let message = match serde_json::to_string(&message_data) {
 		Ok(res) => res,
-		Err(_) => error!("json serialization failed")
+		Err(_) => error!(Serialization, "json serialization failed")
 	};
 	
 	// encrypt message
 	let (msg_ciphertext, new_pfs_key) = match encrypt_msg(remote_pubkey_kyber, Some(own_seckey_sig), pfs_key, pfs_salt, &message) {
 		Ok(res) => res,
-		Err(err) => return Err(err)
+		Err(err) => return Err(DawnError::Crypto(err))
 	};
 	
 	Ok((new_pfs_key, (own_pubkey_kyber, own_seckey_kyber), mdc, msg_ciphertext))
@@ -290,21 +680,25 @@ pub fn accept_init_request(own_pubkey_sig: &[u8], own_seckey_sig: &[u8], remote_
 
 // parse init response message (expected to be the first message on a new ID after an init request was sent)
 // As of now, only accept messages are sent. If the user rejects the request, no message is sent. Therefore, we only try to parse init accept messages.
-// returns remote kyber and signature pubkeys, the new PFS key and message detail code
-pub fn parse_init_response(msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>, String), String> {
+// The name of the peer is not part of the response, it has to be passed in from the handle the request was sent to.
+pub fn parse_init_response(msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], remote_name: &str) -> Result<ParsedInitResponse, DawnError> {
 	// decrypt
 	let (msg_content, new_pfs_key, warning) = match decrypt_msg(own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, msg_ciphertext) {
 		Ok(res) => res,
-		Err(err) => return Err(err)
+		Err(err) => return Err(DawnError::Crypto(err))
 	};
-	if warning != warning::NONE && remote_pubkey_sig.is_some() {
-		error!("CRITICAL: signature verification was requested, but the remote side did not provide a signature");
-	}
+	let warning = Warning::from_code(warning);
+	if let Err(err) = check_warning(warning, remote_pubkey_sig) { return Err(err); }
 	
-	// parse
+	// parse, dispatching on the protocol version first (see version.rs)
+	match version::message_version(&msg_content) {
+		Ok(1) => (),
+		Ok(version) => return Err(DawnError::UnsupportedVersion(version)),
+		Err(err) => return Err(err)
+	}
 	let message = match serde_json::from_str::<Message>(&msg_content) {
 		Ok(res) => res,
-		Err(_) => error!("json parsing failed")
+		Err(_) => error!(Serialization, "json parsing failed")
 	};
 	
 	let init_accept = match message {
@@ -312,106 +706,576 @@ pub fn parse_init_response(msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remot
 		_ => error!("content did not match init accept type")
 	};
 	
-	let remote_pubkey_kyber = match decode(&init_accept.kyber) {
-		Ok(res) => res,
-		Err(_) => error!("remote kyber pubkey invalid")
-	};
-	let remote_pubkey_sig = match decode(&init_accept.sign) {
+	let peer = match Peer::from_encoded(&init_accept.kyber, &init_accept.sign, remote_name, init_accept.capabilities) {
 		Ok(res) => res,
-		Err(_) => error!("remote signature pubkey invalid")
+		Err(err) => return Err(err)
 	};
 	
-	Ok((remote_pubkey_kyber, remote_pubkey_sig, new_pfs_key, init_accept.mdc))
+	Ok(ParsedInitResponse {
+		peer,
+		new_pfs_key,
+		mdc: init_accept.mdc,
+		warning,
+	})
 }
 
 // parse a received message
-// returns content type, content (can be a string, a Vec or both depending on the message type), new PFS key and message detail code
-pub fn parse_msg(msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8]) -> Result<((u8, Option<String>, Option<Vec<u8>>), Vec<u8>, String), String> {
+// returns the message, new PFS key, message detail code and the warning that came with the message
+pub fn parse_msg(msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8]) -> Result<(ReceivedMessage, Vec<u8>, String, Warning), DawnError> {
+	parse_msg_limited(msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, &ParseLimits::default())
+}
+
+// parse a message received with the given message detail code (the one the client fetched or subscribed to)
+// messages carrying another message detail code are rejected, so a server can't move a message into another conversation
+// returns the same as parse_msg
+pub fn parse_msg_for_mdc(msg_ciphertext: &[u8], expected_mdc: &str, own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8]) -> Result<(ReceivedMessage, Vec<u8>, String, Warning), DawnError> {
+	// the routing header allows rejecting the message before decrypting it
+	match read_routing_header(msg_ciphertext) {
+		Ok(Some(header)) if header.mdc != expected_mdc => return Err(DawnError::MdcMismatch { expected: expected_mdc.to_string(), received: header.mdc }),
+		Ok(_) => (),
+		Err(err) => return Err(err)
+	}
+	let (message, new_pfs_key, mdc, warning) = match parse_msg(msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if mdc != expected_mdc { return Err(DawnError::MdcMismatch { expected: expected_mdc.to_string(), received: mdc }); }
+	Ok((message, new_pfs_key, mdc, warning))
+}
+
+// parse a received message, rejecting it early if it exceeds the given limits (see ParseLimits::low_memory)
+// returns the same as parse_msg
+pub fn parse_msg_limited(msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], limits: &ParseLimits) -> Result<(ReceivedMessage, Vec<u8>, String, Warning), DawnError> {
+	match parse_msg_with_config(msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, &ProtocolConfig::new().limits(*limits)) {
+		Ok(parsed) => Ok((parsed.message, parsed.new_pfs_key, parsed.mdc, parsed.warning)),
+		Err(err) => Err(err)
+	}
+}
+
+// parse a received message according to the configuration (limits, parse mode, signature policy and compression)
+// returns the message together with everything it came with (see ParsedMessage)
+pub fn parse_msg_with_config(msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], config: &ProtocolConfig) -> Result<ParsedMessage, DawnError> {
+	parse_msg_into(msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, config, None)
+}
+
+// parse a received message, reusing the buffers of the context for the binary content of the message
+// returns content type, text and data (in the form of ReceivedMessage::into_content), new PFS key, message detail code and warning
+// the binary content borrows from the context and is only valid until the context is used again
+pub fn parse_msg_with_context<'a>(context: &'a mut Context, msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], limits: &ParseLimits) -> Result<(BorrowedContent<'a>, Vec<u8>, String, Warning), DawnError> {
+	let parsed = match parse_msg_into(msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, &ProtocolConfig::new().limits(*limits), None) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	// the binary content is copied into the buffer of the context, which keeps its allocation, the caller borrows it from there
+	let (content_type, text, data) = parsed.message.into_content();
+	let has_data = data.is_some();
+	if let Some(data) = data {
+		context.data_buffer.clear();
+		context.data_buffer.extend_from_slice(&data);
+	}
+	Ok(((content_type, text, if has_data { Some(&context.data_buffer[..]) } else { None }), parsed.new_pfs_key, parsed.mdc, parsed.warning))
+}
+
+// parse a message sent on a message chain, in any order (see chain.rs)
+// a message that arrives before the next one in order is held by the chain and returns None, parse_held_chain_msg
+// decrypts held messages once the missing ones arrived
+// the chain only changes if the message was decrypted or held
+// returns the message, message detail code and warning
+pub fn parse_chain_msg(chain: &mut ReceiveChain, msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_salt: &[u8], config: &ProtocolConfig) -> Result<Option<(ReceivedMessage, String, Warning)>, DawnError> {
+	match receive_chain_msg(chain, msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_salt, config) {
+		Ok(parsed) => Ok(parsed.map(|parsed| (parsed.message, parsed.mdc, parsed.warning))),
+		Err(err) => Err(err)
+	}
+}
+
+// decrypt the next held message of a message chain, if the messages before it arrived
+// held candidates that fail to decrypt (forged or damaged messages) are dropped
+// returns the same as parse_chain_msg
+pub fn parse_held_chain_msg(chain: &mut ReceiveChain, own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_salt: &[u8], config: &ProtocolConfig) -> Result<Option<(ReceivedMessage, String, Warning)>, DawnError> {
+	match receive_held_chain_msg(chain, own_seckey_kyber, remote_pubkey_sig, pfs_salt, config) {
+		Ok(parsed) => Ok(parsed.map(|(parsed, _)| (parsed.message, parsed.mdc, parsed.warning))),
+		Err(err) => Err(err)
+	}
+}
+
+// parse_chain_msg, returning the message with everything it came with
+pub(crate) fn receive_chain_msg(chain: &mut ReceiveChain, msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_salt: &[u8], config: &ProtocolConfig) -> Result<Option<ParsedMessage>, DawnError> {
+	let counter = match read_chain_counter(msg_ciphertext) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if counter != chain.counter() {
+		// a held message can only be checked once the ratchet reached its pfs key, so only its size is checked here
+		if let Err(err) = limits::check_ciphertext(msg_ciphertext, &config.limits) { return Err(err); }
+		if let Err(err) = chain.hold(counter, msg_ciphertext) { return Err(err); }
+		return Ok(None);
+	}
+	parse_next_chain_msg(chain, msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_salt, config).map(Some)
+}
+
+// parse_held_chain_msg, returning the message with everything it came with and the length of its ciphertext
+pub(crate) fn receive_held_chain_msg(chain: &mut ReceiveChain, own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_salt: &[u8], config: &ProtocolConfig) -> Result<Option<(ParsedMessage, usize)>, DawnError> {
+	while let Some(msg_ciphertext) = chain.take_next() {
+		if let Ok(res) = parse_next_chain_msg(chain, &msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_salt, config) {
+			return Ok(Some((res, msg_ciphertext.len())));
+		}
+	}
+	Ok(None)
+}
+
+// decrypt another message with the counter of the last message the chain decrypted, without changing the chain
+// only a clone of the sending session sends such a message (see device_counter.rs)
+// returns None if the message has another counter, is the decrypted message again or does not decrypt
+pub(crate) fn parse_previous_chain_msg(chain: &ReceiveChain, msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_salt: &[u8], config: &ProtocolConfig) -> Option<ParsedMessage> {
+	let counter = match read_chain_counter(msg_ciphertext) {
+		Ok(res) => res,
+		Err(_) => return None
+	};
+	match chain.previous_pfs_key(counter, msg_ciphertext) {
+		Some(pfs_key) => parse_msg_into(msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, config, Some(counter)).ok(),
+		None => None
+	}
+}
+
+// counter of a chained message from its routing header
+fn read_chain_counter(msg_ciphertext: &[u8]) -> Result<u64, DawnError> {
+	match read_routing_header(msg_ciphertext) {
+		Ok(Some(RoutingHeader { counter: Some(counter), .. })) => Ok(counter),
+		Ok(_) => error!("message is not part of a message chain"),
+		Err(err) => Err(err)
+	}
+}
+
+// decrypt the next message in order of a message chain with the pfs key of the chain and advance the ratchet
+fn parse_next_chain_msg(chain: &mut ReceiveChain, msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_salt: &[u8], config: &ProtocolConfig) -> Result<ParsedMessage, DawnError> {
+	let parsed = match parse_msg_into(msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, chain.pfs_key(), pfs_salt, config, Some(chain.counter())) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if let Err(err) = chain.advance(&parsed.new_pfs_key, msg_ciphertext) { return Err(err); }
+	Ok(parsed)
+}
+
+// parse a received message, decoding its binary content (if any) in the buffer data
+// counter is the counter of chained messages, which the routing header and the sequence number have to match
+fn parse_msg_into(msg_ciphertext: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], config: &ProtocolConfig, counter: Option<u64>) -> Result<ParsedMessage, DawnError> {
+	let limits = &config.limits;
+	if let Err(err) = limits::check_ciphertext(msg_ciphertext, limits) { return Err(err); }
+	
+	// the routing header is checked against the message after decryption
+	let (header, msg_ciphertext) = match routing::split_routing_header(msg_ciphertext) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	
+	// escrowed messages carry the escrow copy in front of the ciphertext (see escrow_status)
+	let (escrow_parts, msg_ciphertext) = match escrow::split_escrow(msg_ciphertext) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	
+	// binary messages are recognized by their magic (see wire_format.rs), everything else is JSON
+	let binary_parts = match wire_format::split_binary(msg_ciphertext) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	
 	// decrypt
-	let (msg_content, new_pfs_key, warning) = match decrypt_msg(own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, msg_ciphertext) {
+	let decrypted = match binary_parts {
+		Some((inner_ciphertext, payload)) => wire_format::open_binary(inner_ciphertext, payload, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, config.compression, limits.max_decompressed_len).map(|(content, new_pfs_key, warning)| (MessageContent::Binary(content), new_pfs_key, warning)),
+		None => match decrypt_msg(own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, msg_ciphertext) {
+			Ok((content, new_pfs_key, warning)) => Ok((MessageContent::Json(content), new_pfs_key, warning)),
+			Err(_) => error!(Crypto, "decryption failed")
+		}
+	};
+	let (msg_content, new_pfs_key, warning) = match decrypted {
 		Ok(res) => res,
-		Err(_) => error!("decryption failed")
+		Err(err) => return Err(err)
 	};
-	if warning != warning::NONE && remote_pubkey_sig.is_some() {
-		error!("CRITICAL: signature verification was requested, but the remote side did not provide a signature");
+	let warning = Warning::from_code(warning);
+	if config.signature_policy == SignaturePolicy::Required {
+		if let Err(err) = check_warning(warning, remote_pubkey_sig) { return Err(err); }
 	}
 	
+	let parsed = match &msg_content {
+		MessageContent::Json(msg_content) => parse_message_content(msg_content, limits),
+		MessageContent::Binary(msg_content) => parse_binary_message_content(msg_content, limits)
+	};
+	let ParsedContent { message, mdc, thread_id, device, seq } = match parsed {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if counter.is_some() && seq != counter { error!("sequence number does not match the message chain"); }
+	if let Some(header) = header {
+		if let Err(err) = routing::check_routing_header(&header, message.content_type(), &mdc, thread_id.is_some(), escrow_parts.is_some(), warning, counter) { return Err(err); }
+	}
+	else if counter.is_some() { error!("chained message is missing its routing header"); }
+	Ok(ParsedMessage { message, new_pfs_key, mdc, warning, thread_id, seq, device })
+}
+
+// a decrypted message with everything it came with
+pub(crate) struct ParsedContent {
+	pub(crate) message: ReceivedMessage,
+	pub(crate) mdc: String,
+	pub(crate) thread_id: Option<String>,
+	pub(crate) device: Option<DeviceStamp>,
+	pub(crate) seq: Option<u64>,
+}
+
+// parse a decrypted message
+pub(crate) fn parse_message_content(msg_content: &str, limits: &ParseLimits) -> Result<ParsedContent, DawnError> {
+	// dispatch on the protocol version before the rest of the message is parsed (see version.rs)
+	match version::message_version(msg_content) {
+		Ok(1) => (),
+		Ok(version) => return Err(DawnError::UnsupportedVersion(version)),
+		Err(err) => return Err(err)
+	}
+	
+	// check the field sizes before the fields get allocated
+	if let Err(err) = limits::check_fields(msg_content, limits) { return Err(err); }
+	
 	// parse
-	let message = match serde_json::from_str::<Message>(&msg_content) {
+	let message = match serde_json::from_str::<Message>(msg_content) {
 		Ok(res) => res,
-		Err(_) => error!("json parsing failed")
+		Err(_) => error!(Serialization, "json parsing failed")
 	};
+	if let Err(err) = limits::check_unknown_fields::<Message>(msg_content, limits) { return Err(err); }
+	message_content(message)
+}
+
+// parse a decrypted binary message (see wire_format.rs)
+fn parse_binary_message_content(msg_content: &[u8], limits: &ParseLimits) -> Result<ParsedContent, DawnError> {
+	// dispatch on the protocol version before the rest of the message is parsed (see version.rs)
+	match version::binary_message_version(msg_content) {
+		Ok(1) => (),
+		Ok(version) => return Err(DawnError::UnsupportedVersion(version)),
+		Err(err) => return Err(err)
+	}
+	
+	// check the field sizes before the fields get allocated
+	if let Err(err) = limits::check_binary_fields(msg_content, limits) { return Err(err); }
 	
-	let (content, mdc) = match message {
-		Text(msg) => ((content_type::TEXT, Some(msg.text), None::<Vec<u8>>), msg.mdc),
-		Internal(msg) => ((content_type::INTERNAL, Some(msg.event_data), None), msg.mdc),
+	// parse
+	let message = match ciborium::from_reader::<Message, _>(msg_content) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "cbor parsing failed")
+	};
+	if let Err(err) = limits::check_unknown_binary_fields::<Message>(msg_content, limits) { return Err(err); }
+	message_content(message)
+}
+
+// turn a parsed message into a ReceivedMessage
+fn message_content(message: Message) -> Result<ParsedContent, DawnError> {
+	let (message, mdc, thread_id, device, seq) = match message {
+		Text(msg) => {
+			if let Some(Err(err)) = msg.in_reply_to.as_ref().map(Reply::check) { return Err(err); }
+			if let Some(Err(err)) = msg.expires_after.map(disappearing::check_expires_after) { return Err(err); }
+			(ReceivedMessage::Text { text: msg.text, effect: msg.effect, in_reply_to: msg.in_reply_to, expires_after: msg.expires_after }, msg.mdc, msg.thread_id, msg.device, msg.seq)
+		},
+		Internal(msg) => {
+			(ReceivedMessage::Internal { event: msg.event, data: msg.event_data }, msg.mdc, None, msg.device, msg.seq)
+		},
 		Voice(msg) => {
-			let msg_bytes = BASE64.decode(&msg.voice);
-			if msg_bytes.is_err() { error!("voice message data invalid"); }
-			((content_type::VOICE, None::<String>, Some(msg_bytes.unwrap())), msg.mdc)
+			if let Some(Err(err)) = msg.in_reply_to.as_ref().map(Reply::check) { return Err(err); }
+			if let Some(Err(err)) = msg.codec.as_deref().map(media_codec::check_codec) { return Err(err); }
+			if let Some(Err(err)) = msg.expires_after.map(disappearing::check_expires_after) { return Err(err); }
+			(ReceivedMessage::Voice { data: msg.voice, transcription: msg.transcription, in_reply_to: msg.in_reply_to, codec: msg.codec, expires_after: msg.expires_after }, msg.mdc, msg.thread_id, msg.device, msg.seq)
 		},
 		Picture(msg) => {
-			let msg_bytes = BASE64.decode(&msg.picture);
-			if msg_bytes.is_err() { error!("picture data invalid"); }
-			((content_type::PICTURE, Some(msg.description), Some(msg_bytes.unwrap())), msg.mdc)
+			if let Some(Err(err)) = msg.in_reply_to.as_ref().map(Reply::check) { return Err(err); }
+			if let Some(Err(err)) = msg.codec.as_deref().map(media_codec::check_codec) { return Err(err); }
+			if let Some(Err(err)) = msg.expires_after.map(disappearing::check_expires_after) { return Err(err); }
+			(ReceivedMessage::Picture { data: msg.picture, description: msg.description, alt_text: msg.alt_text, effect: msg.effect, in_reply_to: msg.in_reply_to, codec: msg.codec, expires_after: msg.expires_after }, msg.mdc, msg.thread_id, msg.device, msg.seq)
+		},
+		LinkedMedia(msg) => {
+			let delete_token = match msg.delete_token.as_ref().map(decode) {
+				Some(Ok(res)) => Some(res),
+				Some(Err(_)) => error!("linked media delete token invalid"),
+				None => None
+			};
+			let message = ReceivedMessage::LinkedMedia {
+				link: msg.media_link,
+				key: msg.media_key,
+				description: msg.description,
+				media_type: msg.media_type,
+				expires_at: msg.expires_at,
+				delete_token,
+			};
+			(message, msg.mdc, msg.thread_id, msg.device, msg.seq)
+		},
+		HistorySync(msg) => {
+			let message = ReceivedMessage::HistorySync {
+				transfer_id: msg.transfer_id,
+				chunk_index: msg.chunk_index,
+				chunk_count: msg.chunk_count,
+				chunk: msg.chunk,
+			};
+			(message, msg.mdc, None, msg.device, msg.seq)
+		},
+		DeltaSync(msg) => {
+			(ReceivedMessage::DeltaSync { delta: msg.delta }, msg.mdc, None, msg.device, msg.seq)
+		},
+		Gateway(msg) => {
+			(ReceivedMessage::Gateway { envelope: msg.envelope, data: msg.gateway_data }, msg.mdc, None, msg.device, msg.seq)
+		},
+		Reaction(msg) => {
+			if let Err(err) = reaction::check_reaction(&msg.target, &msg.reaction) { return Err(err); }
+			(ReceivedMessage::Reaction { target: msg.target, reaction: msg.reaction }, msg.mdc, None, msg.device, msg.seq)
+		},
+		Retraction(msg) => {
+			if let Err(err) = retraction::check_retraction(&msg.target) { return Err(err); }
+			(ReceivedMessage::Retraction { target: msg.target }, msg.mdc, None, msg.device, msg.seq)
 		},
-		LinkedMedia(msg) => ((content_type::LINKED_MEDIA, Some(msg.media_link + "\n" + &msg.media_key + "\n" + &msg.description), Some(vec![msg.media_type])), msg.mdc),
 		_ => error!("message type not known or unexpected init message")
 	};
 	
-	Ok((content, new_pfs_key, mdc))
+	Ok(ParsedContent { message, mdc, thread_id, device, seq })
 }
 
 // send a message
 // returns new PFS key, message detail code and ciphertext
-pub fn send_msg((msg_type, msg_text, msg_data): (u8, Option<&str>, Option<&[u8]>), remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<(Vec<u8>, String, Vec<u8>), String> {
+pub fn send_msg(content: (ContentType, Option<&str>, Option<&[u8]>), remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<(Vec<u8>, String, Vec<u8>), DawnError> {
+	send_msg_into(MessageParts::from_content(content), &SendOptions::default(), &predictable_mdc_gen(mdc_seed, id), remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt, &mut Vec::new())
+}
+
+// send a message with its effect, alt text, reply, codec and expiry as set on the OutgoingMessage
+// the options decide how it is sent (wire format, configuration, thread, escrow copy, device counter and sequence number)
+// returns the same as send_msg
+pub fn send_msg_with_options(message: &OutgoingMessage, options: &SendOptions, remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<(Vec<u8>, String, Vec<u8>), DawnError> {
+	send_msg_into(message.parts(), options, &predictable_mdc_gen(mdc_seed, id), remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt, &mut Vec::new())
+}
+
+// send a message, reusing the buffers of the context for serialization
+// returns the same as send_msg
+pub fn send_msg_with_context(context: &mut Context, content: (ContentType, Option<&str>, Option<&[u8]>), remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<(Vec<u8>, String, Vec<u8>), DawnError> {
+	send_msg_into(MessageParts::from_content(content), &SendOptions::default(), &predictable_mdc_gen(mdc_seed, id), remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt, &mut context.serialization_buffer)
+}
+
+// send a message on a message chain, so the receiver can hold it until earlier messages that are missing arrive (see chain.rs)
+// the chain only advances if the message was encrypted successfully
+// returns message detail code and ciphertext
+pub fn send_chain_msg(chain: &mut SendChain, message: &OutgoingMessage, options: &SendOptions, remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<(String, Vec<u8>), DawnError> {
+	send_chain_parts(chain, message.parts(), options, remote_pubkey_kyber, own_seckey_sig, pfs_salt, &predictable_mdc_gen(mdc_seed, id))
+}
+
+// send_chain_msg for the parts of a message
+// the sequence number of a chained message is its counter
+pub(crate) fn send_chain_parts(chain: &mut SendChain, parts: MessageParts, options: &SendOptions, remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_salt: &[u8], mdc: &str) -> Result<(String, Vec<u8>), DawnError> {
+	let options = SendOptions {
+		seq: Some(chain.counter()),
+		counter: Some(chain.counter()),
+		..options.clone()
+	};
+	let (new_pfs_key, mdc, ciphertext) = match send_msg_into(parts, &options, mdc, remote_pubkey_kyber, own_seckey_sig, chain.pfs_key(), pfs_salt, &mut Vec::new()) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if let Err(err) = chain.advance(&new_pfs_key) { return Err(err); }
+	Ok((mdc, ciphertext))
+}
+
+// send a message, serializing it into the given buffer
+fn send_msg_into(parts: MessageParts, options: &SendOptions, mdc: &str, remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], buffer: &mut Vec<u8>) -> Result<(Vec<u8>, String, Vec<u8>), DawnError> {
 	// create message
-	let mdc = predictable_mdc_gen(mdc_seed, id);
+	let message_data = match build_message(parts, mdc, options) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let config = &options.config;
+	
+	// the escrow copy is parsed as JSON (see open_escrow)
+	if options.format != WireFormat::Json && options.escrow_pubkey_kyber.is_some() { error!("escrowed messages can only be sent as JSON"); }
+	
+	// serialize and encrypt message
+	buffer.clear();
+	let (msg_ciphertext, new_pfs_key) = match options.format {
+		WireFormat::Json => {
+			if serde_json::to_writer(&mut *buffer, &message_data).is_err() { error!(Serialization, "json serialization failed"); }
+			// JSON parsers skip trailing whitespace
+			if let Err(err) = config::pad(buffer, config.padding, b' ') { return Err(err); }
+			let message = match std::str::from_utf8(buffer) {
+				Ok(res) => res,
+				Err(_) => error!(Serialization, "json serialization failed")
+			};
+			match encrypt_msg(remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt, message) {
+				Ok(res) => res,
+				Err(err) => return Err(DawnError::Crypto(err))
+			}
+		},
+		WireFormat::Cbor => {
+			if ciborium::into_writer(&message_data, &mut *buffer).is_err() { error!(Serialization, "cbor serialization failed"); }
+			if config.compression { *buffer = wire_format::compress(buffer); }
+			if let Err(err) = config::pad(buffer, config.padding, 0) { return Err(err); }
+			match wire_format::seal_binary(buffer, config.compression, remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt) {
+				Ok(res) => res,
+				Err(err) => return Err(err)
+			}
+		}
+	};
+	
+	// the buffer still holds the serialized message for the escrow copy
+	let msg_ciphertext = match options.escrow_pubkey_kyber {
+		Some(escrow_pubkey_kyber) => match escrow::attach_escrow(escrow_pubkey_kyber, buffer, &msg_ciphertext) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		},
+		None => msg_ciphertext
+	};
+	let header = RoutingHeader {
+		version: routing::ROUTING_VERSION,
+		class: RoutingClass::of(parts.content.0),
+		signed: own_seckey_sig.is_some(),
+		escrowed: options.escrow_pubkey_kyber.is_some(),
+		thread: options.thread_id.is_some(),
+		mdc: mdc.to_string(),
+		counter: options.counter,
+	};
+	match routing::attach_routing_header(&header, &msg_ciphertext) {
+		Ok(res) => Ok((new_pfs_key, mdc.to_string(), res)),
+		Err(err) => Err(err)
+	}
+}
+
+// build the message for the given content, checking that the content fits the content type
+// the thread, device counter and sequence number are taken from the options
+fn build_message(parts: MessageParts, mdc: &str, options: &SendOptions) -> Result<Message, DawnError> {
+	let MessageParts { content: (msg_type, msg_text, msg_data), effect, alt_text, in_reply_to, codec, expires_after } = parts;
+	let (device, seq) = (&options.device, options.seq);
+	if let Some(Err(err)) = effect.map(effect::check_effect) { return Err(err); }
+	if let Some(Err(err)) = in_reply_to.map(Reply::check) { return Err(err); }
+	if let Some(Err(err)) = codec.map(media_codec::check_codec) { return Err(err); }
+	if let Some(Err(err)) = expires_after.map(disappearing::check_expires_after) { return Err(err); }
+	let thread_id = options.thread_id.map(|thread_id| thread_id.to_string());
 	let message_data: Message = match msg_type {
 		content_type::TEXT => { 
-			if msg_text.is_none() { error!("no text was provided"); }
+			let text = match msg_text {
+				Some(res) => res,
+				None => error!("no text was provided")
+			};
 			Message::Text( TextMessage {
-				text: String::from(msg_text.unwrap()),
-				mdc: mdc.clone()
+				text: String::from(text),
+				mdc: mdc.to_string(),
+				thread_id: thread_id.clone(),
+				effect: effect.cloned(),
+				in_reply_to: in_reply_to.cloned(),
+				expires_after,
+				device: device.clone(),
+				seq,
+				protocol_version: PROTOCOL_VERSION
 			} )
 		},
 		content_type::INTERNAL => {
-			if msg_text.is_none() { error!("no event code was provided"); }
-			let event_id = msg_text.unwrap().parse::<u8>();
-			if event_id.is_err() { error!("invalid event code"); }
-			if msg_data.is_none() { error!("missing event data"); }
+			let event_id = match msg_text.map(|text| text.parse::<u8>()) {
+				Some(Ok(res)) => res,
+				Some(Err(_)) => error!("invalid event code"),
+				None => error!("no event code was provided")
+			};
+			let event_data = match msg_data {
+				Some(res) => res,
+				None => error!("missing event data")
+			};
 			Message::Internal( InternalMessage {
-				event: event_id.unwrap(),
-				event_data: BASE64.encode(msg_data.unwrap()),
-				mdc: mdc.clone()
+				event: event_id,
+				event_data: event_data.to_vec(),
+				mdc: mdc.to_string(),
+				device: device.clone(),
+				seq,
+				protocol_version: PROTOCOL_VERSION
 			} )
 		},
 		content_type::VOICE => {
-			if msg_data.is_none() { error!("no voice data was provided"); }
+			let voice = match msg_data {
+				Some(res) => res,
+				None => error!("no voice data was provided")
+			};
+			// the text is an optional transcription (see Transcription::pack)
+			let transcription = match msg_text.map(Transcription::unpack) {
+				Some(Ok(res)) => Some(res),
+				Some(Err(err)) => return Err(err),
+				None => None
+			};
 			Message::Voice( VoiceMessage {
-				voice: BASE64.encode(msg_data.unwrap()),
-				mdc: mdc.clone()
+				voice: voice.to_vec(),
+				mdc: mdc.to_string(),
+				thread_id: thread_id.clone(),
+				transcription,
+				in_reply_to: in_reply_to.cloned(),
+				codec: codec.map(|codec| codec.to_string()),
+				expires_after,
+				device: device.clone(),
+				seq,
+				protocol_version: PROTOCOL_VERSION
 			} )
 		},
 		content_type::PICTURE => {
-			if msg_data.is_none() { error!("no picture data was provided"); }
+			let picture = match msg_data {
+				Some(res) => res,
+				None => error!("no picture data was provided")
+			};
 			let description = msg_text.unwrap_or("");
 			Message::Picture( PictureMessage {
-				picture: BASE64.encode(msg_data.unwrap()),
+				picture: picture.to_vec(),
 				description: description.to_string(),
-				mdc: mdc.clone()
+				alt_text: alt_text.map(|alt_text| alt_text.to_string()),
+				mdc: mdc.to_string(),
+				thread_id: thread_id.clone(),
+				effect: effect.cloned(),
+				in_reply_to: in_reply_to.cloned(),
+				codec: codec.map(|codec| codec.to_string()),
+				expires_after,
+				device: device.clone(),
+				seq,
+				protocol_version: PROTOCOL_VERSION
+			} )
+		},
+		content_type::REACTION => {
+			// msg_text is the mdc of the target message and the reaction (see OutgoingMessage::reaction)
+			let (target, reaction) = match msg_text.map(reaction::unpack_reaction) {
+				Some(Ok(res)) => res,
+				Some(Err(err)) => return Err(err),
+				None => error!("no reaction was provided")
+			};
+			Message::Reaction( ReactionMessage {
+				target: target.to_string(),
+				reaction: reaction.to_string(),
+				mdc: mdc.to_string(),
+				device: device.clone(),
+				seq,
+				protocol_version: PROTOCOL_VERSION
+			} )
+		},
+		content_type::RETRACTION => {
+			// msg_text is the mdc of the target message (see OutgoingMessage::retraction)
+			let target = match msg_text {
+				Some(res) => res,
+				None => error!("no retraction target was provided")
+			};
+			if let Err(err) = retraction::check_retraction(target) { return Err(err); }
+			Message::Retraction( RetractionMessage {
+				target: target.to_string(),
+				mdc: mdc.to_string(),
+				device: device.clone(),
+				seq,
+				protocol_version: PROTOCOL_VERSION
 			} )
 		},
 		content_type::LINKED_MEDIA => {
 			// This data currently has to be provided in a special format:
-			// msg_data is one byte that indicates the media type
+			// msg_data is one byte that indicates the media type, optionally followed by expiry and delete token (see gen_linked_media_data)
 			// msg_text contains the link to the media file in the first line and the encoded symmetric key in the second line. All following lines are interpreted as the description.
-			if msg_data.is_none() { error!("no voice data was provided"); }
-			let msg_data = msg_data.unwrap();
-			if msg_data.len() != 1 { error!(&format!("expected 1 byte to identify media type, got {} bytes", msg_data.len())); }
-			if msg_text.is_none() { error!("no link was provided"); }
-			let mut text_data = msg_text.unwrap().lines();
-			let media_link = text_data.next().unwrap();
+			let (media_type, expires_at, delete_token) = match msg_data.map(parse_linked_media_data) {
+				Some(Ok(res)) => res,
+				Some(Err(err)) => return Err(err),
+				None => error!("no media type was provided")
+			};
+			let mut text_data = match msg_text {
+				Some(res) => res.lines(),
+				None => error!("no link was provided")
+			};
+			let media_link = match text_data.next() {
+				Some(link) if !link.is_empty() => link,
+				_ => error!("no link was provided")
+			};
 			let media_key = match text_data.next() {
 				Some(key) => key,
 				None => { error!("no media key was provided"); }
@@ -423,45 +1287,115 @@ pub fn send_msg((msg_type, msg_text, msg_data): (u8, Option<&str>, Option<&[u8]>
 			}
 			description.pop();
 			Message::LinkedMedia( LinkedMediaMessage {
-				media_type: msg_data[0],
+				media_type,
 				media_link: media_link.to_string(),
 				media_key: media_key.to_string(),
 				description,
-				mdc: mdc.clone()
+				mdc: mdc.to_string(),
+				expires_at,
+				delete_token: delete_token.map(encode),
+				thread_id: thread_id.clone(),
+				device: device.clone(),
+				seq,
+				protocol_version: PROTOCOL_VERSION
+			} )
+		},
+		content_type::HISTORY_SYNC => {
+			// msg_text is the chunk header and msg_data the chunk as returned by gen_history_chunks
+			let (transfer_id, chunk_index, chunk_count) = match msg_text.map(history::parse_chunk_header) {
+				Some(Ok(res)) => res,
+				Some(Err(err)) => return Err(err),
+				None => error!("no history chunk header was provided")
+			};
+			let chunk = match msg_data {
+				Some(res) => res,
+				None => error!("no history chunk data was provided")
+			};
+			Message::HistorySync( HistorySyncMessage {
+				transfer_id,
+				chunk_index,
+				chunk_count,
+				chunk: chunk.to_vec(),
+				mdc: mdc.to_string(),
+				device: device.clone(),
+				seq,
+				protocol_version: PROTOCOL_VERSION
+			} )
+		},
+		content_type::DELTA_SYNC => {
+			// msg_data is the delta as returned by gen_delta_sync
+			let delta = match msg_data {
+				Some(res) => res,
+				None => error!("no delta sync data was provided")
+			};
+			if let Err(err) = parse_delta_sync(delta) { return Err(err); }
+			Message::DeltaSync( DeltaSyncMessage {
+				delta: delta.to_vec(),
+				mdc: mdc.to_string(),
+				device: device.clone(),
+				seq,
+				protocol_version: PROTOCOL_VERSION
+			} )
+		},
+		content_type::GATEWAY => {
+			// msg_text is the envelope as returned by gen_gateway_envelope and msg_data the data of the relayed message
+			let envelope = match msg_text {
+				Some(res) => res,
+				None => error!("no gateway envelope was provided")
+			};
+			let (_, relayed_type, relayed_text) = match gateway::parse_envelope(envelope) {
+				Ok(res) => res,
+				Err(err) => return Err(err)
+			};
+			// the relayed content has to be valid on its own
+			if let Err(err) = build_message(MessageParts::from_content((relayed_type, relayed_text.as_deref(), msg_data)), mdc, &SendOptions::default()) { return Err(err); }
+			Message::Gateway( GatewayMessage {
+				envelope: envelope.to_string(),
+				gateway_data: msg_data.map(<[u8]>::to_vec),
+				mdc: mdc.to_string(),
+				device: device.clone(),
+				seq,
+				protocol_version: PROTOCOL_VERSION
 			} )
 		},
-		_ => error!("requested content type not implemented")
-	};
-	
-	let message = match serde_json::to_string(&message_data) {
-		Ok(res) => res,
-		Err(_) => error!("json serialization failed")
-	};
-	
-	// encrypt message
-	let (msg_ciphertext, new_pfs_key) = match encrypt_msg(remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt, &message) {
-		Ok(res) => res,
-		Err(err) => return Err(err)
 	};
+	if thread_id.is_some() && !matches!(message_data, Message::Text(_) | Message::Voice(_) | Message::Picture(_) | Message::LinkedMedia(_)) {
+		error!("only text, voice, picture and linked media messages can be part of a thread");
+	}
+	if effect.is_some() && !matches!(message_data, Message::Text(_) | Message::Picture(_)) {
+		error!("only text and picture messages can have an effect");
+	}
+	if alt_text.is_some() && !matches!(message_data, Message::Picture(_)) {
+		error!("only picture messages can have alt text");
+	}
+	if in_reply_to.is_some() && !matches!(message_data, Message::Text(_) | Message::Voice(_) | Message::Picture(_)) {
+		error!("only text, voice and picture messages can reply to another message");
+	}
+	if codec.is_some() && !matches!(message_data, Message::Voice(_) | Message::Picture(_)) {
+		error!("only voice and picture messages can name a codec");
+	}
+	if expires_after.is_some() && !matches!(message_data, Message::Text(_) | Message::Voice(_) | Message::Picture(_)) {
+		error!("only text, voice and picture messages can disappear");
+	}
 	
-	Ok((new_pfs_key, mdc, msg_ciphertext))
+	Ok(message_data)
 }
 
 // This encrypts a file using a random key and returns the ciphertext and key
-pub fn encrypt_file(file: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+pub fn encrypt_file(file: &[u8]) -> Result<(Vec<u8>, Vec<u8>), DawnError> {
 	let key = sym_key_gen();
 	let ciphertext = match encrypt_data(file, &key) {
 		Ok(res) => res,
-		Err(err) => { error!(&format!("file encryption failed: {}", err)); }
+		Err(err) => { error!(Crypto, &format!("file encryption failed: {}", err)); }
 	};
 	Ok((ciphertext, key))
 }
 
 // This decrypts a file using the symmetric key and returns the cleartext file
-pub fn decrypt_file(ciphertext: &[u8], key: &[u8]) -> Result<Vec<u8>, String> {
+pub fn decrypt_file(ciphertext: &[u8], key: &[u8]) -> Result<Vec<u8>, DawnError> {
 	let file = match decrypt_data(ciphertext, key) {
 		Ok(res) => res,
-		Err(err) => { error!(&format!("file decryption failed: {}", err)); }
+		Err(err) => { error!(Crypto, &format!("file decryption failed: {}", err)); }
 	};
 	Ok(file)
 }
@@ -478,8 +1412,11 @@ pub fn gen_handle(init_pubkey_kyber: &[u8], init_pubkey_curve: &[u8], init_pubke
 	handle_content.as_bytes().to_vec()
 }
 
+// init keys, name and message detail code of a handle
+type HandleContent = (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, String, String);
+
 // this parses a handle
-pub fn parse_handle(handle_content: Vec<u8>) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, String, String), String> {
+pub fn parse_handle(handle_content: Vec<u8>) -> Result<HandleContent, DawnError> {
 	let handle_string = match String::from_utf8(handle_content) {
 		Ok(res) => res,
 		Err(_) => error!("handle content is not valid UTF-8!")
@@ -0,0 +1,416 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Limits for parsing received messages
+// Constrained clients (watches, feature phones) can only spend a few MB on a single message. The ciphertext length is
+// checked before decryption and the decrypted message is scanned once for the sizes of its fields before anything gets
+// allocated for them, so oversized messages are rejected early instead of exhausting memory during parsing.
+// The parse mode decides what happens to fields this version of the library doesn't know: lenient parsing ignores them,
+// so messages of newer clients with additional fields stay readable, while strict parsing rejects them for deployments
+// that only run clients of one version. Duplicate fields are rejected in both modes. Binary messages (see wire_format.rs)
+// are checked the same way, their binary fields are byte strings instead of base64. Deflated binary messages are inflated
+// up to their own limit, which is finite even by default, so a small ciphertext can't expand into gigabytes.
+
+use std::collections::HashMap;
+use std::cell::Cell;
+use std::fmt;
+use serde::de::{Deserialize, DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, VariantAccess, IntoDeserializer, Visitor, SeqAccess, MapAccess, IgnoredAny};
+use serde_json::Value;
+use crate::content_type::{self, ContentType};
+use crate::device_counter::DeviceStamp;
+use crate::reply::Reply;
+use crate::transcription::Transcription;
+use crate::oversized::OversizedMedia;
+use crate::DawnError;
+
+// fields of the message types that carry base64 encoded binary data
+const DATA_FIELDS: [&str; 5] = ["voice", "picture", "chunk", "delta", "gateway_data"];
+
+// size a deflated message may inflate to unless the limits set a smaller one
+pub const DEFAULT_MAX_DECOMPRESSED_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+	#[default]
+	Lenient, // unknown fields are ignored (forward compatible)
+	Strict, // unknown fields are rejected
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+	pub max_ciphertext_len: usize,
+	pub max_text_len: usize, // per text field, in bytes
+	pub max_data_len: usize, // per binary field, in bytes after decoding
+	pub max_decompressed_len: usize, // per deflated binary message, in bytes after inflating
+	pub mode: ParseMode,
+}
+
+impl Default for ParseLimits {
+	fn default() -> Self {
+		ParseLimits {
+			max_ciphertext_len: usize::MAX,
+			max_text_len: usize::MAX,
+			max_data_len: usize::MAX,
+			max_decompressed_len: DEFAULT_MAX_DECOMPRESSED_LEN,
+			mode: ParseMode::Lenient,
+		}
+	}
+}
+
+impl ParseLimits {
+	// limits suitable for devices with a few MB of RAM
+	pub fn low_memory() -> Self {
+		ParseLimits {
+			max_ciphertext_len: 2 * 1024 * 1024,
+			max_text_len: 64 * 1024,
+			max_data_len: 1024 * 1024,
+			max_decompressed_len: 64 * 1024 + 1024 * 1024, // one text and one binary field
+			mode: ParseMode::Lenient,
+		}
+	}
+	
+	// no size limits, rejecting unknown fields (the mode can be combined with any limits, e.g. those of low_memory)
+	pub fn strict() -> Self {
+		ParseLimits {
+			mode: ParseMode::Strict,
+			..Self::default()
+		}
+	}
+}
+
+// what is read of a voice message or picture that exceeds the limit, all other fields are skipped
+#[derive(serde::Deserialize)]
+struct MediaSummary {
+	mdc: String,
+	#[serde(default)]
+	description: Option<String>,
+}
+
+// size of a JSON or CBOR value, determined without keeping any of its content
+struct FieldSize(usize);
+
+struct FieldSizeVisitor;
+
+impl<'de> Visitor<'de> for FieldSizeVisitor {
+	type Value = FieldSize;
+	
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		formatter.write_str("any JSON or CBOR value")
+	}
+	
+	fn visit_str<E>(self, value: &str) -> Result<FieldSize, E> {
+		Ok(FieldSize(value.len()))
+	}
+	
+	fn visit_bytes<E>(self, value: &[u8]) -> Result<FieldSize, E> {
+		Ok(FieldSize(value.len()))
+	}
+	
+	fn visit_bool<E>(self, _: bool) -> Result<FieldSize, E> {
+		Ok(FieldSize(0))
+	}
+	
+	fn visit_u64<E>(self, _: u64) -> Result<FieldSize, E> {
+		Ok(FieldSize(0))
+	}
+	
+	fn visit_i64<E>(self, _: i64) -> Result<FieldSize, E> {
+		Ok(FieldSize(0))
+	}
+	
+	fn visit_f64<E>(self, _: f64) -> Result<FieldSize, E> {
+		Ok(FieldSize(0))
+	}
+	
+	fn visit_unit<E>(self) -> Result<FieldSize, E> {
+		Ok(FieldSize(0))
+	}
+	
+	fn visit_none<E>(self) -> Result<FieldSize, E> {
+		Ok(FieldSize(0))
+	}
+	
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<FieldSize, A::Error> {
+		let mut size = 0usize;
+		while let Some(FieldSize(element)) = seq.next_element()? {
+			size = size.saturating_add(element);
+		}
+		Ok(FieldSize(size))
+	}
+	
+	fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FieldSize, A::Error> {
+		let mut size = 0usize;
+		while let Some((IgnoredAny, FieldSize(value))) = map.next_entry()? {
+			size = size.saturating_add(value);
+		}
+		Ok(FieldSize(size))
+	}
+}
+
+impl<'de> Deserialize<'de> for FieldSize {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		deserializer.deserialize_any(FieldSizeVisitor)
+	}
+}
+
+// check the ciphertext length before decrypting
+pub(crate) fn check_ciphertext(msg_ciphertext: &[u8], limits: &ParseLimits) -> Result<(), DawnError> {
+	if msg_ciphertext.len() > limits.max_ciphertext_len { error!(&format!("ciphertext exceeds the limit of {} bytes", limits.max_ciphertext_len)); }
+	Ok(())
+}
+
+// check the field sizes of a decrypted message before parsing it
+pub(crate) fn check_fields(msg_content: &str, limits: &ParseLimits) -> Result<(), DawnError> {
+	if limits.max_text_len == usize::MAX && limits.max_data_len == usize::MAX { return Ok(()); }
+	
+	let header = match serde_json::from_str::<HashMap<String, HashMap<String, FieldSize>>>(msg_content) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "json parsing failed")
+	};
+	match check_field_sizes(&header, true, limits) {
+		Ok(Some(media)) => match serde_json::from_str::<HashMap<String, MediaSummary>>(msg_content) {
+			Ok(summary) => Err(oversized_media(summary, media, limits)),
+			Err(_) => error!(Serialization, "json parsing failed")
+		},
+		Ok(None) => Ok(()),
+		Err(err) => Err(err)
+	}
+}
+
+// check the field sizes of a decrypted binary message before parsing it
+pub(crate) fn check_binary_fields(msg_content: &[u8], limits: &ParseLimits) -> Result<(), DawnError> {
+	if limits.max_text_len == usize::MAX && limits.max_data_len == usize::MAX { return Ok(()); }
+	
+	let header = match ciborium::from_reader::<HashMap<String, HashMap<String, FieldSize>>, _>(msg_content) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "cbor parsing failed")
+	};
+	match check_field_sizes(&header, false, limits) {
+		Ok(Some(media)) => match ciborium::from_reader::<HashMap<String, MediaSummary>, _>(msg_content) {
+			Ok(summary) => Err(oversized_media(summary, media, limits)),
+			Err(_) => error!(Serialization, "cbor parsing failed")
+		},
+		Ok(None) => Ok(()),
+		Err(err) => Err(err)
+	}
+}
+
+// content type of the messages with inline media, by their name in the serialized message
+fn inline_media_type(message_type: &str) -> Option<ContentType> {
+	match message_type {
+		"Voice" => Some(content_type::VOICE),
+		"Picture" => Some(content_type::PICTURE),
+		_ => None
+	}
+}
+
+// base64: whether the binary fields are base64 encoded (JSON) or byte strings (CBOR)
+// returns the content type and size of inline media that exceeds the limit, all other fields are within their limits then
+fn check_field_sizes(header: &HashMap<String, HashMap<String, FieldSize>>, base64: bool, limits: &ParseLimits) -> Result<Option<(ContentType, usize)>, DawnError> {
+	let mut oversized = None;
+	for (message_type, fields) in header {
+		for (name, FieldSize(size)) in fields {
+			if DATA_FIELDS.contains(&name.as_str()) {
+				// base64 without padding encodes 3 bytes using 4 characters
+				let data_len = if base64 { size / 4 * 3 } else { *size };
+				if data_len <= limits.max_data_len { continue; }
+				match inline_media_type(message_type) {
+					Some(content_type) => oversized = Some((content_type, data_len)),
+					None => error!(&format!("field {} exceeds the limit of {} bytes", name, limits.max_data_len))
+				}
+			}
+			else if *size > limits.max_text_len { error!(&format!("field {} exceeds the limit of {} bytes", name, limits.max_text_len)); }
+		}
+	}
+	Ok(oversized)
+}
+
+// describe inline media that exceeds the limit for DawnError::TooLarge
+fn oversized_media(summary: HashMap<String, MediaSummary>, (content_type, size): (ContentType, usize), limits: &ParseLimits) -> DawnError {
+	let (mdc, description) = match summary.into_values().next() {
+		Some(summary) => (summary.mdc, summary.description),
+		None => (String::new(), None)
+	};
+	DawnError::TooLarge(Box::new(OversizedMedia {
+		content_type,
+		size,
+		limit: limits.max_data_len,
+		description,
+		mdc,
+	}))
+}
+
+// error of FieldNames, which never deserializes anything
+#[derive(Debug)]
+struct FieldNamesError;
+
+impl fmt::Display for FieldNamesError {
+	fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		formatter.write_str("only field names are read")
+	}
+}
+
+impl std::error::Error for FieldNamesError {}
+
+impl serde::de::Error for FieldNamesError {
+	fn custom<T: fmt::Display>(_: T) -> Self {
+		FieldNamesError
+	}
+}
+
+// reads the field names a struct declares to serde (including the optional ones) from its call to deserialize_struct,
+// for enums the struct of the given variant is read
+struct FieldNames<'a> {
+	variant: &'a str,
+	fields: &'a Cell<&'static [&'static str]>,
+}
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+	type Error = FieldNamesError;
+	
+	fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, FieldNamesError> {
+		Err(FieldNamesError)
+	}
+	
+	fn deserialize_struct<V: Visitor<'de>>(self, _: &'static str, fields: &'static [&'static str], _: V) -> Result<V::Value, FieldNamesError> {
+		self.fields.set(fields);
+		Err(FieldNamesError)
+	}
+	
+	fn deserialize_enum<V: Visitor<'de>>(self, _: &'static str, _: &'static [&'static str], visitor: V) -> Result<V::Value, FieldNamesError> {
+		visitor.visit_enum(self)
+	}
+	
+	serde::forward_to_deserialize_any! {
+		bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit unit_struct
+		newtype_struct seq tuple tuple_struct map identifier ignored_any
+	}
+}
+
+impl<'de> EnumAccess<'de> for FieldNames<'_> {
+	type Error = FieldNamesError;
+	type Variant = Self;
+	
+	fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), FieldNamesError> {
+		let variant = seed.deserialize(self.variant.into_deserializer());
+		variant.map(|variant| (variant, self))
+	}
+}
+
+impl<'de> VariantAccess<'de> for FieldNames<'_> {
+	type Error = FieldNamesError;
+	
+	fn unit_variant(self) -> Result<(), FieldNamesError> {
+		Err(FieldNamesError)
+	}
+	
+	fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, FieldNamesError> {
+		seed.deserialize(self)
+	}
+	
+	fn tuple_variant<V: Visitor<'de>>(self, _: usize, _: V) -> Result<V::Value, FieldNamesError> {
+		Err(FieldNamesError)
+	}
+	
+	fn struct_variant<V: Visitor<'de>>(self, _: &'static [&'static str], _: V) -> Result<V::Value, FieldNamesError> {
+		Err(FieldNamesError)
+	}
+}
+
+// the field names of a struct, or of the struct of a variant of an enum
+fn field_names<T: DeserializeOwned>(variant: &str) -> &'static [&'static str] {
+	let fields = Cell::new(&[][..]);
+	let _ = T::deserialize(FieldNames { variant, fields: &fields });
+	fields.get()
+}
+
+// the field names of the structs nested in messages
+fn nested_field_names(field: &str) -> Option<&'static [&'static str]> {
+	match field {
+		"in_reply_to" => Some(field_names::<Reply>("")),
+		"transcription" => Some(field_names::<Transcription>("")),
+		"device" => Some(field_names::<DeviceStamp>("")),
+		_ => None
+	}
+}
+
+// returns the path of the first field of the received message (as a map of variant to struct) that the message type
+// doesn't declare, fields are compared by name only, so explicit nulls for optional fields are accepted
+fn find_unknown_field<T: DeserializeOwned>(received: &Value) -> Option<String> {
+	let find_in = |fields: &[&str], values: &serde_json::Map<String, Value>, path: &str| values.keys().find(|key| !fields.contains(&key.as_str())).map(|key| format!("{}/{}", path, key));
+	let variants = match received {
+		Value::Object(variants) => variants,
+		_ => return None
+	};
+	variants.iter().find_map(|(variant, message)| {
+		let path = format!("/{}", variant);
+		let message = match message {
+			Value::Object(message) => message,
+			_ => return None
+		};
+		find_in(field_names::<T>(variant), message, &path).or_else(|| message.iter().find_map(|(field, value)| {
+			match (nested_field_names(field), value) {
+				(Some(fields), Value::Object(values)) => find_in(fields, values, &format!("{}/{}", path, field)),
+				_ => None
+			}
+		}))
+	})
+}
+
+// check a received message for fields the message type T doesn't know in strict mode
+pub(crate) fn check_unknown_fields<T: DeserializeOwned>(msg_content: &str, limits: &ParseLimits) -> Result<(), DawnError> {
+	if limits.mode == ParseMode::Lenient { return Ok(()); }
+	
+	let received = match serde_json::from_str::<Value>(msg_content) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "json parsing failed")
+	};
+	match find_unknown_field::<T>(&received) {
+		Some(path) => error!(&format!("unknown field {}", path)),
+		None => Ok(())
+	}
+}
+
+// the structure of a CBOR value as JSON, only maps and arrays are kept since only the field names are compared
+fn cbor_structure(value: &ciborium::Value) -> Value {
+	match value {
+		ciborium::Value::Map(entries) => Value::Object(entries.iter().map(|(key, value)| {
+			let key = match key {
+				ciborium::Value::Text(key) => key.clone(),
+				key => format!("{:?}", key)
+			};
+			(key, cbor_structure(value))
+		}).collect()),
+		ciborium::Value::Array(values) => Value::Array(values.iter().map(cbor_structure).collect()),
+		_ => Value::Null
+	}
+}
+
+// check a received binary message for fields the message type T doesn't know in strict mode
+pub(crate) fn check_unknown_binary_fields<T: DeserializeOwned>(msg_content: &[u8], limits: &ParseLimits) -> Result<(), DawnError> {
+	if limits.mode == ParseMode::Lenient { return Ok(()); }
+	
+	let received = match ciborium::from_reader::<ciborium::Value, _>(msg_content) {
+		Ok(res) => cbor_structure(&res),
+		Err(_) => error!(Serialization, "cbor parsing failed")
+	};
+	match find_unknown_field::<T>(&received) {
+		Some(path) => error!(&format!("unknown field {}", path)),
+		None => Ok(())
+	}
+}
@@ -0,0 +1,130 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Scheduled maintenance
+// Keeping an account healthy takes a few background jobs: uploading fresh prekeys before the server runs out of them,
+// rotating the init keys of the identity (the signed prekeys published in the handle) and closing sessions that went
+// quiet. The rules for when these are due live here, so clients pass what they know about their keys and sessions and
+// schedule a job for every returned event instead of re-deriving the rules themselves. All times are in seconds.
+
+pub struct MaintenancePolicy {
+	min_prekeys: usize, // upload new prekeys once the server has this many or fewer left
+	prekey_target: usize, // number of prekeys the server should have after an upload
+	init_key_rotation_interval: u64,
+	stale_session_age: u64,
+	lead_time: u64, // events that are due within this time are reported in advance
+}
+
+// what the client knows about its keys and sessions
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MaintenanceState {
+	pub prekeys_left: usize, // prekeys the server still has for the account
+	pub init_keys_rotated: u64, // time the init keys were generated or rotated last
+	pub sessions: Vec<(String, u64)>, // id and time of the last sent or received message of every open session
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MaintenanceTask {
+	UploadPrekeys { count: usize },
+	RotateInitKeys, // Identity::rotate_init_keys, followed by KeyAudit::remember and publishing a new handle
+	CloseStaleSession { id: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MaintenanceEvent {
+	pub due: u64, // events that are due already keep their original time, clients run them right away
+	pub task: MaintenanceTask,
+}
+
+impl Default for MaintenancePolicy {
+	fn default() -> Self {
+		MaintenancePolicy {
+			min_prekeys: 10,
+			prekey_target: 100,
+			init_key_rotation_interval: 30 * 24 * 60 * 60,
+			stale_session_age: 180 * 24 * 60 * 60,
+			lead_time: 24 * 60 * 60,
+		}
+	}
+}
+
+impl MaintenancePolicy {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// upload prekeys up to target once min or fewer are left
+	pub fn prekeys(mut self, min: usize, target: usize) -> Self {
+		self.min_prekeys = min;
+		self.prekey_target = target;
+		self
+	}
+	
+	pub fn init_key_rotation_interval(mut self, interval: u64) -> Self {
+		self.init_key_rotation_interval = interval;
+		self
+	}
+	
+	// sessions without messages for this long are considered stale
+	pub fn stale_session_age(mut self, age: u64) -> Self {
+		self.stale_session_age = age;
+		self
+	}
+	
+	// report events this long before they are due, so clients can batch them with other background work
+	pub fn lead_time(mut self, lead_time: u64) -> Self {
+		self.lead_time = lead_time;
+		self
+	}
+}
+
+// list the maintenance that is due now or within the lead time of the policy
+// returns the events ordered by due time
+pub fn maintenance_events(policy: &MaintenancePolicy, state: &MaintenanceState, now: u64) -> Vec<MaintenanceEvent> {
+	let horizon = now.saturating_add(policy.lead_time);
+	let mut events = Vec::new();
+	
+	// running out of prekeys is not something that can be predicted from the state, so it is always due right away
+	if state.prekeys_left <= policy.min_prekeys && policy.prekey_target > state.prekeys_left {
+		events.push(MaintenanceEvent {
+			due: now,
+			task: MaintenanceTask::UploadPrekeys { count: policy.prekey_target - state.prekeys_left },
+		});
+	}
+	
+	let rotation_due = state.init_keys_rotated.saturating_add(policy.init_key_rotation_interval);
+	if rotation_due <= horizon {
+		events.push(MaintenanceEvent {
+			due: rotation_due,
+			task: MaintenanceTask::RotateInitKeys,
+		});
+	}
+	
+	for (id, last_activity) in &state.sessions {
+		let stale_since = last_activity.saturating_add(policy.stale_session_age);
+		if stale_since <= horizon {
+			events.push(MaintenanceEvent {
+				due: stale_since,
+				task: MaintenanceTask::CloseStaleSession { id: id.clone() },
+			});
+		}
+	}
+	
+	events.sort_by_key(|event| event.due);
+	events
+}
@@ -0,0 +1,211 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Linked media helpers
+// The symmetric key of a linked media file is not put into the LINKED_MEDIA message as is. It is wrapped with a key that
+// is derived from the conversation (its secret pfs salt and id) and the message it belongs to, so a leaked message alone
+// does not reveal the file and a wrapped key copied into another conversation or message can't be unwrapped.
+// LINKED_MEDIA messages can also tell the recipient when the content server purges the file and carry a delete token,
+// which is signed by the sender and lets the content server verify requests to delete the file early.
+// Before uploading, the sender creates an upload ticket committing to the hash and size of the encrypted file, which the
+// content server checks against the uploaded blob without learning anything about the plaintext. Large blobs can be checked
+// chunk by chunk with the verifier of the ticket.
+
+use serde::{Serialize, Deserialize};
+use dawn_crypto::{hash, encrypt_data, decrypt_data};
+use crate::codec::{encode, decode};
+use crate::signature::{sign_detached, verify_detached};
+use crate::stream_hash::HashVerifier;
+use crate::domain::{MEDIA_KEY_DOMAIN, DELETE_TOKEN_DOMAIN, UPLOAD_TICKET_DOMAIN};
+use crate::DawnError;
+
+const WRAPPING_KEY_LEN: usize = 32;
+
+// upload ticket as seen by the content server
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UploadTicket {
+	pub blob_hash: Vec<u8>,
+	pub size: u64,
+	pub expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct UploadTicketRecord {
+	blob_hash: String,
+	size: u64,
+	expires_at: u64,
+	signature: String,
+}
+
+// derive the key used to wrap media keys of one message
+fn derive_wrapping_key(pfs_salt: &[u8], id: &str, message_id: &str) -> Result<Vec<u8>, DawnError> {
+	if message_id.is_empty() { error!("message id must not be empty"); }
+	
+	// length-prefix every part, so different splits of the same bytes can't result in the same key
+	let mut input = MEDIA_KEY_DOMAIN.as_bytes().to_vec();
+	for part in [pfs_salt, id.as_bytes(), message_id.as_bytes()] {
+		input.extend_from_slice(&(part.len() as u64).to_be_bytes());
+		input.extend_from_slice(part);
+	}
+	let mut key = hash(&input);
+	if key.len() < WRAPPING_KEY_LEN { error!(Crypto, "hash output too short to derive a media wrapping key"); }
+	key.truncate(WRAPPING_KEY_LEN);
+	Ok(key)
+}
+
+// wrap the key of a linked media file for one message of a conversation
+// The message id can be any string that is unique within the conversation and known to both sides, e.g. the media link.
+// returns the wrapped key as a string that can be used as the media key of a LINKED_MEDIA message
+pub fn wrap_media_key(media_key: &[u8], pfs_salt: &[u8], id: &str, message_id: &str) -> Result<String, DawnError> {
+	let wrapping_key = match derive_wrapping_key(pfs_salt, id, message_id) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	match encrypt_data(media_key, &wrapping_key) {
+		Ok(res) => Ok(encode(res)),
+		Err(err) => { error!(Crypto, &format!("media key wrapping failed: {}", err)); }
+	}
+}
+
+// unwrap a media key received in a LINKED_MEDIA message
+// returns the symmetric key for decrypt_file
+pub fn unwrap_media_key(wrapped_key: &str, pfs_salt: &[u8], id: &str, message_id: &str) -> Result<Vec<u8>, DawnError> {
+	let wrapped_key = match decode(wrapped_key) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "wrapped media key invalid")
+	};
+	let wrapping_key = match derive_wrapping_key(pfs_salt, id, message_id) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	match decrypt_data(&wrapped_key, &wrapping_key) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Crypto, "media key could not be unwrapped for this conversation and message")
+	}
+}
+
+// generate a delete token for a media file uploaded to a content server
+pub fn gen_media_delete_token(media_link: &str, own_seckey_sig: &[u8]) -> Result<Vec<u8>, DawnError> {
+	sign_detached(DELETE_TOKEN_DOMAIN, media_link.as_bytes(), own_seckey_sig)
+}
+
+// verify a delete token (used by content servers, which know the signature key of the uploader)
+pub fn verify_media_delete_token(media_link: &str, delete_token: &[u8], uploader_pubkey_sig: &[u8]) -> Result<(), DawnError> {
+	verify_detached(DELETE_TOKEN_DOMAIN, media_link.as_bytes(), delete_token, uploader_pubkey_sig)
+}
+
+// build the data of a LINKED_MEDIA message for send_msg
+// format: media type (1 byte), optionally followed by the expiry timestamp (8 bytes, big endian, 0 if the file does not
+// expire) and the delete token (all remaining bytes)
+pub fn gen_linked_media_data(media_type: u8, expires_at: Option<u64>, delete_token: Option<&[u8]>) -> Vec<u8> {
+	let mut data = vec![media_type];
+	if expires_at.is_some() || delete_token.is_some() {
+		data.extend_from_slice(&expires_at.unwrap_or(0).to_be_bytes());
+		data.extend_from_slice(delete_token.unwrap_or(&[]));
+	}
+	data
+}
+
+// media type, expiry timestamp and delete token of a LINKED_MEDIA message
+type LinkedMediaData = (u8, Option<u64>, Option<Vec<u8>>);
+
+// parse the data of a LINKED_MEDIA message as returned by parse_msg
+// returns media type, expiry timestamp and delete token
+pub fn parse_linked_media_data(data: &[u8]) -> Result<LinkedMediaData, DawnError> {
+	let (media_type, rest) = match data.split_first() {
+		Some(res) => res,
+		None => error!("linked media data is missing the media type")
+	};
+	if rest.is_empty() { return Ok((*media_type, None, None)); }
+	
+	let (expires_at, delete_token) = match (rest.get(..8).map(<[u8; 8]>::try_from), rest.get(8..)) {
+		(Some(Ok(expires_at)), Some(delete_token)) => (expires_at, delete_token),
+		_ => error!(&format!("expected 8 bytes for the linked media expiry, got {} bytes", rest.len()))
+	};
+	let expires_at = match u64::from_be_bytes(expires_at) {
+		0 => None,
+		timestamp => Some(timestamp)
+	};
+	let delete_token = if delete_token.is_empty() { None } else { Some(delete_token.to_vec()) };
+	Ok((*media_type, expires_at, delete_token))
+}
+
+// the data covered by the signature of an upload ticket
+fn upload_ticket_content(blob_hash: &[u8], size: u64, expires_at: u64) -> Vec<u8> {
+	let mut content = blob_hash.to_vec();
+	content.extend_from_slice(&size.to_be_bytes());
+	content.extend_from_slice(&expires_at.to_be_bytes());
+	content
+}
+
+// generate an upload ticket for an encrypted file (as returned by encrypt_file)
+// returns the ticket that has to be handed to the content server together with the encrypted file
+pub fn gen_upload_ticket(encrypted_file: &[u8], expires_at: u64, own_seckey_sig: &[u8]) -> Result<String, DawnError> {
+	let blob_hash = hash(encrypted_file);
+	let size = encrypted_file.len() as u64;
+	let signature = match sign_detached(UPLOAD_TICKET_DOMAIN, &upload_ticket_content(&blob_hash, size, expires_at), own_seckey_sig) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let record = UploadTicketRecord {
+		blob_hash: encode(blob_hash),
+		size,
+		expires_at,
+		signature: encode(signature),
+	};
+	match serde_json::to_string(&record) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// parse an upload ticket and verify it was issued by the uploader (used by content servers)
+pub fn parse_upload_ticket(ticket: &str, uploader_pubkey_sig: &[u8]) -> Result<UploadTicket, DawnError> {
+	let record = match serde_json::from_str::<UploadTicketRecord>(ticket) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "upload ticket json parsing failed")
+	};
+	let blob_hash = match decode(&record.blob_hash) {
+		Ok(res) => res,
+		Err(_) => error!("upload ticket hash invalid")
+	};
+	let signature = match decode(&record.signature) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "upload ticket signature invalid")
+	};
+	if let Err(err) = verify_detached(UPLOAD_TICKET_DOMAIN, &upload_ticket_content(&blob_hash, record.size, record.expires_at), &signature, uploader_pubkey_sig) {
+		return Err(err);
+	}
+	Ok(UploadTicket {
+		blob_hash,
+		size: record.size,
+		expires_at: record.expires_at,
+	})
+}
+
+impl UploadTicket {
+	// check whether an uploaded blob is the one the ticket was issued for
+	pub fn matches(&self, blob: &[u8]) -> bool {
+		blob.len() as u64 == self.size && hash(blob) == self.blob_hash
+	}
+	
+	// check a blob that is too large to buffer chunk by chunk while it is uploaded
+	pub fn verifier(&self) -> HashVerifier {
+		HashVerifier::new(&self.blob_hash, Some(self.size))
+	}
+}
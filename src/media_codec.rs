@@ -0,0 +1,74 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Media codecs
+// Voice and picture messages can name the codec of their data (e.g. "opus" for voice, "webp" for pictures), so the
+// recipient knows what it gets before decoding. The library doesn't decode media itself, so clients announce the codecs
+// they can decode as capabilities (see InitRequestBuilder::media_codecs and accept_init_request_with_codecs) and senders
+// pick one of them with pick_codec. Peers that announced no codecs are assumed to decode the baseline codecs only. Codec
+// names are not limited to the constants below, so a new codec doesn't need a new version of the library.
+
+use crate::capability;
+use crate::DawnError;
+
+pub const MAX_CODEC_LEN: usize = 32;
+
+// voice messages
+pub const OPUS: &str = "opus";
+pub const AAC: &str = "aac";
+
+// pictures
+pub const JPEG: &str = "jpeg";
+pub const PNG: &str = "png";
+pub const WEBP: &str = "webp";
+pub const AVIF: &str = "avif";
+
+// codecs every client can decode
+pub const BASELINE: [&str; 3] = [OPUS, JPEG, PNG];
+
+// check the form of a codec name: lowercase letters, digits and hyphens
+pub(crate) fn check_codec(codec: &str) -> Result<(), DawnError> {
+	if codec.is_empty() || codec.len() > MAX_CODEC_LEN { error!("codec name must be between 1 and 32 characters"); }
+	if !codec.bytes().all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-') { error!("codec name invalid"); }
+	Ok(())
+}
+
+// capabilities of this library together with the codecs the client can decode, as announced in init requests and accepts
+pub(crate) fn announced_capabilities(codecs: &[&str]) -> Result<Vec<String>, DawnError> {
+	let mut capabilities = capability::supported();
+	for codec in codecs {
+		if let Err(err) = check_codec(codec) { return Err(err); }
+		capabilities.push(format!("{}{}", capability::MEDIA_CODEC_PREFIX, codec));
+	}
+	Ok(capabilities)
+}
+
+// the codecs a peer announced in its capabilities, peers that announced none decode the baseline codecs
+pub fn peer_codecs(capabilities: &[String]) -> Vec<String> {
+	let codecs: Vec<String> = capabilities.iter().filter_map(|capability| capability.strip_prefix(capability::MEDIA_CODEC_PREFIX)).map(|codec| codec.to_string()).collect();
+	match codecs.is_empty() {
+		true => BASELINE.iter().map(|codec| codec.to_string()).collect(),
+		false => codecs
+	}
+}
+
+// the first of the codecs the sender can encode (in order of preference) the peer can decode
+pub fn pick_codec<'a>(preferred: &[&'a str], capabilities: &[String]) -> Option<&'a str> {
+	let codecs = peer_codecs(capabilities);
+	preferred.iter().find(|codec| codecs.iter().any(|supported| supported == *codec)).copied()
+}
@@ -0,0 +1,90 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Conversation id namespaces
+// Conversation ids carry a prefix for the class of conversation they belong to. The prefix survives id rotation and is also
+// put in front of temp ids, so servers and clients can route or rate-limit by conversation class without decrypting anything.
+
+use dawn_crypto::{id_gen, get_next_id, get_custom_temp_id};
+use crate::DawnError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Namespace {
+	Direct,
+	Group,
+	Channel,
+	OwnDevices, // conversations between the own devices
+}
+
+impl Namespace {
+	pub fn prefix(&self) -> &'static str {
+		match self {
+			Namespace::Direct => "d-",
+			Namespace::Group => "g-",
+			Namespace::Channel => "c-",
+			Namespace::OwnDevices => "s-",
+		}
+	}
+}
+
+// split a namespaced id into its namespace and the id without prefix
+fn split_namespaced_id(id: &str) -> Result<(Namespace, &str), DawnError> {
+	let namespace = match id.get(..2) {
+		Some("d-") => Namespace::Direct,
+		Some("g-") => Namespace::Group,
+		Some("c-") => Namespace::Channel,
+		Some("s-") => Namespace::OwnDevices,
+		_ => error!("conversation id has no valid namespace prefix")
+	};
+	let raw_id = match id.get(2..) {
+		Some(res) if !res.is_empty() && res.bytes().all(|byte| byte.is_ascii_hexdigit()) => res,
+		_ => error!("conversation id invalid")
+	};
+	Ok((namespace, raw_id))
+}
+
+// generate a new conversation id in a namespace
+pub fn gen_namespaced_id(namespace: Namespace) -> String {
+	namespace.prefix().to_string() + &id_gen()
+}
+
+// returns the namespace of a conversation id
+pub fn parse_namespaced_id(id: &str) -> Result<Namespace, DawnError> {
+	split_namespaced_id(id).map(|(namespace, _)| namespace)
+}
+
+// derive the next conversation id (like get_next_id), staying in the namespace of the current one
+pub fn get_next_namespaced_id(id: &str, salt: &[u8]) -> Result<String, DawnError> {
+	let (namespace, raw_id) = match split_namespaced_id(id) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	match get_next_id(raw_id, salt) {
+		Ok(next_id) => Ok(namespace.prefix().to_string() + &next_id),
+		Err(err) => Err(DawnError::Crypto(err))
+	}
+}
+
+// derive the temp id of a conversation for a timestamp (like get_custom_temp_id), prefixed with the namespace
+pub fn get_namespaced_temp_id(id: &str, timestamp: u64) -> Result<String, DawnError> {
+	let (namespace, raw_id) = match split_namespaced_id(id) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	Ok(namespace.prefix().to_string() + &get_custom_temp_id(raw_id, timestamp))
+}
@@ -0,0 +1,125 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Nicknames
+// Every side can give the peer a local petname, which is stored with the session and never sent. A nickname is agreed on
+// by both sides instead: one side proposes how the peer should be shown to it and how it wants to be shown to the peer
+// (e.g. a child proposes "Mom" for the mother and "Dana" for itself), and the peer accepts or declines the proposal. Both
+// are INTERNAL messages with event::NICKNAME. resolve_display_name decides which name is shown: the local petname wins
+// over the mutual nickname, which wins over the name the peer chose for itself.
+
+use serde::{Serialize, Deserialize};
+use crate::DawnError;
+
+// the longest nickname that is accepted, in characters
+pub const MAX_NICKNAME_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NicknameProposal {
+	pub id: String,
+	pub recipient_nickname: Option<String>, // how the recipient is shown to the proposing side
+	pub sender_nickname: Option<String>, // how the proposing side wants to be shown to the recipient
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NicknameEvent {
+	Propose(NicknameProposal),
+	Accept(String), // id of the accepted proposal
+	Decline(String), // id of the declined proposal
+}
+
+// serialized form of a NicknameEvent
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum NicknameRecord {
+	Propose {
+		id: String,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		recipient_nickname: Option<String>,
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		sender_nickname: Option<String>,
+	},
+	Accept { id: String },
+	Decline { id: String },
+}
+
+fn check_nickname(nickname: Option<&str>) -> Result<(), DawnError> {
+	match nickname {
+		Some(nickname) if nickname.trim().is_empty() => error!("nickname must not be empty"),
+		Some(nickname) if nickname.chars().count() > MAX_NICKNAME_LEN => error!(&format!("nickname must not be longer than {} characters", MAX_NICKNAME_LEN)),
+		_ => Ok(())
+	}
+}
+
+fn check_event(event: &NicknameEvent) -> Result<(), DawnError> {
+	let id = match event {
+		NicknameEvent::Propose(proposal) => {
+			if proposal.recipient_nickname.is_none() && proposal.sender_nickname.is_none() { error!("nickname proposal contains no nickname"); }
+			if let Err(err) = check_nickname(proposal.recipient_nickname.as_deref()) { return Err(err); }
+			if let Err(err) = check_nickname(proposal.sender_nickname.as_deref()) { return Err(err); }
+			&proposal.id
+		},
+		NicknameEvent::Accept(id) | NicknameEvent::Decline(id) => id
+	};
+	if id.is_empty() { error!("nickname proposal id must not be empty"); }
+	Ok(())
+}
+
+// encode a nickname event as event data
+pub fn gen_nickname_event(event: &NicknameEvent) -> Result<Vec<u8>, DawnError> {
+	if let Err(err) = check_event(event) { return Err(err); }
+	let record = match event.clone() {
+		NicknameEvent::Propose(proposal) => NicknameRecord::Propose {
+			id: proposal.id,
+			recipient_nickname: proposal.recipient_nickname,
+			sender_nickname: proposal.sender_nickname,
+		},
+		NicknameEvent::Accept(id) => NicknameRecord::Accept { id },
+		NicknameEvent::Decline(id) => NicknameRecord::Decline { id }
+	};
+	match serde_json::to_vec(&record) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// parse the event data of a nickname event
+pub fn parse_nickname_event(data: &[u8]) -> Result<NicknameEvent, DawnError> {
+	let record = match serde_json::from_slice::<NicknameRecord>(data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "nickname event json parsing failed")
+	};
+	let event = match record {
+		NicknameRecord::Propose { id, recipient_nickname, sender_nickname } => NicknameEvent::Propose(NicknameProposal { id, recipient_nickname, sender_nickname }),
+		NicknameRecord::Accept { id } => NicknameEvent::Accept(id),
+		NicknameRecord::Decline { id } => NicknameEvent::Decline(id)
+	};
+	match check_event(&event) {
+		Ok(()) => Ok(event),
+		Err(err) => Err(err)
+	}
+}
+
+// the name to show for a peer: the local petname, else the mutual nickname, else the name the peer chose
+pub fn resolve_display_name<'a>(petname: Option<&'a str>, nickname: Option<&'a str>, name: &'a str) -> &'a str {
+	match (petname, nickname) {
+		(Some(petname), _) => petname,
+		(None, Some(nickname)) => nickname,
+		(None, None) => name
+	}
+}
@@ -0,0 +1,283 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Outgoing messages
+// send_msg takes the content of a message as (content type, text, data), whose meaning depends on the content type, e.g.
+// linked media packs link, key and description into lines of the text and media type, expiry and delete token into the
+// data. An OutgoingMessage is built with one constructor per content type that does this packing, and content() returns
+// it in the form send_msg and the other send functions take: send_msg(message.content(), ...)
+// Text and picture messages can carry an effect and pictures alt text, text, voice and picture messages can reply to
+// an earlier message or disappear and voice and picture messages can name their codec. These are sent by a Session or
+// with send_msg_with_options, whose SendOptions decide how the message is sent: wire format, configuration, thread,
+// escrow copy, device counter and sequence number. Any of them can be combined with any attribute of the message.
+
+use crate::content_type::{self, ContentType};
+use crate::media::gen_linked_media_data;
+use crate::effect::Effect;
+use crate::transcription::Transcription;
+use crate::reaction::{check_reaction, pack_reaction};
+use crate::retraction::check_retraction;
+use crate::reply::Reply;
+use crate::media_codec::check_codec;
+use crate::disappearing::check_expires_after;
+use crate::wire_format::WireFormat;
+use crate::config::ProtocolConfig;
+use crate::keys::KyberPublicKey;
+use crate::device_counter::{DeviceCounter, DeviceStamp};
+use crate::sequence::SequenceCounter;
+use crate::DawnError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutgoingMessage {
+	content_type: ContentType,
+	text: Option<String>,
+	data: Option<Vec<u8>>,
+	effect: Option<Effect>,
+	alt_text: Option<String>,
+	in_reply_to: Option<Reply>,
+	codec: Option<String>,
+	expires_after: Option<u64>,
+}
+
+impl OutgoingMessage {
+	// event code (see the event module) and event data
+	pub fn internal(event: u8, data: &[u8]) -> Self {
+		OutgoingMessage { content_type: content_type::INTERNAL, text: Some(event.to_string()), data: Some(data.to_vec()), effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None }
+	}
+	
+	pub fn text(text: &str) -> Self {
+		OutgoingMessage { content_type: content_type::TEXT, text: Some(text.to_string()), data: None, effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None }
+	}
+	
+	pub fn voice(data: &[u8]) -> Self {
+		OutgoingMessage { content_type: content_type::VOICE, text: None, data: Some(data.to_vec()), effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None }
+	}
+	
+	// voice message with a transcription for recipients that can't or don't want to listen to it
+	pub fn voice_with_transcription(data: &[u8], transcription: &Transcription) -> Self {
+		OutgoingMessage { content_type: content_type::VOICE, text: Some(transcription.pack()), data: Some(data.to_vec()), effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None }
+	}
+	
+	pub fn picture(data: &[u8], description: &str) -> Self {
+		OutgoingMessage { content_type: content_type::PICTURE, text: Some(description.to_string()), data: Some(data.to_vec()), effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None }
+	}
+	
+	// picture with alt text for screen readers, which is not shown like the description
+	pub fn picture_with_alt_text(data: &[u8], description: &str, alt_text: &str) -> Self {
+		OutgoingMessage { content_type: content_type::PICTURE, text: Some(description.to_string()), data: Some(data.to_vec()), effect: None, alt_text: Some(alt_text.to_string()), in_reply_to: None, codec: None, expires_after: None }
+	}
+	
+	// react to the message with the given message detail code, an empty reaction withdraws the previous one
+	pub fn reaction(target: &str, reaction: &str) -> Result<Self, DawnError> {
+		if let Err(err) = check_reaction(target, reaction) { return Err(err); }
+		Ok(OutgoingMessage { content_type: content_type::REACTION, text: Some(pack_reaction(target, reaction)), data: None, effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None })
+	}
+	
+	// ask the recipient to delete the own message with the given message detail code (see retraction.rs)
+	pub fn retraction(target: &str) -> Result<Self, DawnError> {
+		if let Err(err) = check_retraction(target) { return Err(err); }
+		Ok(OutgoingMessage { content_type: content_type::RETRACTION, text: Some(target.to_string()), data: None, effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None })
+	}
+	
+	// link to the file on the content server, its wrapped key (see wrap_media_key) and the content type of the file
+	// the expiry and delete token are optional (see gen_linked_media_data)
+	pub fn linked_media(link: &str, key: &str, description: &str, media_type: ContentType, expires_at: Option<u64>, delete_token: Option<&[u8]>) -> Result<Self, DawnError> {
+		if link.is_empty() || link.contains('\n') { error!("media link must be a single non-empty line"); }
+		if key.contains('\n') { error!("media key must be a single line"); }
+		Ok(OutgoingMessage {
+			content_type: content_type::LINKED_MEDIA,
+			text: Some(format!("{}\n{}\n{}", link, key, description)),
+			data: Some(gen_linked_media_data(media_type.into(), expires_at, delete_token)),
+			effect: None,
+			alt_text: None,
+			in_reply_to: None,
+			codec: None,
+			expires_after: None,
+		})
+	}
+	
+	// chunk header and chunk as returned by gen_history_chunks
+	pub fn history_chunk(header: &str, chunk: &[u8]) -> Self {
+		OutgoingMessage { content_type: content_type::HISTORY_SYNC, text: Some(header.to_string()), data: Some(chunk.to_vec()), effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None }
+	}
+	
+	// delta as returned by gen_delta_sync
+	pub fn delta_sync(delta: &[u8]) -> Self {
+		OutgoingMessage { content_type: content_type::DELTA_SYNC, text: None, data: Some(delta.to_vec()), effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None }
+	}
+	
+	// envelope as returned by gen_gateway_envelope and the data of the relayed message
+	pub fn gateway(envelope: &str, data: Option<&[u8]>) -> Self {
+		OutgoingMessage { content_type: content_type::GATEWAY, text: Some(envelope.to_string()), data: data.map(<[u8]>::to_vec), effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None }
+	}
+	
+	// quote an earlier message of the conversation (see reply.rs)
+	pub fn with_reply(mut self, in_reply_to: Reply) -> Result<Self, DawnError> {
+		if ![content_type::TEXT, content_type::VOICE, content_type::PICTURE].contains(&self.content_type) { error!("only text, voice and picture messages can reply to another message"); }
+		self.in_reply_to = Some(in_reply_to);
+		Ok(self)
+	}
+	
+	// name the codec of the voice message or picture (see media_codec.rs)
+	pub fn with_codec(mut self, codec: &str) -> Result<Self, DawnError> {
+		if self.content_type != content_type::VOICE && self.content_type != content_type::PICTURE { error!("only voice and picture messages can name a codec"); }
+		if let Err(err) = check_codec(codec) { return Err(err); }
+		self.codec = Some(codec.to_string());
+		Ok(self)
+	}
+	
+	// ask the recipient to delete the text, voice or picture message this many seconds after receiving it (see disappearing.rs)
+	pub fn with_expires_after(mut self, expires_after: u64) -> Result<Self, DawnError> {
+		if !self.can_disappear() { error!("only text, voice and picture messages can disappear"); }
+		if let Err(err) = check_expires_after(expires_after) { return Err(err); }
+		self.expires_after = Some(expires_after);
+		Ok(self)
+	}
+	
+	// ask the receiving client to play an effect when showing the message (see effect.rs)
+	pub fn with_effect(mut self, effect: Effect) -> Result<Self, DawnError> {
+		if self.content_type != content_type::TEXT && self.content_type != content_type::PICTURE { error!("only text and picture messages can have an effect"); }
+		self.effect = Some(effect);
+		Ok(self)
+	}
+	
+	pub fn alt_text(&self) -> Option<&str> {
+		self.alt_text.as_deref()
+	}
+	
+	pub fn in_reply_to(&self) -> Option<&Reply> {
+		self.in_reply_to.as_ref()
+	}
+	
+	pub fn codec(&self) -> Option<&str> {
+		self.codec.as_deref()
+	}
+	
+	pub fn expires_after(&self) -> Option<u64> {
+		self.expires_after
+	}
+	
+	pub(crate) fn can_disappear(&self) -> bool {
+		self.content_type == content_type::TEXT || self.content_type == content_type::VOICE || self.content_type == content_type::PICTURE
+	}
+	
+	pub fn effect(&self) -> Option<&Effect> {
+		self.effect.as_ref()
+	}
+	
+	pub fn content_type(&self) -> ContentType {
+		self.content_type
+	}
+	
+	// returns content type, text and data in the form send_msg takes
+	pub fn content(&self) -> (ContentType, Option<&str>, Option<&[u8]>) {
+		(self.content_type, self.text.as_deref(), self.data.as_deref())
+	}
+	
+	pub(crate) fn parts(&self) -> MessageParts<'_> {
+		MessageParts {
+			content: self.content(),
+			effect: self.effect.as_ref(),
+			alt_text: self.alt_text.as_deref(),
+			in_reply_to: self.in_reply_to.as_ref(),
+			codec: self.codec.as_deref(),
+			expires_after: self.expires_after,
+		}
+	}
+}
+
+// an outgoing message as it is serialized, borrowed from an OutgoingMessage or built from the content send_msg takes
+#[derive(Clone, Copy)]
+pub(crate) struct MessageParts<'a> {
+	pub(crate) content: (ContentType, Option<&'a str>, Option<&'a [u8]>),
+	pub(crate) effect: Option<&'a Effect>,
+	pub(crate) alt_text: Option<&'a str>,
+	pub(crate) in_reply_to: Option<&'a Reply>,
+	pub(crate) codec: Option<&'a str>,
+	pub(crate) expires_after: Option<u64>,
+}
+
+impl<'a> MessageParts<'a> {
+	pub(crate) fn from_content(content: (ContentType, Option<&'a str>, Option<&'a [u8]>)) -> Self {
+		MessageParts { content, effect: None, alt_text: None, in_reply_to: None, codec: None, expires_after: None }
+	}
+}
+
+// how a message is sent, the default sends it as JSON without any of the options
+#[derive(Clone, Debug, Default)]
+pub struct SendOptions<'a> {
+	pub(crate) format: WireFormat,
+	pub(crate) config: ProtocolConfig,
+	pub(crate) thread_id: Option<&'a str>,
+	pub(crate) escrow_pubkey_kyber: Option<&'a KyberPublicKey>,
+	pub(crate) device: Option<DeviceStamp>,
+	pub(crate) seq: Option<u64>,
+	pub(crate) counter: Option<u64>, // counter of the message chain (see chain.rs)
+}
+
+impl<'a> SendOptions<'a> {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// send the message in the given wire format (see WireFormat::for_peer)
+	pub fn format(mut self, format: WireFormat) -> Self {
+		self.format = format;
+		self
+	}
+	
+	// pad and compress the message according to the configuration
+	// compress only for peers that announced capability::COMPRESSION
+	pub fn config(mut self, config: ProtocolConfig) -> Self {
+		self.config = config;
+		self
+	}
+	
+	// put the message into a thread (see thread_id_for)
+	// only text, voice, picture and linked media messages can be part of a thread
+	pub fn thread(mut self, thread_id: &'a str) -> Self {
+		self.thread_id = Some(thread_id);
+		self
+	}
+	
+	// attach an escrow copy that the organization holding the escrow key can decrypt (see escrow_status)
+	// only for deployments that require escrow, the recipients can see that the message was escrowed
+	pub fn escrow(mut self, escrow_pubkey_kyber: &'a KyberPublicKey) -> Self {
+		self.escrow_pubkey_kyber = Some(escrow_pubkey_kyber);
+		self
+	}
+	
+	// carry the next value of the device counter (see CounterTracker)
+	// the message should be signed, so the counter can't be altered by anyone but the sender
+	pub fn counted(mut self, counter: &mut DeviceCounter) -> Result<Self, DawnError> {
+		self.device = match counter.next() {
+			Ok(res) => Some(res),
+			Err(err) => return Err(err)
+		};
+		Ok(self)
+	}
+	
+	// carry the next sequence number of the conversation (see SequenceTracker)
+	pub fn sequenced(mut self, counter: &mut SequenceCounter) -> Result<Self, DawnError> {
+		self.seq = match counter.next() {
+			Ok(res) => Some(res),
+			Err(err) => return Err(err)
+		};
+		Ok(self)
+	}
+}
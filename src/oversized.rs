@@ -0,0 +1,70 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Oversized inline media
+// Receivers can limit the size of inline media (see ParseLimits). A voice message or picture above the limit is not
+// rejected with a generic error: parsing fails with DawnError::TooLarge, which describes the media, so the UI can show a
+// placeholder (e.g. "picture of 4 MB, too large for this device") and ask the sender to send it again as linked media.
+// The request is an INTERNAL message with event::MEDIA_RESEND (see OversizedMedia::resend_request). The sender answers
+// it by uploading the media (see encrypt_file) and sending it as a LINKED_MEDIA message.
+
+use serde::{Serialize, Deserialize};
+use crate::content_type::ContentType;
+use crate::outgoing::OutgoingMessage;
+use crate::event;
+use crate::DawnError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OversizedMedia {
+	pub content_type: ContentType,
+	pub size: usize, // in bytes
+	pub limit: usize, // the max_data_len of the receiver
+	pub description: Option<String>, // description of a picture
+	pub mdc: String,
+}
+
+// event data of a resend request
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MediaResendRequest {
+	pub mdc: String, // message detail code of the media message
+	pub max_inline_size: usize, // largest media the receiver accepts inline
+}
+
+impl OversizedMedia {
+	// ask the sender to send the media again as linked media
+	pub fn resend_request(&self) -> Result<OutgoingMessage, DawnError> {
+		let request = MediaResendRequest {
+			mdc: self.mdc.clone(),
+			max_inline_size: self.limit,
+		};
+		match serde_json::to_vec(&request) {
+			Ok(res) => Ok(OutgoingMessage::internal(event::MEDIA_RESEND, &res)),
+			Err(_) => error!(Serialization, "json serialization failed")
+		}
+	}
+}
+
+// parse the event data of a resend request
+pub fn parse_media_resend_request(data: &[u8]) -> Result<MediaResendRequest, DawnError> {
+	let request = match serde_json::from_slice::<MediaResendRequest>(data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "media resend request json parsing failed")
+	};
+	if request.mdc.is_empty() { error!("media resend request is missing the mdc"); }
+	Ok(request)
+}
@@ -0,0 +1,153 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Send pacing
+// Servers declare how fast an account may send: a burst of envelopes that can go out at once and a sustained number per
+// minute. Bulk operations like a history transfer easily exceed that and get the account throttled or banned. A
+// SendPacer is the send queue of a client: envelopes are queued as they are produced and only handed out to the
+// transport as fast as the limits allow (a token bucket that holds burst tokens and refills per_minute tokens a minute).
+// When the queue is full enqueue fails, so producers slow down instead of buffering without bound, and wait_time tells
+// the transport when to try again. The pacer reads the time from a Clock.
+
+use std::collections::VecDeque;
+use crate::clock::{Clock, SystemClock};
+use crate::DawnError;
+
+// the bucket is kept in units of 1/60 token, so refilling per_minute tokens a minute needs no fractions
+const UNITS_PER_TOKEN: u64 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimits {
+	pub burst: u32, // envelopes that can be sent at once
+	pub per_minute: u32, // envelopes that can be sent a minute in the long run
+}
+
+// an envelope waiting to be sent (temp id, mdc and ciphertext as returned by the send functions)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PacedEnvelope {
+	pub temp_id: String,
+	pub mdc: String,
+	pub ciphertext: Vec<u8>,
+}
+
+pub struct SendPacer {
+	limits: RateLimits,
+	queue: VecDeque<PacedEnvelope>,
+	max_queued: usize,
+	units: u64, // tokens in the bucket, in units
+	refilled_at: u64,
+	blocked_until: u64, // set when the server throttled the account anyway
+	clock: Box<dyn Clock>,
+}
+
+impl RateLimits {
+	pub fn new(burst: u32, per_minute: u32) -> Result<Self, DawnError> {
+		if burst == 0 || per_minute == 0 { error!("rate limits must allow sending"); }
+		Ok(RateLimits { burst, per_minute })
+	}
+	
+	fn capacity(&self) -> u64 {
+		self.burst as u64 * UNITS_PER_TOKEN
+	}
+}
+
+impl SendPacer {
+	// start with a full bucket, so a burst can be sent right away
+	pub fn new(limits: RateLimits) -> Self {
+		let clock = SystemClock;
+		SendPacer {
+			limits,
+			queue: VecDeque::new(),
+			max_queued: 1000,
+			units: limits.capacity(),
+			refilled_at: clock.now(),
+			blocked_until: 0,
+			clock: Box::new(clock),
+		}
+	}
+	
+	// queue at most this many envelopes (1000 by default)
+	pub fn max_queued(mut self, max_queued: usize) -> Self {
+		self.max_queued = max_queued;
+		self
+	}
+	
+	// use another clock than the system clock (e.g. a ManualClock in tests)
+	pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+		self.refilled_at = clock.now();
+		self.clock = Box::new(clock);
+		self
+	}
+	
+	// apply limits the server declared later on, tokens beyond the new burst are dropped
+	pub fn set_limits(&mut self, limits: RateLimits) {
+		self.refill();
+		self.limits = limits;
+		self.units = self.units.min(limits.capacity());
+	}
+	
+	pub fn limits(&self) -> RateLimits {
+		self.limits
+	}
+	
+	pub fn queued(&self) -> usize {
+		self.queue.len()
+	}
+	
+	// queue an envelope
+	// fails if the queue is full, the producer has to wait for wait_time before queueing more
+	pub fn enqueue(&mut self, envelope: PacedEnvelope) -> Result<(), DawnError> {
+		if self.queue.len() >= self.max_queued { error!("send queue is full"); }
+		self.queue.push_back(envelope);
+		Ok(())
+	}
+	
+	// returns the next envelope if it may be sent now
+	pub fn next_ready(&mut self) -> Option<PacedEnvelope> {
+		if self.queue.is_empty() || self.wait_time() != Some(0) { return None; }
+		self.units -= UNITS_PER_TOKEN;
+		self.queue.pop_front()
+	}
+	
+	// returns the seconds until the next envelope may be sent, or None if nothing is queued
+	pub fn wait_time(&mut self) -> Option<u64> {
+		if self.queue.is_empty() { return None; }
+		self.refill();
+		// the bucket doesn't refill while the account is blocked
+		let blocked = self.blocked_until.saturating_sub(self.clock.now());
+		let missing = UNITS_PER_TOKEN.saturating_sub(self.units);
+		Some(blocked.saturating_add(missing.div_ceil(self.limits.per_minute as u64)))
+	}
+	
+	// the server throttled the account anyway (e.g. because another device sent as well)
+	// nothing is handed out for retry_after seconds and the bucket starts empty afterwards
+	pub fn throttled(&mut self, retry_after: u64) {
+		self.refill();
+		self.blocked_until = self.clock.now().saturating_add(retry_after);
+		self.units = 0;
+		self.refilled_at = self.blocked_until;
+	}
+	
+	fn refill(&mut self) {
+		let now = self.clock.now();
+		if now <= self.refilled_at { return; }
+		let added = (now - self.refilled_at).saturating_mul(self.limits.per_minute as u64);
+		self.units = self.units.saturating_add(added).min(self.limits.capacity());
+		self.refilled_at = now;
+	}
+}
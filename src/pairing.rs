@@ -0,0 +1,152 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Pairing over a short code
+// Two users who meet in person can start a conversation without publishing a handle: one of them displays a short
+// numeric code, the other one enters it, and both run a password-authenticated key exchange (CPace over ristretto255)
+// bound to the code. The resulting key is only shared if both entered the same code, and an attacker in the middle gets
+// a single guess per run instead of being able to test codes offline. Both sides then send their handle sealed with the
+// key, so the usual init request can be sent to authentic keys.
+
+use curve25519_dalek::ristretto::{RistrettoPoint, CompressedRistretto};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
+use sha2::{Sha512, Digest};
+use dawn_crypto::{sym_key_gen, encrypt_data, decrypt_data};
+use crate::secret::SecretBytes;
+use crate::domain::{PAIRING_GENERATOR_DOMAIN, PAIRING_KEY_DOMAIN};
+use crate::DawnError;
+
+const PAIRING_CODE_LEN: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PairingRole {
+	Initiator, // displays the code
+	Responder, // enters the code
+}
+
+pub struct Pairing {
+	role: PairingRole,
+	scalar: Scalar,
+	own_element: [u8; 32],
+}
+
+// key shared after a pairing, with one half for each direction
+pub struct PairingKey {
+	own_key: SecretBytes,
+	remote_key: SecretBytes,
+}
+
+// hash the parts with their lengths in front of them
+fn hash_parts(domain: &str, parts: &[&[u8]]) -> [u8; 64] {
+	let mut hasher = Sha512::new();
+	hasher.update(domain.as_bytes());
+	for part in parts {
+		hasher.update((part.len() as u64).to_be_bytes());
+		hasher.update(part);
+	}
+	hasher.finalize().into()
+}
+
+// random 64 bytes from the randomness of dawn-crypto
+fn random_wide() -> Result<[u8; 64], DawnError> {
+	let mut bytes = sym_key_gen();
+	bytes.extend(sym_key_gen());
+	match <[u8; 64]>::try_from(bytes) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Crypto, "random number generation failed")
+	}
+}
+
+// strip separators users might type, so "123-456" and "123 456" are the same code
+fn normalize_code(code: &str) -> Result<String, DawnError> {
+	let code: String = code.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+	if code.len() < PAIRING_CODE_LEN || !code.bytes().all(|byte| byte.is_ascii_digit()) { error!("pairing code invalid"); }
+	Ok(code)
+}
+
+// generate a random code to display to the other user
+pub fn gen_pairing_code() -> Result<String, DawnError> {
+	let random = match random_wide() {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	// 64 random bytes reduced modulo 10^6, the bias is negligible
+	let number = random.iter().fold(0u64, |number, byte| (number * 256 + *byte as u64) % 10u64.pow(PAIRING_CODE_LEN as u32));
+	Ok(format!("{:0width$}", number, width = PAIRING_CODE_LEN))
+}
+
+impl Pairing {
+	// start a pairing with the code both users see
+	// returns the pairing and the message to send to the other side
+	pub fn start(code: &str, role: PairingRole) -> Result<(Self, Vec<u8>), DawnError> {
+		let code = match normalize_code(code) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let generator = RistrettoPoint::from_uniform_bytes(&hash_parts(PAIRING_GENERATOR_DOMAIN, &[code.as_bytes()]));
+		let scalar = match random_wide() {
+			Ok(res) => Scalar::from_bytes_mod_order_wide(&res),
+			Err(err) => return Err(err)
+		};
+		let own_element = (generator * scalar).compress().to_bytes();
+		Ok((Pairing { role, scalar, own_element }, own_element.to_vec()))
+	}
+	
+	// finish the pairing with the message of the other side and seal the own handle with the shared key
+	// returns the key and the sealed handle to send to the other side
+	pub fn finish(self, remote_msg: &[u8], own_handle: &[u8]) -> Result<(PairingKey, Vec<u8>), DawnError> {
+		let remote_element = match <[u8; 32]>::try_from(remote_msg) {
+			Ok(res) => res,
+			Err(_) => error!("pairing message invalid")
+		};
+		let remote_point = match CompressedRistretto(remote_element).decompress() {
+			Some(res) if !res.is_identity() => res,
+			_ => error!("pairing message invalid")
+		};
+		let shared = (remote_point * self.scalar).compress().to_bytes();
+		
+		// the transcript puts the message of the initiator first, so both sides derive the same key
+		let (initiator_element, responder_element) = match self.role {
+			PairingRole::Initiator => (self.own_element, remote_element),
+			PairingRole::Responder => (remote_element, self.own_element),
+		};
+		let key = hash_parts(PAIRING_KEY_DOMAIN, &[&shared, &initiator_element, &responder_element]);
+		let (initiator_key, responder_key) = key.split_at(32);
+		let key = match self.role {
+			PairingRole::Initiator => PairingKey { own_key: SecretBytes::new(initiator_key), remote_key: SecretBytes::new(responder_key) },
+			PairingRole::Responder => PairingKey { own_key: SecretBytes::new(responder_key), remote_key: SecretBytes::new(initiator_key) },
+		};
+		let sealed_handle = match encrypt_data(own_handle, key.own_key.as_bytes()) {
+			Ok(res) => res,
+			Err(err) => { error!(Crypto, &format!("handle encryption failed: {}", err)); }
+		};
+		Ok((key, sealed_handle))
+	}
+}
+
+impl PairingKey {
+	// open the handle sealed by the other side
+	// this fails if the users entered different codes or someone interfered with the pairing
+	pub fn open_handle(&self, sealed_handle: &[u8]) -> Result<Vec<u8>, DawnError> {
+		match decrypt_data(sealed_handle, self.remote_key.as_bytes()) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Crypto, "pairing failed, the codes don't match")
+		}
+	}
+}
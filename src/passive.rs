@@ -0,0 +1,113 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Passive sessions
+// Archival clients (e.g. a desktop viewer of the conversations of a phone) get a snapshot of the receiving side of a
+// conversation from the primary device and decrypt the incoming messages the primary device shares with them. A
+// PassiveSession keeps its own copy of the PFS key, which it never hands out, and has no way to send, so it can't
+// advance the ratchet of the primary device or make the conversation partner advance theirs.
+// The passive session of a Session gets a copy of its receiving message chain instead, including the messages it holds,
+// and holds chained messages that arrive early like the session does.
+
+use crate::keys::{KyberSecretKey, SignPublicKey};
+use crate::limits::ParseLimits;
+use crate::config::ProtocolConfig;
+use crate::secret::SecretBytes;
+use crate::warning::Warning;
+use crate::chain::ReceiveChain;
+use crate::{parse_msg_with_config, parse_chain_msg, parse_held_chain_msg};
+use crate::received::ReceivedMessage;
+use crate::DawnError;
+
+// receiving state of a passive session
+enum PassiveKeys {
+	Linear(SecretBytes), // pfs key of the next message, for messages sent with send_msg
+	Chain(ReceiveChain), // for messages sent with send_chain_msg (e.g. by a Session)
+}
+
+pub struct PassiveSession {
+	own_seckey_kyber: KyberSecretKey,
+	remote_pubkey_sig: Option<SignPublicKey>,
+	keys: PassiveKeys,
+	pfs_salt: SecretBytes,
+	config: ProtocolConfig,
+}
+
+impl PassiveSession {
+	// create a passive session from the receiving state of the primary device
+	// pfs_key is the key the primary device will use to parse the next incoming message
+	pub fn new(own_seckey_kyber: KyberSecretKey, remote_pubkey_sig: Option<SignPublicKey>, pfs_key: &[u8], pfs_salt: &[u8]) -> Self {
+		PassiveSession {
+			own_seckey_kyber,
+			remote_pubkey_sig,
+			keys: PassiveKeys::Linear(SecretBytes::new(pfs_key)),
+			pfs_salt: SecretBytes::new(pfs_salt),
+			config: ProtocolConfig::default(),
+		}
+	}
+	
+	// create a passive session from a copy of the receiving chain of the primary device
+	pub(crate) fn from_chain(own_seckey_kyber: KyberSecretKey, remote_pubkey_sig: Option<SignPublicKey>, chain: ReceiveChain, pfs_salt: &[u8]) -> Self {
+		PassiveSession {
+			own_seckey_kyber,
+			remote_pubkey_sig,
+			keys: PassiveKeys::Chain(chain),
+			pfs_salt: SecretBytes::new(pfs_salt),
+			config: ProtocolConfig::default(),
+		}
+	}
+	
+	// reject messages exceeding the limits (see ParseLimits::low_memory)
+	pub fn limits(mut self, limits: ParseLimits) -> Self {
+		self.config.limits = limits;
+		self
+	}
+	
+	// parse messages according to the configuration of the primary device (see ProtocolConfig)
+	pub fn config(mut self, config: ProtocolConfig) -> Self {
+		self.config = config;
+		self
+	}
+	
+	// decrypt the next incoming message, advancing only the key of this passive session
+	// a chained message that arrives before earlier ones is held and returns None (see parse_held)
+	// returns the message, message detail code and warning
+	pub fn parse(&mut self, msg_ciphertext: &[u8]) -> Result<Option<(ReceivedMessage, String, Warning)>, DawnError> {
+		let remote_pubkey_sig = self.remote_pubkey_sig.as_ref().map(|pubkey| pubkey.as_bytes());
+		let pfs_key = match &mut self.keys {
+			PassiveKeys::Linear(pfs_key) => pfs_key,
+			PassiveKeys::Chain(chain) => return parse_chain_msg(chain, msg_ciphertext, self.own_seckey_kyber.as_bytes(), remote_pubkey_sig, self.pfs_salt.as_bytes(), &self.config)
+		};
+		let parsed = match parse_msg_with_config(msg_ciphertext, self.own_seckey_kyber.as_bytes(), remote_pubkey_sig, pfs_key.as_bytes(), self.pfs_salt.as_bytes(), &self.config) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		*pfs_key = SecretBytes::new(&parsed.new_pfs_key);
+		Ok(Some((parsed.message, parsed.mdc, parsed.warning)))
+	}
+	
+	// decrypt the next held chained message, if the messages before it arrived
+	// returns the same as parse
+	pub fn parse_held(&mut self) -> Result<Option<(ReceivedMessage, String, Warning)>, DawnError> {
+		let remote_pubkey_sig = self.remote_pubkey_sig.as_ref().map(|pubkey| pubkey.as_bytes());
+		match &mut self.keys {
+			PassiveKeys::Linear(_) => Ok(None),
+			PassiveKeys::Chain(chain) => parse_held_chain_msg(chain, self.own_seckey_kyber.as_bytes(), remote_pubkey_sig, self.pfs_salt.as_bytes(), &self.config)
+		}
+	}
+}
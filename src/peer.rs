@@ -0,0 +1,90 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Remote peer
+// Everything known about the other side of a conversation, as returned by parse_init_request and parse_init_response.
+
+use dawn_crypto::{derive_security_number, hash};
+use crate::codec::{encode, decode};
+use crate::keys::{KyberPublicKey, SignPublicKey};
+use crate::security_encoding::{SecurityEncodings, encode_security_number};
+use crate::domain::IDENTITY_FINGERPRINT_DOMAIN;
+use crate::DawnError;
+
+const FINGERPRINT_LEN: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verification {
+	Unverified,
+	Verified, // the security number was compared successfully
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Peer {
+	pub pubkey_kyber: KyberPublicKey,
+	pub pubkey_sig: SignPublicKey,
+	pub name: String,
+	pub verification: Verification,
+	pub capabilities: Vec<String>,
+}
+
+impl Peer {
+	// create a peer from the hex encoded keys of an init message
+	pub(crate) fn from_encoded(pubkey_kyber: &str, pubkey_sig: &str, name: &str, capabilities: Vec<String>) -> Result<Self, DawnError> {
+		let pubkey_kyber = match decode(pubkey_kyber).map(KyberPublicKey::try_from) {
+			Ok(Ok(res)) => res,
+			_ => error!("remote kyber pubkey invalid")
+		};
+		let pubkey_sig = match decode(pubkey_sig).map(SignPublicKey::try_from) {
+			Ok(Ok(res)) => res,
+			_ => error!("remote signature pubkey invalid")
+		};
+		Ok(Peer {
+			pubkey_kyber,
+			pubkey_sig,
+			name: name.to_string(),
+			verification: Verification::Unverified,
+			capabilities,
+		})
+	}
+	
+	// check whether the peer announced a capability (see the capability module)
+	pub fn supports(&self, capability: &str) -> bool {
+		self.capabilities.iter().any(|supported| supported == capability)
+	}
+	
+	// short hex fingerprint of the signature key, which identifies the same identity across conversations
+	// unlike the security number it doesn't depend on the own keys, so it can be shown before a conversation exists
+	pub fn fingerprint(&self) -> String {
+		let mut input = IDENTITY_FINGERPRINT_DOMAIN.as_bytes().to_vec();
+		input.extend_from_slice(self.pubkey_sig.as_bytes());
+		let mut fingerprint = hash(&input);
+		fingerprint.truncate(FINGERPRINT_LEN);
+		encode(fingerprint)
+	}
+	
+	// derive the security number that has to be compared with the peer to verify it
+	pub fn security_number(&self, own_pubkey_kyber: &[u8]) -> Result<String, DawnError> {
+		derive_security_number(own_pubkey_kyber, self.pubkey_kyber.as_bytes()).map_err(DawnError::Crypto)
+	}
+	
+	// derive the emoji and spoken representations of the security number (see encode_security_number)
+	pub fn security_encodings(&self, own_pubkey_kyber: &[u8]) -> Result<SecurityEncodings, DawnError> {
+		self.security_number(own_pubkey_kyber).map(|security_number| encode_security_number(&security_number))
+	}
+}
@@ -0,0 +1,94 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// One-time prekeys and their reconciliation with the server
+// One-time prekeys are kyber keys that are uploaded to the server in batches (see gen_prekeys), their secrets are kept in
+// the NAMESPACE_PREKEYS namespace of the storage, keyed by prekey id.
+// The server hands each prekey out once and only reports the ones it still holds, so the two sides drift apart as peers
+// start conversations. reconcile_prekeys compares them: prekeys the server handed out were consumed and their secrets can
+// be deleted once the init request that used them was processed, and the server has to be refilled up to a target.
+// The library has no connection to the server itself, clients implement PrekeyServer on top of theirs.
+
+use std::collections::BTreeSet;
+use dawn_crypto::id_gen;
+use crate::keys::{KyberPublicKey, gen_kyber_keypair};
+use crate::storage::{Storage, NAMESPACE_PREKEYS, with_transaction};
+use crate::DawnError;
+
+pub trait PrekeyServer {
+	// returns the ids of the one-time prekeys the server still holds for the account
+	fn remaining_prekeys(&mut self) -> Result<Vec<String>, DawnError>;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrekeyReconciliation {
+	pub delete: Vec<String>, // ids of consumed prekeys whose secrets can be deleted from the storage
+	pub unknown: Vec<String>, // ids the server holds without a local secret, they are useless and should be removed from the server
+	pub generate: usize, // number of new prekeys to generate and upload
+}
+
+// generate one-time prekeys and store their secrets, all of them or none
+// returns the ids and public keys of the new prekeys, which have to be uploaded to the server
+pub fn gen_prekeys<S: Storage + ?Sized>(storage: &mut S, count: usize) -> Result<Vec<(String, KyberPublicKey)>, DawnError> {
+	let mut prekeys = Vec::with_capacity(count);
+	let mut secrets = Vec::with_capacity(count);
+	for _ in 0..count {
+		let (pubkey_kyber, seckey_kyber) = match gen_kyber_keypair() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let id = id_gen();
+		secrets.push((id.clone(), seckey_kyber));
+		prekeys.push((id, pubkey_kyber));
+	}
+	let stored = with_transaction(storage, |storage| {
+		for (id, seckey_kyber) in &secrets {
+			if let Err(err) = storage.put(NAMESPACE_PREKEYS, id, seckey_kyber.as_bytes()) { return Err(err); }
+		}
+		Ok(())
+	});
+	match stored {
+		Ok(()) => Ok(prekeys),
+		Err(err) => Err(err)
+	}
+}
+
+// compare the locally stored prekeys with the ones the server still holds
+// in_use are the prekey ids of init requests that were fetched but not processed yet, their secrets are still needed
+// returns what has to be deleted locally and on the server and how many prekeys to upload to have target on the server
+pub fn reconcile_prekeys<S: Storage + ?Sized, P: PrekeyServer + ?Sized>(storage: &S, server: &mut P, in_use: &[String], target: usize) -> Result<PrekeyReconciliation, DawnError> {
+	let local: BTreeSet<String> = match storage.list(NAMESPACE_PREKEYS) {
+		Ok(res) => res.into_iter().collect(),
+		Err(err) => return Err(err)
+	};
+	let remaining: BTreeSet<String> = match server.remaining_prekeys() {
+		Ok(res) => res.into_iter().collect(),
+		Err(err) => return Err(err)
+	};
+	let delete = local.difference(&remaining)
+		.filter(|id| !in_use.contains(id))
+		.cloned()
+		.collect();
+	let unknown = remaining.difference(&local).cloned().collect();
+	let usable = remaining.intersection(&local).count();
+	Ok(PrekeyReconciliation {
+		delete,
+		unknown,
+		generate: target.saturating_sub(usable),
+	})
+}
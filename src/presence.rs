@@ -0,0 +1,181 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Read horizons and typing indicators
+// Presence updates are tiny, so they get a compact binary encoding instead of JSON: one byte for the kind of update,
+// followed by the read counter as LEB128 varint or the typing state as one byte. They are sent as INTERNAL messages with
+// event::PRESENCE. A PresenceAggregator keeps the latest state of every member for the UI, which makes it usable for
+// conversations with many members, where one indicator per message would be too much.
+// Clients that only need typing indicators use gen_typing_started/gen_typing_stopped and parse_typing_event. A started
+// indicator expires TYPING_TIMEOUT seconds after it was received, so it disappears even if the stop event gets lost;
+// senders refresh it every TYPING_REFRESH_INTERVAL seconds while the user keeps typing.
+
+use std::collections::HashMap;
+use crate::event;
+use crate::outgoing::OutgoingMessage;
+use crate::received::ReceivedMessage;
+use crate::DawnError;
+
+const KIND_READ_HORIZON: u8 = 0;
+const KIND_TYPING: u8 = 1;
+
+// typing indicators expire if they are not refreshed within this time (in seconds)
+pub const TYPING_TIMEOUT: u64 = 10;
+
+// senders repeat gen_typing_started this often (in seconds), so the indicator doesn't expire while the user types
+pub const TYPING_REFRESH_INTERVAL: u64 = TYPING_TIMEOUT / 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypingEvent {
+	Started { expires_at: u64 }, // hide the indicator at this time unless it was refreshed before
+	Stopped,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresenceUpdate {
+	ReadUpTo(u64), // all messages up to this counter were read
+	Typing(bool),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct MemberPresence {
+	read_up_to: Option<u64>,
+	typing_since: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct PresenceAggregator {
+	members: HashMap<String, MemberPresence>,
+}
+
+// encode a presence update as event data
+pub fn gen_presence_update(update: PresenceUpdate) -> Vec<u8> {
+	match update {
+		PresenceUpdate::ReadUpTo(counter) => {
+			let mut data = vec![KIND_READ_HORIZON];
+			let mut rest = counter;
+			while rest >= 0x80 {
+				data.push((rest as u8 & 0x7f) | 0x80);
+				rest >>= 7;
+			}
+			data.push(rest as u8);
+			data
+		},
+		PresenceUpdate::Typing(typing) => vec![KIND_TYPING, typing as u8]
+	}
+}
+
+// parse the event data of a presence update
+pub fn parse_presence_update(data: &[u8]) -> Result<PresenceUpdate, DawnError> {
+	match data.split_first() {
+		Some((&KIND_READ_HORIZON, varint)) => {
+			let mut counter: u64 = 0;
+			for (index, byte) in varint.iter().enumerate() {
+				// a u64 has at most 10 varint bytes, the last one only contributes a single bit
+				if index > 9 || (index == 9 && *byte > 1) { error!("read horizon out of range"); }
+				counter |= ((byte & 0x7f) as u64) << (7 * index);
+				if byte & 0x80 == 0 {
+					if index + 1 != varint.len() { error!("presence update has trailing data"); }
+					return Ok(PresenceUpdate::ReadUpTo(counter));
+				}
+			}
+			error!("read horizon is incomplete")
+		},
+		Some((&KIND_TYPING, [0])) => Ok(PresenceUpdate::Typing(false)),
+		Some((&KIND_TYPING, [1])) => Ok(PresenceUpdate::Typing(true)),
+		_ => error!("presence update invalid")
+	}
+}
+
+// tell the peer that the user started typing (or is still typing, see TYPING_REFRESH_INTERVAL)
+pub fn gen_typing_started() -> OutgoingMessage {
+	OutgoingMessage::internal(event::PRESENCE, &gen_presence_update(PresenceUpdate::Typing(true)))
+}
+
+// tell the peer that the user stopped typing
+pub fn gen_typing_stopped() -> OutgoingMessage {
+	OutgoingMessage::internal(event::PRESENCE, &gen_presence_update(PresenceUpdate::Typing(false)))
+}
+
+// read a received message as typing indicator, received_at is the time it arrived
+// returns None for other messages, including presence updates that are not about typing
+pub fn parse_typing_event(message: &ReceivedMessage, received_at: u64) -> Result<Option<TypingEvent>, DawnError> {
+	let data = match message {
+		ReceivedMessage::Internal { event: event::PRESENCE, data } => data,
+		_ => return Ok(None)
+	};
+	match parse_presence_update(data) {
+		Ok(PresenceUpdate::Typing(true)) => Ok(Some(TypingEvent::Started { expires_at: received_at.saturating_add(TYPING_TIMEOUT) })),
+		Ok(PresenceUpdate::Typing(false)) => Ok(Some(TypingEvent::Stopped)),
+		Ok(PresenceUpdate::ReadUpTo(_)) => Ok(None),
+		Err(err) => Err(err)
+	}
+}
+
+impl PresenceAggregator {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// apply an update received from a member at the given time
+	// read horizons only move forward, so updates that arrive out of order don't move them back
+	pub fn apply(&mut self, member: &str, update: PresenceUpdate, timestamp: u64) {
+		let presence = self.members.entry(member.to_string()).or_default();
+		match update {
+			PresenceUpdate::ReadUpTo(counter) => {
+				if presence.read_up_to.is_none_or(|read_up_to| counter > read_up_to) {
+					presence.read_up_to = Some(counter);
+				}
+				// reading the conversation ends typing
+				presence.typing_since = None;
+			},
+			PresenceUpdate::Typing(true) => presence.typing_since = Some(timestamp),
+			PresenceUpdate::Typing(false) => presence.typing_since = None
+		}
+	}
+	
+	// forget a member that left the conversation
+	pub fn remove(&mut self, member: &str) {
+		self.members.remove(member);
+	}
+	
+	// returns the counter up to which a member has read the conversation
+	pub fn read_up_to(&self, member: &str) -> Option<u64> {
+		self.members.get(member).and_then(|presence| presence.read_up_to)
+	}
+	
+	// returns the members that have read the message with the given counter, sorted by name
+	pub fn read_by(&self, counter: u64) -> Vec<&str> {
+		let mut members: Vec<&str> = self.members.iter()
+			.filter(|(_, presence)| presence.read_up_to.is_some_and(|read_up_to| read_up_to >= counter))
+			.map(|(member, _)| member.as_str())
+			.collect();
+		members.sort_unstable();
+		members
+	}
+	
+	// returns the members that are typing at the given time, sorted by name
+	pub fn typing(&self, now: u64) -> Vec<&str> {
+		let mut members: Vec<&str> = self.members.iter()
+			.filter(|(_, presence)| presence.typing_since.is_some_and(|since| now < since.saturating_add(TYPING_TIMEOUT)))
+			.map(|(member, _)| member.as_str())
+			.collect();
+		members.sort_unstable();
+		members
+	}
+}
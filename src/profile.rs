@@ -0,0 +1,96 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Protocol profile
+// Describes the limits this library enforces when parsing and the content types and capabilities it supports in a
+// serializable form, so servers and other clients can validate messages against the same limits. Limits that are not
+// set are serialized as null.
+
+use serde::{Serialize, Deserialize};
+use crate::capability;
+use crate::content_type;
+use crate::keys::KYBER_CIPHERTEXT_LEN;
+use crate::limits::{ParseLimits, ParseMode, DEFAULT_MAX_DECOMPRESSED_LEN};
+use crate::DawnError;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProtocolProfile {
+	pub library_version: String,
+	pub max_ciphertext_len: Option<usize>,
+	pub max_text_len: Option<usize>, // per text field, in bytes
+	pub max_inline_media_len: Option<usize>, // per binary field, in bytes after decoding
+	pub strict: bool, // unknown fields are rejected (see ParseMode)
+	pub kyber_ciphertext_len: usize,
+	pub content_types: Vec<u8>,
+	pub capabilities: Vec<String>,
+}
+
+fn limit(value: usize) -> Option<usize> {
+	if value == usize::MAX { None } else { Some(value) }
+}
+
+impl Default for ProtocolProfile {
+	fn default() -> Self {
+		Self::from_limits(&ParseLimits::default())
+	}
+}
+
+impl ProtocolProfile {
+	// profile of this version of the library when parsing with the given limits
+	pub fn from_limits(limits: &ParseLimits) -> Self {
+		ProtocolProfile {
+			library_version: env!("CARGO_PKG_VERSION").to_string(),
+			max_ciphertext_len: limit(limits.max_ciphertext_len),
+			max_text_len: limit(limits.max_text_len),
+			max_inline_media_len: limit(limits.max_data_len),
+			strict: limits.mode == ParseMode::Strict,
+			kyber_ciphertext_len: KYBER_CIPHERTEXT_LEN,
+			content_types: content_type::SUPPORTED.iter().map(|content_type| u8::from(*content_type)).collect(),
+			capabilities: capability::supported(),
+		}
+	}
+	
+	// the limits described by the profile
+	pub fn limits(&self) -> ParseLimits {
+		ParseLimits {
+			max_ciphertext_len: self.max_ciphertext_len.unwrap_or(usize::MAX),
+			max_text_len: self.max_text_len.unwrap_or(usize::MAX),
+			max_data_len: self.max_inline_media_len.unwrap_or(usize::MAX),
+			max_decompressed_len: self.max_text_len.unwrap_or(usize::MAX).saturating_add(self.max_inline_media_len.unwrap_or(usize::MAX)).min(DEFAULT_MAX_DECOMPRESSED_LEN),
+			mode: if self.strict { ParseMode::Strict } else { ParseMode::Lenient },
+		}
+	}
+	
+	pub fn supports_content_type(&self, content_type: u8) -> bool {
+		self.content_types.contains(&content_type)
+	}
+	
+	pub fn to_json(&self) -> Result<String, DawnError> {
+		match serde_json::to_string(self) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "json serialization failed")
+		}
+	}
+	
+	pub fn from_json(json: &str) -> Result<Self, DawnError> {
+		match serde_json::from_str::<ProtocolProfile>(json) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "protocol profile json parsing failed")
+		}
+	}
+}
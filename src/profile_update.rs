@@ -0,0 +1,167 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Profile updates
+// A profile update tells a contact the own name, status and avatar. It is sent as INTERNAL message with
+// event::PROFILE_UPDATE. Not every contact should see everything, so every contact gets a ProfilePolicy that decides
+// whether the avatar and the status are shared with them. The send helpers leave out what the policy withholds, so the
+// client keeps a single ProfileUpdate for all contacts instead of filtering it per contact. Fields that are left out are
+// unchanged for the receiver.
+
+use serde::{Serialize, Deserialize};
+use crate::codec::{encode_base64, decode_base64};
+use crate::{content_type, event};
+use crate::send_msg;
+use crate::DawnError;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProfileUpdate {
+	pub name: Option<String>,
+	pub status: Option<String>,
+	pub avatar: Option<Vec<u8>>,
+}
+
+// what is shared with a contact, everything by default
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProfilePolicy {
+	pub share_avatar: bool,
+	pub share_status: bool,
+}
+
+// serialized form of a ProfileUpdate
+#[derive(Serialize, Deserialize, Debug)]
+struct ProfileRecord {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	name: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	status: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	avatar: Option<String>,
+}
+
+impl ProfileUpdate {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	pub fn name(mut self, name: &str) -> Self {
+		self.name = Some(name.to_string());
+		self
+	}
+	
+	pub fn status(mut self, status: &str) -> Self {
+		self.status = Some(status.to_string());
+		self
+	}
+	
+	pub fn avatar(mut self, avatar: &[u8]) -> Self {
+		self.avatar = Some(avatar.to_vec());
+		self
+	}
+	
+	pub fn is_empty(&self) -> bool {
+		self.name.is_none() && self.status.is_none() && self.avatar.is_none()
+	}
+}
+
+impl Default for ProfilePolicy {
+	fn default() -> Self {
+		ProfilePolicy {
+			share_avatar: true,
+			share_status: true,
+		}
+	}
+}
+
+impl ProfilePolicy {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	pub fn share_avatar(mut self, share_avatar: bool) -> Self {
+		self.share_avatar = share_avatar;
+		self
+	}
+	
+	pub fn share_status(mut self, share_status: bool) -> Self {
+		self.share_status = share_status;
+		self
+	}
+	
+	// returns the part of the update the contact may see
+	pub fn apply(&self, update: &ProfileUpdate) -> ProfileUpdate {
+		ProfileUpdate {
+			name: update.name.clone(),
+			status: if self.share_status { update.status.clone() } else { None },
+			avatar: if self.share_avatar { update.avatar.clone() } else { None },
+		}
+	}
+}
+
+// encode the part of a profile update the policy shares as event data
+// returns None if the policy withholds every field of the update, so nothing has to be sent to the contact
+pub fn gen_profile_update(update: &ProfileUpdate, policy: &ProfilePolicy) -> Result<Option<Vec<u8>>, DawnError> {
+	let update = policy.apply(update);
+	if update.is_empty() { return Ok(None); }
+	if update.name.as_deref() == Some("") { error!("name must not be empty"); }
+	let record = ProfileRecord {
+		name: update.name,
+		status: update.status,
+		avatar: update.avatar.map(encode_base64),
+	};
+	match serde_json::to_vec(&record) {
+		Ok(res) => Ok(Some(res)),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// parse the event data of a profile update
+pub fn parse_profile_update(data: &[u8]) -> Result<ProfileUpdate, DawnError> {
+	let record = match serde_json::from_slice::<ProfileRecord>(data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "profile update json parsing failed")
+	};
+	if record.name.as_deref() == Some("") { error!("name must not be empty"); }
+	let avatar = match record.avatar.map(decode_base64) {
+		Some(Ok(res)) => Some(res),
+		Some(Err(_)) => error!("profile update avatar invalid"),
+		None => None
+	};
+	Ok(ProfileUpdate {
+		name: record.name,
+		status: record.status,
+		avatar,
+	})
+}
+
+// new pfs key, message detail code and ciphertext as returned by send_msg
+type SentMessage = (Vec<u8>, String, Vec<u8>);
+
+// send the part of a profile update the policy of the contact shares
+// returns the same as send_msg or None if the policy withholds every field of the update
+pub fn send_profile_update(update: &ProfileUpdate, policy: &ProfilePolicy, remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<Option<SentMessage>, DawnError> {
+	let data = match gen_profile_update(update, policy) {
+		Ok(Some(res)) => res,
+		Ok(None) => return Ok(None),
+		Err(err) => return Err(err)
+	};
+	match send_msg((content_type::INTERNAL, Some(&event::PROFILE_UPDATE.to_string()), Some(&data)), remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt, id, mdc_seed) {
+		Ok(res) => Ok(Some(res)),
+		Err(err) => Err(err)
+	}
+}
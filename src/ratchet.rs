@@ -0,0 +1,173 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Crash-safe sending
+// Every sent message advances the pfs key of the conversation. If the client crashes between storing the new key and
+// handing the ciphertext to the server, the remote side never sees the message and can't decrypt anything after it; if it
+// crashes between sending and storing, the next message reuses the old key. Sending through the storage therefore happens
+// in two phases: send_msg_staged encrypts the message and commits the advanced key together with the ciphertext (kept in
+// an outbox) in one transaction, then the client sends the ciphertext and removes it from the outbox with confirm_sent.
+// A crash before the commit leaves the old key and nothing was sent. A crash after it leaves the ciphertext in the outbox,
+// and pending_sends returns it after the restart to be sent again, before any new message of the conversation. If the
+// crash happened after the server accepted the message but before confirm_sent, the same ciphertext is sent twice, which
+// servers and receivers recognize as a duplicate.
+
+use serde::{Serialize, Deserialize};
+use crate::codec::{encode_base64, decode_base64};
+use crate::send_msg;
+use crate::storage::{Storage, with_transaction};
+use crate::content_type::ContentType;
+use crate::DawnError;
+
+pub const NAMESPACE_RATCHETS: &str = "ratchets";
+pub const NAMESPACE_OUTBOX: &str = "outbox";
+
+// sending state of a conversation
+#[derive(Serialize, Deserialize)]
+struct RatchetRecord {
+	pfs_key: String,
+	sequence: u64, // number of messages staged so far
+}
+
+#[derive(Serialize, Deserialize)]
+struct OutboxRecord {
+	conversation_id: String,
+	mdc: String,
+	ciphertext: String,
+}
+
+// message that was staged but not confirmed as sent yet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingSend {
+	pub outbox_key: String,
+	pub conversation_id: String,
+	pub mdc: String,
+	pub ciphertext: Vec<u8>,
+}
+
+fn read_ratchet<S: Storage + ?Sized>(storage: &S, conversation_id: &str) -> Result<RatchetRecord, DawnError> {
+	let record = match storage.get(NAMESPACE_RATCHETS, conversation_id) {
+		Ok(Some(res)) => res,
+		Ok(None) => error!("no sending ratchet stored for this conversation"),
+		Err(err) => return Err(err)
+	};
+	match serde_json::from_slice::<RatchetRecord>(&record) {
+		Ok(res) => Ok(res),
+		Err(_) => error!("sending ratchet record corrupted")
+	}
+}
+
+fn write_ratchet<S: Storage + ?Sized>(storage: &mut S, conversation_id: &str, pfs_key: &[u8], sequence: u64) -> Result<(), DawnError> {
+	let record = RatchetRecord { pfs_key: encode_base64(pfs_key), sequence };
+	match serde_json::to_vec(&record) {
+		Ok(record) => storage.put(NAMESPACE_RATCHETS, conversation_id, &record),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// store the first pfs key of a conversation (as returned by the handshake)
+pub fn init_send_ratchet<S: Storage + ?Sized>(storage: &mut S, conversation_id: &str, pfs_key: &[u8]) -> Result<(), DawnError> {
+	match storage.get(NAMESPACE_RATCHETS, conversation_id) {
+		Ok(Some(_)) => error!("sending ratchet already exists for this conversation"),
+		Ok(None) => write_ratchet(storage, conversation_id, pfs_key, 0),
+		Err(err) => Err(err)
+	}
+}
+
+// phase one: encrypt a message with the stored pfs key (see send_msg) and commit the advanced key together with the ciphertext
+// fails while earlier messages of the conversation are still pending
+// returns the staged message, which has to be sent and then confirmed
+pub fn send_msg_staged<S: Storage + ?Sized>(storage: &mut S, conversation_id: &str, content: (ContentType, Option<&str>, Option<&[u8]>), remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_salt: &[u8], id: &str, mdc_seed: &str) -> Result<PendingSend, DawnError> {
+	match pending_sends(storage) {
+		Ok(pending) if pending.iter().any(|pending| pending.conversation_id == conversation_id) => error!("earlier messages of the conversation have to be sent first"),
+		Ok(_) => (),
+		Err(err) => return Err(err)
+	}
+	let ratchet = match read_ratchet(storage, conversation_id) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let pfs_key = match decode_base64(&ratchet.pfs_key) {
+		Ok(res) => res,
+		Err(_) => error!("sending ratchet record corrupted")
+	};
+	let (new_pfs_key, mdc, ciphertext) = match send_msg(content, remote_pubkey_kyber, own_seckey_sig, &pfs_key, pfs_salt, id, mdc_seed) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let sequence = ratchet.sequence + 1;
+	// the sequence keeps the outbox of a conversation in sending order
+	let outbox_key = format!("{}:{:020}", conversation_id, sequence);
+	let outbox_record = OutboxRecord {
+		conversation_id: conversation_id.to_string(),
+		mdc: mdc.clone(),
+		ciphertext: encode_base64(&ciphertext),
+	};
+	let outbox_record = match serde_json::to_vec(&outbox_record) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "json serialization failed")
+	};
+	let result = with_transaction(storage, |storage| {
+		if let Err(err) = write_ratchet(storage, conversation_id, &new_pfs_key, sequence) { return Err(err); }
+		storage.put(NAMESPACE_OUTBOX, &outbox_key, &outbox_record)
+	});
+	match result {
+		Ok(_) => Ok(PendingSend {
+			outbox_key,
+			conversation_id: conversation_id.to_string(),
+			mdc,
+			ciphertext,
+		}),
+		Err(err) => Err(err)
+	}
+}
+
+// phase two: remove a message from the outbox after the server accepted it
+pub fn confirm_sent<S: Storage + ?Sized>(storage: &mut S, outbox_key: &str) -> Result<(), DawnError> {
+	storage.delete(NAMESPACE_OUTBOX, outbox_key)
+}
+
+// recovery: returns all staged messages that weren't confirmed, in the order they have to be sent again
+pub fn pending_sends<S: Storage + ?Sized>(storage: &S) -> Result<Vec<PendingSend>, DawnError> {
+	let outbox_keys = match storage.list(NAMESPACE_OUTBOX) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let mut pending = Vec::new();
+	for outbox_key in outbox_keys {
+		let record = match storage.get(NAMESPACE_OUTBOX, &outbox_key) {
+			Ok(Some(res)) => res,
+			Ok(None) => continue,
+			Err(err) => return Err(err)
+		};
+		let (record, ciphertext) = match serde_json::from_slice::<OutboxRecord>(&record).map(|record| {
+			let ciphertext = decode_base64(&record.ciphertext);
+			(record, ciphertext)
+		}) {
+			Ok((record, Ok(ciphertext))) => (record, ciphertext),
+			_ => error!("outbox record corrupted")
+		};
+		pending.push(PendingSend {
+			outbox_key,
+			conversation_id: record.conversation_id,
+			mdc: record.mdc,
+			ciphertext,
+		});
+	}
+	Ok(pending)
+}
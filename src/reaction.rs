@@ -0,0 +1,52 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Message reactions
+// A reaction refers to an earlier message of the conversation by its message detail code and carries a short string,
+// usually a single emoji. Every sender has at most one reaction per message: a new reaction replaces the previous one
+// and an empty reaction withdraws it. In the (content type, text, data) form of a reaction, the text holds the message
+// detail code of the target in its first line and the reaction in the second one (see OutgoingMessage::reaction).
+
+use crate::DawnError;
+
+// in bytes, enough for emoji sequences like flags or families
+pub const MAX_REACTION_LEN: usize = 64;
+
+pub(crate) fn check_reaction(target: &str, reaction: &str) -> Result<(), DawnError> {
+	if target.is_empty() || target.contains('\n') { error!("reaction target must be a single non-empty line"); }
+	if reaction.len() > MAX_REACTION_LEN { error!("reaction must be at most 64 bytes long"); }
+	if reaction.contains('\n') { error!("reaction must be a single line"); }
+	Ok(())
+}
+
+// returns the reaction in the form of the text of a reaction message
+pub(crate) fn pack_reaction(target: &str, reaction: &str) -> String {
+	format!("{}\n{}", target, reaction)
+}
+
+// read target and reaction from the text of a reaction message
+pub(crate) fn unpack_reaction(text: &str) -> Result<(&str, &str), DawnError> {
+	let (target, reaction) = match text.split_once('\n') {
+		Some(res) => res,
+		None => error!("reaction is missing its target")
+	};
+	match check_reaction(target, reaction) {
+		Ok(()) => Ok((target, reaction)),
+		Err(err) => Err(err)
+	}
+}
@@ -0,0 +1,107 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Read and delivery receipts
+// A read receipt tells the sender which of its messages were read, listing them by their message detail codes. Clients
+// collect the messages that were read while the conversation was open and send them in one receipt, instead of one
+// message per read message. Receipts are INTERNAL messages with event::READ_RECEIPT (see gen_read_receipt).
+// A delivery receipt is sent by the receiving client on its own as soon as a message arrived, for one message and with
+// the time it was delivered, so the sender can mark the message as delivered before it was read. Delivery receipts are
+// INTERNAL messages with event::DELIVERY_RECEIPT and are never sent for internal messages, receipts included.
+
+use serde::{Serialize, Deserialize};
+use crate::outgoing::OutgoingMessage;
+use crate::event;
+use dawn_crypto::get_current_timestamp;
+use crate::DawnError;
+
+// message detail codes per receipt, more have to be split over several receipts
+pub const MAX_RECEIPT_MDCS: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReadReceipt {
+	pub mdcs: Vec<String>, // message detail codes of the messages that were read
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeliveryReceipt {
+	pub mdc: String, // message detail code of the delivered message
+	pub delivered_at: u64, // unix timestamp in seconds
+}
+
+impl ReadReceipt {
+	fn check(&self) -> Result<(), DawnError> {
+		if self.mdcs.is_empty() { error!("read receipt must list at least one message"); }
+		if self.mdcs.len() > MAX_RECEIPT_MDCS { error!("read receipt lists too many messages"); }
+		if self.mdcs.iter().any(String::is_empty) { error!("read receipt contains an empty mdc"); }
+		Ok(())
+	}
+}
+
+// acknowledge that the messages with the given message detail codes were read, duplicates are only listed once
+pub fn gen_read_receipt(mdcs: &[&str]) -> Result<OutgoingMessage, DawnError> {
+	let mut receipt = ReadReceipt { mdcs: Vec::with_capacity(mdcs.len()) };
+	for mdc in mdcs {
+		if !receipt.mdcs.iter().any(|listed| listed == mdc) { receipt.mdcs.push(mdc.to_string()); }
+	}
+	if let Err(err) = receipt.check() { return Err(err); }
+	match serde_json::to_vec(&receipt) {
+		Ok(res) => Ok(OutgoingMessage::internal(event::READ_RECEIPT, &res)),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// parse the event data of a read receipt
+pub fn parse_read_receipt(data: &[u8]) -> Result<ReadReceipt, DawnError> {
+	let receipt = match serde_json::from_slice::<ReadReceipt>(data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "read receipt json parsing failed")
+	};
+	match receipt.check() {
+		Ok(()) => Ok(receipt),
+		Err(err) => Err(err)
+	}
+}
+
+// acknowledge that the message with the given message detail code was delivered just now
+pub fn gen_delivery_receipt(mdc: &str) -> Result<OutgoingMessage, DawnError> {
+	gen_delivery_receipt_at(mdc, get_current_timestamp())
+}
+
+// acknowledge the delivery of a message at the given time (e.g. the time of the clock of a session)
+pub(crate) fn gen_delivery_receipt_at(mdc: &str, delivered_at: u64) -> Result<OutgoingMessage, DawnError> {
+	if mdc.is_empty() { error!("delivery receipt contains an empty mdc"); }
+	let receipt = DeliveryReceipt {
+		mdc: mdc.to_string(),
+		delivered_at,
+	};
+	match serde_json::to_vec(&receipt) {
+		Ok(res) => Ok(OutgoingMessage::internal(event::DELIVERY_RECEIPT, &res)),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// parse the event data of a delivery receipt
+pub fn parse_delivery_receipt(data: &[u8]) -> Result<DeliveryReceipt, DawnError> {
+	let receipt = match serde_json::from_slice::<DeliveryReceipt>(data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "delivery receipt json parsing failed")
+	};
+	if receipt.mdc.is_empty() { error!("delivery receipt contains an empty mdc"); }
+	Ok(receipt)
+}
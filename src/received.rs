@@ -0,0 +1,118 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Received messages
+// parse_msg returns the content of a message as a ReceivedMessage, which has one variant per content type carrying the
+// fields of that type, so clients don't have to take apart the text and data of a message depending on its type.
+// into_content turns it back into the (content type, text, data) form that send_msg takes, e.g. to forward a message.
+// Forwarded messages don't keep their effect, alt text, codec, expiry and the message they replied to.
+// parse_msg_with_config returns a ParsedMessage, which carries everything else the message came with: its thread, device
+// counter and sequence number, which are checked with the trackers of the conversation.
+
+use crate::content_type::{self, ContentType};
+use crate::context::OwnedContent;
+use crate::media::gen_linked_media_data;
+use crate::effect::Effect;
+use crate::transcription::Transcription;
+use crate::reaction::pack_reaction;
+use crate::reply::Reply;
+use crate::warning::Warning;
+use crate::device_counter::{DeviceStamp, CounterTracker, SecurityEvent};
+use crate::sequence::{SequenceTracker, SequenceGap};
+
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReceivedMessage {
+	Internal { event: u8, data: Vec<u8> }, // event code (see the event module) and event data
+	Text { text: String, effect: Option<Effect>, in_reply_to: Option<Reply>, expires_after: Option<u64> }, // expires_after: delete this many seconds after receiving it
+	Voice { data: Vec<u8>, transcription: Option<Transcription>, in_reply_to: Option<Reply>, codec: Option<String>, expires_after: Option<u64> }, // transcription provided by the sender
+	Picture { data: Vec<u8>, description: String, alt_text: Option<String>, effect: Option<Effect>, in_reply_to: Option<Reply>, codec: Option<String>, expires_after: Option<u64> }, // alt text is meant for screen readers
+	Reaction { target: String, reaction: String }, // mdc of the message reacted to, an empty reaction withdraws the previous one
+	Retraction { target: String }, // mdc of the message the sender asks to delete
+	LinkedMedia { link: String, key: String, description: String, media_type: u8, expires_at: Option<u64>, delete_token: Option<Vec<u8>> },
+	HistorySync { transfer_id: String, chunk_index: u32, chunk_count: u32, chunk: Vec<u8> },
+	DeltaSync { delta: Vec<u8> },
+	Gateway { envelope: String, data: Option<Vec<u8>> }, // envelope as parsed by parse_gateway_envelope and the data of the relayed message
+}
+
+impl ReceivedMessage {
+	pub fn content_type(&self) -> ContentType {
+		match self {
+			ReceivedMessage::Internal { .. } => content_type::INTERNAL,
+			ReceivedMessage::Text { .. } => content_type::TEXT,
+			ReceivedMessage::Voice { .. } => content_type::VOICE,
+			ReceivedMessage::Picture { .. } => content_type::PICTURE,
+			ReceivedMessage::Reaction { .. } => content_type::REACTION,
+			ReceivedMessage::Retraction { .. } => content_type::RETRACTION,
+			ReceivedMessage::LinkedMedia { .. } => content_type::LINKED_MEDIA,
+			ReceivedMessage::HistorySync { .. } => content_type::HISTORY_SYNC,
+			ReceivedMessage::DeltaSync { .. } => content_type::DELTA_SYNC,
+			ReceivedMessage::Gateway { .. } => content_type::GATEWAY,
+		}
+	}
+	
+	// returns content type, text and data in the form send_msg takes
+	pub fn into_content(self) -> OwnedContent {
+		let content_type = self.content_type();
+		match self {
+			ReceivedMessage::Internal { event, data } => (content_type, Some(event.to_string()), Some(data)),
+			ReceivedMessage::Text { text, .. } => (content_type, Some(text), None),
+			ReceivedMessage::Voice { data, transcription, .. } => (content_type, transcription.map(|transcription| transcription.pack()), Some(data)),
+			ReceivedMessage::Picture { data, description, .. } => (content_type, Some(description), Some(data)),
+			ReceivedMessage::Reaction { target, reaction } => (content_type, Some(pack_reaction(&target, &reaction)), None),
+			ReceivedMessage::Retraction { target } => (content_type, Some(target), None),
+			ReceivedMessage::LinkedMedia { link, key, description, media_type, expires_at, delete_token } => {
+				(content_type, Some(format!("{}\n{}\n{}", link, key, description)), Some(gen_linked_media_data(media_type, expires_at, delete_token.as_deref())))
+			},
+			ReceivedMessage::HistorySync { transfer_id, chunk_index, chunk_count, chunk } => {
+				(content_type, Some(format!("{}\n{}\n{}", transfer_id, chunk_index, chunk_count)), Some(chunk))
+			},
+			ReceivedMessage::DeltaSync { delta } => (content_type, None, Some(delta)),
+			ReceivedMessage::Gateway { envelope, data } => (content_type, Some(envelope), data),
+		}
+	}
+}
+
+// result of parse_msg_with_config
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedMessage {
+	pub message: ReceivedMessage,
+	pub new_pfs_key: Vec<u8>, // pfs key for the next message received
+	pub mdc: String,
+	pub warning: Warning,
+	pub thread_id: Option<String>, // see thread_id_for
+	pub seq: Option<u64>, // sequence number (see SequenceCounter)
+	pub(crate) device: Option<DeviceStamp>,
+}
+
+impl ParsedMessage {
+	// check the device counter of the message
+	// returns a security event if the counter reveals a cloned session of the peer
+	pub fn check_device(&self, tracker: &mut CounterTracker) -> Option<SecurityEvent> {
+		match &self.device {
+			Some(stamp) => tracker.check(stamp),
+			None => None
+		}
+	}
+	
+	// check the sequence number of the message against the messages received before
+	// returns the sequence number with the gap it reveals, or None if the message has no sequence number
+	pub fn check_sequence(&self, tracker: &mut SequenceTracker) -> Option<(u64, SequenceGap)> {
+		self.seq.map(|seq| (seq, tracker.check(seq)))
+	}
+}
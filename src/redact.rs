@@ -0,0 +1,140 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Redaction of messages for bug reports
+// Users reporting a problem with a conversation often need to share what the messages looked like, but not what they said.
+// A Redactor turns parsed messages into redacted copies: media is replaced by its size, texts are cut after a number of
+// characters, keys are removed and identifiers (message detail codes, media links, transfer ids, senders on other
+// networks) are replaced by pseudonyms. Pseudonyms are consistent within one Redactor, so a report still shows which
+// messages refer to the same thing, but use a random salt, so they can't be linked to the real identifiers or across reports.
+
+use serde::Serialize;
+use dawn_crypto::{hash, sym_key_gen};
+use crate::codec::encode;
+use crate::content_type::{self, ContentType};
+use crate::context::OwnedContent;
+use crate::gateway::{parse_envelope, gen_gateway_envelope};
+use crate::history::parse_chunk_header;
+use crate::DawnError;
+
+const DEFAULT_MAX_TEXT_CHARS: usize = 16;
+const REDACTED: &str = "<redacted>";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RedactedMessage {
+	pub content_type: ContentType,
+	pub text: Option<String>,
+	pub data_len: Option<usize>, // size of the stripped data in bytes
+	pub mdc: String,
+}
+
+pub struct Redactor {
+	max_text_chars: usize,
+	salt: Vec<u8>,
+}
+
+impl Default for Redactor {
+	fn default() -> Self {
+		Redactor {
+			max_text_chars: DEFAULT_MAX_TEXT_CHARS,
+			salt: sym_key_gen(),
+		}
+	}
+}
+
+impl Redactor {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// number of characters of every text that are kept (0 removes texts completely)
+	pub fn max_text_chars(mut self, max_text_chars: usize) -> Self {
+		self.max_text_chars = max_text_chars;
+		self
+	}
+	
+	// replace an identifier by its pseudonym (e.g. ids of conversations or threads the client puts into the report)
+	pub fn pseudonymize(&self, id: &str) -> String {
+		let mut input = self.salt.clone();
+		input.extend_from_slice(id.as_bytes());
+		let pseudonym = hash(&input);
+		format!("id-{}", encode(pseudonym.get(..6).unwrap_or(&pseudonym)))
+	}
+	
+	// cut a text after max_text_chars characters, noting how many were removed
+	pub fn elide(&self, text: &str) -> String {
+		let char_count = text.chars().count();
+		if char_count <= self.max_text_chars { return text.to_string(); }
+		let kept = text.chars().take(self.max_text_chars).collect::<String>();
+		format!("{}[{} more characters]", kept, char_count - self.max_text_chars)
+	}
+	
+	fn redact_text(&self, (msg_type, msg_text): (ContentType, Option<&str>)) -> Result<Option<String>, DawnError> {
+		let text = match msg_text {
+			Some(res) => res,
+			None => return Ok(None)
+		};
+		let redacted = match msg_type {
+			// event code
+			content_type::INTERNAL => text.to_string(),
+			// link, key and description
+			content_type::LINKED_MEDIA => {
+				let mut lines = text.splitn(3, '\n');
+				let media_link = lines.next().map(|media_link| self.pseudonymize(media_link)).unwrap_or_default();
+				let description = lines.nth(1).map(|description| self.elide(description)).unwrap_or_default();
+				format!("{}\n{}\n{}", media_link, REDACTED, description)
+			},
+			content_type::HISTORY_SYNC => match parse_chunk_header(text) {
+				Ok((transfer_id, chunk_index, chunk_count)) => format!("{}\n{}\n{}", self.pseudonymize(&transfer_id), chunk_index, chunk_count),
+				Err(err) => return Err(err)
+			},
+			// the relayed message is redacted like any other message
+			content_type::GATEWAY => {
+				let (mut origin, relayed_type, relayed_text) = match parse_envelope(text) {
+					Ok(res) => res,
+					Err(err) => return Err(err)
+				};
+				origin.remote_id = self.pseudonymize(&origin.remote_id);
+				let relayed_text = match self.redact_text((relayed_type, relayed_text.as_deref())) {
+					Ok(res) => res,
+					Err(err) => return Err(err)
+				};
+				match gen_gateway_envelope(&origin, (relayed_type, relayed_text.as_deref(), None)) {
+					Ok(res) => res,
+					Err(err) => return Err(err)
+				}
+			},
+			_ => self.elide(text)
+		};
+		Ok(Some(redacted))
+	}
+	
+	// produce a redacted copy of a parsed message (content and mdc as returned by parse_msg)
+	pub fn redact(&self, (msg_type, msg_text, msg_data): OwnedContent, mdc: &str) -> Result<RedactedMessage, DawnError> {
+		let text = match self.redact_text((msg_type, msg_text.as_deref())) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		Ok(RedactedMessage {
+			content_type: msg_type,
+			text,
+			data_len: msg_data.map(|data| data.len()),
+			mdc: self.pseudonymize(mdc),
+		})
+	}
+}
@@ -0,0 +1,202 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Account registration
+// A new account announces itself to its home server with a registration request: the public keys of its identity, a
+// first batch of one-time prekeys (see gen_prekeys) and optionally a reference to its profile document (e.g. its link on
+// a content server). The request is signed with the signature key it announces, so the server knows the account holds
+// it. The server answers with a confirmation that names the request, the id it assigned to the account and the prekeys
+// it accepted; prekeys it did not accept are never handed out and can be deleted again.
+
+use serde::{Serialize, Deserialize};
+use dawn_crypto::id_gen;
+use crate::canonical::canonical_json;
+use crate::codec::{encode, decode};
+use crate::identity::Identity;
+use crate::keys::{SignPublicKey, KyberPublicKey, CurvePublicKey};
+use crate::signature::{sign_detached, verify_detached};
+use crate::domain::REGISTRATION_DOMAIN;
+use crate::DawnError;
+
+const REGISTRATION_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct RegistrationContent {
+	version: u8,
+	id: String,
+	timestamp: u64,
+	sign: String,
+	init_kyber: String,
+	init_curve: String,
+	init_curve_pfs_2: String,
+	init_kyber_for_salt: String,
+	init_curve_for_salt: String,
+	prekeys: Vec<(String, String)>, // id and public key
+	profile: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedRegistration {
+	registration: RegistrationContent,
+	signature: String,
+}
+
+// a registration request as it is sent to the server
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegistrationRequest {
+	pub id: String,
+	pub prekeys: Vec<String>, // ids of the announced prekeys
+	pub body: Vec<u8>,
+}
+
+// registration as verified by the server
+#[derive(Clone, Debug, PartialEq)]
+pub struct Registration {
+	pub id: String,
+	pub timestamp: u64,
+	pub pubkey_sig: SignPublicKey,
+	pub init_pubkey_kyber: KyberPublicKey,
+	pub init_pubkey_curve: CurvePublicKey,
+	pub init_pubkey_curve_pfs_2: CurvePublicKey,
+	pub init_pubkey_kyber_for_salt: KyberPublicKey,
+	pub init_pubkey_curve_for_salt: CurvePublicKey,
+	pub prekeys: Vec<(String, KyberPublicKey)>,
+	pub profile: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RegistrationConfirmation {
+	pub request: String, // id of the confirmed registration request
+	pub account: String, // id the server assigned to the account
+	pub prekeys: Vec<String>, // ids of the prekeys the server accepted
+}
+
+// build the signed registration request of a new account
+// prekeys are the ids and public keys returned by gen_prekeys, profile is a reference to the profile document of the account
+pub fn gen_registration(identity: &Identity, prekeys: &[(String, KyberPublicKey)], profile: Option<&str>, timestamp: u64) -> Result<RegistrationRequest, DawnError> {
+	if prekeys.iter().any(|(id, _)| id.is_empty()) { error!("prekey id must not be empty"); }
+	if profile == Some("") { error!("profile reference must not be empty"); }
+	let registration = RegistrationContent {
+		version: REGISTRATION_VERSION,
+		id: id_gen(),
+		timestamp,
+		sign: encode(&identity.pubkey_sig),
+		init_kyber: encode(&identity.init_pubkey_kyber),
+		init_curve: encode(&identity.init_pubkey_curve),
+		init_curve_pfs_2: encode(&identity.init_pubkey_curve_pfs_2),
+		init_kyber_for_salt: encode(&identity.init_pubkey_kyber_for_salt),
+		init_curve_for_salt: encode(&identity.init_pubkey_curve_for_salt),
+		prekeys: prekeys.iter().map(|(id, pubkey_kyber)| (id.clone(), encode(pubkey_kyber))).collect(),
+		profile: profile.map(|profile| profile.to_string()),
+	};
+	let signature = match canonical_json(&registration).map(|content| sign_detached(REGISTRATION_DOMAIN, &content, identity.seckey_sig.as_bytes())) {
+		Ok(Ok(res)) => res,
+		Ok(Err(err)) | Err(err) => return Err(err)
+	};
+	let id = registration.id.clone();
+	match serde_json::to_vec(&SignedRegistration { registration, signature: encode(signature) }) {
+		Ok(body) => Ok(RegistrationRequest {
+			id,
+			prekeys: prekeys.iter().map(|(id, _)| id.clone()).collect(),
+			body,
+		}),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// decode a hex encoded public key of a registration
+fn decode_key<K: TryFrom<Vec<u8>, Error = DawnError>>(key: &str) -> Result<K, DawnError> {
+	match decode(key).map(K::try_from) {
+		Ok(Ok(res)) => Ok(res),
+		_ => error!("registration contains an invalid key")
+	}
+}
+
+// verify a registration request as server
+pub fn verify_registration(body: &[u8]) -> Result<Registration, DawnError> {
+	let signed_registration = match serde_json::from_slice::<SignedRegistration>(body) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "registration json parsing failed")
+	};
+	let registration = signed_registration.registration;
+	if registration.version != REGISTRATION_VERSION { error!("registration version not supported"); }
+	let pubkey_sig: SignPublicKey = match decode_key(&registration.sign) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let signature = match decode(&signed_registration.signature) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "registration signature invalid")
+	};
+	let content = match canonical_json(&registration) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if let Err(err) = verify_detached(REGISTRATION_DOMAIN, &content, &signature, pubkey_sig.as_bytes()) { return Err(err); }
+	
+	let init_pubkey_kyber = match decode_key(&registration.init_kyber) { Ok(res) => res, Err(err) => return Err(err) };
+	let init_pubkey_curve = match decode_key(&registration.init_curve) { Ok(res) => res, Err(err) => return Err(err) };
+	let init_pubkey_curve_pfs_2 = match decode_key(&registration.init_curve_pfs_2) { Ok(res) => res, Err(err) => return Err(err) };
+	let init_pubkey_kyber_for_salt = match decode_key(&registration.init_kyber_for_salt) { Ok(res) => res, Err(err) => return Err(err) };
+	let init_pubkey_curve_for_salt = match decode_key(&registration.init_curve_for_salt) { Ok(res) => res, Err(err) => return Err(err) };
+	let mut prekeys = Vec::with_capacity(registration.prekeys.len());
+	for (id, pubkey_kyber) in registration.prekeys {
+		if id.is_empty() { error!("prekey id must not be empty"); }
+		match decode_key(&pubkey_kyber) {
+			Ok(res) => prekeys.push((id, res)),
+			Err(err) => return Err(err)
+		}
+	}
+	Ok(Registration {
+		id: registration.id,
+		timestamp: registration.timestamp,
+		pubkey_sig,
+		init_pubkey_kyber,
+		init_pubkey_curve,
+		init_pubkey_curve_pfs_2,
+		init_pubkey_kyber_for_salt,
+		init_pubkey_curve_for_salt,
+		prekeys,
+		profile: registration.profile,
+	})
+}
+
+// confirm a verified registration as server
+pub fn gen_registration_confirmation(registration: &Registration, account: &str, accepted_prekeys: &[String]) -> Result<Vec<u8>, DawnError> {
+	let confirmation = RegistrationConfirmation {
+		request: registration.id.clone(),
+		account: account.to_string(),
+		prekeys: accepted_prekeys.to_vec(),
+	};
+	match serde_json::to_vec(&confirmation) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// parse the answer of the server to a registration request
+pub fn parse_registration_confirmation(confirmation: &[u8], request: &RegistrationRequest) -> Result<RegistrationConfirmation, DawnError> {
+	let confirmation = match serde_json::from_slice::<RegistrationConfirmation>(confirmation) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "registration confirmation json parsing failed")
+	};
+	if confirmation.request != request.id { error!("registration confirmation belongs to another request"); }
+	if confirmation.account.is_empty() { error!("registration confirmation is missing the account id"); }
+	if confirmation.prekeys.iter().any(|id| !request.prekeys.contains(id)) { error!("registration confirmation accepts prekeys that were not announced"); }
+	Ok(confirmation)
+}
@@ -0,0 +1,66 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Replies
+// A text, voice or picture message can quote an earlier message of the conversation, which it refers to by its message
+// detail code. The sender can add a short excerpt of the quoted message, so the receiver can still show the quote if it
+// doesn't have that message (anymore), e.g. on a newly linked device. Replies are sent by a Session (see
+// OutgoingMessage::with_reply) or with send_msg_with_options.
+
+use serde::{Serialize, Deserialize};
+use crate::DawnError;
+
+// in bytes, longer excerpts are cut off
+pub const MAX_EXCERPT_LEN: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Reply {
+	pub mdc: String, // message detail code of the quoted message
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub excerpt: Option<String>,
+}
+
+impl Reply {
+	// quote the message with the given message detail code
+	// the excerpt is usually the beginning of the quoted text, it is cut off after MAX_EXCERPT_LEN bytes
+	pub fn new(mdc: &str, excerpt: Option<&str>) -> Result<Self, DawnError> {
+		let excerpt = excerpt.map(|excerpt| {
+			let mut end = excerpt.len().min(MAX_EXCERPT_LEN);
+			while !excerpt.is_char_boundary(end) {
+				end -= 1;
+			}
+			excerpt.get(..end).unwrap_or_default().to_string()
+		});
+		let reply = Reply {
+			mdc: mdc.to_string(),
+			excerpt,
+		};
+		match reply.check() {
+			Ok(()) => Ok(reply),
+			Err(err) => Err(err)
+		}
+	}
+	
+	pub(crate) fn check(&self) -> Result<(), DawnError> {
+		if self.mdc.is_empty() { error!("reply is missing the mdc of the quoted message"); }
+		if let Some(excerpt) = &self.excerpt {
+			if excerpt.len() > MAX_EXCERPT_LEN { error!("reply excerpt must be at most 256 bytes long"); }
+		}
+		Ok(())
+	}
+}
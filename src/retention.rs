@@ -0,0 +1,89 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Retention of undelivered messages
+// The sender can ask the server to drop a message that was not delivered within some time. The retention is handed to the
+// server next to the ciphertext as a header of the form "retention=<seconds>". Once the server dropped such a message,
+// it sends an expiry notice to the sender, which is parsed by parse_expiry_notice.
+
+use serde::{Serialize, Deserialize};
+use crate::DawnError;
+
+const RETENTION_HEADER_PREFIX: &str = "retention=";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retention {
+	pub drop_after: u64, // seconds after sending
+}
+
+// notice of the server that a message was dropped because it was not delivered in time
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ExpiryNotice {
+	pub mdc: String,
+	pub expired_at: u64,
+}
+
+impl Retention {
+	pub fn days(days: u64) -> Self {
+		Retention { drop_after: days * 24 * 60 * 60 }
+	}
+	
+	// returns the timestamp at which a message sent at the given time may be dropped
+	pub fn expires_at(&self, sent_at: u64) -> u64 {
+		sent_at.saturating_add(self.drop_after)
+	}
+	
+	pub fn to_header(&self) -> String {
+		format!("{}{}", RETENTION_HEADER_PREFIX, self.drop_after)
+	}
+	
+	pub fn from_header(header: &str) -> Result<Self, DawnError> {
+		let drop_after = match header.strip_prefix(RETENTION_HEADER_PREFIX).map(|value| value.parse::<u64>()) {
+			Some(Ok(res)) => res,
+			_ => error!("retention header invalid")
+		};
+		if drop_after == 0 { error!("retention must not be zero"); }
+		Ok(Retention { drop_after })
+	}
+}
+
+// generate an expiry notice (used by servers)
+pub fn gen_expiry_notice(mdc: &str, expired_at: u64) -> Vec<u8> {
+	let notice = ExpiryNotice {
+		mdc: mdc.to_string(),
+		expired_at,
+	};
+	// serializing a struct of strings and integers can't fail
+	serde_json::to_vec(&notice).unwrap_or_default()
+}
+
+// parse an expiry notice received from the server
+pub fn parse_expiry_notice(notice: &[u8]) -> Result<ExpiryNotice, DawnError> {
+	match serde_json::from_slice::<ExpiryNotice>(notice) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Serialization, "expiry notice json parsing failed")
+	}
+}
+
+impl ExpiryNotice {
+	// check whether the notice applies to a message sent at the given time with the given retention
+	// a notice that arrives before the requested retention ran out is not honored, the message should be treated as pending
+	pub fn applies_to(&self, mdc: &str, sent_at: u64, retention: &Retention) -> bool {
+		self.mdc == mdc && self.expired_at >= retention.expires_at(sent_at)
+	}
+}
@@ -0,0 +1,31 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Message retraction
+// A retraction asks the recipient to delete an earlier message of the sender, which it refers to by its message detail
+// code. In the (content type, text, data) form of a retraction, the text is the message detail code of the target (see
+// OutgoingMessage::retraction). Retracting is a request, not a guarantee: the recipient may have seen or copied the
+// message already. Recipients only delete the target if it was sent by the sender of the retraction, the library can't
+// check this as it doesn't keep messages.
+
+use crate::DawnError;
+
+pub(crate) fn check_retraction(target: &str) -> Result<(), DawnError> {
+	if target.is_empty() || target.contains('\n') { error!("retraction target must be a single non-empty line"); }
+	Ok(())
+}
@@ -0,0 +1,190 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Routing header
+// Every message ciphertext starts with a small unencrypted header in a fixed binary layout, so servers and notification
+// classifiers can route messages without parsing or decrypting anything:
+// magic "DWR" (3) ‖ version (1) ‖ class (1) ‖ flags (1) ‖ mdc length (1) ‖ message detail code ‖ checksum (4) ‖
+// counter (8, u64 BE)
+// The counter is only present if its flag is set, which is the case for chained messages (see chain.rs). As ciphertexts
+// of clients that predate the header can start with the magic by chance, the bytes after it are only read as a header if
+// version, class and flags are ones this version knows. Anything else is taken for a message without header, so a header
+// of an unsupported version fails to decrypt.
+// The checksum is a CRC32 of the whole message without the checksum itself. It is no protection against tampering (the
+// receiver checks everything that matters), but lets servers and recipients tell a message that was damaged in transport
+// (DawnError::Corrupted, fetching or sending it again helps) from one that fails to decrypt (DawnError::Crypto). Messages
+// of clients that predate the checksum don't have its flag set and are accepted without it.
+// The header is protected by the receiver: after decrypting, every field is compared with the message, and messages with a
+// header that doesn't match are rejected, so a server changing the header can only misroute a message, never alter what
+// the recipient sees. Messages of clients that predate the header don't carry one and are still accepted. The escrow copy
+// of escrowed messages follows the header.
+
+use crate::codec::{encode, decode, split_bytes};
+use crate::content_type::{self, ContentType};
+use crate::warning::Warning;
+use crate::DawnError;
+
+const ROUTING_MAGIC: &[u8] = b"DWR";
+const CHECKSUM_LEN: usize = 4;
+const COUNTER_LEN: usize = 8;
+pub const ROUTING_HEADER_LEN: usize = 3 + 4 + CHECKSUM_LEN; // without the message detail code and a counter
+
+// version of the layout, headers of other versions are rejected
+pub const ROUTING_VERSION: u8 = 1;
+
+const FLAG_SIGNED: u8 = 1;
+const FLAG_ESCROWED: u8 = 2;
+const FLAG_THREAD: u8 = 4;
+const FLAG_COUNTER: u8 = 8;
+const FLAG_CHECKSUM: u8 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoutingClass {
+	Chat = 1, // text, voice, pictures, linked media and relayed messages, shown to the user
+	Control = 2, // internal events
+	Sync = 3, // history and delta sync between own devices
+}
+
+impl RoutingClass {
+	// class of the messages of a content type
+	pub fn of(msg_type: ContentType) -> Self {
+		match msg_type {
+			content_type::INTERNAL => RoutingClass::Control,
+			content_type::HISTORY_SYNC | content_type::DELTA_SYNC => RoutingClass::Sync,
+			_ => RoutingClass::Chat
+		}
+	}
+	
+	fn from_byte(byte: u8) -> Result<Self, DawnError> {
+		match byte {
+			1 => Ok(RoutingClass::Chat),
+			2 => Ok(RoutingClass::Control),
+			3 => Ok(RoutingClass::Sync),
+			_ => error!("routing header contains an unknown class")
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoutingHeader {
+	pub version: u8,
+	pub class: RoutingClass,
+	pub signed: bool,
+	pub escrowed: bool,
+	pub thread: bool, // the message is part of a thread
+	pub mdc: String,
+	pub counter: Option<u64>, // position of a chained message in its chain
+}
+
+// checksum of an envelope, which is split around the checksum field
+fn checksum(before: &[u8], after: &[u8]) -> [u8; CHECKSUM_LEN] {
+	let mut hasher = crc32fast::Hasher::new();
+	hasher.update(before);
+	hasher.update(after);
+	hasher.finalize().to_be_bytes()
+}
+
+// put the header in front of a ciphertext
+pub(crate) fn attach_routing_header(header: &RoutingHeader, msg_ciphertext: &[u8]) -> Result<Vec<u8>, DawnError> {
+	let (mdc, mdc_len) = match decode(&header.mdc).map(|mdc| (u8::try_from(mdc.len()), mdc)) {
+		Ok((Ok(mdc_len), mdc)) if mdc_len > 0 => (mdc, mdc_len),
+		_ => error!("message detail code can't be put into the routing header")
+	};
+	let mut flags = FLAG_CHECKSUM;
+	if header.signed { flags |= FLAG_SIGNED; }
+	if header.escrowed { flags |= FLAG_ESCROWED; }
+	if header.thread { flags |= FLAG_THREAD; }
+	if header.counter.is_some() { flags |= FLAG_COUNTER; }
+	let mut before = ROUTING_MAGIC.to_vec();
+	before.extend_from_slice(&[header.version, header.class as u8, flags, mdc_len]);
+	before.extend_from_slice(&mdc);
+	let mut after = Vec::with_capacity(COUNTER_LEN + msg_ciphertext.len());
+	if let Some(counter) = header.counter { after.extend_from_slice(&counter.to_be_bytes()); }
+	after.extend_from_slice(msg_ciphertext);
+	
+	let mut envelope = before;
+	let checksum = checksum(&envelope, &after);
+	envelope.extend_from_slice(&checksum);
+	envelope.extend_from_slice(&after);
+	Ok(envelope)
+}
+
+// split a received message into the header (if any) and the rest of the message
+pub(crate) fn split_routing_header(envelope: &[u8]) -> Result<(Option<RoutingHeader>, &[u8]), DawnError> {
+	let rest = match envelope.strip_prefix(ROUTING_MAGIC) {
+		Some(res) => res,
+		None => return Ok((None, envelope))
+	};
+	let (version, class, flags, mdc_len, rest) = match rest {
+		[version, class, flags, mdc_len, rest @ ..] => (*version, RoutingClass::from_byte(*class), *flags, *mdc_len as usize, rest),
+		_ => return Ok((None, envelope))
+	};
+	let class = match class {
+		Ok(class) if version == ROUTING_VERSION && flags & !(FLAG_SIGNED | FLAG_ESCROWED | FLAG_THREAD | FLAG_COUNTER | FLAG_CHECKSUM) == 0 && mdc_len > 0 => class,
+		_ => return Ok((None, envelope))
+	};
+	let (mdc, rest) = match split_bytes(rest, mdc_len) {
+		Some(res) => res,
+		None => error!("routing header truncated")
+	};
+	let rest = match flags & FLAG_CHECKSUM != 0 {
+		true => match (split_bytes(rest, CHECKSUM_LEN), envelope.get(..ROUTING_HEADER_LEN - CHECKSUM_LEN + mdc_len)) {
+			(Some((expected, rest)), Some(before)) => {
+				if checksum(before, rest) != expected { return Err(DawnError::Corrupted); }
+				rest
+			},
+			_ => error!("routing header truncated")
+		},
+		false => rest
+	};
+	let (counter, rest) = match flags & FLAG_COUNTER != 0 {
+		true => match split_bytes(rest, COUNTER_LEN).map(|(counter, rest)| (<[u8; COUNTER_LEN]>::try_from(counter), rest)) {
+			Some((Ok(counter), rest)) => (Some(u64::from_be_bytes(counter)), rest),
+			_ => error!("routing header truncated")
+		},
+		false => (None, rest)
+	};
+	let header = RoutingHeader {
+		version,
+		class,
+		signed: flags & FLAG_SIGNED != 0,
+		escrowed: flags & FLAG_ESCROWED != 0,
+		thread: flags & FLAG_THREAD != 0,
+		mdc: encode(mdc),
+		counter,
+	};
+	Ok((Some(header), rest))
+}
+
+// read the header of a message without decrypting it (e.g. on a server)
+// returns None for messages of clients that predate the header
+pub fn read_routing_header(msg_ciphertext: &[u8]) -> Result<Option<RoutingHeader>, DawnError> {
+	match split_routing_header(msg_ciphertext) {
+		Ok((header, _)) => Ok(header),
+		Err(err) => Err(err)
+	}
+}
+
+// compare the header with the decrypted message
+// the counter is covered by the key of the message and has to match its sequence number (see chain.rs)
+pub(crate) fn check_routing_header(header: &RoutingHeader, msg_type: ContentType, mdc: &str, thread: bool, escrowed: bool, warning: Warning, counter: Option<u64>) -> Result<(), DawnError> {
+	if header.class != RoutingClass::of(msg_type) || header.mdc != mdc || header.thread != thread || header.escrowed != escrowed || header.signed != (warning == Warning::None) || header.counter != counter {
+		error!("routing header does not match the message");
+	}
+	Ok(())
+}
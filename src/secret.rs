@@ -0,0 +1,78 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Storage for secret key material
+// Secret keys live in their own heap allocation, so they don't get copied around when the key is moved, and are
+// overwritten with zeros when dropped. With the mlock feature, the allocation is also locked into memory, so it can't
+// be swapped to disk. Locking is best effort: if the limit for locked memory is reached, the key is kept unlocked.
+
+pub(crate) struct SecretBytes {
+	#[cfg(feature = "mlock")]
+	lock: Option<region::LockGuard>,
+	bytes: Box<[u8]>,
+}
+
+impl SecretBytes {
+	pub(crate) fn new(bytes: &[u8]) -> Self {
+		let bytes: Box<[u8]> = bytes.into();
+		SecretBytes {
+			#[cfg(feature = "mlock")]
+			lock: if bytes.is_empty() { None } else { region::lock(bytes.as_ptr(), bytes.len()).ok() },
+			bytes,
+		}
+	}
+	
+	pub(crate) fn as_bytes(&self) -> &[u8] {
+		&self.bytes
+	}
+	
+	// returns true if the memory is locked
+	pub(crate) fn is_locked(&self) -> bool {
+		#[cfg(feature = "mlock")]
+		{ self.lock.is_some() }
+		#[cfg(not(feature = "mlock"))]
+		{ false }
+	}
+}
+
+// secrets of the same length are compared in constant time, so the time taken doesn't reveal where they differ
+impl PartialEq for SecretBytes {
+	fn eq(&self, other: &Self) -> bool {
+		if self.bytes.len() != other.bytes.len() { return false; }
+		let diff = self.bytes.iter().zip(other.bytes.iter()).fold(0u8, |diff, (byte, other_byte)| diff | (byte ^ other_byte));
+		std::hint::black_box(diff) == 0
+	}
+}
+
+impl Eq for SecretBytes {}
+
+impl Clone for SecretBytes {
+	fn clone(&self) -> Self {
+		SecretBytes::new(&self.bytes)
+	}
+}
+
+impl Drop for SecretBytes {
+	fn drop(&mut self) {
+		// volatile writes can't be optimized away, the memory is unlocked and freed afterwards
+		for byte in self.bytes.iter_mut() {
+			unsafe { std::ptr::write_volatile(byte, 0) };
+		}
+		std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+	}
+}
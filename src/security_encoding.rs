@@ -0,0 +1,97 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Alternative encodings of security numbers
+// Comparing a long security number digit by digit is tedious, so clients can offer other representations of the same
+// value: a sequence of emoji, the names of these emoji (to read them out over a call) and groups of digits that are easy
+// to speak. All of them are derived deterministically from the security number, so every client renders identical
+// artifacts for the same pair of keys. The emoji table is the one of the Matrix SAS verification, whose emoji render
+// distinctly on all common platforms and have unambiguous names.
+
+use dawn_crypto::hash;
+use crate::domain::SECURITY_ENCODING_DOMAIN;
+
+const EMOJI_COUNT: usize = 16; // 96 bits
+const DIGIT_GROUP_COUNT: usize = 12; // 60 digits
+const DIGIT_GROUP_LEN: usize = 5;
+
+const EMOJI: [(&str, &str); 64] = [
+	("🐶", "Dog"), ("🐱", "Cat"), ("🦁", "Lion"), ("🐎", "Horse"), ("🦄", "Unicorn"), ("🐷", "Pig"), ("🐘", "Elephant"), ("🐰", "Rabbit"),
+	("🐼", "Panda"), ("🐓", "Rooster"), ("🐧", "Penguin"), ("🐢", "Turtle"), ("🐟", "Fish"), ("🐙", "Octopus"), ("🦋", "Butterfly"), ("🌷", "Flower"),
+	("🌳", "Tree"), ("🌵", "Cactus"), ("🍄", "Mushroom"), ("🌏", "Globe"), ("🌙", "Moon"), ("☁\u{fe0f}", "Cloud"), ("🔥", "Fire"), ("🍌", "Banana"),
+	("🍎", "Apple"), ("🍓", "Strawberry"), ("🌽", "Corn"), ("🍕", "Pizza"), ("🎂", "Cake"), ("❤\u{fe0f}", "Heart"), ("😀", "Smiley"), ("🤖", "Robot"),
+	("🎩", "Hat"), ("👓", "Glasses"), ("🔧", "Spanner"), ("🎅", "Santa"), ("👍", "Thumbs Up"), ("☂\u{fe0f}", "Umbrella"), ("⌛", "Hourglass"), ("⏰", "Clock"),
+	("🎁", "Gift"), ("💡", "Light Bulb"), ("📕", "Book"), ("✏\u{fe0f}", "Pencil"), ("📎", "Paperclip"), ("✂\u{fe0f}", "Scissors"), ("🔒", "Lock"), ("🔑", "Key"),
+	("🔨", "Hammer"), ("☎\u{fe0f}", "Telephone"), ("🏁", "Flag"), ("🚂", "Train"), ("🚲", "Bicycle"), ("✈\u{fe0f}", "Aeroplane"), ("🚀", "Rocket"), ("🏆", "Trophy"),
+	("⚽", "Ball"), ("🎸", "Guitar"), ("🎺", "Trumpet"), ("🔔", "Bell"), ("⚓", "Anchor"), ("🎧", "Headphones"), ("📁", "Folder"), ("📌", "Pin"),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecurityEncodings {
+	pub emoji: Vec<&'static str>,
+	pub emoji_names: Vec<&'static str>, // names of the emoji, in the same order
+	pub digit_groups: Vec<String>,
+}
+
+// derive len bytes for one encoding from the security number
+fn expand(label: &[u8], security_number: &str, len: usize) -> Vec<u8> {
+	let mut output = Vec::with_capacity(len);
+	let mut counter: u32 = 0;
+	while output.len() < len {
+		let mut input = SECURITY_ENCODING_DOMAIN.as_bytes().to_vec();
+		input.extend_from_slice(label);
+		input.extend_from_slice(&counter.to_be_bytes());
+		input.extend_from_slice(security_number.as_bytes());
+		output.extend_from_slice(&hash(&input));
+		counter += 1;
+	}
+	output.truncate(len);
+	output
+}
+
+// take count values of bits bits each from a byte string, most significant bit first
+pub(crate) fn split_bits(bytes: &[u8], bits: usize, count: usize) -> Vec<usize> {
+	(0..count).map(|index| {
+		(0..bits).fold(0, |value, bit| {
+			let position = index * bits + bit;
+			let bit_value = bytes.get(position / 8).map_or(0, |byte| (byte >> (7 - position % 8)) & 1);
+			(value << 1) | bit_value as usize
+		})
+	}).collect()
+}
+
+// derive the alternative encodings of a security number (as returned by Peer::security_number)
+pub fn encode_security_number(security_number: &str) -> SecurityEncodings {
+	let emoji_bytes = expand(b"emoji", security_number, (EMOJI_COUNT * 6).div_ceil(8));
+	let (emoji, emoji_names) = split_bits(&emoji_bytes, 6, EMOJI_COUNT).into_iter()
+		.filter_map(|index| EMOJI.get(index).copied())
+		.unzip();
+	
+	// every group is taken from 5 bytes modulo 100000, the bias is negligible
+	let digit_bytes = expand(b"digits", security_number, DIGIT_GROUP_COUNT * 5);
+	let digit_groups = digit_bytes.chunks(5).map(|chunk| {
+		let value = chunk.iter().fold(0u64, |value, byte| (value << 8) | *byte as u64);
+		format!("{:0width$}", value % 10u64.pow(DIGIT_GROUP_LEN as u32), width = DIGIT_GROUP_LEN)
+	}).collect();
+	
+	SecurityEncodings {
+		emoji,
+		emoji_names,
+		digit_groups,
+	}
+}
@@ -0,0 +1,149 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+// Sender key chains
+// Group messages are encrypted once for all members with the sender key of the sending member (see group.rs), which every
+// member gets a copy of. Unlike the pfs keys of 1:1 conversations, whose ratchet mixes in a fresh secret with every
+// message, a sender key can only be a symmetric chain: the key of every message is derived from the current chain key
+// with HKDF-SHA256 and the chain key advances with the same KDF. Every message carries its counter, so a member that gets
+// message 5 while expecting message 3 derives the keys of 3 and 4 on the way and keeps them as skipped keys until these
+// messages arrive. Every key is used for a single message and forgotten afterwards, so a replayed message can't be
+// decrypted again. The number of skipped keys is bounded, so a forged counter can't make a member derive and keep an
+// unbounded number of keys; the oldest skipped keys are dropped first.
+
+use std::collections::BTreeMap;
+use crate::kdf::hkdf_sha256;
+use crate::secret::SecretBytes;
+use crate::domain::{CHAIN_KEY_DOMAIN, CHAIN_MESSAGE_KEY_DOMAIN};
+use crate::DawnError;
+
+// number of skipped keys a member keeps per sender
+const MAX_SKIPPED_KEYS: usize = 1000;
+
+// the chain of the own sender key
+#[derive(Clone)]
+pub(crate) struct SenderChain {
+	chain_key: SecretBytes,
+	counter: u64, // counter of the next message
+}
+
+// the chain of the sender key of another member
+#[derive(Clone)]
+pub(crate) struct MemberChain {
+	chain_key: SecretBytes,
+	counter: u64, // counter of the next message in order
+	skipped: BTreeMap<u64, SecretBytes>, // keys of messages that were skipped, by counter
+}
+
+// how a member chain changes once a message was decrypted with the key returned by MemberChain::key_for
+pub(crate) enum ChainUpdate {
+	UseSkipped(u64),
+	Advance { chain_key: SecretBytes, counter: u64, skipped: Vec<(u64, SecretBytes)> },
+}
+
+fn message_key(chain_key: &[u8], salt: &[u8]) -> SecretBytes {
+	SecretBytes::new(&hkdf_sha256(salt, chain_key, CHAIN_MESSAGE_KEY_DOMAIN.as_bytes()))
+}
+
+fn next_chain_key(chain_key: &[u8], salt: &[u8]) -> SecretBytes {
+	SecretBytes::new(&hkdf_sha256(salt, chain_key, CHAIN_KEY_DOMAIN.as_bytes()))
+}
+
+impl SenderChain {
+	pub(crate) fn new(chain_key: &[u8]) -> Self {
+		SenderChain {
+			chain_key: SecretBytes::new(chain_key),
+			counter: 0,
+		}
+	}
+	
+	// counter of the next message
+	pub(crate) fn counter(&self) -> u64 {
+		self.counter
+	}
+	
+	pub(crate) fn chain_key(&self) -> &[u8] {
+		self.chain_key.as_bytes()
+	}
+	
+	// returns the counter and key of the next message
+	pub(crate) fn next_key(&self, salt: &[u8]) -> (u64, SecretBytes) {
+		(self.counter, message_key(self.chain_key.as_bytes(), salt))
+	}
+	
+	// move on to the next message once the current one was sent
+	pub(crate) fn advance(&mut self, salt: &[u8]) -> Result<(), DawnError> {
+		self.counter = match self.counter.checked_add(1) {
+			Some(res) => res,
+			None => error!("message chain is exhausted")
+		};
+		self.chain_key = next_chain_key(self.chain_key.as_bytes(), salt);
+		Ok(())
+	}
+}
+
+impl MemberChain {
+	// continue the chain of a member at the state it was handed out with
+	pub(crate) fn new(chain_key: &[u8], counter: u64) -> Self {
+		MemberChain {
+			chain_key: SecretBytes::new(chain_key),
+			counter,
+			skipped: BTreeMap::new(),
+		}
+	}
+	
+	// returns the key of the message with the given counter and how the chain changes once the message was decrypted,
+	// without changing the chain yet, so a message that fails to decrypt leaves the chain as it was
+	pub(crate) fn key_for(&self, counter: u64, salt: &[u8]) -> Result<(SecretBytes, ChainUpdate), DawnError> {
+		if counter < self.counter {
+			return match self.skipped.get(&counter) {
+				Some(key) => Ok((key.clone(), ChainUpdate::UseSkipped(counter))),
+				None => error!("message was already received or its key was dropped")
+			};
+		}
+		if counter - self.counter > MAX_SKIPPED_KEYS as u64 { error!("message skips too many messages"); }
+		
+		let mut chain_key = self.chain_key.clone();
+		let mut skipped = Vec::new();
+		for skipped_counter in self.counter..counter {
+			skipped.push((skipped_counter, message_key(chain_key.as_bytes(), salt)));
+			chain_key = next_chain_key(chain_key.as_bytes(), salt);
+		}
+		let key = message_key(chain_key.as_bytes(), salt);
+		let next_counter = match counter.checked_add(1) {
+			Some(res) => res,
+			None => error!("message chain is exhausted")
+		};
+		Ok((key, ChainUpdate::Advance { chain_key: next_chain_key(chain_key.as_bytes(), salt), counter: next_counter, skipped }))
+	}
+	
+	pub(crate) fn apply(&mut self, update: ChainUpdate) {
+		match update {
+			ChainUpdate::UseSkipped(counter) => {
+				self.skipped.remove(&counter);
+			},
+			ChainUpdate::Advance { chain_key, counter, skipped } => {
+				self.chain_key = chain_key;
+				self.counter = counter;
+				self.skipped.extend(skipped);
+				while self.skipped.len() > MAX_SKIPPED_KEYS {
+					self.skipped.pop_first();
+				}
+			}
+		}
+	}
+}
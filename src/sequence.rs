@@ -0,0 +1,99 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Sequence numbers
+// The sender numbers the messages of a conversation with a SequenceCounter, starting at 0, and the receiver follows them
+// with a SequenceTracker. A message whose sequence number is larger than the next expected one shows that messages in
+// between were lost, so the client can tell the user that n messages could not be delivered instead of silently losing
+// track. The sequence number is part of the encrypted message and covered by its signature. Messages sent on a message
+// chain carry their chain counter as sequence number (see chain.rs).
+
+use crate::DawnError;
+
+// the sequence number of the own side of a conversation, it has to be persisted together with the session state
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SequenceCounter {
+	next: u64,
+}
+
+// how the sequence number of a received message relates to the messages received before
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceGap {
+	None,
+	MissedMessages(u64), // this many messages sent before this one have not arrived
+	Late, // the message was sent before the last received one, e.g. because it was delivered out of order
+}
+
+// the next expected sequence number of the peer, it has to be persisted together with the session state
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SequenceTracker {
+	next: u64,
+}
+
+impl SequenceCounter {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// continue with a persisted counter
+	pub fn resume(next: u64) -> Self {
+		SequenceCounter { next }
+	}
+	
+	// returns the sequence number of the next message
+	pub fn next_seq(&self) -> u64 {
+		self.next
+	}
+	
+	pub(crate) fn next(&mut self) -> Result<u64, DawnError> {
+		let seq = self.next;
+		self.next = match seq.checked_add(1) {
+			Some(res) => res,
+			None => error!("sequence number exhausted")
+		};
+		Ok(seq)
+	}
+}
+
+impl SequenceTracker {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// continue with a persisted tracker
+	pub fn resume(next: u64) -> Self {
+		SequenceTracker { next }
+	}
+	
+	// returns the sequence number the next message of the peer should carry
+	pub fn next_seq(&self) -> u64 {
+		self.next
+	}
+	
+	// check the sequence number of a received message
+	// late messages don't move the tracker back
+	pub(crate) fn check(&mut self, seq: u64) -> SequenceGap {
+		if seq < self.next { return SequenceGap::Late; }
+		let gap = match seq - self.next {
+			0 => SequenceGap::None,
+			missed => SequenceGap::MissedMessages(missed)
+		};
+		self.next = seq.saturating_add(1);
+		gap
+	}
+}
@@ -0,0 +1,97 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Server challenge-response authentication
+// Home servers authenticate API calls of an account by handing out a challenge: a random nonce that names the server
+// and expires soon. The client signs it with the signature key of its identity and sends the signature back, the server
+// checks it against the key the account registered with. The signature covers the name of the server, so a challenge
+// relayed by another server can't be used to log in there, and its own domain, so it can never be taken for the
+// signature of a message or the other way round. Servers have to accept every nonce only once.
+
+use serde::{Serialize, Deserialize};
+use dawn_crypto::sym_key_gen;
+use crate::canonical::canonical_json;
+use crate::codec::encode;
+use crate::keys::{SignPublicKey, SignSecretKey};
+use crate::signature::{sign_detached, verify_detached};
+use crate::domain::SERVER_CHALLENGE_DOMAIN;
+use crate::DawnError;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ServerChallenge {
+	pub server: String, // name of the server that issued the challenge, e.g. its domain
+	pub nonce: String,
+	pub expires_at: u64,
+}
+
+impl ServerChallenge {
+	// issue a challenge as server that can be answered for ttl seconds
+	pub fn new(server: &str, now: u64, ttl: u64) -> Self {
+		ServerChallenge {
+			server: server.to_string(),
+			nonce: encode(sym_key_gen()),
+			expires_at: now.saturating_add(ttl),
+		}
+	}
+	
+	pub fn to_bytes(&self) -> Result<Vec<u8>, DawnError> {
+		match serde_json::to_vec(self) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "json serialization failed")
+		}
+	}
+	
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, DawnError> {
+		let challenge = match serde_json::from_slice::<ServerChallenge>(bytes) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "server challenge json parsing failed")
+		};
+		if challenge.server.is_empty() || challenge.nonce.is_empty() { error!("server challenge is missing its server or nonce"); }
+		Ok(challenge)
+	}
+	
+	fn check(&self, server: &str, now: u64) -> Result<(), DawnError> {
+		if self.server != server { error!("server challenge was issued by another server"); }
+		if now > self.expires_at { error!("server challenge expired"); }
+		Ok(())
+	}
+}
+
+// answer a challenge of the own home server
+// server is the name the client knows its home server by, challenges of any other server are refused
+// returns the signature, which is sent back to the server
+pub fn sign_server_challenge(challenge: &[u8], server: &str, own_seckey_sig: &SignSecretKey, now: u64) -> Result<Vec<u8>, DawnError> {
+	let challenge = match ServerChallenge::from_bytes(challenge) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	if let Err(err) = challenge.check(server, now) { return Err(err); }
+	match canonical_json(&challenge) {
+		Ok(content) => sign_detached(SERVER_CHALLENGE_DOMAIN, &content, own_seckey_sig.as_bytes()),
+		Err(err) => Err(err)
+	}
+}
+
+// check the answer to a challenge as server, using the signature key the account registered with
+pub fn verify_server_challenge(challenge: &ServerChallenge, signature: &[u8], server: &str, remote_pubkey_sig: &SignPublicKey, now: u64) -> Result<(), DawnError> {
+	if let Err(err) = challenge.check(server, now) { return Err(err); }
+	match canonical_json(challenge) {
+		Ok(content) => verify_detached(SERVER_CHALLENGE_DOMAIN, &content, signature, remote_pubkey_sig.as_bytes()),
+		Err(err) => Err(err)
+	}
+}
@@ -0,0 +1,781 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Sessions
+// A Session owns the state of one conversation: the conversation secrets, the keys of both sides and the message chains
+// of the sending and the receiving direction (see chain.rs). send and receive advance the chains internally, so clients
+// don't have to thread the keys through every call and can't forget to replace a key after a message. A chain is only
+// advanced once the message was encrypted or decrypted successfully, so a failed call leaves the session usable. Messages
+// that arrive before earlier ones are held by the session and decrypted by receive_held once the earlier ones arrived.
+// A session also tracks the close handshake of the conversation (see close.rs): once it is closing or closed, send
+// rejects every message, only the acknowledgement of a close request of the peer can still be sent.
+// The profile policy of the peer (see profile_update.rs) is kept with the session, so send_profile_update only shares
+// what the user chose to share with this contact. Messages are sent in the binary wire format if the peer announced support
+// for it (see wire_format.rs). The session also keeps the local petname of the peer and the nickname both sides agreed on
+// (see nickname.rs), which display_name resolves. The disappearing message timer of the conversation (see disappearing.rs)
+// is kept as well and applied to every text, voice and picture message sent while it is set. The bytes of ciphertext sent
+// and received are counted by content type (see bandwidth.rs) and persisted with the rest of the state.
+// Every sent message carries the device counter of the session and every received one is checked against the counters
+// seen from the peer before, so a copy of the session of the peer restored twice from the same export is detected and its
+// messages are rejected with DawnError::Security (see device_counter.rs).
+// Every message is sent with its own message detail code: like clients of the free functions do, the session advances the
+// message id with get_next_id after every sent and received message and derives the code from it, so messages of a
+// conversation can't be linked by their code. The conversation id itself stays the same.
+// Sessions are persisted with export, which encrypts the state with a key derived from a passphrase (PBKDF2-HMAC-SHA256
+// with a random salt) into a versioned blob: version (1 byte) || iterations (u32 BE) || salt || encrypted state. Clients
+// that encrypt their storage themselves can enable the session-serde feature and serialize sessions directly instead.
+// Limits, the clock and the maximum number of held messages are configuration, not state: they are not persisted and have to
+// be set again after import.
+
+use serde::{Serialize, Deserialize};
+use dawn_crypto::{encrypt_data, decrypt_data, sym_key_gen, id_gen, get_next_id, predictable_mdc_gen};
+use crate::codec::{encode, decode, split_bytes};
+use crate::keys::{KyberPublicKey, KyberSecretKey, SignPublicKey, SignSecretKey};
+use crate::peer::{Peer, Verification};
+use crate::clock::{Clock, SystemClock};
+use crate::limits::ParseLimits;
+use crate::config::ProtocolConfig;
+use crate::secret::SecretBytes;
+use crate::warning::Warning;
+use crate::identity::Identity;
+use crate::init_request::{InitRequestResult, ParsedInitRequest, ParsedInitResponse};
+use crate::outgoing::{OutgoingMessage, SendOptions};
+use crate::received::{ReceivedMessage, ParsedMessage};
+use crate::device_counter::{DeviceCounter, CounterTracker};
+use crate::bandwidth::BandwidthCounters;
+use crate::passive::PassiveSession;
+use crate::chain::{SendChain, ReceiveChain};
+use crate::wire_format::WireFormat;
+use crate::profile_update::{ProfileUpdate, ProfilePolicy, gen_profile_update};
+use crate::disappearing::{gen_disappearing_timer, parse_disappearing_timer};
+use crate::nickname::{NicknameEvent, NicknameProposal, gen_nickname_event, parse_nickname_event, resolve_display_name};
+use crate::receipt::gen_delivery_receipt_at;
+use crate::close::{ConversationClose, gen_conversation_close, parse_conversation_close};
+use crate::{capability, event};
+use crate::client_key::{self, ClientKeyPurpose};
+use crate::kdf::pbkdf2_sha256;
+use crate::domain::SESSION_EXPORT_DOMAIN;
+use crate::{send_chain_parts, receive_chain_msg, receive_held_chain_msg, parse_previous_chain_msg};
+use crate::DawnError;
+
+const SESSION_STATE_VERSION: u8 = 1;
+const EXPORT_ITERATIONS: u32 = 600_000;
+const MAX_EXPORT_ITERATIONS: u32 = 10 * EXPORT_ITERATIONS; // keeps forged blobs from stalling import
+const EXPORT_SALT_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SessionStatus {
+	#[default]
+	Open,
+	Closing(String), // a close request with this id was sent and waits for its acknowledgement
+	Closed,
+}
+
+pub struct Session {
+	id: String,
+	id_salt: SecretBytes,
+	msg_id: String, // id the message detail code of the next message is derived from
+	pfs_salt: SecretBytes,
+	mdc_seed: String,
+	own_seckey_kyber: KyberSecretKey,
+	own_seckey_sig: Option<SignSecretKey>,
+	peer: Peer,
+	send_chain: SendChain,
+	recv_chain: ReceiveChain,
+	status: SessionStatus,
+	close_ack: Option<String>, // id of a close request of the peer that was not acknowledged yet
+	profile_policy: ProfilePolicy,
+	petname: Option<String>, // local name of the peer, never sent
+	nickname: Option<String>, // name of the peer both sides agreed on
+	nickname_sent: Option<NicknameProposal>, // own proposal waiting for an answer of the peer
+	nickname_received: Option<NicknameProposal>, // proposal of the peer waiting for an answer
+	disappearing: Option<u64>, // disappearing message timer of the conversation in seconds
+	device: DeviceCounter, // counter of the sent messages, the device id is random
+	peer_devices: CounterTracker, // counters of the received messages
+	bandwidth: BandwidthCounters,
+	config: ProtocolConfig,
+	clock: Box<dyn Clock>,
+}
+
+// serialized form of a session
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct SessionState {
+	version: u8,
+	id: String,
+	#[serde(default)]
+	id_salt: String,
+	#[serde(default)]
+	msg_id: Option<String>,
+	pfs_salt: String,
+	mdc_seed: String,
+	own_seckey_kyber: String,
+	own_seckey_sig: Option<String>,
+	peer_pubkey_kyber: String,
+	peer_pubkey_sig: String,
+	peer_name: String,
+	peer_verified: bool,
+	peer_capabilities: Vec<String>,
+	send_pfs_key: String, // pfs key of the next sent message
+	recv_pfs_key: String, // pfs key of the next received message in order
+	#[serde(default)]
+	send_counter: u64,
+	#[serde(default)]
+	recv_counter: u64,
+	#[serde(default)]
+	held: Vec<(u64, String)>, // ciphertexts of received messages that arrived early
+	#[serde(default)]
+	recv_previous: Option<(String, String)>, // pfs key and ciphertext hash of the last received message in order
+	#[serde(default)]
+	status: SessionStatus,
+	#[serde(default)]
+	close_ack: Option<String>,
+	#[serde(default)]
+	profile_policy: ProfilePolicy,
+	#[serde(default)]
+	petname: Option<String>,
+	#[serde(default)]
+	nickname: Option<String>,
+	#[serde(default)]
+	nickname_sent: Option<NicknameProposal>,
+	#[serde(default)]
+	nickname_received: Option<NicknameProposal>,
+	#[serde(default)]
+	disappearing: Option<u64>,
+	#[serde(default)]
+	device: Option<DeviceCounter>,
+	#[serde(default)]
+	peer_devices: CounterTracker,
+	#[serde(default)]
+	bandwidth: BandwidthCounters,
+}
+
+// decode a hex encoded key of a session state
+fn decode_key<K: TryFrom<Vec<u8>, Error = DawnError>>(key: &str) -> Result<K, DawnError> {
+	match decode(key).map(K::try_from) {
+		Ok(Ok(res)) => Ok(res),
+		_ => error!("session state contains an invalid key")
+	}
+}
+
+// key an exported session is encrypted with
+fn export_key(passphrase: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+	pbkdf2_sha256(passphrase.as_bytes(), &[SESSION_EXPORT_DOMAIN.as_bytes(), salt].concat(), iterations).to_vec()
+}
+
+impl Session {
+	// create a session from the state of a conversation
+	// the pfs keys start the message chains of both directions
+	// messages are sent unsigned unless own signature keys are set with sign_with
+	pub fn new(id: &str, id_salt: &[u8], pfs_salt: &[u8], mdc_seed: &str, own_seckey_kyber: KyberSecretKey, peer: Peer, send_pfs_key: &[u8], recv_pfs_key: &[u8]) -> Self {
+		Session {
+			id: id.to_string(),
+			id_salt: SecretBytes::new(id_salt),
+			msg_id: id.to_string(),
+			pfs_salt: SecretBytes::new(pfs_salt),
+			mdc_seed: mdc_seed.to_string(),
+			own_seckey_kyber,
+			own_seckey_sig: None,
+			peer,
+			send_chain: SendChain::new(send_pfs_key),
+			recv_chain: ReceiveChain::new(recv_pfs_key),
+			config: ProtocolConfig::default(),
+			status: SessionStatus::Open,
+			close_ack: None,
+			profile_policy: ProfilePolicy::default(),
+			petname: None,
+			nickname: None,
+			nickname_sent: None,
+			nickname_received: None,
+			disappearing: None,
+			device: DeviceCounter::new(&id_gen()),
+			peer_devices: CounterTracker::new(),
+			bandwidth: BandwidthCounters::new(),
+			clock: Box::new(SystemClock),
+		}
+	}
+	
+	// create the session of the initiating side once the init request was accepted
+	// request is the result of building the init request, response the parsed accept of the peer
+	pub fn from_init_response(identity: &Identity, request: &InitRequestResult, response: &ParsedInitResponse) -> Result<Self, DawnError> {
+		let own_seckey_kyber = match KyberSecretKey::try_from(request.own_seckey_kyber.as_slice()) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		Ok(Session::new(&request.id, &request.id_salt, &request.pfs_salt, &request.mdc_seed, own_seckey_kyber, response.peer.clone(), &request.own_pfs_key, &response.new_pfs_key).sign_with(identity.seckey_sig.clone()))
+	}
+	
+	// accept a parsed init request and create the session of the accepting side
+	// returns the session, the message detail code and the ciphertext of the accept that has to be sent to the peer
+	pub fn accept(identity: &Identity, request: &ParsedInitRequest) -> Result<(Self, String, Vec<u8>), DawnError> {
+		let (send_pfs_key, (_, own_seckey_kyber), mdc, ciphertext) = match identity.accept_init_request(request.peer.pubkey_kyber.as_bytes(), &request.own_pfs_key, &request.pfs_salt, &request.id, &request.mdc_seed) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let own_seckey_kyber = match KyberSecretKey::try_from(own_seckey_kyber) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let session = Session::new(&request.id, &request.id_salt, &request.pfs_salt, &request.mdc_seed, own_seckey_kyber, request.peer.clone(), &send_pfs_key, &request.remote_pfs_key).sign_with(identity.seckey_sig.clone());
+		Ok((session, mdc, ciphertext))
+	}
+	
+	// sign sent messages with these keys
+	pub fn sign_with(mut self, own_seckey_sig: SignSecretKey) -> Self {
+		self.own_seckey_sig = Some(own_seckey_sig);
+		self
+	}
+	
+	// reject received messages exceeding the limits (see ParseLimits::low_memory)
+	pub fn limits(mut self, limits: ParseLimits) -> Self {
+		self.config.limits = limits;
+		self
+	}
+	
+	// send and receive messages according to the configuration (see ProtocolConfig)
+	// messages are only compressed if the peer announced capability::COMPRESSION, and only inflated if compression is enabled
+	pub fn config(mut self, config: ProtocolConfig) -> Self {
+		self.config = config;
+		self
+	}
+	
+	// hold at most this many messages that arrived early (DEFAULT_MAX_SKIPPED by default)
+	// a received message that skips more messages than that is rejected
+	pub fn max_skipped(mut self, max_skipped: usize) -> Self {
+		self.recv_chain = self.recv_chain.max_skipped(max_skipped);
+		self
+	}
+	
+	// use another clock than the system clock (e.g. a ManualClock in tests)
+	pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+		self.clock = Box::new(clock);
+		self
+	}
+	
+	pub fn id(&self) -> &str {
+		&self.id
+	}
+	
+	pub fn peer(&self) -> &Peer {
+		&self.peer
+	}
+	
+	pub fn status(&self) -> &SessionStatus {
+		&self.status
+	}
+	
+	pub fn profile_policy(&self) -> ProfilePolicy {
+		self.profile_policy
+	}
+	
+	// change what is shared with the peer in later profile updates
+	pub fn set_profile_policy(&mut self, profile_policy: ProfilePolicy) {
+		self.profile_policy = profile_policy;
+	}
+	
+	// the local name of the peer, it is never sent to anyone
+	pub fn petname(&self) -> Option<&str> {
+		self.petname.as_deref()
+	}
+	
+	// set or remove the local name of the peer
+	pub fn set_petname(&mut self, petname: Option<&str>) {
+		self.petname = petname.map(|petname| petname.to_string());
+	}
+	
+	// the name of the peer both sides agreed on
+	pub fn nickname(&self) -> Option<&str> {
+		self.nickname.as_deref()
+	}
+	
+	// the name to show for the peer (see resolve_display_name)
+	pub fn display_name(&self) -> &str {
+		resolve_display_name(self.petname.as_deref(), self.nickname.as_deref(), &self.peer.name)
+	}
+	
+	// a nickname proposal of the peer that was neither accepted nor declined yet
+	pub fn nickname_proposal(&self) -> Option<&NicknameProposal> {
+		self.nickname_received.as_ref()
+	}
+	
+	// seconds after which sent and received messages disappear, None if the timer is off
+	pub fn disappearing_timer(&self) -> Option<u64> {
+		self.disappearing
+	}
+	
+	// counters of the received messages that are still missing before the held ones
+	pub fn skipped(&self) -> Vec<u64> {
+		self.recv_chain.skipped()
+	}
+	
+	// counters of the received messages that are held until the missing ones arrive
+	pub fn held(&self) -> Vec<u64> {
+		self.recv_chain.held()
+	}
+	
+	// bytes of ciphertext sent and received in the conversation
+	pub fn bandwidth(&self) -> &BandwidthCounters {
+		&self.bandwidth
+	}
+	
+	// start counting the bytes sent and received from zero again
+	pub fn reset_bandwidth(&mut self) {
+		self.bandwidth.reset();
+	}
+	
+	// current time according to the clock of the session
+	pub fn now(&self) -> u64 {
+		self.clock.now()
+	}
+	
+	// encrypt a message for the peer and advance the sending chain
+	// returns the message detail code and the ciphertext
+	pub fn send(&mut self, message: &OutgoingMessage) -> Result<(String, Vec<u8>), DawnError> {
+		if self.status != SessionStatus::Open { error!("conversation is closed"); }
+		self.send_content(message)
+	}
+	
+	fn send_content(&mut self, message: &OutgoingMessage) -> Result<(String, Vec<u8>), DawnError> {
+		let next_msg_id = match self.next_msg_id() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let own_seckey_sig = self.own_seckey_sig.as_ref().map(|seckey| seckey.as_bytes());
+		let config = ProtocolConfig {
+			compression: self.config.compression && self.peer.capabilities.iter().any(|capability| capability == capability::COMPRESSION),
+			..self.config
+		};
+		// the counter is only taken over once the message was sent
+		let mut device = self.device.clone();
+		let options = match SendOptions::new().format(WireFormat::for_peer(&self.peer.capabilities)).config(config).counted(&mut device) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let mut parts = message.parts();
+		if message.can_disappear() { parts.expires_after = parts.expires_after.or(self.disappearing); }
+		let sent = match send_chain_parts(&mut self.send_chain, parts, &options, self.peer.pubkey_kyber.as_bytes(), own_seckey_sig, self.pfs_salt.as_bytes(), &predictable_mdc_gen(&self.mdc_seed, &self.msg_id)) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.msg_id = next_msg_id;
+		self.device = device;
+		self.bandwidth.record_sent(message.content_type(), sent.1.len());
+		Ok(sent)
+	}
+	
+	// the message id that follows the current one, it is only taken over once a message was sent or received
+	fn next_msg_id(&self) -> Result<String, DawnError> {
+		match get_next_id(&self.msg_id, self.id_salt.as_bytes()) {
+			Ok(res) => Ok(res),
+			Err(err) => Err(DawnError::Crypto(err))
+		}
+	}
+	
+	// decrypt a message of the peer and advance the receiving chain
+	// messages have to be signed by the peer, they can arrive in any order but each one is only decrypted once
+	// a message that arrives before earlier ones is held and returns None, receive_held decrypts it once they arrived
+	// a message of a cloned session of the peer is rejected with DawnError::Security
+	// returns the message, message detail code and warning
+	pub fn receive(&mut self, msg_ciphertext: &[u8]) -> Result<Option<(ReceivedMessage, String, Warning)>, DawnError> {
+		let next_msg_id = match self.next_msg_id() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		// a clone of the session of the peer sends its messages with the counters the chain already decrypted
+		if let Some(parsed) = parse_previous_chain_msg(&self.recv_chain, msg_ciphertext, self.own_seckey_kyber.as_bytes(), Some(self.peer.pubkey_sig.as_bytes()), self.pfs_salt.as_bytes(), &self.config) {
+			if let Some(event) = parsed.check_device(&mut self.peer_devices) { return Err(DawnError::Security(event)); }
+		}
+		match receive_chain_msg(&mut self.recv_chain, msg_ciphertext, self.own_seckey_kyber.as_bytes(), Some(self.peer.pubkey_sig.as_bytes()), self.pfs_salt.as_bytes(), &self.config) {
+			Ok(Some(parsed)) => self.process_received(next_msg_id, parsed, msg_ciphertext.len()).map(Some),
+			Ok(None) => Ok(None),
+			Err(err) => Err(err)
+		}
+	}
+	
+	// decrypt the next held message of the peer, if the messages before it arrived
+	// call it after every message receive decrypted until it returns None
+	// returns the same as receive
+	pub fn receive_held(&mut self) -> Result<Option<(ReceivedMessage, String, Warning)>, DawnError> {
+		let next_msg_id = match self.next_msg_id() {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		match receive_held_chain_msg(&mut self.recv_chain, self.own_seckey_kyber.as_bytes(), Some(self.peer.pubkey_sig.as_bytes()), self.pfs_salt.as_bytes(), &self.config) {
+			Ok(Some((parsed, ciphertext_len))) => self.process_received(next_msg_id, parsed, ciphertext_len).map(Some),
+			Ok(None) => Ok(None),
+			Err(err) => Err(err)
+		}
+	}
+	
+	// apply the events of a decrypted message to the session
+	fn process_received(&mut self, next_msg_id: String, parsed: ParsedMessage, ciphertext_len: usize) -> Result<(ReceivedMessage, String, Warning), DawnError> {
+		// the chain was advanced already, so an invalid close or nickname message is consumed like any other message
+		self.msg_id = next_msg_id;
+		self.bandwidth.record_received(parsed.message.content_type(), ciphertext_len);
+		if let Some(event) = parsed.check_device(&mut self.peer_devices) { return Err(DawnError::Security(event)); }
+		let ParsedMessage { message: content, mdc, warning, .. } = parsed;
+		if let ReceivedMessage::Internal { event: event::NICKNAME, data } = &content {
+			let nickname_event = match parse_nickname_event(data) {
+				Ok(res) => res,
+				Err(err) => return Err(err)
+			};
+			match nickname_event {
+				NicknameEvent::Propose(proposal) => self.nickname_received = Some(proposal),
+				NicknameEvent::Accept(id) => {
+					let proposal = match self.nickname_sent.take_if(|proposal| proposal.id == id) {
+						Some(res) => res,
+						None => error!("acceptance does not match a nickname proposal")
+					};
+					if let Some(nickname) = proposal.recipient_nickname { self.nickname = Some(nickname); }
+				},
+				NicknameEvent::Decline(id) => {
+					if self.nickname_sent.take_if(|proposal| proposal.id == id).is_none() { error!("decline does not match a nickname proposal"); }
+				}
+			}
+		}
+		if let ReceivedMessage::Internal { event: event::DISAPPEARING_TIMER, data } = &content {
+			match parse_disappearing_timer(data) {
+				Ok(timer) => self.disappearing = timer.expires_after,
+				Err(err) => return Err(err)
+			}
+		}
+		if let ReceivedMessage::Internal { event: event::CONVERSATION_CLOSE, data } = &content {
+			let close = match parse_conversation_close(data, &self.id, self.peer.pubkey_sig.as_bytes()) {
+				Ok(res) => res,
+				Err(err) => return Err(err)
+			};
+			match close {
+				ConversationClose::Request(close_id) => {
+					self.status = SessionStatus::Closed;
+					self.close_ack = Some(close_id);
+				},
+				ConversationClose::Ack(close_id) => {
+					if self.status != SessionStatus::Closing(close_id) { error!("acknowledgement does not match a close request"); }
+					self.status = SessionStatus::Closed;
+				}
+			}
+		}
+		Ok((content, mdc, warning))
+	}
+	
+	// send the part of a profile update the profile policy of the peer shares
+	// returns the message detail code and the ciphertext or None if the policy withholds every field of the update
+	pub fn send_profile_update(&mut self, update: &ProfileUpdate) -> Result<Option<(String, Vec<u8>)>, DawnError> {
+		let data = match gen_profile_update(update, &self.profile_policy) {
+			Ok(Some(res)) => res,
+			Ok(None) => return Ok(None),
+			Err(err) => return Err(err)
+		};
+		match self.send(&OutgoingMessage::internal(event::PROFILE_UPDATE, &data)) {
+			Ok(res) => Ok(Some(res)),
+			Err(err) => Err(err)
+		}
+	}
+	
+	// acknowledge the delivery of a received message, stamped with the time of the clock of the session
+	// clients send this for every received message except internal messages, so receipts don't acknowledge each other
+	// returns the message detail code and the ciphertext of the delivery receipt
+	pub fn send_delivery_receipt(&mut self, mdc: &str) -> Result<(String, Vec<u8>), DawnError> {
+		let receipt = match gen_delivery_receipt_at(mdc, self.clock.now()) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.send(&receipt)
+	}
+	
+	// set the disappearing message timer for both sides, None turns it off
+	// returns the message detail code and the ciphertext of the timer event
+	pub fn set_disappearing_timer(&mut self, expires_after: Option<u64>) -> Result<(String, Vec<u8>), DawnError> {
+		let timer = match gen_disappearing_timer(expires_after) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let (mdc, ciphertext) = match self.send(&timer) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.disappearing = expires_after;
+		Ok((mdc, ciphertext))
+	}
+	
+	// propose nicknames to the peer: how the peer is shown to this side and how this side wants to be shown to the peer
+	// replaces an earlier proposal that was not answered yet
+	// returns the message detail code and the ciphertext of the proposal
+	pub fn propose_nickname(&mut self, recipient_nickname: Option<&str>, sender_nickname: Option<&str>) -> Result<(String, Vec<u8>), DawnError> {
+		let proposal = NicknameProposal {
+			id: id_gen(),
+			recipient_nickname: recipient_nickname.map(|nickname| nickname.to_string()),
+			sender_nickname: sender_nickname.map(|nickname| nickname.to_string()),
+		};
+		let (mdc, ciphertext) = match self.send_nickname_event(&NicknameEvent::Propose(proposal.clone())) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.nickname_sent = Some(proposal);
+		Ok((mdc, ciphertext))
+	}
+	
+	// accept the nickname proposal of the peer, the peer is shown with the nickname it proposed for itself
+	// returns the message detail code and the ciphertext of the acceptance
+	pub fn accept_nickname(&mut self) -> Result<(String, Vec<u8>), DawnError> {
+		let proposal = match &self.nickname_received {
+			Some(res) => res.clone(),
+			None => error!("there is no nickname proposal to accept")
+		};
+		let (mdc, ciphertext) = match self.send_nickname_event(&NicknameEvent::Accept(proposal.id)) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		if let Some(nickname) = proposal.sender_nickname { self.nickname = Some(nickname); }
+		self.nickname_received = None;
+		Ok((mdc, ciphertext))
+	}
+	
+	// decline the nickname proposal of the peer
+	// returns the message detail code and the ciphertext of the decline
+	pub fn decline_nickname(&mut self) -> Result<(String, Vec<u8>), DawnError> {
+		let id = match &self.nickname_received {
+			Some(res) => res.id.clone(),
+			None => error!("there is no nickname proposal to decline")
+		};
+		let (mdc, ciphertext) = match self.send_nickname_event(&NicknameEvent::Decline(id)) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.nickname_received = None;
+		Ok((mdc, ciphertext))
+	}
+	
+	fn send_nickname_event(&mut self, nickname_event: &NicknameEvent) -> Result<(String, Vec<u8>), DawnError> {
+		let data = match gen_nickname_event(nickname_event) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.send(&OutgoingMessage::internal(event::NICKNAME, &data))
+	}
+	
+	// ask the peer to close the conversation, afterwards nothing can be sent anymore
+	// returns the message detail code and the ciphertext of the close request
+	pub fn close(&mut self) -> Result<(String, Vec<u8>), DawnError> {
+		if self.status != SessionStatus::Open { error!("conversation is closed"); }
+		let close_id = id_gen();
+		let (mdc, ciphertext) = match self.send_close(&ConversationClose::Request(close_id.clone())) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.status = SessionStatus::Closing(close_id);
+		Ok((mdc, ciphertext))
+	}
+	
+	// acknowledge a close request received from the peer
+	// returns the message detail code and the ciphertext of the acknowledgement
+	pub fn acknowledge_close(&mut self) -> Result<(String, Vec<u8>), DawnError> {
+		let close_id = match &self.close_ack {
+			Some(res) => res.clone(),
+			None => error!("there is no close request to acknowledge")
+		};
+		let (mdc, ciphertext) = match self.send_close(&ConversationClose::Ack(close_id)) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.close_ack = None;
+		Ok((mdc, ciphertext))
+	}
+	
+	fn send_close(&mut self, close: &ConversationClose) -> Result<(String, Vec<u8>), DawnError> {
+		let own_seckey_sig = match &self.own_seckey_sig {
+			Some(res) => res,
+			None => error!("closing a conversation requires own signature keys")
+		};
+		let data = match gen_conversation_close(&self.id, close, own_seckey_sig.as_bytes()) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		self.send_content(&OutgoingMessage::internal(event::CONVERSATION_CLOSE, &data))
+	}
+	
+	// hand the receiving state to an archival client
+	pub fn passive(&self) -> PassiveSession {
+		PassiveSession::from_chain(self.own_seckey_kyber.clone(), Some(self.peer.pubkey_sig.clone()), self.recv_chain.clone(), self.pfs_salt.as_bytes()).config(self.config)
+	}
+	
+	// key for local client data of this conversation (see derive_client_key)
+	pub fn derive_client_key(&self, purpose: ClientKeyPurpose) -> Result<Vec<u8>, DawnError> {
+		client_key::derive_client_key(self.pfs_salt.as_bytes(), &self.id, purpose)
+	}
+	
+	pub(crate) fn state(&self) -> SessionState {
+		SessionState {
+			version: SESSION_STATE_VERSION,
+			id: self.id.clone(),
+			id_salt: encode(self.id_salt.as_bytes()),
+			msg_id: Some(self.msg_id.clone()),
+			pfs_salt: encode(self.pfs_salt.as_bytes()),
+			mdc_seed: self.mdc_seed.clone(),
+			own_seckey_kyber: encode(&self.own_seckey_kyber),
+			own_seckey_sig: self.own_seckey_sig.as_ref().map(encode),
+			peer_pubkey_kyber: encode(&self.peer.pubkey_kyber),
+			peer_pubkey_sig: encode(&self.peer.pubkey_sig),
+			peer_name: self.peer.name.clone(),
+			peer_verified: self.peer.verification == Verification::Verified,
+			peer_capabilities: self.peer.capabilities.clone(),
+			send_pfs_key: encode(self.send_chain.pfs_key()),
+			recv_pfs_key: encode(self.recv_chain.pfs_key()),
+			send_counter: self.send_chain.counter(),
+			recv_counter: self.recv_chain.counter(),
+			held: self.recv_chain.held_messages().into_iter().map(|(counter, ciphertext)| (counter, encode(ciphertext))).collect(),
+			recv_previous: self.recv_chain.previous().map(|(pfs_key, ciphertext_hash)| (encode(pfs_key), encode(ciphertext_hash))),
+			status: self.status.clone(),
+			close_ack: self.close_ack.clone(),
+			profile_policy: self.profile_policy,
+			petname: self.petname.clone(),
+			nickname: self.nickname.clone(),
+			nickname_sent: self.nickname_sent.clone(),
+			nickname_received: self.nickname_received.clone(),
+			disappearing: self.disappearing,
+			device: Some(self.device.clone()),
+			peer_devices: self.peer_devices.clone(),
+			bandwidth: self.bandwidth.clone(),
+		}
+	}
+	
+	pub(crate) fn from_state(state: SessionState) -> Result<Self, DawnError> {
+		if state.version != SESSION_STATE_VERSION { error!(&format!("session state version {} is not supported", state.version)); }
+		let (pfs_salt, send_pfs_key, recv_pfs_key) = match (decode(&state.pfs_salt), decode(&state.send_pfs_key), decode(&state.recv_pfs_key)) {
+			(Ok(pfs_salt), Ok(send_pfs_key), Ok(recv_pfs_key)) => (pfs_salt, send_pfs_key, recv_pfs_key),
+			_ => error!("session state contains an invalid pfs key or salt")
+		};
+		let id_salt = match decode(&state.id_salt) {
+			Ok(res) => res,
+			Err(_) => error!("session state contains an invalid id salt")
+		};
+		let mut held = Vec::with_capacity(state.held.len());
+		for (counter, ciphertext) in &state.held {
+			match decode(ciphertext) {
+				Ok(ciphertext) => held.push((*counter, ciphertext)),
+				Err(_) => error!("session state contains an invalid held message")
+			}
+		}
+		let recv_previous = match state.recv_previous.as_ref().map(|(pfs_key, ciphertext_hash)| (decode(pfs_key), decode(ciphertext_hash))) {
+			Some((Ok(pfs_key), Ok(ciphertext_hash))) => Some((pfs_key, ciphertext_hash)),
+			Some(_) => error!("session state contains an invalid pfs key or salt"),
+			None => None
+		};
+		let own_seckey_kyber = match decode_key::<KyberSecretKey>(&state.own_seckey_kyber) { Ok(res) => res, Err(err) => return Err(err) };
+		let own_seckey_sig = match state.own_seckey_sig.as_deref().map(decode_key::<SignSecretKey>) {
+			Some(Ok(res)) => Some(res),
+			Some(Err(err)) => return Err(err),
+			None => None
+		};
+		let pubkey_kyber = match decode_key::<KyberPublicKey>(&state.peer_pubkey_kyber) { Ok(res) => res, Err(err) => return Err(err) };
+		let pubkey_sig = match decode_key::<SignPublicKey>(&state.peer_pubkey_sig) { Ok(res) => res, Err(err) => return Err(err) };
+		let peer = Peer {
+			pubkey_kyber,
+			pubkey_sig,
+			name: state.peer_name,
+			verification: if state.peer_verified { Verification::Verified } else { Verification::Unverified },
+			capabilities: state.peer_capabilities,
+		};
+		let mut session = Session::new(&state.id, &id_salt, &pfs_salt, &state.mdc_seed, own_seckey_kyber, peer, &send_pfs_key, &recv_pfs_key);
+		if let Some(msg_id) = state.msg_id { session.msg_id = msg_id; }
+		session.send_chain = SendChain::restore(&send_pfs_key, state.send_counter);
+		session.recv_chain = ReceiveChain::restore(&recv_pfs_key, state.recv_counter, held, recv_previous);
+		session.own_seckey_sig = own_seckey_sig;
+		session.status = state.status;
+		session.close_ack = state.close_ack;
+		session.profile_policy = state.profile_policy;
+		session.petname = state.petname;
+		session.nickname = state.nickname;
+		session.nickname_sent = state.nickname_sent;
+		session.nickname_received = state.nickname_received;
+		session.disappearing = state.disappearing;
+		if let Some(device) = state.device { session.device = device; }
+		session.peer_devices = state.peer_devices;
+		session.bandwidth = state.bandwidth;
+		Ok(session)
+	}
+	
+	// encrypt the state of the session with a passphrase, so it can be stored and restored with import
+	// the blob contains the current pfs keys: an older export must not be imported after the session was used
+	pub fn export(&self, passphrase: &str) -> Result<Vec<u8>, DawnError> {
+		self.export_with_iterations(passphrase, EXPORT_ITERATIONS)
+	}
+	
+	pub(crate) fn export_with_iterations(&self, passphrase: &str, iterations: u32) -> Result<Vec<u8>, DawnError> {
+		let state = match serde_json::to_vec(&self.state()) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "json serialization failed")
+		};
+		let salt = sym_key_gen();
+		let ciphertext = match encrypt_data(&state, &export_key(passphrase, &salt, iterations)) {
+			Ok(res) => res,
+			Err(err) => { error!(Crypto, &format!("session state encryption failed: {}", err)); }
+		};
+		let mut blob = vec![SESSION_STATE_VERSION];
+		blob.extend(iterations.to_be_bytes());
+		blob.extend(salt);
+		blob.extend(ciphertext);
+		Ok(blob)
+	}
+	
+	// restore a session exported with export
+	pub fn import(blob: &[u8], passphrase: &str) -> Result<Self, DawnError> {
+		let (version, rest) = match split_bytes(blob, 1) {
+			Some(res) => res,
+			None => error!("exported session is empty")
+		};
+		if version != [SESSION_STATE_VERSION] { error!("exported session has an unsupported version"); }
+		let (iterations, rest) = match split_bytes(rest, 4).map(|(iterations, rest)| (<[u8; 4]>::try_from(iterations), rest)) {
+			Some((Ok(iterations), rest)) => (u32::from_be_bytes(iterations), rest),
+			_ => error!("exported session is truncated")
+		};
+		if iterations == 0 || iterations > MAX_EXPORT_ITERATIONS { error!("exported session has an invalid iteration count"); }
+		let (salt, ciphertext) = match split_bytes(rest, EXPORT_SALT_LEN) {
+			Some(res) => res,
+			None => error!("exported session is truncated")
+		};
+		let state = match decrypt_data(ciphertext, &export_key(passphrase, salt, iterations)) {
+			Ok(res) => res,
+			Err(err) => { error!(Crypto, &format!("session state decryption failed: {}", err)); }
+		};
+		let state = match serde_json::from_slice::<SessionState>(&state) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "session state json parsing failed")
+		};
+		Session::from_state(state)
+	}
+}
+
+// plain serialization for clients that encrypt their storage themselves
+// the serialized session contains all secrets of the conversation unencrypted
+#[cfg(feature = "session-serde")]
+impl Serialize for Session {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		self.state().serialize(serializer)
+	}
+}
+
+#[cfg(feature = "session-serde")]
+impl<'de> Deserialize<'de> for Session {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let state = match SessionState::deserialize(deserializer) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		Session::from_state(state).map_err(serde::de::Error::custom)
+	}
+}
@@ -0,0 +1,115 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Session persistence
+// Higher-level APIs load a Session before handling a message of a conversation and save it afterwards through the
+// SessionStore trait, so clients decide where the ratchet state lives (a database, a platform keychain) by implementing
+// it. MemorySessionStore keeps the sessions in memory for tests and short-lived clients, StorageSessionStore puts them
+// into any Storage backend. Both keep the state unencrypted: backends have to encrypt at rest themselves, or clients
+// use Session::export for the records instead. Only the state of a session is stored, limits and the clock are set
+// again after loading it.
+
+use std::collections::BTreeMap;
+use crate::session::{Session, SessionState};
+use crate::storage::{Storage, NAMESPACE_SESSIONS};
+use crate::DawnError;
+
+pub trait SessionStore {
+	// returns None if there is no session for the conversation
+	fn get(&self, id: &str) -> Result<Option<Session>, DawnError>;
+	// stores the session under its conversation id, replacing the previous state
+	fn put(&mut self, session: &Session) -> Result<(), DawnError>;
+	// deleting a session that doesn't exist is not an error
+	fn delete(&mut self, id: &str) -> Result<(), DawnError>;
+}
+
+#[derive(Clone, Default)]
+pub struct MemorySessionStore {
+	sessions: BTreeMap<String, SessionState>,
+}
+
+impl MemorySessionStore {
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+impl SessionStore for MemorySessionStore {
+	fn get(&self, id: &str) -> Result<Option<Session>, DawnError> {
+		match self.sessions.get(id) {
+			Some(state) => Session::from_state(state.clone()).map(Some),
+			None => Ok(None)
+		}
+	}
+	
+	fn put(&mut self, session: &Session) -> Result<(), DawnError> {
+		self.sessions.insert(session.id().to_string(), session.state());
+		Ok(())
+	}
+	
+	fn delete(&mut self, id: &str) -> Result<(), DawnError> {
+		self.sessions.remove(id);
+		Ok(())
+	}
+}
+
+// stores sessions as records of the sessions namespace of a storage backend
+pub struct StorageSessionStore<S: Storage> {
+	storage: S,
+}
+
+impl<S: Storage> StorageSessionStore<S> {
+	pub fn new(storage: S) -> Self {
+		StorageSessionStore { storage }
+	}
+	
+	pub fn into_inner(self) -> S {
+		self.storage
+	}
+	
+	// returns the ids of all stored sessions in ascending order
+	pub fn ids(&self) -> Result<Vec<String>, DawnError> {
+		self.storage.list(NAMESPACE_SESSIONS)
+	}
+}
+
+impl<S: Storage> SessionStore for StorageSessionStore<S> {
+	fn get(&self, id: &str) -> Result<Option<Session>, DawnError> {
+		let record = match self.storage.get(NAMESPACE_SESSIONS, id) {
+			Ok(Some(res)) => res,
+			Ok(None) => return Ok(None),
+			Err(err) => return Err(err)
+		};
+		match serde_json::from_slice::<SessionState>(&record) {
+			Ok(state) => Session::from_state(state).map(Some),
+			Err(_) => error!(Serialization, "session state json parsing failed")
+		}
+	}
+	
+	fn put(&mut self, session: &Session) -> Result<(), DawnError> {
+		let record = match serde_json::to_vec(&session.state()) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "json serialization failed")
+		};
+		self.storage.put(NAMESPACE_SESSIONS, session.id(), &record)
+	}
+	
+	fn delete(&mut self, id: &str) -> Result<(), DawnError> {
+		self.storage.delete(NAMESPACE_SESSIONS, id)
+	}
+}
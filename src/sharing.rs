@@ -0,0 +1,181 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Threshold secret sharing
+// Shamir's scheme over GF(256), applied to every byte of the secret: any threshold shares reconstruct the secret, fewer
+// shares reveal nothing about it. Share x coordinates run from 1 to the number of shares. The field arithmetic avoids
+// lookup tables and branches on secret data, so it doesn't leak the secret through the cache or the branch predictor.
+// Backup keys (see Identity::backup) can be split into recovery shares for trusted contacts or devices. Such a share is
+// a single line of text: "dawn-share-<version>-<threshold>-<index>-<check>-<data>". The data shares the backup key together
+// with a random check key, and check is a short hash of both that tells shares of different keys apart and detects a wrong
+// recovery. As the check key is only known after recovery, the check doesn't reveal anything about the backup key either.
+
+use dawn_crypto::{sym_key_gen, hash};
+use crate::codec::{encode, decode};
+use crate::DawnError;
+
+const BACKUP_SHARE_PREFIX: &str = "dawn-share";
+const BACKUP_SHARE_VERSION: &str = "1";
+const CHECK_KEY_LEN: usize = 32;
+
+// multiplication in GF(256) with the AES polynomial x^8 + x^4 + x^3 + x + 1
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+	let mut product = 0u8;
+	for _ in 0..8 {
+		product ^= a & 0u8.wrapping_sub(b & 1);
+		let carry = 0u8.wrapping_sub(a >> 7);
+		a = (a << 1) ^ (carry & 0x1b);
+		b >>= 1;
+	}
+	product
+}
+
+// inverse in GF(256) as a^254
+fn gf_inv(a: u8) -> u8 {
+	let mut result = 1u8;
+	let mut power = a;
+	let mut exponent = 254u8;
+	while exponent > 0 {
+		if exponent & 1 == 1 { result = gf_mul(result, power); }
+		power = gf_mul(power, power);
+		exponent >>= 1;
+	}
+	result
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+	let mut bytes = Vec::with_capacity(len + 32);
+	while bytes.len() < len {
+		bytes.extend(sym_key_gen());
+	}
+	bytes.truncate(len);
+	bytes
+}
+
+// split a secret into count shares of which threshold are needed to reconstruct it
+// returns (x coordinate, share data) for every share
+pub(crate) fn split_secret(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<(u8, Vec<u8>)>, DawnError> {
+	if secret.is_empty() { error!("secret is empty"); }
+	if threshold < 2 { error!("threshold must be at least 2"); }
+	if count < threshold { error!("share count must not be smaller than the threshold"); }
+	
+	// one random polynomial per byte of the secret, the secret byte being the constant term
+	let coefficients = random_bytes(secret.len() * (threshold as usize - 1));
+	let polynomials: Vec<&[u8]> = coefficients.chunks(threshold as usize - 1).collect();
+	let mut shares = Vec::with_capacity(count as usize);
+	for x in 1..=count {
+		let data = secret.iter().zip(&polynomials).map(|(secret_byte, polynomial)| {
+			// Horner's method, starting with the highest coefficient
+			let value = polynomial.iter().rev().fold(0u8, |value, coefficient| gf_mul(value, x) ^ coefficient);
+			gf_mul(value, x) ^ secret_byte
+		}).collect();
+		shares.push((x, data));
+	}
+	Ok(shares)
+}
+
+// reconstruct a secret from at least threshold shares (more shares don't hurt, but wrong ones result in a wrong secret)
+pub(crate) fn combine_shares(shares: &[(u8, &[u8])]) -> Result<Vec<u8>, DawnError> {
+	let len = match shares.first() {
+		Some((_, data)) => data.len(),
+		None => error!("no shares were provided")
+	};
+	for (index, (x, data)) in shares.iter().enumerate() {
+		if *x == 0 { error!("share has an invalid index"); }
+		if data.len() != len { error!("shares belong to different secrets"); }
+		if shares.iter().skip(index + 1).any(|(other_x, _)| other_x == x) { error!("share was provided twice"); }
+	}
+	
+	// lagrange interpolation at x = 0
+	let mut secret = vec![0u8; len];
+	for (x, data) in shares {
+		let mut numerator = 1u8;
+		let mut denominator = 1u8;
+		for (other_x, _) in shares {
+			if other_x == x { continue; }
+			numerator = gf_mul(numerator, *other_x);
+			denominator = gf_mul(denominator, other_x ^ x);
+		}
+		let basis = gf_mul(numerator, gf_inv(denominator));
+		for (secret_byte, share_byte) in secret.iter_mut().zip(data.iter()) {
+			*secret_byte ^= gf_mul(basis, *share_byte);
+		}
+	}
+	Ok(secret)
+}
+
+// short hash of a backup key keyed with the check key, contained in every share
+fn backup_key_check(backup_key: &[u8], check_key: &[u8]) -> String {
+	encode(hash(&[check_key, backup_key].concat()).get(..4).unwrap_or_default())
+}
+
+// split a backup key into count recovery shares of which threshold are needed to recover it
+pub fn split_backup_key(backup_key: &[u8], threshold: u8, count: u8) -> Result<Vec<String>, DawnError> {
+	if backup_key.is_empty() { error!("backup key is empty"); }
+	let check_key = random_bytes(CHECK_KEY_LEN);
+	let shares = match split_secret(&[backup_key, &check_key].concat(), threshold, count) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let check = backup_key_check(backup_key, &check_key);
+	Ok(shares.into_iter().map(|(index, data)| format!("{}-{}-{}-{}-{}-{}", BACKUP_SHARE_PREFIX, BACKUP_SHARE_VERSION, threshold, index, check, encode(data))).collect())
+}
+
+// parse a recovery share
+// returns threshold, index, check and share data
+fn parse_backup_share(share: &str) -> Result<(u8, u8, &str, Vec<u8>), DawnError> {
+	let fields = match share.trim().strip_prefix(BACKUP_SHARE_PREFIX).and_then(|rest| rest.strip_prefix('-')) {
+		Some(res) => res.split('-').collect::<Vec<&str>>(),
+		None => error!("recovery share invalid")
+	};
+	let (version, threshold, index, check, data) = match fields.as_slice() {
+		[version, threshold, index, check, data] => (*version, threshold.parse::<u8>(), index.parse::<u8>(), *check, decode(data)),
+		_ => error!("recovery share invalid")
+	};
+	if version != BACKUP_SHARE_VERSION { error!("recovery share version not supported"); }
+	match (threshold, index, data) {
+		(Ok(threshold), Ok(index), Ok(data)) if index != 0 && data.len() > CHECK_KEY_LEN => Ok((threshold, index, check, data)),
+		_ => error!("recovery share invalid")
+	}
+}
+
+// recover a backup key from at least threshold recovery shares
+pub fn recover_backup_key(shares: &[&str]) -> Result<Vec<u8>, DawnError> {
+	let mut parsed = Vec::with_capacity(shares.len());
+	for share in shares {
+		match parse_backup_share(share) {
+			Ok(res) => parsed.push(res),
+			Err(err) => return Err(err)
+		}
+	}
+	let (threshold, check) = match parsed.first() {
+		Some((threshold, _, check, _)) => (*threshold, *check),
+		None => error!("no recovery shares were provided")
+	};
+	if parsed.iter().any(|(other_threshold, _, other_check, _)| *other_threshold != threshold || *other_check != check) { error!("recovery shares belong to different backup keys"); }
+	if parsed.len() < threshold as usize { error!(&format!("{} recovery shares are needed", threshold)); }
+	
+	let points: Vec<(u8, &[u8])> = parsed.iter().map(|(_, index, _, data)| (*index, data.as_slice())).collect();
+	let mut backup_key = match combine_shares(&points) {
+		Ok(res) => res,
+		Err(err) => return Err(err)
+	};
+	let check_key = backup_key.split_off(backup_key.len().saturating_sub(CHECK_KEY_LEN));
+	if backup_key_check(&backup_key, &check_key) != check { error!("recovery failed, at least one share is damaged"); }
+	Ok(backup_key)
+}
@@ -0,0 +1,48 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Detached signatures
+// Every signature made by this library covers a domain label in front of the signed data, so a signature created for one
+// purpose (e.g. deleting a media file) can never be passed off as a signature for another one. The signatures are made
+// with the detached sign/verify API of dawn_crypto and use the same signature keys as encrypt_msg.
+
+use dawn_crypto::{sign, verify};
+use crate::DawnError;
+
+pub(crate) fn sign_detached(domain: &str, data: &[u8], own_seckey_sig: &[u8]) -> Result<Vec<u8>, DawnError> {
+	match sign(own_seckey_sig, &domain_separated(domain, data)) {
+		Ok(res) => Ok(res),
+		Err(err) => { error!(Crypto, &format!("signing failed: {}", err)); }
+	}
+}
+
+pub(crate) fn verify_detached(domain: &str, data: &[u8], signature: &[u8], remote_pubkey_sig: &[u8]) -> Result<(), DawnError> {
+	match verify(remote_pubkey_sig, &domain_separated(domain, data), signature) {
+		Ok(true) => Ok(()),
+		Ok(false) => error!(Crypto, "signature invalid"),
+		Err(err) => { error!(Crypto, &format!("signature verification failed: {}", err)); }
+	}
+}
+
+fn domain_separated(domain: &str, data: &[u8]) -> Vec<u8> {
+	let mut input = Vec::with_capacity(domain.len() + 1 + data.len());
+	input.extend_from_slice(domain.as_bytes());
+	input.push(0);
+	input.extend_from_slice(data);
+	input
+}
@@ -0,0 +1,147 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Conversation statistics
+// Aggregates a stream of messages (e.g. the history of a conversation or of the whole account) into statistics for
+// "year in review"-style features: messages per content type and direction, the hours of the day the conversation is
+// active in, the amount of text and the largest media. Everything is computed on the device, the report is serializable
+// so clients can cache it or render it elsewhere.
+
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use crate::content_type::{self, ContentType};
+use crate::history::HistoryEntry;
+use crate::DawnError;
+
+const DEFAULT_TOP_MEDIA: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MediaStat {
+	pub timestamp: u64,
+	pub content_type: ContentType,
+	pub size: usize,
+	pub sent: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatsReport {
+	pub messages: u64,
+	pub sent: u64,
+	pub received: u64,
+	pub per_content_type: BTreeMap<ContentType, u64>,
+	pub active_hours: Vec<u64>, // messages per hour of the day (24 entries, local time)
+	pub text_chars: u64,
+	pub media_bytes: u64,
+	pub top_media: Vec<MediaStat>, // largest media first
+	pub first_timestamp: Option<u64>,
+	pub last_timestamp: Option<u64>,
+}
+
+pub struct ConversationStats {
+	utc_offset: i64,
+	max_top_media: usize,
+	report: StatsReport,
+}
+
+impl Default for ConversationStats {
+	fn default() -> Self {
+		ConversationStats {
+			utc_offset: 0,
+			max_top_media: DEFAULT_TOP_MEDIA,
+			report: StatsReport {
+				active_hours: vec![0; 24],
+				..StatsReport::default()
+			},
+		}
+	}
+}
+
+impl ConversationStats {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// offset of the local time zone in seconds, used for the active hours
+	pub fn utc_offset(mut self, utc_offset: i64) -> Self {
+		self.utc_offset = utc_offset;
+		self
+	}
+	
+	// number of media files listed in the report
+	pub fn max_top_media(mut self, max_top_media: usize) -> Self {
+		self.max_top_media = max_top_media;
+		self
+	}
+	
+	// add a message (content as returned by parse_msg or passed to send_msg)
+	pub fn record(&mut self, timestamp: u64, sent: bool, (msg_type, msg_text, msg_data): (ContentType, Option<&str>, Option<&[u8]>)) {
+		let report = &mut self.report;
+		report.messages += 1;
+		if sent { report.sent += 1; } else { report.received += 1; }
+		*report.per_content_type.entry(msg_type).or_insert(0) += 1;
+		
+		let local_time = (timestamp as i64).saturating_add(self.utc_offset);
+		if let Some(hour) = report.active_hours.get_mut(local_time.rem_euclid(86400) as usize / 3600) {
+			*hour += 1;
+		}
+		report.first_timestamp = Some(report.first_timestamp.map_or(timestamp, |first| first.min(timestamp)));
+		report.last_timestamp = Some(report.last_timestamp.map_or(timestamp, |last| last.max(timestamp)));
+		
+		if msg_type == content_type::TEXT {
+			report.text_chars += msg_text.map_or(0, |text| text.chars().count() as u64);
+		}
+		if let (content_type::VOICE | content_type::PICTURE, Some(data)) = (msg_type, msg_data) {
+			report.media_bytes = report.media_bytes.saturating_add(data.len() as u64);
+			let media = MediaStat { timestamp, content_type: msg_type, size: data.len(), sent };
+			let position = report.top_media.partition_point(|larger| larger.size >= media.size);
+			if position < self.max_top_media {
+				report.top_media.insert(position, media);
+				report.top_media.truncate(self.max_top_media);
+			}
+		}
+	}
+	
+	// add a message of the history
+	pub fn record_entry(&mut self, entry: &HistoryEntry) {
+		self.record(entry.timestamp, entry.sent, (entry.content_type, entry.text.as_deref(), entry.data.as_deref()));
+	}
+	
+	pub fn report(&self) -> &StatsReport {
+		&self.report
+	}
+	
+	pub fn into_report(self) -> StatsReport {
+		self.report
+	}
+}
+
+impl StatsReport {
+	pub fn to_json(&self) -> Result<String, DawnError> {
+		match serde_json::to_string(self) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "json serialization failed")
+		}
+	}
+	
+	pub fn from_json(json: &str) -> Result<Self, DawnError> {
+		match serde_json::from_str::<StatsReport>(json) {
+			Ok(res) => Ok(res),
+			Err(_) => error!(Serialization, "statistics report json parsing failed")
+		}
+	}
+}
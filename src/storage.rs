@@ -0,0 +1,458 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Pluggable storage
+// Everything the library expects clients to persist (sessions, prekeys, peers and their verification state) goes through
+// the Storage trait, so every client stores it the same way and can pick the backend: MemoryStorage for tests and
+// short-lived clients, FileStorage (feature file-storage) with one file per record and SqliteStorage (feature sqlite)
+// with one table for all records. Records are grouped into namespaces and identified by a key, values are opaque bytes.
+// Operations that write several records (e.g. a session together with its peer) run them in a transaction, so a crash
+// in between can't leave half of them written: either all writes between begin and commit are stored or none of them.
+
+use std::collections::BTreeMap;
+use crate::DawnError;
+
+// namespaces of the records written by the library
+pub const NAMESPACE_SESSIONS: &str = "sessions";
+pub const NAMESPACE_PREKEYS: &str = "prekeys";
+pub const NAMESPACE_PEERS: &str = "peers";
+
+pub trait Storage {
+	fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, DawnError>;
+	fn put(&mut self, namespace: &str, key: &str, value: &[u8]) -> Result<(), DawnError>;
+	// deleting a record that doesn't exist is not an error
+	fn delete(&mut self, namespace: &str, key: &str) -> Result<(), DawnError>;
+	// returns the keys of all records in a namespace in ascending order
+	fn list(&self, namespace: &str) -> Result<Vec<String>, DawnError>;
+	// transactions can't be nested, reads inside a transaction see its own writes
+	fn begin(&mut self) -> Result<(), DawnError>;
+	fn commit(&mut self) -> Result<(), DawnError>;
+	fn rollback(&mut self) -> Result<(), DawnError>;
+}
+
+// run an operation in a transaction, committing it if the operation succeeds and rolling it back otherwise
+pub fn with_transaction<S: Storage + ?Sized, T, F: FnOnce(&mut S) -> Result<T, DawnError>>(storage: &mut S, operation: F) -> Result<T, DawnError> {
+	if let Err(err) = storage.begin() { return Err(err); }
+	match operation(storage) {
+		Ok(res) => match storage.commit() {
+			Ok(_) => Ok(res),
+			Err(err) => Err(err)
+		},
+		Err(err) => {
+			// the error of the operation is more useful than a failed rollback
+			let _ = storage.rollback();
+			Err(err)
+		}
+	}
+}
+
+fn check_namespace(namespace: &str) -> Result<(), DawnError> {
+	if namespace.is_empty() { error!("storage namespace must not be empty"); }
+	Ok(())
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+	records: BTreeMap<String, BTreeMap<String, Vec<u8>>>,
+	snapshot: Option<BTreeMap<String, BTreeMap<String, Vec<u8>>>>, // records before the active transaction
+}
+
+impl MemoryStorage {
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+impl Storage for MemoryStorage {
+	fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, DawnError> {
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		Ok(self.records.get(namespace).and_then(|records| records.get(key)).cloned())
+	}
+	
+	fn put(&mut self, namespace: &str, key: &str, value: &[u8]) -> Result<(), DawnError> {
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		self.records.entry(namespace.to_string()).or_default().insert(key.to_string(), value.to_vec());
+		Ok(())
+	}
+	
+	fn delete(&mut self, namespace: &str, key: &str) -> Result<(), DawnError> {
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		if let Some(records) = self.records.get_mut(namespace) {
+			records.remove(key);
+		}
+		Ok(())
+	}
+	
+	fn list(&self, namespace: &str) -> Result<Vec<String>, DawnError> {
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		Ok(self.records.get(namespace).map(|records| records.keys().cloned().collect()).unwrap_or_default())
+	}
+	
+	fn begin(&mut self) -> Result<(), DawnError> {
+		if self.snapshot.is_some() { error!("transaction already active"); }
+		self.snapshot = Some(self.records.clone());
+		Ok(())
+	}
+	
+	fn commit(&mut self) -> Result<(), DawnError> {
+		match self.snapshot.take() {
+			Some(_) => Ok(()),
+			None => error!("no active transaction")
+		}
+	}
+	
+	fn rollback(&mut self) -> Result<(), DawnError> {
+		match self.snapshot.take() {
+			Some(snapshot) => {
+				self.records = snapshot;
+				Ok(())
+			},
+			None => error!("no active transaction")
+		}
+	}
+}
+
+// one directory per namespace and one file per record below a root directory
+// namespaces and keys are hex-encoded in the file names, so they can contain any character
+// Writes of a transaction are kept in memory until the commit, which first writes them to a journal file and then applies
+// them. A journal left behind by a crash during a commit is applied again when the storage is opened.
+#[cfg(feature = "file-storage")]
+pub struct FileStorage {
+	root: std::path::PathBuf,
+	transaction: Option<PendingWrites>,
+}
+
+// pending writes of a transaction by namespace and key, None deletes the record
+#[cfg(feature = "file-storage")]
+type PendingWrites = BTreeMap<(String, String), Option<Vec<u8>>>;
+
+#[cfg(feature = "file-storage")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JournalEntry {
+	namespace: String,
+	key: String,
+	value: Option<String>, // base64, None deletes the record
+}
+
+#[cfg(feature = "file-storage")]
+const JOURNAL_FILE: &str = "journal";
+
+#[cfg(feature = "file-storage")]
+impl FileStorage {
+	// use a directory as storage, creating it if necessary
+	pub fn open<P: AsRef<std::path::Path>>(root: P) -> Result<Self, DawnError> {
+		let root = root.as_ref().to_path_buf();
+		if let Err(err) = std::fs::create_dir_all(&root) { error!(Storage, &format!("storage directory could not be created: {}", err)); }
+		let storage = FileStorage { root, transaction: None };
+		match storage.recover() {
+			Ok(_) => Ok(storage),
+			Err(err) => Err(err)
+		}
+	}
+	
+	fn namespace_dir(&self, namespace: &str) -> Result<std::path::PathBuf, DawnError> {
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		Ok(self.root.join(crate::codec::encode(namespace)))
+	}
+	
+	fn record_path(&self, namespace: &str, key: &str) -> Result<std::path::PathBuf, DawnError> {
+		match self.namespace_dir(namespace) {
+			// "k" prefix, so the empty key has a file name as well
+			Ok(dir) => Ok(dir.join(format!("k{}", crate::codec::encode(key)))),
+			Err(err) => Err(err)
+		}
+	}
+	
+	// write a temporary file first and rename it, so a crash never leaves a half-written file behind
+	fn write_file(path: &std::path::Path, value: &[u8]) -> Result<(), DawnError> {
+		if let Some(dir) = path.parent() {
+			if let Err(err) = std::fs::create_dir_all(dir) { error!(Storage, &format!("storage directory could not be created: {}", err)); }
+		}
+		let temp_path = path.with_extension("tmp");
+		if let Err(err) = std::fs::write(&temp_path, value) { error!(Storage, &format!("record could not be written: {}", err)); }
+		match std::fs::rename(&temp_path, path) {
+			Ok(_) => Ok(()),
+			Err(err) => error!(Storage, &format!("record could not be written: {}", err))
+		}
+	}
+	
+	fn write_record(&self, namespace: &str, key: &str, value: Option<&[u8]>) -> Result<(), DawnError> {
+		let path = match self.record_path(namespace, key) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		match value {
+			Some(value) => Self::write_file(&path, value),
+			None => match std::fs::remove_file(path) {
+				Ok(_) => Ok(()),
+				Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+				Err(err) => error!(Storage, &format!("record could not be deleted: {}", err))
+			}
+		}
+	}
+	
+	fn read_record(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, DawnError> {
+		let path = match self.record_path(namespace, key) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		match std::fs::read(path) {
+			Ok(value) => Ok(Some(value)),
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+			Err(err) => error!(Storage, &format!("record could not be read: {}", err))
+		}
+	}
+	
+	fn list_records(&self, namespace: &str) -> Result<Vec<String>, DawnError> {
+		let dir = match self.namespace_dir(namespace) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		let entries = match std::fs::read_dir(dir) {
+			Ok(res) => res,
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(err) => error!(Storage, &format!("storage directory could not be read: {}", err))
+		};
+		let mut keys = Vec::new();
+		for entry in entries {
+			let file_name = match entry {
+				Ok(entry) => entry.file_name(),
+				Err(err) => error!(Storage, &format!("storage directory could not be read: {}", err))
+			};
+			// skip temporary files and anything else not written by this storage
+			let key = match file_name.to_str().and_then(|file_name| file_name.strip_prefix('k')).map(crate::codec::decode) {
+				Some(Ok(key)) => key,
+				_ => continue
+			};
+			match String::from_utf8(key) {
+				Ok(key) => keys.push(key),
+				Err(_) => continue
+			}
+		}
+		keys.sort();
+		Ok(keys)
+	}
+	
+	// apply the journal of a commit that was interrupted
+	fn recover(&self) -> Result<(), DawnError> {
+		let journal_path = self.root.join(JOURNAL_FILE);
+		let journal = match std::fs::read(&journal_path) {
+			Ok(res) => res,
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+			Err(err) => error!(Storage, &format!("storage journal could not be read: {}", err))
+		};
+		let entries = match serde_json::from_slice::<Vec<JournalEntry>>(&journal) {
+			Ok(res) => res,
+			Err(_) => error!(Storage, "storage journal corrupted")
+		};
+		for entry in entries {
+			let value = match entry.value.map(crate::codec::decode_base64) {
+				Some(Ok(value)) => Some(value),
+				Some(Err(_)) => error!(Storage, "storage journal corrupted"),
+				None => None
+			};
+			if let Err(err) = self.write_record(&entry.namespace, &entry.key, value.as_deref()) { return Err(err); }
+		}
+		match std::fs::remove_file(journal_path) {
+			Ok(_) => Ok(()),
+			Err(err) => error!(Storage, &format!("storage journal could not be removed: {}", err))
+		}
+	}
+}
+
+#[cfg(feature = "file-storage")]
+impl Storage for FileStorage {
+	fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, DawnError> {
+		if let Some(pending) = self.transaction.as_ref().and_then(|pending| pending.get(&(namespace.to_string(), key.to_string()))) {
+			return Ok(pending.clone());
+		}
+		self.read_record(namespace, key)
+	}
+	
+	fn put(&mut self, namespace: &str, key: &str, value: &[u8]) -> Result<(), DawnError> {
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		match self.transaction.as_mut() {
+			Some(pending) => {
+				pending.insert((namespace.to_string(), key.to_string()), Some(value.to_vec()));
+				Ok(())
+			},
+			None => self.write_record(namespace, key, Some(value))
+		}
+	}
+	
+	fn delete(&mut self, namespace: &str, key: &str) -> Result<(), DawnError> {
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		match self.transaction.as_mut() {
+			Some(pending) => {
+				pending.insert((namespace.to_string(), key.to_string()), None);
+				Ok(())
+			},
+			None => self.write_record(namespace, key, None)
+		}
+	}
+	
+	fn list(&self, namespace: &str) -> Result<Vec<String>, DawnError> {
+		let mut keys = match self.list_records(namespace) {
+			Ok(res) => res,
+			Err(err) => return Err(err)
+		};
+		if let Some(pending) = &self.transaction {
+			for ((pending_namespace, key), value) in pending {
+				if pending_namespace != namespace { continue; }
+				match (value, keys.binary_search(key)) {
+					(Some(_), Err(position)) => keys.insert(position, key.clone()),
+					(None, Ok(position)) => { keys.remove(position); },
+					_ => ()
+				}
+			}
+		}
+		Ok(keys)
+	}
+	
+	fn begin(&mut self) -> Result<(), DawnError> {
+		if self.transaction.is_some() { error!("transaction already active"); }
+		self.transaction = Some(BTreeMap::new());
+		Ok(())
+	}
+	
+	fn commit(&mut self) -> Result<(), DawnError> {
+		let pending = match self.transaction.take() {
+			Some(res) => res,
+			None => error!("no active transaction")
+		};
+		let entries = pending.iter().map(|((namespace, key), value)| JournalEntry {
+			namespace: namespace.clone(),
+			key: key.clone(),
+			value: value.as_ref().map(crate::codec::encode_base64),
+		}).collect::<Vec<JournalEntry>>();
+		let journal = match serde_json::to_vec(&entries) {
+			Ok(res) => res,
+			Err(_) => error!(Serialization, "json serialization failed")
+		};
+		// once the journal is written the transaction is committed, even if applying it gets interrupted
+		if let Err(err) = Self::write_file(&self.root.join(JOURNAL_FILE), &journal) { return Err(err); }
+		self.recover()
+	}
+	
+	fn rollback(&mut self) -> Result<(), DawnError> {
+		match self.transaction.take() {
+			Some(_) => Ok(()),
+			None => error!("no active transaction")
+		}
+	}
+}
+
+// all records in one table of an SQLite database
+#[cfg(feature = "sqlite")]
+pub struct SqliteStorage {
+	connection: rusqlite::Connection,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStorage {
+	// open or create a database file
+	pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, DawnError> {
+		match rusqlite::Connection::open(path) {
+			Ok(connection) => Self::init(connection),
+			Err(err) => error!(Storage, &format!("database could not be opened: {}", err))
+		}
+	}
+	
+	// database that only lives in memory
+	pub fn open_in_memory() -> Result<Self, DawnError> {
+		match rusqlite::Connection::open_in_memory() {
+			Ok(connection) => Self::init(connection),
+			Err(err) => error!(Storage, &format!("database could not be opened: {}", err))
+		}
+	}
+	
+	fn init(connection: rusqlite::Connection) -> Result<Self, DawnError> {
+		match connection.execute("CREATE TABLE IF NOT EXISTS dawn_storage (namespace TEXT NOT NULL, key TEXT NOT NULL, value BLOB NOT NULL, PRIMARY KEY (namespace, key))", rusqlite::params![]) {
+			Ok(_) => Ok(SqliteStorage { connection }),
+			Err(err) => error!(Storage, &format!("database could not be initialized: {}", err))
+		}
+	}
+}
+
+#[cfg(feature = "sqlite")]
+impl Storage for SqliteStorage {
+	fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, DawnError> {
+		use rusqlite::OptionalExtension;
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		match self.connection.query_row("SELECT value FROM dawn_storage WHERE namespace = ?1 AND key = ?2", rusqlite::params![namespace, key], |row| row.get::<_, Vec<u8>>(0)).optional() {
+			Ok(res) => Ok(res),
+			Err(err) => error!(Storage, &format!("record could not be read: {}", err))
+		}
+	}
+	
+	fn put(&mut self, namespace: &str, key: &str, value: &[u8]) -> Result<(), DawnError> {
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		match self.connection.execute("INSERT OR REPLACE INTO dawn_storage (namespace, key, value) VALUES (?1, ?2, ?3)", rusqlite::params![namespace, key, value]) {
+			Ok(_) => Ok(()),
+			Err(err) => error!(Storage, &format!("record could not be written: {}", err))
+		}
+	}
+	
+	fn delete(&mut self, namespace: &str, key: &str) -> Result<(), DawnError> {
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		match self.connection.execute("DELETE FROM dawn_storage WHERE namespace = ?1 AND key = ?2", rusqlite::params![namespace, key]) {
+			Ok(_) => Ok(()),
+			Err(err) => error!(Storage, &format!("record could not be deleted: {}", err))
+		}
+	}
+	
+	fn list(&self, namespace: &str) -> Result<Vec<String>, DawnError> {
+		if let Err(err) = check_namespace(namespace) { return Err(err); }
+		let mut statement = match self.connection.prepare("SELECT key FROM dawn_storage WHERE namespace = ?1 ORDER BY key") {
+			Ok(res) => res,
+			Err(err) => error!(Storage, &format!("records could not be listed: {}", err))
+		};
+		let keys = match statement.query_map(rusqlite::params![namespace], |row| row.get::<_, String>(0)) {
+			Ok(rows) => rows.collect::<Result<Vec<String>, rusqlite::Error>>(),
+			Err(err) => error!(Storage, &format!("records could not be listed: {}", err))
+		};
+		match keys {
+			Ok(res) => Ok(res),
+			Err(err) => error!(Storage, &format!("records could not be listed: {}", err))
+		}
+	}
+	
+	fn begin(&mut self) -> Result<(), DawnError> {
+		if !self.connection.is_autocommit() { error!("transaction already active"); }
+		match self.connection.execute_batch("BEGIN IMMEDIATE") {
+			Ok(_) => Ok(()),
+			Err(err) => error!(Storage, &format!("transaction could not be started: {}", err))
+		}
+	}
+	
+	fn commit(&mut self) -> Result<(), DawnError> {
+		if self.connection.is_autocommit() { error!("no active transaction"); }
+		match self.connection.execute_batch("COMMIT") {
+			Ok(_) => Ok(()),
+			Err(err) => error!(Storage, &format!("transaction could not be committed: {}", err))
+		}
+	}
+	
+	fn rollback(&mut self) -> Result<(), DawnError> {
+		if self.connection.is_autocommit() { error!("no active transaction"); }
+		match self.connection.execute_batch("ROLLBACK") {
+			Ok(_) => Ok(()),
+			Err(err) => error!(Storage, &format!("transaction could not be rolled back: {}", err))
+		}
+	}
+}
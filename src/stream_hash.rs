@@ -0,0 +1,92 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Incremental hashing
+// Linked media files can be several gigabytes large, too large to buffer them before checking their hash. StreamHasher
+// computes the same hash as dawn_crypto::hash (SHA3-512) chunk by chunk, and HashVerifier checks a download against an
+// expected hash and size while it arrives, failing as soon as it gets larger than announced.
+
+use sha3::{Digest, Sha3_512};
+use crate::DawnError;
+
+#[derive(Clone, Default)]
+pub struct StreamHasher {
+	hasher: Sha3_512,
+	len: u64,
+}
+
+impl StreamHasher {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	pub fn update(&mut self, chunk: &[u8]) {
+		self.hasher.update(chunk);
+		self.len += chunk.len() as u64;
+	}
+	
+	// returns the number of bytes hashed so far
+	pub fn len(&self) -> u64 {
+		self.len
+	}
+	
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+	
+	// returns the hash of everything passed to update
+	pub fn finalize(self) -> Vec<u8> {
+		self.hasher.finalize().to_vec()
+	}
+}
+
+#[derive(Clone)]
+pub struct HashVerifier {
+	hasher: StreamHasher,
+	expected_hash: Vec<u8>,
+	expected_size: Option<u64>,
+}
+
+impl HashVerifier {
+	// verify data against a hash, optionally also against its size in bytes
+	pub fn new(expected_hash: &[u8], expected_size: Option<u64>) -> Self {
+		HashVerifier {
+			hasher: StreamHasher::new(),
+			expected_hash: expected_hash.to_vec(),
+			expected_size,
+		}
+	}
+	
+	// add the next chunk, fails once more data arrived than expected
+	pub fn update(&mut self, chunk: &[u8]) -> Result<(), DawnError> {
+		if let Some(expected_size) = self.expected_size {
+			if self.hasher.len() + chunk.len() as u64 > expected_size { error!("data is larger than expected"); }
+		}
+		self.hasher.update(chunk);
+		Ok(())
+	}
+	
+	// check the hash (and size) after the last chunk
+	pub fn finalize(self) -> Result<(), DawnError> {
+		if let Some(expected_size) = self.expected_size {
+			if self.hasher.len() != expected_size { error!("data is smaller than expected"); }
+		}
+		if self.hasher.finalize() != self.expected_hash { error!(Crypto, "hash mismatch"); }
+		Ok(())
+	}
+}
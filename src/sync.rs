@@ -0,0 +1,128 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Differential sync between the own devices
+// Every device counts its own changes (new messages, read receipts, setting changes) with a monotonic counter. When two
+// own devices reconnect, each sends a DELTA_SYNC message containing its changes since the counter the other device is
+// known to have and the counter of the other device's changes it has already applied itself ("known"). The receiving
+// device answers with its own changes since "known", so both converge without transferring the whole history again.
+
+use serde::{Serialize, Deserialize};
+use crate::history::{HistoryEntry, HistoryRecord};
+use crate::DawnError;
+
+// a setting that was changed on one of the own devices
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingChange {
+	pub key: String,
+	pub value: String,
+}
+
+// changes of one device within the counter range (since, until]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeltaSync {
+	pub known: u64, // counter of the receiving device's changes the sending device has already applied
+	pub since: u64,
+	pub until: u64,
+	pub entries: Vec<HistoryEntry>,
+	pub receipts: Vec<String>, // message detail codes of messages that were read on the sending device
+	pub settings: Vec<SettingChange>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SettingRecord {
+	key: String,
+	value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DeltaSyncRecord {
+	known: u64,
+	since: u64,
+	until: u64,
+	entries: Vec<HistoryRecord>,
+	receipts: Vec<String>,
+	settings: Vec<SettingRecord>,
+}
+
+// serialize a delta for sending it using send_msg with content_type::DELTA_SYNC
+pub fn gen_delta_sync(delta: &DeltaSync) -> Result<Vec<u8>, DawnError> {
+	if delta.until < delta.since { error!("delta sync range is invalid"); }
+	
+	let record = DeltaSyncRecord {
+		known: delta.known,
+		since: delta.since,
+		until: delta.until,
+		entries: delta.entries.iter().map(HistoryRecord::from_entry).collect(),
+		receipts: delta.receipts.clone(),
+		settings: delta.settings.iter().map(|setting| SettingRecord { key: setting.key.clone(), value: setting.value.clone() }).collect(),
+	};
+	match serde_json::to_vec(&record) {
+		Ok(res) => Ok(res),
+		Err(_) => error!(Serialization, "json serialization failed")
+	}
+}
+
+// parse the data returned by parse_msg for a DELTA_SYNC message
+pub fn parse_delta_sync(delta_data: &[u8]) -> Result<DeltaSync, DawnError> {
+	let record = match serde_json::from_slice::<DeltaSyncRecord>(delta_data) {
+		Ok(res) => res,
+		Err(_) => error!(Serialization, "delta sync json parsing failed")
+	};
+	if record.until < record.since { error!("delta sync range is invalid"); }
+	
+	let mut entries = Vec::with_capacity(record.entries.len());
+	for entry in record.entries {
+		match entry.into_entry() {
+			Ok(res) => entries.push(res),
+			Err(err) => return Err(err)
+		}
+	}
+	Ok(DeltaSync {
+		known: record.known,
+		since: record.since,
+		until: record.until,
+		entries,
+		receipts: record.receipts,
+		settings: record.settings.into_iter().map(|setting| SettingChange { key: setting.key, value: setting.value }).collect(),
+	})
+}
+
+impl DeltaSync {
+	// create an empty delta that only tells the other device which of its changes are already known
+	pub fn request(known: u64, own_counter: u64) -> Self {
+		DeltaSync {
+			known,
+			since: own_counter,
+			until: own_counter,
+			entries: vec![],
+			receipts: vec![],
+			settings: vec![],
+		}
+	}
+	
+	// check whether this delta directly follows the changes already applied locally (counter of the sending device's
+	// changes that were applied last). If not, some changes are missing and a new delta has to be requested.
+	pub fn follows(&self, applied: u64) -> bool {
+		self.since <= applied
+	}
+	
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty() && self.receipts.is_empty() && self.settings.is_empty()
+	}
+}
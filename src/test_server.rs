@@ -0,0 +1,97 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Simulated message queue of a Dawn server (only used by the tests)
+// Messages are stored in buckets named by the temp id they were sent to, together with their MDC. Clients poll a bucket or
+// retrieve messages by MDC and have to acknowledge every message they processed, everything else is delivered again on the
+// next poll. The delivery order within a bucket can be reversed to test how the protocol copes with reordering.
+
+use std::collections::HashMap;
+
+struct QueuedMessage {
+	mdc: String,
+	ciphertext: Vec<u8>,
+}
+
+#[derive(Default)]
+pub(crate) struct TestServer {
+	buckets: HashMap<String, Vec<QueuedMessage>>,
+	reorder: bool,
+}
+
+impl TestServer {
+	pub(crate) fn new() -> Self {
+		Self::default()
+	}
+	
+	// deliver the messages of every bucket in reverse order from now on
+	pub(crate) fn reorder(mut self) -> Self {
+		self.reorder = true;
+		self
+	}
+	
+	// store a message in the bucket of a temp id
+	pub(crate) fn send(&mut self, temp_id: &str, mdc: &str, ciphertext: &[u8]) {
+		self.buckets.entry(temp_id.to_string()).or_default().push(QueuedMessage {
+			mdc: mdc.to_string(),
+			ciphertext: ciphertext.to_vec(),
+		});
+	}
+	
+	// returns all messages of a bucket that have not been acknowledged yet as (MDC, ciphertext)
+	pub(crate) fn poll(&self, temp_id: &str) -> Vec<(String, Vec<u8>)> {
+		let mut messages: Vec<(String, Vec<u8>)> = match self.buckets.get(temp_id) {
+			Some(bucket) => bucket.iter().map(|msg| (msg.mdc.clone(), msg.ciphertext.clone())).collect(),
+			None => Vec::new()
+		};
+		if self.reorder { messages.reverse(); }
+		messages
+	}
+	
+	// returns all messages with the given MDC that have not been acknowledged yet, regardless of their bucket
+	pub(crate) fn get_by_mdc(&self, mdc: &str) -> Vec<Vec<u8>> {
+		let mut messages = Vec::new();
+		for bucket in self.buckets.values() {
+			for msg in bucket.iter().filter(|msg| msg.mdc == mdc) {
+				messages.push(msg.ciphertext.clone());
+			}
+		}
+		messages
+	}
+	
+	// remove a processed message from the queue
+	// returns false if there was no such message
+	pub(crate) fn ack(&mut self, temp_id: &str, ciphertext: &[u8]) -> bool {
+		let bucket = match self.buckets.get_mut(temp_id) {
+			Some(res) => res,
+			None => return false
+		};
+		match bucket.iter().position(|msg| msg.ciphertext == ciphertext) {
+			Some(index) => {
+				bucket.remove(index);
+				true
+			},
+			None => false
+		}
+	}
+	
+	// returns the number of messages that are still queued
+	pub(crate) fn pending(&self) -> usize {
+		self.buckets.values().map(|bucket| bucket.len()).sum()
+	}
+}
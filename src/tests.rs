@@ -36,26 +36,28 @@ fn test_init_and_messaging() {
 	
 	// Alice sends an init request to Bob
 	let mdc = mdc_gen();
-	let ((alice_pk_kyber, alice_sk_kyber), (alice_pk_curve, alice_sk_curve), alice_new_pfs_key, recv_bob_pfs_key, pfs_salt, id, id_salt, _, mdc_seed, init_request_ciphertext) = gen_init_request(&bob_init_pk_kyber, &bob_init_pk_kyber_for_salt, &bob_init_pk_curve, &bob_init_pk_curve_pfs_2, &bob_init_pk_curve_for_salt, &alice_pk_sig, &alice_sk_sig, name, comment, &mdc).unwrap();
+	let InitRequestResult { own_pubkey_kyber: alice_pk_kyber, own_seckey_kyber: alice_sk_kyber, own_pubkey_curve: alice_pk_curve, own_seckey_curve: alice_sk_curve, own_pfs_key: alice_new_pfs_key, remote_pfs_key: recv_bob_pfs_key, pfs_salt, id, id_salt, mdc_seed, ciphertext: init_request_ciphertext, .. } = gen_init_request(&bob_init_pk_kyber, &bob_init_pk_kyber_for_salt, &bob_init_pk_curve, &bob_init_pk_curve_pfs_2, &bob_init_pk_curve_for_salt, &alice_pk_sig, &alice_sk_sig, name, comment, &mdc).unwrap();
 	
 	// Bob's client parses the init request
-	let (recv_id, recv_id_salt, recv_mdc, recv_alice_pk_kyber, recv_alice_pk_sig, bob_pfs_key, recv_alice_new_pfs_key, recv_pfs_salt, recv_name, recv_comment, recv_mdc_seed) = parse_init_request(&init_request_ciphertext, &bob_init_sk_kyber, &bob_init_sk_curve, &bob_init_sk_curve_pfs_2, &bob_init_sk_kyber_for_salt, &bob_init_sk_curve_for_salt).unwrap();
+	let ParsedInitRequest { id: recv_id, id_salt: recv_id_salt, mdc: recv_mdc, peer: recv_alice, own_pfs_key: bob_pfs_key, remote_pfs_key: recv_alice_new_pfs_key, pfs_salt: recv_pfs_salt, comment: recv_comment, mdc_seed: recv_mdc_seed } = parse_init_request(&init_request_ciphertext, &bob_init_sk_kyber, &bob_init_sk_curve, &bob_init_sk_curve_pfs_2, &bob_init_sk_kyber_for_salt, &bob_init_sk_curve_for_salt).unwrap();
 	
 	// check the received init request
 	assert_eq!(recv_id, id);
 	assert_eq!(recv_id_salt, id_salt);
 	assert_eq!(recv_mdc, mdc);
-	assert_eq!(recv_alice_pk_kyber, alice_pk_kyber);
-	assert_eq!(recv_alice_pk_sig, alice_pk_sig);
+	assert_eq!(recv_alice.pubkey_kyber.to_vec(), alice_pk_kyber);
+	assert_eq!(recv_alice.pubkey_sig.to_vec(), alice_pk_sig);
+	assert_eq!(recv_alice.verification, Verification::Unverified);
+	assert!(recv_alice.supports(capability::LINKED_MEDIA));
 	assert_eq!(recv_alice_new_pfs_key, alice_new_pfs_key);
 	assert_eq!(recv_bob_pfs_key, bob_pfs_key);
 	assert_eq!(recv_pfs_salt, pfs_salt);
-	assert_eq!(recv_name, name);
+	assert_eq!(recv_alice.name, name);
 	assert_eq!(recv_comment, comment);
 	assert_eq!(recv_mdc_seed, mdc_seed);
 	
 	// Bob accepts the init request
-	let (bob_new_pfs_key_2, (bob_pk_kyber, bob_sk_kyber), mdc_2, init_accept_ciphertext) = accept_init_request(&bob_pk_sig, &bob_sk_sig, &recv_alice_pk_kyber, &bob_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (bob_new_pfs_key_2, (bob_pk_kyber, bob_sk_kyber), mdc_2, init_accept_ciphertext) = accept_init_request(&bob_pk_sig, &bob_sk_sig, recv_alice.pubkey_kyber.as_bytes(), &bob_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
 	
 	// Check security number derivation
 	let security_number = derive_security_number(&alice_pk_kyber, &bob_pk_kyber).unwrap();
@@ -63,11 +65,13 @@ fn test_init_and_messaging() {
 	println!("Security number: {}", security_number);
 	
 	// Alice happily receives the accept message
-	let (recv_bob_pk_kyber, recv_bob_pk_sig, recv_bob_new_pfs_key_2, mdc_3) = parse_init_response(&init_accept_ciphertext, &alice_sk_kyber, None, &recv_bob_pfs_key, &pfs_salt).unwrap();
+	let ParsedInitResponse { peer: recv_bob, new_pfs_key: recv_bob_new_pfs_key_2, mdc: mdc_3, .. } = parse_init_response(&init_accept_ciphertext, &alice_sk_kyber, None, &recv_bob_pfs_key, &pfs_salt, "bob").unwrap();
 	
 	// check the received values
-	assert_eq!(recv_bob_pk_kyber, bob_pk_kyber);
-	assert_eq!(recv_bob_pk_sig, bob_pk_sig);
+	assert_eq!(recv_bob.pubkey_kyber.to_vec(), bob_pk_kyber);
+	assert_eq!(recv_bob.pubkey_sig.to_vec(), bob_pk_sig);
+	assert_eq!(recv_bob.name, "bob");
+	assert_eq!(recv_bob.security_number(&alice_pk_kyber).unwrap(), security_number);
 	assert_eq!(bob_new_pfs_key_2, recv_bob_new_pfs_key_2);
 	assert_eq!(mdc_2, mdc_3);
 	
@@ -76,7 +80,8 @@ fn test_init_and_messaging() {
 	let (bob_new_pfs_key_3, mdc_4, bob_msg_ciphertext_1) = send_msg((content_type::TEXT, Some("Hi Alice"), None), &alice_pk_kyber, Some(&bob_sk_sig), &bob_new_pfs_key_2, &pfs_salt, &id, &mdc_seed).unwrap();
 	
 	// Alice receives it
-	let ((recv_content_type, recv_text, recv_bytes), recv_bob_new_pfs_key_3, mdc_5) = parse_msg(&bob_msg_ciphertext_1, &alice_sk_kyber, Some(&bob_pk_sig), &recv_bob_new_pfs_key_2, &pfs_salt).unwrap();
+	let (received, recv_bob_new_pfs_key_3, mdc_5, _) = parse_msg(&bob_msg_ciphertext_1, &alice_sk_kyber, Some(&bob_pk_sig), &recv_bob_new_pfs_key_2, &pfs_salt).unwrap();
+	let (recv_content_type, recv_text, recv_bytes) = received.into_content();
 	
 	// check what was received
 	assert_eq!(recv_content_type, content_type::TEXT);
@@ -90,8 +95,10 @@ fn test_init_and_messaging() {
 	let (alice_new_pfs_key_3, mdc_7, alice_msg_ciphertext_2) = send_msg((content_type::TEXT, Some("How are you?"), None), &bob_pk_kyber, Some(&alice_sk_sig), &alice_new_pfs_key_2, &pfs_salt, &id, &mdc_seed).unwrap();
 	
 	// Bob receives both messages
-	let ((recv_content_type_1, recv_text_1, recv_bytes_1), recv_alice_new_pfs_key_2, mdc_8) = parse_msg(&alice_msg_ciphertext_1, &bob_sk_kyber, Some(&alice_pk_sig), &recv_alice_new_pfs_key, &pfs_salt).unwrap();
-	let ((recv_content_type_2, recv_text_2, recv_bytes_2), recv_alice_new_pfs_key_3, mdc_9) = parse_msg(&alice_msg_ciphertext_2, &bob_sk_kyber, Some(&alice_pk_sig), &recv_alice_new_pfs_key_2, &pfs_salt).unwrap();
+	let (received, recv_alice_new_pfs_key_2, mdc_8, _) = parse_msg(&alice_msg_ciphertext_1, &bob_sk_kyber, Some(&alice_pk_sig), &recv_alice_new_pfs_key, &pfs_salt).unwrap();
+	let (recv_content_type_1, recv_text_1, recv_bytes_1) = received.into_content();
+	let (received, recv_alice_new_pfs_key_3, mdc_9, _) = parse_msg(&alice_msg_ciphertext_2, &bob_sk_kyber, Some(&alice_pk_sig), &recv_alice_new_pfs_key_2, &pfs_salt).unwrap();
+	let (recv_content_type_2, recv_text_2, recv_bytes_2) = received.into_content();
 	
 	// check what was received
 	assert!(recv_content_type_1 == recv_content_type_2 && recv_content_type_1 == content_type::TEXT);
@@ -109,7 +116,8 @@ fn test_init_and_messaging() {
 	let (bob_new_pfs_key_4, mdc_10, bob_msg_ciphertext_2) = send_msg((content_type::TEXT, Some("I'm very happy because the test just passed!"), None), &alice_pk_kyber, Some(&bob_sk_sig), &bob_new_pfs_key_3, &pfs_salt, &id, &mdc_seed).unwrap();
 	
 	// Alice receives it
-	let ((recv_content_type, recv_text, recv_bytes), recv_bob_new_pfs_key_4, mdc_11) = parse_msg(&bob_msg_ciphertext_2, &alice_sk_kyber, Some(&bob_pk_sig), &recv_bob_new_pfs_key_3, &pfs_salt).unwrap();
+	let (received, recv_bob_new_pfs_key_4, mdc_11, _) = parse_msg(&bob_msg_ciphertext_2, &alice_sk_kyber, Some(&bob_pk_sig), &recv_bob_new_pfs_key_3, &pfs_salt).unwrap();
+	let (recv_content_type, recv_text, recv_bytes) = received.into_content();
 	
 	// check what was received
 	assert_eq!(recv_content_type, content_type::TEXT);
@@ -119,10 +127,11 @@ fn test_init_and_messaging() {
 	assert_eq!(mdc_10, mdc_11);
 	
 	// Alice sends a voice message
-	let (alice_new_pfs_key_3, mdc_12, alice_msg_ciphertext_3) = send_msg((content_type::VOICE, None, Some(&vec![1,3,5,7,9,42])), &bob_pk_kyber, Some(&alice_sk_sig), &alice_new_pfs_key_2, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (alice_new_pfs_key_3, mdc_12, alice_msg_ciphertext_3) = send_msg((content_type::VOICE, None, Some(&[1,3,5,7,9,42])), &bob_pk_kyber, Some(&alice_sk_sig), &alice_new_pfs_key_2, &pfs_salt, &id, &mdc_seed).unwrap();
 	
 	// Bob receives it
-	let ((recv_content_type, recv_text, recv_bytes), recv_alice_new_pfs_key_3, mdc_13) = parse_msg(&alice_msg_ciphertext_3, &bob_sk_kyber, Some(&alice_pk_sig), &recv_alice_new_pfs_key_2, &pfs_salt).unwrap();
+	let (received, recv_alice_new_pfs_key_3, mdc_13, _) = parse_msg(&alice_msg_ciphertext_3, &bob_sk_kyber, Some(&alice_pk_sig), &recv_alice_new_pfs_key_2, &pfs_salt).unwrap();
+	let (recv_content_type, recv_text, recv_bytes) = received.into_content();
 	
 	assert_eq!(recv_content_type, content_type::VOICE);
 	assert!(recv_text.is_none());
@@ -132,10 +141,11 @@ fn test_init_and_messaging() {
 	assert_ne!(alice_new_pfs_key_2, alice_new_pfs_key_3);
 	
 	// Bob sends a picture
-	let (bob_new_pfs_key_5, mdc_14, bob_msg_ciphertext_3) = send_msg((content_type::PICTURE, Some("Here is a photo for you!"), Some(&vec![42,42,42,42,7,6,5,4,3,2,1])), &alice_pk_kyber, Some(&bob_sk_sig), &bob_new_pfs_key_4, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (bob_new_pfs_key_5, mdc_14, bob_msg_ciphertext_3) = send_msg((content_type::PICTURE, Some("Here is a photo for you!"), Some(&[42,42,42,42,7,6,5,4,3,2,1])), &alice_pk_kyber, Some(&bob_sk_sig), &bob_new_pfs_key_4, &pfs_salt, &id, &mdc_seed).unwrap();
 	
 	// Alice receives it
-	let ((recv_content_type, recv_text, recv_bytes), recv_bob_new_pfs_key_5, mdc_15) = parse_msg(&bob_msg_ciphertext_3, &alice_sk_kyber, Some(&bob_pk_sig), &recv_bob_new_pfs_key_4, &pfs_salt).unwrap();
+	let (received, recv_bob_new_pfs_key_5, mdc_15, _) = parse_msg(&bob_msg_ciphertext_3, &alice_sk_kyber, Some(&bob_pk_sig), &recv_bob_new_pfs_key_4, &pfs_salt).unwrap();
+	let (recv_content_type, recv_text, recv_bytes) = received.into_content();
 	
 	assert_eq!(recv_content_type, content_type::PICTURE);
 	assert_eq!(recv_text, Some("Here is a photo for you!".to_string()));
@@ -149,10 +159,11 @@ fn test_init_and_messaging() {
 	let key = "42424242";
 	let comment = "This is a test file!\nThe comment can use multiple lines just like a normal message!\nPretty neat, right? :)";
 	let msg_string = link.to_string() + "\n" + key + "\n" + comment;
-	let (alice_new_pfs_key_4, mdc_16, alice_msg_ciphertext_4) = send_msg((content_type::LINKED_MEDIA, Some(&msg_string), Some(&vec![42])), &bob_pk_kyber, Some(&alice_sk_sig), &alice_new_pfs_key_3, &pfs_salt, &id, &mdc).unwrap();
+	let (alice_new_pfs_key_4, mdc_16, alice_msg_ciphertext_4) = send_msg((content_type::LINKED_MEDIA, Some(&msg_string), Some(&[42])), &bob_pk_kyber, Some(&alice_sk_sig), &alice_new_pfs_key_3, &pfs_salt, &id, &mdc).unwrap();
 	
 	// Bob receives it
-	let ((recv_content_type, recv_text, recv_bytes), recv_alice_new_pfs_key_4, mdc_17) = parse_msg(&alice_msg_ciphertext_4, &bob_sk_kyber, Some(&alice_pk_sig), &recv_alice_new_pfs_key_3, &pfs_salt).unwrap();
+	let (received, recv_alice_new_pfs_key_4, mdc_17, _) = parse_msg(&alice_msg_ciphertext_4, &bob_sk_kyber, Some(&alice_pk_sig), &recv_alice_new_pfs_key_3, &pfs_salt).unwrap();
+	let (recv_content_type, recv_text, recv_bytes) = received.into_content();
 	
 	assert_eq!(recv_content_type, content_type::LINKED_MEDIA);
 	assert_eq!(recv_text, Some(link.to_string() + "\n" + key + "\n" + comment));
@@ -184,7 +195,7 @@ fn test_handle_parsing() {
 
 #[test]
 fn test_gen_init_request() {
-	assert!(gen_init_request(&vec![], &vec![], &vec![], &vec![], &vec![], &vec![], &vec![], "", "", "").is_err());
+	assert!(gen_init_request(&[], &[], &[], &[], &[], &[], &[], "", "", "").is_err());
 	let name = "alice";
 	let comment = "\nhi\n\\{}[]{{}\"";
 	let mdc = mdc_gen();
@@ -193,3 +204,3609 @@ fn test_gen_init_request() {
 	let (alice_pk_sig, alice_sk_sig) = sign_keygen();
 	assert!(gen_init_request(&bob_init_pk_kyber, &bob_init_pk_kyber, &bob_init_pk_curve, &bob_init_pk_curve, &bob_init_pk_curve, &alice_pk_sig, &alice_sk_sig, "", comment, &mdc).is_err());
 }
+
+#[test]
+fn test_history_transfer() {
+	// the own new device is treated like any other conversation partner
+	let (new_device_pk_kyber, new_device_sk_kyber) = kyber_keygen();
+	let (old_device_pk_sig, old_device_sk_sig) = sign_keygen();
+	let pfs_salt = sym_key_gen();
+	let id = id_gen();
+	let mdc_seed = mdc_gen();
+	
+	let entries = vec![
+		HistoryEntry { id: id_gen(), sent: true, timestamp: 1672531200, content_type: content_type::TEXT, text: Some("Hi Bob".to_string()), data: None },
+		HistoryEntry { id: id_gen(), sent: false, timestamp: 1672531260, content_type: content_type::VOICE, text: None, data: Some(vec![1,3,5,7,9,42]) },
+		HistoryEntry { id: id_gen(), sent: false, timestamp: 1672531320, content_type: content_type::PICTURE, text: Some("multi\nline".to_string()), data: Some(vec![42; 300]) },
+	];
+	assert!(gen_history_chunks(&entries, 0).is_err());
+	let chunks = gen_history_chunks(&entries, 64).unwrap();
+	assert!(chunks.len() > 1);
+	
+	let mut sender_pfs_key = sym_key_gen();
+	let mut receiver_pfs_key = sender_pfs_key.clone();
+	let mut reassembler = HistoryReassembler::new();
+	let mut result = None;
+	for (header, chunk) in &chunks {
+		let (new_pfs_key, _, ciphertext) = send_msg((content_type::HISTORY_SYNC, Some(header), Some(chunk)), &new_device_pk_kyber, Some(&old_device_sk_sig), &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		sender_pfs_key = new_pfs_key;
+		let (received, new_pfs_key, _, _) = parse_msg(&ciphertext, &new_device_sk_kyber, Some(&old_device_pk_sig), &receiver_pfs_key, &pfs_salt).unwrap();
+		let (recv_content_type, recv_text, recv_bytes) = received.into_content();
+		receiver_pfs_key = new_pfs_key;
+		assert_eq!(recv_content_type, content_type::HISTORY_SYNC);
+		assert!(result.is_none());
+		result = reassembler.add_chunk(&recv_text.unwrap(), &recv_bytes.unwrap()).unwrap();
+	}
+	assert_eq!(result, Some(entries));
+	assert_eq!(reassembler.pending(), 0);
+	
+	// incomplete transfers are limited in number and size
+	let mut reassembler = HistoryReassembler::new().max_pending(2).max_bytes(100);
+	reassembler.add_chunk("first\n0\n2", &[42; 60]).unwrap();
+	reassembler.add_chunk("second\n0\n2", &[42; 10]).unwrap();
+	assert!(reassembler.add_chunk("third\n0\n2", &[42; 10]).is_err());
+	assert!(reassembler.add_chunk("first\n1\n2", &[42; 60]).is_err());
+	assert_eq!(reassembler.pending(), 1);
+	assert!(reassembler.add_chunk("third\n0\n2", &[42; 10]).unwrap().is_none());
+	assert_eq!(reassembler.pending(), 2);
+	
+	// malformed chunk headers are rejected
+	assert!(reassembler.add_chunk("transfer\n3\n3", &[]).is_err());
+	assert!(reassembler.add_chunk("transfer\nx\n3", &[]).is_err());
+}
+
+#[test]
+fn test_delta_sync() {
+	let (device_pk_kyber, device_sk_kyber) = kyber_keygen();
+	let pfs_key = sym_key_gen();
+	let pfs_salt = sym_key_gen();
+	let id = id_gen();
+	let mdc_seed = mdc_gen();
+	
+	let delta = DeltaSync {
+		known: 7,
+		since: 40,
+		until: 43,
+		entries: vec![HistoryEntry { id: id_gen(), sent: true, timestamp: 1672531200, content_type: content_type::TEXT, text: Some("sent while the laptop was offline".to_string()), data: None }],
+		receipts: vec![mdc_gen(), mdc_gen()],
+		settings: vec![SettingChange { key: "theme".to_string(), value: "dark".to_string() }],
+	};
+	let delta_data = gen_delta_sync(&delta).unwrap();
+	let (_, _, ciphertext) = send_msg((content_type::DELTA_SYNC, None, Some(&delta_data)), &device_pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &device_sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	let (recv_content_type, recv_text, recv_bytes) = received.into_content();
+	assert_eq!(recv_content_type, content_type::DELTA_SYNC);
+	assert!(recv_text.is_none());
+	let recv_delta = parse_delta_sync(&recv_bytes.unwrap()).unwrap();
+	assert_eq!(recv_delta, delta);
+	assert!(recv_delta.follows(40) && recv_delta.follows(41) && !recv_delta.follows(39));
+	
+	let request = DeltaSync::request(43, 12);
+	assert!(request.is_empty());
+	assert!(gen_delta_sync(&DeltaSync { since: 5, until: 4, ..request }).is_err());
+	assert!(send_msg((content_type::DELTA_SYNC, None, Some(b"{}")), &device_pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).is_err());
+}
+
+#[test]
+fn test_parse_limits() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let limits = ParseLimits { max_ciphertext_len: 4096, max_text_len: 40, max_data_len: 30, max_decompressed_len: 70, mode: ParseMode::Lenient };
+	
+	let (_, _, short_text) = send_msg((content_type::TEXT, Some("short"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (_, _, long_text) = send_msg((content_type::TEXT, Some("this text is longer than the forty bytes that are allowed"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (_, _, small_voice) = send_msg((content_type::VOICE, None, Some(&[42; 30])), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (_, _, large_voice) = send_msg((content_type::VOICE, None, Some(&[42; 36])), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (_, _, huge_voice) = send_msg((content_type::VOICE, None, Some(&[42; 8192])), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	
+	assert!(parse_msg_limited(&short_text, &sk_kyber, None, &pfs_key, &pfs_salt, &limits).is_ok());
+	assert!(parse_msg_limited(&long_text, &sk_kyber, None, &pfs_key, &pfs_salt, &limits).is_err());
+	assert!(parse_msg_limited(&small_voice, &sk_kyber, None, &pfs_key, &pfs_salt, &limits).is_ok());
+	assert!(parse_msg_limited(&large_voice, &sk_kyber, None, &pfs_key, &pfs_salt, &limits).is_err());
+	assert!(parse_msg_limited(&huge_voice, &sk_kyber, None, &pfs_key, &pfs_salt, &limits).is_err());
+	assert!(parse_msg(&huge_voice, &sk_kyber, None, &pfs_key, &pfs_salt).is_ok());
+}
+
+#[test]
+fn test_context() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_salt, id, mdc_seed, .. } = gen_test_conversation();
+	let mut sender_pfs_key = sym_key_gen();
+	let mut receiver_pfs_key = sender_pfs_key.clone();
+	let mut sender_context = Context::new();
+	let mut receiver_context = Context::with_capacity(1024);
+	let data_buffer = receiver_context.data_buffer.as_ptr();
+	
+	for size in [500, 3, 0, 2000] {
+		let voice = vec![42u8; size];
+		let (new_pfs_key, mdc, ciphertext) = send_msg_with_context(&mut sender_context, (content_type::VOICE, None, Some(&voice)), &pk_kyber, None, &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		sender_pfs_key = new_pfs_key;
+		let ((recv_content_type, recv_text, recv_bytes), new_pfs_key, recv_mdc, _) = parse_msg_with_context(&mut receiver_context, &ciphertext, &sk_kyber, None, &receiver_pfs_key, &pfs_salt, &ParseLimits::default()).unwrap();
+		receiver_pfs_key = new_pfs_key;
+		assert_eq!(recv_content_type, content_type::VOICE);
+		assert!(recv_text.is_none());
+		assert_eq!(recv_bytes, Some(&voice[..]));
+		assert_eq!(recv_mdc, mdc);
+		// the data buffer keeps its allocation as long as the content fits
+		if size <= 1024 {
+			assert_eq!(receiver_context.data_buffer.as_ptr(), data_buffer);
+			assert_eq!(receiver_context.data_buffer.capacity(), 1024);
+		}
+	}
+	assert!(receiver_context.data_buffer.capacity() >= 2000);
+	assert_eq!(sender_pfs_key, receiver_pfs_key);
+	
+	let (_, _, ciphertext) = send_msg_with_context(&mut sender_context, (content_type::TEXT, Some("no binary content"), None), &pk_kyber, None, &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let ((_, recv_text, recv_bytes), _, _, _) = parse_msg_with_context(&mut receiver_context, &ciphertext, &sk_kyber, None, &receiver_pfs_key, &pfs_salt, &ParseLimits::default()).unwrap();
+	assert_eq!(recv_text, Some("no binary content".to_string()));
+	assert!(recv_bytes.is_none());
+}
+
+#[test]
+fn test_typed_keys() {
+	let (pk_kyber, sk_kyber) = gen_kyber_keypair().unwrap();
+	let (pk_curve, sk_curve) = gen_curve_keypair().unwrap();
+	let (pk_sig, sk_sig) = gen_sign_keypair().unwrap();
+	assert_eq!(pk_kyber.as_bytes().len(), KyberPublicKey::LEN);
+	assert_eq!(sk_kyber.as_bytes().len(), KyberSecretKey::LEN);
+	assert_eq!(KyberPublicKey::from_bytes(pk_kyber.as_bytes()).unwrap(), pk_kyber);
+	assert_eq!(SignPublicKey::try_from(pk_sig.to_vec()).unwrap(), pk_sig);
+	
+	// a curve key is no kyber key
+	assert!(KyberPublicKey::from_bytes(pk_curve.as_bytes()).is_err());
+	assert!(CurvePublicKey::try_from(vec![0u8; 31]).is_err());
+	assert!(SignSecretKey::from_bytes(&[]).is_err());
+	
+	// secret keys don't end up in logs
+	assert!(!format!("{:?}", sk_curve).contains(&encode(sk_curve.as_bytes())));
+	assert!(!format!("{:?}", sk_sig).contains(&encode(sk_sig.as_bytes())));
+	assert!(format!("{:?}", pk_curve).contains(&encode(pk_curve.as_bytes())));
+	
+	// secret keys are copied into their own (locked) memory
+	assert!(KyberSecretKey::from_bytes(&[42; 32]).is_err());
+	let sk_kyber_copy = sk_kyber.clone();
+	assert_eq!(sk_kyber_copy, sk_kyber);
+	assert_ne!(sk_kyber_copy.as_bytes().as_ptr(), sk_kyber.as_bytes().as_ptr());
+	assert_ne!(gen_kyber_keypair().unwrap().1, sk_kyber);
+	assert!(crate::secret::SecretBytes::new(&[1, 2]) != crate::secret::SecretBytes::new(&[1, 2, 3]));
+	assert!(crate::secret::SecretBytes::new(&[1, 2, 3]) != crate::secret::SecretBytes::new(&[1, 2, 4]));
+	assert!(cfg!(feature = "mlock") || !sk_kyber.is_locked());
+}
+
+#[test]
+fn test_init_request_builder() {
+	let (bob_init_pk_curve, bob_init_sk_curve) = curve_keygen();
+	let (bob_init_pk_curve_pfs_2, bob_init_sk_curve_pfs_2) = curve_keygen();
+	let (bob_init_pk_kyber, bob_init_sk_kyber) = kyber_keygen();
+	let (bob_init_pk_curve_for_salt, bob_init_sk_curve_for_salt) = curve_keygen();
+	let (bob_init_pk_kyber_for_salt, bob_init_sk_kyber_for_salt) = kyber_keygen();
+	let (alice_pk_sig, alice_sk_sig) = gen_sign_keypair().unwrap();
+	let bob_mdc = mdc_gen();
+	let handle = gen_handle(&bob_init_pk_kyber, &bob_init_pk_curve, &bob_init_pk_curve_pfs_2, &bob_init_pk_kyber_for_salt, &bob_init_pk_curve_for_salt, "bob", &bob_mdc);
+	
+	// nothing is sent without the own identity
+	let builder = InitRequestBuilder::new().handle(handle).unwrap();
+	assert!(builder.build().is_err());
+	
+	let builder = builder.own_signature_keys(alice_pk_sig.clone(), alice_sk_sig).name("alice").comment("hi bob");
+	let InitRequestResult { pfs_salt, id, mdc, ciphertext, .. } = builder.build().unwrap();
+	assert_eq!(mdc, bob_mdc);
+	
+	let ParsedInitRequest { id: recv_id, mdc: recv_mdc, peer: recv_alice, pfs_salt: recv_pfs_salt, comment: recv_comment, .. } = parse_init_request(&ciphertext, &bob_init_sk_kyber, &bob_init_sk_curve, &bob_init_sk_curve_pfs_2, &bob_init_sk_kyber_for_salt, &bob_init_sk_curve_for_salt).unwrap();
+	assert_eq!(recv_id, id);
+	assert_eq!(recv_mdc, bob_mdc);
+	assert_eq!(recv_alice.pubkey_sig, alice_pk_sig);
+	assert_eq!(recv_pfs_salt, pfs_salt);
+	assert_eq!(recv_alice.name, "alice");
+	assert_eq!(recv_comment, "hi bob");
+	
+	// handles with keys of the wrong size are rejected
+	let broken_handle = gen_handle(&bob_init_pk_curve, &bob_init_pk_curve, &bob_init_pk_curve_pfs_2, &bob_init_pk_kyber_for_salt, &bob_init_pk_curve_for_salt, "bob", &bob_mdc);
+	assert!(InitRequestBuilder::new().handle(broken_handle).is_err());
+}
+
+#[test]
+fn test_identity() {
+	let alice = Identity::generate().unwrap();
+	let bob = Identity::generate().unwrap();
+	
+	// backups can only be restored with the right key
+	let backup_key = sym_key_gen();
+	let backup = bob.backup(&backup_key).unwrap();
+	assert_eq!(Identity::from_backup(&backup, &backup_key).unwrap(), bob);
+	assert!(Identity::from_backup(&backup, &sym_key_gen()).is_err());
+	
+	// handshake using identities
+	let bob_mdc = mdc_gen();
+	let handle = bob.gen_handle("bob", &bob_mdc);
+	let InitRequestResult { own_pfs_key: alice_pfs_key, remote_pfs_key: bob_pfs_key, pfs_salt, id, mdc_seed, ciphertext: request, .. } = alice.init_request_builder().handle(handle).unwrap().name("alice").build().unwrap();
+	let ParsedInitRequest { id: recv_id, peer: recv_alice, own_pfs_key: recv_bob_pfs_key, remote_pfs_key: recv_alice_pfs_key, mdc_seed: recv_mdc_seed, .. } = bob.parse_init_request(&request).unwrap();
+	assert_eq!(recv_id, id);
+	assert_eq!(recv_alice.pubkey_sig, alice.pubkey_sig);
+	assert_eq!(recv_bob_pfs_key, bob_pfs_key);
+	assert_eq!(recv_alice_pfs_key, alice_pfs_key);
+	assert_eq!(recv_alice.name, "alice");
+	assert!(bob.accept_init_request(recv_alice.pubkey_kyber.as_bytes(), &recv_bob_pfs_key, &pfs_salt, &recv_id, &recv_mdc_seed).is_ok());
+	
+	// old handles stop working after rotating the init keys
+	let mut rotated_bob = bob.clone();
+	rotated_bob.rotate_init_keys().unwrap();
+	assert_eq!(rotated_bob.pubkey_sig, bob.pubkey_sig);
+	assert!(rotated_bob.parse_init_request(&request).is_err());
+}
+
+#[test]
+fn test_media_key_wrapping() {
+	let (ciphertext, media_key) = encrypt_file(&[42; 1000]).unwrap();
+	let pfs_salt = sym_key_gen();
+	let id = id_gen();
+	let link = "https://contentserver.dawn-privacy.org/f/42";
+	
+	let wrapped_key = wrap_media_key(&media_key, &pfs_salt, &id, link).unwrap();
+	assert_ne!(wrapped_key, encode(&media_key));
+	let unwrapped_key = unwrap_media_key(&wrapped_key, &pfs_salt, &id, link).unwrap();
+	assert_eq!(decrypt_file(&ciphertext, &unwrapped_key).unwrap(), vec![42; 1000]);
+	
+	// the wrapped key is bound to the conversation and the message
+	assert!(unwrap_media_key(&wrapped_key, &sym_key_gen(), &id, link).is_err());
+	assert!(unwrap_media_key(&wrapped_key, &pfs_salt, &id_gen(), link).is_err());
+	assert!(unwrap_media_key(&wrapped_key, &pfs_salt, &id, "https://contentserver.dawn-privacy.org/f/43").is_err());
+	assert!(wrap_media_key(&media_key, &pfs_salt, &id, "").is_err());
+}
+
+#[test]
+fn test_linked_media_expiry_and_deletion() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let (pk_sig, sk_sig) = sign_keygen();
+	let link = "https://contentserver.dawn-privacy.org/f/42";
+	
+	let delete_token = gen_media_delete_token(link, &sk_sig).unwrap();
+	assert!(verify_media_delete_token(link, &delete_token, &pk_sig).is_ok());
+	assert!(verify_media_delete_token("https://contentserver.dawn-privacy.org/f/43", &delete_token, &pk_sig).is_err());
+	assert!(verify_media_delete_token(link, &delete_token, &sign_keygen().0).is_err());
+	assert!(verify_media_delete_token(link, &delete_token[..delete_token.len() - 1], &pk_sig).is_err());
+	assert!(verify_media_delete_token(link, &gen_media_delete_token(link, &sign_keygen().1).unwrap(), &pk_sig).is_err());
+	
+	let data = gen_linked_media_data(42, Some(1700000000), Some(&delete_token));
+	let text = link.to_string() + "\n" + "42424242" + "\n" + "description";
+	let (_, _, ciphertext) = send_msg((content_type::LINKED_MEDIA, Some(&text), Some(&data)), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	let (_, recv_text, recv_bytes) = received.into_content();
+	assert_eq!(recv_text, Some(text));
+	assert_eq!(parse_linked_media_data(&recv_bytes.unwrap()).unwrap(), (42, Some(1700000000), Some(delete_token)));
+	
+	assert_eq!(gen_linked_media_data(7, None, None), vec![7]);
+	assert_eq!(parse_linked_media_data(&gen_linked_media_data(7, None, Some(&[1, 2]))).unwrap(), (7, None, Some(vec![1, 2])));
+	assert!(parse_linked_media_data(&[]).is_err());
+	assert!(parse_linked_media_data(&[7, 0, 0]).is_err());
+}
+
+#[test]
+fn test_upload_ticket() {
+	let (pk_sig, sk_sig) = sign_keygen();
+	let (other_pk_sig, _) = sign_keygen();
+	let (encrypted_file, _) = encrypt_file(&[42; 1000]).unwrap();
+	
+	let ticket = gen_upload_ticket(&encrypted_file, 1700000000, &sk_sig).unwrap();
+	let parsed_ticket = parse_upload_ticket(&ticket, &pk_sig).unwrap();
+	assert_eq!(parsed_ticket.size, encrypted_file.len() as u64);
+	assert_eq!(parsed_ticket.expires_at, 1700000000);
+	assert!(parsed_ticket.matches(&encrypted_file));
+	assert!(!parsed_ticket.matches(&encrypted_file[1..]));
+	
+	// tickets can't be issued for someone else or modified
+	assert!(parse_upload_ticket(&ticket, &other_pk_sig).is_err());
+	let modified_ticket = ticket.replace("1700000000", "1800000000");
+	assert!(parse_upload_ticket(&modified_ticket, &pk_sig).is_err());
+}
+
+#[test]
+fn test_server_queue_delivery() {
+	let (bob_pk_kyber, bob_sk_kyber) = kyber_keygen();
+	let (alice_pk_sig, alice_sk_sig) = sign_keygen();
+	let pfs_salt = sym_key_gen();
+	let id = id_gen();
+	let mdc_seed = mdc_gen();
+	let mut server = test_server::TestServer::new().reorder();
+	
+	// Alice sends three messages spread over two hours, so they end up in two temp id buckets
+	let start = 1672531200;
+	let temp_ids = vec![get_custom_temp_id(&id, start), get_custom_temp_id(&id, start + 3600)];
+	let mut alice_pfs_key = sym_key_gen();
+	let mut bob_pfs_key = alice_pfs_key.clone();
+	let texts = ["first", "second", "third"];
+	for (index, text) in texts.iter().enumerate() {
+		let (new_pfs_key, mdc, ciphertext) = send_msg((content_type::TEXT, Some(text), None), &bob_pk_kyber, Some(&alice_sk_sig), &alice_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		server.send(&temp_ids[index / 2], &mdc, &ciphertext);
+		alice_pfs_key = new_pfs_key;
+	}
+	
+	// the MDC is predictable, so Bob can look the messages up without polling
+	assert_eq!(server.get_by_mdc(&predictable_mdc_gen(&mdc_seed, &id)).len(), 3);
+	assert!(server.get_by_mdc(&mdc_gen()).is_empty());
+	
+	// Bob polls all buckets and only acknowledges what he could process, the rest is delivered again on the next poll
+	let mut received = Vec::new();
+	let mut polls = 0;
+	while server.pending() > 0 {
+		polls += 1;
+		assert!(polls <= texts.len(), "messages were not redelivered");
+		for temp_id in &temp_ids {
+			for (mdc, ciphertext) in server.poll(temp_id) {
+				if let Ok((message, new_pfs_key, recv_mdc, _)) = parse_msg(&ciphertext, &bob_sk_kyber, Some(&alice_pk_sig), &bob_pfs_key, &pfs_salt) {
+					let ReceivedMessage::Text { text, .. } = message else { panic!("expected a text message") };
+					assert_eq!(recv_mdc, mdc);
+					assert!(server.ack(temp_id, &ciphertext));
+					received.push(text);
+					bob_pfs_key = new_pfs_key;
+				}
+			}
+		}
+	}
+	assert_eq!(received, texts);
+	assert_eq!(bob_pfs_key, alice_pfs_key);
+	
+	// acknowledged messages are gone
+	assert!(!server.ack(&temp_ids[0], &[42]));
+	assert!(server.poll(&temp_ids[1]).is_empty());
+}
+
+#[test]
+fn test_namespaced_ids() {
+	let group_id = gen_namespaced_id(Namespace::Group);
+	assert!(group_id.starts_with(Namespace::Group.prefix()));
+	assert_eq!(parse_namespaced_id(&group_id).unwrap(), Namespace::Group);
+	assert_eq!(parse_namespaced_id(&gen_namespaced_id(Namespace::OwnDevices)).unwrap(), Namespace::OwnDevices);
+	
+	// the namespace survives id rotation and shows up in temp ids
+	let salt = sym_key_gen();
+	let next_id = get_next_namespaced_id(&group_id, &salt).unwrap();
+	assert_eq!(next_id, get_next_namespaced_id(&group_id, &salt).unwrap());
+	assert_ne!(next_id, group_id);
+	assert_eq!(parse_namespaced_id(&next_id).unwrap(), Namespace::Group);
+	let temp_id = get_namespaced_temp_id(&group_id, 1672531200).unwrap();
+	assert_eq!(parse_namespaced_id(&temp_id).unwrap(), Namespace::Group);
+	
+	// ids without (valid) prefix are rejected
+	assert!(parse_namespaced_id(&id_gen()).is_err());
+	assert!(parse_namespaced_id("x-42").is_err());
+	assert!(parse_namespaced_id("d-").is_err());
+	assert!(parse_namespaced_id("d-not hex").is_err());
+	assert!(get_next_namespaced_id(&id_gen(), &salt).is_err());
+}
+
+#[test]
+fn test_retention() {
+	let retention = Retention::days(7);
+	assert_eq!(retention.drop_after, 604800);
+	assert_eq!(Retention::from_header(&retention.to_header()).unwrap(), retention);
+	assert!(Retention::from_header("retention=0").is_err());
+	assert!(Retention::from_header("retention=soon").is_err());
+	assert!(Retention::from_header("604800").is_err());
+	
+	let sent_at = 1672531200;
+	let mdc = mdc_gen();
+	let notice = parse_expiry_notice(&gen_expiry_notice(&mdc, retention.expires_at(sent_at))).unwrap();
+	assert!(notice.applies_to(&mdc, sent_at, &retention));
+	assert!(!notice.applies_to(&mdc_gen(), sent_at, &retention));
+	
+	// the server must not drop messages before the requested retention ran out
+	let premature_notice = parse_expiry_notice(&gen_expiry_notice(&mdc, sent_at + 3600)).unwrap();
+	assert!(!premature_notice.applies_to(&mdc, sent_at, &retention));
+	assert!(parse_expiry_notice(b"{}").is_err());
+}
+
+#[test]
+fn test_init_fanout() {
+	let alice = Identity::generate().unwrap();
+	let bob_card = Identity::generate().unwrap();
+	let bob_online = Identity::generate().unwrap();
+	let builders = vec![
+		alice.init_request_builder().handle(bob_card.gen_handle("bob", &mdc_gen())).unwrap().name("alice"),
+		alice.init_request_builder().handle(bob_online.gen_handle("bob", &mdc_gen())).unwrap().name("alice"),
+	];
+	assert!(InitFanout::new(Vec::new()).is_err());
+	let mut fanout = InitFanout::new(builders).unwrap();
+	let requests = fanout.requests();
+	assert_eq!(requests.len(), 2);
+	
+	// both requests share the conversation id, so Bob can tell they are the same
+	let ParsedInitRequest { id: card_id, peer: card_alice, own_pfs_key: card_pfs_key, pfs_salt: card_pfs_salt, mdc_seed: card_mdc_seed, .. } = bob_card.parse_init_request(requests[0].1).unwrap();
+	let ParsedInitRequest { id: online_id, peer: online_alice, own_pfs_key: online_pfs_key, pfs_salt: online_pfs_salt, mdc_seed: online_mdc_seed, .. } = bob_online.parse_init_request(requests[1].1).unwrap();
+	assert_eq!(card_id, fanout.id());
+	assert_eq!(online_id, card_id);
+	
+	// Bob accepts both (e.g. from two clients), the one for the online handle arrives first
+	let (_, (bob_pk_kyber, _), online_mdc, online_accept) = bob_online.accept_init_request(online_alice.pubkey_kyber.as_bytes(), &online_pfs_key, &online_pfs_salt, &online_id, &online_mdc_seed).unwrap();
+	let (_, _, _, card_accept) = bob_card.accept_init_request(card_alice.pubkey_kyber.as_bytes(), &card_pfs_key, &card_pfs_salt, &card_id, &card_mdc_seed).unwrap();
+	
+	assert!(!fanout.is_accepted());
+	let accepted = fanout.reconcile(&online_accept, Some(bob_online.pubkey_sig.as_bytes()), "bob").unwrap().unwrap();
+	assert_eq!(accepted.index, 1);
+	assert_eq!(accepted.id, online_id);
+	assert_eq!(accepted.mdc, online_mdc);
+	assert_eq!(accepted.pfs_salt, online_pfs_salt);
+	assert_eq!(accepted.peer.pubkey_kyber.to_vec(), bob_pk_kyber);
+	assert!(fanout.is_accepted());
+	
+	// the late accept is ignored, unrelated messages are rejected
+	assert!(fanout.reconcile(&card_accept, Some(bob_card.pubkey_sig.as_bytes()), "bob").unwrap().is_none());
+	assert!(fanout.reconcile(&[42; 2000], None, "bob").is_err());
+}
+
+#[test]
+fn test_warnings() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let (pk_sig, sk_sig) = sign_keygen();
+	
+	let (_, _, signed) = send_msg((content_type::TEXT, Some("signed"), None), &pk_kyber, Some(&sk_sig), &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (_, _, unsigned) = send_msg((content_type::TEXT, Some("unsigned"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	
+	let (_, _, _, warning) = parse_msg(&signed, &sk_kyber, Some(&pk_sig), &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(warning, Warning::None);
+	
+	// unsigned messages are only accepted if no signature was requested, and then come with a warning
+	assert!(parse_msg(&unsigned, &sk_kyber, Some(&pk_sig), &pfs_key, &pfs_salt).is_err());
+	let (_, _, _, warning) = parse_msg(&unsigned, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(warning, Warning::Unsigned);
+	
+	assert!(check_warning(Warning::None, Some(&pk_sig)).is_ok());
+	assert!(check_warning(Warning::Unsigned, None).is_ok());
+	assert!(check_warning(Warning::Unsigned, Some(&pk_sig)).is_err());
+}
+
+#[test]
+fn test_malformed_input() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let (init_pk_curve, init_sk_curve) = curve_keygen();
+	let (init_pk_kyber, init_sk_kyber) = kyber_keygen();
+	
+	// content that does not fit the content type
+	let send = |content| send_msg(content, &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed);
+	assert!(send((content_type::TEXT, None, None)).is_err());
+	assert!(send((content_type::INTERNAL, None, Some(&[1]))).is_err());
+	assert!(send((content_type::INTERNAL, Some("256"), Some(&[1]))).is_err());
+	assert!(send((content_type::INTERNAL, Some("1"), None)).is_err());
+	assert!(send((content_type::VOICE, Some("text"), None)).is_err());
+	assert!(send((content_type::PICTURE, Some("text"), None)).is_err());
+	assert!(send((content_type::LINKED_MEDIA, None, Some(&[42]))).is_err());
+	assert!(send((content_type::LINKED_MEDIA, Some(""), Some(&[42]))).is_err());
+	assert!(send((content_type::LINKED_MEDIA, Some("link"), Some(&[42]))).is_err());
+	assert!(send((content_type::LINKED_MEDIA, Some("link\nkey"), None)).is_err());
+	assert!(send((content_type::LINKED_MEDIA, Some("link\nkey"), Some(&[]))).is_err());
+	assert!(send((content_type::LINKED_MEDIA, Some("link\nkey"), Some(&[42, 0, 0]))).is_err());
+	assert!(send((content_type::HISTORY_SYNC, Some("transfer\n1"), Some(&[42]))).is_err());
+	assert!(send((content_type::HISTORY_SYNC, Some("transfer\n0\n1"), None)).is_err());
+	assert!(send((content_type::DELTA_SYNC, None, None)).is_err());
+	assert!(send((content_type::DELTA_SYNC, None, Some(b"not a delta"))).is_err());
+	
+	// garbage instead of messages
+	for garbage in [&[][..], &[0], &[42; 31], &[42; 1600], &[255; 5000]] {
+		assert!(parse_msg(garbage, &sk_kyber, None, &pfs_key, &pfs_salt).is_err());
+		assert!(parse_init_response(garbage, &sk_kyber, None, &pfs_key, &pfs_salt, "bob").is_err());
+		assert!(parse_init_request(garbage, &init_sk_kyber, &init_sk_curve, &init_sk_curve, &init_sk_kyber, &init_sk_curve).is_err());
+		assert!(parse_handle(garbage.to_vec()).is_err());
+		assert!(parse_delta_sync(garbage).is_err());
+		assert!(decrypt_file(garbage, &pfs_key).is_err());
+	}
+	assert!(parse_handle(gen_handle(&init_pk_kyber, &init_pk_curve, &init_pk_curve, &init_pk_kyber, &init_pk_curve, "bob", "")[..100].to_vec()).is_err());
+	assert!(parse_handle("zz\n\n\n\n\n\n".as_bytes().to_vec()).is_err());
+	assert!(parse_linked_media_data(&[]).is_err());
+	assert!(parse_linked_media_data(&[42, 1, 2, 3]).is_err());
+	assert!(parse_namespaced_id("").is_err());
+	assert!(parse_namespaced_id("dä").is_err());
+	assert!(parse_namespaced_id("ä-42").is_err());
+	assert!(HistoryReassembler::new().add_chunk("transfer\n7\n3", &[42]).is_err());
+	assert!(unwrap_media_key("", &pfs_salt, &id, "").is_err());
+}
+
+#[test]
+fn test_estimate_ciphertext_len() {
+	let TestConversation { pk_kyber, pfs_key, pfs_salt, id, mdc_seed, .. } = gen_test_conversation();
+	let (_, sk_sig) = sign_keygen();
+	
+	type Content<'a> = (ContentType, Option<&'a str>, Option<&'a [u8]>);
+	let contents: Vec<Content> = vec![
+		(content_type::TEXT, Some("Hi Bob"), None),
+		(content_type::TEXT, Some("multi\nline \"text\" with ümlauts"), None),
+		(content_type::VOICE, None, Some(&[42; 1000])),
+		(content_type::PICTURE, Some("a picture"), Some(&[7; 4097])),
+	];
+	for content in contents {
+		let (_, _, unsigned) = send_msg(content, &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		let (_, _, signed) = send_msg(content, &pk_kyber, Some(&sk_sig), &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		assert_eq!(estimate_ciphertext_len(content, false).unwrap(), unsigned.len());
+		assert_eq!(estimate_ciphertext_len(content, true).unwrap(), signed.len());
+	}
+	assert!(estimate_ciphertext_len((content_type::VOICE, None, None), false).is_err());
+}
+
+#[test]
+fn test_bandwidth_counters() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let mut counters = BandwidthCounters::new();
+	
+	let (_, _, text) = send_msg((content_type::TEXT, Some("Hi Bob"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (_, _, voice) = send_msg((content_type::VOICE, None, Some(&[42; 1000])), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	counters.record_sent(content_type::TEXT, text.len());
+	counters.record_sent(content_type::VOICE, voice.len());
+	let (received, _, _, _) = parse_msg(&voice, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	let (recv_content_type, _, _) = received.into_content();
+	counters.record_received(recv_content_type, voice.len());
+	
+	assert_eq!(counters.sent(content_type::TEXT), text.len() as u64);
+	assert_eq!(counters.received(content_type::VOICE), voice.len() as u64);
+	assert_eq!(counters.received(content_type::TEXT), 0);
+	assert_eq!(counters.total_sent(), (text.len() + voice.len()) as u64);
+	assert_eq!(counters.total(), (text.len() + voice.len() * 2) as u64);
+	
+	// the counters are stored along with the conversation
+	let restored = BandwidthCounters::from_bytes(&counters.to_bytes().unwrap()).unwrap();
+	assert_eq!(restored, counters);
+	assert!(BandwidthCounters::from_bytes(b"[]").is_err());
+	counters.reset();
+	assert_eq!(counters.total(), 0);
+}
+
+#[test]
+fn test_data_saver() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let small_picture = vec![42; 100];
+	let large_picture = vec![42; 10000];
+	let send = |policy: &DataSaverPolicy, picture: &[u8]| send_file(policy, (content_type::PICTURE, Some("a picture"), picture), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	
+	// small pictures are sent inline, large ones have to be uploaded
+	let policy = DataSaverPolicy::new().max_inline_size(5000);
+	let ciphertext = match send(&policy, &small_picture) {
+		MediaSend::Inline((_, _, ciphertext)) => ciphertext,
+		MediaSend::Upload(_) => panic!("small picture was not sent inline")
+	};
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	let (recv_content_type, _, recv_bytes) = received.into_content();
+	assert_eq!(recv_content_type, content_type::PICTURE);
+	assert_eq!(recv_bytes, Some(small_picture.clone()));
+	match send(&policy, &large_picture) {
+		MediaSend::Upload((encrypted_file, key)) => assert_eq!(decrypt_file(&encrypted_file, &key).unwrap(), large_picture),
+		MediaSend::Inline(_) => panic!("large picture was sent inline")
+	}
+	
+	// the downscaling hook gets a chance to make large pictures fit
+	let policy = DataSaverPolicy::new().max_inline_size(5000).downscale_image(|picture| Ok(picture[..picture.len() / 10].to_vec()));
+	assert!(matches!(send(&policy, &large_picture), MediaSend::Inline(_)));
+	
+	// on metered connections everything is linked
+	let policy = policy.metered(true);
+	assert!(matches!(send(&policy, &small_picture), MediaSend::Upload(_)));
+	assert!(matches!(send(&policy.prefer_linked_on_metered(false), &small_picture), MediaSend::Inline(_)));
+	
+	assert!(send_file(&DataSaverPolicy::new(), (content_type::TEXT, Some("text"), &[42]), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).is_err());
+}
+
+#[test]
+fn test_presence_aggregation() {
+	for update in [PresenceUpdate::ReadUpTo(0), PresenceUpdate::ReadUpTo(127), PresenceUpdate::ReadUpTo(300), PresenceUpdate::ReadUpTo(u64::MAX), PresenceUpdate::Typing(true), PresenceUpdate::Typing(false)] {
+		assert_eq!(parse_presence_update(&gen_presence_update(update)).unwrap(), update);
+	}
+	// small counters fit in a few bytes
+	assert_eq!(gen_presence_update(PresenceUpdate::ReadUpTo(300)).len(), 3);
+	assert!(parse_presence_update(&[]).is_err());
+	assert!(parse_presence_update(&[0, 0x80]).is_err());
+	assert!(parse_presence_update(&[0, 1, 2]).is_err());
+	assert!(parse_presence_update(&[0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]).is_err());
+	assert!(parse_presence_update(&[1, 2]).is_err());
+	assert!(parse_presence_update(&[2, 0]).is_err());
+	
+	// the update is sent as an internal event
+	let (pk_kyber, _) = kyber_keygen();
+	let event_data = gen_presence_update(PresenceUpdate::Typing(true));
+	assert!(send_msg((content_type::INTERNAL, Some(&event::PRESENCE.to_string()), Some(&event_data)), &pk_kyber, None, &sym_key_gen(), &sym_key_gen(), &id_gen(), &mdc_gen()).is_ok());
+	
+	let now = 1672531200;
+	let mut aggregator = PresenceAggregator::new();
+	aggregator.apply("bob", PresenceUpdate::ReadUpTo(5), now);
+	aggregator.apply("carol", PresenceUpdate::ReadUpTo(3), now);
+	aggregator.apply("bob", PresenceUpdate::ReadUpTo(4), now); // late update
+	aggregator.apply("dave", PresenceUpdate::Typing(true), now);
+	aggregator.apply("carol", PresenceUpdate::Typing(true), now + 5);
+	assert_eq!(aggregator.read_up_to("bob"), Some(5));
+	assert_eq!(aggregator.read_up_to("dave"), None);
+	assert_eq!(aggregator.read_by(3), vec!["bob", "carol"]);
+	assert_eq!(aggregator.read_by(5), vec!["bob"]);
+	assert_eq!(aggregator.typing(now + 1), vec!["carol", "dave"]);
+	
+	// typing indicators time out and end when the member reads or stops typing
+	assert_eq!(aggregator.typing(now + TYPING_TIMEOUT), vec!["carol"]);
+	aggregator.apply("carol", PresenceUpdate::Typing(false), now + 6);
+	assert_eq!(aggregator.typing(now + 6), vec!["dave"]);
+	aggregator.remove("bob");
+	assert!(aggregator.read_by(5).is_empty());
+}
+
+#[test]
+fn test_threads() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let mut alice_threads = ThreadIndex::new();
+	let mut bob_threads = ThreadIndex::new();
+	
+	// Alice starts a thread at a message she sent, Bob derives the same thread id from the received message
+	let (_, _, root) = send_msg((content_type::TEXT, Some("Who is in for lunch?"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let thread_id = alice_threads.create(&root, "msg-1", "alice");
+	assert!(parse_msg_with_config(&root, &sk_kyber, None, &pfs_key, &pfs_salt, &ProtocolConfig::default()).unwrap().thread_id.is_none());
+	assert_eq!(bob_threads.create(&root, "msg-1", "alice"), thread_id);
+	
+	// replies carry the thread id
+	let (_, _, reply) = send_msg_with_options(&OutgoingMessage::text("Me!"), &SendOptions::new().thread(&thread_id), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let parsed = parse_msg_with_config(&reply, &sk_kyber, None, &pfs_key, &pfs_salt, &ProtocolConfig::default()).unwrap();
+	let (recv_content_type, recv_text, _) = parsed.message.into_content();
+	assert_eq!(recv_content_type, content_type::TEXT);
+	assert_eq!(recv_text, Some("Me!".to_string()));
+	assert_eq!(parsed.thread_id, Some(thread_id.clone()));
+	bob_threads.add(&thread_id, "msg-2", "bob");
+	bob_threads.add(&thread_id, "msg-2", "bob");
+	
+	// old clients just see a normal message
+	let (received, _, _, _) = parse_msg(&reply, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	let (_, recv_text, _) = received.into_content();
+	assert_eq!(recv_text, Some("Me!".to_string()));
+	
+	assert_eq!(bob_threads.messages(&thread_id), ["msg-1", "msg-2"]);
+	assert_eq!(bob_threads.participants(&thread_id), ["alice", "bob"]);
+	assert_eq!(bob_threads.threads(), vec![thread_id.as_str()]);
+	assert!(bob_threads.messages("unknown").is_empty());
+	
+	// meta messages can't be part of a thread
+	let delta = gen_delta_sync(&DeltaSync::request(0, 0)).unwrap();
+	assert!(send_msg_with_options(&OutgoingMessage::delta_sync(&delta), &SendOptions::new().thread(&thread_id), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).is_err());
+}
+
+#[test]
+fn test_channel_actions() {
+	let channel_id = gen_namespaced_id(Namespace::Channel);
+	let (owner_pk_sig, owner_sk_sig) = gen_sign_keypair().unwrap();
+	let (editor_pk_sig, editor_sk_sig) = gen_sign_keypair().unwrap();
+	let (subscriber_pk_sig, subscriber_sk_sig) = gen_sign_keypair().unwrap();
+	let publishers = vec![owner_pk_sig.clone(), editor_pk_sig.clone()];
+	
+	let pin = gen_channel_action(&channel_id, 1, &ChannelAction::Pin("msg-1".to_string()), owner_pk_sig.as_bytes(), owner_sk_sig.as_bytes()).unwrap();
+	let setting = gen_channel_action(&channel_id, 2, &ChannelAction::Setting("title".to_string(), "News".to_string()), editor_pk_sig.as_bytes(), editor_sk_sig.as_bytes()).unwrap();
+	let unpin = gen_channel_action(&channel_id, 3, &ChannelAction::Unpin("msg-1".to_string()), owner_pk_sig.as_bytes(), owner_sk_sig.as_bytes()).unwrap();
+	assert_eq!(verify_channel_action(&pin, &channel_id, &publishers).unwrap(), (1, ChannelAction::Pin("msg-1".to_string())));
+	
+	let mut state = ChannelAdminState::new();
+	assert!(state.apply(&pin, &channel_id, &publishers).unwrap());
+	assert!(state.apply(&setting, &channel_id, &publishers).unwrap());
+	assert_eq!(state.pinned, vec!["msg-1".to_string()]);
+	assert_eq!(state.settings.get("title"), Some(&"News".to_string()));
+	
+	// replayed actions are ignored
+	assert!(!state.apply(&pin, &channel_id, &publishers).unwrap());
+	assert!(state.apply(&unpin, &channel_id, &publishers).unwrap());
+	assert!(state.pinned.is_empty());
+	
+	// subscribers can't act as publishers, and actions can't be moved to another channel or altered
+	let forged = gen_channel_action(&channel_id, 4, &ChannelAction::Pin("spam".to_string()), subscriber_pk_sig.as_bytes(), subscriber_sk_sig.as_bytes()).unwrap();
+	assert!(state.apply(&forged, &channel_id, &publishers).is_err());
+	let impersonating = gen_channel_action(&channel_id, 4, &ChannelAction::Pin("spam".to_string()), owner_pk_sig.as_bytes(), subscriber_sk_sig.as_bytes()).unwrap();
+	assert!(verify_channel_action(&impersonating, &channel_id, &publishers).is_err());
+	assert!(verify_channel_action(&pin, &gen_namespaced_id(Namespace::Channel), &publishers).is_err());
+	let altered = String::from_utf8(setting.clone()).unwrap().replace("News", "Spam");
+	assert!(verify_channel_action(altered.as_bytes(), &channel_id, &publishers).is_err());
+	assert_eq!(state.sequence, 3);
+}
+
+#[test]
+fn test_history_import() {
+	let export = r#"{
+		"version": 1,
+		"source": "signal",
+		"conversations": [
+			{"name": "Bob", "messages": [
+				{"timestamp": 1672531260, "from_self": false, "sender": "Bob", "kind": "voice", "data": "AQMFBwkq"},
+				{"timestamp": 1672531200, "from_self": true, "kind": "text", "text": "Hi Bob"},
+				{"timestamp": 1672531230, "from_self": false, "sender": "Bob", "kind": "call"}
+			]},
+			{"name": "Family", "messages": [
+				{"timestamp": 1672531320, "from_self": false, "sender": "Carol", "kind": "picture", "text": "beach", "data": "KioqKg"}
+			]}
+		]
+	}"#;
+	
+	let self_id = id_gen();
+	let (conversations, skipped) = import_history(export.as_bytes(), ImportTarget::SelfConversation(&self_id)).unwrap();
+	assert_eq!(skipped, 1);
+	assert_eq!(conversations.len(), 2);
+	assert_eq!(conversations[0].source, "signal");
+	assert_eq!(conversations[0].name, "Bob");
+	assert_eq!(conversations[0].messages, vec![
+		(HistoryEntry { id: self_id.clone(), sent: true, timestamp: 1672531200, content_type: content_type::TEXT, text: Some("Hi Bob".to_string()), data: None }, None),
+		(HistoryEntry { id: self_id.clone(), sent: false, timestamp: 1672531260, content_type: content_type::VOICE, text: None, data: Some(vec![1,3,5,7,9,42]) }, Some("Bob".to_string())),
+	]);
+	assert_eq!(conversations[1].messages[0].0.content_type, content_type::PICTURE);
+	assert_eq!(conversations[1].messages[0].1, Some("Carol".to_string()));
+	
+	// archives get a separate id per conversation
+	let (conversations, _) = import_history(export.as_bytes(), ImportTarget::Archive).unwrap();
+	assert_ne!(conversations[0].messages[0].0.id, conversations[1].messages[0].0.id);
+	assert_ne!(conversations[0].messages[0].0.id, self_id);
+	
+	// imported entries can be handed to a new device like any other history
+	let entries: Vec<HistoryEntry> = conversations.iter().flat_map(|conversation| conversation.messages.iter().map(|(entry, _)| entry.clone())).collect();
+	assert!(gen_history_chunks(&entries, 64).is_ok());
+	
+	assert!(import_history(export.replace("\"version\": 1", "\"version\": 2").as_bytes(), ImportTarget::Archive).is_err());
+	assert!(import_history(export.replace("\"data\": \"AQMFBwkq\"", "\"text\": \"no data\"").as_bytes(), ImportTarget::Archive).is_err());
+	assert!(import_history(b"{}", ImportTarget::Archive).is_err());
+}
+
+#[test]
+fn test_gateway_envelope() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (bot_pk_sig, bot_sk_sig) = sign_keygen();
+	let origin = GatewayOrigin { network: "matrix".to_string(), remote_id: "@bob:example.org".to_string(), timestamp: 1672531200 };
+	
+	// a bridge bot relays a picture from matrix
+	let picture = vec![42u8; 100];
+	let envelope = gen_gateway_envelope(&origin, (content_type::PICTURE, Some("holiday"), Some(&picture))).unwrap();
+	let (_, _, ciphertext) = send_msg((content_type::GATEWAY, Some(&envelope), Some(&picture)), &pk_kyber, Some(&bot_sk_sig), &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, Some(&bot_pk_sig), &pfs_key, &pfs_salt).unwrap();
+	let (recv_content_type, recv_text, recv_data) = received.into_content();
+	assert_eq!(recv_content_type, content_type::GATEWAY);
+	let (recv_origin, content) = parse_gateway_envelope(&recv_text.unwrap(), recv_data).unwrap();
+	assert_eq!(recv_origin, origin);
+	assert_eq!(content, (content_type::PICTURE, Some("holiday".to_string()), Some(picture.clone())));
+	
+	// text without data
+	let envelope = gen_gateway_envelope(&origin, (content_type::TEXT, Some("hi from matrix"), None)).unwrap();
+	let (_, _, ciphertext) = send_msg((content_type::GATEWAY, Some(&envelope), None), &pk_kyber, Some(&bot_sk_sig), &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, Some(&bot_pk_sig), &pfs_key, &pfs_salt).unwrap();
+	let (_, recv_text, recv_data) = received.into_content();
+	assert_eq!(parse_gateway_envelope(&recv_text.unwrap(), recv_data).unwrap().1, (content_type::TEXT, Some("hi from matrix".to_string()), None));
+	
+	// only regular content can be relayed, and it has to be complete
+	assert!(gen_gateway_envelope(&origin, (content_type::HISTORY_SYNC, Some("header"), Some(&picture))).is_err());
+	assert!(gen_gateway_envelope(&GatewayOrigin { network: String::new(), ..origin.clone() }, (content_type::TEXT, Some("hi"), None)).is_err());
+	let envelope = gen_gateway_envelope(&origin, (content_type::VOICE, None, None)).unwrap();
+	assert!(send_msg((content_type::GATEWAY, Some(&envelope), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).is_err());
+	assert!(send_msg((content_type::GATEWAY, Some("not an envelope"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).is_err());
+	
+	// the relayed data counts against the parse limits
+	let limits = ParseLimits { max_data_len: 50, ..ParseLimits::default() };
+	let envelope = gen_gateway_envelope(&origin, (content_type::VOICE, None, Some(&picture))).unwrap();
+	let (_, _, ciphertext) = send_msg((content_type::GATEWAY, Some(&envelope), Some(&picture)), &pk_kyber, Some(&bot_sk_sig), &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert!(parse_msg_limited(&ciphertext, &sk_kyber, Some(&bot_pk_sig), &pfs_key, &pfs_salt, &limits).is_err());
+}
+
+#[test]
+fn test_application_identity() {
+	let (owner_pk_sig, owner_sk_sig) = gen_sign_keypair().unwrap();
+	let ci_conversation = id_gen();
+	let other_conversation = id_gen();
+	
+	assert!(ApplicationIdentity::mint("ci", ApplicationPolicy::new().content_type(content_type::TEXT), &owner_sk_sig).is_err());
+	let policy = ApplicationPolicy::new().content_type(content_type::TEXT).content_type(content_type::LINKED_MEDIA).conversation(&ci_conversation);
+	let bot = ApplicationIdentity::mint("ci", policy.clone(), &owner_sk_sig).unwrap();
+	assert!(bot.check_send(content_type::TEXT, &ci_conversation).is_ok());
+	assert!(bot.check_send(content_type::VOICE, &ci_conversation).is_err());
+	assert!(bot.check_send(content_type::TEXT, &other_conversation).is_err());
+	
+	// the receiver verifies the certificate and enforces the policy
+	let (name, bot_pk_sig, recv_policy) = verify_application_identity(&bot.certificate, &owner_pk_sig).unwrap();
+	assert_eq!(name, "ci");
+	assert_eq!(bot_pk_sig, bot.pubkey_sig);
+	assert_eq!(recv_policy, policy);
+	assert!(recv_policy.permits(content_type::LINKED_MEDIA, &ci_conversation));
+	assert!(!recv_policy.permits(content_type::PICTURE, &ci_conversation));
+	
+	// the policy is declared as capabilities
+	let capabilities = policy.to_capabilities();
+	assert!(capabilities.contains(&capability::APPLICATION.to_string()));
+	assert_eq!(ApplicationPolicy::from_capabilities(&capabilities).unwrap(), Some(policy));
+	assert_eq!(ApplicationPolicy::from_capabilities(&capability::SUPPORTED.map(String::from)).unwrap(), None);
+	
+	// certificates can't be extended or claimed by someone else
+	let (stranger_pk_sig, _) = gen_sign_keypair().unwrap();
+	assert!(verify_application_identity(&bot.certificate, &stranger_pk_sig).is_err());
+	let extended = bot.certificate.replace(&format!("\"application:conversation:{}\"", ci_conversation), &format!("\"application:conversation:{}\",\"application:conversation:{}\"", ci_conversation, other_conversation));
+	assert_ne!(extended, bot.certificate);
+	assert!(verify_application_identity(&extended, &owner_pk_sig).is_err());
+}
+
+#[test]
+fn test_armored_messages() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, mdc, ciphertext) = send_msg((content_type::TEXT, Some("sent by email"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	
+	let armored = armor_msg(&ciphertext, &mdc);
+	assert!(armored.lines().all(|line| line.len() <= 64));
+	assert_eq!(dearmor_msg(&armored).unwrap(), (ciphertext.clone(), mdc.clone()));
+	
+	// quoted in a reply, with CRLF line endings
+	let quoted = "Hi,\r\nsee below\r\n".to_string() + &armored.lines().map(|line| "> ".to_string() + line).collect::<Vec<_>>().join("\r\n") + "\r\n-- \r\nsignature";
+	assert_eq!(dearmor_msg(&quoted).unwrap(), (ciphertext.clone(), mdc.clone()));
+	
+	// line breaks collapsed into spaces and rewrapped at other positions
+	let collapsed = armored.replace('\n', " ");
+	assert_eq!(dearmor_msg(&collapsed).unwrap(), (ciphertext.clone(), mdc.clone()));
+	let body: String = armored.lines().skip(5).take_while(|line| !line.starts_with("-----")).collect();
+	let rewrapped = armored.lines().take(5).collect::<Vec<_>>().join("\n").replace("MDC: ", "MDC:\n") + "\n" + &body.as_bytes().chunks(40).map(|line| String::from_utf8_lossy(line).to_string()).collect::<Vec<_>>().join("\n") + "\n-----END DAWN MESSAGE-----";
+	assert_eq!(dearmor_msg(&rewrapped).unwrap(), (ciphertext.clone(), mdc.clone()));
+	
+	let (received, _, _, _) = parse_msg(&dearmor_msg(&collapsed).unwrap().0, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	let (_, text, _) = received.into_content();
+	assert_eq!(text, Some("sent by email".to_string()));
+	
+	// truncation and corruption are detected
+	assert!(dearmor_msg(&armored[..armored.len() / 2]).is_err());
+	let body_line = armored.lines().nth(5).unwrap();
+	let corrupted = armored.replacen(body_line, &body_line.chars().rev().collect::<String>(), 1);
+	assert!(dearmor_msg(&corrupted).is_err());
+	assert!(dearmor_msg(&armored.replace("Version: 1", "Version: 2")).is_err());
+	assert!(dearmor_msg("no message here").is_err());
+}
+
+#[test]
+fn test_pairing() {
+	let alice = Identity::generate().unwrap();
+	let bob = Identity::generate().unwrap();
+	let alice_handle = alice.gen_handle("alice", &mdc_gen());
+	let bob_handle = bob.gen_handle("bob", &mdc_gen());
+	
+	// alice displays the code, bob types it in with a separator
+	let code = gen_pairing_code().unwrap();
+	assert_eq!(code.len(), 6);
+	assert!(code.bytes().all(|byte| byte.is_ascii_digit()));
+	let (alice_pairing, alice_msg) = Pairing::start(&code, PairingRole::Initiator).unwrap();
+	let (bob_pairing, bob_msg) = Pairing::start(&format!("{}-{}", &code[..3], &code[3..]), PairingRole::Responder).unwrap();
+	let (alice_key, alice_sealed) = alice_pairing.finish(&bob_msg, &alice_handle).unwrap();
+	let (bob_key, bob_sealed) = bob_pairing.finish(&alice_msg, &bob_handle).unwrap();
+	assert_eq!(bob_key.open_handle(&alice_sealed).unwrap(), alice_handle);
+	assert_eq!(alice_key.open_handle(&bob_sealed).unwrap(), bob_handle);
+	// a sealed handle can't be reflected to its sender
+	assert!(alice_key.open_handle(&alice_sealed).is_err());
+	
+	// the received handle is used for the usual init request
+	assert!(InitRequestBuilder::new().handle(alice_key.open_handle(&bob_sealed).unwrap()).unwrap().own_signature_keys(alice.pubkey_sig.clone(), alice.seckey_sig.clone()).name("alice").build().is_ok());
+	
+	// different codes don't result in a shared key
+	let wrong_code = if code == "000000" { "000001" } else { "000000" };
+	let (alice_pairing, alice_msg) = Pairing::start(&code, PairingRole::Initiator).unwrap();
+	let (mallory_pairing, mallory_msg) = Pairing::start(wrong_code, PairingRole::Responder).unwrap();
+	let (alice_key, alice_sealed) = alice_pairing.finish(&mallory_msg, &alice_handle).unwrap();
+	let (mallory_key, _) = mallory_pairing.finish(&alice_msg, &bob_handle).unwrap();
+	assert!(mallory_key.open_handle(&alice_sealed).is_err());
+	
+	assert!(Pairing::start("12345", PairingRole::Initiator).is_err());
+	assert!(Pairing::start("12345a", PairingRole::Initiator).is_err());
+	let (pairing, _) = Pairing::start(&code, PairingRole::Initiator).unwrap();
+	assert!(pairing.finish(&[0; 32], &alice_handle).is_err());
+}
+
+#[test]
+fn test_key_ceremony() {
+	let (dealer_pk_sig, dealer_sk_sig) = gen_sign_keypair().unwrap();
+	let holders: Vec<(SignPublicKey, SignSecretKey)> = (0..5).map(|_| gen_sign_keypair().unwrap()).collect();
+	let holder_keys: Vec<SignPublicKey> = holders.iter().map(|(pk, _)| pk.clone()).collect();
+	
+	// the announcement channel key is split among five holders, three of them are needed
+	let (_, channel_sk_sig) = gen_sign_keypair().unwrap();
+	assert!(run_key_ceremony("announcements", channel_sk_sig.as_bytes(), 6, &holder_keys, &dealer_pk_sig, &dealer_sk_sig).is_err());
+	assert!(run_key_ceremony("announcements", channel_sk_sig.as_bytes(), 1, &holder_keys, &dealer_pk_sig, &dealer_sk_sig).is_err());
+	let (mut transcript, shares) = run_key_ceremony("announcements", channel_sk_sig.as_bytes(), 3, &holder_keys, &dealer_pk_sig, &dealer_sk_sig).unwrap();
+	assert_eq!(shares.len(), 5);
+	assert_eq!(transcript.purpose(), "announcements");
+	
+	// every holder checks and acknowledges their share, a share can't be acknowledged with another key
+	let (_, stranger_sk_sig) = gen_sign_keypair().unwrap();
+	assert!(transcript.clone().acknowledge(&shares[0], &stranger_sk_sig).is_err());
+	assert!(transcript.clone().acknowledge(&shares[0], &holders[1].1).is_err());
+	for (share, (_, holder_sk_sig)) in shares.iter().zip(&holders) {
+		let share = CeremonyShare::from_bytes(&share.to_bytes()).unwrap();
+		transcript.acknowledge(&share, holder_sk_sig).unwrap();
+	}
+	assert_eq!(transcript.acknowledged(), 5);
+	let transcript = CeremonyTranscript::from_bytes(&transcript.to_bytes().unwrap()).unwrap();
+	transcript.verify(&dealer_pk_sig).unwrap();
+	let (other_pk_sig, _) = gen_sign_keypair().unwrap();
+	assert!(transcript.verify(&other_pk_sig).is_err());
+	let tampered = String::from_utf8(transcript.to_bytes().unwrap()).unwrap().replace("announcements", "payroll");
+	assert!(CeremonyTranscript::from_bytes(tampered.as_bytes()).unwrap().verify(&dealer_pk_sig).is_err());
+	
+	// any three shares reassemble the key
+	let key = transcript.reassemble(&[shares[4].clone(), shares[1].clone(), shares[2].clone()]).unwrap();
+	assert_eq!(key, channel_sk_sig.to_vec());
+	assert_eq!(transcript.reassemble(&shares).unwrap(), key);
+	assert!(SignSecretKey::try_from(key).is_ok());
+	assert!(transcript.reassemble(&[shares[0].clone(), shares[1].clone()]).is_err());
+	assert!(transcript.reassemble(&[shares[0].clone(), shares[0].clone(), shares[1].clone()]).is_err());
+	
+	// altered shares and shares of other ceremonies are rejected
+	let mut altered = shares[3].to_bytes();
+	*altered.last_mut().unwrap() ^= 1;
+	assert!(transcript.reassemble(&[shares[0].clone(), shares[1].clone(), CeremonyShare::from_bytes(&altered).unwrap()]).is_err());
+	let (_, other_shares) = run_key_ceremony("announcements", channel_sk_sig.as_bytes(), 3, &holder_keys, &dealer_pk_sig, &dealer_sk_sig).unwrap();
+	assert!(transcript.verify_share(&other_shares[0]).is_err());
+	assert!(!format!("{:?}", shares[0]).contains(&encode(channel_sk_sig.as_bytes())));
+}
+
+#[test]
+fn test_backup_key_shares() {
+	let identity = Identity::generate().unwrap();
+	let backup_key = sym_key_gen();
+	let backup = identity.backup(&backup_key).unwrap();
+	
+	// three of five trusted contacts are needed
+	assert!(split_backup_key(&backup_key, 4, 3).is_err());
+	let shares = split_backup_key(&backup_key, 3, 5).unwrap();
+	assert_eq!(shares.len(), 5);
+	assert!(shares.iter().all(|share| share.starts_with("dawn-share-1-3-")));
+	let recovered = recover_backup_key(&[&shares[3], &shares[0], &format!("  {}\n", shares[4])]).unwrap();
+	assert_eq!(recovered, backup_key);
+	assert_eq!(Identity::from_backup(&backup, &recovered).unwrap(), identity);
+	assert_eq!(recover_backup_key(&shares.iter().map(|share| share.as_str()).collect::<Vec<_>>()).unwrap(), backup_key);
+	
+	// the check isn't derived from the backup key alone, so splitting the same key again results in another one
+	let check = |share: &str| share.split('-').nth(5).unwrap().to_string();
+	assert_ne!(check(&split_backup_key(&backup_key, 3, 5).unwrap()[0]), check(&shares[0]));
+	
+	// too few, repeated, damaged or mixed up shares don't recover anything
+	assert!(recover_backup_key(&[&shares[0], &shares[1]]).is_err());
+	assert!(recover_backup_key(&[&shares[0], &shares[1], &shares[1]]).is_err());
+	let last = shares[2].chars().last().unwrap();
+	let damaged = shares[2][..shares[2].len() - 1].to_string() + if last == '0' { "1" } else { "0" };
+	assert!(recover_backup_key(&[&shares[0], &shares[1], &damaged]).is_err());
+	let other_shares = split_backup_key(&sym_key_gen(), 3, 5).unwrap();
+	assert!(recover_backup_key(&[&shares[0], &shares[1], &other_shares[2]]).is_err());
+	assert!(recover_backup_key(&["dawn-share-2-3-1-00-00"]).is_err());
+	assert!(recover_backup_key(&[]).is_err());
+}
+
+#[test]
+fn test_device_counters() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_salt, id, mdc_seed, .. } = gen_test_conversation();
+	let (pk_sig, sk_sig) = sign_keygen();
+	let mut sender_pfs_key = sym_key_gen();
+	let mut receiver_pfs_key = sender_pfs_key.clone();
+	let mut counter = DeviceCounter::new("phone");
+	let mut tracker = CounterTracker::new();
+	
+	for text in ["one", "two"] {
+		let (new_pfs_key, _, ciphertext) = send_msg_with_options(&OutgoingMessage::text(text), &SendOptions::new().counted(&mut counter).unwrap(), &pk_kyber, Some(&sk_sig), &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		sender_pfs_key = new_pfs_key;
+		let parsed = parse_msg_with_config(&ciphertext, &sk_kyber, Some(&pk_sig), &receiver_pfs_key, &pfs_salt, &ProtocolConfig::default()).unwrap();
+		assert_eq!(parsed.check_device(&mut tracker), None);
+		let (_, recv_text, _) = parsed.message.into_content();
+		receiver_pfs_key = parsed.new_pfs_key;
+		assert_eq!(recv_text, Some(text.to_string()));
+	}
+	assert_eq!(counter.counter(), 2);
+	assert_eq!(tracker.last_counter("phone"), Some(2));
+	
+	// messages without counter are accepted as before
+	let (new_pfs_key, _, ciphertext) = send_msg((content_type::TEXT, Some("old client"), None), &pk_kyber, Some(&sk_sig), &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let parsed = parse_msg_with_config(&ciphertext, &sk_kyber, Some(&pk_sig), &receiver_pfs_key, &pfs_salt, &ProtocolConfig::default()).unwrap();
+	assert_eq!(parsed.check_device(&mut tracker), None);
+	sender_pfs_key = new_pfs_key.clone();
+	receiver_pfs_key = new_pfs_key;
+	
+	// the session state was restored from a backup taken after the first message onto a second device
+	let mut clone = DeviceCounter::resume("phone", 1);
+	let (_, _, ciphertext) = send_msg_with_options(&OutgoingMessage::text("from the clone"), &SendOptions::new().counted(&mut clone).unwrap(), &pk_kyber, Some(&sk_sig), &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let parsed = parse_msg_with_config(&ciphertext, &sk_kyber, Some(&pk_sig), &receiver_pfs_key, &pfs_salt, &ProtocolConfig::default()).unwrap();
+	let event = parsed.check_device(&mut tracker);
+	let (_, recv_text, _) = parsed.message.into_content();
+	assert_eq!(recv_text, Some("from the clone".to_string()));
+	assert_eq!(event, Some(SecurityEvent::ClonedSession { device_id: "phone".to_string(), counter: 2, last_counter: 2 }));
+	assert_eq!(tracker.last_counter("phone"), Some(2));
+	
+	// other devices of the peer are tracked separately
+	let mut tablet = DeviceCounter::new("tablet");
+	let (_, _, ciphertext) = send_msg_with_options(&OutgoingMessage::text("tablet"), &SendOptions::new().counted(&mut tablet).unwrap(), &pk_kyber, Some(&sk_sig), &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	assert_eq!(parse_msg_with_config(&ciphertext, &sk_kyber, Some(&pk_sig), &receiver_pfs_key, &pfs_salt, &ProtocolConfig::default()).unwrap().check_device(&mut tracker), None);
+	let mut restored = CounterTracker::new();
+	restored.restore("phone", 2);
+	assert_eq!(restored.last_counter("phone"), Some(2));
+	assert!(SendOptions::new().counted(&mut DeviceCounter::resume("phone", u64::MAX)).is_err());
+}
+
+#[test]
+fn test_passive_session() {
+	let (pk_kyber, sk_kyber) = gen_kyber_keypair().unwrap();
+	let (pk_sig, sk_sig) = gen_sign_keypair().unwrap();
+	let pfs_salt = sym_key_gen();
+	let id = id_gen();
+	let mdc_seed = mdc_gen();
+	let mut sender_pfs_key = sym_key_gen();
+	let mut primary_pfs_key = sender_pfs_key.clone();
+	
+	// the archival client gets the receiving state of the primary device
+	let mut passive = PassiveSession::new(sk_kyber.clone(), Some(pk_sig.clone()), &primary_pfs_key, &pfs_salt);
+	for text in ["first", "second"] {
+		let (new_pfs_key, mdc, ciphertext) = send_msg((content_type::TEXT, Some(text), None), pk_kyber.as_bytes(), Some(sk_sig.as_bytes()), &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		sender_pfs_key = new_pfs_key;
+		
+		// both decrypt the message, the primary device is not affected by the passive session
+		let (received, new_pfs_key, _, _) = parse_msg(&ciphertext, sk_kyber.as_bytes(), Some(pk_sig.as_bytes()), &primary_pfs_key, &pfs_salt).unwrap();
+		let (_, primary_text, _) = received.into_content();
+		primary_pfs_key = new_pfs_key;
+		let (passive_received, passive_mdc, _) = passive.parse(&ciphertext).unwrap().unwrap();
+		let (_, passive_text, _) = passive_received.into_content();
+		assert_eq!(primary_text, Some(text.to_string()));
+		assert_eq!(passive_text, primary_text);
+		assert_eq!(passive_mdc, mdc);
+	}
+	
+	// limits apply to passive sessions as well
+	let (_, _, ciphertext) = send_msg((content_type::TEXT, Some("a long text"), None), pk_kyber.as_bytes(), Some(sk_sig.as_bytes()), &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let mut limited = PassiveSession::new(sk_kyber.clone(), Some(pk_sig.clone()), &sender_pfs_key, &pfs_salt).limits(ParseLimits { max_text_len: 4, ..ParseLimits::default() });
+	assert!(limited.parse(&ciphertext).is_err());
+	assert!(passive.parse(&ciphertext).is_ok());
+}
+
+#[test]
+fn test_protocol_profile() {
+	let profile = ProtocolProfile::from_limits(&ParseLimits::low_memory());
+	assert_eq!(profile.max_inline_media_len, Some(1024 * 1024));
+	assert_eq!(profile.kyber_ciphertext_len, keys::KYBER_CIPHERTEXT_LEN);
+	assert!(profile.supports_content_type(content_type::TEXT.into()));
+	assert!(!profile.supports_content_type(42));
+	assert!(profile.capabilities.contains(&capability::LINKED_MEDIA.to_string()));
+	
+	// a server gets the profile as JSON and enforces the same limits
+	let json = profile.to_json().unwrap();
+	let received = ProtocolProfile::from_json(&json).unwrap();
+	assert_eq!(received, profile);
+	assert_eq!(received.limits(), ParseLimits::low_memory());
+	
+	// unset limits are null
+	let unlimited = ProtocolProfile::default();
+	assert_eq!(unlimited.max_text_len, None);
+	assert!(unlimited.to_json().unwrap().contains("\"max_text_len\":null"));
+	assert_eq!(unlimited.limits(), ParseLimits::default());
+	assert!(ProtocolProfile::from_json("{}").is_err());
+	
+	// messages sent by this library fit the profile
+	let (pk_kyber, _) = kyber_keygen();
+	assert!(send_msg((content_type::TEXT, Some("within the profile"), None), &pk_kyber, None, &sym_key_gen(), &sym_key_gen(), &id_gen(), &mdc_gen()).unwrap().2.len() <= received.max_ciphertext_len.unwrap());
+}
+
+#[test]
+fn test_parse_modes() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let strict = ParseLimits { mode: ParseMode::Strict, ..ParseLimits::default() };
+	let encrypt = |message: &str| encrypt_msg(&pk_kyber, None, &pfs_key, &pfs_salt, message).unwrap().0;
+	
+	// messages of this version pass both modes
+	let (_, _, ciphertext) = send_msg_with_options(&OutgoingMessage::picture(&[42; 10], "picture"), &SendOptions::new().thread("thread"), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert!(parse_msg_limited(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt, &strict).is_ok());
+	assert!(parse_msg_limited(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt, &ParseLimits::strict()).is_ok());
+	
+	// fields of a newer version are ignored in lenient mode only
+	let newer = encrypt(r#"{"Text":{"text":"hi","mdc":"00","reactions":["+1"]}}"#);
+	let (received, _, _, _) = parse_msg_limited(&newer, &sk_kyber, None, &pfs_key, &pfs_salt, &ParseLimits::default()).unwrap();
+	let (_, text, _) = received.into_content();
+	assert_eq!(text, Some("hi".to_string()));
+	let err = parse_msg_limited(&newer, &sk_kyber, None, &pfs_key, &pfs_salt, &strict).unwrap_err();
+	assert!(matches!(&err, DawnError::InvalidInput(message) if message.contains("/Text/reactions")));
+	let nested = encrypt(r#"{"Text":{"text":"hi","mdc":"00","in_reply_to":{"mdc":"01","author":"bob"}}}"#);
+	let err = parse_msg_limited(&nested, &sk_kyber, None, &pfs_key, &pfs_salt, &strict).unwrap_err();
+	assert!(matches!(&err, DawnError::InvalidInput(message) if message.contains("/Text/in_reply_to/author")));
+	
+	// optional fields sent as null are known fields
+	let nulls = encrypt(r#"{"Text":{"text":"hi","mdc":"00","thread_id":null,"in_reply_to":{"mdc":"01","excerpt":null},"seq":null}}"#);
+	assert!(parse_msg_limited(&nulls, &sk_kyber, None, &pfs_key, &pfs_salt, &strict).is_ok());
+	
+	// duplicate fields are rejected in both modes
+	let duplicate = encrypt(r#"{"Text":{"text":"hi","text":"bye","mdc":"00"}}"#);
+	assert!(parse_msg_limited(&duplicate, &sk_kyber, None, &pfs_key, &pfs_salt, &ParseLimits::default()).is_err());
+	assert!(parse_msg_limited(&duplicate, &sk_kyber, None, &pfs_key, &pfs_salt, &strict).is_err());
+	let two_messages = encrypt(r#"{"Text":{"text":"hi","mdc":"00"},"Voice":{"voice":"","mdc":"00"}}"#);
+	assert!(parse_msg_limited(&two_messages, &sk_kyber, None, &pfs_key, &pfs_salt, &ParseLimits::default()).is_err());
+	
+	// the mode is part of the protocol profile
+	assert!(ProtocolProfile::from_limits(&ParseLimits::strict()).strict);
+	assert_eq!(ProtocolProfile::from_limits(&ParseLimits::strict()).limits(), ParseLimits::strict());
+	
+	// strictness doesn't imply size limits
+	assert_eq!(ParseLimits::strict(), strict);
+	let big = encrypt(&format!(r#"{{"Text":{{"text":"{}","mdc":"00"}}}}"#, "a".repeat(ParseLimits::low_memory().max_text_len + 1)));
+	assert!(parse_msg_limited(&big, &sk_kyber, None, &pfs_key, &pfs_salt, &strict).is_ok());
+}
+
+#[test]
+fn test_canonical_serialization() {
+	#[derive(Serialize, Deserialize, Debug, PartialEq)]
+	struct Profile {
+		name: String,
+		status: String,
+		avatar_size: u64,
+		links: Vec<String>,
+	}
+	let profile = Profile { name: "Alice \"A\"".to_string(), status: "ünterwegs".to_string(), avatar_size: 1024, links: vec!["b".to_string(), "a".to_string()] };
+	
+	// keys are sorted, no whitespace, independent of the input representation
+	let canonical = canonical_json(&profile).unwrap();
+	assert_eq!(String::from_utf8(canonical.clone()).unwrap(), r#"{"avatar_size":1024,"links":["b","a"],"name":"Alice \"A\"","status":"ünterwegs"}"#);
+	let reordered = "{\n\t\"status\": \"\\u00fcnterwegs\",\n\t\"links\": [\"b\", \"a\"],\n\t\"name\": \"Alice \\\"A\\\"\",\n\t\"avatar_size\": 1024\n}";
+	assert_eq!(canonicalize_json(reordered.as_bytes()).unwrap(), canonical);
+	assert_eq!(canonicalize_json(&canonical).unwrap(), canonical);
+	assert!(canonical_json(&serde_json::json!({"x": 1.5})).is_err());
+	
+	// signed documents survive re-serialization by other clients
+	let (pubkey_sig, seckey_sig) = gen_sign_keypair().unwrap();
+	let (document, signature) = sign_document("profile", &profile, &seckey_sig).unwrap();
+	assert_eq!(document, canonical);
+	assert_eq!(verify_document::<Profile>("profile", &document, &signature, &pubkey_sig).unwrap(), profile);
+	assert_eq!(verify_document::<Profile>("profile", reordered.as_bytes(), &signature, &pubkey_sig).unwrap(), profile);
+	
+	// but not changes of their content or domain
+	let tampered = reordered.replace("1024", "1025");
+	assert!(verify_document::<Profile>("profile", tampered.as_bytes(), &signature, &pubkey_sig).is_err());
+	assert!(verify_document::<Profile>("group-state", &document, &signature, &pubkey_sig).is_err());
+	let (other_pubkey_sig, _) = gen_sign_keypair().unwrap();
+	assert!(verify_document::<Profile>("profile", &document, &signature, &other_pubkey_sig).is_err());
+}
+
+#[test]
+fn test_stream_hash() {
+	let blob = (0..100_000u32).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+	
+	// chunked hashing matches the hash of the whole blob for every chunk size
+	for chunk_size in [1, 7, 4096, blob.len()] {
+		let mut hasher = StreamHasher::new();
+		for chunk in blob.chunks(chunk_size) {
+			hasher.update(chunk);
+		}
+		assert_eq!(hasher.len(), blob.len() as u64);
+		assert_eq!(hasher.finalize(), hash(&blob));
+	}
+	assert_eq!(StreamHasher::new().finalize(), hash(&[]));
+	
+	// verification against an upload ticket
+	let ticket = UploadTicket { blob_hash: hash(&blob), size: blob.len() as u64, expires_at: 0 };
+	let mut verifier = ticket.verifier();
+	for chunk in blob.chunks(1000) {
+		verifier.update(chunk).unwrap();
+	}
+	assert!(verifier.finalize().is_ok());
+	
+	// modified, truncated and oversized data is rejected
+	let mut modified = blob.clone();
+	modified[500] ^= 1;
+	let mut verifier = ticket.verifier();
+	verifier.update(&modified).unwrap();
+	assert!(verifier.finalize().is_err());
+	let mut verifier = ticket.verifier();
+	verifier.update(&blob[..1000]).unwrap();
+	assert!(verifier.finalize().is_err());
+	let mut verifier = ticket.verifier();
+	verifier.update(&blob).unwrap();
+	assert!(verifier.update(&[0]).is_err());
+	
+	// without a size only the hash is checked
+	let mut verifier = HashVerifier::new(&hash(&blob), None);
+	verifier.update(&blob[..10]).unwrap();
+	verifier.update(&blob[10..]).unwrap();
+	assert!(verifier.finalize().is_ok());
+}
+
+#[test]
+fn test_convergent_media() {
+	let account_secret = sym_key_gen();
+	let file = b"the same picture sent into two conversations".to_vec();
+	let mut uploads = ConvergentUploads::new();
+	
+	// first conversation: the file is uploaded
+	let (ciphertext, key, dedup_id) = encrypt_file_convergent(&file, &account_secret).unwrap();
+	assert_eq!(decrypt_file(&ciphertext, &key).unwrap(), file);
+	assert!(uploads.get(&dedup_id).is_none());
+	uploads.add(&dedup_id, "https://media.example/1");
+	
+	// second conversation: the earlier upload is reused with the same key, wrapped for the new conversation
+	let (key_again, dedup_id_again) = derive_convergent_key(&file, &account_secret).unwrap();
+	assert_eq!((&key_again, &dedup_id_again), (&key, &dedup_id));
+	assert_eq!(uploads.get(&dedup_id_again), Some("https://media.example/1"));
+	let pfs_salt = sym_key_gen();
+	let wrapped = wrap_media_key(&key_again, &pfs_salt, "conversation-2", "https://media.example/1").unwrap();
+	assert_eq!(unwrap_media_key(&wrapped, &pfs_salt, "conversation-2", "https://media.example/1").unwrap(), key);
+	
+	// keys depend on the file and the account
+	let (other_file_key, other_file_id) = derive_convergent_key(b"another picture", &account_secret).unwrap();
+	assert_ne!(other_file_key, key);
+	assert_ne!(other_file_id, dedup_id);
+	let (other_account_key, other_account_id) = derive_convergent_key(&file, &sym_key_gen()).unwrap();
+	assert_ne!(other_account_key, key);
+	assert_ne!(other_account_id, dedup_id);
+	assert!(derive_convergent_key(&file, b"short").is_err());
+	
+	// random keys stay the default
+	assert_ne!(encrypt_file(&file).unwrap().1, key);
+	uploads.remove(&dedup_id);
+	assert!(uploads.get(&dedup_id).is_none());
+}
+
+#[test]
+fn test_redaction() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let redactor = Redactor::new().max_text_chars(5);
+	let parse = |ciphertext: &[u8]| {
+		let (received, _, mdc, _) = parse_msg(ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+		(received.into_content(), mdc)
+	};
+	
+	// texts are elided, identifiers pseudonymized
+	let (_, mdc, ciphertext) = send_msg((content_type::TEXT, Some("my password is hunter2"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (content, parsed_mdc) = parse(&ciphertext);
+	let redacted = redactor.redact(content, &parsed_mdc).unwrap();
+	assert_eq!(redacted.text, Some("my pa[17 more characters]".to_string()));
+	assert_ne!(redacted.mdc, mdc);
+	assert_eq!(redacted.mdc, redactor.pseudonymize(&mdc));
+	assert_ne!(Redactor::new().pseudonymize(&mdc), redacted.mdc);
+	assert_eq!(redactor.redact((content_type::TEXT, Some("short".to_string()), None), &mdc).unwrap().text, Some("short".to_string()));
+	
+	// media is stripped
+	let (_, _, ciphertext) = send_msg((content_type::PICTURE, Some("the beach"), Some(&[7; 300])), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (content, parsed_mdc) = parse(&ciphertext);
+	let redacted = redactor.redact(content, &parsed_mdc).unwrap();
+	assert_eq!((redacted.content_type, redacted.text, redacted.data_len), (content_type::PICTURE, Some("the b[4 more characters]".to_string()), Some(300)));
+	
+	// keys of linked media are removed
+	let media_key = encode(sym_key_gen());
+	let linked_text = format!("https://media.example/abc\n{}\nholiday\nvideo", media_key);
+	let (_, _, ciphertext) = send_msg((content_type::LINKED_MEDIA, Some(&linked_text), Some(&gen_linked_media_data(content_type::PICTURE.into(), None, None))), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (content, parsed_mdc) = parse(&ciphertext);
+	let redacted = redactor.redact(content, &parsed_mdc).unwrap();
+	let text = redacted.text.unwrap();
+	assert!(!text.contains(&media_key));
+	assert!(!text.contains("media.example"));
+	assert!(text.starts_with(&redactor.pseudonymize("https://media.example/abc")));
+	assert!(text.ends_with("<redacted>\nholid[8 more characters]"));
+	
+	// the relayed content and remote sender of gateway messages are redacted as well
+	let origin = GatewayOrigin { network: "matrix".to_string(), remote_id: "@bob:example.org".to_string(), timestamp: 1 };
+	let envelope = gen_gateway_envelope(&origin, (content_type::TEXT, Some("secret plans"), None)).unwrap();
+	let redacted = redactor.redact((content_type::GATEWAY, Some(envelope), None), &mdc).unwrap();
+	let (redacted_origin, (_, relayed_text, _)) = parse_gateway_envelope(&redacted.text.unwrap(), None).unwrap();
+	assert_eq!(redacted_origin.remote_id, redactor.pseudonymize("@bob:example.org"));
+	assert_eq!(relayed_text, Some("secre[7 more characters]".to_string()));
+	
+	// texts can be removed completely
+	assert_eq!(Redactor::new().max_text_chars(0).elide("abc"), "[3 more characters]");
+}
+
+#[test]
+fn test_conversation_stats() {
+	let mut stats = ConversationStats::new().utc_offset(3600).max_top_media(2);
+	stats.record(1_700_000_000, true, (content_type::TEXT, Some("hello äöü"), None));
+	stats.record(1_700_000_100, false, (content_type::TEXT, Some("hi"), None));
+	stats.record(1_700_003_600, false, (content_type::PICTURE, Some("beach"), Some(&[0; 500])));
+	stats.record(1_699_990_000, true, (content_type::VOICE, None, Some(&[0; 2000])));
+	stats.record_entry(&HistoryEntry { id: id_gen(), sent: true, timestamp: 1_700_007_200, content_type: content_type::PICTURE, text: None, data: Some(vec![0; 100]) });
+	stats.record(1_700_007_300, true, (content_type::LINKED_MEDIA, Some("link\nkey\n"), Some(&[content_type::PICTURE.into()])));
+	
+	let report = stats.into_report();
+	assert_eq!((report.messages, report.sent, report.received), (6, 4, 2));
+	assert_eq!(report.per_content_type.get(&content_type::TEXT), Some(&2));
+	assert_eq!(report.per_content_type.get(&content_type::PICTURE), Some(&2));
+	assert_eq!(report.per_content_type.get(&content_type::LINKED_MEDIA), Some(&1));
+	assert_eq!(report.text_chars, 11);
+	assert_eq!(report.media_bytes, 2600);
+	assert_eq!((report.first_timestamp, report.last_timestamp), (Some(1_699_990_000), Some(1_700_007_300)));
+	
+	// 1_700_000_000 is 22:13 UTC, 23:13 in the local time zone
+	assert_eq!(report.active_hours.len(), 24);
+	assert_eq!(report.active_hours[23], 2);
+	assert_eq!(report.active_hours[0], 1);
+	assert_eq!(report.active_hours[1], 2);
+	assert_eq!(report.active_hours[20], 1);
+	assert_eq!(report.active_hours.iter().sum::<u64>(), 6);
+	
+	// only the largest media are kept
+	assert_eq!(report.top_media.iter().map(|media| media.size).collect::<Vec<usize>>(), vec![2000, 500]);
+	assert_eq!(report.top_media[0].content_type, content_type::VOICE);
+	
+	assert_eq!(StatsReport::from_json(&report.to_json().unwrap()).unwrap(), report);
+}
+
+// behaviour every storage backend has to implement
+fn check_storage<S: Storage>(storage: &mut S) {
+	assert_eq!(storage.get(NAMESPACE_SESSIONS, "a").unwrap(), None);
+	assert!(storage.list(NAMESPACE_SESSIONS).unwrap().is_empty());
+	
+	storage.put(NAMESPACE_SESSIONS, "b", b"session b").unwrap();
+	storage.put(NAMESPACE_SESSIONS, "a/../x", b"session a").unwrap();
+	storage.put(NAMESPACE_PREKEYS, "b", b"prekey b").unwrap();
+	storage.put(NAMESPACE_SESSIONS, "", &[]).unwrap();
+	assert_eq!(storage.get(NAMESPACE_SESSIONS, "b").unwrap(), Some(b"session b".to_vec()));
+	assert_eq!(storage.get(NAMESPACE_PREKEYS, "b").unwrap(), Some(b"prekey b".to_vec()));
+	assert_eq!(storage.get(NAMESPACE_SESSIONS, "").unwrap(), Some(Vec::new()));
+	assert_eq!(storage.list(NAMESPACE_SESSIONS).unwrap(), vec!["".to_string(), "a/../x".to_string(), "b".to_string()]);
+	
+	// overwrite and delete
+	storage.put(NAMESPACE_SESSIONS, "b", b"session b v2").unwrap();
+	assert_eq!(storage.get(NAMESPACE_SESSIONS, "b").unwrap(), Some(b"session b v2".to_vec()));
+	storage.delete(NAMESPACE_SESSIONS, "b").unwrap();
+	storage.delete(NAMESPACE_SESSIONS, "b").unwrap();
+	assert_eq!(storage.get(NAMESPACE_SESSIONS, "b").unwrap(), None);
+	assert_eq!(storage.get(NAMESPACE_PREKEYS, "b").unwrap(), Some(b"prekey b".to_vec()));
+	assert_eq!(storage.list(NAMESPACE_PEERS).unwrap(), Vec::<String>::new());
+	assert!(storage.put("", "b", b"").is_err());
+	
+	// transactions: reads see pending writes, rollback discards all of them
+	storage.begin().unwrap();
+	assert!(storage.begin().is_err());
+	storage.put(NAMESPACE_PEERS, "p", b"peer").unwrap();
+	storage.delete(NAMESPACE_PREKEYS, "b").unwrap();
+	assert_eq!(storage.get(NAMESPACE_PEERS, "p").unwrap(), Some(b"peer".to_vec()));
+	assert_eq!(storage.list(NAMESPACE_PEERS).unwrap(), vec!["p".to_string()]);
+	assert_eq!(storage.list(NAMESPACE_PREKEYS).unwrap(), Vec::<String>::new());
+	storage.rollback().unwrap();
+	assert_eq!(storage.get(NAMESPACE_PEERS, "p").unwrap(), None);
+	assert_eq!(storage.get(NAMESPACE_PREKEYS, "b").unwrap(), Some(b"prekey b".to_vec()));
+	assert!(storage.commit().is_err());
+	assert!(storage.rollback().is_err());
+	
+	// a failing operation writes nothing, a successful one everything
+	let result = with_transaction(storage, |storage| {
+		storage.put(NAMESPACE_SESSIONS, "s", b"session").unwrap();
+		storage.put(NAMESPACE_PEERS, "p", b"peer").unwrap();
+		Err::<(), DawnError>(DawnError::InvalidInput("prekey already consumed".to_string()))
+	});
+	assert_eq!(result, Err(DawnError::InvalidInput("prekey already consumed".to_string())));
+	assert_eq!(storage.get(NAMESPACE_SESSIONS, "s").unwrap(), None);
+	with_transaction(storage, |storage| {
+		storage.put(NAMESPACE_SESSIONS, "s", b"session").unwrap();
+		storage.put(NAMESPACE_PEERS, "p", b"peer").unwrap();
+		storage.delete(NAMESPACE_PREKEYS, "b")
+	}).unwrap();
+	assert_eq!(storage.get(NAMESPACE_SESSIONS, "s").unwrap(), Some(b"session".to_vec()));
+	assert_eq!(storage.get(NAMESPACE_PEERS, "p").unwrap(), Some(b"peer".to_vec()));
+	assert_eq!(storage.get(NAMESPACE_PREKEYS, "b").unwrap(), None);
+}
+
+#[test]
+fn test_storage() {
+	check_storage(&mut MemoryStorage::new());
+	
+	#[cfg(feature = "file-storage")]
+	{
+		let root = std::env::temp_dir().join(format!("dawn-storage-{}", id_gen()));
+		check_storage(&mut FileStorage::open(&root).unwrap());
+		// records survive reopening the storage
+		assert_eq!(FileStorage::open(&root).unwrap().get(NAMESPACE_PEERS, "p").unwrap(), Some(b"peer".to_vec()));
+		// a commit interrupted after writing its journal is completed when the storage is opened again
+		std::fs::write(root.join("journal"), format!(r#"[{{"namespace":"{}","key":"p","value":null}},{{"namespace":"{}","key":"s","value":"{}"}}]"#, NAMESPACE_PEERS, NAMESPACE_SESSIONS, crate::codec::encode_base64(b"session v2"))).unwrap();
+		let storage = FileStorage::open(&root).unwrap();
+		assert_eq!(storage.get(NAMESPACE_PEERS, "p").unwrap(), None);
+		assert_eq!(storage.get(NAMESPACE_SESSIONS, "s").unwrap(), Some(b"session v2".to_vec()));
+		assert!(!root.join("journal").exists());
+		std::fs::remove_dir_all(root).unwrap();
+	}
+	
+	#[cfg(feature = "sqlite")]
+	check_storage(&mut SqliteStorage::open_in_memory().unwrap());
+}
+
+#[test]
+fn test_staged_sending() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let mut storage = MemoryStorage::new();
+	init_send_ratchet(&mut storage, "conversation", &pfs_key).unwrap();
+	assert!(init_send_ratchet(&mut storage, "conversation", &pfs_key).is_err());
+	
+	// the first message is sent and confirmed
+	let first = send_msg_staged(&mut storage, "conversation", (content_type::TEXT, Some("first"), None), &pk_kyber, None, &pfs_salt, &id, &mdc_seed).unwrap();
+	assert_eq!(pending_sends(&storage).unwrap(), vec![first.clone()]);
+	confirm_sent(&mut storage, &first.outbox_key).unwrap();
+	assert!(pending_sends(&storage).unwrap().is_empty());
+	let (received, receiver_pfs_key, mdc, _) = parse_msg(&first.ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	let (_, text, _) = received.into_content();
+	assert_eq!((text, mdc), (Some("first".to_string()), first.mdc));
+	
+	// the process dies after staging the second message, the ciphertext survives in the outbox
+	let second = send_msg_staged(&mut storage, "conversation", (content_type::TEXT, Some("second"), None), &pk_kyber, None, &pfs_salt, &id, &mdc_seed).unwrap();
+	assert!(send_msg_staged(&mut storage, "conversation", (content_type::TEXT, Some("third"), None), &pk_kyber, None, &pfs_salt, &id, &mdc_seed).is_err());
+	let recovered = pending_sends(&storage).unwrap();
+	assert_eq!(recovered, vec![second]);
+	let (received, receiver_pfs_key, _, _) = parse_msg(&recovered[0].ciphertext, &sk_kyber, None, &receiver_pfs_key, &pfs_salt).unwrap();
+	let (_, text, _) = received.into_content();
+	assert_eq!(text, Some("second".to_string()));
+	confirm_sent(&mut storage, &recovered[0].outbox_key).unwrap();
+	
+	// the key used for the next message matches the one the receiver expects
+	let third = send_msg_staged(&mut storage, "conversation", (content_type::TEXT, Some("third"), None), &pk_kyber, None, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (received, _, _, _) = parse_msg(&third.ciphertext, &sk_kyber, None, &receiver_pfs_key, &pfs_salt).unwrap();
+	let (_, text, _) = received.into_content();
+	assert_eq!(text, Some("third".to_string()));
+	
+	// a failed encryption leaves the stored state untouched
+	let before = storage.get(NAMESPACE_RATCHETS, "conversation").unwrap();
+	assert!(send_msg_staged(&mut storage, "other", (content_type::TEXT, Some("x"), None), &pk_kyber, None, &pfs_salt, &id, &mdc_seed).is_err());
+	confirm_sent(&mut storage, &third.outbox_key).unwrap();
+	assert!(send_msg_staged(&mut storage, "conversation", (content_type::TEXT, None, None), &pk_kyber, None, &pfs_salt, &id, &mdc_seed).is_err());
+	assert_eq!(storage.get(NAMESPACE_RATCHETS, "conversation").unwrap(), before);
+	assert!(pending_sends(&storage).unwrap().is_empty());
+}
+
+#[test]
+fn test_escrow() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (escrow_pubkey, escrow_seckey) = gen_kyber_keypair().unwrap();
+	
+	// the recipient parses escrowed messages as usual and sees the escrow key
+	let (new_pfs_key, mdc, ciphertext) = send_msg_with_options(&OutgoingMessage::picture(&[1, 2, 3], "whiteboard"), &SendOptions::new().escrow(&escrow_pubkey), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, parsed_pfs_key, parsed_mdc, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(received, ReceivedMessage::Picture { data: vec![1, 2, 3], description: "whiteboard".to_string(), alt_text: None, effect: None, in_reply_to: None, codec: None, expires_after: None });
+	assert_eq!((parsed_pfs_key, parsed_mdc.clone()), (new_pfs_key, mdc.clone()));
+	assert_eq!(escrow_status(&ciphertext).unwrap(), Some(escrow_key_fingerprint(&escrow_pubkey)));
+	
+	// the escrow holder decrypts without conversation keys
+	assert_eq!(open_escrow(&ciphertext, &escrow_pubkey, &escrow_seckey).unwrap(), (received, mdc));
+	let (other_pubkey, other_seckey) = gen_kyber_keypair().unwrap();
+	assert!(open_escrow(&ciphertext, &other_pubkey, &other_seckey).is_err());
+	
+	// normal messages are not escrowed
+	let (_, _, plain_ciphertext) = send_msg((content_type::TEXT, Some("hi"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert_eq!(escrow_status(&plain_ciphertext).unwrap(), None);
+	assert!(open_escrow(&plain_ciphertext, &escrow_pubkey, &escrow_seckey).is_err());
+	
+	// truncated escrow copies are rejected
+	assert!(escrow_status(&ciphertext[..100]).is_err());
+	assert!(parse_msg(&ciphertext[..1600], &sk_kyber, None, &pfs_key, &pfs_salt).is_err());
+}
+
+#[test]
+fn test_abuse_report() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_salt, id, mdc_seed, .. } = gen_test_conversation();
+	let (abuser_pubkey_sig, abuser_seckey_sig) = gen_sign_keypair().unwrap();
+	let (reporter_pubkey_sig, reporter_seckey_sig) = gen_sign_keypair().unwrap();
+	
+	// two signed messages received in a row, each with the pfs key it was parsed with
+	let first_pfs_key = sym_key_gen();
+	let (second_pfs_key, _, first) = send_msg((content_type::TEXT, Some("threat"), None), &pk_kyber, Some(abuser_seckey_sig.as_bytes()), &first_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (_, second_mdc, second) = send_msg((content_type::PICTURE, Some(""), Some(&[6; 6])), &pk_kyber, Some(abuser_seckey_sig.as_bytes()), &second_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	
+	let report = gen_abuse_report(&id, &[(&first, &first_pfs_key), (&second, &second_pfs_key)], &sk_kyber, &pfs_salt, &abuser_pubkey_sig, &reporter_pubkey_sig, &reporter_seckey_sig).unwrap();
+	let verified = verify_abuse_report(&report).unwrap();
+	assert_eq!(verified.conversation_id, id);
+	assert_eq!((&verified.reporter, &verified.reported), (&reporter_pubkey_sig, &abuser_pubkey_sig));
+	assert_eq!(verified.messages.len(), 2);
+	assert_eq!(verified.messages[0].0, ReceivedMessage::Text { text: "threat".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert_eq!(verified.messages[1], (ReceivedMessage::Picture { data: vec![6; 6], description: String::new(), alt_text: None, effect: None, in_reply_to: None, codec: None, expires_after: None }, second_mdc));
+	
+	// messages that weren't signed by the reported key can't be reported
+	let (_, _, unsigned) = send_msg((content_type::TEXT, Some("made up"), None), &pk_kyber, None, &first_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	assert!(gen_abuse_report(&id, &[(&unsigned, &first_pfs_key)], &sk_kyber, &pfs_salt, &abuser_pubkey_sig, &reporter_pubkey_sig, &reporter_seckey_sig).is_err());
+	let (other_pubkey_sig, _) = gen_sign_keypair().unwrap();
+	assert!(gen_abuse_report(&id, &[(&first, &first_pfs_key)], &sk_kyber, &pfs_salt, &other_pubkey_sig, &reporter_pubkey_sig, &reporter_seckey_sig).is_err());
+	assert!(gen_abuse_report(&id, &[], &sk_kyber, &pfs_salt, &abuser_pubkey_sig, &reporter_pubkey_sig, &reporter_seckey_sig).is_err());
+	
+	// changing a signed report breaks the signature of the reporter
+	let mut tampered = serde_json::from_slice::<serde_json::Value>(&report).unwrap();
+	tampered["report"]["conversation_id"] = serde_json::Value::String(id_gen());
+	assert!(verify_abuse_report(&serde_json::to_vec(&tampered).unwrap()).is_err());
+	let mut tampered = serde_json::from_slice::<serde_json::Value>(&report).unwrap();
+	tampered["report"]["messages"].as_array_mut().unwrap().pop();
+	assert!(verify_abuse_report(&serde_json::to_vec(&tampered).unwrap()).is_err());
+}
+
+#[test]
+fn test_security_encodings() {
+	let (alice_pk_kyber, _) = kyber_keygen();
+	let (bob_pk_kyber, _) = kyber_keygen();
+	let security_number = derive_security_number(&alice_pk_kyber, &bob_pk_kyber).unwrap();
+	
+	// both sides derive the same artifacts
+	let encodings = encode_security_number(&security_number);
+	assert_eq!(encodings, encode_security_number(&derive_security_number(&bob_pk_kyber, &alice_pk_kyber).unwrap()));
+	let bob = Peer::from_encoded(&encode(&bob_pk_kyber), &encode(sign_keygen().0), "bob", Vec::new()).unwrap();
+	assert_eq!(bob.security_encodings(&alice_pk_kyber).unwrap(), encodings);
+	
+	assert_eq!(encodings.emoji.len(), 16);
+	assert_eq!(encodings.emoji_names.len(), 16);
+	assert_eq!(encodings.digit_groups.len(), 12);
+	assert!(encodings.digit_groups.iter().all(|group| group.len() == 5 && group.bytes().all(|digit| digit.is_ascii_digit())));
+	
+	// other keys result in other artifacts
+	let (carol_pk_kyber, _) = kyber_keygen();
+	let other = encode_security_number(&derive_security_number(&alice_pk_kyber, &carol_pk_kyber).unwrap());
+	assert_ne!(other.emoji, encodings.emoji);
+	assert_ne!(other.digit_groups, encodings.digit_groups);
+	
+	// values are taken from the bytes most significant bit first
+	assert_eq!(security_encoding::split_bits(&[0b0000_0111, 0b1111_0000, 0xff], 6, 4), vec![1, 63, 3, 63]);
+}
+
+#[test]
+fn test_key_audit() {
+	let mut identity = Identity::generate().unwrap();
+	let mut audit = KeyAudit::new(&identity, 86400);
+	assert!(audit.is_due(1000));
+	
+	// the honestly published handle passes
+	let handle = identity.gen_handle("alice", &mdc_gen());
+	assert_eq!(audit.audit(1000, &identity, handle.clone()).unwrap(), None);
+	assert!(!audit.is_due(1000 + 86399));
+	assert!(audit.is_due(1000 + 86400));
+	
+	// rotated keys are still known, so a server publishing the old handle for a while raises no alarm
+	identity.rotate_init_keys().unwrap();
+	audit.remember(&identity);
+	assert_eq!(audit.audit(2000, &identity, handle).unwrap(), None);
+	assert_eq!(audit.audit(2000, &identity, identity.gen_handle("alice", &mdc_gen())).unwrap(), None);
+	
+	// keys substituted by the server are reported by name
+	let (server_pk_kyber, _) = kyber_keygen();
+	let forged = gen_handle(&server_pk_kyber, identity.init_pubkey_curve.as_bytes(), identity.init_pubkey_curve_pfs_2.as_bytes(), identity.init_pubkey_kyber_for_salt.as_bytes(), identity.init_pubkey_curve_for_salt.as_bytes(), "alice", &mdc_gen());
+	let restored = KeyAudit::from_bytes(&audit.to_bytes().unwrap()).unwrap();
+	assert_eq!(restored, audit);
+	assert_eq!(audit.audit(3000, &identity, forged).unwrap(), Some(SecurityEvent::UnknownPublishedKeys { keys: vec!["init_kyber".to_string()] }));
+	assert!(audit.audit(3000, &identity, b"not a handle".to_vec()).is_err());
+}
+
+#[test]
+fn test_validate_outgoing() {
+	let rules = OutgoingRules::new();
+	assert!(validate_outgoing((content_type::TEXT, Some("hello"), None), &rules).is_empty());
+	assert_eq!(validate_outgoing((content_type::TEXT, None, None), &rules), vec![Violation::MissingText]);
+	assert_eq!(validate_outgoing((content_type::INTERNAL, None, None), &rules), vec![Violation::MissingText, Violation::MissingData]);
+	assert!(matches!(validate_outgoing((content_type::LINKED_MEDIA, Some("\nkey"), Some(&[1])), &rules).as_slice(), [Violation::InvalidContent(_)]));
+	
+	// all violations are reported at once
+	let (bob_pk_kyber, _) = kyber_keygen();
+	let old_bob = Peer::from_encoded(&encode(&bob_pk_kyber), &encode(sign_keygen().0), "bob", Vec::new()).unwrap();
+	let policy = ApplicationPolicy::new().content_type(content_type::TEXT).conversation("conversation");
+	let rules = OutgoingRules::new().peer(&old_bob).limits(ParseLimits::low_memory()).signed(true).policy(&policy, "conversation");
+	let picture = vec![0u8; 2 * 1024 * 1024];
+	assert_eq!(validate_outgoing((content_type::PICTURE, None, Some(&picture)), &rules), vec![
+		Violation::TooLarge { field: "data", len: picture.len(), max: 1024 * 1024 },
+		Violation::TooLarge { field: "ciphertext", len: estimate_ciphertext_len((content_type::PICTURE, None, Some(&picture)), true).unwrap(), max: 2 * 1024 * 1024 },
+		Violation::NotPermitted,
+	]);
+	let link = "https://example.com/file\nkey";
+	assert_eq!(validate_outgoing((content_type::LINKED_MEDIA, Some(link), Some(&gen_linked_media_data(3, None, None))), &rules), vec![
+		Violation::UnsupportedByPeer(capability::LINKED_MEDIA),
+		Violation::NotPermitted,
+	]);
+	let bob = Peer::from_encoded(&encode(&bob_pk_kyber), &encode(sign_keygen().0), "bob", capability::supported()).unwrap();
+	assert_eq!(validate_outgoing((content_type::LINKED_MEDIA, Some(link), Some(&gen_linked_media_data(3, None, None))), &OutgoingRules::new().peer(&bob)), Vec::new());
+}
+
+// length of the routing header of a message without a counter, which depends on the length of its message detail code
+fn routing_header_len(envelope: &[u8]) -> usize {
+	routing::ROUTING_HEADER_LEN + envelope[6] as usize
+}
+
+#[test]
+fn test_routing_header() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, seckey_sig) = sign_keygen();
+	
+	// the header is readable without any keys
+	let content = (content_type::TEXT, Some("hello"), None);
+	let (_, mdc, ciphertext) = send_msg_with_options(&OutgoingMessage::text("hello"), &SendOptions::new().thread("thread"), &pk_kyber, Some(&seckey_sig), &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert_eq!(read_routing_header(&ciphertext).unwrap(), Some(RoutingHeader { version: 1, class: RoutingClass::Chat, signed: true, escrowed: false, thread: true, mdc, counter: None }));
+	assert!(parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).is_ok());
+	let (_, _, event) = send_msg((content_type::INTERNAL, Some("1"), Some(&[])), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert_eq!(read_routing_header(&event).unwrap().map(|header| (header.class, header.signed)), Some((RoutingClass::Control, false)));
+	let (escrow_pubkey, _) = gen_kyber_keypair().unwrap();
+	let (_, _, escrowed) = send_msg_with_options(&OutgoingMessage::text("hello"), &SendOptions::new().escrow(&escrow_pubkey), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert!(read_routing_header(&escrowed).unwrap().unwrap().escrowed);
+	
+	// the estimate includes the header
+	let (_, _, plain) = send_msg(content, &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert_eq!(plain.len(), estimate_ciphertext_len(content, false).unwrap());
+	
+	// headers that don't match the message are rejected by the recipient, even with a fitting checksum
+	let header_len = routing_header_len(&ciphertext);
+	let with_checksum = |mut envelope: Vec<u8>| {
+		let checksum = crc32fast::hash(&[&envelope[..header_len - 4], &envelope[header_len..]].concat());
+		envelope[header_len - 4..header_len].copy_from_slice(&checksum.to_be_bytes());
+		envelope
+	};
+	for (position, value) in [(4, RoutingClass::Control as u8), (5, 16), (7, 0)] {
+		let mut tampered = ciphertext.clone();
+		tampered[position] = value;
+		assert!(parse_msg(&with_checksum(tampered), &sk_kyber, None, &pfs_key, &pfs_salt).is_err());
+	}
+	
+	// bytes that only look like a header are taken for a message without one, so a legacy ciphertext that happens to start
+	// with the magic is still accepted
+	let mut unknown_version = ciphertext.clone();
+	unknown_version[3] = 2;
+	assert_eq!(read_routing_header(&unknown_version).unwrap(), None);
+	assert!(parse_msg(&unknown_version, &sk_kyber, None, &pfs_key, &pfs_salt).is_err());
+	for legacy in [&b"DWR"[..], b"DWR\x01", b"DWR\x01\x09\x10\x10", b"DWR\x01\x01\x40\x10", b"DWR\x01\x01\x10\x00"] {
+		assert_eq!(read_routing_header(&[legacy, &[42; 64]].concat()).unwrap(), None);
+	}
+	
+	// the message detail code isn't tied to one length
+	let header = RoutingHeader { version: 1, class: RoutingClass::Chat, signed: false, escrowed: false, thread: false, mdc: encode([42; 40]), counter: Some(7) };
+	let envelope = routing::attach_routing_header(&header, b"ciphertext").unwrap();
+	assert_eq!(routing::split_routing_header(&envelope).unwrap(), (Some(header.clone()), &b"ciphertext"[..]));
+	assert!(routing::attach_routing_header(&RoutingHeader { mdc: encode([42; 256]), ..header.clone() }, b"ciphertext").is_err());
+	assert!(routing::attach_routing_header(&RoutingHeader { mdc: String::new(), ..header }, b"ciphertext").is_err());
+	
+	// damage in transport is told apart from messages that fail to decrypt
+	let mut damaged = ciphertext.clone();
+	if let Some(byte) = damaged.last_mut() { *byte ^= 1; }
+	assert_eq!(read_routing_header(&damaged), Err(DawnError::Corrupted));
+	assert_eq!(parse_msg(&damaged, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap_err(), DawnError::Corrupted);
+	assert!(matches!(parse_msg(&with_checksum(damaged), &sk_kyber, None, &pfs_key, &pfs_salt), Err(DawnError::Crypto(_))));
+	
+	// messages of clients without checksum or routing header are still accepted
+	let mut without_checksum = [&plain[..header_len - 4], &plain[header_len..]].concat();
+	without_checksum[5] &= !16;
+	assert!(parse_msg(&without_checksum, &sk_kyber, None, &pfs_key, &pfs_salt).is_ok());
+	assert_eq!(read_routing_header(&plain[header_len..]).unwrap(), None);
+	assert!(parse_msg(&plain[header_len..], &sk_kyber, None, &pfs_key, &pfs_salt).is_ok());
+}
+
+#[test]
+fn test_dawn_error() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (pubkey_sig, _) = sign_keygen();
+	let (_, _, ciphertext) = send_msg((content_type::TEXT, Some("hi"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	
+	// the variant tells what went wrong, Display keeps the strings of earlier versions
+	let err = parse_msg(&ciphertext, &sk_kyber, Some(&pubkey_sig), &pfs_key, &pfs_salt).unwrap_err();
+	assert_eq!(err, DawnError::SignatureWarning(Warning::Unsigned));
+	assert_eq!(err.to_string(), "@dawn-stdlib: CRITICAL: signature verification was requested, but the remote side did not provide a signature");
+	assert!(matches!(parse_msg(&ciphertext, &sk_kyber, None, &sym_key_gen(), &pfs_salt), Err(DawnError::Crypto(_))));
+	assert!(matches!(parse_delta_sync(b"{"), Err(DawnError::Serialization(_))));
+	let err = send_msg((content_type::TEXT, None, None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap_err();
+	assert_eq!(err, DawnError::InvalidInput("no text was provided".to_string()));
+	assert_eq!(err.message(), "no text was provided");
+	
+	// usable as a standard error
+	let boxed: Box<dyn std::error::Error> = Box::new(err);
+	assert_eq!(boxed.to_string(), "@dawn-stdlib: no text was provided");
+}
+
+#[test]
+fn test_mdc_binding() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, mdc, ciphertext) = send_msg((content_type::TEXT, Some("hi"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	
+	// messages are accepted under the message detail code they were sent with
+	let (received, _, parsed_mdc, _) = parse_msg_for_mdc(&ciphertext, &mdc, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(received, ReceivedMessage::Text { text: "hi".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert_eq!(parsed_mdc, mdc);
+	
+	// a message moved to another conversation by the server is rejected, with or without routing header
+	let other_mdc = mdc_gen();
+	let expected = Err(DawnError::MdcMismatch { expected: other_mdc.clone(), received: mdc.clone() });
+	assert_eq!(parse_msg_for_mdc(&ciphertext, &other_mdc, &sk_kyber, None, &pfs_key, &pfs_salt), expected);
+	assert_eq!(parse_msg_for_mdc(&ciphertext[routing_header_len(&ciphertext)..], &other_mdc, &sk_kyber, None, &pfs_key, &pfs_salt), expected);
+	assert!(parse_msg_for_mdc(&ciphertext[routing_header_len(&ciphertext)..], &mdc, &sk_kyber, None, &pfs_key, &pfs_salt).is_ok());
+}
+
+#[test]
+fn test_content_type() {
+	// wire values are unchanged
+	for (content_type, byte) in [(content_type::INTERNAL, 0), (content_type::TEXT, 1), (content_type::PICTURE, 3), (content_type::LINKED_MEDIA, 200), (content_type::GATEWAY, 203)] {
+		assert_eq!(u8::from(content_type), byte);
+		assert_eq!(ContentType::try_from(byte), Ok(content_type));
+	}
+	for content_type in content_type::SUPPORTED {
+		assert_eq!(ContentType::try_from(u8::from(content_type)), Ok(content_type));
+	}
+	assert_eq!(ContentType::try_from(77), Err(DawnError::InvalidInput("unknown content type 77".to_string())));
+	
+	// serialized as the byte
+	assert_eq!(serde_json::to_string(&content_type::VOICE).unwrap(), "2");
+	assert_eq!(serde_json::from_str::<ContentType>("201").unwrap(), content_type::HISTORY_SYNC);
+	assert!(serde_json::from_str::<ContentType>("77").is_err());
+}
+
+#[test]
+fn test_fragmentation() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, _, ciphertext) = send_msg((content_type::TEXT, Some("sent over a tiny transport"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	
+	let fragments = fragment_msg(&ciphertext, 140).unwrap();
+	assert!(fragments.len() > 1);
+	assert!(fragments.iter().all(|fragment| fragment.len() <= 140 && is_fragment(fragment)));
+	assert!(!is_fragment(&ciphertext));
+	assert_eq!(fragment_msg(&ciphertext, 140).unwrap(), fragments);
+	assert!(fragment_msg(&ciphertext, FRAGMENT_HEADER_LEN).is_err());
+	
+	// fragments can arrive in any order and more than once
+	let mut reassembler = FragmentReassembler::new();
+	let (last, rest) = fragments.split_last().unwrap();
+	for fragment in rest.iter().rev() {
+		assert_eq!(reassembler.add_fragment(fragment, 1000).unwrap(), None);
+		assert_eq!(reassembler.add_fragment(fragment, 1001).unwrap(), None);
+	}
+	assert_eq!(reassembler.pending(), 1);
+	let reassembled = reassembler.add_fragment(last, 1002).unwrap().unwrap();
+	assert_eq!(reassembled, ciphertext);
+	assert_eq!(reassembler.pending(), 0);
+	let (received, _, _, _) = parse_msg(&reassembled, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	let (_, text, _) = received.into_content();
+	assert_eq!(text.as_deref(), Some("sent over a tiny transport"));
+	
+	// incomplete messages time out
+	let mut reassembler = FragmentReassembler::new().timeout(60);
+	assert_eq!(reassembler.add_fragment(&fragments[0], 1000).unwrap(), None);
+	assert_eq!(reassembler.expire(1059), 0);
+	assert_eq!(reassembler.expire(1060), 1);
+	for fragment in &fragments[1..] {
+		assert_eq!(reassembler.add_fragment(fragment, 1100).unwrap(), None);
+	}
+	
+	// tampered fragments are rejected
+	let mut reassembler = FragmentReassembler::new();
+	let mut tampered = fragments.clone();
+	*tampered[0].last_mut().unwrap() ^= 1;
+	for fragment in &tampered[..tampered.len() - 1] {
+		reassembler.add_fragment(fragment, 1000).unwrap();
+	}
+	assert!(reassembler.add_fragment(tampered.last().unwrap(), 1000).is_err());
+	let mut wrong_count = fragments[0].clone();
+	wrong_count[14] ^= 1;
+	assert!(reassembler.add_fragment(&fragments[0], 1000).unwrap().is_none());
+	assert!(reassembler.add_fragment(&wrong_count, 1000).is_err());
+	assert!(reassembler.add_fragment(&ciphertext, 1000).is_err());
+	
+	// the number of incomplete messages and the bytes buffered per message are limited
+	let mut reassembler = FragmentReassembler::new().max_pending(2);
+	for text in ["first", "second", "third"] {
+		let (_, _, ciphertext) = send_msg((content_type::TEXT, Some(text), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+		let result = reassembler.add_fragment(&fragment_msg(&ciphertext, 140).unwrap()[0], 1000);
+		assert_eq!(result.is_ok(), text != "third");
+	}
+	assert_eq!(reassembler.pending(), 2);
+	let mut reassembler = FragmentReassembler::new().max_bytes(ciphertext.len() - 1);
+	assert!(fragments[..fragments.len() - 1].iter().all(|fragment| reassembler.add_fragment(fragment, 1000).unwrap().is_none()));
+	assert!(reassembler.add_fragment(fragments.last().unwrap(), 1000).is_err());
+	assert_eq!(reassembler.pending(), 0);
+	let mut reassembler = FragmentReassembler::new().max_bytes(ciphertext.len());
+	assert!(fragments.iter().filter_map(|fragment| reassembler.add_fragment(fragment, 1000).unwrap()).eq([ciphertext.clone()]));
+}
+
+#[test]
+fn test_clock() {
+	let clock = ManualClock::new(1000);
+	let shared = clock.clone();
+	assert_eq!(clock.now(), 1000);
+	clock.advance(30);
+	assert_eq!(shared.now(), 1030);
+	shared.set(5);
+	assert_eq!(clock.now(), 5);
+	clock.advance(u64::MAX);
+	assert_eq!(clock.now(), u64::MAX);
+	
+	// usable behind a trait object
+	let clocks: Vec<Box<dyn Clock>> = vec![Box::new(SystemClock), Box::new(ManualClock::new(42))];
+	assert!(clocks[0].now() >= get_current_timestamp() - 1);
+	assert_eq!(clocks[1].now(), 42);
+}
+
+#[test]
+fn test_received_message() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let roundtrip = |content: (ContentType, Option<&str>, Option<&[u8]>)| {
+		let (_, _, ciphertext) = send_msg(content, &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+		parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap().0
+	};
+	
+	// linked media arrives with its fields split up
+	let linked_data = gen_linked_media_data(content_type::VOICE.into(), Some(1_700_000_000), Some(&[9, 9]));
+	let received = roundtrip((content_type::LINKED_MEDIA, Some("https://media.example/f\nkey\nfirst line\nsecond line"), Some(&linked_data)));
+	assert_eq!(received, ReceivedMessage::LinkedMedia {
+		link: "https://media.example/f".to_string(),
+		key: "key".to_string(),
+		description: "first line\nsecond line".to_string(),
+		media_type: content_type::VOICE.into(),
+		expires_at: Some(1_700_000_000),
+		delete_token: Some(vec![9, 9]),
+	});
+	assert_eq!(received.content_type(), content_type::LINKED_MEDIA);
+	assert_eq!(received.into_content(), (content_type::LINKED_MEDIA, Some("https://media.example/f\nkey\nfirst line\nsecond line".to_string()), Some(linked_data)));
+	
+	// events come with their code and decoded data
+	let received = roundtrip((content_type::INTERNAL, Some(&event::PRESENCE.to_string()), Some(&[1, 2, 3])));
+	assert_eq!(received, ReceivedMessage::Internal { event: event::PRESENCE, data: vec![1, 2, 3] });
+	
+	// history chunks can be fed into the reassembler as they are
+	let chunks = gen_history_chunks(&[HistoryEntry { id: id_gen(), sent: true, timestamp: 1, content_type: content_type::TEXT, text: Some("old".to_string()), data: None }], 1024).unwrap();
+	let (header, chunk) = &chunks[0];
+	let received = roundtrip((content_type::HISTORY_SYNC, Some(header), Some(chunk)));
+	assert!(matches!(&received, ReceivedMessage::HistorySync { chunk_index: 0, chunk_count: 1, chunk: received_chunk, .. } if received_chunk == chunk));
+	let (_, received_header, received_chunk) = received.into_content();
+	assert_eq!(HistoryReassembler::new().add_chunk(&received_header.unwrap(), &received_chunk.unwrap()).unwrap().unwrap()[0].text, Some("old".to_string()));
+	
+	// the context variant keeps returning the flat form
+	let mut context = Context::new();
+	let (_, _, ciphertext) = send_msg((content_type::PICTURE, Some("desc"), Some(&[5; 64])), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let ((recv_content_type, recv_text, recv_data), _, _, _) = parse_msg_with_context(&mut context, &ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt, &ParseLimits::default()).unwrap();
+	assert_eq!((recv_content_type, recv_text.as_deref(), recv_data), (content_type::PICTURE, Some("desc"), Some(&[5; 64][..])));
+}
+
+#[test]
+fn test_domain_separation() {
+	assert!(check_domain_separation(domain_labels()).is_ok());
+	assert!(domain_labels().iter().any(|label| label.label == "dawn-media-key" && label.kind == LabelKind::Derivation));
+	
+	// clients can add the domains of their own documents
+	let mut labels = domain_labels().to_vec();
+	labels.push(DomainLabel { label: "dawn-document:contact-card", kind: LabelKind::Signature, purpose: "contact card" });
+	assert!(check_domain_separation(&labels).is_ok());
+	labels.push(DomainLabel { label: "dawn-abuse-report", kind: LabelKind::Signature, purpose: "reused label" });
+	assert!(check_domain_separation(&labels).is_err());
+	
+	// derivation labels must not be prefixes of each other, signature labels are terminated
+	let signatures = [
+		DomainLabel { label: "dawn-a", kind: LabelKind::Signature, purpose: "a" },
+		DomainLabel { label: "dawn-ab", kind: LabelKind::Signature, purpose: "ab" },
+	];
+	assert!(check_domain_separation(&signatures).is_ok());
+	let derivations = [
+		DomainLabel { label: "dawn-a", kind: LabelKind::Derivation, purpose: "a" },
+		DomainLabel { label: "dawn-ab", kind: LabelKind::Derivation, purpose: "ab" },
+	];
+	assert!(check_domain_separation(&derivations).is_err());
+	assert!(check_domain_separation(&[DomainLabel { label: "", kind: LabelKind::Signature, purpose: "empty" }]).is_err());
+}
+
+#[test]
+fn test_outgoing_message() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let roundtrip = |message: &OutgoingMessage| {
+		let (_, _, ciphertext) = send_msg(message.content(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+		parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap().0
+	};
+	
+	assert_eq!(OutgoingMessage::text("hello").content(), (content_type::TEXT, Some("hello"), None));
+	assert_eq!(roundtrip(&OutgoingMessage::picture(&[1, 2], "beach")), ReceivedMessage::Picture { data: vec![1, 2], description: "beach".to_string(), alt_text: None, effect: None, in_reply_to: None, codec: None, expires_after: None });
+	assert_eq!(roundtrip(&OutgoingMessage::internal(event::PRESENCE, &[0, 1])), ReceivedMessage::Internal { event: event::PRESENCE, data: vec![0, 1] });
+	
+	// linked media is packed without the caller knowing the layout
+	let message = OutgoingMessage::linked_media("https://media.example/f", "key", "two\nlines", content_type::VOICE, None, Some(&[4])).unwrap();
+	assert_eq!(message.content_type(), content_type::LINKED_MEDIA);
+	assert_eq!(roundtrip(&message), ReceivedMessage::LinkedMedia {
+		link: "https://media.example/f".to_string(),
+		key: "key".to_string(),
+		description: "two\nlines".to_string(),
+		media_type: content_type::VOICE.into(),
+		expires_at: None,
+		delete_token: Some(vec![4]),
+	});
+	assert!(OutgoingMessage::linked_media("", "key", "", content_type::PICTURE, None, None).is_err());
+	assert!(OutgoingMessage::linked_media("https://media.example/f\nx", "key", "", content_type::PICTURE, None, None).is_err());
+	
+	// the other send paths take the same content
+	assert!(validate_outgoing(OutgoingMessage::voice(&[7; 10]).content(), &OutgoingRules::new()).is_empty());
+	assert!(estimate_ciphertext_len(OutgoingMessage::text("hello").content(), false).is_ok());
+}
+
+#[test]
+fn test_client_keys() {
+	// RFC 5869 test case 1 (first block)
+	let okm = kdf::hkdf_sha256(&decode("000102030405060708090a0b0c").unwrap(), &[0x0b; 22], &decode("f0f1f2f3f4f5f6f7f8f9").unwrap());
+	assert_eq!(encode(okm), "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf");
+	
+	let pfs_salt = sym_key_gen();
+	let id = id_gen();
+	let search_key = derive_client_key(&pfs_salt, &id, ClientKeyPurpose::SearchIndex).unwrap();
+	assert_eq!(search_key.len(), CLIENT_KEY_LEN);
+	assert_eq!(derive_client_key(&pfs_salt, &id, ClientKeyPurpose::SearchIndex).unwrap(), search_key);
+	
+	// every purpose and conversation gets its own key
+	assert_ne!(derive_client_key(&pfs_salt, &id, ClientKeyPurpose::Drafts).unwrap(), search_key);
+	assert_ne!(derive_client_key(&pfs_salt, &id, ClientKeyPurpose::ThumbnailCache).unwrap(), search_key);
+	assert_ne!(derive_client_key(&pfs_salt, &id_gen(), ClientKeyPurpose::SearchIndex).unwrap(), search_key);
+	assert_ne!(derive_client_key(&sym_key_gen(), &id, ClientKeyPurpose::SearchIndex).unwrap(), search_key);
+	assert!(derive_client_key(&[], &id, ClientKeyPurpose::Drafts).is_err());
+	assert!(derive_client_key(&pfs_salt, "", ClientKeyPurpose::Drafts).is_err());
+}
+
+#[test]
+fn test_session() {
+	let alice = Identity::generate().unwrap();
+	let bob = Identity::generate().unwrap();
+	let request = alice.init_request_builder().handle(bob.gen_handle("bob", &mdc_gen())).unwrap().name("alice").build().unwrap();
+	let parsed_request = bob.parse_init_request(&request.ciphertext).unwrap();
+	let (mut bob_session, _, accept) = Session::accept(&bob, &parsed_request).unwrap();
+	let response = parse_init_response(&accept, &request.own_seckey_kyber, None, &request.remote_pfs_key, &request.pfs_salt, "bob").unwrap();
+	let mut alice_session = Session::from_init_response(&alice, &request, &response).unwrap().clock(ManualClock::new(1000));
+	assert_eq!(alice_session.id(), bob_session.id());
+	assert_eq!(alice_session.peer().pubkey_sig, bob.pubkey_sig);
+	assert_eq!(alice_session.now(), 1000);
+	
+	// the ratchet advances in both directions without the caller handling any keys
+	let mut mdcs = Vec::new();
+	for text in ["hello", "how are you?"] {
+		let (mdc, ciphertext) = alice_session.send(&OutgoingMessage::text(text)).unwrap();
+		let (received, received_mdc, warning) = bob_session.receive(&ciphertext).unwrap().unwrap();
+		assert_eq!(received, ReceivedMessage::Text { text: text.to_string(), effect: None, in_reply_to: None, expires_after: None });
+		assert_eq!(received_mdc, mdc);
+		assert_eq!(warning, Warning::None);
+		mdcs.push(mdc);
+	}
+	let (mdc, ciphertext) = bob_session.send(&OutgoingMessage::voice(&[1, 2, 3])).unwrap();
+	
+	// every message has its own message detail code, the id advances with every sent and received message on both sides
+	assert_ne!(mdcs[0], mdcs[1]);
+	assert!(!mdcs.contains(&mdc));
+	assert_eq!(mdc, predictable_mdc_gen(&request.mdc_seed, &get_next_id(&get_next_id(&request.id, &request.id_salt).unwrap(), &request.id_salt).unwrap()));
+	assert_eq!(alice_session.id(), request.id);
+	let mut archive = alice_session.passive();
+	assert_eq!(alice_session.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Voice { data: vec![1, 2, 3], transcription: None, in_reply_to: None, codec: None, expires_after: None });
+	assert_eq!(archive.parse(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Voice { data: vec![1, 2, 3], transcription: None, in_reply_to: None, codec: None, expires_after: None });
+	
+	// a failed receive leaves the session usable
+	let (_, ciphertext) = bob_session.send(&OutgoingMessage::text("still there")).unwrap();
+	let mut tampered = ciphertext.clone();
+	if let Some(byte) = tampered.last_mut() { *byte ^= 1; }
+	assert!(alice_session.receive(&tampered).is_err());
+	assert!(alice_session.receive(&ciphertext).is_ok());
+	assert!(alice_session.receive(&ciphertext).is_err());
+	
+	// both sides derive the same keys for local data
+	assert_eq!(alice_session.derive_client_key(ClientKeyPurpose::Drafts).unwrap(), bob_session.derive_client_key(ClientKeyPurpose::Drafts).unwrap());
+}
+
+#[test]
+fn test_analyze_handle() {
+	let bob = Identity::generate().unwrap();
+	let handle = bob.gen_handle("bob", &mdc_gen());
+	let report = analyze_handle(&handle);
+	assert!(report.is_valid());
+	assert_eq!(report.version, Some(1));
+	assert!(report.keys.iter().all(|key| key.valid));
+	assert_eq!(report.name, Some("bob".to_string()));
+	assert!(!report.signature_present);
+	assert_eq!(report.expires_at, None);
+	assert_eq!(report.len, handle.len());
+	
+	assert_eq!(estimate_qr_version(17), Some(1));
+	assert_eq!(estimate_qr_version(18), Some(2));
+	assert_eq!(estimate_qr_version(2953), Some(40));
+	assert_eq!(estimate_qr_version(2954), None);
+	
+	// a truncated handle reports what is missing instead of failing
+	let report = analyze_handle(&handle[..handle.len() / 2]);
+	assert!(!report.is_valid());
+	assert_eq!(report.version, None);
+	assert!(report.keys[0].valid);
+	assert!(!report.keys[4].valid);
+	assert_eq!(report.name, None);
+	
+	// a damaged key is pointed out
+	let mut damaged = handle.clone();
+	damaged[10] = b'x';
+	let report = analyze_handle(&damaged);
+	assert_eq!(report.version, Some(1));
+	assert_eq!(report.keys[0].len, None);
+	assert!(report.keys[1..].iter().all(|key| key.valid));
+	assert_eq!(report.problems.len(), 1);
+	assert!(!analyze_handle(&[0xff, 0xfe]).is_valid());
+}
+
+#[test]
+fn test_session_export() {
+	// RFC 7914 test vector for PBKDF2-HMAC-SHA256 (first block)
+	assert_eq!(encode(kdf::pbkdf2_sha256(b"passwd", b"salt", 1)), "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc");
+	
+	let alice = Identity::generate().unwrap();
+	let bob = Identity::generate().unwrap();
+	let request = alice.init_request_builder().handle(bob.gen_handle("bob", &mdc_gen())).unwrap().name("alice").build().unwrap();
+	let (mut bob_session, _, accept) = Session::accept(&bob, &bob.parse_init_request(&request.ciphertext).unwrap()).unwrap();
+	let response = parse_init_response(&accept, &request.own_seckey_kyber, None, &request.remote_pfs_key, &request.pfs_salt, "bob").unwrap();
+	let mut alice_session = Session::from_init_response(&alice, &request, &response).unwrap();
+	let (_, ciphertext) = alice_session.send(&OutgoingMessage::text("before the restart")).unwrap();
+	bob_session.receive(&ciphertext).unwrap().unwrap();
+	assert_eq!(alice_session.bandwidth().sent(content_type::TEXT), ciphertext.len() as u64);
+	assert_eq!(bob_session.bandwidth().received(content_type::TEXT), ciphertext.len() as u64);
+	
+	// the restored session continues where the exported one stopped
+	// (with fewer iterations than export uses, which takes seconds in unoptimized builds)
+	let blob = alice_session.export_with_iterations("correct horse battery staple", 1000).unwrap();
+	assert!(Session::import(&blob, "wrong passphrase").is_err());
+	let mut restored = Session::import(&blob, "correct horse battery staple").unwrap();
+	assert_eq!(restored.id(), alice_session.id());
+	assert_eq!(restored.peer(), alice_session.peer());
+	assert_eq!(restored.bandwidth(), alice_session.bandwidth());
+	let (mdc, ciphertext) = restored.send(&OutgoingMessage::text("after the restart")).unwrap();
+	assert_eq!(bob_session.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Text { text: "after the restart".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert_eq!(alice_session.send(&OutgoingMessage::text("after the restart")).unwrap().0, mdc);
+	
+	// damaged blobs and unknown versions are rejected
+	let mut damaged = blob.clone();
+	if let Some(byte) = damaged.last_mut() { *byte ^= 1; }
+	assert!(Session::import(&damaged, "correct horse battery staple").is_err());
+	let mut future = blob.clone();
+	future[0] = 2;
+	assert!(Session::import(&future, "correct horse battery staple").is_err());
+	assert!(Session::import(&blob[..10], "correct horse battery staple").is_err());
+}
+
+#[test]
+fn test_cloned_session() {
+	let (mut alice_session, mut bob_session) = gen_session_pair();
+	let (_, ciphertext) = alice_session.send(&OutgoingMessage::text("before the backup")).unwrap();
+	bob_session.receive(&ciphertext).unwrap().unwrap();
+	
+	// the backup is restored onto two devices, both continue the conversation with the same chain
+	let blob = alice_session.export_with_iterations("passphrase", 1000).unwrap();
+	let mut phone = Session::import(&blob, "passphrase").unwrap();
+	let mut tablet = Session::import(&blob, "passphrase").unwrap();
+	let (_, from_phone) = phone.send(&OutgoingMessage::text("from the phone")).unwrap();
+	let (_, from_tablet) = tablet.send(&OutgoingMessage::text("from the tablet")).unwrap();
+	assert_eq!(bob_session.receive(&from_phone).unwrap().unwrap().0, ReceivedMessage::Text { text: "from the phone".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	let err = bob_session.receive(&from_tablet).unwrap_err();
+	assert!(matches!(err, DawnError::Security(SecurityEvent::ClonedSession { counter: 2, last_counter: 2, .. })));
+	
+	// a replayed message is rejected without reporting a clone
+	assert!(!matches!(bob_session.receive(&from_phone).unwrap_err(), DawnError::Security(_)));
+	
+	// the counters are persisted with the session of the receiver
+	let mut restored = Session::import(&bob_session.export_with_iterations("passphrase", 1000).unwrap(), "passphrase").unwrap();
+	assert!(matches!(restored.receive(&from_tablet).unwrap_err(), DawnError::Security(SecurityEvent::ClonedSession { .. })));
+	let (_, ciphertext) = phone.send(&OutgoingMessage::text("still the phone")).unwrap();
+	assert!(restored.receive(&ciphertext).unwrap().is_some());
+}
+
+#[cfg(feature = "session-serde")]
+#[test]
+fn test_session_serde() {
+	let (pk_kyber, sk_kyber) = gen_kyber_keypair().unwrap();
+	let (pk_sig, sk_sig) = gen_sign_keypair().unwrap();
+	let peer = Peer { pubkey_kyber: pk_kyber, pubkey_sig: pk_sig, name: "bob".to_string(), verification: Verification::Verified, capabilities: vec![] };
+	let pfs_key = sym_key_gen();
+	let mut session = Session::new(&id_gen(), &sym_key_gen(), &sym_key_gen(), &mdc_gen(), sk_kyber, peer, &pfs_key, &pfs_key).sign_with(sk_sig);
+	let json = serde_json::to_string(&session).unwrap();
+	let mut restored: Session = serde_json::from_str(&json).unwrap();
+	assert_eq!(restored.peer(), session.peer());
+	let (_, ciphertext) = session.send(&OutgoingMessage::text("hello")).unwrap();
+	assert_eq!(restored.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Text { text: "hello".to_string(), effect: None, in_reply_to: None, expires_after: None });
+}
+
+#[test]
+fn test_peek_init_request() {
+	let alice = Identity::generate().unwrap();
+	let bob = Identity::generate().unwrap();
+	let request = alice.init_request_builder().handle(bob.gen_handle("bob", &mdc_gen())).unwrap().name("alice").comment("we met at the conference").build().unwrap();
+	
+	let preview = bob.peek_init_request(&request.ciphertext).unwrap();
+	assert_eq!(preview.name, "alice");
+	assert_eq!(preview.comment, "we met at the conference");
+	
+	// the fingerprint belongs to the identity, not to the conversation
+	let other_request = alice.init_request_builder().handle(bob.gen_handle("bob", &mdc_gen())).unwrap().name("alice").build().unwrap();
+	assert_eq!(bob.peek_init_request(&other_request.ciphertext).unwrap().fingerprint, preview.fingerprint);
+	let mallory_request = Identity::generate().unwrap().init_request_builder().handle(bob.gen_handle("bob", &mdc_gen())).unwrap().name("alice").build().unwrap();
+	assert_ne!(bob.peek_init_request(&mallory_request.ciphertext).unwrap().fingerprint, preview.fingerprint);
+	
+	// peeking doesn't prevent accepting the same request later
+	let parsed = bob.parse_init_request(&request.ciphertext).unwrap();
+	assert_eq!(parsed.peer.fingerprint(), preview.fingerprint);
+	assert!(Session::accept(&bob, &parsed).is_ok());
+	assert!(Identity::generate().unwrap().peek_init_request(&request.ciphertext).is_err());
+}
+
+// keys of one direction of a conversation without a handshake, the same pfs key sends and parses a message
+struct TestConversation {
+	pk_kyber: Vec<u8>,
+	sk_kyber: Vec<u8>,
+	pfs_key: Vec<u8>,
+	pfs_salt: Vec<u8>,
+	id: String,
+	mdc_seed: String,
+}
+
+fn gen_test_conversation() -> TestConversation {
+	let (pk_kyber, sk_kyber) = kyber_keygen();
+	TestConversation { pk_kyber, sk_kyber, pfs_key: sym_key_gen(), pfs_salt: sym_key_gen(), id: id_gen(), mdc_seed: mdc_gen() }
+}
+
+impl TestConversation {
+	fn send(&self, message: &OutgoingMessage, options: &SendOptions) -> Result<(Vec<u8>, String, Vec<u8>), DawnError> {
+		send_msg_with_options(message, options, &self.pk_kyber, None, &self.pfs_key, &self.pfs_salt, &self.id, &self.mdc_seed)
+	}
+	
+	fn parse(&self, ciphertext: &[u8], config: &ProtocolConfig) -> Result<ParsedMessage, DawnError> {
+		parse_msg_with_config(ciphertext, &self.sk_kyber, None, &self.pfs_key, &self.pfs_salt, config)
+	}
+}
+
+// sessions of both sides of a new conversation between two identities
+fn gen_session_pair() -> (Session, Session) {
+	let alice = Identity::generate().unwrap();
+	let bob = Identity::generate().unwrap();
+	let request = alice.init_request_builder().handle(bob.gen_handle("bob", &mdc_gen())).unwrap().name("alice").build().unwrap();
+	let (bob_session, _, accept) = Session::accept(&bob, &bob.parse_init_request(&request.ciphertext).unwrap()).unwrap();
+	let response = parse_init_response(&accept, &request.own_seckey_kyber, None, &request.remote_pfs_key, &request.pfs_salt, "bob").unwrap();
+	(Session::from_init_response(&alice, &request, &response).unwrap(), bob_session)
+}
+
+// behaviour every session store has to implement
+fn check_session_store<S: SessionStore>(store: &mut S) {
+	let (alice_session, mut bob_session) = gen_session_pair();
+	assert!(store.get(alice_session.id()).unwrap().is_none());
+	store.put(&alice_session).unwrap();
+	
+	// every message is sent with a freshly loaded session, which is saved again afterwards
+	for text in ["first", "second"] {
+		let mut session = store.get(alice_session.id()).unwrap().unwrap();
+		let (_, ciphertext) = session.send(&OutgoingMessage::text(text)).unwrap();
+		store.put(&session).unwrap();
+		assert_eq!(bob_session.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Text { text: text.to_string(), effect: None, in_reply_to: None, expires_after: None });
+	}
+	
+	store.delete(alice_session.id()).unwrap();
+	assert!(store.get(alice_session.id()).unwrap().is_none());
+	store.delete(alice_session.id()).unwrap();
+}
+
+#[test]
+fn test_session_store() {
+	check_session_store(&mut MemorySessionStore::new());
+	let mut store = StorageSessionStore::new(MemoryStorage::new());
+	check_session_store(&mut store);
+	assert!(store.ids().unwrap().is_empty());
+	assert!(store.into_inner().list(NAMESPACE_SESSIONS).unwrap().is_empty());
+}
+
+#[test]
+fn test_conversation_close() {
+	let (mut alice_session, mut bob_session) = gen_session_pair();
+	assert!(bob_session.acknowledge_close().is_err());
+	
+	// after asking to close, alice can't send anymore
+	let (_, close_request) = alice_session.close().unwrap();
+	assert!(matches!(alice_session.status(), SessionStatus::Closing(_)));
+	assert!(alice_session.send(&OutgoingMessage::text("one more thing")).is_err());
+	assert!(alice_session.close().is_err());
+	
+	// bob learns about the close with the message and can only acknowledge it
+	let (received, _, _) = bob_session.receive(&close_request).unwrap().unwrap();
+	assert_eq!(received.content_type(), content_type::INTERNAL);
+	assert_eq!(bob_session.status(), &SessionStatus::Closed);
+	assert!(bob_session.send(&OutgoingMessage::text("wait")).is_err());
+	let (_, close_ack) = bob_session.acknowledge_close().unwrap();
+	assert!(bob_session.acknowledge_close().is_err());
+	alice_session.receive(&close_ack).unwrap().unwrap();
+	assert_eq!(alice_session.status(), &SessionStatus::Closed);
+	
+	// close messages are bound to the conversation and the peer
+	let (pk_sig, sk_sig) = gen_sign_keypair().unwrap();
+	let id = id_gen();
+	let close = ConversationClose::Request(id_gen());
+	let data = gen_conversation_close(&id, &close, sk_sig.as_bytes()).unwrap();
+	assert_eq!(parse_conversation_close(&data, &id, pk_sig.as_bytes()).unwrap(), close);
+	assert!(parse_conversation_close(&data, &id_gen(), pk_sig.as_bytes()).is_err());
+	assert!(parse_conversation_close(&data, &id, gen_sign_keypair().unwrap().0.as_bytes()).is_err());
+	
+	// the handshake survives storing the session in between
+	let (mut alice_session, mut bob_session) = gen_session_pair();
+	let mut store = MemorySessionStore::new();
+	let (_, close_request) = bob_session.close().unwrap();
+	store.put(&bob_session).unwrap();
+	alice_session.receive(&close_request).unwrap().unwrap();
+	let (_, close_ack) = alice_session.acknowledge_close().unwrap();
+	let mut bob_session = store.get(bob_session.id()).unwrap().unwrap();
+	assert!(matches!(bob_session.status(), SessionStatus::Closing(_)));
+	bob_session.receive(&close_ack).unwrap().unwrap();
+	assert_eq!(bob_session.status(), &SessionStatus::Closed);
+}
+
+#[test]
+fn test_held_chain_messages() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let config = ProtocolConfig::default();
+	let options = SendOptions::new();
+	let mut send_chain = SendChain::new(&pfs_key);
+	let mut recv_chain = ReceiveChain::new(&pfs_key).max_skipped(3);
+	let mut ciphertexts = Vec::new();
+	for text in ["zero", "one", "two", "three", "four"] {
+		ciphertexts.push(send_chain_msg(&mut send_chain, &OutgoingMessage::text(text), &options, &pk_kyber, None, &pfs_salt, &id, &mdc_seed).unwrap().1);
+	}
+	assert_eq!(send_chain.counter(), 5);
+	
+	// messages 2 and 1 arrive first and are held until message 0 arrived
+	assert!(parse_chain_msg(&mut recv_chain, &ciphertexts[2], &sk_kyber, None, &pfs_salt, &config).unwrap().is_none());
+	assert!(parse_chain_msg(&mut recv_chain, &ciphertexts[2], &sk_kyber, None, &pfs_salt, &config).is_err());
+	assert_eq!(recv_chain.skipped(), vec![0, 1]);
+	assert!(parse_chain_msg(&mut recv_chain, &ciphertexts[1], &sk_kyber, None, &pfs_salt, &config).unwrap().is_none());
+	assert_eq!(recv_chain.held(), vec![1, 2]);
+	assert_eq!(recv_chain.skipped(), vec![0]);
+	assert!(parse_held_chain_msg(&mut recv_chain, &sk_kyber, None, &pfs_salt, &config).unwrap().is_none());
+	let (received, _, _) = parse_chain_msg(&mut recv_chain, &ciphertexts[0], &sk_kyber, None, &pfs_salt, &config).unwrap().unwrap();
+	assert_eq!(received, ReceivedMessage::Text { text: "zero".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	for text in ["one", "two"] {
+		let (received, _, _) = parse_held_chain_msg(&mut recv_chain, &sk_kyber, None, &pfs_salt, &config).unwrap().unwrap();
+		assert_eq!(received, ReceivedMessage::Text { text: text.to_string(), effect: None, in_reply_to: None, expires_after: None });
+	}
+	assert!(parse_held_chain_msg(&mut recv_chain, &sk_kyber, None, &pfs_salt, &config).unwrap().is_none());
+	assert_eq!(recv_chain.counter(), 3);
+	assert!(recv_chain.held().is_empty());
+	
+	// every message is only decrypted once
+	assert!(parse_chain_msg(&mut recv_chain, &ciphertexts[1], &sk_kyber, None, &pfs_salt, &config).is_err());
+	assert!(parse_chain_msg(&mut recv_chain, &ciphertexts[2], &sk_kyber, None, &pfs_salt, &config).is_err());
+	
+	// chained messages can't be parsed without their chain and linear messages not with one
+	assert!(parse_msg(&ciphertexts[3], &sk_kyber, None, &pfs_key, &pfs_salt).is_err());
+	let (_, _, linear) = send_msg((content_type::TEXT, Some("linear"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	assert!(parse_chain_msg(&mut recv_chain, &linear, &sk_kyber, None, &pfs_salt, &config).is_err());
+	
+	// a forged message with the counter of a missing one is dropped and doesn't keep the real one out
+	let mut forger = SendChain::restore(&sym_key_gen(), 4);
+	let (_, forged) = send_chain_msg(&mut forger, &OutgoingMessage::text("forged"), &options, &pk_kyber, None, &pfs_salt, &id, &mdc_seed).unwrap();
+	assert!(parse_chain_msg(&mut recv_chain, &ciphertexts[4], &sk_kyber, None, &pfs_salt, &config).unwrap().is_none());
+	assert!(parse_chain_msg(&mut recv_chain, &forged, &sk_kyber, None, &pfs_salt, &config).unwrap().is_none());
+	parse_chain_msg(&mut recv_chain, &ciphertexts[3], &sk_kyber, None, &pfs_salt, &config).unwrap().unwrap();
+	let (received, _, _) = parse_held_chain_msg(&mut recv_chain, &sk_kyber, None, &pfs_salt, &config).unwrap().unwrap();
+	assert_eq!(received, ReceivedMessage::Text { text: "four".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert_eq!(recv_chain.counter(), 5);
+	
+	// at most max_skipped messages are held and none that skip more
+	let mut recv_chain = ReceiveChain::new(&pfs_key).max_skipped(2);
+	assert!(parse_chain_msg(&mut recv_chain, &ciphertexts[3], &sk_kyber, None, &pfs_salt, &config).is_err());
+	assert!(parse_chain_msg(&mut recv_chain, &ciphertexts[1], &sk_kyber, None, &pfs_salt, &config).unwrap().is_none());
+	assert!(parse_chain_msg(&mut recv_chain, &ciphertexts[2], &sk_kyber, None, &pfs_salt, &config).unwrap().is_none());
+	assert!(parse_chain_msg(&mut recv_chain, &forged, &sk_kyber, None, &pfs_salt, &config).is_err());
+	assert_eq!(recv_chain.max_skipped(1).held(), vec![1]);
+	
+	// sessions hold messages across export and import
+	let (mut alice_session, bob_session) = gen_session_pair();
+	let mut bob_session = bob_session.max_skipped(10);
+	let mut ciphertexts = Vec::new();
+	for text in ["first", "second", "third"] {
+		ciphertexts.push(alice_session.send(&OutgoingMessage::text(text)).unwrap().1);
+	}
+	assert!(bob_session.receive(&ciphertexts[2]).unwrap().is_none());
+	let mut archive = bob_session.passive();
+	let mut bob_session = Session::import(&bob_session.export_with_iterations("passphrase", 1000).unwrap(), "passphrase").unwrap();
+	assert_eq!(bob_session.skipped(), vec![0, 1]);
+	assert_eq!(bob_session.held(), vec![2]);
+	assert!(bob_session.receive(&ciphertexts[1]).unwrap().is_none());
+	assert_eq!(bob_session.receive(&ciphertexts[0]).unwrap().unwrap().0, ReceivedMessage::Text { text: "first".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert_eq!(bob_session.receive_held().unwrap().unwrap().0, ReceivedMessage::Text { text: "second".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert_eq!(bob_session.receive_held().unwrap().unwrap().0, ReceivedMessage::Text { text: "third".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert!(bob_session.receive_held().unwrap().is_none());
+	// held messages are counted once they were decrypted
+	assert_eq!(bob_session.bandwidth().total_received(), ciphertexts.iter().map(|ciphertext| ciphertext.len() as u64).sum::<u64>());
+	assert!(bob_session.receive(&ciphertexts[0]).is_err());
+	assert!(bob_session.skipped().is_empty());
+	assert_eq!(archive.parse(&ciphertexts[0]).unwrap().unwrap().0, ReceivedMessage::Text { text: "first".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert!(archive.parse_held().unwrap().is_none());
+	assert_eq!(archive.parse(&ciphertexts[1]).unwrap().unwrap().0, ReceivedMessage::Text { text: "second".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert_eq!(archive.parse_held().unwrap().unwrap().0, ReceivedMessage::Text { text: "third".to_string(), effect: None, in_reply_to: None, expires_after: None });
+}
+
+#[test]
+fn test_sequence_numbers() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_salt, id, mdc_seed, .. } = gen_test_conversation();
+	let (pk_sig, sk_sig) = sign_keygen();
+	let mut sender_pfs_key = sym_key_gen();
+	let mut receiver_pfs_key = sender_pfs_key.clone();
+	let mut counter = SequenceCounter::new();
+	let mut tracker = SequenceTracker::new();
+	let mut exchange = |counter: &mut SequenceCounter, tracker: &mut SequenceTracker| {
+		let (new_pfs_key, _, ciphertext) = send_msg_with_options(&OutgoingMessage::text("hello"), &SendOptions::new().sequenced(counter).unwrap(), &pk_kyber, Some(&sk_sig), &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		sender_pfs_key = new_pfs_key;
+		let parsed = parse_msg_with_config(&ciphertext, &sk_kyber, Some(&pk_sig), &receiver_pfs_key, &pfs_salt, &ProtocolConfig::default()).unwrap();
+		receiver_pfs_key = parsed.new_pfs_key.clone();
+		parsed.check_sequence(tracker)
+	};
+	
+	assert_eq!(exchange(&mut counter, &mut tracker), Some((0, SequenceGap::None)));
+	assert_eq!(exchange(&mut counter, &mut tracker), Some((1, SequenceGap::None)));
+	assert_eq!(counter.next_seq(), 2);
+	assert_eq!(tracker.next_seq(), 2);
+	
+	// two messages got lost on the way
+	let mut counter = SequenceCounter::resume(4);
+	assert_eq!(exchange(&mut counter, &mut tracker), Some((4, SequenceGap::MissedMessages(2))));
+	assert_eq!(tracker.next_seq(), 5);
+	
+	// messages sent before the last received one don't move the tracker back
+	let mut late_tracker = SequenceTracker::resume(10);
+	assert_eq!(exchange(&mut counter, &mut late_tracker), Some((5, SequenceGap::Late)));
+	assert_eq!(late_tracker.next_seq(), 10);
+	
+	// messages without sequence number are accepted as before
+	let (_, _, ciphertext) = send_msg((content_type::TEXT, Some("old client"), None), &pk_kyber, Some(&sk_sig), &sender_pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let parsed = parse_msg_with_config(&ciphertext, &sk_kyber, Some(&pk_sig), &receiver_pfs_key, &pfs_salt, &ProtocolConfig::default()).unwrap();
+	assert_eq!(parsed.check_sequence(&mut tracker), None);
+	assert_eq!(tracker.next_seq(), 5);
+}
+
+#[test]
+fn test_profile_policy() {
+	let update = ProfileUpdate::new().name("alice").status("on vacation").avatar(&[1, 2, 3]);
+	let private = ProfilePolicy::new().share_avatar(false).share_status(false);
+	assert_eq!(parse_profile_update(&gen_profile_update(&update, &ProfilePolicy::new()).unwrap().unwrap()).unwrap(), update);
+	assert_eq!(parse_profile_update(&gen_profile_update(&update, &private).unwrap().unwrap()).unwrap(), ProfileUpdate::new().name("alice"));
+	assert_eq!(gen_profile_update(&ProfileUpdate::new().avatar(&[1, 2, 3]), &private).unwrap(), None);
+	assert!(gen_profile_update(&ProfileUpdate::new().name(""), &private).is_err());
+	assert!(parse_profile_update(b"{\"avatar\":\"!\"}").is_err());
+	
+	// the free send helper applies the policy as well
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, _, ciphertext) = send_profile_update(&update, &ProfilePolicy::new().share_avatar(false), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap().unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	let data = match received {
+		ReceivedMessage::Internal { event: event::PROFILE_UPDATE, data } => data,
+		_ => panic!("not a profile update")
+	};
+	assert_eq!(parse_profile_update(&data).unwrap(), ProfileUpdate::new().name("alice").status("on vacation"));
+	
+	// sessions keep the policy of their peer
+	let (mut alice_session, mut bob_session) = gen_session_pair();
+	assert_eq!(alice_session.profile_policy(), ProfilePolicy::default());
+	alice_session.set_profile_policy(ProfilePolicy::new().share_status(false));
+	let mut store = MemorySessionStore::new();
+	store.put(&alice_session).unwrap();
+	let mut alice_session = store.get(alice_session.id()).unwrap().unwrap();
+	assert_eq!(alice_session.profile_policy(), ProfilePolicy::new().share_status(false));
+	let (_, ciphertext) = alice_session.send_profile_update(&update).unwrap().unwrap();
+	let data = match bob_session.receive(&ciphertext).unwrap().unwrap().0 {
+		ReceivedMessage::Internal { event: event::PROFILE_UPDATE, data } => data,
+		_ => panic!("not a profile update")
+	};
+	assert_eq!(parse_profile_update(&data).unwrap(), ProfileUpdate::new().name("alice").avatar(&[1, 2, 3]));
+	assert_eq!(alice_session.send_profile_update(&ProfileUpdate::new().status("busy")).unwrap(), None);
+}
+
+#[test]
+fn test_binary_wire_format() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let (pk_sig, sk_sig) = sign_keygen();
+	let voice = vec![7u8; 3000];
+	let picture = vec![8u8; 64 * 1024]; // longer than the read buffer of the cbor parser
+	let envelope = gen_gateway_envelope(&GatewayOrigin { network: "matrix".to_string(), remote_id: "@bob:example.org".to_string(), timestamp: 1000 }, (content_type::PICTURE, Some("relayed"), Some(&[4, 5]))).unwrap();
+	let cbor = SendOptions::new().format(WireFormat::Cbor);
+	let messages = [
+		OutgoingMessage::text("hello"),
+		OutgoingMessage::voice(&voice),
+		OutgoingMessage::picture(&[1, 2, 3], "a picture"),
+		OutgoingMessage::picture(&picture, "a large picture"),
+		OutgoingMessage::internal(0, b"{}"),
+		OutgoingMessage::gateway(&envelope, Some(&[4, 5])),
+	];
+	for message in &messages {
+		let (json_pfs_key, json_mdc, json) = send_msg(message.content(), &pk_kyber, Some(&sk_sig), &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		let (binary_pfs_key, binary_mdc, binary) = send_msg_with_options(message, &cbor, &pk_kyber, Some(&sk_sig), &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		assert_eq!(binary_mdc, json_mdc);
+		
+		// both formats are parsed by the same functions and give the same message
+		let (json_message, json_new_pfs_key, _, _) = parse_msg(&json, &sk_kyber, Some(&pk_sig), &pfs_key, &pfs_salt).unwrap();
+		let (binary_message, binary_new_pfs_key, mdc, warning) = parse_msg_limited(&binary, &sk_kyber, Some(&pk_sig), &pfs_key, &pfs_salt, &ParseLimits::strict()).unwrap();
+		assert_eq!(binary_message, json_message);
+		assert_eq!(binary_new_pfs_key, binary_pfs_key);
+		assert_eq!(json_new_pfs_key, json_pfs_key);
+		assert_eq!(mdc, json_mdc);
+		assert_eq!(warning, Warning::None);
+	}
+	
+	// binary data is not base64 encoded
+	let (_, _, json) = send_msg((content_type::VOICE, None, Some(&voice)), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (_, _, binary) = send_msg_with_options(&OutgoingMessage::voice(&voice), &cbor, &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	assert!(binary.len() + voice.len() / 4 < json.len());
+	
+	// limits apply to binary fields without base64 overhead
+	let limits = ParseLimits { max_data_len: 2999, ..ParseLimits::default() };
+	assert!(parse_msg_limited(&binary, &sk_kyber, None, &pfs_key, &pfs_salt, &limits).is_err());
+	let limits = ParseLimits { max_data_len: 3000, ..ParseLimits::default() };
+	assert!(parse_msg_limited(&binary, &sk_kyber, None, &pfs_key, &pfs_salt, &limits).is_ok());
+	
+	// the encrypted payload can't be swapped or altered
+	let (_, _, other) = send_msg_with_options(&OutgoingMessage::text("other"), &cbor, &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let mut tampered = binary.clone();
+	if let Some(byte) = tampered.last_mut() { *byte ^= 1; }
+	assert!(parse_msg(&tampered, &sk_kyber, None, &pfs_key, &pfs_salt).is_err());
+	let header_len = routing_header_len(&binary) + 8;
+	let inner_len = u32::from_be_bytes(binary[header_len - 4..header_len].try_into().unwrap()) as usize;
+	let other_inner_len = u32::from_be_bytes(other[header_len - 4..header_len].try_into().unwrap()) as usize;
+	let swapped = [&binary[..header_len + inner_len], &other[header_len + other_inner_len..]].concat();
+	assert!(parse_msg(&swapped, &sk_kyber, None, &pfs_key, &pfs_salt).is_err());
+	let mut unknown_version = binary.clone();
+	unknown_version[routing_header_len(&binary) + 3] = BINARY_FORMAT_VERSION + 1;
+	assert!(parse_msg(&unknown_version, &sk_kyber, None, &pfs_key, &pfs_salt).is_err());
+	
+	// the format is negotiated with the capabilities of the peer
+	assert_eq!(WireFormat::for_peer(&capability::supported()), WireFormat::Cbor);
+	assert_eq!(WireFormat::for_peer(&[capability::LINKED_MEDIA.to_string()]), WireFormat::Json);
+	// the escrow copy is JSON, so escrowed messages can't be sent as binary messages
+	let escrow_pubkey = KyberPublicKey::try_from(pk_kyber.clone()).unwrap();
+	assert!(send_msg_with_options(&OutgoingMessage::text("escrowed"), &SendOptions::new().escrow(&escrow_pubkey), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).is_ok());
+	assert!(send_msg_with_options(&OutgoingMessage::text("escrowed"), &cbor.clone().escrow(&escrow_pubkey), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).is_err());
+	let (mut alice_session, mut bob_session) = gen_session_pair();
+	let (_, ciphertext) = alice_session.send(&OutgoingMessage::voice(&voice)).unwrap();
+	assert_eq!(&ciphertext[routing_header_len(&ciphertext) + 8..routing_header_len(&ciphertext) + 11], b"DWB");
+	assert_eq!(bob_session.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Voice { data: voice, transcription: None, in_reply_to: None, codec: None, expires_after: None });
+}
+
+#[test]
+fn test_nicknames() {
+	let proposal = NicknameEvent::Propose(NicknameProposal { id: id_gen(), recipient_nickname: Some("bobby".to_string()), sender_nickname: None });
+	assert_eq!(parse_nickname_event(&gen_nickname_event(&proposal).unwrap()).unwrap(), proposal);
+	assert!(gen_nickname_event(&NicknameEvent::Propose(NicknameProposal { id: id_gen(), recipient_nickname: None, sender_nickname: None })).is_err());
+	assert!(gen_nickname_event(&NicknameEvent::Propose(NicknameProposal { id: id_gen(), recipient_nickname: Some("x".repeat(MAX_NICKNAME_LEN + 1)), sender_nickname: None })).is_err());
+	assert!(gen_nickname_event(&NicknameEvent::Accept(String::new())).is_err());
+	assert!(parse_nickname_event(b"{\"kind\":\"accept\"}").is_err());
+	assert_eq!(resolve_display_name(Some("dad"), Some("bobby"), "bob"), "dad");
+	assert_eq!(resolve_display_name(None, Some("bobby"), "bob"), "bobby");
+	assert_eq!(resolve_display_name(None, None, "bob"), "bob");
+	
+	// a declined proposal changes nothing
+	let (mut alice, mut bob) = gen_session_pair();
+	let (_, ciphertext) = alice.propose_nickname(Some("bobby"), Some("ally")).unwrap();
+	bob.receive(&ciphertext).unwrap().unwrap();
+	assert_eq!(bob.nickname_proposal().unwrap().sender_nickname.as_deref(), Some("ally"));
+	let (_, ciphertext) = bob.decline_nickname().unwrap();
+	alice.receive(&ciphertext).unwrap().unwrap();
+	assert!(bob.nickname_proposal().is_none());
+	assert!(alice.accept_nickname().is_err());
+	assert_eq!(alice.nickname(), None);
+	assert_eq!(alice.display_name(), "bob");
+	
+	// an accepted proposal sets the nickname on both sides
+	let (_, ciphertext) = alice.propose_nickname(Some("bobby"), Some("ally")).unwrap();
+	bob.receive(&ciphertext).unwrap().unwrap();
+	let (_, acceptance) = bob.accept_nickname().unwrap();
+	assert_eq!(bob.display_name(), "ally");
+	alice.receive(&acceptance).unwrap().unwrap();
+	assert_eq!(alice.display_name(), "bobby");
+	
+	// an acceptance without a matching proposal is rejected
+	let (_, ciphertext) = alice.propose_nickname(Some("robert"), None).unwrap();
+	bob.receive(&ciphertext).unwrap().unwrap();
+	let (_, ciphertext) = alice.propose_nickname(Some("rob"), None).unwrap();
+	let (_, stale) = bob.accept_nickname().unwrap();
+	assert!(alice.receive(&stale).is_err());
+	bob.receive(&ciphertext).unwrap().unwrap();
+	let (_, ciphertext) = bob.accept_nickname().unwrap();
+	alice.receive(&ciphertext).unwrap().unwrap();
+	assert_eq!(alice.nickname(), Some("rob"));
+	
+	// the petname is local and wins over the nickname
+	alice.set_petname(Some("brother"));
+	let mut store = MemorySessionStore::new();
+	store.put(&alice).unwrap();
+	let mut alice = store.get(alice.id()).unwrap().unwrap();
+	assert_eq!(alice.petname(), Some("brother"));
+	assert_eq!(alice.display_name(), "brother");
+	alice.set_petname(None);
+	assert_eq!(alice.display_name(), "rob");
+}
+
+#[test]
+fn test_message_effects() {
+	assert_eq!(Effect::from("invisible_ink"), Effect::InvisibleInk);
+	assert_eq!(Effect::from("sparkles"), Effect::Unknown("sparkles".to_string()));
+	assert!(!Effect::from("sparkles").is_known());
+	assert!(KNOWN_EFFECTS.iter().all(|effect| Effect::from(effect.name()) == *effect));
+	
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, _, ciphertext) = send_msg_with_options(&OutgoingMessage::text("happy birthday").with_effect(Effect::Confetti).unwrap(), &SendOptions::new(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(received, ReceivedMessage::Text { text: "happy birthday".to_string(), effect: Some(Effect::Confetti), in_reply_to: None, expires_after: None });
+	
+	// effects of later versions arrive as unknown effects
+	let (_, _, ciphertext) = send_msg_with_options(&OutgoingMessage::picture(&[1, 2], "fireplace").with_effect(Effect::from("sparkles")).unwrap(), &SendOptions::new(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(received, ReceivedMessage::Picture { data: vec![1, 2], description: "fireplace".to_string(), alt_text: None, effect: Some(Effect::Unknown("sparkles".to_string())), in_reply_to: None, codec: None, expires_after: None });
+	
+	// only text and picture messages with a valid effect name can be sent
+	assert!(send_msg_with_options(&OutgoingMessage::text("hi").with_effect(Effect::from("Not Valid")).unwrap(), &SendOptions::new(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).is_err());
+	assert!(OutgoingMessage::voice(&[1]).with_effect(Effect::Shake).is_err());
+	
+	// sessions send the effect of an outgoing message
+	let (mut alice, mut bob) = gen_session_pair();
+	let (_, ciphertext) = alice.send(&OutgoingMessage::text("boo").with_effect(Effect::InvisibleInk).unwrap()).unwrap();
+	assert_eq!(bob.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Text { text: "boo".to_string(), effect: Some(Effect::InvisibleInk), in_reply_to: None, expires_after: None });
+}
+
+#[test]
+fn test_protocol_versions() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let encrypt = |message: &str| encrypt_msg(&pk_kyber, None, &pfs_key, &pfs_salt, message).unwrap().0;
+	
+	// messages of this version and messages without a version are parsed
+	let (_, _, ciphertext) = send_msg((content_type::TEXT, Some("hi"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert!(parse_msg_limited(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt, &ParseLimits::strict()).is_ok());
+	let legacy = encrypt(r#"{"Text":{"text":"hi","mdc":"00"}}"#);
+	assert!(parse_msg_limited(&legacy, &sk_kyber, None, &pfs_key, &pfs_salt, &ParseLimits::strict()).is_ok());
+	
+	// later versions are rejected before the rest of the message is parsed
+	let newer = encrypt(r#"{"Text":{"body":{"parts":["hi"]},"protocol_version":2}}"#);
+	assert_eq!(parse_msg(&newer, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap_err(), DawnError::UnsupportedVersion(2));
+	let (_, _, binary) = send_msg_with_options(&OutgoingMessage::text("hi"), &SendOptions::new().format(WireFormat::Cbor), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert!(parse_msg(&binary, &sk_kyber, None, &pfs_key, &pfs_salt).is_ok());
+	
+	// negotiation
+	assert_eq!(negotiate_version(&capability::supported()).unwrap(), PROTOCOL_VERSION);
+	assert_eq!(peer_versions(&[]), vec![1]);
+	assert_eq!(negotiate_version(&[]).unwrap(), 1);
+	assert_eq!(negotiate_version(&["protocol_version:7".to_string(), "protocol_version:9".to_string()]).unwrap_err(), DawnError::UnsupportedVersion(9));
+	assert_eq!(DawnError::UnsupportedVersion(2).to_string(), "@dawn-stdlib: the protocol version is not supported");
+}
+
+#[test]
+fn test_compat_parser() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	
+	// payloads of version 1 clients as they were queued on content servers
+	let expected = [
+		ReceivedMessage::Text { text: "hello from the old client".to_string(), effect: None, in_reply_to: None, expires_after: None },
+		ReceivedMessage::Internal { event: event::PROFILE_UPDATE, data: b"{\"name\":\"alice\"}".to_vec() },
+		ReceivedMessage::Voice { data: (0..12).collect(), transcription: None, in_reply_to: None, codec: None, expires_after: None },
+		ReceivedMessage::Picture { data: b"\x89PNG\r\n\x1a\n".to_vec(), description: "old picture".to_string(), alt_text: None, effect: None, in_reply_to: None, codec: None, expires_after: None },
+		ReceivedMessage::LinkedMedia { link: "https://media.example.org/f/2d8e".to_string(), key: "5e1f".to_string(), description: String::new(), media_type: 3, expires_at: None, delete_token: None },
+		ReceivedMessage::LinkedMedia { link: "https://media.example.org/f/9a0b".to_string(), key: "6a2b".to_string(), description: "voice note".to_string(), media_type: 2, expires_at: Some(1700000000), delete_token: Some(vec![0x00, 0x11, 0x22, 0x33]) },
+		ReceivedMessage::HistorySync { transfer_id: "4c5d6e7f".to_string(), chunk_index: 0, chunk_count: 2, chunk: b"[{\"id\"".to_vec() },
+		ReceivedMessage::DeltaSync { delta: br#"{"known":3,"since":10,"until":12,"entries":[],"receipts":["8a2e4f6b1c3d5e7f"],"settings":[]}"#.to_vec() },
+		ReceivedMessage::Text { text: "in a thread".to_string(), effect: None, in_reply_to: None, expires_after: None },
+		ReceivedMessage::Picture { data: b"GIF89a".to_vec(), description: String::new(), alt_text: None, effect: Some(Effect::Confetti), in_reply_to: None, codec: None, expires_after: None },
+		ReceivedMessage::Voice { data: vec![9, 8, 7], transcription: Some(Transcription { text: "call me back\nwhen you can".to_string(), language: "en-GB".to_string() }), in_reply_to: None, codec: None, expires_after: None },
+		ReceivedMessage::Reaction { target: "3f1c9a0e7b2d4c58".to_string(), reaction: "👍".to_string() },
+		ReceivedMessage::Text { text: "sounds good".to_string(), effect: None, in_reply_to: Some(Reply { mdc: "3f1c9a0e7b2d4c58".to_string(), excerpt: Some("hello from the old client".to_string()) }), expires_after: None },
+		ReceivedMessage::Retraction { target: "7c8d9e0f1a2b3c4d".to_string() },
+	];
+	let fixtures: Vec<&str> = include_str!("fixtures/compat_v1.jsonl").lines().collect();
+	assert_eq!(fixtures.len(), expected.len());
+	for (fixture, expected) in fixtures.iter().zip(expected) {
+		let (ciphertext, _) = encrypt_msg(&pk_kyber, None, &pfs_key, &pfs_salt, fixture).unwrap();
+		let (parsed, generation) = parse_compat_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+		assert_eq!(generation, WireGeneration::JsonV1);
+		assert_eq!(parsed.warning, Warning::Unsigned);
+		assert!(fixture.contains(&parsed.mdc));
+		let received = parsed.message;
+		assert_eq!(received, expected);
+		
+		// resending the content with the current library gives the same message
+		let (content_type, text, data) = received.clone().into_content();
+		let (_, _, resent) = send_msg((content_type, text.as_deref(), data.as_deref()), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+		let (reparsed, _) = parse_compat_msg(&resent, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+		assert_eq!(reparsed.message.into_content(), received.into_content());
+	}
+	
+	// binary messages and messages of later versions are left to parse_msg
+	let (_, _, binary) = send_msg_with_options(&OutgoingMessage::text("hi"), &SendOptions::new().format(WireFormat::Cbor), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert_eq!(parse_compat_msg(&binary, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap().1, WireGeneration::Current);
+	let (newer, _) = encrypt_msg(&pk_kyber, None, &pfs_key, &pfs_salt, r#"{"Text":{"text":"hi","mdc":"00","protocol_version":2}}"#).unwrap();
+	assert_eq!(parse_compat_msg(&newer, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap_err(), DawnError::UnsupportedVersion(2));
+}
+
+#[test]
+fn test_voice_transcription() {
+	assert!(Transcription::new("", "en").is_err());
+	assert!(Transcription::new("hello", "").is_err());
+	assert!(Transcription::new("hello", "en_US").is_err());
+	assert!(Transcription::new("hello", "en--US").is_err());
+	let transcription = Transcription::new("see you at eight\nbring the keys", "de-AT").unwrap();
+	
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let message = OutgoingMessage::voice_with_transcription(&[5, 6, 7], &transcription);
+	let (_, _, ciphertext) = send_msg(message.content(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(received, ReceivedMessage::Voice { data: vec![5, 6, 7], transcription: Some(transcription.clone()), in_reply_to: None, codec: None, expires_after: None });
+	
+	// forwarding keeps the transcription, a voice message text without language tag is rejected
+	let (content_type, text, data) = received.into_content();
+	assert_eq!((content_type, text.as_deref(), data.as_deref()), message.content());
+	assert!(send_msg((content_type::VOICE, Some("no language tag"), Some(&[1])), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).is_err());
+	
+	// sessions carry it as well
+	let (mut alice, mut bob) = gen_session_pair();
+	let (_, ciphertext) = alice.send(&message).unwrap();
+	assert_eq!(bob.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Voice { data: vec![5, 6, 7], transcription: Some(transcription), in_reply_to: None, codec: None, expires_after: None });
+}
+
+#[test]
+fn test_alt_text() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, _, ciphertext) = send_msg_with_options(&OutgoingMessage::picture_with_alt_text(&[1, 2, 3], "my new bike", "a red bicycle leaning against a wall"), &SendOptions::new(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(received, ReceivedMessage::Picture { data: vec![1, 2, 3], description: "my new bike".to_string(), alt_text: Some("a red bicycle leaning against a wall".to_string()), effect: None, in_reply_to: None, codec: None, expires_after: None });
+	
+	// the alt text is carried end to end by sessions, together with an effect
+	let (mut alice, mut bob) = gen_session_pair();
+	let message = OutgoingMessage::picture_with_alt_text(&[4, 5], "", "a birthday cake with five candles").with_effect(Effect::Balloons).unwrap();
+	let (_, ciphertext) = alice.send(&message).unwrap();
+	assert_eq!(bob.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Picture { data: vec![4, 5], description: String::new(), alt_text: Some("a birthday cake with five candles".to_string()), effect: Some(Effect::Balloons), in_reply_to: None, codec: None, expires_after: None });
+}
+
+#[test]
+fn test_group_sender_keys() {
+	// the sender key message of the creator goes through the 1:1 session and invites the member
+	let mut alice = Group::create("alice", &["bob", "carol"]).unwrap();
+	let (mut alice_session, mut bob_session) = gen_session_pair();
+	let (_, ciphertext) = alice_session.send(&alice.sender_key_message().unwrap()).unwrap();
+	let invitation = match bob_session.receive(&ciphertext).unwrap().unwrap().0 {
+		ReceivedMessage::Internal { event: event::SENDER_KEY, data } => data,
+		_ => panic!("not a sender key message")
+	};
+	let mut bob = Group::join("bob", "alice", &invitation).unwrap();
+	let sender_key = |group: &Group| group.sender_key_message().unwrap().content().2.unwrap().to_vec();
+	let invitation = sender_key(&alice);
+	let mut carol = Group::join("carol", "alice", &invitation).unwrap();
+	assert_eq!(bob.id(), alice.id());
+	assert_eq!(carol.members(), alice.members());
+	assert!(Group::join("mallory", "alice", &invitation).is_err());
+	assert!(bob.process_sender_key("carol", &invitation).is_err());
+	
+	// the other members hand out their sender keys as well
+	let bob_key = sender_key(&bob);
+	let carol_key = sender_key(&carol);
+	alice.process_sender_key("bob", &bob_key).unwrap();
+	carol.process_sender_key("bob", &bob_key).unwrap();
+	alice.process_sender_key("carol", &carol_key).unwrap();
+	bob.process_sender_key("carol", &carol_key).unwrap();
+	
+	// one ciphertext is read by all members
+	let (mdc, ciphertext) = send_group_msg(&mut alice, &OutgoingMessage::text("hello group")).unwrap();
+	for member in [&mut bob, &mut carol] {
+		let (sender, received, received_mdc) = parse_group_msg(member, &ciphertext, &ProtocolConfig::default()).unwrap();
+		assert_eq!((sender.as_str(), received_mdc.as_str()), ("alice", mdc.as_str()));
+		assert_eq!(received, ReceivedMessage::Text { text: "hello group".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	}
+	assert!(parse_group_msg(&mut bob, &ciphertext, &ProtocolConfig::default()).is_err());
+	assert!(parse_group_msg(&mut alice, &ciphertext, &ProtocolConfig::default()).is_err());
+	
+	// messages can arrive out of order, every message has its own message detail code
+	let (first_mdc, first) = send_group_msg(&mut carol, &OutgoingMessage::text("first")).unwrap();
+	let (second_mdc, second) = send_group_msg(&mut carol, &OutgoingMessage::picture(&[1, 2], "second")).unwrap();
+	assert!(first_mdc != second_mdc && first_mdc != mdc);
+	assert_eq!(parse_group_msg(&mut bob, &second, &ProtocolConfig::default()).unwrap().1, ReceivedMessage::Picture { data: vec![1, 2], description: "second".to_string(), alt_text: None, effect: None, in_reply_to: None, codec: None, expires_after: None });
+	assert_eq!(parse_group_msg(&mut bob, &first, &ProtocolConfig::default()).unwrap().1, ReceivedMessage::Text { text: "first".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	
+	// members can't send in the name of another member
+	let (_, forged) = send_group_msg(&mut bob, &OutgoingMessage::text("i am carol")).unwrap();
+	let forged = String::from_utf8(forged).unwrap().replace("\"sender\":\"bob\"", "\"sender\":\"carol\"");
+	assert!(parse_group_msg(&mut alice, forged.as_bytes(), &ProtocolConfig::default()).is_err());
+	
+	// removed members are not trusted anymore
+	alice.remove_member("bob").unwrap();
+	let (_, ciphertext) = send_group_msg(&mut bob, &OutgoingMessage::text("still here")).unwrap();
+	assert!(parse_group_msg(&mut alice, &ciphertext, &ProtocolConfig::default()).is_err());
+	assert!(!alice.has_sender_key("bob"));
+	assert!(alice.remove_member("alice").is_err());
+}
+
+#[test]
+fn test_maintenance_events() {
+	let policy = MaintenancePolicy::new().prekeys(10, 50).init_key_rotation_interval(1000).stale_session_age(5000).lead_time(100);
+	let mut state = MaintenanceState {
+		prekeys_left: 30,
+		init_keys_rotated: 0,
+		sessions: vec![("a".to_string(), 4000), ("b".to_string(), 200)],
+	};
+	
+	// nothing is due yet
+	assert_eq!(maintenance_events(&policy, &state, 500), vec![]);
+	
+	// events within the lead time are reported in advance, overdue events keep their due time
+	assert_eq!(maintenance_events(&policy, &state, 950), vec![MaintenanceEvent { due: 1000, task: MaintenanceTask::RotateInitKeys }]);
+	state.prekeys_left = 10;
+	assert_eq!(maintenance_events(&policy, &state, 5200), vec![
+		MaintenanceEvent { due: 1000, task: MaintenanceTask::RotateInitKeys },
+		MaintenanceEvent { due: 5200, task: MaintenanceTask::UploadPrekeys { count: 40 } },
+		MaintenanceEvent { due: 5200, task: MaintenanceTask::CloseStaleSession { id: "b".to_string() } },
+	]);
+	
+	// handled events disappear once the state is updated
+	state.prekeys_left = 50;
+	state.init_keys_rotated = 5200;
+	state.sessions.retain(|(id, _)| id != "b");
+	assert_eq!(maintenance_events(&policy, &state, 5200), vec![]);
+	assert_eq!(maintenance_events(&policy, &state, 8950), vec![
+		MaintenanceEvent { due: 6200, task: MaintenanceTask::RotateInitKeys },
+		MaintenanceEvent { due: 9000, task: MaintenanceTask::CloseStaleSession { id: "a".to_string() } },
+	]);
+}
+
+#[test]
+fn test_group_key_rotation() {
+	let sender_key = |group: &Group| group.sender_key_message().unwrap().content().2.unwrap().to_vec();
+	let mut alice = Group::create("alice", &["bob", "carol"]).unwrap();
+	let invitation = sender_key(&alice);
+	let mut bob = Group::join("bob", "alice", &invitation).unwrap();
+	let mut carol = Group::join("carol", "alice", &invitation).unwrap();
+	carol.process_sender_key("bob", &sender_key(&bob)).unwrap();
+	let config = ProtocolConfig::default();
+	
+	// membership changes are applied in order, a change that arrives early can be parsed again later
+	let (_, add_dave) = send_membership_change(&mut alice, &["dave"], &[]).unwrap();
+	let (_, add_erin) = send_membership_change(&mut alice, &["erin"], &[]).unwrap();
+	for member in [&mut bob, &mut carol] {
+		assert!(parse_group_msg(member, &add_erin, &config).is_err());
+		parse_group_msg(member, &add_dave, &config).unwrap();
+		parse_group_msg(member, &add_erin, &config).unwrap();
+		assert_eq!(member.members(), alice.members());
+		assert!(parse_group_msg(member, &add_erin, &config).is_err());
+	}
+	
+	// a removal is applied by every member and blocks sending until the own sender key was rotated
+	let (_, change) = send_membership_change(&mut alice, &[], &["bob"]).unwrap();
+	assert!(send_membership_change(&mut alice, &[], &["alice"]).is_err());
+	for member in [&mut bob, &mut carol] {
+		let data = match parse_group_msg(member, &change, &config).unwrap().1 {
+			ReceivedMessage::Internal { event: event::GROUP_MEMBERS, data } => data,
+			_ => panic!("not a membership change")
+		};
+		assert_eq!(parse_membership_change(&data).unwrap(), MembershipChange { sender: "alice".to_string(), epoch: 3, added: vec![], removed: vec!["bob".to_string()], admins: vec![] });
+	}
+	assert_eq!(carol.members(), alice.members());
+	assert_eq!(bob.epoch(), 3);
+	assert!(!bob.is_member());
+	assert!(send_group_msg(&mut bob, &OutgoingMessage::text("still here")).is_err());
+	assert!(alice.rotation_pending());
+	assert!(send_group_msg(&mut alice, &OutgoingMessage::text("bob is gone")).is_err());
+	
+	// the new key is only handed to the remaining members
+	let messages = rotate_group_keys(&mut alice).unwrap();
+	assert_eq!(messages.iter().map(|(member, _)| member.as_str()).collect::<Vec<&str>>(), vec!["carol", "dave", "erin"]);
+	assert!(!alice.rotation_pending());
+	carol.process_sender_key("alice", messages[0].1.content().2.unwrap()).unwrap();
+	
+	// the removed member can't read messages sent with the new key
+	let (_, ciphertext) = send_group_msg(&mut alice, &OutgoingMessage::text("bob is gone")).unwrap();
+	assert_eq!(parse_group_msg(&mut carol, &ciphertext, &ProtocolConfig::default()).unwrap().1, ReceivedMessage::Text { text: "bob is gone".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert!(parse_group_msg(&mut bob, &ciphertext, &ProtocolConfig::default()).is_err());
+	
+	// removing someone who is not a member needs no rotation
+	assert!(carol.rotation_pending());
+	rotate_group_keys(&mut carol).unwrap();
+	carol.remove_member("frank").unwrap();
+	assert!(!carol.rotation_pending());
+}
+
+#[test]
+fn test_concurrent_membership_changes() {
+	let sender_key = |group: &Group| group.sender_key_message().unwrap().content().2.unwrap().to_vec();
+	let config = ProtocolConfig::default();
+	let mut alice = Group::create("alice", &["bob", "carol"]).unwrap();
+	let mut bob = Group::join("bob", "alice", &sender_key(&alice)).unwrap();
+	let mut carol = Group::join("carol", "alice", &sender_key(&alice)).unwrap();
+	alice.process_sender_key("bob", &sender_key(&bob)).unwrap();
+	carol.process_sender_key("bob", &sender_key(&bob)).unwrap();
+	let (_, change) = send_admin_change(&mut alice, &["bob"]).unwrap();
+	parse_group_msg(&mut bob, &change, &config).unwrap();
+	parse_group_msg(&mut carol, &change, &config).unwrap();
+	
+	// two admins change the members with the same epoch at the same time
+	let (_, add_dave) = send_membership_change(&mut alice, &["dave"], &[]).unwrap();
+	let (_, remove_carol) = send_membership_change(&mut bob, &["erin"], &["carol"]).unwrap();
+	assert_eq!((alice.epoch(), bob.epoch()), (2, 2));
+	
+	// every member keeps the same one of them, whatever order they arrive in
+	let alice_result = parse_group_msg(&mut alice, &remove_carol, &config);
+	let bob_result = parse_group_msg(&mut bob, &add_dave, &config);
+	assert_ne!(alice_result.is_ok(), bob_result.is_ok());
+	let carol_first = parse_group_msg(&mut carol, &add_dave, &config);
+	let carol_second = parse_group_msg(&mut carol, &remove_carol, &config);
+	assert!(carol_first.is_ok());
+	assert_eq!(carol_second.is_ok(), alice_result.is_ok());
+	assert_eq!(alice.members(), bob.members());
+	assert_eq!(carol.members(), alice.members());
+	assert_eq!((alice.epoch(), bob.epoch(), carol.epoch()), (2, 2, 2));
+	let dave_won = alice.members().contains(&"dave".to_string());
+	assert_eq!(alice.members().contains(&"erin".to_string()), !dave_won);
+	assert_eq!(carol.is_member(), dave_won);
+	
+	// the losing change is not applied again and the group moves on from the winner
+	assert!(parse_group_msg(&mut alice, &remove_carol, &config).is_err());
+	let (winner, mut loser, loser_id) = if dave_won { (&mut alice, bob, "bob") } else { (&mut bob, alice, "alice") };
+	if winner.rotation_pending() {
+		let messages = rotate_group_keys(winner).unwrap();
+		let (_, message) = messages.iter().find(|(member, _)| member == loser_id).unwrap();
+		loser.process_sender_key(winner.own_member(), message.content().2.unwrap()).unwrap();
+	}
+	let (_, add_frank) = send_membership_change(winner, &["frank"], &[]).unwrap();
+	parse_group_msg(&mut loser, &add_frank, &config).unwrap();
+	assert_eq!(loser.members(), winner.members());
+	assert_eq!(loser.epoch(), 3);
+}
+
+#[test]
+fn test_group_appearance() {
+	let sender_key = |group: &Group| group.sender_key_message().unwrap().content().2.unwrap().to_vec();
+	let config = ProtocolConfig::default();
+	let mut alice = Group::create("alice", &["bob", "carol"]).unwrap();
+	let mut bob = Group::join("bob", "alice", &sender_key(&alice)).unwrap();
+	let mut carol = Group::join("carol", "alice", &sender_key(&alice)).unwrap();
+	alice.process_sender_key("bob", &sender_key(&bob)).unwrap();
+	carol.process_sender_key("bob", &sender_key(&bob)).unwrap();
+	assert!(alice.appearance().unwrap().is_none());
+	
+	// the avatar key is wrapped with the group key and unwrapped by every member
+	let avatar = GroupAvatar { media_link: "https://media.example/avatar".to_string(), media_key: sym_key_gen() };
+	let (_, ciphertext) = send_group_appearance(&mut alice, Some("hiking club"), Some(&avatar)).unwrap();
+	let appearance = GroupAppearance { epoch: 1, author: "alice".to_string(), description: Some("hiking club".to_string()), avatar: Some(avatar) };
+	for member in [&mut bob, &mut carol] {
+		parse_group_msg(member, &ciphertext, &config).unwrap();
+		assert_eq!(member.appearance().unwrap(), Some(appearance.clone()));
+	}
+	
+	// appearances set concurrently converge whatever order they arrive in
+	let (_, from_alice) = send_group_appearance(&mut alice, Some("hiking club 2024"), None).unwrap();
+	let (_, from_bob) = send_group_appearance(&mut bob, Some("climbing club"), None).unwrap();
+	parse_group_msg(&mut carol, &from_alice, &config).unwrap();
+	parse_group_msg(&mut carol, &from_bob, &config).unwrap();
+	parse_group_msg(&mut alice, &from_bob, &config).unwrap();
+	parse_group_msg(&mut bob, &from_alice, &config).unwrap();
+	let appearance = GroupAppearance { epoch: 2, author: "bob".to_string(), description: Some("climbing club".to_string()), avatar: None };
+	for member in [&alice, &bob, &carol] {
+		assert_eq!(member.appearance().unwrap(), Some(appearance.clone()));
+	}
+	
+	// new members get the current appearance with their invitation
+	send_membership_change(&mut alice, &["dave"], &[]).unwrap();
+	let dave = Group::join("dave", "alice", &sender_key(&alice)).unwrap();
+	assert_eq!(dave.appearance().unwrap(), Some(appearance));
+	assert!(send_group_appearance(&mut alice, Some(&"a".repeat(MAX_GROUP_DESCRIPTION_LEN + 1)), None).is_err());
+}
+
+#[test]
+fn test_group_join_requests() {
+	let sender_key = |group: &Group| group.sender_key_message().unwrap().content().2.unwrap().to_vec();
+	let config = ProtocolConfig::default();
+	let mut alice = Group::create("alice", &["bob"]).unwrap();
+	let mut bob = Group::join("bob", "alice", &sender_key(&alice)).unwrap();
+	alice.process_sender_key("bob", &sender_key(&bob)).unwrap();
+	assert_eq!(bob.admins(), ["alice".to_string()]);
+	assert!(bob.gen_handle().is_err());
+	assert!(send_membership_change(&mut bob, &["mallory"], &[]).is_err());
+	
+	// the request goes through the 1:1 conversation with an admin, which authenticates the requester
+	let handle = parse_group_handle(&alice.gen_handle().unwrap()).unwrap();
+	assert_eq!(handle.admins, vec!["alice".to_string()]);
+	let (request, message) = gen_group_join_request(&handle, "carol", Some("carol from the hiking trip")).unwrap();
+	let (mut carol_session, mut alice_session) = gen_session_pair();
+	let (_, ciphertext) = carol_session.send(&message).unwrap();
+	let data = match alice_session.receive(&ciphertext).unwrap().unwrap().0 {
+		ReceivedMessage::Internal { event: event::GROUP_JOIN_REQUEST, data } => data,
+		_ => panic!("not a join request")
+	};
+	assert!(alice.process_join_request("dave", &data).is_err());
+	assert!(bob.process_join_request("carol", &data).is_err());
+	assert_eq!(alice.process_join_request("carol", &data).unwrap(), request);
+	assert_eq!(alice.join_requests().iter().map(|pending| pending.id.as_str()).collect::<Vec<&str>>(), vec![request.id.as_str()]);
+	
+	// approving adds the requester for all members and invites it
+	let ((_, change), invitation) = approve_join_request(&mut alice, &request.id).unwrap();
+	assert!(alice.join_requests().is_empty());
+	parse_group_msg(&mut bob, &change, &config).unwrap();
+	assert_eq!(bob.members(), alice.members());
+	// the inviter is checked against the admins of the handle, not the admins the invitation names
+	let invitation = invitation.content().2.unwrap().to_vec();
+	let mut forged: serde_json::Value = serde_json::from_slice(&sender_key(&bob)).unwrap();
+	forged["admins"] = serde_json::Value::from(vec!["bob"]);
+	forged["members"] = serde_json::Value::from(alice.members().to_vec());
+	let forged = forged.to_string().into_bytes();
+	assert!(Group::join("carol", "bob", &forged).is_ok());
+	assert!(Group::join_with_handle("carol", &handle, "bob", &forged).is_err());
+	let other = Group::create("alice", &["carol"]).unwrap();
+	assert!(Group::join_with_handle("carol", &handle, "alice", &sender_key(&other)).is_err());
+	let mut carol = Group::join_with_handle("carol", &handle, "alice", &invitation).unwrap();
+	carol.process_sender_key("bob", &sender_key(&bob)).unwrap();
+	let (_, ciphertext) = send_group_msg(&mut bob, &OutgoingMessage::text("welcome")).unwrap();
+	assert_eq!(parse_group_msg(&mut carol, &ciphertext, &config).unwrap().1, ReceivedMessage::Text { text: "welcome".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	
+	// other members can be made admins
+	let (_, change) = send_admin_change(&mut alice, &["bob"]).unwrap();
+	parse_group_msg(&mut bob, &change, &config).unwrap();
+	assert!(bob.is_admin("bob"));
+	assert!(send_admin_change(&mut alice, &["dave"]).is_err());
+	
+	// requests need the join secret of a handle of the group, denials name the request
+	let mut forged: serde_json::Value = serde_json::from_slice(&bob.gen_handle().unwrap()).unwrap();
+	forged["join_secret"] = serde_json::Value::from("00");
+	let (_, message) = gen_group_join_request(&parse_group_handle(forged.to_string().as_bytes()).unwrap(), "dave", None).unwrap();
+	assert!(bob.process_join_request("dave", message.content().2.unwrap()).is_err());
+	let (request, message) = gen_group_join_request(&parse_group_handle(&bob.gen_handle().unwrap()).unwrap(), "dave", None).unwrap();
+	bob.process_join_request("dave", message.content().2.unwrap()).unwrap();
+	let denial = deny_join_request(&mut bob, &request.id).unwrap();
+	assert_eq!(parse_group_join_denial(denial.content().2.unwrap()).unwrap(), (bob.id().to_string(), request.id));
+	assert!(bob.join_requests().is_empty());
+}
+
+#[test]
+fn test_group_sender_attribution() {
+	let sender_key = |group: &Group| group.sender_key_message().unwrap().content().2.unwrap().to_vec();
+	let config = ProtocolConfig::default();
+	let mut alice = Group::create("alice", &["bob", "carol"]).unwrap();
+	let mut bob = Group::join("bob", "alice", &sender_key(&alice)).unwrap();
+	let mut carol = Group::join("carol", "alice", &sender_key(&alice)).unwrap();
+	alice.process_sender_key("bob", &sender_key(&bob)).unwrap();
+	alice.process_sender_key("carol", &sender_key(&carol)).unwrap();
+	carol.process_sender_key("bob", &sender_key(&bob)).unwrap();
+	
+	// every member sees the same group identity of a member
+	assert_eq!(alice.member_identity("bob"), Some(bob.identity()));
+	assert_eq!(carol.member_identity("bob"), Some(bob.identity()));
+	assert_ne!(bob.identity(), carol.identity());
+	assert_eq!(alice.member_identity("dave"), None);
+	
+	// members that are no admins can't hand out their sender key as invitation
+	send_membership_change(&mut alice, &["dave"], &[]).unwrap();
+	assert!(Group::join("dave", "bob", &sender_key(&bob)).is_err());
+	assert!(Group::join("dave", "alice", &sender_key(&alice)).is_ok());
+	
+	// a member can't claim the identity or the signature key of another member
+	let (_, ciphertext) = send_group_msg(&mut bob, &OutgoingMessage::text("i am carol")).unwrap();
+	let envelope = String::from_utf8(ciphertext).unwrap();
+	let claimed = envelope.replace(&bob.identity(), &carol.identity());
+	assert!(parse_group_msg(&mut alice, claimed.as_bytes(), &config).is_err());
+	let claimed = claimed.replace("\"sender\":\"bob\"", "\"sender\":\"carol\"");
+	assert!(parse_group_msg(&mut alice, claimed.as_bytes(), &config).is_err());
+	let mut forged_key: serde_json::Value = serde_json::from_slice(&sender_key(&bob)).unwrap();
+	let carol_key: serde_json::Value = serde_json::from_slice(&sender_key(&carol)).unwrap();
+	forged_key["sign"] = carol_key["sign"].clone();
+	forged_key["key_id"] = serde_json::Value::from(id_gen());
+	assert!(alice.process_sender_key("bob", forged_key.to_string().as_bytes()).is_err());
+	assert_eq!(parse_group_msg(&mut alice, envelope.as_bytes(), &config).unwrap().0, "bob");
+}
+
+#[test]
+fn test_prekey_reconciliation() {
+	struct Server(Vec<String>);
+	impl PrekeyServer for Server {
+		fn remaining_prekeys(&mut self) -> Result<Vec<String>, DawnError> {
+			Ok(self.0.clone())
+		}
+	}
+	let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<String>>();
+	
+	let mut storage = MemoryStorage::new();
+	for id in ["a", "b", "c", "d"] {
+		storage.put(NAMESPACE_PREKEYS, id, b"secret").unwrap();
+	}
+	
+	// everything is in sync
+	let mut server = Server(ids(&["a", "b", "c", "d"]));
+	assert_eq!(reconcile_prekeys(&storage, &mut server, &[], 4).unwrap(), PrekeyReconciliation { delete: vec![], unknown: vec![], generate: 0 });
+	
+	// consumed prekeys can be deleted unless an init request still needs them, unknown ones don't count
+	let mut server = Server(ids(&["c", "e", "d"]));
+	let reconciliation = reconcile_prekeys(&storage, &mut server, &ids(&["b"]), 5).unwrap();
+	assert_eq!(reconciliation, PrekeyReconciliation { delete: ids(&["a"]), unknown: ids(&["e"]), generate: 3 });
+	
+	// errors of the server are passed on
+	struct Offline;
+	impl PrekeyServer for Offline {
+		fn remaining_prekeys(&mut self) -> Result<Vec<String>, DawnError> {
+			Err(DawnError::Storage("offline".to_string()))
+		}
+	}
+	assert!(reconcile_prekeys(&storage, &mut Offline, &[], 5).is_err());
+}
+
+#[test]
+fn test_account_registration() {
+	let identity = Identity::generate().unwrap();
+	let mut storage = MemoryStorage::new();
+	let prekeys = gen_prekeys(&mut storage, 3).unwrap();
+	assert_eq!(storage.list(NAMESPACE_PREKEYS).unwrap().len(), 3);
+	let request = gen_registration(&identity, &prekeys, Some("https://contentserver.dawn-privacy.org/f/profile"), 1700000000).unwrap();
+	assert_eq!(request.prekeys, prekeys.iter().map(|(id, _)| id.clone()).collect::<Vec<String>>());
+	
+	// the server sees the keys of the identity and the prekeys
+	let registration = verify_registration(&request.body).unwrap();
+	assert_eq!(registration.id, request.id);
+	assert_eq!(registration.timestamp, 1700000000);
+	assert_eq!(registration.pubkey_sig, identity.pubkey_sig);
+	assert_eq!(registration.init_pubkey_kyber, identity.init_pubkey_kyber);
+	assert_eq!(registration.init_pubkey_curve_for_salt, identity.init_pubkey_curve_for_salt);
+	assert_eq!(registration.prekeys, prekeys);
+	assert_eq!(registration.profile.as_deref(), Some("https://contentserver.dawn-privacy.org/f/profile"));
+	
+	// a request can't be altered or announce keys of someone else
+	let tampered = String::from_utf8(request.body.clone()).unwrap().replace("1700000000", "1700000001");
+	assert!(verify_registration(tampered.as_bytes()).is_err());
+	let other = Identity::generate().unwrap();
+	let forged = String::from_utf8(request.body.clone()).unwrap().replace(&encode(&identity.pubkey_sig), &encode(&other.pubkey_sig));
+	assert!(verify_registration(forged.as_bytes()).is_err());
+	assert!(gen_registration(&identity, &prekeys, Some(""), 1700000000).is_err());
+	
+	// the confirmation has to answer this request and may only accept announced prekeys
+	let accepted = vec![prekeys[0].0.clone(), prekeys[2].0.clone()];
+	let confirmation = gen_registration_confirmation(&registration, "account-42", &accepted).unwrap();
+	let confirmation = parse_registration_confirmation(&confirmation, &request).unwrap();
+	assert_eq!(confirmation, RegistrationConfirmation { request: request.id.clone(), account: "account-42".to_string(), prekeys: accepted });
+	let other_request = gen_registration(&identity, &prekeys, None, 1700000000).unwrap();
+	let confirmation = gen_registration_confirmation(&registration, "account-42", &[]).unwrap();
+	assert!(parse_registration_confirmation(&confirmation, &other_request).is_err());
+	let confirmation = gen_registration_confirmation(&registration, "account-42", &["unknown".to_string()]).unwrap();
+	assert!(parse_registration_confirmation(&confirmation, &request).is_err());
+}
+
+#[test]
+fn test_reactions() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, target, _) = send_msg((content_type::TEXT, Some("lunch?"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	
+	// reactions refer to the target by its mdc
+	let reaction = OutgoingMessage::reaction(&target, "👍").unwrap();
+	assert_eq!(reaction.content_type(), content_type::REACTION);
+	let (_, _, ciphertext) = send_msg(reaction.content(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(received, ReceivedMessage::Reaction { target: target.clone(), reaction: "👍".to_string() });
+	let (content_type, text, data) = received.into_content();
+	assert_eq!((content_type, data), (content_type::REACTION, None));
+	assert_eq!(OutgoingMessage::reaction(&target, "👍").unwrap().content(), (content_type::REACTION, text.as_deref(), None));
+	
+	// an empty reaction withdraws the previous one
+	let (_, _, ciphertext) = send_msg(OutgoingMessage::reaction(&target, "").unwrap().content(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert_eq!(parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap().0, ReceivedMessage::Reaction { target: target.clone(), reaction: String::new() });
+	
+	// reactions are short and need a target
+	assert!(OutgoingMessage::reaction("", "👍").is_err());
+	assert!(OutgoingMessage::reaction(&target, &"👍".repeat(MAX_REACTION_LEN)).is_err());
+	assert!(OutgoingMessage::reaction(&target, "a\nb").is_err());
+	assert!(send_msg((content_type::REACTION, Some(&target), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).is_err());
+	assert!(send_msg_with_options(&OutgoingMessage::reaction(&target, "👍").unwrap(), &SendOptions::new().thread(&id_gen()), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).is_err());
+	
+	// peers without support for reactions are caught before sending
+	let (bob_pk_kyber, _) = kyber_keygen();
+	let peer = Peer::from_encoded(&encode(&bob_pk_kyber), &encode(sign_keygen().0), "bob", vec![]).unwrap();
+	let rules = OutgoingRules::new().peer(&peer);
+	assert_eq!(validate_outgoing(reaction.content(), &rules), vec![Violation::UnsupportedByPeer(capability::REACTION)]);
+}
+
+#[test]
+fn test_server_challenge() {
+	let identity = Identity::generate().unwrap();
+	let challenge = ServerChallenge::new("dawn.example.org", 1000, 60);
+	let signature = sign_server_challenge(&challenge.to_bytes().unwrap(), "dawn.example.org", &identity.seckey_sig, 1030).unwrap();
+	verify_server_challenge(&challenge, &signature, "dawn.example.org", &identity.pubkey_sig, 1030).unwrap();
+	
+	// the answer only works for this challenge, server and account, and only until it expires
+	let other_challenge = ServerChallenge::new("dawn.example.org", 1000, 60);
+	assert!(verify_server_challenge(&other_challenge, &signature, "dawn.example.org", &identity.pubkey_sig, 1030).is_err());
+	assert!(verify_server_challenge(&challenge, &signature, "evil.example.org", &identity.pubkey_sig, 1030).is_err());
+	assert!(verify_server_challenge(&challenge, &signature, "dawn.example.org", &Identity::generate().unwrap().pubkey_sig, 1030).is_err());
+	assert!(verify_server_challenge(&challenge, &signature, "dawn.example.org", &identity.pubkey_sig, 1061).is_err());
+	
+	// clients refuse challenges of other servers and expired ones
+	let relayed = ServerChallenge::new("evil.example.org", 1000, 60).to_bytes().unwrap();
+	assert!(sign_server_challenge(&relayed, "dawn.example.org", &identity.seckey_sig, 1030).is_err());
+	assert!(sign_server_challenge(&challenge.to_bytes().unwrap(), "dawn.example.org", &identity.seckey_sig, 1061).is_err());
+	assert!(sign_server_challenge(b"{}", "dawn.example.org", &identity.seckey_sig, 1030).is_err());
+	
+	// the signature can't be passed off as a signed document
+	assert!(verify_document::<ServerChallenge>("server-challenge", &challenge.to_bytes().unwrap(), &signature, &identity.pubkey_sig).is_err());
+}
+
+#[test]
+fn test_send_pacing() {
+	let clock = ManualClock::new(1000);
+	let mut pacer = SendPacer::new(RateLimits::new(2, 30).unwrap()).max_queued(3).clock(clock.clone());
+	let envelope = |index: u8| PacedEnvelope { temp_id: "temp".to_string(), mdc: index.to_string(), ciphertext: vec![index] };
+	assert_eq!(pacer.wait_time(), None);
+	assert_eq!(pacer.next_ready(), None);
+	
+	// a full queue pushes back
+	for index in 0..3 {
+		pacer.enqueue(envelope(index)).unwrap();
+	}
+	assert!(pacer.enqueue(envelope(3)).is_err());
+	
+	// the burst goes out at once, then one envelope every two seconds
+	assert_eq!(pacer.next_ready(), Some(envelope(0)));
+	assert_eq!(pacer.next_ready(), Some(envelope(1)));
+	assert_eq!(pacer.wait_time(), Some(2));
+	assert_eq!(pacer.next_ready(), None);
+	clock.advance(1);
+	assert_eq!(pacer.wait_time(), Some(1));
+	clock.advance(1);
+	assert_eq!(pacer.next_ready(), Some(envelope(2)));
+	assert_eq!(pacer.queued(), 0);
+	
+	// idle time refills the bucket up to the burst only
+	clock.advance(600);
+	for index in 0..3 {
+		pacer.enqueue(envelope(index)).unwrap();
+	}
+	assert!(pacer.next_ready().is_some());
+	assert!(pacer.next_ready().is_some());
+	assert_eq!(pacer.next_ready(), None);
+	
+	// throttling by the server blocks sending for the given time
+	clock.advance(10);
+	pacer.throttled(30);
+	assert_eq!(pacer.wait_time(), Some(32));
+	clock.advance(32);
+	assert_eq!(pacer.next_ready(), Some(envelope(2)));
+	
+	// new limits apply right away
+	pacer.set_limits(RateLimits::new(1, 60).unwrap());
+	pacer.enqueue(envelope(4)).unwrap();
+	assert_eq!(pacer.wait_time(), Some(1));
+	assert!(RateLimits::new(0, 60).is_err());
+}
+
+#[test]
+fn test_replies() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, target, _) = send_msg((content_type::TEXT, Some("lunch?"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	
+	// replies refer to the quoted message by its mdc and can carry an excerpt of it
+	let reply = Reply::new(&target, Some("lunch?")).unwrap();
+	let (_, _, ciphertext) = send_msg_with_options(&OutgoingMessage::text("sure").with_reply(reply.clone()).unwrap(), &SendOptions::new(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(received, ReceivedMessage::Text { text: "sure".to_string(), effect: None, in_reply_to: Some(reply.clone()), expires_after: None });
+	let (_, _, ciphertext) = send_msg_with_options(&OutgoingMessage::voice(&[1, 2]).with_reply(reply.clone()).unwrap(), &SendOptions::new(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert_eq!(parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap().0, ReceivedMessage::Voice { data: vec![1, 2], transcription: None, in_reply_to: Some(reply.clone()), codec: None, expires_after: None });
+	
+	// long excerpts are cut off at a character boundary, a reply needs the mdc
+	let excerpt = Reply::new(&target, Some(&"ä".repeat(MAX_EXCERPT_LEN))).unwrap().excerpt.unwrap();
+	assert_eq!(excerpt, "ä".repeat(MAX_EXCERPT_LEN / 2));
+	assert_eq!(Reply::new(&target, None).unwrap().excerpt, None);
+	assert!(Reply::new("", Some("lunch?")).is_err());
+	
+	// only text, voice and picture messages can reply
+	let reaction = OutgoingMessage::reaction(&target, "👍").unwrap();
+	assert!(reaction.clone().with_reply(reply.clone()).is_err());
+	let forged = Reply { mdc: target.clone(), excerpt: Some("a".repeat(MAX_EXCERPT_LEN + 1)) };
+	assert!(send_msg_with_options(&OutgoingMessage::text("sure").with_reply(forged).unwrap(), &SendOptions::new(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).is_err());
+	
+	// sessions carry the reply end to end
+	let (mut alice, mut bob) = gen_session_pair();
+	let message = OutgoingMessage::picture(&[4, 5], "the menu").with_reply(reply.clone()).unwrap();
+	assert_eq!(message.in_reply_to(), Some(&reply));
+	let (_, ciphertext) = alice.send(&message).unwrap();
+	assert_eq!(bob.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Picture { data: vec![4, 5], description: "the menu".to_string(), alt_text: None, effect: None, in_reply_to: Some(reply), codec: None, expires_after: None });
+}
+
+#[test]
+fn test_media_codecs() {
+	// both sides announce the codecs they can decode
+	let alice = Identity::generate().unwrap();
+	let bob = Identity::generate().unwrap();
+	let request = alice.init_request_builder().handle(bob.gen_handle("bob", &mdc_gen())).unwrap().name("alice").media_codecs(&[media_codec::AAC, media_codec::WEBP]).build().unwrap();
+	let parsed = bob.parse_init_request(&request.ciphertext).unwrap();
+	assert_eq!(media_codec::peer_codecs(&parsed.peer.capabilities), vec!["aac", "webp"]);
+	assert!(parsed.peer.supports(capability::REACTION));
+	let (_, _, _, accept) = accept_init_request_with_codecs(&[media_codec::OPUS, "flac"], bob.pubkey_sig.as_bytes(), bob.seckey_sig.as_bytes(), parsed.peer.pubkey_kyber.as_bytes(), &parsed.own_pfs_key, &parsed.pfs_salt, &parsed.id, &parsed.mdc_seed).unwrap();
+	let response = parse_init_response(&accept, &request.own_seckey_kyber, None, &request.remote_pfs_key, &request.pfs_salt, "bob").unwrap();
+	assert_eq!(media_codec::peer_codecs(&response.peer.capabilities), vec!["opus", "flac"]);
+	assert!(alice.init_request_builder().handle(bob.gen_handle("bob", &mdc_gen())).unwrap().name("alice").media_codecs(&["Opus"]).build().is_err());
+	
+	// the sender picks the first codec the peer can decode, peers without announced codecs get the baseline
+	assert_eq!(media_codec::pick_codec(&[media_codec::AAC, media_codec::OPUS], &response.peer.capabilities), Some(media_codec::OPUS));
+	assert_eq!(media_codec::pick_codec(&[media_codec::AVIF, media_codec::WEBP], &parsed.peer.capabilities), Some(media_codec::WEBP));
+	assert_eq!(media_codec::pick_codec(&[media_codec::AVIF], &parsed.peer.capabilities), None);
+	assert_eq!(media_codec::pick_codec(&[media_codec::AVIF, media_codec::PNG], &capability::supported()), Some(media_codec::PNG));
+	
+	// the codec travels with voice messages and pictures
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, _, ciphertext) = send_msg_with_options(&OutgoingMessage::voice(&[1, 2]).with_codec(media_codec::AAC).unwrap(), &SendOptions::new(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(received, ReceivedMessage::Voice { data: vec![1, 2], transcription: None, in_reply_to: None, codec: Some("aac".to_string()), expires_after: None });
+	assert!(OutgoingMessage::picture(&[1], "").with_codec("image/webp").is_err());
+	assert!(OutgoingMessage::text("hi").with_codec(media_codec::OPUS).is_err());
+	assert!(OutgoingMessage::voice(&[1]).with_codec(&"a".repeat(media_codec::MAX_CODEC_LEN + 1)).is_err());
+	
+	// sessions carry it end to end
+	let (mut alice_session, mut bob_session) = gen_session_pair();
+	let message = OutgoingMessage::picture(&[4, 5], "sunset").with_codec(media_codec::WEBP).unwrap();
+	assert_eq!(message.codec(), Some("webp"));
+	let (_, ciphertext) = alice_session.send(&message).unwrap();
+	assert_eq!(bob_session.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Picture { data: vec![4, 5], description: "sunset".to_string(), alt_text: None, effect: None, in_reply_to: None, codec: Some("webp".to_string()), expires_after: None });
+}
+
+#[test]
+fn test_retractions() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, target, _) = send_msg((content_type::TEXT, Some("wrong chat"), None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	
+	// retractions refer to the target by its mdc
+	let retraction = OutgoingMessage::retraction(&target).unwrap();
+	assert_eq!(retraction.content(), (content_type::RETRACTION, Some(target.as_str()), None));
+	let (_, _, ciphertext) = send_msg(retraction.content(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let (received, _, _, _) = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap();
+	assert_eq!(received, ReceivedMessage::Retraction { target: target.clone() });
+	assert_eq!(received.into_content(), (content_type::RETRACTION, Some(target.clone()), None));
+	
+	// a retraction needs a single target and can't be part of a thread
+	assert!(OutgoingMessage::retraction("").is_err());
+	assert!(OutgoingMessage::retraction("a\nb").is_err());
+	assert!(send_msg((content_type::RETRACTION, None, None), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).is_err());
+	assert!(send_msg_with_options(&retraction, &SendOptions::new().thread(&id_gen()), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).is_err());
+	
+	// sessions carry it, peers without support are caught before sending
+	let (mut alice, mut bob) = gen_session_pair();
+	let (_, ciphertext) = alice.send(&retraction).unwrap();
+	assert_eq!(bob.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Retraction { target });
+	let (bob_pk_kyber, _) = kyber_keygen();
+	let peer = Peer::from_encoded(&encode(&bob_pk_kyber), &encode(sign_keygen().0), "bob", vec![capability::REACTION.to_string()]).unwrap();
+	assert_eq!(validate_outgoing(retraction.content(), &OutgoingRules::new().peer(&peer)), vec![Violation::UnsupportedByPeer(capability::RETRACTION)]);
+}
+
+#[test]
+fn test_oversized_media() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let limits = ParseLimits { max_data_len: 1000, ..ParseLimits::default() };
+	let picture = vec![7; 3000];
+	
+	// the error describes the media, in both wire formats
+	for format in [WireFormat::Json, WireFormat::Cbor] {
+		let (_, mdc, ciphertext) = send_msg_with_options(&OutgoingMessage::picture(&picture, "the whole team"), &SendOptions::new().format(format), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+		let oversized = match parse_msg_limited(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt, &limits) {
+			Err(DawnError::TooLarge(oversized)) => oversized,
+			_ => panic!("oversized picture not reported")
+		};
+		assert_eq!(*oversized, OversizedMedia { content_type: content_type::PICTURE, size: 3000, limit: 1000, description: Some("the whole team".to_string()), mdc });
+	}
+	let (_, _, ciphertext) = send_msg((content_type::VOICE, None, Some(&picture)), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let oversized = match parse_msg_limited(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt, &limits) {
+		Err(DawnError::TooLarge(oversized)) => oversized,
+		_ => panic!("oversized voice message not reported")
+	};
+	assert_eq!((oversized.content_type, oversized.description.clone()), (content_type::VOICE, None));
+	
+	// other oversized fields are still plain errors
+	let (_, _, ciphertext) = send_msg((content_type::HISTORY_SYNC, Some("4c5d6e7f\n0\n1"), Some(&picture)), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert!(matches!(parse_msg_limited(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt, &limits), Err(DawnError::InvalidInput(_))));
+	let limits = ParseLimits { max_text_len: 5, ..limits };
+	let (_, _, ciphertext) = send_msg((content_type::PICTURE, Some("the whole team"), Some(&picture)), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	assert!(matches!(parse_msg_limited(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt, &limits), Err(DawnError::InvalidInput(_))));
+	
+	// the receiver asks the sender for linked media
+	let request = oversized.resend_request().unwrap();
+	let (_, _, ciphertext) = send_msg(request.content(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let data = match parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap().0 {
+		ReceivedMessage::Internal { event: event::MEDIA_RESEND, data } => data,
+		_ => panic!("not a resend request")
+	};
+	assert_eq!(parse_media_resend_request(&data).unwrap(), MediaResendRequest { mdc: oversized.mdc.clone(), max_inline_size: 1000 });
+	assert!(parse_media_resend_request(br#"{"mdc":"","max_inline_size":1000}"#).is_err());
+}
+
+#[test]
+fn test_read_receipts() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (first, second) = (mdc_gen(), mdc_gen());
+	
+	// one receipt acknowledges a batch of messages
+	let receipt = gen_read_receipt(&[&first, &second, &first]).unwrap();
+	let (_, _, ciphertext) = send_msg(receipt.content(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let data = match parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap().0 {
+		ReceivedMessage::Internal { event: event::READ_RECEIPT, data } => data,
+		_ => panic!("not a read receipt")
+	};
+	assert_eq!(parse_read_receipt(&data).unwrap(), ReadReceipt { mdcs: vec![first.clone(), second] });
+	
+	// receipts list between one and MAX_RECEIPT_MDCS messages
+	assert!(gen_read_receipt(&[]).is_err());
+	assert!(gen_read_receipt(&[""]).is_err());
+	let mdcs: Vec<String> = (0..=MAX_RECEIPT_MDCS).map(|_| mdc_gen()).collect();
+	let mdcs: Vec<&str> = mdcs.iter().map(String::as_str).collect();
+	assert!(gen_read_receipt(&mdcs[1..]).is_ok());
+	assert!(gen_read_receipt(&mdcs).is_err());
+	assert!(parse_read_receipt(br#"{"mdcs":[]}"#).is_err());
+	assert!(parse_read_receipt(b"[]").is_err());
+	
+	// sessions send them like any other event
+	let (mut alice, mut bob) = gen_session_pair();
+	let (mdc, ciphertext) = alice.send(&OutgoingMessage::text("hi")).unwrap();
+	bob.receive(&ciphertext).unwrap().unwrap();
+	let (_, ciphertext) = bob.send(&gen_read_receipt(&[&mdc]).unwrap()).unwrap();
+	match alice.receive(&ciphertext).unwrap().unwrap().0 {
+		ReceivedMessage::Internal { event: event::READ_RECEIPT, data } => assert_eq!(parse_read_receipt(&data).unwrap().mdcs, vec![mdc]),
+		_ => panic!("not a read receipt")
+	}
+}
+
+#[test]
+fn test_protocol_config() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, id, mdc_seed } = gen_test_conversation();
+	let (pk_sig, _) = sign_keygen();
+	
+	// the default behaves like the plain functions
+	assert_eq!(ProtocolConfig::new(), ProtocolConfig::default());
+	assert_eq!(ProtocolConfig::new().strict(true).limits.mode, ParseMode::Strict);
+	let (_, _, unsigned) = send_msg_with_options(&OutgoingMessage::text("unsigned"), &SendOptions::new().config(ProtocolConfig::default()), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	assert!(parse_msg_with_config(&unsigned, &sk_kyber, Some(&pk_sig), &pfs_key, &pfs_salt, &ProtocolConfig::default()).is_err());
+	
+	// unsigned messages can be allowed, they still come with a warning
+	let config = ProtocolConfig::new().signature_policy(SignaturePolicy::AllowUnsigned);
+	let parsed = parse_msg_with_config(&unsigned, &sk_kyber, Some(&pk_sig), &pfs_key, &pfs_salt, &config).unwrap();
+	assert_eq!(parsed.message, ReceivedMessage::Text { text: "unsigned".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	assert_eq!(parsed.warning, Warning::Unsigned);
+	
+	// padded messages of different lengths can't be told apart by the length of their ciphertext
+	let config = ProtocolConfig::new().padding(Padding::Bucket(256));
+	for format in [WireFormat::Json, WireFormat::Cbor] {
+		let options = SendOptions::new().format(format).config(config);
+		let (_, _, short) = send_msg_with_options(&OutgoingMessage::text("hi"), &options, &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		let (_, _, long) = send_msg_with_options(&OutgoingMessage::text("a somewhat longer message"), &options, &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+		assert_eq!(short.len(), long.len());
+		let parsed = parse_msg_with_config(&short, &sk_kyber, None, &pfs_key, &pfs_salt, &ProtocolConfig::new().strict(true)).unwrap();
+		assert_eq!(parsed.message, ReceivedMessage::Text { text: "hi".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	}
+	assert!(send_msg_with_options(&OutgoingMessage::text("hi"), &SendOptions::new().config(ProtocolConfig::new().padding(Padding::Bucket(0))), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).is_err());
+	
+	// binary messages can be compressed, receivers only inflate them if compression is enabled
+	let text = "a".repeat(100_000);
+	let message = OutgoingMessage::text(&text);
+	let compression = ProtocolConfig::new().compression(true);
+	let cbor = SendOptions::new().format(WireFormat::Cbor);
+	let (_, _, plain) = send_msg_with_options(&message, &cbor, &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	let (_, _, compressed) = send_msg_with_options(&message, &cbor.clone().config(compression.padding(Padding::Bucket(64))), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	assert!(compressed.len() * 10 < plain.len());
+	assert_eq!(parse_msg_with_config(&compressed, &sk_kyber, None, &pfs_key, &pfs_salt, &compression).unwrap().message, ReceivedMessage::Text { text: text.clone(), effect: None, in_reply_to: None, expires_after: None });
+	assert!(parse_msg(&compressed, &sk_kyber, None, &pfs_key, &pfs_salt).is_err());
+	
+	// inflating is bounded by its own limit, which is finite by default
+	assert!(ParseLimits::default().max_decompressed_len < usize::MAX);
+	assert_eq!(ProtocolConfig::default().limits.max_decompressed_len, DEFAULT_MAX_DECOMPRESSED_LEN);
+	let limits = ParseLimits { max_decompressed_len: 50_000, ..ParseLimits::default() };
+	assert!(parse_msg_with_config(&compressed, &sk_kyber, None, &pfs_key, &pfs_salt, &compression.limits(limits)).is_err());
+	let bomb = "a".repeat(DEFAULT_MAX_DECOMPRESSED_LEN + 1);
+	let (_, _, bomb) = send_msg_with_options(&OutgoingMessage::text(&bomb), &cbor.config(compression), &pk_kyber, None, &pfs_key, &pfs_salt, &id, &mdc_seed).unwrap();
+	assert!(bomb.len() < 100_000);
+	assert!(parse_msg_with_config(&bomb, &sk_kyber, None, &pfs_key, &pfs_salt, &compression).is_err());
+	
+	// sessions compress for peers that announced it
+	assert!(capability::SUPPORTED.contains(&capability::COMPRESSION));
+	let (alice, bob) = gen_session_pair();
+	let mut alice = alice.config(compression);
+	let (_, ciphertext) = alice.send(&OutgoingMessage::text(&text)).unwrap();
+	assert!(ciphertext.len() * 10 < plain.len());
+	let mut bob = bob.config(compression);
+	let mut archive = bob.passive();
+	assert_eq!(bob.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Text { text: text.clone(), effect: None, in_reply_to: None, expires_after: None });
+	assert_eq!(archive.parse(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Text { text, effect: None, in_reply_to: None, expires_after: None });
+}
+
+#[test]
+fn test_delivery_receipts() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let mdc = mdc_gen();
+	
+	// a delivery receipt acknowledges one message with the time it arrived
+	let before = get_current_timestamp();
+	let receipt = gen_delivery_receipt(&mdc).unwrap();
+	let (_, _, ciphertext) = send_msg(receipt.content(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let receipt = match parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap().0 {
+		ReceivedMessage::Internal { event: event::DELIVERY_RECEIPT, data } => parse_delivery_receipt(&data).unwrap(),
+		_ => panic!("not a delivery receipt")
+	};
+	assert_eq!(receipt.mdc, mdc);
+	assert!(receipt.delivered_at >= before && receipt.delivered_at <= get_current_timestamp());
+	assert!(gen_delivery_receipt("").is_err());
+	assert!(parse_delivery_receipt(br#"{"mdc":"","delivered_at":0}"#).is_err());
+	assert!(parse_delivery_receipt(br#"{"mdcs":["a"]}"#).is_err());
+	
+	// sessions stamp them with their clock
+	let (mut alice, bob) = gen_session_pair();
+	let mut bob = bob.clock(ManualClock::new(1_700_000_000));
+	let (mdc, ciphertext) = alice.send(&OutgoingMessage::text("hi")).unwrap();
+	bob.receive(&ciphertext).unwrap().unwrap();
+	let (_, ciphertext) = bob.send_delivery_receipt(&mdc).unwrap();
+	match alice.receive(&ciphertext).unwrap().unwrap().0 {
+		ReceivedMessage::Internal { event: event::DELIVERY_RECEIPT, data } => assert_eq!(parse_delivery_receipt(&data).unwrap(), DeliveryReceipt { mdc, delivered_at: 1_700_000_000 }),
+		_ => panic!("not a delivery receipt")
+	}
+}
+
+#[test]
+fn test_conformance() {
+	// the vectors shipped with the library pass
+	let report = run_conformance(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/fixtures/conformance")).unwrap();
+	assert!(report.all_passed(), "{}", report.to_json().unwrap());
+	assert_eq!(report.passed, include_str!("fixtures/conformance/plaintext.jsonl").lines().count());
+	
+	// message and handshake vectors as another implementation would produce them
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	let (_, mdc, ciphertext) = send_msg((content_type::VOICE, None, Some(&[1, 2, 3])), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let message = |expect: &str| format!(r#"{{"kind":"message","name":"voice","ciphertext":"{}","own_seckey_kyber":"{}","remote_pubkey_sig":null,"pfs_key":"{}","pfs_salt":"{}",{}}}"#, hex::encode(&ciphertext), hex::encode(&sk_kyber), hex::encode(&pfs_key), hex::encode(&pfs_salt), expect);
+	let alice = Identity::generate().unwrap();
+	let bob = Identity::generate().unwrap();
+	let request = alice.init_request_builder().handle(bob.gen_handle("bob", &mdc_gen())).unwrap().name("alice").comment("hi bob").build().unwrap();
+	let keys = [bob.init_seckey_kyber.as_bytes(), bob.init_seckey_curve.as_bytes(), bob.init_seckey_curve_pfs_2.as_bytes(), bob.init_seckey_kyber_for_salt.as_bytes(), bob.init_seckey_curve_for_salt.as_bytes()].map(hex::encode);
+	let handshake = |request: &[u8], expect: &str| format!(r#"{{"kind":"handshake","name":"init request","request":"{}","own_seckey_kyber":"{}","own_seckey_curve":"{}","own_seckey_curve_pfs_2":"{}","own_seckey_kyber_for_salt":"{}","own_seckey_curve_for_salt":"{}",{}}}"#, hex::encode(request), keys[0], keys[1], keys[2], keys[3], keys[4], expect);
+	let capabilities = serde_json::to_string(&bob.parse_init_request(&request.ciphertext).unwrap().peer.capabilities).unwrap();
+	let vectors = [
+		message(&format!(r#""expect":{{"content_type":2,"data":"010203","mdc":"{}"}}"#, mdc)),
+		handshake(&request.ciphertext, &format!(r#""expect":{{"id":"{}","name":"alice","comment":"hi bob","capabilities":{}}}"#, request.id, capabilities)),
+		handshake(&request.ciphertext[1..], r#""expect_error":"crypto""#),
+	].join("\n");
+	let report = run_conformance_vectors(&[("generated.jsonl", &vectors)]);
+	assert!(report.all_passed(), "{}", report.to_json().unwrap());
+	assert_eq!(report.passed, 3);
+	
+	// failures are reported with the reason, the report is machine-readable
+	let failing = [
+		message(&format!(r#""expect":{{"content_type":2,"data":"0102","mdc":"{}"}}"#, mdc)),
+		message(r#""expect_error":"crypto""#),
+		message(r#""expect":{"content_type":2,"mdc":""},"expect_error":"crypto""#),
+		"{\"kind\":\"sticker\"}".to_string(),
+	].join("\n");
+	let report = run_conformance_vectors(&[("failing.jsonl", &failing)]);
+	assert_eq!((report.passed, report.failed), (0, 4));
+	assert_eq!(report.results[3].name, "line 4");
+	assert!(report.results.iter().all(|result| result.file == "failing.jsonl" && result.detail.is_some()));
+	let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+	assert_eq!(json["failed"], 4);
+	assert_eq!(json["protocol_version"], PROTOCOL_VERSION);
+	assert!(run_conformance(std::path::Path::new("/nonexistent/dawn-vectors")).is_err());
+	assert_eq!(DawnError::Corrupted.code(), "corrupted");
+}
+
+#[test]
+fn test_typing_events() {
+	let (mut alice, bob) = gen_session_pair();
+	let clock = ManualClock::new(1_700_000_000);
+	let mut bob = bob.clock(clock.clone());
+	let mut receive = |message: &OutgoingMessage| {
+		let (_, ciphertext) = alice.send(message).unwrap();
+		let received = bob.receive(&ciphertext).unwrap().unwrap().0;
+		parse_typing_event(&received, bob.now()).unwrap()
+	};
+	
+	// started indicators expire on their own, so a lost stop event doesn't leave them behind
+	assert_eq!(receive(&gen_typing_started()), Some(TypingEvent::Started { expires_at: 1_700_000_000 + TYPING_TIMEOUT }));
+	clock.advance(TYPING_REFRESH_INTERVAL);
+	assert_eq!(receive(&gen_typing_started()), Some(TypingEvent::Started { expires_at: 1_700_000_000 + TYPING_REFRESH_INTERVAL + TYPING_TIMEOUT }));
+	assert_eq!(receive(&gen_typing_stopped()), Some(TypingEvent::Stopped));
+	
+	// other messages and presence updates are not typing events
+	assert_eq!(receive(&OutgoingMessage::text("hi")), None);
+	assert_eq!(receive(&OutgoingMessage::internal(event::PRESENCE, &gen_presence_update(PresenceUpdate::ReadUpTo(3)))), None);
+	assert!(parse_typing_event(&ReceivedMessage::Internal { event: event::PRESENCE, data: vec![1, 2] }, 0).is_err());
+	
+	// they are the same updates the aggregator understands
+	let mut aggregator = PresenceAggregator::new();
+	let data = gen_typing_started().content().2.unwrap().to_vec();
+	aggregator.apply("alice", parse_presence_update(&data).unwrap(), 100);
+	assert_eq!(aggregator.typing(100 + TYPING_TIMEOUT - 1), vec!["alice"]);
+	assert!(aggregator.typing(100 + TYPING_TIMEOUT).is_empty());
+}
+
+#[test]
+fn test_disappearing_messages() {
+	let TestConversation { pk_kyber, sk_kyber, pfs_key, pfs_salt, .. } = gen_test_conversation();
+	
+	// the timer event carries the expiry or None to turn it off
+	let timer = gen_disappearing_timer(Some(3600)).unwrap();
+	let (_, _, ciphertext) = send_msg(timer.content(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	match parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap().0 {
+		ReceivedMessage::Internal { event: event::DISAPPEARING_TIMER, data } => assert_eq!(parse_disappearing_timer(&data).unwrap(), DisappearingTimer { expires_after: Some(3600) }),
+		_ => panic!("not a disappearing timer")
+	}
+	assert!(gen_disappearing_timer(None).is_ok());
+	assert!(gen_disappearing_timer(Some(0)).is_err());
+	assert!(gen_disappearing_timer(Some(MAX_EXPIRES_AFTER + 1)).is_err());
+	assert!(parse_disappearing_timer(br#"{"expires_after":0}"#).is_err());
+	
+	// single messages can disappear without a timer
+	let (_, _, ciphertext) = send_msg_with_options(&OutgoingMessage::text("burn after reading").with_expires_after(60).unwrap(), &SendOptions::new(), &pk_kyber, None, &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+	let received = parse_msg(&ciphertext, &sk_kyber, None, &pfs_key, &pfs_salt).unwrap().0;
+	assert_eq!(received, ReceivedMessage::Text { text: "burn after reading".to_string(), effect: None, in_reply_to: None, expires_after: Some(60) });
+	assert!(OutgoingMessage::text("now").with_expires_after(0).is_err());
+	assert!(OutgoingMessage::reaction("target", "👍").unwrap().with_expires_after(60).is_err());
+	assert!(OutgoingMessage::text("hi").with_expires_after(MAX_EXPIRES_AFTER + 1).is_err());
+	
+	// a timer set by one side applies to the messages of both sides
+	let (mut alice, mut bob) = gen_session_pair();
+	let (_, ciphertext) = alice.set_disappearing_timer(Some(86400)).unwrap();
+	assert_eq!(alice.disappearing_timer(), Some(86400));
+	bob.receive(&ciphertext).unwrap().unwrap();
+	assert_eq!(bob.disappearing_timer(), Some(86400));
+	let (_, ciphertext) = bob.send(&OutgoingMessage::voice(&[1, 2, 3])).unwrap();
+	assert_eq!(alice.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Voice { data: vec![1, 2, 3], transcription: None, in_reply_to: None, codec: None, expires_after: Some(86400) });
+	
+	// an expiry of the message itself takes precedence, receipts don't disappear
+	let (mdc, ciphertext) = alice.send(&OutgoingMessage::text("quick").with_expires_after(10).unwrap()).unwrap();
+	assert_eq!(bob.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Text { text: "quick".to_string(), effect: None, in_reply_to: None, expires_after: Some(10) });
+	let (_, ciphertext) = bob.send_delivery_receipt(&mdc).unwrap();
+	assert!(matches!(alice.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Internal { event: event::DELIVERY_RECEIPT, .. }));
+	
+	// turning the timer off
+	let (_, ciphertext) = bob.set_disappearing_timer(None).unwrap();
+	alice.receive(&ciphertext).unwrap().unwrap();
+	assert_eq!(alice.disappearing_timer(), None);
+	let (_, ciphertext) = alice.send(&OutgoingMessage::text("stays")).unwrap();
+	assert_eq!(bob.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Text { text: "stays".to_string(), effect: None, in_reply_to: None, expires_after: None });
+	
+	// the timer survives an export
+	alice.set_disappearing_timer(Some(300)).unwrap();
+	let restored = Session::import(&alice.export("passphrase").unwrap(), "passphrase").unwrap();
+	assert_eq!(restored.disappearing_timer(), Some(300));
+}
+
+#[test]
+fn test_message_targets() {
+	let received_mdc = |received: Option<(ReceivedMessage, String, Warning)>| received.unwrap().1;
+	let receipt_mdcs = |received: ReceivedMessage| match received {
+		ReceivedMessage::Internal { event: event::READ_RECEIPT, data } => parse_read_receipt(&data).unwrap().mdcs,
+		ReceivedMessage::Internal { event: event::DELIVERY_RECEIPT, data } => vec![parse_delivery_receipt(&data).unwrap().mdc],
+		_ => panic!("not a receipt")
+	};
+	
+	// every message of a session has its own mdc, which is what the receiver sees
+	let (mut alice, mut bob) = gen_session_pair();
+	let (first, first_ciphertext) = alice.send(&OutgoingMessage::text("first")).unwrap();
+	let (second, second_ciphertext) = alice.send(&OutgoingMessage::text("second")).unwrap();
+	assert_ne!(first, second);
+	assert_eq!(received_mdc(bob.receive(&first_ciphertext).unwrap()), first);
+	assert_eq!(received_mdc(bob.receive(&second_ciphertext).unwrap()), second);
+	
+	// reactions, replies and receipts each name the message they were sent for
+	let reply = Reply::new(&second, Some("second")).unwrap();
+	let (_, ciphertext) = bob.send(&OutgoingMessage::reaction(&first, "👍").unwrap()).unwrap();
+	assert_eq!(alice.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Reaction { target: first.clone(), reaction: "👍".to_string() });
+	let (_, ciphertext) = bob.send(&OutgoingMessage::text("yes").with_reply(reply.clone()).unwrap()).unwrap();
+	assert_eq!(alice.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Text { text: "yes".to_string(), effect: None, in_reply_to: Some(reply), expires_after: None });
+	let (_, ciphertext) = bob.send(&gen_read_receipt(&[&second]).unwrap()).unwrap();
+	assert_eq!(receipt_mdcs(alice.receive(&ciphertext).unwrap().unwrap().0), vec![second.clone()]);
+	let (_, ciphertext) = bob.send_delivery_receipt(&first).unwrap();
+	assert_eq!(receipt_mdcs(alice.receive(&ciphertext).unwrap().unwrap().0), vec![first.clone()]);
+	let (_, ciphertext) = alice.send(&OutgoingMessage::retraction(&second).unwrap()).unwrap();
+	assert_eq!(bob.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Retraction { target: second });
+	
+	// the same holds for the per-message mdcs of a group
+	let sender_key = |group: &Group| group.sender_key_message().unwrap().content().2.unwrap().to_vec();
+	let config = ProtocolConfig::default();
+	let mut alice = Group::create("alice", &["bob"]).unwrap();
+	let mut bob = Group::join("bob", "alice", &sender_key(&alice)).unwrap();
+	alice.process_sender_key("bob", &sender_key(&bob)).unwrap();
+	let (first, first_ciphertext) = send_group_msg(&mut alice, &OutgoingMessage::text("first")).unwrap();
+	let (second, second_ciphertext) = send_group_msg(&mut alice, &OutgoingMessage::text("second")).unwrap();
+	assert_ne!(first, second);
+	assert_eq!(parse_group_msg(&mut bob, &first_ciphertext, &config).unwrap().2, first);
+	assert_eq!(parse_group_msg(&mut bob, &second_ciphertext, &config).unwrap().2, second);
+	
+	let reply = Reply::new(&second, Some("second")).unwrap();
+	let (_, ciphertext) = send_group_msg(&mut bob, &OutgoingMessage::reaction(&first, "👍").unwrap()).unwrap();
+	assert_eq!(parse_group_msg(&mut alice, &ciphertext, &config).unwrap().1, ReceivedMessage::Reaction { target: first.clone(), reaction: "👍".to_string() });
+	let (_, ciphertext) = send_group_msg(&mut bob, &OutgoingMessage::text("yes").with_reply(reply.clone()).unwrap()).unwrap();
+	assert_eq!(parse_group_msg(&mut alice, &ciphertext, &config).unwrap().1, ReceivedMessage::Text { text: "yes".to_string(), effect: None, in_reply_to: Some(reply), expires_after: None });
+	let (_, ciphertext) = send_group_msg(&mut bob, &gen_read_receipt(&[&second]).unwrap()).unwrap();
+	assert_eq!(receipt_mdcs(parse_group_msg(&mut alice, &ciphertext, &config).unwrap().1), vec![second.clone()]);
+	let (_, ciphertext) = send_group_msg(&mut bob, &gen_delivery_receipt(&first).unwrap()).unwrap();
+	assert_eq!(receipt_mdcs(parse_group_msg(&mut alice, &ciphertext, &config).unwrap().1), vec![first]);
+	let (_, ciphertext) = send_group_msg(&mut alice, &OutgoingMessage::retraction(&second).unwrap()).unwrap();
+	assert_eq!(parse_group_msg(&mut bob, &ciphertext, &config).unwrap().1, ReceivedMessage::Retraction { target: second });
+}
+
+#[test]
+fn test_combined_features() {
+	let conversation = gen_test_conversation();
+	let (_, target, root) = conversation.send(&OutgoingMessage::text("voice or picture?"), &SendOptions::new()).unwrap();
+	let reply = Reply::new(&target, Some("voice or picture?")).unwrap();
+	
+	// reply, expiry and codec of one message go through a single send
+	let voice = OutgoingMessage::voice(&[1, 2, 3]).with_reply(reply.clone()).unwrap().with_expires_after(3600).unwrap().with_codec(media_codec::OPUS).unwrap();
+	let (_, mdc, ciphertext) = conversation.send(&voice, &SendOptions::new()).unwrap();
+	let parsed = conversation.parse(&ciphertext, &ProtocolConfig::default()).unwrap();
+	assert_eq!(parsed.message, ReceivedMessage::Voice { data: vec![1, 2, 3], transcription: None, in_reply_to: Some(reply.clone()), codec: Some(media_codec::OPUS.to_string()), expires_after: Some(3600) });
+	assert_eq!((parsed.mdc, parsed.thread_id, parsed.seq), (mdc, None, None));
+	
+	// the options combine as well: thread, binary format with compression, sequence number and device counter
+	let thread_id = thread_id_for(&root);
+	let config = ProtocolConfig::new().compression(true);
+	let mut sequence = SequenceCounter::new();
+	let mut device = DeviceCounter::new("phone");
+	let picture = OutgoingMessage::picture(&[7; 2000], "the menu").with_reply(reply.clone()).unwrap().with_effect(Effect::Confetti).unwrap().with_expires_after(60).unwrap();
+	let options = SendOptions::new().format(WireFormat::Cbor).config(config).thread(&thread_id).sequenced(&mut sequence).unwrap().counted(&mut device).unwrap();
+	let (_, _, ciphertext) = conversation.send(&picture, &options).unwrap();
+	assert!(conversation.parse(&ciphertext, &ProtocolConfig::default()).is_err());
+	let parsed = conversation.parse(&ciphertext, &config).unwrap();
+	assert_eq!(parsed.message, ReceivedMessage::Picture { data: vec![7; 2000], description: "the menu".to_string(), alt_text: None, effect: Some(Effect::Confetti), in_reply_to: Some(reply.clone()), codec: None, expires_after: Some(60) });
+	assert_eq!((parsed.thread_id.as_deref(), parsed.seq), (Some(thread_id.as_str()), Some(0)));
+	assert_eq!(parsed.check_device(&mut CounterTracker::new()), None);
+	assert_eq!(sequence.next_seq(), 1);
+	
+	// escrow is combined with the JSON format only
+	let (escrow_pubkey, escrow_seckey) = gen_kyber_keypair().unwrap();
+	let (_, mdc, ciphertext) = conversation.send(&voice, &SendOptions::new().thread(&thread_id).escrow(&escrow_pubkey)).unwrap();
+	let parsed = conversation.parse(&ciphertext, &ProtocolConfig::default()).unwrap();
+	assert_eq!(parsed.thread_id.as_deref(), Some(thread_id.as_str()));
+	assert_eq!(open_escrow(&ciphertext, &escrow_pubkey, &escrow_seckey).unwrap(), (parsed.message, mdc));
+	assert!(conversation.send(&voice, &SendOptions::new().format(WireFormat::Cbor).escrow(&escrow_pubkey)).is_err());
+	
+	// sessions send the same combination with their own options
+	let (mut alice, mut bob) = gen_session_pair();
+	let (_, ciphertext) = alice.set_disappearing_timer(Some(86400)).unwrap();
+	bob.receive(&ciphertext).unwrap().unwrap();
+	let (_, ciphertext) = alice.send(&voice).unwrap();
+	assert_eq!(bob.receive(&ciphertext).unwrap().unwrap().0, ReceivedMessage::Voice { data: vec![1, 2, 3], transcription: None, in_reply_to: Some(reply), codec: Some(media_codec::OPUS.to_string()), expires_after: Some(3600) });
+}
@@ -0,0 +1,94 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Threads inside a conversation
+// A thread is rooted at a message and identified by the hash of the root ciphertext, so every member of the conversation
+// derives the same thread id without further coordination. Messages are put into a thread with SendOptions::thread and
+// the thread_id of a ParsedMessage tells which thread they belong to. The ThreadIndex keeps track of which messages belong to which
+// thread on the client.
+
+use std::collections::HashMap;
+use dawn_crypto::hash;
+use crate::codec::encode;
+
+// id of a thread, only the first 16 bytes of the hash are used
+pub fn thread_id_for(root_ciphertext: &[u8]) -> String {
+	let root_hash = hash(root_ciphertext);
+	encode(root_hash.get(..16).unwrap_or(&root_hash))
+}
+
+struct Thread {
+	messages: Vec<String>,
+	participants: Vec<String>,
+}
+
+// messages and participants of the threads in a conversation
+// messages are referenced by an id chosen by the client (e.g. the id of its database entry)
+#[derive(Default)]
+pub struct ThreadIndex {
+	threads: HashMap<String, Thread>,
+}
+
+impl ThreadIndex {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// start a thread at a message
+	// returns the thread id
+	pub fn create(&mut self, root_ciphertext: &[u8], root_message: &str, root_sender: &str) -> String {
+		let thread_id = thread_id_for(root_ciphertext);
+		self.add(&thread_id, root_message, root_sender);
+		thread_id
+	}
+	
+	// add a message sent or received in a thread
+	pub fn add(&mut self, thread_id: &str, message: &str, sender: &str) {
+		let thread = self.threads.entry(thread_id.to_string()).or_insert_with(|| Thread {
+			messages: Vec::new(),
+			participants: Vec::new(),
+		});
+		if !thread.messages.iter().any(|known| known == message) {
+			thread.messages.push(message.to_string());
+		}
+		if !thread.participants.iter().any(|known| known == sender) {
+			thread.participants.push(sender.to_string());
+		}
+	}
+	
+	// returns the messages of a thread in the order they were added, starting with the root
+	pub fn messages(&self, thread_id: &str) -> &[String] {
+		match self.threads.get(thread_id) {
+			Some(thread) => &thread.messages,
+			None => &[]
+		}
+	}
+	
+	// returns everyone who sent a message into the thread
+	pub fn participants(&self, thread_id: &str) -> &[String] {
+		match self.threads.get(thread_id) {
+			Some(thread) => &thread.participants,
+			None => &[]
+		}
+	}
+	
+	// returns the ids of all threads
+	pub fn threads(&self) -> Vec<&str> {
+		self.threads.keys().map(|thread_id| thread_id.as_str()).collect()
+	}
+}
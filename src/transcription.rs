@@ -0,0 +1,68 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Voice message transcriptions
+// The sender of a voice message can attach a transcription of it together with a language tag (e.g. "en" or "pt-BR"),
+// so recipients can read voice messages without running speech recognition on their device. In the (content type,
+// text, data) form of a voice message, the text holds the language tag in its first line and the transcription in the
+// following lines (see OutgoingMessage::voice_with_transcription).
+
+use serde::{Serialize, Deserialize};
+use crate::DawnError;
+
+// language tags are at most 35 characters long (RFC 5646)
+pub const MAX_LANGUAGE_TAG_LEN: usize = 35;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Transcription {
+	pub text: String,
+	pub language: String, // language tag of the spoken language
+}
+
+// check the form of a language tag: subtags of letters and digits separated by hyphens
+fn check_language_tag(language: &str) -> Result<(), DawnError> {
+	if language.is_empty() || language.len() > MAX_LANGUAGE_TAG_LEN { error!("language tag must be between 1 and 35 characters"); }
+	if language.split('-').any(|subtag| subtag.is_empty() || subtag.len() > 8 || !subtag.bytes().all(|byte| byte.is_ascii_alphanumeric())) {
+		error!("language tag invalid");
+	}
+	Ok(())
+}
+
+impl Transcription {
+	pub fn new(text: &str, language: &str) -> Result<Self, DawnError> {
+		if text.is_empty() { error!("transcription must not be empty"); }
+		if let Err(err) = check_language_tag(language) { return Err(err); }
+		Ok(Transcription {
+			text: text.to_string(),
+			language: language.to_string(),
+		})
+	}
+	
+	// returns the transcription in the form of the text of a voice message
+	pub(crate) fn pack(&self) -> String {
+		format!("{}\n{}", self.language, self.text)
+	}
+	
+	// read a transcription from the text of a voice message
+	pub(crate) fn unpack(text: &str) -> Result<Self, DawnError> {
+		match text.split_once('\n') {
+			Some((language, text)) => Transcription::new(text, language),
+			None => error!("transcription is missing its language tag")
+		}
+	}
+}
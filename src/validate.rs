@@ -0,0 +1,136 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Validation of outgoing messages
+// validate_outgoing checks a message against everything that would make sending it fail or make the peer reject it,
+// without encrypting it: the fields the content type requires, the limits the peer parses with, the capabilities the peer
+// announced and the policy of an application identity. All violations are returned at once, so clients can show them
+// next to the affected input before the user hits send.
+
+use crate::{build_message, capability, content_type};
+use crate::outgoing::{MessageParts, SendOptions};
+use crate::content_type::ContentType;
+use crate::application::ApplicationPolicy;
+use crate::estimate::estimate_ciphertext_len;
+use crate::limits::ParseLimits;
+use crate::peer::Peer;
+use crate::DawnError;
+use dawn_crypto::mdc_gen;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+	MissingText, // the content type requires text (e.g. the link of linked media)
+	MissingData, // the content type requires data (e.g. the voice recording)
+	InvalidContent(DawnError), // the fields are present but malformed
+	TooLarge { field: &'static str, len: usize, max: usize }, // field is "text", "data" or "ciphertext"
+	UnsupportedByPeer(&'static str), // the capability the peer didn't announce
+	NotPermitted, // the application policy doesn't allow this content type in the conversation
+}
+
+// what outgoing messages are checked against, nothing but the content itself by default
+#[derive(Clone, Debug, Default)]
+pub struct OutgoingRules<'a> {
+	peer: Option<&'a Peer>,
+	limits: ParseLimits,
+	signed: bool,
+	policy: Option<(&'a ApplicationPolicy, &'a str)>,
+}
+
+impl<'a> OutgoingRules<'a> {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	
+	// check the capabilities announced by the peer
+	pub fn peer(mut self, peer: &'a Peer) -> Self {
+		self.peer = Some(peer);
+		self
+	}
+	
+	// check against the limits the peer parses with (e.g. ParseLimits::low_memory for a watch)
+	pub fn limits(mut self, limits: ParseLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+	
+	// the message will be signed, which makes the ciphertext larger
+	pub fn signed(mut self, signed: bool) -> Self {
+		self.signed = signed;
+		self
+	}
+	
+	// the message is sent by an application identity into the conversation with the given id
+	pub fn policy(mut self, policy: &'a ApplicationPolicy, id: &'a str) -> Self {
+		self.policy = Some((policy, id));
+		self
+	}
+}
+
+// capability the receiver needs to announce for a content type
+fn required_capability(msg_type: ContentType) -> Option<&'static str> {
+	match msg_type {
+		content_type::LINKED_MEDIA => Some(capability::LINKED_MEDIA),
+		content_type::HISTORY_SYNC => Some(capability::HISTORY_SYNC),
+		content_type::DELTA_SYNC => Some(capability::DELTA_SYNC),
+		content_type::REACTION => Some(capability::REACTION),
+		content_type::RETRACTION => Some(capability::RETRACTION),
+		_ => None
+	}
+}
+
+// check a message before sending it (content as passed to send_msg)
+// returns all violations, an empty list means the message can be sent
+pub fn validate_outgoing(content: (ContentType, Option<&str>, Option<&[u8]>), rules: &OutgoingRules) -> Vec<Violation> {
+	let (msg_type, msg_text, msg_data) = content;
+	let mut violations = Vec::new();
+	
+	// required fields (pictures may come without a description)
+	let needs_text = matches!(msg_type, content_type::TEXT | content_type::INTERNAL | content_type::REACTION | content_type::RETRACTION | content_type::LINKED_MEDIA | content_type::HISTORY_SYNC | content_type::GATEWAY);
+	let needs_data = matches!(msg_type, content_type::INTERNAL | content_type::VOICE | content_type::PICTURE | content_type::LINKED_MEDIA | content_type::HISTORY_SYNC | content_type::DELTA_SYNC);
+	if needs_text && msg_text.is_none() { violations.push(Violation::MissingText); }
+	if needs_data && msg_data.is_none() { violations.push(Violation::MissingData); }
+	
+	// the format of the fields is only checked once they are all there
+	let complete = violations.is_empty();
+	if complete {
+		if let Err(err) = build_message(MessageParts::from_content(content), &mdc_gen(), &SendOptions::default()) { violations.push(Violation::InvalidContent(err)); }
+	}
+	
+	// sizes
+	let limits = &rules.limits;
+	if let Some(text) = msg_text {
+		if text.len() > limits.max_text_len { violations.push(Violation::TooLarge { field: "text", len: text.len(), max: limits.max_text_len }); }
+	}
+	if let Some(data) = msg_data {
+		if data.len() > limits.max_data_len { violations.push(Violation::TooLarge { field: "data", len: data.len(), max: limits.max_data_len }); }
+	}
+	if complete && limits.max_ciphertext_len != usize::MAX {
+		if let Ok(len) = estimate_ciphertext_len(content, rules.signed) {
+			if len > limits.max_ciphertext_len { violations.push(Violation::TooLarge { field: "ciphertext", len, max: limits.max_ciphertext_len }); }
+		}
+	}
+	
+	// peer and policy
+	if let (Some(peer), Some(capability)) = (rules.peer, required_capability(msg_type)) {
+		if !peer.supports(capability) { violations.push(Violation::UnsupportedByPeer(capability)); }
+	}
+	if let Some((policy, id)) = rules.policy {
+		if !policy.permits(msg_type, id) { violations.push(Violation::NotPermitted); }
+	}
+	violations
+}
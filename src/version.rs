@@ -0,0 +1,92 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Protocol versions
+// Every message carries the version of the protocol it was built with, messages of clients that predate versions are
+// version 1. A received message is dispatched on its version before the rest of it is parsed, so a message of a later
+// version fails with DawnError::UnsupportedVersion instead of a parse error. Clients announce the versions they
+// support as capabilities, negotiate_version picks the one to send to a peer.
+
+use std::collections::HashMap;
+use serde::Deserialize;
+use crate::capability;
+use crate::DawnError;
+
+// version of the messages this library builds
+pub const PROTOCOL_VERSION: u16 = 1;
+
+// versions this library can parse
+pub const SUPPORTED_VERSIONS: [u16; 1] = [1];
+
+// version of the messages without a version field
+pub(crate) const LEGACY_VERSION: u16 = 1;
+
+// only the version of a message, all other fields are skipped
+#[derive(Deserialize)]
+struct VersionProbe {
+	#[serde(default = "legacy_version")]
+	protocol_version: u16,
+}
+
+pub(crate) fn legacy_version() -> u16 {
+	LEGACY_VERSION
+}
+
+// capabilities announcing the supported versions
+pub(crate) fn capabilities() -> Vec<String> {
+	SUPPORTED_VERSIONS.iter().map(|version| format!("{}{}", capability::PROTOCOL_VERSION_PREFIX, version)).collect()
+}
+
+// a message is a map with a single entry (see Message), the highest version is taken in case there are more
+fn probe_version(probe: HashMap<String, VersionProbe>) -> u16 {
+	probe.values().map(|probe| probe.protocol_version).max().unwrap_or(LEGACY_VERSION)
+}
+
+// read the version of a decrypted JSON message
+pub(crate) fn message_version(msg_content: &str) -> Result<u16, DawnError> {
+	match serde_json::from_str::<HashMap<String, VersionProbe>>(msg_content) {
+		Ok(res) => Ok(probe_version(res)),
+		Err(_) => error!(Serialization, "json parsing failed")
+	}
+}
+
+// read the version of a decrypted binary message
+pub(crate) fn binary_message_version(msg_content: &[u8]) -> Result<u16, DawnError> {
+	match ciborium::from_reader::<HashMap<String, VersionProbe>, _>(msg_content) {
+		Ok(res) => Ok(probe_version(res)),
+		Err(_) => error!(Serialization, "cbor parsing failed")
+	}
+}
+
+// the versions a peer announced in its capabilities, peers that predate versions only support version 1
+pub fn peer_versions(capabilities: &[String]) -> Vec<u16> {
+	let versions: Vec<u16> = capabilities.iter().filter_map(|capability| capability.strip_prefix(capability::PROTOCOL_VERSION_PREFIX)).filter_map(|version| version.parse::<u16>().ok()).collect();
+	match versions.is_empty() {
+		true => vec![LEGACY_VERSION],
+		false => versions
+	}
+}
+
+// the highest version both this library and the peer support
+pub fn negotiate_version(capabilities: &[String]) -> Result<u16, DawnError> {
+	let versions = peer_versions(capabilities);
+	match SUPPORTED_VERSIONS.iter().filter(|version| versions.contains(version)).max() {
+		Some(version) => Ok(*version),
+		None => Err(DawnError::UnsupportedVersion(versions.iter().copied().max().unwrap_or(LEGACY_VERSION)))
+	}
+}
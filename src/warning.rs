@@ -0,0 +1,46 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Warnings returned by dawn_crypto when decrypting a message
+// Every parse function passes its warning through check_warning, so the policy lives in one place. Warnings that pass the
+// policy are returned to the client along with the parsed message.
+
+use dawn_crypto::warning;
+use crate::DawnError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Warning {
+	None,
+	Unsigned, // the message did not carry a signature
+}
+
+impl Warning {
+	pub(crate) fn from_code(code: u8) -> Self {
+		// dawn_crypto only warns about missing signatures
+		if code == warning::NONE { Warning::None } else { Warning::Unsigned }
+	}
+}
+
+// decide whether a message with the given warning may be processed
+// messages without signature are rejected if signature verification was requested by passing the remote signature key
+pub fn check_warning(warning: Warning, remote_pubkey_sig: Option<&[u8]>) -> Result<(), DawnError> {
+	if warning != Warning::None && remote_pubkey_sig.is_some() {
+		return Err(DawnError::SignatureWarning(warning));
+	}
+	Ok(())
+}
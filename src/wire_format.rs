@@ -0,0 +1,221 @@
+/*	Copyright (c) 2022, 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// Binary wire format
+// JSON carries the binary content of voice messages, pictures and other data base64 encoded, which makes it about a third
+// larger before encryption. Messages in the binary format are serialized as CBOR instead, with binary fields as byte
+// strings. dawn_crypto only encrypts text, so the CBOR message is encrypted with a fresh key using encrypt_data, and
+// encrypt_msg encrypts that key together with a hash of the encrypted message. This keeps the message under the pfs
+// ratchet and the signature of the sender. The ciphertext of a binary message starts with a magic and a version byte:
+// "DWB" (3) ‖ format version (1) ‖ length of the inner ciphertext (u32 BE) ‖ inner ciphertext ‖ encrypted CBOR message
+// Messages without the magic are JSON messages, which are still parsed as before. Clients announce that they can parse
+// binary messages with capability::BINARY_FORMAT, Session sends binary messages to peers that announced it.
+// A deflated CBOR message is marked by a third line in the inner plaintext, so the marker is covered by the signature.
+
+use serde::{Serializer, Deserializer, Deserialize};
+use serde::de::{self, Visitor};
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use dawn_crypto::{encrypt_msg, decrypt_msg, encrypt_data, decrypt_data, sym_key_gen, hash};
+use crate::capability;
+use crate::codec::{encode, decode, encode_base64, decode_base64, split_bytes};
+use crate::domain::BINARY_PAYLOAD_DOMAIN;
+use crate::DawnError;
+
+const BINARY_MAGIC: &[u8] = b"DWB";
+const PAYLOAD_KEY_LEN: usize = 32;
+const DEFLATE_MARKER: &str = "deflate";
+
+// version of the binary format, messages of other versions are rejected
+pub const BINARY_FORMAT_VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WireFormat {
+	#[default]
+	Json,
+	Cbor,
+}
+
+impl WireFormat {
+	// the most compact format the peer announced support for
+	pub fn for_peer(capabilities: &[String]) -> Self {
+		match capabilities.iter().any(|capability| capability == capability::BINARY_FORMAT) {
+			true => WireFormat::Cbor,
+			false => WireFormat::Json
+		}
+	}
+}
+
+// hash of the encrypted CBOR message, signed together with its key
+fn payload_hash(payload: &[u8]) -> Vec<u8> {
+	hash(&[BINARY_PAYLOAD_DOMAIN.as_bytes(), payload].concat())
+}
+
+// deflate a CBOR message before it is sealed with compressed set
+pub(crate) fn compress(message: &[u8]) -> Vec<u8> {
+	compress_to_vec(message, 6)
+}
+
+// encrypt a CBOR message, compressed marks a message that was deflated with compress
+// returns the ciphertext and the new pfs key
+pub(crate) fn seal_binary(message: &[u8], compressed: bool, remote_pubkey_kyber: &[u8], own_seckey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8]) -> Result<(Vec<u8>, Vec<u8>), DawnError> {
+	let payload_key = sym_key_gen();
+	let payload = match encrypt_data(message, &payload_key) {
+		Ok(res) => res,
+		Err(err) => return Err(DawnError::Crypto(err))
+	};
+	let mut inner = format!("{}\n{}", encode(&payload_key), encode(payload_hash(&payload)));
+	if compressed { inner = format!("{}\n{}", inner, DEFLATE_MARKER); }
+	let (inner_ciphertext, new_pfs_key) = match encrypt_msg(remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt, &inner) {
+		Ok(res) => res,
+		Err(err) => return Err(DawnError::Crypto(err))
+	};
+	let inner_len = match u32::try_from(inner_ciphertext.len()) {
+		Ok(res) => res,
+		Err(_) => error!("inner ciphertext too large")
+	};
+	let mut ciphertext = BINARY_MAGIC.to_vec();
+	ciphertext.push(BINARY_FORMAT_VERSION);
+	ciphertext.extend_from_slice(&inner_len.to_be_bytes());
+	ciphertext.extend(inner_ciphertext);
+	ciphertext.extend(payload);
+	Ok((ciphertext, new_pfs_key))
+}
+
+// inner ciphertext and encrypted CBOR message of a binary message
+type BinaryParts<'a> = (&'a [u8], &'a [u8]);
+
+// split the ciphertext of a binary message into the inner ciphertext and the encrypted CBOR message
+// returns None for JSON messages
+pub(crate) fn split_binary(msg_ciphertext: &[u8]) -> Result<Option<BinaryParts<'_>>, DawnError> {
+	let rest = match msg_ciphertext.strip_prefix(BINARY_MAGIC) {
+		Some(res) => res,
+		None => return Ok(None)
+	};
+	let (version, rest) = match split_bytes(rest, 1) {
+		Some(res) => res,
+		None => error!("binary message truncated")
+	};
+	if version != [BINARY_FORMAT_VERSION] { error!("unsupported binary message version"); }
+	let (inner_len, rest) = match split_bytes(rest, 4).map(|(inner_len, rest)| (<[u8; 4]>::try_from(inner_len), rest)) {
+		Some((Ok(inner_len), rest)) => (u32::from_be_bytes(inner_len) as usize, rest),
+		_ => error!("binary message truncated")
+	};
+	match split_bytes(rest, inner_len) {
+		Some(res) => Ok(Some(res)),
+		None => error!("binary message truncated")
+	}
+}
+
+// decrypt a binary message split with split_binary
+// deflated messages are rejected unless decompression is enabled and may not inflate to more than max_len bytes
+// returns the CBOR message, the new pfs key and the warning code of dawn_crypto
+pub(crate) fn open_binary(inner_ciphertext: &[u8], payload: &[u8], own_seckey_kyber: &[u8], remote_pubkey_sig: Option<&[u8]>, pfs_key: &[u8], pfs_salt: &[u8], decompress: bool, max_len: usize) -> Result<(Vec<u8>, Vec<u8>, u8), DawnError> {
+	let (inner, new_pfs_key, warning) = match decrypt_msg(own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, inner_ciphertext) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "decryption failed")
+	};
+	let mut lines = inner.split('\n');
+	let (payload_key, expected_hash) = match (lines.next().map(decode), lines.next().map(decode)) {
+		(Some(Ok(key)), Some(Ok(hash))) if key.len() == PAYLOAD_KEY_LEN => (key, hash),
+		_ => error!("binary message contains an invalid payload key")
+	};
+	let compressed = match (lines.next(), lines.next()) {
+		(None, _) => false,
+		(Some(DEFLATE_MARKER), None) => true,
+		_ => error!("binary message contains an unknown payload marker")
+	};
+	if compressed && !decompress { error!("compressed message received without compression being enabled"); }
+	if payload_hash(payload) != expected_hash { error!(Crypto, "binary message payload does not match its hash"); }
+	let message = match decrypt_data(payload, &payload_key) {
+		Ok(res) => res,
+		Err(_) => error!(Crypto, "decryption failed")
+	};
+	if !compressed { return Ok((message, new_pfs_key, warning)); }
+	match decompress_to_vec_with_limit(&message, max_len) {
+		Ok(res) => Ok((res, new_pfs_key, warning)),
+		Err(_) => error!("binary message could not be inflated or is too large")
+	}
+}
+
+// serde helper for binary fields
+// human readable formats (JSON) carry the data base64 encoded, binary formats (CBOR) as byte string, which is read
+// directly into the field without another encoding step
+pub(crate) mod binary_field {
+	use super::*;
+	
+	pub(crate) fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+		if serializer.is_human_readable() { return serializer.serialize_str(&encode_base64(value)); }
+		serializer.serialize_bytes(value)
+	}
+	
+	pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+		if !deserializer.is_human_readable() { return deserializer.deserialize_byte_buf(BytesVisitor); }
+		match String::deserialize(deserializer).map(decode_base64) {
+			Ok(Ok(res)) => Ok(res),
+			Ok(Err(_)) => Err(de::Error::custom("binary field is not valid base64")),
+			Err(err) => Err(err)
+		}
+	}
+}
+
+// binary_field for optional fields
+pub(crate) mod optional_binary_field {
+	use super::*;
+	
+	pub(crate) fn serialize<S: Serializer>(value: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+		match value {
+			Some(value) => binary_field::serialize(value, serializer),
+			None => serializer.serialize_none()
+		}
+	}
+	
+	pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+		match Option::<BinaryField>::deserialize(deserializer) {
+			Ok(res) => Ok(res.map(|BinaryField(value)| value)),
+			Err(err) => Err(err)
+		}
+	}
+}
+
+// a binary field of a message
+struct BinaryField(Vec<u8>);
+
+impl<'de> Deserialize<'de> for BinaryField {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		binary_field::deserialize(deserializer).map(BinaryField)
+	}
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+	type Value = Vec<u8>;
+	
+	fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+		formatter.write_str("a byte string")
+	}
+	
+	fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Vec<u8>, E> {
+		Ok(value.to_vec())
+	}
+	
+	fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Vec<u8>, E> {
+		Ok(value)
+	}
+}